@@ -0,0 +1,132 @@
+-- Fee schedules: Versioned, database-driven tariffs per payment rail
+-- Tariff changes are made by inserting a new schedule version with a future
+-- effective_from date, so no redeploy is needed when Safaricom or our pricing changes
+
+-- How a fee component is computed
+CREATE TYPE fee_kind AS ENUM (
+    'percentage',  -- A share of the amount (e.g., 1%)
+    'flat',        -- A fixed KES amount per transaction
+    'tiered'       -- A fixed KES amount chosen by amount band (e.g., M-Pesa tariff)
+);
+
+CREATE TABLE fee_schedules (
+    -- Primary key
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+
+    -- Which rail this schedule prices (deposit, withdrawal, Lightning send/receive)
+    rail transaction_type NOT NULL,
+
+    -- Monotonic version number per rail (1, 2, 3...)
+    version INTEGER NOT NULL,
+
+    -- When this schedule starts applying (the latest past schedule wins)
+    effective_from TIMESTAMPTZ NOT NULL,
+
+    -- Human-readable note (e.g., "Safaricom tariff update Jan 2025")
+    description TEXT,
+
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+
+    CONSTRAINT unique_fee_schedule_version UNIQUE(rail, version),
+    CONSTRAINT positive_fee_schedule_version CHECK (version > 0)
+);
+
+-- Fast lookup of the active schedule for a rail
+CREATE INDEX idx_fee_schedules_rail_effective ON fee_schedules(rail, effective_from DESC);
+
+-- Components that add up to the total fee of a schedule
+CREATE TABLE fee_schedule_components (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    schedule_id UUID NOT NULL REFERENCES fee_schedules(id) ON DELETE CASCADE,
+
+    -- Name shown in fee breakdowns (e.g., "service_fee", "mpesa_tariff")
+    name VARCHAR(50) NOT NULL,
+    kind fee_kind NOT NULL,
+
+    -- Percentage as a fraction (0.01 = 1%), used by 'percentage' components
+    rate DECIMAL(7,6),
+
+    -- Fixed fee in KES, used by 'flat' components
+    flat_kes DECIMAL(15,2),
+
+    -- Optional bounds applied to the computed component fee
+    min_kes DECIMAL(15,2),
+    max_kes DECIMAL(15,2),
+
+    -- Order components are shown in
+    position INTEGER NOT NULL DEFAULT 0,
+
+    CONSTRAINT unique_fee_component_name UNIQUE(schedule_id, name),
+    CONSTRAINT valid_fee_component CHECK (
+        (kind = 'percentage' AND rate IS NOT NULL AND rate >= 0) OR
+        (kind = 'flat' AND flat_kes IS NOT NULL AND flat_kes >= 0) OR
+        (kind = 'tiered')
+    ),
+    CONSTRAINT valid_fee_bounds CHECK (
+        min_kes IS NULL OR max_kes IS NULL OR min_kes <= max_kes
+    )
+);
+
+CREATE INDEX idx_fee_schedule_components_schedule ON fee_schedule_components(schedule_id);
+
+-- Amount bands for 'tiered' components
+-- A band applies to amounts up to and including up_to_kes; NULL means "everything above"
+CREATE TABLE fee_schedule_tiers (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    component_id UUID NOT NULL REFERENCES fee_schedule_components(id) ON DELETE CASCADE,
+    up_to_kes DECIMAL(15,2),
+    fee_kes DECIMAL(15,2) NOT NULL,
+
+    CONSTRAINT positive_tier_fee CHECK (fee_kes >= 0),
+    CONSTRAINT unique_tier_band UNIQUE(component_id, up_to_kes)
+);
+
+CREATE INDEX idx_fee_schedule_tiers_component ON fee_schedule_tiers(component_id);
+
+-- Record which schedule priced each transaction (audit and dispute handling)
+ALTER TABLE transactions ADD COLUMN fee_schedule_id UUID REFERENCES fee_schedules(id);
+
+-- Seed version 1 with the tariffs that were previously hardcoded
+DO $$
+DECLARE
+    deposit_schedule UUID;
+    withdrawal_schedule UUID;
+    tariff_component UUID;
+BEGIN
+    -- Deposits: 1% service fee, minimum 10 KES
+    INSERT INTO fee_schedules (rail, version, effective_from, description)
+    VALUES ('deposit_mpesa', 1, '2024-01-01T00:00:00Z', 'Initial deposit pricing')
+    RETURNING id INTO deposit_schedule;
+
+    INSERT INTO fee_schedule_components (schedule_id, name, kind, rate, min_kes, position)
+    VALUES (deposit_schedule, 'service_fee', 'percentage', 0.01, 10, 0);
+
+    -- Withdrawals: 1% service fee plus Safaricom's B2C tariff
+    INSERT INTO fee_schedules (rail, version, effective_from, description)
+    VALUES ('withdrawal_mpesa', 1, '2024-01-01T00:00:00Z', 'Initial withdrawal pricing')
+    RETURNING id INTO withdrawal_schedule;
+
+    INSERT INTO fee_schedule_components (schedule_id, name, kind, rate, position)
+    VALUES (withdrawal_schedule, 'service_fee', 'percentage', 0.01, 0);
+
+    INSERT INTO fee_schedule_components (schedule_id, name, kind, position)
+    VALUES (withdrawal_schedule, 'mpesa_tariff', 'tiered', 1)
+    RETURNING id INTO tariff_component;
+
+    INSERT INTO fee_schedule_tiers (component_id, up_to_kes, fee_kes) VALUES
+        (tariff_component, 49, 1),
+        (tariff_component, 100, 5),
+        (tariff_component, 500, 7),
+        (tariff_component, 1000, 13),
+        (tariff_component, 1500, 20),
+        (tariff_component, 2500, 25),
+        (tariff_component, 3500, 30),
+        (tariff_component, 5000, 35),
+        (tariff_component, 7500, 45),
+        (tariff_component, 10000, 55),
+        (tariff_component, 15000, 60),
+        (tariff_component, 20000, 65),
+        (tariff_component, 25000, 70),
+        (tariff_component, 30000, 75),
+        (tariff_component, NULL, 105);
+END $$;
@@ -0,0 +1,10 @@
+-- Exchange rate unit: KES per whole bitcoin everywhere
+-- transactions.exchange_rate was documented as "KES per 100k sats" while
+-- exchange_rates.btc_kes is KES per BTC. All code now reads and writes KES per BTC
+-- through shared_types::conversion::BtcKesRate, so document the column accordingly.
+
+COMMENT ON COLUMN exchange_rates.btc_kes IS 'Bitcoin price in KES per whole BTC';
+COMMENT ON COLUMN transactions.exchange_rate IS 'Bitcoin price in KES per whole BTC at time of transaction';
+
+-- Rates are stored with cent precision like the exchange_rates table
+ALTER TABLE transactions ADD CONSTRAINT positive_exchange_rate CHECK (exchange_rate IS NULL OR exchange_rate > 0);
@@ -0,0 +1,38 @@
+-- Idempotency keys: Make retried money-moving requests safe
+-- Mobile clients resend POSTs on flaky networks. Each request to a money-moving
+-- endpoint carries an Idempotency-Key header; the first request with a key is
+-- executed and its response stored, and retries with the same key get that
+-- response back instead of moving money twice. Stored in Postgres so every
+-- payment-service instance sees the same keys.
+
+CREATE TABLE idempotency_keys (
+    -- Keys are scoped per user so clients can't collide with each other
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+
+    -- Client-generated key (usually a UUID)
+    idempotency_key VARCHAR(255) NOT NULL,
+
+    -- Route the key was first used on (e.g., "/withdrawals/mpesa")
+    endpoint VARCHAR(100) NOT NULL,
+
+    -- SHA-256 of the canonical JSON request body, to detect a key reused with a different body
+    request_hash CHAR(64) NOT NULL,
+
+    -- Stored response, NULL while the first request is still being processed
+    response_status SMALLINT,
+    response_body JSONB,
+
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    completed_at TIMESTAMPTZ,
+
+    -- Keys can be reused once they expire
+    expires_at TIMESTAMPTZ NOT NULL,
+
+    PRIMARY KEY (user_id, idempotency_key),
+    CONSTRAINT completed_has_response CHECK (
+        completed_at IS NULL OR (response_status IS NOT NULL AND response_body IS NOT NULL)
+    )
+);
+
+-- Cleanup of expired keys
+CREATE INDEX idx_idempotency_keys_expires_at ON idempotency_keys(expires_at);
@@ -0,0 +1,44 @@
+-- Outbox events: Domain events waiting to be published
+-- Services insert an event here in the same database transaction as the state
+-- change it describes (deposit completed, user registered, ...). A relay in each
+-- service publishes unpublished rows to the Redis Stream "pesabit:events" and
+-- marks them published, giving at-least-once delivery without dual writes.
+
+CREATE TABLE outbox_events (
+    -- Event ID, also sent to consumers for deduplication
+    id UUID PRIMARY KEY,
+
+    -- Insert order, used to publish events in order
+    sequence BIGSERIAL NOT NULL UNIQUE,
+
+    -- Event name (e.g., "DepositCompleted", "UserRegistered")
+    event_type VARCHAR(100) NOT NULL,
+
+    -- Entity the event is about ("transaction" or "user") and its ID
+    aggregate_type VARCHAR(50) NOT NULL,
+    aggregate_id UUID NOT NULL,
+
+    -- User the event concerns (no foreign key so events outlive deleted users)
+    user_id UUID NOT NULL,
+
+    -- Serialized event ({"type": ..., "data": {...}})
+    payload JSONB NOT NULL,
+
+    occurred_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+
+    -- Set once the relay has written the event to Redis
+    published_at TIMESTAMPTZ,
+
+    -- Publishing failures, for monitoring a stuck relay
+    publish_attempts INTEGER NOT NULL DEFAULT 0,
+    last_error TEXT
+);
+
+-- The relay only scans unpublished events
+CREATE INDEX idx_outbox_events_unpublished ON outbox_events(sequence) WHERE published_at IS NULL;
+
+-- Purging old published events
+CREATE INDEX idx_outbox_events_published_at ON outbox_events(published_at) WHERE published_at IS NOT NULL;
+
+-- Looking up the event history of a transaction or user
+CREATE INDEX idx_outbox_events_aggregate ON outbox_events(aggregate_type, aggregate_id);
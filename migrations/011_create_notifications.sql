@@ -0,0 +1,111 @@
+-- Notifications: In-app inbox, preferences, Web Push subscriptions and delivery log
+-- Written by notification-service, which turns domain events from the
+-- "pesabit:events" stream into messages for each user.
+
+-- Delivery channels
+CREATE TYPE notification_channel AS ENUM (
+    'sms',       -- Text message to the user's phone number
+    'web_push',  -- Browser push notification (PWA)
+    'in_app'     -- Inbox shown inside the app
+);
+
+-- Languages notifications can be rendered in
+CREATE TYPE notification_language AS ENUM (
+    'en',  -- English
+    'sw'   -- Kiswahili
+);
+
+-- Delivery attempt state
+CREATE TYPE delivery_status AS ENUM (
+    'pending',  -- Not delivered yet (first attempt or waiting for a retry)
+    'sent',     -- Accepted by the channel provider
+    'failed'    -- Gave up after the maximum number of attempts
+);
+
+-- Per-user notification preferences (defaults apply when no row exists)
+CREATE TABLE notification_preferences (
+    user_id UUID PRIMARY KEY REFERENCES users(id) ON DELETE CASCADE,
+    language notification_language NOT NULL DEFAULT 'en',
+    sms_enabled BOOLEAN NOT NULL DEFAULT TRUE,
+    push_enabled BOOLEAN NOT NULL DEFAULT TRUE,
+
+    -- Event types the user never wants to hear about (e.g., {"PaymentSent"})
+    muted_events TEXT[] NOT NULL DEFAULT '{}',
+
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+-- In-app inbox
+CREATE TABLE notifications (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+
+    -- Domain event this notification was created for (one inbox entry per event)
+    event_id UUID NOT NULL UNIQUE,
+    event_type VARCHAR(100) NOT NULL,
+
+    title VARCHAR(200) NOT NULL,
+    body TEXT NOT NULL,
+
+    -- Event data for deep links (transaction ID, amounts, ...)
+    data JSONB NOT NULL DEFAULT '{}',
+
+    read_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_notifications_user_created ON notifications(user_id, created_at DESC);
+CREATE INDEX idx_notifications_user_unread ON notifications(user_id) WHERE read_at IS NULL;
+
+-- Browser push subscriptions (one per device/browser)
+CREATE TABLE push_subscriptions (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+
+    -- Push service URL from PushSubscription.endpoint
+    endpoint TEXT NOT NULL UNIQUE,
+
+    -- Client keys from PushSubscription.getKey()
+    p256dh VARCHAR(200) NOT NULL,
+    auth VARCHAR(100) NOT NULL,
+
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    last_used_at TIMESTAMPTZ
+);
+
+CREATE INDEX idx_push_subscriptions_user_id ON push_subscriptions(user_id);
+
+-- Delivery log: one row per event and channel, with retry state
+CREATE TABLE notification_deliveries (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    event_id UUID NOT NULL,
+    event_type VARCHAR(100) NOT NULL,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    channel notification_channel NOT NULL,
+    language notification_language NOT NULL,
+
+    -- Rendered message and event data (kept so retries send exactly the same thing)
+    title VARCHAR(200) NOT NULL,
+    body TEXT NOT NULL,
+    data JSONB NOT NULL DEFAULT '{}',
+
+    status delivery_status NOT NULL DEFAULT 'pending',
+    attempts INTEGER NOT NULL DEFAULT 0,
+    max_attempts INTEGER NOT NULL DEFAULT 5,
+
+    -- When the next attempt may run (also used as a lease while an attempt is in flight)
+    next_attempt_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    last_error TEXT,
+
+    -- Provider reference (e.g., SMS message ID) for support and reconciliation
+    provider_message_id VARCHAR(200),
+
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    sent_at TIMESTAMPTZ,
+
+    -- Redelivered events must not notify twice
+    CONSTRAINT unique_event_channel UNIQUE(event_id, channel)
+);
+
+CREATE INDEX idx_notification_deliveries_due ON notification_deliveries(next_attempt_at) WHERE status = 'pending';
+CREATE INDEX idx_notification_deliveries_user ON notification_deliveries(user_id, created_at DESC);
@@ -0,0 +1,44 @@
+-- SMS messages: Log of texts sent by user-service (OTP codes) and their delivery state
+-- Rows are created when the provider accepts or rejects a message, then updated
+-- by the provider's delivery reports. Message text is never stored.
+
+-- Delivery state of an SMS
+CREATE TYPE sms_status AS ENUM (
+    'queued',     -- Accepted by the provider, waiting to be sent
+    'sent',       -- Handed to the mobile network
+    'delivered',  -- Network confirmed delivery to the handset
+    'failed',     -- Network could not deliver (e.g., phone off for too long)
+    'rejected'    -- Provider or network refused the message (invalid number, blacklisted, ...)
+);
+
+CREATE TABLE sms_messages (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+
+    -- Provider name and its message ID (used to match delivery reports)
+    provider VARCHAR(50) NOT NULL,
+    provider_message_id VARCHAR(100) UNIQUE,
+
+    phone_number VARCHAR(20) NOT NULL,
+
+    -- Why the message was sent (e.g., "otp")
+    purpose VARCHAR(50) NOT NULL,
+
+    status sms_status NOT NULL,
+    -- Provider status code (Africa's Talking: 100-102 accepted, 4xx/5xx rejected)
+    status_code INTEGER,
+
+    -- Price charged by the provider
+    cost_amount DECIMAL(10,4),
+    cost_currency VARCHAR(3),
+
+    -- From delivery reports
+    network_code VARCHAR(10),
+    failure_reason VARCHAR(100),
+
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    delivered_at TIMESTAMPTZ
+);
+
+CREATE INDEX idx_sms_messages_phone_created ON sms_messages(phone_number, created_at DESC);
+CREATE INDEX idx_sms_messages_status ON sms_messages(status, created_at) WHERE status IN ('queued', 'sent');
@@ -0,0 +1,64 @@
+-- Webhooks: Signed HTTP callbacks to business customers when their events happen
+-- Written by notification-service, which turns domain events into deliveries for
+-- every active endpoint subscribed to the event type.
+
+-- Endpoints registered by an account
+CREATE TABLE webhook_endpoints (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+
+    url TEXT NOT NULL,
+    description VARCHAR(200),
+
+    -- Event types delivered to this endpoint (e.g., {"InvoicePaid", "WithdrawalCompleted"})
+    event_types TEXT[] NOT NULL,
+
+    -- HMAC-SHA256 signing secret, shown to the customer once at registration
+    secret VARCHAR(100) NOT NULL,
+
+    is_active BOOLEAN NOT NULL DEFAULT TRUE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_webhook_endpoints_user_id ON webhook_endpoints(user_id) WHERE is_active;
+
+-- Delivery state of a webhook
+CREATE TYPE webhook_delivery_status AS ENUM (
+    'pending',    -- Not delivered yet (first attempt or waiting for a retry)
+    'delivered',  -- Endpoint answered with 2xx
+    'dead'        -- Gave up after the maximum number of attempts (dead-letter list)
+);
+
+-- One row per event and endpoint
+CREATE TABLE webhook_deliveries (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    endpoint_id UUID NOT NULL REFERENCES webhook_endpoints(id) ON DELETE CASCADE,
+    event_id UUID NOT NULL,
+    event_type VARCHAR(100) NOT NULL,
+
+    -- Exact JSON body sent (retries and replays send the same payload)
+    payload JSONB NOT NULL,
+
+    status webhook_delivery_status NOT NULL DEFAULT 'pending',
+    attempts INTEGER NOT NULL DEFAULT 0,
+    max_attempts INTEGER NOT NULL DEFAULT 10,
+
+    -- When the next attempt may run (also used as a lease while an attempt is in flight)
+    next_attempt_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+
+    -- Outcome of the last attempt
+    last_response_status SMALLINT,
+    last_error TEXT,
+
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    delivered_at TIMESTAMPTZ,
+    dead_at TIMESTAMPTZ,
+
+    -- Redelivered events must not be sent twice
+    CONSTRAINT unique_webhook_event_endpoint UNIQUE(endpoint_id, event_id)
+);
+
+CREATE INDEX idx_webhook_deliveries_due ON webhook_deliveries(next_attempt_at) WHERE status = 'pending';
+CREATE INDEX idx_webhook_deliveries_endpoint ON webhook_deliveries(endpoint_id, created_at DESC);
+CREATE INDEX idx_webhook_deliveries_dead ON webhook_deliveries(endpoint_id, dead_at DESC) WHERE status = 'dead';
@@ -0,0 +1,54 @@
+-- Refunds: Reversals of failed transactions
+-- Every refund adds a 'refund' ledger entry linked to the original transaction,
+-- which is moved to 'refunded'. Failed withdrawals and Lightning sends are
+-- credited back to the wallet in sats; deposits that reached PesaBit but could
+-- not be converted are returned in KES over M-Pesa by an operator.
+
+ALTER TYPE transaction_type ADD VALUE 'refund';
+
+-- Reversal entries point at the transaction they reverse
+ALTER TABLE transactions ADD COLUMN reverses_transaction_id UUID REFERENCES transactions(id);
+
+-- A transaction can only be reversed once
+CREATE UNIQUE INDEX idx_transactions_reverses ON transactions(reverses_transaction_id)
+    WHERE reverses_transaction_id IS NOT NULL;
+
+-- Why a transaction was refunded
+CREATE TYPE refund_reason AS ENUM (
+    'withdrawal_failed',      -- M-Pesa B2C payout failed after sats were debited
+    'lightning_send_failed',  -- Lightning payment failed after funds were reserved
+    'deposit_not_converted',  -- M-Pesa payment arrived but could not be converted to sats
+    'manual'                  -- Triggered by an operator
+);
+
+CREATE TABLE refunds (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    original_transaction_id UUID NOT NULL REFERENCES transactions(id),
+    reversal_transaction_id UUID NOT NULL REFERENCES transactions(id),
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+
+    reason refund_reason NOT NULL,
+    -- Operator's explanation (required for manual refunds)
+    note TEXT,
+    -- Operator who triggered a manual refund (NULL for automatic refunds)
+    initiated_by VARCHAR(100),
+
+    -- Exactly one of these is set: sats go back to the wallet, KES back to M-Pesa
+    amount_sats BIGINT,
+    amount_kes DECIMAL(15,2),
+
+    -- KES refunds: M-Pesa receipt of the payout once an operator has sent it
+    payout_mpesa_code VARCHAR(50),
+    paid_out_at TIMESTAMPTZ,
+
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+
+    CONSTRAINT unique_refund_original UNIQUE(original_transaction_id),
+    CONSTRAINT unique_refund_reversal UNIQUE(reversal_transaction_id),
+    CONSTRAINT refund_single_currency CHECK ((amount_sats IS NULL) <> (amount_kes IS NULL)),
+    CONSTRAINT positive_refund_amount CHECK (amount_sats > 0 OR amount_kes > 0),
+    CONSTRAINT manual_refund_note CHECK (reason <> 'manual' OR (note IS NOT NULL AND initiated_by IS NOT NULL))
+);
+
+CREATE INDEX idx_refunds_user_id ON refunds(user_id, created_at DESC);
+CREATE INDEX idx_refunds_pending_payout ON refunds(created_at) WHERE amount_kes IS NOT NULL AND paid_out_at IS NULL;
@@ -0,0 +1,81 @@
+-- Transaction state machine
+-- Transactions move pending → processing → completed | failed, and failed
+-- transactions can be refunded. Any other status change is rejected, and every
+-- status a transaction takes is recorded with who set it and why.
+
+CREATE TABLE transaction_status_history (
+    id BIGSERIAL PRIMARY KEY,
+    transaction_id UUID NOT NULL REFERENCES transactions(id) ON DELETE CASCADE,
+
+    -- NULL for the status a transaction was created with
+    from_status transaction_status,
+    to_status transaction_status NOT NULL,
+
+    -- Who made the change (e.g., "system", "operator:jane")
+    -- Set per database transaction with set_config('pesabit.status_actor', ..., true)
+    actor VARCHAR(100) NOT NULL,
+    -- Why, from set_config('pesabit.status_reason', ..., true)
+    reason TEXT,
+
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_transaction_status_history_transaction
+    ON transaction_status_history(transaction_id, created_at);
+
+-- Whether a status change is allowed
+CREATE OR REPLACE FUNCTION is_valid_transaction_transition(from_status transaction_status, to_status transaction_status)
+RETURNS BOOLEAN AS $$
+    SELECT (from_status::text, to_status::text) IN (
+        ('pending', 'processing'),
+        ('processing', 'completed'),
+        ('processing', 'failed'),
+        ('failed', 'refunded')
+    );
+$$ LANGUAGE sql IMMUTABLE;
+
+-- Reject illegal transitions and record legal ones
+CREATE OR REPLACE FUNCTION record_transaction_status()
+RETURNS TRIGGER AS $$
+BEGIN
+    IF TG_OP = 'UPDATE' AND NOT is_valid_transaction_transition(OLD.status, NEW.status) THEN
+        RAISE EXCEPTION 'Illegal transaction status transition from % to % for transaction %',
+            OLD.status, NEW.status, NEW.id
+            USING ERRCODE = 'check_violation';
+    END IF;
+
+    INSERT INTO transaction_status_history (transaction_id, from_status, to_status, actor, reason)
+    VALUES (
+        NEW.id,
+        CASE WHEN TG_OP = 'UPDATE' THEN OLD.status END,
+        NEW.status,
+        COALESCE(NULLIF(current_setting('pesabit.status_actor', true), ''), 'unknown'),
+        NULLIF(current_setting('pesabit.status_reason', true), '')
+    );
+
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER transactions_status_created
+    AFTER INSERT ON transactions
+    FOR EACH ROW
+    EXECUTE FUNCTION record_transaction_status();
+
+CREATE TRIGGER transactions_status_changed
+    AFTER UPDATE OF status ON transactions
+    FOR EACH ROW
+    WHEN (OLD.status IS DISTINCT FROM NEW.status)
+    EXECUTE FUNCTION record_transaction_status();
+
+-- Completed transactions can no longer move back, so completed_at is only ever set
+CREATE OR REPLACE FUNCTION set_completed_at()
+RETURNS TRIGGER AS $$
+BEGIN
+    IF OLD.status != 'completed' AND NEW.status = 'completed' THEN
+        NEW.completed_at = NOW();
+    END IF;
+
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
@@ -0,0 +1,67 @@
+-- Account statements: CSV and PDF statements over a date range
+-- Short ranges are generated while the user waits; longer ones are queued and
+-- picked up by payment-service in the background. Generated files are kept for
+-- a few days and downloaded through signed links that expire.
+
+CREATE TYPE statement_format AS ENUM ('csv', 'pdf');
+
+CREATE TYPE statement_status AS ENUM (
+    'pending',     -- Queued for background generation
+    'generating',  -- Being generated (lease_until guards against crashed workers)
+    'ready',       -- File available for download
+    'failed',      -- Gave up after repeated errors
+    'expired'      -- File deleted after the retention period
+);
+
+CREATE TABLE statements (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+
+    format statement_format NOT NULL,
+    -- Transactions created in [period_start, period_end) are included
+    period_start TIMESTAMPTZ NOT NULL,
+    period_end TIMESTAMPTZ NOT NULL,
+
+    status statement_status NOT NULL DEFAULT 'pending',
+    attempts INTEGER NOT NULL DEFAULT 0,
+    -- While generating, another worker may take over after this time
+    lease_until TIMESTAMPTZ,
+    last_error TEXT,
+
+    -- Generated file (NULL until ready and again once expired)
+    content BYTEA,
+    file_name VARCHAR(100),
+    transaction_count INTEGER,
+
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    ready_at TIMESTAMPTZ,
+    -- File is deleted and download links stop working after this time
+    expires_at TIMESTAMPTZ,
+
+    CONSTRAINT valid_statement_period CHECK (period_start < period_end),
+    CONSTRAINT ready_statement_content CHECK (status <> 'ready' OR (content IS NOT NULL AND expires_at IS NOT NULL))
+);
+
+CREATE INDEX idx_statements_user_id ON statements(user_id, created_at DESC);
+CREATE INDEX idx_statements_queue ON statements(created_at) WHERE status IN ('pending', 'generating');
+CREATE INDEX idx_statements_expiry ON statements(expires_at) WHERE status = 'ready';
+
+-- Change in a wallet's sats balance caused by a transaction
+-- Outgoing payments take sats (amount plus fee) when created; failed ones are
+-- given back by a separate 'refund' entry. Incoming sats only count once the
+-- transaction has completed. KES-only entries (e.g., deposit refunds) are 0.
+CREATE OR REPLACE FUNCTION transaction_balance_effect(
+    tx_type transaction_type,
+    tx_status transaction_status,
+    amount_sats BIGINT,
+    fee_sats BIGINT
+)
+RETURNS BIGINT AS $$
+    SELECT CASE
+        WHEN tx_type::text IN ('withdrawal_mpesa', 'lightning_send')
+            THEN -(COALESCE(amount_sats, 0) + COALESCE(fee_sats, 0))
+        WHEN tx_status = 'completed'
+            THEN COALESCE(amount_sats, 0)
+        ELSE 0
+    END;
+$$ LANGUAGE sql IMMUTABLE;
@@ -0,0 +1,101 @@
+-- Scheduled payments: One-off future and recurring payment instructions
+-- payment-service runs due instructions in the background. Each run checks the
+-- balance and limits, is retried according to the instruction's policy, and the
+-- user is notified when a run finally fails.
+
+-- Where a scheduled payment goes
+CREATE TYPE scheduled_payment_target AS ENUM (
+    'lightning_address',  -- Any Lightning address (e.g., alice@getalby.com)
+    'phone_number',       -- Another PesaBit user, found by phone number
+    'mpesa_withdrawal'    -- Cash out to an M-Pesa number
+);
+
+CREATE TYPE scheduled_payment_status AS ENUM (
+    'active',     -- Will run at next_run_at
+    'paused',     -- Kept but not run until resumed
+    'completed',  -- One-off payment that has run
+    'cancelled',  -- Stopped by the user
+    'failed'      -- One-off payment whose run failed for good
+);
+
+CREATE TABLE scheduled_payments (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+
+    target_type scheduled_payment_target NOT NULL,
+    -- Lightning address or E.164 phone number
+    target VARCHAR(255) NOT NULL,
+
+    -- Exactly one is set; KES amounts are converted at the rate when the payment runs
+    amount_sats BIGINT,
+    amount_kes DECIMAL(15,2),
+    memo VARCHAR(140),
+
+    -- Exactly one is set: a single run time, or a cron expression in Nairobi time
+    run_at TIMESTAMPTZ,
+    cron VARCHAR(100),
+
+    -- Retry policy for each run
+    max_attempts INTEGER NOT NULL DEFAULT 3,
+    retry_after_minutes INTEGER NOT NULL DEFAULT 30,
+
+    status scheduled_payment_status NOT NULL DEFAULT 'active',
+    -- Scheduled time of the run in progress (stays the same across its retries)
+    occurrence_at TIMESTAMPTZ,
+    -- When the next attempt is due (NULL once nothing is left to run)
+    next_run_at TIMESTAMPTZ,
+    -- Attempts made for the current occurrence
+    attempt INTEGER NOT NULL DEFAULT 0,
+    -- Set while a worker is running the payment
+    lease_until TIMESTAMPTZ,
+
+    run_count INTEGER NOT NULL DEFAULT 0,
+    last_run_at TIMESTAMPTZ,
+    last_error TEXT,
+
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+
+    CONSTRAINT scheduled_payment_single_amount CHECK ((amount_sats IS NULL) <> (amount_kes IS NULL)),
+    CONSTRAINT scheduled_payment_positive_amount CHECK (amount_sats > 0 OR amount_kes > 0),
+    CONSTRAINT scheduled_payment_single_schedule CHECK ((run_at IS NULL) <> (cron IS NULL)),
+    CONSTRAINT scheduled_payment_retry_policy CHECK (
+        max_attempts BETWEEN 1 AND 5 AND retry_after_minutes BETWEEN 5 AND 1440
+    )
+);
+
+CREATE INDEX idx_scheduled_payments_user_id ON scheduled_payments(user_id, created_at DESC);
+CREATE INDEX idx_scheduled_payments_due ON scheduled_payments(next_run_at) WHERE status = 'active';
+
+CREATE TRIGGER scheduled_payments_updated_at
+    BEFORE UPDATE ON scheduled_payments
+    FOR EACH ROW
+    EXECUTE FUNCTION update_updated_at();
+
+-- Outcome of a run attempt
+CREATE TYPE scheduled_payment_run_status AS ENUM (
+    'started',      -- Payment being made
+    'succeeded',    -- Payment initiated (transaction_id is set)
+    'failed',       -- Payment could not be made
+    'interrupted'   -- Worker stopped mid-run; not retried so the payment is never made twice
+);
+
+-- Every attempt of every run
+CREATE TABLE scheduled_payment_runs (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    scheduled_payment_id UUID NOT NULL REFERENCES scheduled_payments(id) ON DELETE CASCADE,
+    occurrence_at TIMESTAMPTZ NOT NULL,
+    attempt INTEGER NOT NULL,
+
+    status scheduled_payment_run_status NOT NULL DEFAULT 'started',
+    transaction_id UUID REFERENCES transactions(id),
+    error TEXT,
+
+    started_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    finished_at TIMESTAMPTZ,
+
+    -- Two workers can never make the same attempt
+    CONSTRAINT unique_scheduled_payment_attempt UNIQUE(scheduled_payment_id, occurrence_at, attempt)
+);
+
+CREATE INDEX idx_scheduled_payment_runs_payment ON scheduled_payment_runs(scheduled_payment_id, started_at DESC);
@@ -0,0 +1,52 @@
+-- Payment requests: One user asking another for money
+-- The payer approves (paid with an internal transfer) or declines; the requester
+-- can withdraw a request while it is pending. Pending requests expire.
+
+CREATE TYPE payment_request_status AS ENUM (
+    'pending',    -- Waiting for the payer
+    'paid',       -- Approved and paid (transaction IDs are set)
+    'declined',   -- Refused by the payer
+    'cancelled',  -- Withdrawn by the requester
+    'expired'     -- Not answered before expires_at
+);
+
+CREATE TABLE payment_requests (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    requester_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    payer_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+
+    -- Exactly one is set; KES amounts are converted at the rate when the request is paid
+    amount_sats BIGINT,
+    amount_kes DECIMAL(15,2),
+    memo VARCHAR(140),
+
+    status payment_request_status NOT NULL DEFAULT 'pending',
+    expires_at TIMESTAMPTZ NOT NULL,
+
+    -- Set once paid
+    paid_amount_sats BIGINT,
+    -- Payer's debit and requester's credit
+    debit_transaction_id UUID REFERENCES transactions(id),
+    credit_transaction_id UUID REFERENCES transactions(id),
+
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    -- When the request was paid, declined, cancelled or expired
+    resolved_at TIMESTAMPTZ,
+
+    CONSTRAINT payment_request_single_amount CHECK ((amount_sats IS NULL) <> (amount_kes IS NULL)),
+    CONSTRAINT payment_request_positive_amount CHECK (amount_sats > 0 OR amount_kes > 0),
+    CONSTRAINT payment_request_distinct_users CHECK (requester_id <> payer_id),
+    CONSTRAINT payment_request_paid_transactions CHECK (
+        (status = 'paid') = (debit_transaction_id IS NOT NULL AND credit_transaction_id IS NOT NULL)
+    )
+);
+
+CREATE INDEX idx_payment_requests_requester ON payment_requests(requester_id, created_at DESC);
+CREATE INDEX idx_payment_requests_payer ON payment_requests(payer_id, created_at DESC);
+CREATE INDEX idx_payment_requests_expiring ON payment_requests(expires_at) WHERE status = 'pending';
+
+CREATE TRIGGER payment_requests_updated_at
+    BEFORE UPDATE ON payment_requests
+    FOR EACH ROW
+    EXECUTE FUNCTION update_updated_at();
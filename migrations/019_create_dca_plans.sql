@@ -0,0 +1,73 @@
+-- DCA plans: Recurring sats purchases from M-Pesa
+-- On each scheduled run payment-service sends an STK Push deposit for the plan's
+-- KES amount, converted at the quote when the deposit completes. Plans pause
+-- themselves after repeated declines.
+
+CREATE TYPE dca_plan_status AS ENUM (
+    'active',     -- Will run at next_run_at
+    'paused',     -- By the user, or automatically after repeated declines
+    'cancelled'   -- Stopped by the user
+);
+
+CREATE TABLE dca_plans (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+
+    -- Deposited on every run (same bounds as a single M-Pesa deposit)
+    amount_kes INTEGER NOT NULL,
+    -- Cron expression in Nairobi time
+    cron VARCHAR(100) NOT NULL,
+
+    status dca_plan_status NOT NULL DEFAULT 'active',
+    -- Why the plan was paused automatically
+    pause_reason TEXT,
+    next_run_at TIMESTAMPTZ,
+    -- Set while a worker is starting a run
+    lease_until TIMESTAMPTZ,
+    -- Runs in a row that were declined or could not be started
+    consecutive_failures INTEGER NOT NULL DEFAULT 0,
+
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+
+    CONSTRAINT dca_plan_amount CHECK (amount_kes BETWEEN 10 AND 500000)
+);
+
+CREATE INDEX idx_dca_plans_user_id ON dca_plans(user_id, created_at DESC);
+CREATE INDEX idx_dca_plans_due ON dca_plans(next_run_at) WHERE status = 'active';
+
+CREATE TRIGGER dca_plans_updated_at
+    BEFORE UPDATE ON dca_plans
+    FOR EACH ROW
+    EXECUTE FUNCTION update_updated_at();
+
+CREATE TYPE dca_run_status AS ENUM (
+    'starting',    -- Recorded before the STK Push is sent
+    'initiated',   -- STK Push sent, waiting for the deposit to complete
+    'completed',   -- Deposit completed and sats credited
+    'declined',    -- Deposit cancelled, declined or timed out
+    'failed'       -- STK Push could not be sent (or the worker stopped before sending it)
+);
+
+-- One row per scheduled purchase
+CREATE TABLE dca_plan_runs (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    dca_plan_id UUID NOT NULL REFERENCES dca_plans(id) ON DELETE CASCADE,
+    scheduled_for TIMESTAMPTZ NOT NULL,
+
+    status dca_run_status NOT NULL DEFAULT 'starting',
+    transaction_id UUID REFERENCES transactions(id),
+    amount_kes INTEGER NOT NULL,
+    -- Sats credited once completed
+    amount_sats BIGINT,
+    error TEXT,
+
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    finished_at TIMESTAMPTZ,
+
+    -- Each occurrence is attempted at most once
+    CONSTRAINT unique_dca_run UNIQUE(dca_plan_id, scheduled_for)
+);
+
+CREATE INDEX idx_dca_plan_runs_plan ON dca_plan_runs(dca_plan_id, scheduled_for DESC);
+CREATE INDEX idx_dca_plan_runs_transaction ON dca_plan_runs(transaction_id) WHERE transaction_id IS NOT NULL;
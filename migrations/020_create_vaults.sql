@@ -0,0 +1,94 @@
+-- Savings vaults: Goal-based sub-balances of a wallet
+-- wallets.balance_sats is the spendable balance; sats moved into a vault leave it
+-- and are tracked on the vault, with every move recorded in vault_entries.
+-- Withdrawing from a locked vault needs the user's PIN and waits out a
+-- cooling-off delay.
+
+CREATE TYPE vault_status AS ENUM (
+    'active',
+    'closed'   -- Emptied and closed by the user
+);
+
+CREATE TABLE vaults (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+
+    name VARCHAR(50) NOT NULL,
+    -- Savings goal (optional)
+    target_sats BIGINT,
+    -- Sats can't be withdrawn before this without the early-withdrawal delay
+    lock_until TIMESTAMPTZ,
+
+    balance_sats BIGINT NOT NULL DEFAULT 0,
+    status vault_status NOT NULL DEFAULT 'active',
+
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    closed_at TIMESTAMPTZ,
+
+    CONSTRAINT vault_positive_balance CHECK (balance_sats >= 0),
+    CONSTRAINT vault_positive_target CHECK (target_sats IS NULL OR target_sats > 0),
+    CONSTRAINT vault_closed_empty CHECK (status = 'active' OR balance_sats = 0)
+);
+
+CREATE INDEX idx_vaults_user_id ON vaults(user_id, created_at);
+-- Names are unique among a user's open vaults
+CREATE UNIQUE INDEX idx_vaults_user_name ON vaults(user_id, LOWER(name)) WHERE status = 'active';
+
+CREATE TRIGGER vaults_updated_at
+    BEFORE UPDATE ON vaults
+    FOR EACH ROW
+    EXECUTE FUNCTION update_updated_at();
+
+CREATE TYPE vault_entry_type AS ENUM (
+    'deposit',            -- Spendable → vault
+    'withdrawal',         -- Vault → spendable
+    'early_withdrawal'    -- Vault → spendable before lock_until, after the cooling-off delay
+);
+
+-- Ledger of moves between the spendable balance and vaults
+CREATE TABLE vault_entries (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    vault_id UUID NOT NULL REFERENCES vaults(id) ON DELETE CASCADE,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+
+    entry_type vault_entry_type NOT NULL,
+    amount_sats BIGINT NOT NULL,
+    -- Vault balance after the entry
+    vault_balance_sats BIGINT NOT NULL,
+
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+
+    CONSTRAINT vault_entry_positive_amount CHECK (amount_sats > 0)
+);
+
+CREATE INDEX idx_vault_entries_vault ON vault_entries(vault_id, created_at DESC);
+
+CREATE TYPE vault_withdrawal_status AS ENUM (
+    'pending',     -- Waiting out the cooling-off delay
+    'completed',   -- Sats moved back to the spendable balance
+    'cancelled',   -- Cancelled by the user
+    'failed'       -- Vault no longer held enough when the delay ended
+);
+
+-- Early withdrawals from locked vaults
+CREATE TABLE vault_withdrawals (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    vault_id UUID NOT NULL REFERENCES vaults(id) ON DELETE CASCADE,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+
+    amount_sats BIGINT NOT NULL,
+    status vault_withdrawal_status NOT NULL DEFAULT 'pending',
+    -- End of the cooling-off delay
+    available_at TIMESTAMPTZ NOT NULL,
+    entry_id UUID REFERENCES vault_entries(id),
+    failure_reason TEXT,
+
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    resolved_at TIMESTAMPTZ,
+
+    CONSTRAINT vault_withdrawal_positive_amount CHECK (amount_sats > 0)
+);
+
+CREATE INDEX idx_vault_withdrawals_vault ON vault_withdrawals(vault_id, created_at DESC);
+CREATE INDEX idx_vault_withdrawals_due ON vault_withdrawals(available_at) WHERE status = 'pending';
@@ -0,0 +1,136 @@
+-- Merchant accounts: Businesses taking point-of-sale payments
+-- A merchant account is a user registered with account_type 'merchant'. Its
+-- business profile holds the till name shown to customers and the share of
+-- incoming sats settled to M-Pesa each day. POS invoices are priced in KES and
+-- locked to sats when created. Merchants script against the API with API keys.
+
+CREATE TYPE account_type AS ENUM (
+    'personal',
+    'merchant'
+);
+
+ALTER TABLE users ADD COLUMN account_type account_type NOT NULL DEFAULT 'personal';
+
+CREATE TABLE merchants (
+    user_id UUID PRIMARY KEY REFERENCES users(id) ON DELETE CASCADE,
+
+    business_name VARCHAR(100) NOT NULL,
+    -- Shown to customers on invoices and receipts
+    till_name VARCHAR(30) NOT NULL,
+    business_category VARCHAR(50),
+    -- KRA PIN (e.g. P051234567X)
+    kra_pin VARCHAR(11),
+    contact_email VARCHAR(255),
+
+    -- Share of each day's incoming sats sent to M-Pesa (0 = settlement off)
+    settlement_percent SMALLINT NOT NULL DEFAULT 0,
+    -- M-Pesa number settled to (NULL = the account's phone number)
+    settlement_phone VARCHAR(15),
+    -- Incoming sats before this have been considered for settlement
+    settled_until TIMESTAMPTZ,
+    -- Set while a worker is settling the merchant
+    settlement_lease_until TIMESTAMPTZ,
+
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+
+    CONSTRAINT merchant_settlement_percent CHECK (settlement_percent BETWEEN 0 AND 100)
+);
+
+CREATE UNIQUE INDEX idx_merchants_till_name ON merchants(LOWER(till_name));
+CREATE INDEX idx_merchants_settlement ON merchants(settled_until) WHERE settlement_percent > 0;
+
+CREATE TRIGGER merchants_updated_at
+    BEFORE UPDATE ON merchants
+    FOR EACH ROW
+    EXECUTE FUNCTION update_updated_at();
+
+-- Long-lived keys for calling the API from scripts and POS terminals
+CREATE TABLE api_keys (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+
+    name VARCHAR(50) NOT NULL,
+    -- First characters of the key, shown in listings and used to look it up
+    key_prefix VARCHAR(16) NOT NULL UNIQUE,
+    -- SHA-256 of the full key (the key itself is only shown once)
+    key_hash CHAR(64) NOT NULL,
+
+    last_used_at TIMESTAMPTZ,
+    revoked_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_api_keys_user_id ON api_keys(user_id, created_at DESC);
+
+-- KES-priced invoices; the Lightning invoice itself is a lightning_receive transaction
+CREATE TABLE pos_invoices (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    merchant_id UUID NOT NULL REFERENCES merchants(user_id) ON DELETE CASCADE,
+    transaction_id UUID NOT NULL REFERENCES transactions(id),
+
+    amount_kes DECIMAL(15,2) NOT NULL,
+    -- Locked at creation
+    amount_sats BIGINT NOT NULL,
+    exchange_rate DECIMAL(15,2) NOT NULL,
+    payment_request TEXT NOT NULL,
+
+    -- Merchant's own order or receipt number
+    reference VARCHAR(64),
+    description VARCHAR(200),
+    expires_at TIMESTAMPTZ NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+
+    CONSTRAINT pos_invoice_positive_amount CHECK (amount_kes > 0 AND amount_sats > 0)
+);
+
+CREATE INDEX idx_pos_invoices_merchant ON pos_invoices(merchant_id, created_at DESC);
+CREATE INDEX idx_pos_invoices_transaction ON pos_invoices(transaction_id);
+
+CREATE TYPE merchant_settlement_status AS ENUM (
+    'starting',    -- Recorded before the withdrawal is sent
+    'initiated',   -- M-Pesa withdrawal started
+    'skipped',     -- Share was below the minimum withdrawal
+    'failed'       -- Withdrawal could not be started
+);
+
+-- One row per merchant per settlement day
+CREATE TABLE merchant_settlements (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    merchant_id UUID NOT NULL REFERENCES merchants(user_id) ON DELETE CASCADE,
+
+    -- Incoming sats completed in [period_start, period_end)
+    period_start TIMESTAMPTZ NOT NULL,
+    period_end TIMESTAMPTZ NOT NULL,
+    received_sats BIGINT NOT NULL,
+    settlement_percent SMALLINT NOT NULL,
+    amount_sats BIGINT NOT NULL,
+
+    status merchant_settlement_status NOT NULL DEFAULT 'starting',
+    transaction_id UUID REFERENCES transactions(id),
+    error TEXT,
+
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+
+    CONSTRAINT unique_merchant_settlement UNIQUE(merchant_id, period_end)
+);
+
+CREATE INDEX idx_merchant_settlements_merchant ON merchant_settlements(merchant_id, period_end DESC);
+
+-- Per-account daily metrics, so merchant sales reports can be built from the view
+-- (summing over user_id gives the platform-wide figures as before)
+CREATE OR REPLACE VIEW daily_transaction_metrics AS
+SELECT
+    DATE(created_at) as date,
+    type,
+    status,
+    COUNT(*) as transaction_count,
+    COALESCE(SUM(amount_kes), 0) as total_kes,
+    COALESCE(SUM(amount_sats), 0) as total_sats,
+    COALESCE(SUM(fee_kes), 0) as total_fees_kes,
+    COALESCE(SUM(fee_sats), 0) as total_fees_sats,
+    user_id
+FROM transactions
+WHERE created_at >= CURRENT_DATE - INTERVAL '90 days'
+GROUP BY DATE(created_at), type, status, user_id
+ORDER BY date DESC, type, status;
@@ -0,0 +1,17 @@
+-- Scoped API keys: Any account can create keys for programmatic access
+-- Each key lists what it may do (e.g. read:balance, create:invoice,
+-- send:payment), optionally the addresses it may be used from, and its own
+-- rate limit. The gateway checks all of them before forwarding a request.
+
+ALTER TABLE api_keys
+    -- Empty = the key can do nothing
+    ADD COLUMN scopes TEXT[] NOT NULL DEFAULT '{}',
+    -- IP addresses or CIDR ranges (empty = any address)
+    ADD COLUMN ip_allowlist TEXT[] NOT NULL DEFAULT '{}',
+    ADD COLUMN rate_limit_per_minute INTEGER NOT NULL DEFAULT 60,
+    -- NULL = never expires
+    ADD COLUMN expires_at TIMESTAMPTZ,
+    ADD CONSTRAINT api_key_rate_limit CHECK (rate_limit_per_minute BETWEEN 1 AND 1000);
+
+-- Keys created before scopes existed were only used for POS invoices
+UPDATE api_keys SET scopes = '{create:invoice}';
@@ -0,0 +1,44 @@
+-- Mobile money providers: Deposits and withdrawals on networks other than M-Pesa
+-- Every mobile money deposit or withdrawal records the network it went through.
+-- The transaction types keep their M-Pesa names; `provider` tells them apart.
+
+CREATE TYPE mobile_money_network AS ENUM (
+    'mpesa',
+    'airtel_money'
+);
+
+ALTER TABLE transactions
+    ADD COLUMN provider mobile_money_network,
+    -- Provider's ID for the request (e.g. M-Pesa CheckoutRequestID), matched against callbacks
+    ADD COLUMN provider_reference VARCHAR(100),
+    -- Last time the provider was asked about a transaction stuck in processing
+    ADD COLUMN provider_checked_at TIMESTAMPTZ;
+
+COMMENT ON COLUMN transactions.mpesa_code IS 'Provider receipt (M-Pesa code or Airtel Money ID)';
+
+UPDATE transactions SET provider = 'mpesa' WHERE type IN ('deposit_mpesa', 'withdrawal_mpesa');
+
+-- Deposits and withdrawals inserted without a provider went through M-Pesa
+CREATE OR REPLACE FUNCTION default_transaction_provider()
+RETURNS TRIGGER AS $$
+BEGIN
+    IF NEW.provider IS NULL AND NEW.type IN ('deposit_mpesa', 'withdrawal_mpesa') THEN
+        NEW.provider = 'mpesa';
+    END IF;
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER transactions_default_provider
+    BEFORE INSERT ON transactions
+    FOR EACH ROW
+    EXECUTE FUNCTION default_transaction_provider();
+
+ALTER TABLE transactions ADD CONSTRAINT mobile_money_provider CHECK (
+    (provider IS NOT NULL) = (type IN ('deposit_mpesa', 'withdrawal_mpesa'))
+);
+
+CREATE UNIQUE INDEX idx_transactions_provider_reference
+    ON transactions(provider, provider_reference) WHERE provider_reference IS NOT NULL;
+CREATE INDEX idx_transactions_provider_processing
+    ON transactions(created_at) WHERE status = 'processing' AND provider_reference IS NOT NULL;
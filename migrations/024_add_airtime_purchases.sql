@@ -0,0 +1,28 @@
+-- Airtime purchases: Airtime for any Kenyan phone, paid in sats
+-- The wallet is debited when the purchase is made; the airtime provider's
+-- reference is kept in `provider_reference` (with no mobile money `provider`)
+-- until its status callback arrives. Failed purchases are refunded in sats.
+
+ALTER TYPE transaction_type ADD VALUE 'airtime_purchase';
+ALTER TYPE refund_reason ADD VALUE 'airtime_failed';  -- Airtime provider could not deliver
+
+-- Airtime is paid for up front, like withdrawals and Lightning sends
+CREATE OR REPLACE FUNCTION transaction_balance_effect(
+    tx_type transaction_type,
+    tx_status transaction_status,
+    amount_sats BIGINT,
+    fee_sats BIGINT
+)
+RETURNS BIGINT AS $$
+    SELECT CASE
+        WHEN tx_type::text IN ('withdrawal_mpesa', 'lightning_send', 'airtime_purchase')
+            THEN -(COALESCE(amount_sats, 0) + COALESCE(fee_sats, 0))
+        WHEN tx_status = 'completed'
+            THEN COALESCE(amount_sats, 0)
+        ELSE 0
+    END;
+$$ LANGUAGE sql IMMUTABLE;
+
+-- Airtime purchases waiting for the provider, found by its reference
+CREATE INDEX idx_transactions_airtime_reference
+    ON transactions(provider_reference) WHERE provider IS NULL AND provider_reference IS NOT NULL;
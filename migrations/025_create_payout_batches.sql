@@ -0,0 +1,82 @@
+-- Payout batches: Bulk disbursements to many recipients at once
+-- An employer or NGO uploads rows of recipient (phone number or Lightning
+-- address), amount and reference. The whole batch is validated against the
+-- balance and limits before anything is paid; payment-service then pays the
+-- rows in the background at a rate each provider accepts. Pending rows are
+-- dropped when the batch is cancelled.
+
+CREATE TYPE payout_batch_status AS ENUM (
+    'processing',  -- Rows are being paid
+    'completed',   -- Every row has been paid or has failed
+    'cancelled'    -- Stopped by the user; rows not yet started were dropped
+);
+
+CREATE TYPE payout_recipient_type AS ENUM (
+    'mobile_money',      -- Withdrawal to a phone (provider says which network)
+    'lightning_address'  -- Any Lightning address
+);
+
+CREATE TYPE payout_item_status AS ENUM (
+    'pending',     -- Waiting its turn
+    'processing',  -- Payment being made
+    'sent',        -- Payment made (transaction_id is set)
+    'failed',      -- Payment could not be made
+    'cancelled'    -- Batch cancelled before the row was paid
+);
+
+CREATE TABLE payout_batches (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    name VARCHAR(100),
+
+    status payout_batch_status NOT NULL DEFAULT 'processing',
+    -- Sats debited if every row is paid, fees included (KES rows at the rate when the batch was created)
+    total_sats BIGINT NOT NULL,
+    exchange_rate DECIMAL(15,2) NOT NULL,
+
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    finished_at TIMESTAMPTZ,
+
+    CONSTRAINT payout_batch_positive_total CHECK (total_sats > 0)
+);
+
+CREATE INDEX idx_payout_batches_user_id ON payout_batches(user_id, created_at DESC);
+CREATE INDEX idx_payout_batches_processing ON payout_batches(created_at) WHERE status = 'processing';
+
+CREATE TRIGGER payout_batches_updated_at
+    BEFORE UPDATE ON payout_batches
+    FOR EACH ROW
+    EXECUTE FUNCTION update_updated_at();
+
+-- One row of a batch
+CREATE TABLE payout_items (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    batch_id UUID NOT NULL REFERENCES payout_batches(id) ON DELETE CASCADE,
+    -- Position in the uploaded batch, from 1
+    row_number INTEGER NOT NULL,
+
+    recipient_type payout_recipient_type NOT NULL,
+    -- E.164 phone number or Lightning address
+    recipient VARCHAR(255) NOT NULL,
+    provider mobile_money_network,
+
+    amount_sats BIGINT NOT NULL,
+    -- Amount asked for, when the row was in KES
+    amount_kes DECIMAL(15,2),
+    reference VARCHAR(100),
+
+    status payout_item_status NOT NULL DEFAULT 'pending',
+    transaction_id UUID REFERENCES transactions(id),
+    error TEXT,
+    started_at TIMESTAMPTZ,
+    finished_at TIMESTAMPTZ,
+
+    UNIQUE (batch_id, row_number),
+    CONSTRAINT payout_item_positive_amount CHECK (amount_sats > 0),
+    CONSTRAINT payout_item_provider CHECK ((recipient_type = 'mobile_money') = (provider IS NOT NULL))
+);
+
+CREATE INDEX idx_payout_items_pending ON payout_items(batch_id, row_number) WHERE status = 'pending';
+-- Rows started in the last minute, per provider, for throttling
+CREATE INDEX idx_payout_items_started ON payout_items(started_at) WHERE started_at IS NOT NULL;
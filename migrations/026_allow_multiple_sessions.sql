@@ -0,0 +1,28 @@
+-- Multiple sessions: Users can stay signed in on several devices at once
+-- Every login or registration starts a new session instead of replacing the
+-- last one. Tokens carry their session's ID, so signing a device out (or all
+-- the others) stops its tokens at the gateway straight away.
+
+CREATE TYPE device_platform AS ENUM (
+    'android',
+    'ios',
+    'web',
+    'other'
+);
+
+ALTER TABLE sessions
+    DROP CONSTRAINT unique_user_session,
+    -- Name the app reports for the device (e.g. "Pixel 7")
+    ADD COLUMN device_name VARCHAR(100),
+    ADD COLUMN platform device_platform NOT NULL DEFAULT 'other',
+    -- Address the session was started from, then the one it was last used from
+    ADD COLUMN ip_address VARCHAR(45),
+    ADD COLUMN user_agent VARCHAR(255),
+    ADD COLUMN last_seen_at TIMESTAMPTZ NOT NULL DEFAULT NOW();
+
+-- Sessions from before this change have no session ID in their tokens and
+-- can't be refreshed any more; drop them so they don't show up as devices
+DELETE FROM sessions;
+
+DROP INDEX idx_sessions_user_id;
+CREATE INDEX idx_sessions_user_id ON sessions(user_id, last_seen_at DESC);
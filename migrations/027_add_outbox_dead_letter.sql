@@ -0,0 +1,16 @@
+-- Outbox dead letters: Events the relay could not decode
+-- A payload the relay cannot read (hand-written row, or an event type added
+-- by a newer release) is retried for a day after the event occurred, then set
+-- aside here so it no longer holds up the events behind it. Clearing dead_lettered_at requeues it.
+
+ALTER TABLE outbox_events
+    ADD COLUMN dead_lettered_at TIMESTAMPTZ;
+
+-- The relay only scans events that are neither published nor dead-lettered
+DROP INDEX idx_outbox_events_unpublished;
+CREATE INDEX idx_outbox_events_unpublished ON outbox_events(sequence)
+    WHERE published_at IS NULL AND dead_lettered_at IS NULL;
+
+-- Listing dead letters for inspection
+CREATE INDEX idx_outbox_events_dead_lettered ON outbox_events(dead_lettered_at)
+    WHERE dead_lettered_at IS NOT NULL;
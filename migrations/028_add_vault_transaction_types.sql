@@ -0,0 +1,7 @@
+-- Vault transfer types: Moves between the spendable balance and savings vaults
+-- Booked as transactions so statements and balance history account for them.
+-- The values are used by the next migration (new enum values can't be used in
+-- the transaction that adds them).
+
+ALTER TYPE transaction_type ADD VALUE 'vault_deposit';     -- Spendable → vault
+ALTER TYPE transaction_type ADD VALUE 'vault_withdrawal';  -- Vault → spendable
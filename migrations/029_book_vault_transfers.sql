@@ -0,0 +1,44 @@
+-- Vault transfers in the ledger: Every vault entry gets a matching transaction
+-- Vault moves change wallets.balance_sats, which statements rebuild from
+-- transactions, so each move is booked as a completed vault_deposit or
+-- vault_withdrawal and linked from its vault entry.
+
+-- Sats moved into a vault leave the spendable balance like an outgoing payment
+CREATE OR REPLACE FUNCTION transaction_balance_effect(
+    tx_type transaction_type,
+    tx_status transaction_status,
+    amount_sats BIGINT,
+    fee_sats BIGINT
+)
+RETURNS BIGINT AS $$
+    SELECT CASE
+        WHEN tx_type::text IN ('withdrawal_mpesa', 'lightning_send', 'airtime_purchase', 'vault_deposit')
+            THEN -(COALESCE(amount_sats, 0) + COALESCE(fee_sats, 0))
+        WHEN tx_status = 'completed'
+            THEN COALESCE(amount_sats, 0)
+        ELSE 0
+    END;
+$$ LANGUAGE sql IMMUTABLE;
+
+ALTER TABLE vault_entries
+    ADD COLUMN transaction_id UUID REFERENCES transactions(id);
+
+-- Book the moves made before vault transfers were transactions
+SELECT set_config('pesabit.status_actor', 'system', true),
+       set_config('pesabit.status_reason', 'vault_transfer_backfill', true);
+
+WITH booked AS (
+    INSERT INTO transactions (id, user_id, type, status, amount_sats, fee_sats, metadata, created_at, completed_at)
+    SELECT e.id, e.user_id,
+           CASE WHEN e.entry_type = 'deposit' THEN 'vault_deposit' ELSE 'vault_withdrawal' END::transaction_type,
+           'completed', e.amount_sats, 0,
+           jsonb_build_object(
+               'vault_id', e.vault_id,
+               'description', CASE WHEN e.entry_type = 'deposit' THEN 'To vault ' ELSE 'From vault ' END || v.name
+           ),
+           e.created_at, e.created_at
+    FROM vault_entries e
+    JOIN vaults v ON v.id = e.vault_id
+    RETURNING id
+)
+UPDATE vault_entries SET transaction_id = booked.id FROM booked WHERE vault_entries.id = booked.id;
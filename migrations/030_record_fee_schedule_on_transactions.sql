@@ -0,0 +1,35 @@
+-- Fee schedule on every priced transaction: Fill in fee_schedule_id where it was left out
+-- Mobile money and bill payments record the schedule they priced with. The
+-- original M-Pesa deposit and withdrawal paths price with the active schedule
+-- but insert without its ID, so it is filled in from the schedule in force
+-- when the row was created.
+
+CREATE OR REPLACE FUNCTION default_transaction_fee_schedule()
+RETURNS TRIGGER AS $$
+BEGIN
+    IF NEW.fee_schedule_id IS NULL AND NEW.type IN ('deposit_mpesa', 'withdrawal_mpesa') THEN
+        SELECT id INTO NEW.fee_schedule_id
+        FROM fee_schedules
+        WHERE rail = NEW.type AND effective_from <= NEW.created_at
+        ORDER BY effective_from DESC, version DESC
+        LIMIT 1;
+    END IF;
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER transactions_default_fee_schedule
+    BEFORE INSERT ON transactions
+    FOR EACH ROW
+    EXECUTE FUNCTION default_transaction_fee_schedule();
+
+-- Rows already written without one
+UPDATE transactions t
+SET fee_schedule_id = (
+    SELECT s.id
+    FROM fee_schedules s
+    WHERE s.rail = t.type AND s.effective_from <= t.created_at
+    ORDER BY s.effective_from DESC, s.version DESC
+    LIMIT 1
+)
+WHERE t.fee_schedule_id IS NULL AND t.type IN ('deposit_mpesa', 'withdrawal_mpesa');
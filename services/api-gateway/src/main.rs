@@ -22,6 +22,8 @@ use shared_config::AppConfig;
 use shared_errors::{AppError, Result};
 use shared_security::{create_cors_layer, request_validation_middleware, security_headers_middleware};
 use shared_tracing::init_tracing;
+use sqlx::PgPool;
+use std::net::SocketAddr;
 use std::sync::Arc;
 use tokio::net::TcpListener;
 use tracing::{error, info, instrument, warn};
@@ -39,7 +41,9 @@ use service_client::*;
 pub struct AppState {
     pub user_service_client: Arc<UserServiceClient>,
     pub payment_service_client: Arc<PaymentServiceClient>,
+    pub notification_service_client: Arc<NotificationServiceClient>,
     pub rate_limiter: Arc<RateLimiter>,
+    pub db: PgPool,
     pub config: AppConfig,
 }
 
@@ -59,14 +63,20 @@ async fn main() -> Result<()> {
     // Create service clients
     let user_service_client = Arc::new(UserServiceClient::new(&config.services.user_service_url));
     let payment_service_client = Arc::new(PaymentServiceClient::new(&config.services.payment_service_url));
+    let notification_service_client = Arc::new(NotificationServiceClient::new(&config.services.notification_service_url));
     
     // Create rate limiter
     let rate_limiter = Arc::new(RateLimiter::new(&config.redis.url).await?);
 
+    // Connect to database (API keys are looked up here)
+    let db = shared_database::init().await?;
+
     let state = AppState {
         user_service_client,
         payment_service_client,
+        notification_service_client,
         rate_limiter,
+        db,
         config: config.clone(),
     };
 
@@ -74,6 +84,10 @@ async fn main() -> Result<()> {
     let app = Router::new()
         .route("/health", get(health_check))
         .route("/v1/*path", any(route_to_services))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            auth_middleware,
+        ))
         .layer(create_cors_layer(&config))
         .layer(middleware::from_fn_with_state(
             state.clone(),
@@ -91,7 +105,8 @@ async fn main() -> Result<()> {
     info!("🚀 PesaBit API Gateway listening on {}", addr);
     info!("📋 API Documentation available at http://{}/docs", addr);
     
-    axum::serve(listener, app)
+    // Peer addresses are needed to identify clients when there is no load balancer
+    axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>())
         .await
         .map_err(|e| AppError::Internal(anyhow::anyhow!("Server error: {}", e)))?;
 
@@ -104,8 +119,12 @@ async fn health_check(State(state): State<AppState>) -> Result<impl IntoResponse
     // Check if downstream services are healthy
     let user_service_health = state.user_service_client.health_check().await;
     let payment_service_health = state.payment_service_client.health_check().await;
+    let notification_service_health = state.notification_service_client.health_check().await;
     
-    let overall_status = if user_service_health.is_ok() && payment_service_health.is_ok() {
+    let overall_status = if user_service_health.is_ok()
+        && payment_service_health.is_ok()
+        && notification_service_health.is_ok()
+    {
         "healthy"
     } else {
         "degraded"
@@ -117,7 +136,8 @@ async fn health_check(State(state): State<AppState>) -> Result<impl IntoResponse
         "timestamp": chrono::Utc::now(),
         "services": {
             "user_service": user_service_health.is_ok(),
-            "payment_service": payment_service_health.is_ok()
+            "payment_service": payment_service_health.is_ok(),
+            "notification_service": notification_service_health.is_ok()
         },
         "version": env!("CARGO_PKG_VERSION")
     });
@@ -142,6 +162,9 @@ async fn route_to_services(
         path if path.starts_with("/v1/users/") => {
             (&state.user_service_client as &dyn ServiceClient, path.strip_prefix("/v1").unwrap())
         }
+        path if path.starts_with("/v1/sms/") => {
+            (&state.user_service_client as &dyn ServiceClient, path.strip_prefix("/v1").unwrap())
+        }
         
         // Payment service routes
         path if path.starts_with("/v1/balance") => {
@@ -162,6 +185,50 @@ async fn route_to_services(
         path if path.starts_with("/v1/exchange-rates/") => {
             (&state.payment_service_client as &dyn ServiceClient, path.strip_prefix("/v1").unwrap())
         }
+        path if path.starts_with("/v1/fees/") => {
+            (&state.payment_service_client as &dyn ServiceClient, path.strip_prefix("/v1").unwrap())
+        }
+        path if path.starts_with("/v1/limits") => {
+            (&state.payment_service_client as &dyn ServiceClient, path.strip_prefix("/v1").unwrap())
+        }
+        path if path.starts_with("/v1/statements") => {
+            (&state.payment_service_client as &dyn ServiceClient, path.strip_prefix("/v1").unwrap())
+        }
+        path if path.starts_with("/v1/scheduled-payments") => {
+            (&state.payment_service_client as &dyn ServiceClient, path.strip_prefix("/v1").unwrap())
+        }
+        path if path.starts_with("/v1/payment-requests") => {
+            (&state.payment_service_client as &dyn ServiceClient, path.strip_prefix("/v1").unwrap())
+        }
+        path if path.starts_with("/v1/dca-plans") => {
+            (&state.payment_service_client as &dyn ServiceClient, path.strip_prefix("/v1").unwrap())
+        }
+        path if path.starts_with("/v1/vaults") => {
+            (&state.payment_service_client as &dyn ServiceClient, path.strip_prefix("/v1").unwrap())
+        }
+        path if path.starts_with("/v1/bill-payments") => {
+            (&state.payment_service_client as &dyn ServiceClient, path.strip_prefix("/v1").unwrap())
+        }
+        path if path.starts_with("/v1/payouts") => {
+            (&state.payment_service_client as &dyn ServiceClient, path.strip_prefix("/v1").unwrap())
+        }
+        path if path.starts_with("/v1/airtime") => {
+            (&state.payment_service_client as &dyn ServiceClient, path.strip_prefix("/v1").unwrap())
+        }
+        path if path.starts_with("/v1/mobile-money/") => {
+            (&state.payment_service_client as &dyn ServiceClient, path.strip_prefix("/v1").unwrap())
+        }
+        path if path.starts_with("/v1/merchant") => {
+            (&state.payment_service_client as &dyn ServiceClient, path.strip_prefix("/v1").unwrap())
+        }
+        
+        // Notification service routes
+        path if path.starts_with("/v1/notifications") => {
+            (&state.notification_service_client as &dyn ServiceClient, path.strip_prefix("/v1").unwrap())
+        }
+        path if path.starts_with("/v1/webhooks/") => {
+            (&state.notification_service_client as &dyn ServiceClient, path.strip_prefix("/v1").unwrap())
+        }
         
         _ => {
             warn!("Unknown route: {}", path);
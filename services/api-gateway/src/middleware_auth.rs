@@ -2,16 +2,30 @@
 /// 
 /// This module handles JWT token validation and user authentication for protected routes.
 /// It extracts user information from tokens and makes it available to downstream services.
+/// Requests may instead carry an API key, which is checked against its scopes, IP
+/// allowlist and rate limit and then forwarded with a short-lived access token.
+/// Access tokens name the login session they belong to; once that session is
+/// signed out (from another device, say) the token is refused.
 
+use crate::middleware_rate_limit::RateLimit;
 use axum::{
-    extract::{Request, State},
-    http::{HeaderMap, StatusCode},
+    extract::{ConnectInfo, Request, State},
+    http::{HeaderMap, Method, StatusCode},
     middleware::Next,
     response::{IntoResponse, Response},
 };
+use shared_auth::api_keys::{api_key_lookup, hash_api_key, ip_allowed, ApiKeyScope, API_KEY_HEADER};
 use shared_auth::{AuthUser, JwtService};
 use shared_errors::{AppError, Result};
+use shared_types::KycTier;
+use std::net::{IpAddr, SocketAddr};
 use tracing::{info, instrument, warn};
+use uuid::Uuid;
+
+/// Lifetime of the access token forwarded with an API-key request
+const API_KEY_ACCESS_TOKEN_SECONDS: i64 = 60;
+/// How often a session's last-seen time is updated while it's in use
+const SESSION_LAST_SEEN_INTERVAL_SECONDS: i64 = 60;
 
 /// Authentication middleware that validates JWT tokens
 #[instrument(skip(request, next))]
@@ -27,8 +41,26 @@ pub async fn auth_middleware(
         return Ok(next.run(request).await);
     }
 
-    // Extract and validate JWT token
-    match extract_and_validate_token(request.headers()) {
+    // API keys are checked against the database rather than decoded
+    if request.headers().contains_key(API_KEY_HEADER) {
+        return authenticate_api_key(&state, request, next).await;
+    }
+
+    // Extract and validate JWT token, then check its session hasn't been signed out
+    let user = match extract_and_validate_token(request.headers()) {
+        Ok(user) => {
+            if session_active(&state, &user, request_client_ip(&state, &request)).await? {
+                Ok(user)
+            } else {
+                Err(AppError::Auth {
+                    message: "Session has ended".to_string(),
+                })
+            }
+        }
+        Err(e) => Err(e),
+    };
+
+    match user {
         Ok(user) => {
             // Add user info to request headers for downstream services
             add_user_headers(&mut request, &user);
@@ -41,6 +73,38 @@ pub async fn auth_middleware(
     }
 }
 
+/// Whether the token's login session still exists, noting that it was just used
+/// (tokens without a session were issued before sessions were tracked and expire soon)
+async fn session_active(state: &crate::AppState, user: &AuthUser, ip: Option<IpAddr>) -> Result<bool> {
+    let Some(session_id) = user.session_id else {
+        return Ok(true);
+    };
+
+    let last_seen_at = sqlx::query_scalar!(
+        "SELECT last_seen_at FROM sessions WHERE id = $1 AND user_id = $2 AND expires_at > NOW()",
+        session_id,
+        user.user_id.0,
+    )
+    .fetch_optional(&state.db)
+    .await?;
+
+    let Some(last_seen_at) = last_seen_at else {
+        return Ok(false);
+    };
+
+    if chrono::Utc::now() - last_seen_at > chrono::Duration::seconds(SESSION_LAST_SEEN_INTERVAL_SECONDS) {
+        sqlx::query!(
+            "UPDATE sessions SET last_seen_at = NOW(), ip_address = COALESCE($2, ip_address) WHERE id = $1",
+            session_id,
+            ip.map(|ip| ip.to_string()),
+        )
+        .execute(&state.db)
+        .await?;
+    }
+
+    Ok(true)
+}
+
 /// Check if endpoint is public (doesn't require authentication)
 fn is_public_endpoint(path: &str) -> bool {
     matches!(path, 
@@ -48,10 +112,230 @@ fn is_public_endpoint(path: &str) -> bool {
         "/v1/auth/register" |
         "/v1/auth/verify-otp" |
         "/v1/auth/login" |
+        "/v1/auth/refresh" |
+        "/v1/deposits/mpesa/callback" |
+        "/v1/airtime/callback" |
+        "/v1/sms/delivery-reports" |
         "/v1/exchange-rates/current" |
+        "/v1/exchange-rates/history" |
         "/docs" |
         "/docs/"
+    ) || is_signed_statement_download(path) || is_lightning_address_lookup(path) || is_mobile_money_callback(path)
+}
+
+/// Statement downloads are authorized by the signature in the link, so they open in a browser
+fn is_signed_statement_download(path: &str) -> bool {
+    path.strip_prefix("/v1/statements/")
+        .and_then(|rest| rest.strip_suffix("/download"))
+        .is_some_and(|id| !id.is_empty() && !id.contains('/'))
+}
+
+/// Lightning addresses are looked up by anyone paying the user
+fn is_lightning_address_lookup(path: &str) -> bool {
+    path.strip_prefix("/v1/users/")
+        .and_then(|rest| rest.strip_suffix("/lightning-address"))
+        .is_some_and(|id| !id.is_empty() && !id.contains('/'))
+}
+
+/// Mobile money providers post results to `/v1/mobile-money/<provider>/callback`;
+/// the payment service checks the token in the callback URL
+fn is_mobile_money_callback(path: &str) -> bool {
+    path.strip_prefix("/v1/mobile-money/")
+        .and_then(|rest| rest.strip_suffix("/callback"))
+        .is_some_and(|provider| !provider.is_empty() && !provider.contains('/'))
+}
+
+/// Active API key and the account it belongs to
+struct ApiKeyOwner {
+    id: Uuid,
+    user: AuthUser,
+    scopes: Vec<String>,
+    ip_allowlist: Vec<String>,
+    rate_limit_per_minute: i32,
+}
+
+/// Authenticate a request carrying an API key and forward it as its owner
+async fn authenticate_api_key(state: &crate::AppState, mut request: Request, next: Next) -> Result<Response> {
+    let path = request.uri().path().to_string();
+    let Some(key) = request.headers().get(API_KEY_HEADER).and_then(|h| h.to_str().ok()) else {
+        return Ok(create_api_key_error_response(StatusCode::UNAUTHORIZED, "INVALID_API_KEY", "Invalid or expired API key"));
+    };
+    let Some(owner) = find_api_key(state, key).await? else {
+        warn!("Invalid API key used for {}", path);
+        return Ok(create_api_key_error_response(StatusCode::UNAUTHORIZED, "INVALID_API_KEY", "Invalid or expired API key"));
+    };
+
+    if !ip_allowed(&owner.ip_allowlist, request_client_ip(state, &request)) {
+        warn!("API key {} used from an address outside its allowlist", owner.id);
+        return Ok(create_api_key_error_response(
+            StatusCode::FORBIDDEN,
+            "IP_NOT_ALLOWED",
+            "This API key can't be used from your IP address",
+        ));
+    }
+
+    match required_scope(request.method(), &path) {
+        Some(scope) if owner.scopes.iter().any(|s| s == scope.as_str()) => {}
+        Some(scope) => {
+            return Ok(create_api_key_error_response(
+                StatusCode::FORBIDDEN,
+                "INSUFFICIENT_SCOPE",
+                &format!("This API key needs the {} scope", scope.as_str()),
+            ));
+        }
+        None => {
+            return Ok(create_api_key_error_response(
+                StatusCode::FORBIDDEN,
+                "INSUFFICIENT_SCOPE",
+                "This endpoint can't be used with an API key",
+            ));
+        }
+    }
+
+    let rate_limit = RateLimit {
+        requests_per_minute: owner.rate_limit_per_minute as u32,
+        window_seconds: 60,
+    };
+    let rate_limit_key = format!("rate_limit:api_key:{}", owner.id);
+    match state.rate_limiter.check_rate_limit(&rate_limit_key, &rate_limit).await {
+        Ok(true) => {}
+        Ok(false) => {
+            let error_response = serde_json::json!({
+                "error": "RATE_LIMIT_EXCEEDED",
+                "message": "Too many requests for this API key. Please wait and try again.",
+                "retry_after_seconds": rate_limit.window_seconds
+            });
+            return Ok((
+                StatusCode::TOO_MANY_REQUESTS,
+                [("Retry-After", rate_limit.window_seconds.to_string())],
+                axum::Json(error_response),
+            ).into_response());
+        }
+        // Redis error - allow request but log error, as for other requests
+        Err(e) => warn!("Rate limiting failed: {:?}", e),
+    }
+
+    sqlx::query!("UPDATE api_keys SET last_used_at = NOW() WHERE id = $1", owner.id)
+        .execute(&state.db)
+        .await?;
+
+    // Services only understand bearer tokens, so forward a short-lived one
+    let jwt_secret = std::env::var("JWT_SECRET")
+        .unwrap_or_else(|_| "your-secret-key".to_string());
+    let access_token = JwtService::new(&jwt_secret).generate_access_token(
+        owner.user.user_id,
+        &owner.user.phone,
+        owner.user.kyc_tier.clone(),
+        chrono::Duration::seconds(API_KEY_ACCESS_TOKEN_SECONDS),
+    )?;
+    let authorization = format!("Bearer {}", access_token)
+        .parse()
+        .map_err(|e| AppError::Internal(anyhow::anyhow!("Invalid authorization header: {}", e)))?;
+
+    let headers = request.headers_mut();
+    headers.remove(API_KEY_HEADER);
+    headers.insert("authorization", authorization);
+    add_user_headers(&mut request, &owner.user);
+
+    info!("API key {} authenticated for {}", owner.id, path);
+    Ok(next.run(request).await)
+}
+
+/// Look up an active API key by its prefix and hash
+async fn find_api_key(state: &crate::AppState, key: &str) -> Result<Option<ApiKeyOwner>> {
+    let Some(key_prefix) = api_key_lookup(key) else {
+        return Ok(None);
+    };
+
+    let row = sqlx::query!(
+        r#"
+        SELECT k.id, k.user_id, k.scopes, k.ip_allowlist, k.rate_limit_per_minute,
+               u.phone_number, u.kyc_tier as "kyc_tier: KycTier"
+        FROM api_keys k
+        JOIN users u ON u.id = k.user_id
+        WHERE k.key_prefix = $1 AND k.key_hash = $2
+          AND k.revoked_at IS NULL AND (k.expires_at IS NULL OR k.expires_at > NOW())
+        "#,
+        key_prefix,
+        hash_api_key(key),
     )
+    .fetch_optional(&state.db)
+    .await?;
+
+    let Some(row) = row else {
+        return Ok(None);
+    };
+    let phone = shared_types::PhoneNumber::new(row.phone_number)
+        .map_err(|_| AppError::Internal(anyhow::anyhow!("Invalid phone number for user {}", row.user_id)))?;
+
+    Ok(Some(ApiKeyOwner {
+        id: row.id,
+        user: AuthUser {
+            user_id: shared_types::UserId(row.user_id),
+            phone,
+            kyc_tier: row.kyc_tier,
+            session_id: None,
+        },
+        scopes: row.scopes,
+        ip_allowlist: row.ip_allowlist,
+        rate_limit_per_minute: row.rate_limit_per_minute,
+    }))
+}
+
+/// Scope an API key needs for an endpoint (None = not available to API keys)
+fn required_scope(method: &Method, path: &str) -> Option<ApiKeyScope> {
+    let read = method == Method::GET;
+    match path {
+        "/v1/balance" | "/v1/limits" | "/v1/fees/preview" if read => Some(ApiKeyScope::ReadBalance),
+        path if read && (path.starts_with("/v1/transactions") || path.starts_with("/v1/statements")) => {
+            Some(ApiKeyScope::ReadTransactions)
+        }
+        "/v1/lightning/invoice" if method == Method::POST => Some(ApiKeyScope::CreateInvoice),
+        "/v1/merchant/invoices" if read || method == Method::POST => Some(ApiKeyScope::CreateInvoice),
+        path if read && path.starts_with("/v1/merchant/invoices/") => Some(ApiKeyScope::CreateInvoice),
+        "/v1/lightning/pay" if method == Method::POST => Some(ApiKeyScope::SendPayment),
+        "/v1/withdrawals/mpesa" | "/v1/withdrawals/mobile-money" if method == Method::POST => {
+            Some(ApiKeyScope::WithdrawMpesa)
+        }
+        path if read && (path.starts_with("/v1/merchant/reports/") || path == "/v1/merchant/settlements") => {
+            Some(ApiKeyScope::ReadReports)
+        }
+        path if (read || method == Method::POST) && path.starts_with("/v1/payouts") => Some(ApiKeyScope::SendPayouts),
+        _ => None,
+    }
+}
+
+/// Client address of a request
+fn request_client_ip(state: &crate::AppState, request: &Request) -> Option<IpAddr> {
+    let peer = request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip());
+    client_ip(request.headers(), peer, state.config.security.trusted_proxy_hops)
+}
+
+/// Client address behind `trusted_hops` proxies
+///
+/// Each proxy appends the address it received the request from to
+/// X-Forwarded-For, so only the last `trusted_hops` entries were written by our
+/// own infrastructure; anything before them came from the client and can't be
+/// trusted. Without proxies the socket peer address is used.
+fn client_ip(headers: &HeaderMap, peer: Option<IpAddr>, trusted_hops: usize) -> Option<IpAddr> {
+    if trusted_hops == 0 {
+        return peer;
+    }
+
+    let hops: Vec<&str> = headers
+        .get_all("x-forwarded-for")
+        .iter()
+        .filter_map(|h| h.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .collect();
+
+    hops.len()
+        .checked_sub(trusted_hops)
+        .and_then(|index| hops[index].parse().ok())
 }
 
 /// Extract JWT token from Authorization header and validate it
@@ -94,10 +378,13 @@ fn extract_and_validate_token(headers: &HeaderMap) -> Result<AuthUser> {
             message: "Invalid phone number in token".to_string(),
         })?;
 
+    let session_id = claims.session_id()?;
+
     Ok(AuthUser {
         user_id: shared_types::UserId(user_id),
         phone,
         kyc_tier: claims.kyc_tier,
+        session_id,
     })
 }
 
@@ -140,6 +427,17 @@ fn create_auth_error_response() -> Response {
     ).into_response()
 }
 
+/// Error response for a rejected API key
+fn create_api_key_error_response(status: StatusCode, code: &str, message: &str) -> Response {
+    let error_response = serde_json::json!({
+        "error": if status == StatusCode::UNAUTHORIZED { "UNAUTHORIZED" } else { "FORBIDDEN" },
+        "message": message,
+        "code": code
+    });
+
+    (status, axum::Json(error_response)).into_response()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -149,8 +447,68 @@ mod tests {
         assert!(is_public_endpoint("/health"));
         assert!(is_public_endpoint("/v1/auth/register"));
         assert!(is_public_endpoint("/v1/auth/login"));
+        assert!(is_public_endpoint("/v1/exchange-rates/history"));
         assert!(!is_public_endpoint("/v1/balance"));
         assert!(!is_public_endpoint("/v1/transactions"));
+        assert!(is_public_endpoint("/v1/statements/0b8f4c1e-5d1a-4c3e-9f53-2f0e6a7b9c10/download"));
+        assert!(!is_public_endpoint("/v1/statements"));
+        assert!(!is_public_endpoint("/v1/statements/0b8f4c1e-5d1a-4c3e-9f53-2f0e6a7b9c10"));
+        assert!(!is_public_endpoint("/v1/statements/a/b/download"));
+        assert!(is_public_endpoint("/v1/auth/refresh"));
+        assert!(is_public_endpoint("/v1/deposits/mpesa/callback"));
+        assert!(is_public_endpoint("/v1/users/0b8f4c1e-5d1a-4c3e-9f53-2f0e6a7b9c10/lightning-address"));
+        assert!(!is_public_endpoint("/v1/users/me"));
+        assert!(!is_public_endpoint("/v1/users/me/api-keys"));
+        assert!(is_public_endpoint("/v1/mobile-money/airtel_money/callback"));
+        assert!(!is_public_endpoint("/v1/mobile-money/providers"));
+        assert!(is_public_endpoint("/v1/airtime/callback"));
+        assert!(!is_public_endpoint("/v1/airtime"));
+    }
+
+    #[test]
+    fn test_api_key_scopes() {
+        assert_eq!(required_scope(&Method::GET, "/v1/balance"), Some(ApiKeyScope::ReadBalance));
+        assert_eq!(required_scope(&Method::GET, "/v1/transactions/abc"), Some(ApiKeyScope::ReadTransactions));
+        assert_eq!(required_scope(&Method::POST, "/v1/lightning/invoice"), Some(ApiKeyScope::CreateInvoice));
+        assert_eq!(required_scope(&Method::GET, "/v1/merchant/invoices/abc"), Some(ApiKeyScope::CreateInvoice));
+        assert_eq!(required_scope(&Method::POST, "/v1/lightning/pay"), Some(ApiKeyScope::SendPayment));
+        assert_eq!(required_scope(&Method::POST, "/v1/withdrawals/mpesa"), Some(ApiKeyScope::WithdrawMpesa));
+        assert_eq!(required_scope(&Method::POST, "/v1/withdrawals/mobile-money"), Some(ApiKeyScope::WithdrawMpesa));
+        assert_eq!(required_scope(&Method::GET, "/v1/merchant/reports/daily"), Some(ApiKeyScope::ReadReports));
+        assert_eq!(required_scope(&Method::POST, "/v1/payouts"), Some(ApiKeyScope::SendPayouts));
+        assert_eq!(required_scope(&Method::POST, "/v1/payouts/abc/cancel"), Some(ApiKeyScope::SendPayouts));
+
+        // Keys can't manage the account or other keys
+        assert_eq!(required_scope(&Method::POST, "/v1/balance"), None);
+        assert_eq!(required_scope(&Method::POST, "/v1/users/me/api-keys"), None);
+        assert_eq!(required_scope(&Method::PATCH, "/v1/merchant"), None);
+        assert_eq!(required_scope(&Method::POST, "/v1/vaults/abc/withdraw"), None);
+    }
+
+    #[test]
+    fn test_client_ip() {
+        let peer: Option<IpAddr> = "10.0.0.5".parse().ok();
+        let mut headers = HeaderMap::new();
+        assert_eq!(client_ip(&headers, peer, 1), None);
+        assert_eq!(client_ip(&headers, peer, 0), peer);
+
+        // The load balancer appends the address it saw
+        headers.insert("x-forwarded-for", "41.90.12.7".parse().unwrap());
+        assert_eq!(client_ip(&headers, peer, 1), "41.90.12.7".parse().ok());
+
+        // Entries sent by the client are ignored, so an allowlisted address can't be claimed
+        headers.insert("x-forwarded-for", "196.201.214.200, 41.90.12.7".parse().unwrap());
+        assert_eq!(client_ip(&headers, peer, 1), "41.90.12.7".parse().ok());
+        headers.append("x-forwarded-for", "10.0.0.1".parse().unwrap());
+        assert_eq!(client_ip(&headers, peer, 2), "41.90.12.7".parse().ok());
+        assert_eq!(client_ip(&headers, peer, 3), "196.201.214.200".parse().ok());
+        assert_eq!(client_ip(&headers, peer, 4), None);
+
+        // X-Real-IP is client-controlled too
+        let mut headers = HeaderMap::new();
+        headers.insert("x-real-ip", "196.201.214.200".parse().unwrap());
+        assert_eq!(client_ip(&headers, peer, 1), None);
+        assert_eq!(client_ip(&headers, peer, 0), peer);
     }
 
     #[test]
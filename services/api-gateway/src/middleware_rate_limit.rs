@@ -91,7 +91,8 @@ impl RateLimiter {
             },
             
             // Payment endpoints (high security)
-            (path, true) if path.contains("/deposits/") || path.contains("/withdrawals/") => RateLimit {
+            (path, true) if path.contains("/deposits/") || path.contains("/withdrawals/") || path.contains("/bill-payments")
+                || path.contains("/airtime") || path.contains("/payouts") => RateLimit {
                 requests_per_minute: 10,  // 10 financial transactions per minute
                 window_seconds: 60,
             },
@@ -151,8 +152,8 @@ pub async fn rate_limit_middleware(
     let path = request.uri().path();
     let headers = request.headers();
     
-    // Skip rate limiting for health checks
-    if path == "/health" {
+    // Skip rate limiting for health checks, and for API keys (limited per key in auth_middleware)
+    if path == "/health" || headers.contains_key(shared_auth::api_keys::API_KEY_HEADER) {
         return Ok(next.run(request).await);
     }
 
@@ -1,7 +1,7 @@
 /// Service client for making HTTP requests to other microservices
 /// 
 /// This module handles communication between the API gateway and the backend services
-/// (user-service and payment-service). It provides a clean interface for forwarding
+/// (user-service, payment-service and notification-service). It provides a clean interface for forwarding
 /// requests and handling service-to-service communication.
 
 use axum::{
@@ -76,6 +76,33 @@ impl ServiceClient for PaymentServiceClient {
     }
 }
 
+/// Client for communicating with notification service
+pub struct NotificationServiceClient {
+    base_url: String,
+    http_client: Client,
+}
+
+impl NotificationServiceClient {
+    pub fn new(base_url: &str) -> Self {
+        Self {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            http_client: Client::new(),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl ServiceClient for NotificationServiceClient {
+    #[instrument(skip(self, request))]
+    async fn forward_request(&self, request: Request<Body>) -> Result<Response> {
+        forward_request_impl(&self.http_client, &self.base_url, request).await
+    }
+
+    async fn health_check(&self) -> Result<serde_json::Value> {
+        health_check_impl(&self.http_client, &self.base_url).await
+    }
+}
+
 /// Generic request forwarding implementation
 async fn forward_request_impl(
     http_client: &Client,
@@ -0,0 +1,221 @@
+/// Delivery channels
+///
+/// Each channel implements `NotificationChannel`. New channels (e.g., email or
+/// WhatsApp) only need an implementation and an entry in the channel map built
+/// in `main`.
+
+use crate::domain::*;
+use crate::repository::NotificationRepository;
+use async_trait::async_trait;
+use reqwest::{Client, StatusCode};
+use serde::Serialize;
+use shared_africastalking::AfricasTalkingSms;
+use shared_config::SmsConfig;
+use shared_errors::{AppError, Result};
+use std::sync::Arc;
+use tracing::{info, instrument, warn};
+
+/// Something that can deliver a rendered notification to a user
+#[async_trait]
+pub trait NotificationChannel: Send + Sync {
+    fn channel(&self) -> Channel;
+
+    async fn send(&self, recipient: &Recipient, delivery: &Delivery) -> Result<DeliveryReceipt>;
+}
+
+/// SMS delivery through Africa's Talking
+pub struct SmsChannel {
+    sms: AfricasTalkingSms,
+}
+
+impl SmsChannel {
+    pub fn new(config: SmsConfig) -> Self {
+        Self {
+            sms: AfricasTalkingSms::new(config),
+        }
+    }
+}
+
+#[async_trait]
+impl NotificationChannel for SmsChannel {
+    fn channel(&self) -> Channel {
+        Channel::Sms
+    }
+
+    #[instrument(skip(self, recipient, delivery), fields(delivery_id = %delivery.id))]
+    async fn send(&self, recipient: &Recipient, delivery: &Delivery) -> Result<DeliveryReceipt> {
+        // In development, just log the message
+        if std::env::var("ENVIRONMENT").unwrap_or_default() != "production" {
+            info!("📱 SMS to {}: {}", recipient.phone_number.0, delivery.message.body);
+            return Ok(DeliveryReceipt::default());
+        }
+
+        let recipients = self
+            .sms
+            .send(&[recipient.phone_number.0.as_str()], &delivery.message.body)
+            .await?;
+
+        match recipients.into_iter().next() {
+            Some(result) if result.is_accepted() => Ok(DeliveryReceipt {
+                provider_message_id: result.message_id,
+            }),
+            Some(result) => Err(AppError::ExternalService {
+                message: format!("SMS rejected: {} ({})", result.status, result.status_code),
+            }),
+            None => Err(AppError::ExternalService {
+                message: "SMS provider returned no recipients".to_string(),
+            }),
+        }
+    }
+}
+
+/// VAPID credentials identifying PesaBit to browser push services
+#[derive(Debug, Clone)]
+pub struct VapidConfig {
+    /// EC P-256 private key in PEM format
+    pub private_key_pem: String,
+    /// Matching public key (uncompressed point, base64url), also given to browsers
+    pub public_key: String,
+    /// Contact for push services (e.g., "mailto:support@pesa.co.ke")
+    pub subject: String,
+}
+
+impl VapidConfig {
+    pub fn from_env() -> Self {
+        Self {
+            private_key_pem: std::env::var("VAPID_PRIVATE_KEY").unwrap_or_default(),
+            public_key: std::env::var("VAPID_PUBLIC_KEY").unwrap_or_default(),
+            subject: std::env::var("VAPID_SUBJECT").unwrap_or_else(|_| "mailto:support@pesa.co.ke".to_string()),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct VapidClaims<'a> {
+    aud: &'a str,
+    exp: i64,
+    sub: &'a str,
+}
+
+/// Browser push delivery
+///
+/// Pushes carry no payload: the service worker wakes up and fetches the latest
+/// inbox entries from `/notifications`, so message content never passes
+/// through third-party push services.
+pub struct WebPushChannel {
+    config: VapidConfig,
+    repository: Arc<NotificationRepository>,
+    http_client: Client,
+}
+
+impl WebPushChannel {
+    pub fn new(config: VapidConfig, repository: Arc<NotificationRepository>) -> Self {
+        Self {
+            config,
+            repository,
+            http_client: Client::new(),
+        }
+    }
+
+    /// `Authorization` header value for a push service origin
+    fn vapid_authorization(&self, endpoint: &str) -> Result<String> {
+        let url = reqwest::Url::parse(endpoint).map_err(|e| AppError::Validation {
+            message: format!("Invalid push endpoint: {}", e),
+        })?;
+        let audience = url.origin().ascii_serialization();
+
+        let claims = VapidClaims {
+            aud: &audience,
+            exp: (chrono::Utc::now() + chrono::Duration::hours(12)).timestamp(),
+            sub: &self.config.subject,
+        };
+
+        let key = jsonwebtoken::EncodingKey::from_ec_pem(self.config.private_key_pem.as_bytes())
+            .map_err(|e| AppError::Internal(anyhow::anyhow!("Invalid VAPID private key: {}", e)))?;
+        let token = jsonwebtoken::encode(&jsonwebtoken::Header::new(jsonwebtoken::Algorithm::ES256), &claims, &key)
+            .map_err(|e| AppError::Internal(anyhow::anyhow!("Failed to sign VAPID token: {}", e)))?;
+
+        Ok(format!("vapid t={}, k={}", token, self.config.public_key))
+    }
+}
+
+#[async_trait]
+impl NotificationChannel for WebPushChannel {
+    fn channel(&self) -> Channel {
+        Channel::WebPush
+    }
+
+    #[instrument(skip(self, recipient, delivery), fields(delivery_id = %delivery.id))]
+    async fn send(&self, recipient: &Recipient, delivery: &Delivery) -> Result<DeliveryReceipt> {
+        let subscriptions = self.repository.push_subscriptions(recipient.user_id).await?;
+        if subscriptions.is_empty() {
+            return Ok(DeliveryReceipt::default());
+        }
+
+        let mut delivered = 0;
+        let mut last_error = None;
+
+        for subscription in subscriptions {
+            let result = self
+                .http_client
+                .post(&subscription.endpoint)
+                .header("Authorization", self.vapid_authorization(&subscription.endpoint)?)
+                .header("TTL", "86400")
+                .header("Urgency", "normal")
+                .header("Content-Length", "0")
+                .send()
+                .await;
+
+            match result {
+                Ok(response) if response.status().is_success() => {
+                    delivered += 1;
+                    self.repository.touch_push_subscription(subscription.id).await?;
+                }
+                // The browser unsubscribed or the subscription expired
+                Ok(response) if matches!(response.status(), StatusCode::NOT_FOUND | StatusCode::GONE) => {
+                    info!("Removing expired push subscription {}", subscription.id);
+                    self.repository.delete_push_subscription(subscription.id).await?;
+                }
+                Ok(response) => {
+                    warn!("Push service returned {} for subscription {}", response.status(), subscription.id);
+                    last_error = Some(format!("Push service returned {}", response.status()));
+                }
+                Err(e) => {
+                    warn!("Push request failed for subscription {}: {}", subscription.id, e);
+                    last_error = Some(format!("Push request failed: {}", e));
+                }
+            }
+        }
+
+        // Retry only if no device could be reached
+        match last_error {
+            Some(message) if delivered == 0 => Err(AppError::ExternalService { message }),
+            _ => Ok(DeliveryReceipt::default()),
+        }
+    }
+}
+
+/// In-app inbox delivery
+pub struct InAppChannel {
+    repository: Arc<NotificationRepository>,
+}
+
+impl InAppChannel {
+    pub fn new(repository: Arc<NotificationRepository>) -> Self {
+        Self { repository }
+    }
+}
+
+#[async_trait]
+impl NotificationChannel for InAppChannel {
+    fn channel(&self) -> Channel {
+        Channel::InApp
+    }
+
+    async fn send(&self, _recipient: &Recipient, delivery: &Delivery) -> Result<DeliveryReceipt> {
+        let notification_id = self.repository.insert_inbox(delivery).await?;
+        Ok(DeliveryReceipt {
+            provider_message_id: Some(notification_id.to_string()),
+        })
+    }
+}
@@ -0,0 +1,240 @@
+/// Domain models for notifications
+///
+/// Channels, languages, preferences, inbox entries and delivery records, plus
+/// the request/response types of the notification API.
+
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use shared_types::*;
+use uuid::Uuid;
+use validator::Validate;
+
+/// Ways a notification can reach a user
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, sqlx::Type)]
+#[sqlx(type_name = "notification_channel", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum Channel {
+    /// Text message to the user's phone
+    Sms,
+    /// Browser push notification
+    WebPush,
+    /// Inbox inside the app
+    InApp,
+}
+
+/// Languages templates are written in
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize, sqlx::Type)]
+#[sqlx(type_name = "notification_language", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum Language {
+    #[default]
+    En,
+    Sw,
+}
+
+/// User's notification settings
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NotificationPreferences {
+    pub language: Language,
+    pub sms_enabled: bool,
+    pub push_enabled: bool,
+    /// Event types the user opted out of entirely
+    pub muted_events: Vec<String>,
+}
+
+impl Default for NotificationPreferences {
+    fn default() -> Self {
+        Self {
+            language: Language::En,
+            sms_enabled: true,
+            push_enabled: true,
+            muted_events: Vec::new(),
+        }
+    }
+}
+
+impl NotificationPreferences {
+    /// Channels to deliver an event on
+    /// The in-app inbox always gets a copy unless the event is muted; SMS is only
+    /// used for events whose template allows it
+    pub fn channels_for(&self, event_type: &str, sms_allowed: bool) -> Vec<Channel> {
+        if self.muted_events.iter().any(|muted| muted == event_type) {
+            return Vec::new();
+        }
+
+        let mut channels = vec![Channel::InApp];
+        if self.push_enabled {
+            channels.push(Channel::WebPush);
+        }
+        if self.sms_enabled && sms_allowed {
+            channels.push(Channel::Sms);
+        }
+        channels
+    }
+}
+
+/// Who a notification is sent to
+#[derive(Debug, Clone)]
+pub struct Recipient {
+    pub user_id: UserId,
+    pub phone_number: PhoneNumber,
+}
+
+/// Rendered notification text
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RenderedMessage {
+    pub title: String,
+    pub body: String,
+}
+
+/// Notification to deliver on one channel
+#[derive(Debug, Clone)]
+pub struct Delivery {
+    pub id: Uuid,
+    pub event_id: Uuid,
+    pub event_type: String,
+    pub user_id: UserId,
+    pub channel: Channel,
+    pub message: RenderedMessage,
+    /// Event data shown with in-app notifications
+    pub data: serde_json::Value,
+    pub attempts: i32,
+    pub max_attempts: i32,
+}
+
+impl Delivery {
+    /// Wait before the next attempt after `attempts` failures (1m, 4m, 16m, 64m, capped at 4h)
+    pub fn retry_backoff(attempts: i32) -> Duration {
+        let exponent = attempts.clamp(1, 8) as u32 - 1;
+        Duration::minutes(4_i64.pow(exponent)).min(Duration::hours(4))
+    }
+
+    /// Whether another attempt is allowed after the current one failed
+    pub fn can_retry(&self) -> bool {
+        self.attempts + 1 < self.max_attempts
+    }
+}
+
+/// Result of a successful send
+#[derive(Debug, Clone, Default)]
+pub struct DeliveryReceipt {
+    /// Provider reference (e.g., SMS message ID)
+    pub provider_message_id: Option<String>,
+}
+
+/// In-app inbox entry
+#[derive(Debug, Clone, Serialize)]
+pub struct InboxNotification {
+    pub id: Uuid,
+    pub event_type: String,
+    pub title: String,
+    pub body: String,
+    pub data: serde_json::Value,
+    pub read_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Query parameters for listing the inbox
+#[derive(Debug, Deserialize)]
+pub struct InboxParams {
+    pub unread_only: Option<bool>,
+    pub limit: Option<i64>,
+    /// Return notifications created before this time (for paging)
+    pub before: Option<DateTime<Utc>>,
+}
+
+/// Inbox page
+#[derive(Debug, Serialize)]
+pub struct InboxResponse {
+    pub notifications: Vec<InboxNotification>,
+    pub unread_count: i64,
+}
+
+/// Request to change notification preferences (omitted fields are unchanged)
+#[derive(Debug, Deserialize, Validate)]
+pub struct UpdatePreferencesRequest {
+    pub language: Option<Language>,
+    pub sms_enabled: Option<bool>,
+    pub push_enabled: Option<bool>,
+    #[validate(length(max = 50))]
+    pub muted_events: Option<Vec<String>>,
+}
+
+impl NotificationPreferences {
+    /// Apply a partial update
+    pub fn apply(&mut self, update: UpdatePreferencesRequest) {
+        if let Some(language) = update.language {
+            self.language = language;
+        }
+        if let Some(sms_enabled) = update.sms_enabled {
+            self.sms_enabled = sms_enabled;
+        }
+        if let Some(push_enabled) = update.push_enabled {
+            self.push_enabled = push_enabled;
+        }
+        if let Some(muted_events) = update.muted_events {
+            self.muted_events = muted_events;
+        }
+    }
+}
+
+/// Browser push subscription as produced by `PushSubscription.toJSON()`
+#[derive(Debug, Clone, Deserialize, Validate)]
+pub struct PushSubscriptionRequest {
+    #[validate(url, length(max = 2000))]
+    pub endpoint: String,
+    pub keys: PushSubscriptionKeys,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct PushSubscriptionKeys {
+    pub p256dh: String,
+    pub auth: String,
+}
+
+/// Request to remove a push subscription (e.g., on logout)
+#[derive(Debug, Deserialize)]
+pub struct RemovePushSubscriptionRequest {
+    pub endpoint: String,
+}
+
+/// Stored push subscription
+/// Pushes are sent without payload, so the client keys aren't needed to deliver
+#[derive(Debug, Clone)]
+pub struct PushSubscription {
+    pub id: Uuid,
+    pub endpoint: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_channels_follow_preferences() {
+        let mut preferences = NotificationPreferences::default();
+        assert_eq!(
+            preferences.channels_for("DepositCompleted", true),
+            vec![Channel::InApp, Channel::WebPush, Channel::Sms]
+        );
+        assert_eq!(preferences.channels_for("PaymentSent", false), vec![Channel::InApp, Channel::WebPush]);
+
+        preferences.apply(UpdatePreferencesRequest {
+            language: Some(Language::Sw),
+            sms_enabled: Some(false),
+            push_enabled: None,
+            muted_events: Some(vec!["InvoicePaid".to_string()]),
+        });
+        assert_eq!(preferences.language, Language::Sw);
+        assert_eq!(preferences.channels_for("DepositCompleted", true), vec![Channel::InApp, Channel::WebPush]);
+        assert!(preferences.channels_for("InvoicePaid", true).is_empty());
+    }
+
+    #[test]
+    fn test_retry_backoff() {
+        assert_eq!(Delivery::retry_backoff(1), Duration::minutes(1));
+        assert_eq!(Delivery::retry_backoff(2), Duration::minutes(4));
+        assert_eq!(Delivery::retry_backoff(4), Duration::minutes(64));
+        assert_eq!(Delivery::retry_backoff(8), Duration::hours(4));
+    }
+}
@@ -0,0 +1,348 @@
+/// Notification Service for PesaBit
+///
+/// This service tells users what happened to their money:
+/// - Consumes domain events from the "pesabit:events" stream
+/// - Renders per-event templates in English or Kiswahili
+/// - Delivers by SMS, Web Push and the in-app inbox, following user preferences
+/// - Keeps a delivery log and retries failed sends with backoff
+/// - Sends signed webhooks to business customers
+
+use axum::{
+    extract::{Path, Query, State},
+    response::Json,
+    routing::{delete, get, post},
+    Router,
+};
+use shared_auth::AuthUser;
+use shared_config::SmsConfig;
+use shared_errors::{AppError, Result};
+use shared_events::EventConsumer;
+use shared_tracing::init_tracing;
+use sqlx::PgPool;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tower_http::cors::CorsLayer;
+use tracing::{error, info, instrument, warn};
+
+mod channels;
+mod domain;
+mod repository;
+mod service;
+mod templates;
+mod webhooks;
+
+use channels::*;
+use domain::*;
+use repository::*;
+use service::*;
+use webhooks::*;
+
+/// Consumer group of this service on the events stream
+const CONSUMER_GROUP: &str = "notification-service";
+
+/// Application state shared across all handlers
+#[derive(Clone)]
+pub struct AppState {
+    pub notification_service: Arc<NotificationService>,
+    pub webhook_service: Arc<WebhookService>,
+    pub vapid_public_key: String,
+    pub db: PgPool,
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    // Initialize logging
+    init_tracing("notification-service");
+
+    // Connect to database
+    let db = shared_database::init().await?;
+
+    let repository = Arc::new(NotificationRepository::new(db.clone()));
+
+    // Delivery channels
+    let vapid_config = VapidConfig::from_env();
+    let vapid_public_key = vapid_config.public_key.clone();
+    let channels: Vec<Arc<dyn NotificationChannel>> = vec![
+        Arc::new(InAppChannel::new(repository.clone())),
+        Arc::new(WebPushChannel::new(vapid_config, repository.clone())),
+        Arc::new(SmsChannel::new(SmsConfig::from_env())),
+    ];
+
+    let notification_service = Arc::new(NotificationService::new(repository, channels));
+    let webhook_service = Arc::new(WebhookService::new(Arc::new(WebhookRepository::new(db.clone()))));
+
+    // Consume domain events
+    let redis_url = std::env::var("REDIS_URL")
+        .unwrap_or_else(|_| "redis://:redis_dev_password@localhost:6379".to_string());
+    let redis = redis::Client::open(redis_url).map_err(|e| AppError::ExternalService {
+        message: format!("Invalid Redis URL: {}", e),
+    })?;
+    let consumer_name = std::env::var("HOSTNAME").unwrap_or_else(|_| uuid::Uuid::new_v4().to_string());
+    let consumer = EventConsumer::new(redis, CONSUMER_GROUP, &consumer_name);
+    consumer.ensure_group().await?;
+    tokio::spawn(consume_events(consumer, notification_service.clone(), webhook_service.clone()));
+
+    // Retry failed deliveries
+    let retry_notifications = notification_service.clone();
+    let retry_webhooks = webhook_service.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(30));
+        loop {
+            interval.tick().await;
+            match retry_notifications.retry_due(100).await {
+                Ok(0) => {}
+                Ok(attempted) => info!("Retried {} notification deliveries", attempted),
+                Err(e) => warn!("Failed to retry notification deliveries: {}", e),
+            }
+            match retry_webhooks.retry_due(100).await {
+                Ok(0) => {}
+                Ok(attempted) => info!("Retried {} webhook deliveries", attempted),
+                Err(e) => warn!("Failed to retry webhook deliveries: {}", e),
+            }
+        }
+    });
+
+    let state = AppState {
+        notification_service,
+        webhook_service,
+        vapid_public_key,
+        db,
+    };
+
+    // Build router with all endpoints
+    let app = Router::new()
+        .route("/health", get(health_check))
+
+        // In-app inbox
+        .route("/notifications", get(get_inbox))
+        .route("/notifications/:id/read", post(mark_read))
+        .route("/notifications/read-all", post(mark_all_read))
+
+        // Preferences
+        .route("/notifications/preferences", get(get_preferences).put(update_preferences))
+
+        // Web Push
+        .route("/notifications/push/vapid-public-key", get(get_vapid_public_key))
+        .route("/notifications/push/subscriptions", post(subscribe_push).delete(unsubscribe_push))
+
+        // Webhooks for business customers
+        .route("/webhooks/endpoints", get(list_webhook_endpoints).post(register_webhook_endpoint))
+        .route("/webhooks/endpoints/:id", delete(delete_webhook_endpoint))
+        .route("/webhooks/deliveries", get(list_webhook_deliveries))
+        .route("/webhooks/deliveries/:id/replay", post(replay_webhook_delivery))
+
+        .layer(CorsLayer::permissive())
+        .layer(shared_tracing::trace_id_layer())
+        .with_state(state);
+
+    // Start server
+    let port = std::env::var("PORT").unwrap_or_else(|_| "3003".to_string());
+    let addr = format!("0.0.0.0:{}", port);
+    let listener = TcpListener::bind(&addr).await?;
+
+    info!("Notification service listening on {}", addr);
+
+    axum::serve(listener, app)
+        .await
+        .map_err(|e| AppError::Internal(anyhow::anyhow!("Server error: {}", e)))?;
+
+    Ok(())
+}
+
+/// Handle events from the stream until the process exits
+/// Events are acknowledged only once handled, so failures are redelivered; both
+/// handlers skip deliveries they already created, so redelivery is harmless
+async fn consume_events(
+    consumer: EventConsumer,
+    notification_service: Arc<NotificationService>,
+    webhook_service: Arc<WebhookService>,
+) {
+    loop {
+        let events = match consumer.read(50, Duration::from_secs(5)).await {
+            Ok(events) => events,
+            Err(e) => {
+                error!("Failed to read events: {}", e);
+                tokio::time::sleep(Duration::from_secs(5)).await;
+                continue;
+            }
+        };
+
+        let mut handled = Vec::with_capacity(events.len());
+        for event in events {
+            let result = match notification_service.handle_event(&event.envelope).await {
+                Ok(()) => webhook_service.handle_event(&event.envelope).await,
+                Err(e) => Err(e),
+            };
+
+            match result {
+                Ok(()) => handled.push(event.stream_id),
+                Err(e) => error!("Failed to handle event {}: {}", event.envelope.id, e),
+            }
+        }
+
+        if !handled.is_empty() {
+            if let Err(e) = consumer.ack(&handled).await {
+                error!("Failed to acknowledge events: {}", e);
+            }
+        }
+    }
+}
+
+/// Health check endpoint
+#[instrument]
+async fn health_check(State(state): State<AppState>) -> Result<Json<serde_json::Value>> {
+    let db_health = shared_database::health_check(&state.db).await?;
+
+    Ok(Json(serde_json::json!({
+        "status": "healthy",
+        "service": "notification-service",
+        "database": db_health,
+        "timestamp": chrono::Utc::now()
+    })))
+}
+
+/// Get the user's in-app notifications
+#[instrument(skip(state))]
+async fn get_inbox(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    Query(params): Query<InboxParams>,
+) -> Result<Json<InboxResponse>> {
+    let inbox = state.notification_service.get_inbox(auth_user.user_id, params).await?;
+    Ok(Json(inbox))
+}
+
+/// Mark one notification as read
+#[instrument(skip(state))]
+async fn mark_read(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    Path(notification_id): Path<String>,
+) -> Result<Json<serde_json::Value>> {
+    let notification_id = notification_id.parse::<uuid::Uuid>()
+        .map_err(|_| AppError::Validation { message: "Invalid notification ID".to_string() })?;
+
+    state.notification_service.mark_read(auth_user.user_id, notification_id).await?;
+    Ok(Json(serde_json::json!({"status": "read"})))
+}
+
+/// Mark all notifications as read
+#[instrument(skip(state))]
+async fn mark_all_read(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+) -> Result<Json<serde_json::Value>> {
+    let updated = state.notification_service.mark_all_read(auth_user.user_id).await?;
+    Ok(Json(serde_json::json!({"status": "read", "updated": updated})))
+}
+
+/// Get the user's notification preferences
+#[instrument(skip(state))]
+async fn get_preferences(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+) -> Result<Json<NotificationPreferences>> {
+    let preferences = state.notification_service.get_preferences(auth_user.user_id).await?;
+    Ok(Json(preferences))
+}
+
+/// Change language, channels or muted events
+#[instrument(skip(state))]
+async fn update_preferences(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    Json(request): Json<UpdatePreferencesRequest>,
+) -> Result<Json<NotificationPreferences>> {
+    let preferences = state.notification_service
+        .update_preferences(auth_user.user_id, request)
+        .await?;
+    Ok(Json(preferences))
+}
+
+/// Public key browsers need to create a push subscription
+async fn get_vapid_public_key(State(state): State<AppState>) -> Json<serde_json::Value> {
+    Json(serde_json::json!({"public_key": state.vapid_public_key}))
+}
+
+/// Register a browser push subscription
+#[instrument(skip(state, request))]
+async fn subscribe_push(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    Json(request): Json<PushSubscriptionRequest>,
+) -> Result<Json<serde_json::Value>> {
+    state.notification_service.subscribe_push(auth_user.user_id, request).await?;
+    Ok(Json(serde_json::json!({"status": "subscribed"})))
+}
+
+/// Remove a browser push subscription (e.g., on logout)
+#[instrument(skip(state, request))]
+async fn unsubscribe_push(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    Json(request): Json<RemovePushSubscriptionRequest>,
+) -> Result<Json<serde_json::Value>> {
+    state.notification_service.unsubscribe_push(auth_user.user_id, request).await?;
+    Ok(Json(serde_json::json!({"status": "unsubscribed"})))
+}
+
+/// Register a webhook endpoint (the response contains the signing secret)
+#[instrument(skip(state, request))]
+async fn register_webhook_endpoint(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    Json(request): Json<CreateWebhookEndpointRequest>,
+) -> Result<Json<CreateWebhookEndpointResponse>> {
+    let response = state.webhook_service.register_endpoint(auth_user.user_id, request).await?;
+    Ok(Json(response))
+}
+
+/// List the account's webhook endpoints
+#[instrument(skip(state))]
+async fn list_webhook_endpoints(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+) -> Result<Json<Vec<WebhookEndpoint>>> {
+    let endpoints = state.webhook_service.list_endpoints(auth_user.user_id).await?;
+    Ok(Json(endpoints))
+}
+
+/// Delete a webhook endpoint
+#[instrument(skip(state))]
+async fn delete_webhook_endpoint(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    Path(endpoint_id): Path<String>,
+) -> Result<Json<serde_json::Value>> {
+    let endpoint_id = endpoint_id.parse::<uuid::Uuid>()
+        .map_err(|_| AppError::Validation { message: "Invalid endpoint ID".to_string() })?;
+
+    state.webhook_service.delete_endpoint(auth_user.user_id, endpoint_id).await?;
+    Ok(Json(serde_json::json!({"status": "deleted"})))
+}
+
+/// List webhook deliveries (`?status=dead` for the dead-letter list)
+#[instrument(skip(state))]
+async fn list_webhook_deliveries(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    Query(params): Query<WebhookDeliveryParams>,
+) -> Result<Json<Vec<WebhookDeliverySummary>>> {
+    let deliveries = state.webhook_service.list_deliveries(auth_user.user_id, params).await?;
+    Ok(Json(deliveries))
+}
+
+/// Send a webhook delivery again
+#[instrument(skip(state))]
+async fn replay_webhook_delivery(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    Path(delivery_id): Path<String>,
+) -> Result<Json<serde_json::Value>> {
+    let delivery_id = delivery_id.parse::<uuid::Uuid>()
+        .map_err(|_| AppError::Validation { message: "Invalid delivery ID".to_string() })?;
+
+    state.webhook_service.replay(auth_user.user_id, delivery_id).await?;
+    Ok(Json(serde_json::json!({"status": "scheduled"})))
+}
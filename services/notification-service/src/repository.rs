@@ -0,0 +1,391 @@
+/// Repository layer for notification data access
+///
+/// Preferences, the in-app inbox, push subscriptions and the delivery log.
+
+use crate::domain::*;
+use chrono::{DateTime, Utc};
+use shared_errors::Result;
+use shared_types::*;
+use sqlx::PgPool;
+use tracing::instrument;
+use uuid::Uuid;
+
+/// Repository for all notification tables
+pub struct NotificationRepository {
+    pool: PgPool,
+}
+
+impl NotificationRepository {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+
+    /// Contact details of a user
+    #[instrument(skip(self))]
+    pub async fn find_recipient(&self, user_id: UserId) -> Result<Option<Recipient>> {
+        let row = sqlx::query!(
+            "SELECT phone_number FROM users WHERE id = $1",
+            user_id.0,
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.map(|r| Recipient {
+            user_id,
+            phone_number: PhoneNumber(r.phone_number),
+        }))
+    }
+
+    /// User's preferences, or the defaults if they never changed them
+    #[instrument(skip(self))]
+    pub async fn get_preferences(&self, user_id: UserId) -> Result<NotificationPreferences> {
+        let row = sqlx::query!(
+            r#"
+            SELECT language as "language: Language", sms_enabled, push_enabled, muted_events
+            FROM notification_preferences
+            WHERE user_id = $1
+            "#,
+            user_id.0,
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row
+            .map(|r| NotificationPreferences {
+                language: r.language,
+                sms_enabled: r.sms_enabled,
+                push_enabled: r.push_enabled,
+                muted_events: r.muted_events,
+            })
+            .unwrap_or_default())
+    }
+
+    #[instrument(skip(self, preferences))]
+    pub async fn save_preferences(&self, user_id: UserId, preferences: &NotificationPreferences) -> Result<()> {
+        sqlx::query!(
+            r#"
+            INSERT INTO notification_preferences (user_id, language, sms_enabled, push_enabled, muted_events)
+            VALUES ($1, $2, $3, $4, $5)
+            ON CONFLICT (user_id) DO UPDATE SET
+                language = EXCLUDED.language,
+                sms_enabled = EXCLUDED.sms_enabled,
+                push_enabled = EXCLUDED.push_enabled,
+                muted_events = EXCLUDED.muted_events,
+                updated_at = NOW()
+            "#,
+            user_id.0,
+            preferences.language as _,
+            preferences.sms_enabled,
+            preferences.push_enabled,
+            &preferences.muted_events,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Create the delivery record for an event on one channel
+    /// Returns `None` if it already exists (the event was redelivered)
+    #[instrument(skip(self, message, data))]
+    #[allow(clippy::too_many_arguments)]
+    pub async fn create_delivery(
+        &self,
+        event_id: Uuid,
+        event_type: &str,
+        user_id: UserId,
+        channel: Channel,
+        language: Language,
+        message: &RenderedMessage,
+        data: &serde_json::Value,
+        lease_until: DateTime<Utc>,
+    ) -> Result<Option<Delivery>> {
+        let row = sqlx::query!(
+            r#"
+            INSERT INTO notification_deliveries
+                (event_id, event_type, user_id, channel, language, title, body, data, next_attempt_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+            ON CONFLICT (event_id, channel) DO NOTHING
+            RETURNING id, attempts, max_attempts
+            "#,
+            event_id,
+            event_type,
+            user_id.0,
+            channel as _,
+            language as _,
+            message.title,
+            message.body,
+            data,
+            lease_until,
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.map(|r| Delivery {
+            id: r.id,
+            event_id,
+            event_type: event_type.to_string(),
+            user_id,
+            channel,
+            message: message.clone(),
+            data: data.clone(),
+            attempts: r.attempts,
+            max_attempts: r.max_attempts,
+        }))
+    }
+
+    /// Lease pending deliveries whose retry time has come
+    /// Leased rows are hidden from other instances until `lease_until`
+    #[instrument(skip(self))]
+    pub async fn claim_due_deliveries(&self, limit: i64, lease_until: DateTime<Utc>) -> Result<Vec<Delivery>> {
+        let rows = sqlx::query!(
+            r#"
+            UPDATE notification_deliveries
+            SET next_attempt_at = $2
+            WHERE id IN (
+                SELECT id FROM notification_deliveries
+                WHERE status = 'pending' AND next_attempt_at <= NOW()
+                ORDER BY next_attempt_at
+                LIMIT $1
+                FOR UPDATE SKIP LOCKED
+            )
+            RETURNING id, event_id, event_type, user_id, channel as "channel: Channel",
+                      title, body, data, attempts, max_attempts
+            "#,
+            limit,
+            lease_until,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|r| Delivery {
+                id: r.id,
+                event_id: r.event_id,
+                event_type: r.event_type,
+                user_id: UserId(r.user_id),
+                channel: r.channel,
+                message: RenderedMessage { title: r.title, body: r.body },
+                data: r.data,
+                attempts: r.attempts,
+                max_attempts: r.max_attempts,
+            })
+            .collect())
+    }
+
+    #[instrument(skip(self))]
+    pub async fn mark_sent(&self, delivery_id: Uuid, provider_message_id: Option<String>) -> Result<()> {
+        sqlx::query!(
+            r#"
+            UPDATE notification_deliveries
+            SET status = 'sent', attempts = attempts + 1, sent_at = NOW(),
+                provider_message_id = $2, last_error = NULL
+            WHERE id = $1
+            "#,
+            delivery_id,
+            provider_message_id,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Record a failed attempt; `retry_at` of `None` gives up on the delivery
+    #[instrument(skip(self))]
+    pub async fn mark_failed(&self, delivery_id: Uuid, error: &str, retry_at: Option<DateTime<Utc>>) -> Result<()> {
+        sqlx::query!(
+            r#"
+            UPDATE notification_deliveries
+            SET attempts = attempts + 1,
+                last_error = $2,
+                status = CASE WHEN $3::timestamptz IS NULL THEN 'failed'::delivery_status ELSE 'pending'::delivery_status END,
+                next_attempt_at = COALESCE($3, next_attempt_at)
+            WHERE id = $1
+            "#,
+            delivery_id,
+            error,
+            retry_at,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Add a delivery to the user's inbox, returning the inbox entry ID
+    #[instrument(skip(self, delivery), fields(delivery_id = %delivery.id))]
+    pub async fn insert_inbox(&self, delivery: &Delivery) -> Result<Uuid> {
+        let row = sqlx::query!(
+            r#"
+            INSERT INTO notifications (user_id, event_id, event_type, title, body, data)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            ON CONFLICT (event_id) DO UPDATE SET event_id = EXCLUDED.event_id
+            RETURNING id
+            "#,
+            delivery.user_id.0,
+            delivery.event_id,
+            delivery.event_type,
+            delivery.message.title,
+            delivery.message.body,
+            delivery.data,
+        )
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(row.id)
+    }
+
+    /// Page of the user's inbox, newest first
+    #[instrument(skip(self))]
+    pub async fn list_inbox(
+        &self,
+        user_id: UserId,
+        unread_only: bool,
+        before: Option<DateTime<Utc>>,
+        limit: i64,
+    ) -> Result<Vec<InboxNotification>> {
+        let rows = sqlx::query!(
+            r#"
+            SELECT id, event_type, title, body, data, read_at, created_at
+            FROM notifications
+            WHERE user_id = $1
+              AND (NOT $2 OR read_at IS NULL)
+              AND ($3::timestamptz IS NULL OR created_at < $3)
+            ORDER BY created_at DESC
+            LIMIT $4
+            "#,
+            user_id.0,
+            unread_only,
+            before,
+            limit,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|r| InboxNotification {
+                id: r.id,
+                event_type: r.event_type,
+                title: r.title,
+                body: r.body,
+                data: r.data,
+                read_at: r.read_at,
+                created_at: r.created_at,
+            })
+            .collect())
+    }
+
+    #[instrument(skip(self))]
+    pub async fn unread_count(&self, user_id: UserId) -> Result<i64> {
+        let row = sqlx::query!(
+            r#"SELECT COUNT(*) as "count!" FROM notifications WHERE user_id = $1 AND read_at IS NULL"#,
+            user_id.0,
+        )
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(row.count)
+    }
+
+    /// Mark one inbox entry read, returning false if it isn't the user's
+    #[instrument(skip(self))]
+    pub async fn mark_read(&self, user_id: UserId, notification_id: Uuid) -> Result<bool> {
+        let result = sqlx::query!(
+            "UPDATE notifications SET read_at = COALESCE(read_at, NOW()) WHERE id = $1 AND user_id = $2",
+            notification_id,
+            user_id.0,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    #[instrument(skip(self))]
+    pub async fn mark_all_read(&self, user_id: UserId) -> Result<u64> {
+        let result = sqlx::query!(
+            "UPDATE notifications SET read_at = NOW() WHERE user_id = $1 AND read_at IS NULL",
+            user_id.0,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    /// Store a browser subscription (re-subscribing moves it to the current user)
+    #[instrument(skip(self, request))]
+    pub async fn save_push_subscription(&self, user_id: UserId, request: &PushSubscriptionRequest) -> Result<()> {
+        sqlx::query!(
+            r#"
+            INSERT INTO push_subscriptions (user_id, endpoint, p256dh, auth)
+            VALUES ($1, $2, $3, $4)
+            ON CONFLICT (endpoint) DO UPDATE SET
+                user_id = EXCLUDED.user_id,
+                p256dh = EXCLUDED.p256dh,
+                auth = EXCLUDED.auth
+            "#,
+            user_id.0,
+            request.endpoint,
+            request.keys.p256dh,
+            request.keys.auth,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    #[instrument(skip(self))]
+    pub async fn remove_push_subscription(&self, user_id: UserId, endpoint: &str) -> Result<bool> {
+        let result = sqlx::query!(
+            "DELETE FROM push_subscriptions WHERE user_id = $1 AND endpoint = $2",
+            user_id.0,
+            endpoint,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    #[instrument(skip(self))]
+    pub async fn push_subscriptions(&self, user_id: UserId) -> Result<Vec<PushSubscription>> {
+        let rows = sqlx::query!(
+            "SELECT id, endpoint FROM push_subscriptions WHERE user_id = $1",
+            user_id.0,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|r| PushSubscription {
+                id: r.id,
+                endpoint: r.endpoint,
+            })
+            .collect())
+    }
+
+    pub async fn touch_push_subscription(&self, subscription_id: Uuid) -> Result<()> {
+        sqlx::query!(
+            "UPDATE push_subscriptions SET last_used_at = NOW() WHERE id = $1",
+            subscription_id,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn delete_push_subscription(&self, subscription_id: Uuid) -> Result<()> {
+        sqlx::query!("DELETE FROM push_subscriptions WHERE id = $1", subscription_id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+}
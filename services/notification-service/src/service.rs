@@ -0,0 +1,222 @@
+/// Business logic for notifications
+///
+/// Turns domain events into deliveries on each channel the user wants, sends
+/// them, and retries failed sends with exponential backoff.
+
+use crate::channels::NotificationChannel;
+use crate::domain::*;
+use crate::repository::NotificationRepository;
+use crate::templates;
+use chrono::{Duration, Utc};
+use shared_errors::{AppError, Result};
+use shared_events::EventEnvelope;
+use shared_types::*;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tracing::{info, instrument, warn};
+use uuid::Uuid;
+use validator::Validate;
+
+/// How long a delivery is hidden from the retry loop while a send is in flight
+const SEND_LEASE_SECONDS: i64 = 120;
+
+/// Default and maximum inbox page size
+const DEFAULT_INBOX_LIMIT: i64 = 20;
+const MAX_INBOX_LIMIT: i64 = 100;
+
+/// Notification orchestration
+pub struct NotificationService {
+    repository: Arc<NotificationRepository>,
+    channels: HashMap<Channel, Arc<dyn NotificationChannel>>,
+}
+
+impl NotificationService {
+    pub fn new(repository: Arc<NotificationRepository>, channels: Vec<Arc<dyn NotificationChannel>>) -> Self {
+        Self {
+            repository,
+            channels: channels.into_iter().map(|channel| (channel.channel(), channel)).collect(),
+        }
+    }
+
+    /// Create and send the notifications for one domain event
+    /// Safe to call again for a redelivered event: existing deliveries are skipped
+    #[instrument(skip(self, envelope), fields(event_id = %envelope.id, event_type = envelope.event.event_type()))]
+    pub async fn handle_event(&self, envelope: &EventEnvelope) -> Result<()> {
+        let event_type = envelope.event.event_type();
+        let Some(template) = templates::find(event_type) else {
+            return Ok(());
+        };
+
+        let user_id = envelope.event.user_id();
+        let Some(recipient) = self.repository.find_recipient(user_id).await? else {
+            warn!("Skipping {} for unknown user {}", event_type, user_id.0);
+            return Ok(());
+        };
+
+        let preferences = self.repository.get_preferences(user_id).await?;
+        let message = template.render(preferences.language, &envelope.event);
+        let data = envelope.event.data();
+
+        for channel in preferences.channels_for(event_type, template.sms) {
+            if !self.channels.contains_key(&channel) {
+                continue;
+            }
+
+            let delivery = self
+                .repository
+                .create_delivery(
+                    envelope.id,
+                    event_type,
+                    user_id,
+                    channel,
+                    preferences.language,
+                    &message,
+                    &data,
+                    Utc::now() + Duration::seconds(SEND_LEASE_SECONDS),
+                )
+                .await?;
+
+            // Already created by an earlier delivery of this event; the retry loop owns it
+            if let Some(delivery) = delivery {
+                self.deliver(&recipient, &delivery).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Send deliveries whose retry time has come, returning how many were attempted
+    #[instrument(skip(self))]
+    pub async fn retry_due(&self, limit: i64) -> Result<usize> {
+        let deliveries = self
+            .repository
+            .claim_due_deliveries(limit, Utc::now() + Duration::seconds(SEND_LEASE_SECONDS))
+            .await?;
+
+        for delivery in &deliveries {
+            match self.repository.find_recipient(delivery.user_id).await? {
+                Some(recipient) => self.deliver(&recipient, delivery).await?,
+                None => {
+                    self.repository
+                        .mark_failed(delivery.id, "User no longer exists", None)
+                        .await?
+                }
+            }
+        }
+
+        Ok(deliveries.len())
+    }
+
+    /// Attempt one delivery and record the outcome
+    async fn deliver(&self, recipient: &Recipient, delivery: &Delivery) -> Result<()> {
+        let Some(channel) = self.channels.get(&delivery.channel) else {
+            return self
+                .repository
+                .mark_failed(delivery.id, "Channel not configured", None)
+                .await;
+        };
+
+        match channel.send(recipient, delivery).await {
+            Ok(receipt) => {
+                self.repository
+                    .mark_sent(delivery.id, receipt.provider_message_id)
+                    .await
+            }
+            Err(e) => {
+                let retry_at = delivery
+                    .can_retry()
+                    .then(|| Utc::now() + Delivery::retry_backoff(delivery.attempts + 1));
+
+                match retry_at {
+                    Some(retry_at) => warn!(
+                        "{:?} delivery {} failed, retrying at {}: {}",
+                        delivery.channel, delivery.id, retry_at, e
+                    ),
+                    None => warn!("{:?} delivery {} failed permanently: {}", delivery.channel, delivery.id, e),
+                }
+
+                self.repository.mark_failed(delivery.id, &e.to_string(), retry_at).await
+            }
+        }
+    }
+
+    /// Page of the user's inbox with their unread count
+    #[instrument(skip(self))]
+    pub async fn get_inbox(&self, user_id: UserId, params: InboxParams) -> Result<InboxResponse> {
+        let limit = params.limit.unwrap_or(DEFAULT_INBOX_LIMIT).clamp(1, MAX_INBOX_LIMIT);
+        let notifications = self
+            .repository
+            .list_inbox(user_id, params.unread_only.unwrap_or(false), params.before, limit)
+            .await?;
+        let unread_count = self.repository.unread_count(user_id).await?;
+
+        Ok(InboxResponse {
+            notifications,
+            unread_count,
+        })
+    }
+
+    #[instrument(skip(self))]
+    pub async fn mark_read(&self, user_id: UserId, notification_id: Uuid) -> Result<()> {
+        if !self.repository.mark_read(user_id, notification_id).await? {
+            return Err(AppError::User {
+                message: "Notification not found".to_string(),
+            });
+        }
+        Ok(())
+    }
+
+    #[instrument(skip(self))]
+    pub async fn mark_all_read(&self, user_id: UserId) -> Result<u64> {
+        self.repository.mark_all_read(user_id).await
+    }
+
+    #[instrument(skip(self))]
+    pub async fn get_preferences(&self, user_id: UserId) -> Result<NotificationPreferences> {
+        self.repository.get_preferences(user_id).await
+    }
+
+    #[instrument(skip(self))]
+    pub async fn update_preferences(
+        &self,
+        user_id: UserId,
+        request: UpdatePreferencesRequest,
+    ) -> Result<NotificationPreferences> {
+        request.validate().map_err(|e| AppError::Validation {
+            message: format!("Invalid preferences: {}", e),
+        })?;
+
+        if let Some(unknown) = request
+            .muted_events
+            .iter()
+            .flatten()
+            .find(|event_type| templates::find(event_type).is_none())
+        {
+            return Err(AppError::Validation {
+                message: format!("Unknown event type: {}", unknown),
+            });
+        }
+
+        let mut preferences = self.repository.get_preferences(user_id).await?;
+        preferences.apply(request);
+        self.repository.save_preferences(user_id, &preferences).await?;
+
+        info!("Notification preferences updated for user {}", user_id.0);
+        Ok(preferences)
+    }
+
+    #[instrument(skip(self, request))]
+    pub async fn subscribe_push(&self, user_id: UserId, request: PushSubscriptionRequest) -> Result<()> {
+        request.validate().map_err(|e| AppError::Validation {
+            message: format!("Invalid push subscription: {}", e),
+        })?;
+
+        self.repository.save_push_subscription(user_id, &request).await
+    }
+
+    #[instrument(skip(self, request))]
+    pub async fn unsubscribe_push(&self, user_id: UserId, request: RemovePushSubscriptionRequest) -> Result<()> {
+        self.repository.remove_push_subscription(user_id, &request.endpoint).await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,403 @@
+/// Notification templates per event and language
+///
+/// Templates use `{field}` placeholders filled from the event's data (the `data`
+/// object of the serialized `DomainEvent`). Events without a template don't
+/// produce notifications.
+
+use crate::domain::{Language, RenderedMessage};
+use shared_events::DomainEvent;
+use std::collections::HashMap;
+
+/// Shown for placeholders whose value is missing
+const MISSING_VALUE: &str = "-";
+
+/// Title and body in one language
+struct Text {
+    title: &'static str,
+    body: &'static str,
+}
+
+/// Template for one event type
+pub struct Template {
+    pub event_type: &'static str,
+    /// Whether the event is important enough to send by SMS
+    pub sms: bool,
+    en: Text,
+    sw: Text,
+}
+
+static TEMPLATES: &[Template] = &[
+    Template {
+        event_type: "DepositCompleted",
+        sms: true,
+        en: Text {
+            title: "Deposit received",
+            body: "Your deposit of KES {amount_kes} was received and {amount_sats} sats were added to your wallet. M-Pesa ref {mpesa_code}.",
+        },
+        sw: Text {
+            title: "Amana imepokelewa",
+            body: "Amana yako ya KES {amount_kes} imepokelewa na sats {amount_sats} zimeongezwa kwenye pochi yako. Kumbukumbu ya M-Pesa {mpesa_code}.",
+        },
+    },
+    Template {
+        event_type: "DepositFailed",
+        sms: true,
+        en: Text {
+            title: "Deposit not completed",
+            body: "Your M-Pesa deposit of KES {amount_kes} was not completed. Please try again.",
+        },
+        sw: Text {
+            title: "Amana haikukamilika",
+            body: "Amana yako ya M-Pesa ya KES {amount_kes} haikukamilika. Tafadhali jaribu tena.",
+        },
+    },
+    Template {
+        event_type: "WithdrawalCompleted",
+        sms: true,
+        en: Text {
+            title: "Withdrawal sent",
+            body: "KES {amount_kes} has been sent to your M-Pesa. Ref {mpesa_code}.",
+        },
+        sw: Text {
+            title: "Pesa zimetumwa",
+            body: "KES {amount_kes} zimetumwa kwa M-Pesa yako. Kumbukumbu {mpesa_code}.",
+        },
+    },
+    Template {
+        event_type: "WithdrawalFailed",
+        sms: true,
+        en: Text {
+            title: "Withdrawal failed",
+            body: "Your withdrawal of {amount_sats} sats could not be sent to M-Pesa.",
+        },
+        sw: Text {
+            title: "Utoaji umeshindikana",
+            body: "Utoaji wako wa sats {amount_sats} haukuweza kutumwa kwa M-Pesa.",
+        },
+    },
+    Template {
+        event_type: "InvoicePaid",
+        sms: true,
+        en: Text {
+            title: "Payment received",
+            body: "You received {amount_sats} sats.",
+        },
+        sw: Text {
+            title: "Malipo yamepokelewa",
+            body: "Umepokea sats {amount_sats}.",
+        },
+    },
+    Template {
+        event_type: "PaymentSent",
+        sms: false,
+        en: Text {
+            title: "Payment sent",
+            body: "You sent {amount_sats} sats.",
+        },
+        sw: Text {
+            title: "Malipo yametumwa",
+            body: "Umetuma sats {amount_sats}.",
+        },
+    },
+    Template {
+        event_type: "PaymentFailed",
+        sms: false,
+        en: Text {
+            title: "Payment failed",
+            body: "Your payment of {amount_sats} sats could not be completed.",
+        },
+        sw: Text {
+            title: "Malipo yameshindikana",
+            body: "Malipo yako ya sats {amount_sats} hayakukamilika.",
+        },
+    },
+    Template {
+        event_type: "RefundCredited",
+        sms: true,
+        en: Text {
+            title: "Refund received",
+            body: "{amount_sats} sats were refunded to your wallet.",
+        },
+        sw: Text {
+            title: "Umerejeshewa pesa",
+            body: "Sats {amount_sats} zimerejeshwa kwenye pochi yako.",
+        },
+    },
+    Template {
+        event_type: "DepositRefunded",
+        sms: true,
+        en: Text {
+            title: "Deposit refunded",
+            body: "KES {amount_kes} from your deposit was returned to your M-Pesa. Ref {mpesa_code}.",
+        },
+        sw: Text {
+            title: "Amana imerejeshwa",
+            body: "KES {amount_kes} za amana yako zimerejeshwa kwa M-Pesa yako. Kumbukumbu {mpesa_code}.",
+        },
+    },
+    Template {
+        event_type: "ScheduledPaymentFailed",
+        sms: true,
+        en: Text {
+            title: "Scheduled payment failed",
+            body: "Your scheduled payment to {target} failed after {attempts} attempts: {reason}",
+        },
+        sw: Text {
+            title: "Malipo yaliyoratibiwa yameshindwa",
+            body: "Malipo yako yaliyoratibiwa kwa {target} yameshindwa baada ya majaribio {attempts}: {reason}",
+        },
+    },
+    Template {
+        event_type: "PaymentRequestReceived",
+        sms: true,
+        en: Text {
+            title: "Payment request",
+            body: "{requester} is asking you for {amount}. Open PesaBit to pay or decline.",
+        },
+        sw: Text {
+            title: "Ombi la malipo",
+            body: "{requester} anakuomba {amount}. Fungua PesaBit kulipa au kukataa.",
+        },
+    },
+    Template {
+        event_type: "PaymentRequestPaid",
+        sms: true,
+        en: Text {
+            title: "Payment request paid",
+            body: "{payer} paid your request. {amount_sats} sats were added to your wallet.",
+        },
+        sw: Text {
+            title: "Ombi la malipo limelipwa",
+            body: "{payer} amelipa ombi lako. Sats {amount_sats} zimeongezwa kwenye pochi yako.",
+        },
+    },
+    Template {
+        event_type: "PaymentRequestApproved",
+        sms: false,
+        en: Text {
+            title: "Payment request paid",
+            body: "You paid {amount_sats} sats to {requester}.",
+        },
+        sw: Text {
+            title: "Ombi la malipo limelipwa",
+            body: "Umemlipa {requester} sats {amount_sats}.",
+        },
+    },
+    Template {
+        event_type: "PaymentRequestDeclined",
+        sms: false,
+        en: Text {
+            title: "Payment request declined",
+            body: "{payer} declined your request for {amount}.",
+        },
+        sw: Text {
+            title: "Ombi la malipo limekataliwa",
+            body: "{payer} amekataa ombi lako la {amount}.",
+        },
+    },
+    Template {
+        event_type: "PaymentRequestCancelled",
+        sms: false,
+        en: Text {
+            title: "Payment request withdrawn",
+            body: "{requester} withdrew their request for {amount}.",
+        },
+        sw: Text {
+            title: "Ombi la malipo limeondolewa",
+            body: "{requester} ameondoa ombi lake la {amount}.",
+        },
+    },
+    Template {
+        event_type: "DcaPlanPaused",
+        sms: true,
+        en: Text {
+            title: "Recurring buy paused",
+            body: "Your recurring buy of KES {amount_kes} was paused after {failures} failed attempts: {reason}. Resume it in the app.",
+        },
+        sw: Text {
+            title: "Ununuzi wa mara kwa mara umesitishwa",
+            body: "Ununuzi wako wa mara kwa mara wa KES {amount_kes} umesitishwa baada ya majaribio {failures} kushindwa: {reason}. Uendeleze kwenye programu.",
+        },
+    },
+    Template {
+        event_type: "VaultWithdrawalScheduled",
+        sms: true,
+        en: Text {
+            title: "Early vault withdrawal requested",
+            body: "{amount_sats} sats will leave your {vault_name} vault at {available_at}. If this wasn't you, cancel it in the app and change your PIN.",
+        },
+        sw: Text {
+            title: "Ombi la kutoa mapema kwenye hazina",
+            body: "Sats {amount_sats} zitatoka kwenye hazina yako ya {vault_name} saa {available_at}. Kama si wewe, ighairi kwenye programu na ubadilishe PIN yako.",
+        },
+    },
+    Template {
+        event_type: "AirtimePurchased",
+        sms: false,
+        en: Text {
+            title: "Airtime sent",
+            body: "KES {amount_kes} airtime was sent to {phone_number} for {amount_sats} sats.",
+        },
+        sw: Text {
+            title: "Muda wa maongezi umetumwa",
+            body: "Muda wa maongezi wa KES {amount_kes} umetumwa kwa {phone_number} kwa sats {amount_sats}.",
+        },
+    },
+    Template {
+        event_type: "AirtimeFailed",
+        sms: true,
+        en: Text {
+            title: "Airtime not sent",
+            body: "Airtime for {phone_number} could not be sent. Your {amount_sats} sats will be refunded.",
+        },
+        sw: Text {
+            title: "Muda wa maongezi haukutumwa",
+            body: "Muda wa maongezi kwa {phone_number} haukuweza kutumwa. Sats {amount_sats} zako zitarejeshwa.",
+        },
+    },
+    Template {
+        event_type: "UserRegistered",
+        sms: false,
+        en: Text {
+            title: "Welcome to PesaBit",
+            body: "Karibu {lightning_username}! Your Lightning address is {lightning_username}@pesa.co.ke.",
+        },
+        sw: Text {
+            title: "Karibu PesaBit",
+            body: "Karibu {lightning_username}! Anwani yako ya Lightning ni {lightning_username}@pesa.co.ke.",
+        },
+    },
+];
+
+/// Template for an event type, if it notifies users
+pub fn find(event_type: &str) -> Option<&'static Template> {
+    TEMPLATES.iter().find(|template| template.event_type == event_type)
+}
+
+impl Template {
+    /// Render the template for an event in the given language
+    pub fn render(&self, language: Language, event: &DomainEvent) -> RenderedMessage {
+        let text = match language {
+            Language::En => &self.en,
+            Language::Sw => &self.sw,
+        };
+        let values = event_values(event);
+
+        RenderedMessage {
+            title: fill(text.title, &values),
+            body: fill(text.body, &values),
+        }
+    }
+}
+
+/// Flatten the event's data into placeholder values
+pub fn event_values(event: &DomainEvent) -> HashMap<String, String> {
+    let serde_json::Value::Object(data) = event.data() else {
+        return HashMap::new();
+    };
+
+    data.into_iter()
+        .filter_map(|(key, value)| {
+            let value = match value {
+                serde_json::Value::String(s) => s,
+                serde_json::Value::Number(n) => n.to_string(),
+                serde_json::Value::Bool(b) => b.to_string(),
+                _ => return None,
+            };
+            Some((key, value))
+        })
+        .collect()
+}
+
+/// Replace `{name}` placeholders
+fn fill(template: &str, values: &HashMap<String, String>) -> String {
+    let mut output = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find('{') {
+        output.push_str(&rest[..start]);
+        match rest[start..].find('}') {
+            Some(end) => {
+                let name = &rest[start + 1..start + end];
+                output.push_str(values.get(name).map(String::as_str).unwrap_or(MISSING_VALUE));
+                rest = &rest[start + end + 1..];
+            }
+            None => {
+                output.push_str(&rest[start..]);
+                rest = "";
+            }
+        }
+    }
+
+    output.push_str(rest);
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal::Decimal;
+    use shared_types::{KesAmount, MpesaCode, SatAmount, UserId};
+    use uuid::Uuid;
+
+    fn deposit(mpesa_code: Option<&str>) -> DomainEvent {
+        DomainEvent::DepositCompleted {
+            transaction_id: Uuid::new_v4(),
+            user_id: UserId::new(),
+            amount_kes: Some(KesAmount::new(Decimal::from(1000))),
+            amount_sats: Some(SatAmount::new(18_679)),
+            mpesa_code: mpesa_code.map(|code| MpesaCode(code.to_string())),
+        }
+    }
+
+    #[test]
+    fn test_render_per_language() {
+        let template = find("DepositCompleted").unwrap();
+        let event = deposit(Some("QL12XYZ789"));
+
+        let en = template.render(Language::En, &event);
+        assert_eq!(en.title, "Deposit received");
+        assert_eq!(
+            en.body,
+            "Your deposit of KES 1000.00 was received and 18679 sats were added to your wallet. M-Pesa ref QL12XYZ789."
+        );
+
+        let sw = template.render(Language::Sw, &event);
+        assert!(sw.body.starts_with("Amana yako ya KES 1000.00 imepokelewa"));
+    }
+
+    #[test]
+    fn test_missing_values_are_marked() {
+        let message = find("DepositCompleted").unwrap().render(Language::En, &deposit(None));
+        assert!(message.body.ends_with("M-Pesa ref -."));
+    }
+
+    #[test]
+    fn test_every_event_type_has_a_template() {
+        for event_type in [
+            "DepositCompleted",
+            "DepositFailed",
+            "WithdrawalCompleted",
+            "WithdrawalFailed",
+            "InvoicePaid",
+            "PaymentSent",
+            "PaymentFailed",
+            "RefundCredited",
+            "DepositRefunded",
+            "ScheduledPaymentFailed",
+            "PaymentRequestReceived",
+            "PaymentRequestPaid",
+            "PaymentRequestApproved",
+            "PaymentRequestDeclined",
+            "PaymentRequestCancelled",
+            "DcaPlanPaused",
+            "VaultWithdrawalScheduled",
+            "AirtimePurchased",
+            "AirtimeFailed",
+            "UserRegistered",
+        ] {
+            assert!(find(event_type).is_some(), "missing template for {}", event_type);
+        }
+        assert!(find("Unknown").is_none());
+    }
+}
@@ -0,0 +1,913 @@
+/// Signed outbound webhooks for business customers
+///
+/// Accounts register endpoints and pick the event types they want. Every matching
+/// domain event is POSTed to the endpoint as JSON with these headers:
+///
+/// - `X-PesaBit-Event`: event type (e.g., "InvoicePaid")
+/// - `X-PesaBit-Delivery`: delivery ID, stable across retries and replays
+/// - `X-PesaBit-Timestamp`: Unix time the request was signed
+/// - `X-PesaBit-Signature`: `v1=` followed by the hex HMAC-SHA256 of
+///   `"{timestamp}.{body}"` keyed with the endpoint secret
+///
+/// Receivers should recompute the signature and reject timestamps more than five
+/// minutes old so captured requests can't be replayed. Deliveries that keep
+/// failing are retried with exponential backoff and then moved to the dead-letter
+/// list, from where the customer can replay them.
+///
+/// Endpoint URLs are customer-controlled and requested from inside the cluster,
+/// so only public addresses are allowed: hosts are checked at registration and
+/// again at send time, and the HTTP client resolves names through a resolver
+/// that refuses internal addresses (so a DNS change after registration can't
+/// point an endpoint at an internal service).
+
+use chrono::{DateTime, Duration, Utc};
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use shared_errors::{AppError, Result};
+use shared_events::EventEnvelope;
+use shared_types::*;
+use sqlx::PgPool;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use tracing::{info, instrument, warn};
+use uuid::Uuid;
+use validator::Validate;
+
+/// Event types that can be delivered by webhook
+pub const WEBHOOK_EVENT_TYPES: &[&str] = &[
+    "DepositCompleted",
+    "DepositFailed",
+    "WithdrawalCompleted",
+    "WithdrawalFailed",
+    "InvoicePaid",
+    "PaymentSent",
+    "PaymentFailed",
+    "RefundCredited",
+    "DepositRefunded",
+    "ScheduledPaymentFailed",
+    "PaymentRequestReceived",
+    "PaymentRequestPaid",
+    "PaymentRequestApproved",
+    "PaymentRequestDeclined",
+    "PaymentRequestCancelled",
+    "DcaPlanPaused",
+    "VaultWithdrawalScheduled",
+    "AirtimePurchased",
+    "AirtimeFailed",
+];
+
+/// How long a delivery is hidden from the retry loop while a request is in flight
+const SEND_LEASE_SECONDS: i64 = 60;
+
+/// Endpoints must answer within this time
+const REQUEST_TIMEOUT_SECONDS: u64 = 10;
+
+/// Default and maximum delivery list page size
+const DEFAULT_DELIVERY_LIMIT: i64 = 50;
+const MAX_DELIVERY_LIMIT: i64 = 200;
+
+/// Registered webhook endpoint (the secret is only returned at registration)
+#[derive(Debug, Clone, Serialize)]
+pub struct WebhookEndpoint {
+    pub id: Uuid,
+    pub url: String,
+    pub description: Option<String>,
+    pub event_types: Vec<String>,
+    pub is_active: bool,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Request to register a webhook endpoint
+#[derive(Debug, Deserialize, Validate)]
+pub struct CreateWebhookEndpointRequest {
+    #[validate(url, length(max = 2000))]
+    pub url: String,
+    #[validate(length(max = 200))]
+    pub description: Option<String>,
+    #[validate(length(min = 1))]
+    pub event_types: Vec<String>,
+}
+
+/// Registration response, the only time the signing secret is shown
+#[derive(Debug, Serialize)]
+pub struct CreateWebhookEndpointResponse {
+    #[serde(flatten)]
+    pub endpoint: WebhookEndpoint,
+    pub secret: String,
+}
+
+/// Delivery state of a webhook
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[sqlx(type_name = "webhook_delivery_status", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum WebhookDeliveryStatus {
+    Pending,
+    Delivered,
+    /// In the dead-letter list
+    Dead,
+}
+
+/// Delivery as shown to the account owner
+#[derive(Debug, Clone, Serialize)]
+pub struct WebhookDeliverySummary {
+    pub id: Uuid,
+    pub endpoint_id: Uuid,
+    pub event_id: Uuid,
+    pub event_type: String,
+    pub status: WebhookDeliveryStatus,
+    pub attempts: i32,
+    pub last_response_status: Option<i16>,
+    pub last_error: Option<String>,
+    pub next_attempt_at: DateTime<Utc>,
+    pub created_at: DateTime<Utc>,
+    pub delivered_at: Option<DateTime<Utc>>,
+    pub dead_at: Option<DateTime<Utc>>,
+}
+
+/// Query parameters for listing deliveries (`status=dead` lists the dead-letter list)
+#[derive(Debug, Deserialize)]
+pub struct WebhookDeliveryParams {
+    pub status: Option<WebhookDeliveryStatus>,
+    pub endpoint_id: Option<Uuid>,
+    pub limit: Option<i64>,
+}
+
+/// Delivery ready to be sent
+#[derive(Debug, Clone)]
+pub struct WebhookDelivery {
+    pub id: Uuid,
+    pub event_type: String,
+    pub url: String,
+    pub secret: String,
+    pub payload: serde_json::Value,
+    pub attempts: i32,
+    pub max_attempts: i32,
+}
+
+impl WebhookDelivery {
+    /// Wait before the next attempt after `attempts` failures (1m, 2m, 4m, ... capped at 6h)
+    pub fn retry_backoff(attempts: i32) -> Duration {
+        let exponent = attempts.clamp(1, 16) as u32 - 1;
+        Duration::minutes(2_i64.pow(exponent)).min(Duration::hours(6))
+    }
+
+    /// Whether another attempt is allowed after the current one failed
+    pub fn can_retry(&self) -> bool {
+        self.attempts + 1 < self.max_attempts
+    }
+}
+
+/// JSON body sent for an event
+pub fn webhook_payload(envelope: &EventEnvelope) -> serde_json::Value {
+    serde_json::json!({
+        "id": envelope.id,
+        "type": envelope.event.event_type(),
+        "occurred_at": envelope.occurred_at,
+        "data": envelope.event.data(),
+    })
+}
+
+/// Value of the `X-PesaBit-Signature` header
+pub fn sign_payload(secret: &str, timestamp: i64, body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(body);
+    format!("v1={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+/// New random signing secret
+fn generate_secret() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    format!("whsec_{}", hex::encode(bytes))
+}
+
+/// Whether an address belongs to a private, loopback, link-local or otherwise
+/// non-public range (cloud metadata at 169.254.169.254 is link-local)
+fn is_internal_ip(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(v4) => {
+            let [a, b, ..] = v4.octets();
+            v4.is_private()
+                || v4.is_loopback()
+                || v4.is_link_local()
+                || v4.is_unspecified()
+                || v4.is_broadcast()
+                || v4.is_multicast()
+                || v4.is_documentation()
+                || a == 0
+                // Carrier-grade NAT (100.64.0.0/10)
+                || (a == 100 && (64..128).contains(&b))
+        }
+        IpAddr::V6(v6) => {
+            let first = v6.segments()[0];
+            v6.is_loopback()
+                || v6.is_unspecified()
+                || v6.is_multicast()
+                // Unique local (fc00::/7) and link-local (fe80::/10)
+                || (first & 0xfe00) == 0xfc00
+                || (first & 0xffc0) == 0xfe80
+                || v6.to_ipv4_mapped().map_or(false, |v4| is_internal_ip(IpAddr::V4(v4)))
+        }
+    }
+}
+
+/// Whether a host name only resolves inside our network (cluster services,
+/// Docker Compose names, mDNS, cloud-internal zones)
+fn is_internal_host_name(host: &str) -> bool {
+    let host = host.trim_end_matches('.').to_ascii_lowercase();
+    !host.contains('.')
+        || [".localhost", ".local", ".internal", ".svc", ".lan", ".home.arpa"]
+            .iter()
+            .any(|suffix| host.ends_with(suffix))
+}
+
+fn internal_url_error() -> AppError {
+    AppError::Validation {
+        message: "Webhook URL must point to a public host".to_string(),
+    }
+}
+
+/// Check the scheme and host of an endpoint URL without resolving it
+fn check_webhook_url(url: &str) -> Result<reqwest::Url> {
+    let parsed = reqwest::Url::parse(url).map_err(|_| AppError::Validation {
+        message: "Invalid webhook URL".to_string(),
+    })?;
+
+    if !matches!(parsed.scheme(), "http" | "https") {
+        return Err(AppError::Validation {
+            message: "Webhook URL must use http or https".to_string(),
+        });
+    }
+
+    let internal = match host_ip(&parsed) {
+        Some(ip) => is_internal_ip(ip),
+        None => match parsed.host_str() {
+            Some(name) => is_internal_host_name(name),
+            None => {
+                return Err(AppError::Validation {
+                    message: "Webhook URL must have a host".to_string(),
+                })
+            }
+        },
+    };
+
+    if internal {
+        return Err(internal_url_error());
+    }
+    Ok(parsed)
+}
+
+/// Host of a URL if it is an IP literal (IPv6 hosts are bracketed)
+fn host_ip(url: &reqwest::Url) -> Option<IpAddr> {
+    url.host_str()?
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .parse()
+        .ok()
+}
+
+/// Resolve a host name, failing if it has no addresses or any internal one
+async fn resolve_public(host: &str) -> std::result::Result<Vec<SocketAddr>, String> {
+    let addrs: Vec<SocketAddr> = tokio::net::lookup_host((host, 0))
+        .await
+        .map_err(|e| format!("Could not resolve {}: {}", host, e))?
+        .collect();
+
+    if addrs.is_empty() {
+        return Err(format!("{} has no addresses", host));
+    }
+    if addrs.iter().any(|addr| is_internal_ip(addr.ip())) {
+        return Err(format!("{} resolves to an internal address", host));
+    }
+
+    Ok(addrs)
+}
+
+/// DNS resolver for webhook requests that only returns public addresses
+struct PublicOnlyResolver;
+
+impl reqwest::dns::Resolve for PublicOnlyResolver {
+    fn resolve(&self, name: reqwest::dns::Name) -> reqwest::dns::Resolving {
+        Box::pin(async move {
+            let addrs = resolve_public(name.as_str()).await?;
+            let addrs: reqwest::dns::Addrs = Box::new(addrs.into_iter());
+            Ok::<_, Box<dyn std::error::Error + Send + Sync>>(addrs)
+        })
+    }
+}
+
+/// Repository for webhook endpoints and deliveries
+pub struct WebhookRepository {
+    pool: PgPool,
+}
+
+impl WebhookRepository {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+
+    #[instrument(skip(self, request, secret))]
+    pub async fn create_endpoint(
+        &self,
+        user_id: UserId,
+        request: &CreateWebhookEndpointRequest,
+        secret: &str,
+    ) -> Result<WebhookEndpoint> {
+        let row = sqlx::query!(
+            r#"
+            INSERT INTO webhook_endpoints (user_id, url, description, event_types, secret)
+            VALUES ($1, $2, $3, $4, $5)
+            RETURNING id, url, description, event_types, is_active, created_at
+            "#,
+            user_id.0,
+            request.url,
+            request.description,
+            &request.event_types,
+            secret,
+        )
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(WebhookEndpoint {
+            id: row.id,
+            url: row.url,
+            description: row.description,
+            event_types: row.event_types,
+            is_active: row.is_active,
+            created_at: row.created_at,
+        })
+    }
+
+    #[instrument(skip(self))]
+    pub async fn list_endpoints(&self, user_id: UserId) -> Result<Vec<WebhookEndpoint>> {
+        let rows = sqlx::query!(
+            r#"
+            SELECT id, url, description, event_types, is_active, created_at
+            FROM webhook_endpoints
+            WHERE user_id = $1
+            ORDER BY created_at DESC
+            "#,
+            user_id.0,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|r| WebhookEndpoint {
+                id: r.id,
+                url: r.url,
+                description: r.description,
+                event_types: r.event_types,
+                is_active: r.is_active,
+                created_at: r.created_at,
+            })
+            .collect())
+    }
+
+    /// Delete an endpoint and its deliveries, returning false if it isn't the user's
+    #[instrument(skip(self))]
+    pub async fn delete_endpoint(&self, user_id: UserId, endpoint_id: Uuid) -> Result<bool> {
+        let result = sqlx::query!(
+            "DELETE FROM webhook_endpoints WHERE id = $1 AND user_id = $2",
+            endpoint_id,
+            user_id.0,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Create a delivery for every active endpoint of the user subscribed to the event
+    /// Endpoints that already have a delivery for the event are skipped
+    #[instrument(skip(self, payload))]
+    pub async fn create_deliveries(
+        &self,
+        user_id: UserId,
+        event_id: Uuid,
+        event_type: &str,
+        payload: &serde_json::Value,
+        lease_until: DateTime<Utc>,
+    ) -> Result<Vec<WebhookDelivery>> {
+        let rows = sqlx::query!(
+            r#"
+            WITH created AS (
+                INSERT INTO webhook_deliveries (endpoint_id, event_id, event_type, payload, next_attempt_at)
+                SELECT id, $2, $3, $4, $5
+                FROM webhook_endpoints
+                WHERE user_id = $1 AND is_active AND $3 = ANY(event_types)
+                ON CONFLICT (endpoint_id, event_id) DO NOTHING
+                RETURNING id, endpoint_id, attempts, max_attempts
+            )
+            SELECT created.id as "id!", created.attempts as "attempts!", created.max_attempts as "max_attempts!",
+                   e.url, e.secret
+            FROM created
+            JOIN webhook_endpoints e ON e.id = created.endpoint_id
+            "#,
+            user_id.0,
+            event_id,
+            event_type,
+            payload,
+            lease_until,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|r| WebhookDelivery {
+                id: r.id,
+                event_type: event_type.to_string(),
+                url: r.url,
+                secret: r.secret,
+                payload: payload.clone(),
+                attempts: r.attempts,
+                max_attempts: r.max_attempts,
+            })
+            .collect())
+    }
+
+    /// Lease pending deliveries of active endpoints whose retry time has come
+    #[instrument(skip(self))]
+    pub async fn claim_due_deliveries(&self, limit: i64, lease_until: DateTime<Utc>) -> Result<Vec<WebhookDelivery>> {
+        let rows = sqlx::query!(
+            r#"
+            UPDATE webhook_deliveries d
+            SET next_attempt_at = $2
+            FROM webhook_endpoints e
+            WHERE d.endpoint_id = e.id
+              AND d.id IN (
+                SELECT wd.id FROM webhook_deliveries wd
+                JOIN webhook_endpoints we ON we.id = wd.endpoint_id
+                WHERE wd.status = 'pending' AND wd.next_attempt_at <= NOW() AND we.is_active
+                ORDER BY wd.next_attempt_at
+                LIMIT $1
+                FOR UPDATE OF wd SKIP LOCKED
+              )
+            RETURNING d.id, d.event_type, d.payload, d.attempts, d.max_attempts, e.url, e.secret
+            "#,
+            limit,
+            lease_until,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|r| WebhookDelivery {
+                id: r.id,
+                event_type: r.event_type,
+                url: r.url,
+                secret: r.secret,
+                payload: r.payload,
+                attempts: r.attempts,
+                max_attempts: r.max_attempts,
+            })
+            .collect())
+    }
+
+    #[instrument(skip(self))]
+    pub async fn mark_delivered(&self, delivery_id: Uuid, response_status: i16) -> Result<()> {
+        sqlx::query!(
+            r#"
+            UPDATE webhook_deliveries
+            SET status = 'delivered', attempts = attempts + 1, delivered_at = NOW(),
+                last_response_status = $2, last_error = NULL
+            WHERE id = $1
+            "#,
+            delivery_id,
+            response_status,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Record a failed attempt; `retry_at` of `None` moves the delivery to the dead-letter list
+    #[instrument(skip(self))]
+    pub async fn mark_failed(
+        &self,
+        delivery_id: Uuid,
+        response_status: Option<i16>,
+        error: &str,
+        retry_at: Option<DateTime<Utc>>,
+    ) -> Result<()> {
+        sqlx::query!(
+            r#"
+            UPDATE webhook_deliveries
+            SET attempts = attempts + 1,
+                last_response_status = $2,
+                last_error = $3,
+                status = CASE WHEN $4::timestamptz IS NULL
+                              THEN 'dead'::webhook_delivery_status
+                              ELSE 'pending'::webhook_delivery_status END,
+                dead_at = CASE WHEN $4::timestamptz IS NULL THEN NOW() ELSE NULL END,
+                next_attempt_at = COALESCE($4, next_attempt_at)
+            WHERE id = $1
+            "#,
+            delivery_id,
+            response_status,
+            error,
+            retry_at,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Deliveries to the user's endpoints, newest first
+    #[instrument(skip(self))]
+    pub async fn list_deliveries(
+        &self,
+        user_id: UserId,
+        status: Option<WebhookDeliveryStatus>,
+        endpoint_id: Option<Uuid>,
+        limit: i64,
+    ) -> Result<Vec<WebhookDeliverySummary>> {
+        let rows = sqlx::query!(
+            r#"
+            SELECT d.id, d.endpoint_id, d.event_id, d.event_type,
+                   d.status as "status: WebhookDeliveryStatus",
+                   d.attempts, d.last_response_status, d.last_error, d.next_attempt_at,
+                   d.created_at, d.delivered_at, d.dead_at
+            FROM webhook_deliveries d
+            JOIN webhook_endpoints e ON e.id = d.endpoint_id
+            WHERE e.user_id = $1
+              AND ($2::webhook_delivery_status IS NULL OR d.status = $2)
+              AND ($3::uuid IS NULL OR d.endpoint_id = $3)
+            ORDER BY d.created_at DESC
+            LIMIT $4
+            "#,
+            user_id.0,
+            status as Option<WebhookDeliveryStatus>,
+            endpoint_id,
+            limit,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|r| WebhookDeliverySummary {
+                id: r.id,
+                endpoint_id: r.endpoint_id,
+                event_id: r.event_id,
+                event_type: r.event_type,
+                status: r.status,
+                attempts: r.attempts,
+                last_response_status: r.last_response_status,
+                last_error: r.last_error,
+                next_attempt_at: r.next_attempt_at,
+                created_at: r.created_at,
+                delivered_at: r.delivered_at,
+                dead_at: r.dead_at,
+            })
+            .collect())
+    }
+
+    /// Schedule a delivered or dead delivery to be sent again with a fresh attempt budget
+    /// Returns the delivery's status before the replay, or `None` if it isn't the user's
+    #[instrument(skip(self))]
+    pub async fn replay(&self, user_id: UserId, delivery_id: Uuid) -> Result<Option<WebhookDeliveryStatus>> {
+        let mut tx = self.pool.begin().await?;
+
+        let current = sqlx::query!(
+            r#"
+            SELECT d.status as "status: WebhookDeliveryStatus"
+            FROM webhook_deliveries d
+            JOIN webhook_endpoints e ON e.id = d.endpoint_id
+            WHERE d.id = $1 AND e.user_id = $2
+            FOR UPDATE OF d
+            "#,
+            delivery_id,
+            user_id.0,
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        let Some(current) = current else {
+            return Ok(None);
+        };
+
+        if current.status != WebhookDeliveryStatus::Pending {
+            sqlx::query!(
+                r#"
+                UPDATE webhook_deliveries
+                SET status = 'pending', attempts = 0, next_attempt_at = NOW(),
+                    dead_at = NULL, last_error = NULL
+                WHERE id = $1
+                "#,
+                delivery_id,
+            )
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        tx.commit().await?;
+        Ok(Some(current.status))
+    }
+}
+
+/// Webhook registration and delivery
+pub struct WebhookService {
+    repository: Arc<WebhookRepository>,
+    http_client: reqwest::Client,
+}
+
+impl WebhookService {
+    pub fn new(repository: Arc<WebhookRepository>) -> Self {
+        let http_client = reqwest::Client::builder()
+            .timeout(std::time::Duration::from_secs(REQUEST_TIMEOUT_SECONDS))
+            .redirect(reqwest::redirect::Policy::none())
+            .dns_resolver(Arc::new(PublicOnlyResolver))
+            .build()
+            .expect("Failed to build HTTP client");
+
+        Self {
+            repository,
+            http_client,
+        }
+    }
+
+    /// Register an endpoint, returning its signing secret
+    #[instrument(skip(self, request))]
+    pub async fn register_endpoint(
+        &self,
+        user_id: UserId,
+        request: CreateWebhookEndpointRequest,
+    ) -> Result<CreateWebhookEndpointResponse> {
+        request.validate().map_err(|e| AppError::Validation {
+            message: format!("Invalid webhook endpoint: {}", e),
+        })?;
+
+        // Payloads carry payment data, so production endpoints must use TLS
+        if std::env::var("ENVIRONMENT").unwrap_or_default() == "production" && !request.url.starts_with("https://") {
+            return Err(AppError::Validation {
+                message: "Webhook URL must use https".to_string(),
+            });
+        }
+
+        let url = check_webhook_url(&request.url)?;
+        if let (None, Some(name)) = (host_ip(&url), url.host_str()) {
+            resolve_public(name).await.map_err(|e| {
+                warn!("Rejected webhook URL for user {}: {}", user_id.0, e);
+                internal_url_error()
+            })?;
+        }
+
+        if let Some(unknown) = request
+            .event_types
+            .iter()
+            .find(|event_type| !WEBHOOK_EVENT_TYPES.contains(&event_type.as_str()))
+        {
+            return Err(AppError::Validation {
+                message: format!("Unknown webhook event type: {}", unknown),
+            });
+        }
+
+        let secret = generate_secret();
+        let endpoint = self.repository.create_endpoint(user_id, &request, &secret).await?;
+
+        info!("Webhook endpoint {} registered for user {}", endpoint.id, user_id.0);
+        Ok(CreateWebhookEndpointResponse { endpoint, secret })
+    }
+
+    #[instrument(skip(self))]
+    pub async fn list_endpoints(&self, user_id: UserId) -> Result<Vec<WebhookEndpoint>> {
+        self.repository.list_endpoints(user_id).await
+    }
+
+    #[instrument(skip(self))]
+    pub async fn delete_endpoint(&self, user_id: UserId, endpoint_id: Uuid) -> Result<()> {
+        if !self.repository.delete_endpoint(user_id, endpoint_id).await? {
+            return Err(AppError::User {
+                message: "Webhook endpoint not found".to_string(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Create and send the webhook deliveries for one domain event
+    /// Safe to call again for a redelivered event: existing deliveries are skipped
+    #[instrument(skip(self, envelope), fields(event_id = %envelope.id, event_type = envelope.event.event_type()))]
+    pub async fn handle_event(&self, envelope: &EventEnvelope) -> Result<()> {
+        let event_type = envelope.event.event_type();
+        if !WEBHOOK_EVENT_TYPES.contains(&event_type) {
+            return Ok(());
+        }
+
+        let deliveries = self
+            .repository
+            .create_deliveries(
+                envelope.event.user_id(),
+                envelope.id,
+                event_type,
+                &webhook_payload(envelope),
+                Utc::now() + Duration::seconds(SEND_LEASE_SECONDS),
+            )
+            .await?;
+
+        for delivery in &deliveries {
+            self.deliver(delivery).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Send deliveries whose retry time has come, returning how many were attempted
+    #[instrument(skip(self))]
+    pub async fn retry_due(&self, limit: i64) -> Result<usize> {
+        let deliveries = self
+            .repository
+            .claim_due_deliveries(limit, Utc::now() + Duration::seconds(SEND_LEASE_SECONDS))
+            .await?;
+
+        for delivery in &deliveries {
+            self.deliver(delivery).await?;
+        }
+
+        Ok(deliveries.len())
+    }
+
+    /// POST a signed payload to an endpoint URL that passed the host checks
+    async fn send(
+        &self,
+        delivery: &WebhookDelivery,
+        url: reqwest::Url,
+        timestamp: i64,
+        body: Vec<u8>,
+    ) -> std::result::Result<reqwest::Response, String> {
+        self.http_client
+            .post(url)
+            .header("Content-Type", "application/json")
+            .header("User-Agent", "PesaBit-Webhooks/1.0")
+            .header("X-PesaBit-Event", &delivery.event_type)
+            .header("X-PesaBit-Delivery", delivery.id.to_string())
+            .header("X-PesaBit-Timestamp", timestamp.to_string())
+            .header("X-PesaBit-Signature", sign_payload(&delivery.secret, timestamp, &body))
+            .body(body)
+            .send()
+            .await
+            .map_err(|e| format!("Request failed: {}", e))
+    }
+
+    /// POST one delivery and record the outcome
+    async fn deliver(&self, delivery: &WebhookDelivery) -> Result<()> {
+        let body = serde_json::to_vec(&delivery.payload)
+            .map_err(|e| AppError::Internal(anyhow::anyhow!("Failed to serialize webhook payload: {}", e)))?;
+        let timestamp = Utc::now().timestamp();
+
+        // Endpoints registered before host checks existed may point inside the
+        // network; names are re-checked by the client's resolver on connect
+        let result = match check_webhook_url(&delivery.url) {
+            Ok(url) => self.send(delivery, url, timestamp, body).await,
+            Err(e) => Err(e.to_string()),
+        };
+
+        let (response_status, error) = match result {
+            Ok(response) if response.status().is_success() => {
+                return self
+                    .repository
+                    .mark_delivered(delivery.id, response.status().as_u16() as i16)
+                    .await;
+            }
+            Ok(response) => (
+                Some(response.status().as_u16() as i16),
+                format!("Endpoint returned {}", response.status()),
+            ),
+            Err(error) => (None, error),
+        };
+
+        let retry_at = delivery
+            .can_retry()
+            .then(|| Utc::now() + WebhookDelivery::retry_backoff(delivery.attempts + 1));
+
+        match retry_at {
+            Some(retry_at) => warn!("Webhook delivery {} failed, retrying at {}: {}", delivery.id, retry_at, error),
+            None => warn!("Webhook delivery {} moved to dead-letter list: {}", delivery.id, error),
+        }
+
+        self.repository
+            .mark_failed(delivery.id, response_status, &error, retry_at)
+            .await
+    }
+
+    #[instrument(skip(self))]
+    pub async fn list_deliveries(
+        &self,
+        user_id: UserId,
+        params: WebhookDeliveryParams,
+    ) -> Result<Vec<WebhookDeliverySummary>> {
+        let limit = params.limit.unwrap_or(DEFAULT_DELIVERY_LIMIT).clamp(1, MAX_DELIVERY_LIMIT);
+        self.repository
+            .list_deliveries(user_id, params.status, params.endpoint_id, limit)
+            .await
+    }
+
+    /// Send a delivery again (typically one from the dead-letter list)
+    #[instrument(skip(self))]
+    pub async fn replay(&self, user_id: UserId, delivery_id: Uuid) -> Result<()> {
+        match self.repository.replay(user_id, delivery_id).await? {
+            None => Err(AppError::User {
+                message: "Webhook delivery not found".to_string(),
+            }),
+            Some(WebhookDeliveryStatus::Pending) => Err(AppError::Conflict {
+                message: "Webhook delivery is already scheduled".to_string(),
+            }),
+            Some(_) => {
+                info!("Webhook delivery {} scheduled for replay", delivery_id);
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use shared_events::DomainEvent;
+
+    #[test]
+    fn test_signature_matches_receiver_computation() {
+        let secret = "whsec_test";
+        let body = br#"{"id":"1","type":"InvoicePaid"}"#;
+
+        // HMAC-SHA256("whsec_test", "1700000000." + body), as any receiver would compute it
+        let signature = sign_payload(secret, 1_700_000_000, body);
+        assert_eq!(
+            signature,
+            "v1=0f6bf41de352689c577ffbdfed382b0ec1dd3ee9a6f520885188d68dfad19472"
+        );
+
+        assert_ne!(signature, sign_payload(secret, 1_700_000_001, body));
+        assert_ne!(signature, sign_payload("whsec_other", 1_700_000_000, body));
+        assert_ne!(signature, sign_payload(secret, 1_700_000_000, br#"{"id":"2","type":"InvoicePaid"}"#));
+    }
+
+    #[test]
+    fn test_payload_shape() {
+        let envelope = EventEnvelope {
+            id: Uuid::new_v4(),
+            sequence: 7,
+            occurred_at: Utc::now(),
+            event: DomainEvent::InvoicePaid {
+                transaction_id: Uuid::new_v4(),
+                user_id: UserId::new(),
+                amount_sats: Some(SatAmount::new(2_500)),
+            },
+        };
+
+        let payload = webhook_payload(&envelope);
+        assert_eq!(payload["id"], serde_json::json!(envelope.id));
+        assert_eq!(payload["type"], "InvoicePaid");
+        assert_eq!(payload["data"]["amount_sats"], "2500");
+        assert!(payload.get("sequence").is_none());
+    }
+
+    #[test]
+    fn test_retry_backoff() {
+        assert_eq!(WebhookDelivery::retry_backoff(1), Duration::minutes(1));
+        assert_eq!(WebhookDelivery::retry_backoff(3), Duration::minutes(4));
+        assert_eq!(WebhookDelivery::retry_backoff(9), Duration::hours(4) + Duration::minutes(16));
+        assert_eq!(WebhookDelivery::retry_backoff(10), Duration::hours(6));
+    }
+
+    #[test]
+    fn test_internal_addresses_are_rejected() {
+        for url in [
+            "http://169.254.169.254/latest/meta-data/",
+            "https://127.0.0.1/hook",
+            "https://10.0.3.7/hook",
+            "https://192.168.1.10/hook",
+            "https://100.64.0.1/hook",
+            "https://[::1]/hook",
+            "https://[fd00::1]/hook",
+            "https://[::ffff:10.0.0.1]/hook",
+            "https://localhost/hook",
+            "https://payment-service:3002/hook",
+            "https://redis.default.svc/hook",
+            "https://metadata.google.internal/computeMetadata/v1/",
+            "ftp://example.com/hook",
+        ] {
+            assert!(check_webhook_url(url).is_err(), "{} should be rejected", url);
+        }
+
+        assert!(check_webhook_url("https://hooks.example.com/pesabit").is_ok());
+        assert!(check_webhook_url("https://203.0.114.10/hook").is_ok());
+    }
+
+    #[test]
+    fn test_generated_secrets_are_unique() {
+        let secret = generate_secret();
+        assert!(secret.starts_with("whsec_"));
+        assert_eq!(secret.len(), 6 + 64);
+        assert_ne!(secret, generate_secret());
+    }
+}
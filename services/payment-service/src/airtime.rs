@@ -0,0 +1,756 @@
+/// Airtime top-ups paid in sats
+///
+/// Airtime for any Kenyan number is priced in sats at the current rate, the
+/// wallet is debited and an `AirtimeProvider` sends it. Providers confirm
+/// delivery by callback; purchases they reject or fail are moved to failed and
+/// refunded in sats by the refunds consumer, and purchases they never answered
+/// are left for an operator. Africa's Talking is the provider.
+
+use crate::domain::{StatusActor, StatusTransition};
+use crate::limits::{lock_and_check_limits, LimitsService};
+use crate::mobile_money::{ProviderCallback, ProviderOutcome};
+use crate::transitions::{set_status_actor, transition_status};
+use async_trait::async_trait;
+use reqwest::Method;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use shared_africastalking::AfricasTalkingClient;
+use shared_errors::{AppError, Result};
+use shared_events::{record_event, DomainEvent};
+use shared_security::authorize_callback;
+use shared_types::conversion::{Rounding, Side};
+use shared_types::*;
+use sqlx::{PgConnection, PgPool};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tracing::{info, instrument, warn};
+use uuid::Uuid;
+use validator::Validate;
+
+/// How long a purchase may wait for its callback before we ask the provider
+const STATUS_CHECK_AFTER_MINUTES: i32 = 5;
+
+/// Kenyan mobile network, by number prefix
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum KenyanCarrier {
+    Safaricom,
+    Airtel,
+    Telkom,
+    Equitel,
+    Faiba,
+}
+
+impl KenyanCarrier {
+    /// Carrier a number's prefix was issued to (ported numbers keep their prefix,
+    /// the provider routes them correctly)
+    pub fn from_phone(phone_number: &PhoneNumber) -> Option<Self> {
+        let national = phone_number.0.strip_prefix("+254")?;
+        if national.len() != 9 || !national.chars().all(|c| c.is_ascii_digit()) {
+            return None;
+        }
+
+        match national[..3].parse::<u32>().ok()? {
+            700..=729 | 740..=743 | 745 | 746 | 748 | 757..=759 | 768 | 769 | 790..=799 | 110..=115 => {
+                Some(KenyanCarrier::Safaricom)
+            }
+            730..=739 | 750..=756 | 762 | 780..=789 | 100..=102 => Some(KenyanCarrier::Airtel),
+            770..=779 => Some(KenyanCarrier::Telkom),
+            763..=766 => Some(KenyanCarrier::Equitel),
+            747 => Some(KenyanCarrier::Faiba),
+            _ => None,
+        }
+    }
+}
+
+/// Kenyan number in E.164 form, from `0712…`, `254712…` or `+254712…`
+pub fn kenyan_phone_number(input: &str) -> Result<PhoneNumber> {
+    let digits: String = input.chars().filter(|c| !c.is_whitespace() && *c != '-').collect();
+    let number = if let Some(national) = digits.strip_prefix('0') {
+        format!("+254{}", national)
+    } else if digits.starts_with("254") {
+        format!("+{}", digits)
+    } else {
+        digits
+    };
+
+    let phone_number = PhoneNumber::new(number).map_err(|_| AppError::invalid_phone_number())?;
+    if KenyanCarrier::from_phone(&phone_number).is_none() {
+        return Err(AppError::Validation {
+            message: "Airtime can only be bought for Kenyan mobile numbers".to_string(),
+        });
+    }
+    Ok(phone_number)
+}
+
+/// Airtime to send
+#[derive(Debug, Clone)]
+pub struct AirtimeRequest {
+    pub transaction_id: Uuid,
+    pub phone_number: PhoneNumber,
+    /// Whole shillings
+    pub amount_kes: i32,
+}
+
+/// Something that can send airtime
+#[async_trait]
+pub trait AirtimeProvider: Send + Sync {
+    /// Provider name stored with each purchase
+    fn name(&self) -> &'static str;
+
+    /// Send airtime; returns the provider's reference, or the failure when it's rejected outright
+    ///
+    /// `Err` means no usable answer came back and the airtime may still be delivered.
+    async fn send(&self, request: &AirtimeRequest) -> Result<std::result::Result<String, String>>;
+
+    /// Current state of an earlier send
+    async fn query_status(&self, provider_reference: &str) -> Result<ProviderOutcome>;
+
+    /// Read a status callback posted by the provider
+    fn parse_callback(&self, fields: &HashMap<String, String>) -> Result<ProviderCallback>;
+}
+
+/// Africa's Talking credentials (the SMS account's, unless airtime has its own)
+#[derive(Debug, Clone)]
+pub struct AirtimeConfig {
+    pub base_url: String,
+    pub username: String,
+    pub api_key: String,
+}
+
+impl AirtimeConfig {
+    pub fn from_env() -> Self {
+        let env = |name: &str, fallback: &str| {
+            std::env::var(name)
+                .ok()
+                .filter(|value| !value.is_empty())
+                .or_else(|| std::env::var(fallback).ok())
+        };
+        Self {
+            base_url: std::env::var("AIRTIME_API_URL").unwrap_or_else(|_| "https://api.africastalking.com".to_string()),
+            username: env("AIRTIME_USERNAME", "SMS_USERNAME").unwrap_or_else(|| "sandbox".to_string()),
+            api_key: env("AIRTIME_API_KEY", "SMS_API_KEY").unwrap_or_default(),
+        }
+    }
+}
+
+/// Africa's Talking airtime API
+pub struct AfricasTalkingAirtime {
+    base_url: String,
+    client: AfricasTalkingClient,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AtAirtimeResponse {
+    error_message: String,
+    #[serde(default)]
+    responses: Vec<AtAirtimeEntry>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AtAirtimeEntry {
+    status: String,
+    request_id: String,
+    error_message: String,
+}
+
+impl AfricasTalkingAirtime {
+    pub fn new(config: AirtimeConfig) -> Self {
+        Self {
+            base_url: config.base_url,
+            client: AfricasTalkingClient::new(config.username, config.api_key),
+        }
+    }
+}
+
+/// Status reported by Africa's Talking for an airtime request
+fn at_outcome(status: &str, description: Option<&str>) -> ProviderOutcome {
+    match status {
+        "Success" => ProviderOutcome::Succeeded { receipt: None },
+        "Failed" | "Rejected" => ProviderOutcome::Failed {
+            reason: description
+                .filter(|description| !description.is_empty() && *description != "None")
+                .unwrap_or("Airtime could not be delivered")
+                .to_string(),
+        },
+        _ => ProviderOutcome::Pending,
+    }
+}
+
+#[async_trait]
+impl AirtimeProvider for AfricasTalkingAirtime {
+    fn name(&self) -> &'static str {
+        "africastalking"
+    }
+
+    #[instrument(skip(self, request), fields(transaction_id = %request.transaction_id))]
+    async fn send(&self, request: &AirtimeRequest) -> Result<std::result::Result<String, String>> {
+        let response = self
+            .client
+            .request(Method::POST, &format!("{}/version1/airtime/send", self.base_url))
+            .json(&serde_json::json!({
+                "username": self.client.username(),
+                "recipients": [{
+                    "phoneNumber": request.phone_number.0,
+                    "currencyCode": "KES",
+                    "amount": request.amount_kes,
+                }],
+            }))
+            .send()
+            .await
+            .map_err(|e| AppError::ExternalService {
+                message: format!("Airtime request failed: {}", e),
+            })?;
+
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            let message = format!("Airtime provider returned {}: {}", status, body);
+            // Refused requests aren't sent; server errors and timeouts may have been
+            if status.is_client_error() && status != reqwest::StatusCode::REQUEST_TIMEOUT {
+                return Ok(Err(message));
+            }
+            return Err(AppError::ExternalService { message });
+        }
+
+        let body: AtAirtimeResponse = response.json().await.map_err(|e| AppError::ExternalService {
+            message: format!("Invalid airtime provider response: {}", e),
+        })?;
+
+        Ok(match body.responses.into_iter().next() {
+            Some(entry) if entry.status == "Sent" || entry.status == "Success" => Ok(entry.request_id),
+            Some(entry) => Err(entry.error_message),
+            None => Err(body.error_message),
+        })
+    }
+
+    #[instrument(skip(self))]
+    async fn query_status(&self, provider_reference: &str) -> Result<ProviderOutcome> {
+        let response: Value = self
+            .client
+            .request(Method::GET, &format!("{}/query/transaction/find", self.base_url))
+            .query(&[("username", self.client.username()), ("transactionId", provider_reference)])
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| AppError::ExternalService {
+                message: format!("Airtime status query failed: {}", e),
+            })?
+            .json()
+            .await
+            .map_err(|e| AppError::ExternalService {
+                message: format!("Invalid airtime status response: {}", e),
+            })?;
+
+        let data = &response["data"];
+        Ok(at_outcome(
+            data["status"].as_str().unwrap_or_default(),
+            data["description"].as_str(),
+        ))
+    }
+
+    fn parse_callback(&self, fields: &HashMap<String, String>) -> Result<ProviderCallback> {
+        let (Some(request_id), Some(status)) = (fields.get("requestId"), fields.get("status")) else {
+            return Err(AppError::Validation {
+                message: "Unrecognised airtime callback".to_string(),
+            });
+        };
+
+        Ok(ProviderCallback {
+            provider_reference: request_id.clone(),
+            outcome: at_outcome(status, fields.get("description").map(String::as_str)),
+        })
+    }
+}
+
+/// Buy airtime for a phone
+#[derive(Debug, Deserialize, Validate)]
+pub struct BuyAirtimeRequest {
+    /// Any Kenyan number (defaults to the user's registered number)
+    pub phone_number: Option<String>,
+    /// Whole shillings
+    #[validate(range(min = 10, max = 10000))]
+    pub amount_kes: i32,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct AirtimeQuoteParams {
+    #[validate(range(min = 10, max = 10000))]
+    pub amount_kes: i32,
+}
+
+/// What airtime costs in sats at the current rate (airtime carries no fee)
+#[derive(Debug, Clone, Serialize)]
+pub struct AirtimeQuote {
+    pub amount_kes: KesAmount,
+    pub amount_sats: SatAmount,
+    pub exchange_rate: BtcKesRate,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AirtimePurchaseResponse {
+    pub transaction_id: Uuid,
+    pub phone_number: PhoneNumber,
+    pub carrier: KenyanCarrier,
+    #[serde(flatten)]
+    pub quote: AirtimeQuote,
+    pub status: TransactionStatus,
+}
+
+/// Airtime purchase waiting on the provider
+#[derive(Debug, Clone)]
+pub struct PendingAirtime {
+    pub id: Uuid,
+    pub user_id: UserId,
+    pub provider_reference: String,
+    pub phone_number: String,
+    pub amount_kes: Option<Decimal>,
+    pub amount_sats: Option<i64>,
+}
+
+/// Airtime purchase repository
+pub struct AirtimeRepository {
+    pool: PgPool,
+}
+
+impl AirtimeRepository {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+
+    #[instrument(skip(self))]
+    pub async fn phone_number(&self, user_id: UserId) -> Result<String> {
+        let row = sqlx::query!("SELECT phone_number FROM users WHERE id = $1", user_id.0)
+            .fetch_optional(&self.pool)
+            .await?
+            .ok_or_else(|| AppError::User {
+                message: "User not found".to_string(),
+            })?;
+
+        Ok(row.phone_number)
+    }
+
+    /// Debit the wallet and record a purchase about to be sent to the provider
+    #[instrument(skip(self))]
+    pub async fn create(
+        &self,
+        user_id: UserId,
+        phone_number: &PhoneNumber,
+        carrier: KenyanCarrier,
+        amount_kes: KesAmount,
+        amount_sats: SatAmount,
+        rate: BtcKesRate,
+        provider: &str,
+    ) -> Result<Uuid> {
+        let mut tx = self.pool.begin().await?;
+        lock_and_check_limits(&mut *tx, user_id, amount_kes.as_decimal(), rate).await?;
+        set_status_actor(&mut *tx, &StatusActor::System, Some("airtime_purchase")).await?;
+
+        let debited = sqlx::query!(
+            "UPDATE wallets SET balance_sats = balance_sats - $2 WHERE user_id = $1 AND balance_sats >= $2",
+            user_id.0,
+            amount_sats.as_i64(),
+        )
+        .execute(&mut *tx)
+        .await?;
+        if debited.rows_affected() != 1 {
+            return Err(AppError::Payment {
+                message: "Insufficient balance".to_string(),
+            });
+        }
+
+        let id = sqlx::query_scalar!(
+            r#"
+            INSERT INTO transactions (user_id, type, status, amount_kes, amount_sats, exchange_rate, metadata)
+            VALUES ($1, 'airtime_purchase', 'processing', $2, $3, $4, $5)
+            RETURNING id
+            "#,
+            user_id.0,
+            amount_kes.as_decimal(),
+            amount_sats.as_i64(),
+            rate.kes_per_btc(),
+            serde_json::json!({
+                "phone_number": phone_number.0,
+                "carrier": carrier,
+                "airtime_provider": provider,
+                "description": format!("Airtime for {}", phone_number.0),
+                "counterparty": phone_number.0,
+            }),
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+        Ok(id)
+    }
+
+    #[instrument(skip(self))]
+    pub async fn set_provider_reference(&self, id: Uuid, provider_reference: &str) -> Result<()> {
+        sqlx::query!(
+            "UPDATE transactions SET provider_reference = $2 WHERE id = $1",
+            id,
+            provider_reference,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Leave a purchase the provider never confirmed to an operator, who refunds it
+    /// through `/internal/refunds` if the airtime never arrived
+    #[instrument(skip(self))]
+    pub async fn flag_for_review(&self, id: Uuid, reason: &str) -> Result<()> {
+        sqlx::query!(
+            r#"
+            UPDATE transactions
+            SET metadata = COALESCE(metadata, '{}') || jsonb_build_object('needs_review', true, 'error', $2::text)
+            WHERE id = $1 AND status = 'processing'
+            "#,
+            id,
+            reason,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn lock_processing(conn: &mut PgConnection, id: Uuid) -> Result<Option<PendingAirtime>> {
+        let row = sqlx::query!(
+            r#"
+            SELECT id, user_id, provider_reference, metadata->>'phone_number' AS phone_number,
+                   amount_kes, amount_sats
+            FROM transactions
+            WHERE id = $1 AND type = 'airtime_purchase' AND status = 'processing'
+            FOR UPDATE
+            "#,
+            id,
+        )
+        .fetch_optional(conn)
+        .await?;
+
+        Ok(row.map(|r| PendingAirtime {
+            id: r.id,
+            user_id: UserId(r.user_id),
+            provider_reference: r.provider_reference.unwrap_or_default(),
+            phone_number: r.phone_number.unwrap_or_default(),
+            amount_kes: r.amount_kes,
+            amount_sats: r.amount_sats,
+        }))
+    }
+
+    #[instrument(skip(self))]
+    pub async fn find_by_reference(&self, provider_reference: &str) -> Result<Option<Uuid>> {
+        let id = sqlx::query_scalar!(
+            r#"
+            SELECT id FROM transactions
+            WHERE provider IS NULL AND provider_reference = $1 AND type = 'airtime_purchase'
+            "#,
+            provider_reference,
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(id)
+    }
+
+    /// Apply the provider's final answer; returns the purchase if it was still processing
+    #[instrument(skip(self))]
+    pub async fn settle(&self, id: Uuid, outcome: &ProviderOutcome) -> Result<Option<PendingAirtime>> {
+        let mut tx = self.pool.begin().await?;
+        let Some(purchase) = Self::lock_processing(&mut *tx, id).await? else {
+            return Ok(None);
+        };
+
+        let (to, event) = match outcome {
+            ProviderOutcome::Pending => return Ok(None),
+            ProviderOutcome::Succeeded { .. } => (
+                TransactionStatus::Completed,
+                DomainEvent::AirtimePurchased {
+                    transaction_id: purchase.id,
+                    user_id: purchase.user_id,
+                    phone_number: purchase.phone_number.clone(),
+                    amount_kes: purchase.amount_kes.map(KesAmount::new),
+                    amount_sats: purchase.amount_sats.map(SatAmount::new),
+                },
+            ),
+            ProviderOutcome::Failed { reason } => {
+                sqlx::query!(
+                    "UPDATE transactions SET metadata = COALESCE(metadata, '{}') || jsonb_build_object('error', $2::text) WHERE id = $1",
+                    purchase.id,
+                    reason,
+                )
+                .execute(&mut *tx)
+                .await?;
+
+                // Refunded to the wallet by the refunds consumer
+                (
+                    TransactionStatus::Failed,
+                    DomainEvent::AirtimeFailed {
+                        transaction_id: purchase.id,
+                        user_id: purchase.user_id,
+                        phone_number: purchase.phone_number.clone(),
+                        amount_sats: purchase.amount_sats.map(SatAmount::new),
+                        reason: Some(reason.clone()),
+                    },
+                )
+            }
+        };
+
+        let transition = StatusTransition::new(TransactionStatus::Processing, to)?;
+        transition_status(&mut *tx, purchase.id, &transition, &StatusActor::System, Some("airtime_status")).await?;
+        record_event(&mut *tx, &event).await?;
+
+        tx.commit().await?;
+        Ok(Some(purchase))
+    }
+
+    /// Claim purchases whose callback hasn't arrived
+    #[instrument(skip(self))]
+    pub async fn claim_stale(&self, limit: i64) -> Result<Vec<PendingAirtime>> {
+        let rows = sqlx::query!(
+            r#"
+            UPDATE transactions SET provider_checked_at = NOW()
+            WHERE id IN (
+                SELECT id FROM transactions
+                WHERE type = 'airtime_purchase' AND status = 'processing' AND provider_reference IS NOT NULL
+                  AND created_at <= NOW() - make_interval(mins => $1)
+                  AND (provider_checked_at IS NULL OR provider_checked_at <= NOW() - make_interval(mins => $1))
+                ORDER BY created_at
+                LIMIT $2
+                FOR UPDATE SKIP LOCKED
+            )
+            RETURNING id, user_id, provider_reference as "provider_reference!",
+                      metadata->>'phone_number' AS phone_number, amount_kes, amount_sats
+            "#,
+            STATUS_CHECK_AFTER_MINUTES,
+            limit,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|r| PendingAirtime {
+                id: r.id,
+                user_id: UserId(r.user_id),
+                provider_reference: r.provider_reference,
+                phone_number: r.phone_number.unwrap_or_default(),
+                amount_kes: r.amount_kes,
+                amount_sats: r.amount_sats,
+            })
+            .collect())
+    }
+}
+
+/// Airtime service
+pub struct AirtimeService {
+    repository: Arc<AirtimeRepository>,
+    provider: Arc<dyn AirtimeProvider>,
+    limits_service: Arc<LimitsService>,
+    callback_token: Option<String>,
+}
+
+impl AirtimeService {
+    pub fn new(
+        repository: Arc<AirtimeRepository>,
+        provider: Arc<dyn AirtimeProvider>,
+        limits_service: Arc<LimitsService>,
+    ) -> Self {
+        Self {
+            repository,
+            provider,
+            limits_service,
+            callback_token: std::env::var("AIRTIME_CALLBACK_TOKEN").ok().filter(|token| !token.is_empty()),
+        }
+    }
+
+    /// Price airtime in sats
+    #[instrument(skip(self))]
+    pub async fn quote(&self, params: AirtimeQuoteParams) -> Result<AirtimeQuote> {
+        params.validate().map_err(|e| AppError::Validation {
+            message: format!("Invalid airtime amount: {}", e),
+        })?;
+
+        let amount_kes = KesAmount::new(Decimal::from(params.amount_kes));
+        let rate = self.limits_service.current_rate().await?;
+        let amount_sats = rate.kes_to_sats(&amount_kes, Side::Charge, Rounding::HouseFavourable)?;
+
+        Ok(AirtimeQuote {
+            amount_kes,
+            amount_sats,
+            exchange_rate: rate,
+        })
+    }
+
+    /// Charge the wallet at a quote and send the airtime; failures are refunded in sats
+    #[instrument(skip(self, request))]
+    pub async fn buy(&self, user_id: UserId, request: BuyAirtimeRequest) -> Result<AirtimePurchaseResponse> {
+        request.validate().map_err(|e| AppError::Validation {
+            message: format!("Invalid airtime purchase: {}", e),
+        })?;
+        let phone_number = match request.phone_number {
+            Some(phone_number) => kenyan_phone_number(&phone_number)?,
+            None => kenyan_phone_number(&self.repository.phone_number(user_id).await?)?,
+        };
+        let carrier = KenyanCarrier::from_phone(&phone_number).expect("checked by kenyan_phone_number");
+
+        self.limits_service
+            .check_kes(user_id, Decimal::from(request.amount_kes))
+            .await?;
+        let quote = self
+            .quote(AirtimeQuoteParams {
+                amount_kes: request.amount_kes,
+            })
+            .await?;
+
+        let transaction_id = self
+            .repository
+            .create(
+                user_id,
+                &phone_number,
+                carrier,
+                quote.amount_kes,
+                quote.amount_sats,
+                quote.exchange_rate,
+                self.provider.name(),
+            )
+            .await?;
+
+        let airtime_request = AirtimeRequest {
+            transaction_id,
+            phone_number: phone_number.clone(),
+            amount_kes: request.amount_kes,
+        };
+        let status = match self.provider.send(&airtime_request).await {
+            Ok(Ok(provider_reference)) => {
+                self.repository.set_provider_reference(transaction_id, &provider_reference).await?;
+                TransactionStatus::Processing
+            }
+            Ok(Err(reason)) => {
+                self.repository.settle(transaction_id, &ProviderOutcome::Failed { reason }).await?;
+                TransactionStatus::Failed
+            }
+            // The airtime may have gone out, and without the provider's reference
+            // there's nothing to ask about, so an operator settles it
+            Err(e) => {
+                warn!("Airtime purchase {} unconfirmed, flagging for review: {:?}", transaction_id, e);
+                self.repository
+                    .flag_for_review(transaction_id, &format!("Provider did not confirm the request: {}", e))
+                    .await?;
+                TransactionStatus::Processing
+            }
+        };
+
+        info!("Airtime purchase {} for user {} is {}", transaction_id, user_id, status.as_str());
+        Ok(AirtimePurchaseResponse {
+            transaction_id,
+            phone_number,
+            carrier,
+            quote,
+            status,
+        })
+    }
+
+    /// Apply a provider status callback (duplicates are ignored)
+    ///
+    /// Failures are confirmed with the provider before the purchase is refunded.
+    #[instrument(skip(self, token, fields))]
+    pub async fn handle_callback(&self, token: Option<&str>, fields: &HashMap<String, String>) -> Result<()> {
+        if let Err(e) = authorize_callback(self.callback_token.as_deref(), token) {
+            warn!("Refused airtime callback without a valid token");
+            return Err(e);
+        }
+
+        let callback = self.provider.parse_callback(fields)?;
+        let Some(id) = self.repository.find_by_reference(&callback.provider_reference).await? else {
+            warn!("Airtime callback for unknown request {}", callback.provider_reference);
+            return Ok(());
+        };
+
+        let outcome = match callback.outcome {
+            ProviderOutcome::Failed { .. } => self.provider.query_status(&callback.provider_reference).await?,
+            outcome => outcome,
+        };
+        if self.repository.settle(id, &outcome).await?.is_some() {
+            info!("Airtime callback settled purchase {}", id);
+        }
+        Ok(())
+    }
+
+    /// Ask the provider about purchases whose callback hasn't arrived
+    pub async fn check_stale(&self, limit: i64) -> Result<usize> {
+        let mut settled = 0;
+        for purchase in self.repository.claim_stale(limit).await? {
+            let outcome = match self.provider.query_status(&purchase.provider_reference).await {
+                Ok(outcome) => outcome,
+                Err(e) => {
+                    warn!("Status check failed for airtime purchase {}: {:?}", purchase.id, e);
+                    continue;
+                }
+            };
+            if self.repository.settle(purchase.id, &outcome).await?.is_some() {
+                settled += 1;
+            }
+        }
+        Ok(settled)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_kenyan_phone_numbers() {
+        assert_eq!(kenyan_phone_number("0712 345 678").unwrap().0, "+254712345678");
+        assert_eq!(kenyan_phone_number("254733123456").unwrap().0, "+254733123456");
+        assert_eq!(kenyan_phone_number("+254110123456").unwrap().0, "+254110123456");
+        assert!(kenyan_phone_number("+255712345678").is_err());
+        assert!(kenyan_phone_number("0201234567").is_err());
+
+        let carrier = |number: &str| KenyanCarrier::from_phone(&PhoneNumber(number.to_string()));
+        assert_eq!(carrier("+254712345678"), Some(KenyanCarrier::Safaricom));
+        assert_eq!(carrier("+254733123456"), Some(KenyanCarrier::Airtel));
+        assert_eq!(carrier("+254771234567"), Some(KenyanCarrier::Telkom));
+        assert_eq!(carrier("+254763123456"), Some(KenyanCarrier::Equitel));
+        assert_eq!(carrier("+25471234567"), None);
+    }
+
+    #[test]
+    fn test_africastalking_callbacks() {
+        let provider = AfricasTalkingAirtime::new(AirtimeConfig {
+            base_url: "https://api.sandbox.africastalking.com".to_string(),
+            username: "sandbox".to_string(),
+            api_key: "key".to_string(),
+        });
+        let fields = |status: &str, description: &str| {
+            HashMap::from([
+                ("requestId".to_string(), "ATQid_1234".to_string()),
+                ("phoneNumber".to_string(), "+254712345678".to_string()),
+                ("status".to_string(), status.to_string()),
+                ("description".to_string(), description.to_string()),
+                ("value".to_string(), "KES 100.0000".to_string()),
+            ])
+        };
+
+        assert_eq!(
+            provider.parse_callback(&fields("Success", "Airtime Delivered Successfully")).unwrap(),
+            ProviderCallback {
+                provider_reference: "ATQid_1234".to_string(),
+                outcome: ProviderOutcome::Succeeded { receipt: None },
+            }
+        );
+        assert_eq!(
+            provider.parse_callback(&fields("Failed", "Invalid phone number")).unwrap().outcome,
+            ProviderOutcome::Failed {
+                reason: "Invalid phone number".to_string()
+            }
+        );
+        assert!(provider.parse_callback(&HashMap::new()).is_err());
+    }
+}
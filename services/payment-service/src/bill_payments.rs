@@ -0,0 +1,293 @@
+/// Paying M-Pesa Paybill numbers and Buy Goods tills from sats
+///
+/// A KES bill is priced in sats at the current rate, the wallet is debited and the
+/// money goes out over the M-Pesa B2B API. Bill payments are recorded as M-Pesa
+/// withdrawals, so result and timeout callbacks, status checks and refunds of
+/// failed payments work exactly as they do for withdrawals.
+
+use crate::fees::FeeService;
+use crate::limits::LimitsService;
+use crate::mobile_money::{MobileMoneyProvider, MobileMoneyRepository, MpesaProvider, Submission, TransferDirection};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use shared_errors::{AppError, Result};
+use shared_types::conversion::{Rounding, Side};
+use shared_types::*;
+use std::sync::Arc;
+use tracing::{info, instrument, warn};
+use uuid::Uuid;
+use validator::Validate;
+
+/// Longest account reference accepted by Paybill numbers
+const MAX_ACCOUNT_REFERENCE_LENGTH: usize = 20;
+
+/// Business being paid
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum BusinessPayee {
+    /// Paybill number with the customer's account at the business (e.g. meter number)
+    Paybill {
+        paybill_number: String,
+        account_reference: String,
+    },
+    /// Buy Goods till number
+    Till { till_number: String },
+}
+
+impl BusinessPayee {
+    pub fn shortcode(&self) -> &str {
+        match self {
+            BusinessPayee::Paybill { paybill_number, .. } => paybill_number,
+            BusinessPayee::Till { till_number } => till_number,
+        }
+    }
+
+    /// Shown in transaction history and statements
+    pub fn description(&self) -> String {
+        match self {
+            BusinessPayee::Paybill {
+                paybill_number,
+                account_reference,
+            } => format!("Paybill {} account {}", paybill_number, account_reference),
+            BusinessPayee::Till { till_number } => format!("Till {}", till_number),
+        }
+    }
+
+    /// Trim whitespace and check the numbers look like M-Pesa shortcodes
+    fn normalized(self) -> Result<Self> {
+        let shortcode = |number: String, kind: &str| {
+            let number = number.trim().to_string();
+            if (5..=7).contains(&number.len()) && number.chars().all(|c| c.is_ascii_digit()) {
+                Ok(number)
+            } else {
+                Err(AppError::Validation {
+                    message: format!("Invalid {} number", kind),
+                })
+            }
+        };
+
+        match self {
+            BusinessPayee::Paybill {
+                paybill_number,
+                account_reference,
+            } => {
+                let account_reference = account_reference.trim().to_string();
+                if account_reference.is_empty()
+                    || account_reference.chars().count() > MAX_ACCOUNT_REFERENCE_LENGTH
+                    || !account_reference.chars().all(|c| c.is_ascii_alphanumeric() || "-/ ".contains(c))
+                {
+                    return Err(AppError::Validation {
+                        message: "Invalid account reference".to_string(),
+                    });
+                }
+                Ok(BusinessPayee::Paybill {
+                    paybill_number: shortcode(paybill_number, "paybill")?,
+                    account_reference,
+                })
+            }
+            BusinessPayee::Till { till_number } => Ok(BusinessPayee::Till {
+                till_number: shortcode(till_number, "till")?,
+            }),
+        }
+    }
+}
+
+/// Pay a Paybill number or till
+#[derive(Debug, Deserialize, Validate)]
+pub struct BillPaymentRequest {
+    #[serde(flatten)]
+    pub payee: BusinessPayee,
+    /// Whole shillings
+    #[validate(range(min = 10, max = 250000))]
+    pub amount_kes: i32,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct BillQuoteParams {
+    #[validate(range(min = 10, max = 250000))]
+    pub amount_kes: i32,
+}
+
+/// What a bill costs in sats at the current rate
+#[derive(Debug, Clone, Serialize)]
+pub struct BillPaymentQuote {
+    pub amount_kes: KesAmount,
+    pub fee_kes: KesAmount,
+    pub amount_sats: SatAmount,
+    pub fee_sats: SatAmount,
+    /// Debited from the wallet
+    pub total_sats: SatAmount,
+    pub exchange_rate: BtcKesRate,
+    pub fee_schedule_version: i32,
+    #[serde(skip)]
+    fee_schedule_id: Uuid,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BillPaymentResponse {
+    pub transaction_id: Uuid,
+    #[serde(flatten)]
+    pub payee: BusinessPayee,
+    #[serde(flatten)]
+    pub quote: BillPaymentQuote,
+    pub status: TransactionStatus,
+}
+
+/// Bill payment service
+pub struct BillPaymentService {
+    repository: Arc<MobileMoneyRepository>,
+    mpesa: Arc<MpesaProvider>,
+    fee_service: Arc<FeeService>,
+    limits_service: Arc<LimitsService>,
+}
+
+impl BillPaymentService {
+    pub fn new(
+        repository: Arc<MobileMoneyRepository>,
+        mpesa: Arc<MpesaProvider>,
+        fee_service: Arc<FeeService>,
+        limits_service: Arc<LimitsService>,
+    ) -> Self {
+        Self {
+            repository,
+            mpesa,
+            fee_service,
+            limits_service,
+        }
+    }
+
+    /// Price a bill in sats (the wallet pays the M-Pesa withdrawal fee on top)
+    #[instrument(skip(self))]
+    pub async fn quote(&self, params: BillQuoteParams) -> Result<BillPaymentQuote> {
+        params.validate().map_err(|e| AppError::Validation {
+            message: format!("Invalid bill amount: {}", e),
+        })?;
+
+        let amount_kes = KesAmount::new(Decimal::from(params.amount_kes));
+        let fees = self
+            .fee_service
+            .quote(&TransactionType::WithdrawalMpesa, amount_kes.as_decimal())
+            .await?;
+        let rate = self.limits_service.current_rate().await?;
+        let amount_sats = rate.kes_to_sats(&amount_kes, Side::Charge, Rounding::HouseFavourable)?;
+        let fee_sats = rate.kes_to_sats(&fees.total_kes, Side::Charge, Rounding::HouseFavourable)?;
+
+        Ok(BillPaymentQuote {
+            amount_kes,
+            fee_kes: fees.total_kes,
+            amount_sats,
+            fee_sats,
+            total_sats: SatAmount::new(amount_sats.as_i64() + fee_sats.as_i64()),
+            exchange_rate: rate,
+            fee_schedule_version: fees.schedule_version,
+            fee_schedule_id: fees.schedule_id,
+        })
+    }
+
+    /// Debit the wallet and pay the business; failed payments are refunded to the wallet
+    #[instrument(skip(self, request))]
+    pub async fn pay(&self, user_id: UserId, request: BillPaymentRequest) -> Result<BillPaymentResponse> {
+        request.validate().map_err(|e| AppError::Validation {
+            message: format!("Invalid bill payment: {}", e),
+        })?;
+        let payee = request.payee.normalized()?;
+
+        self.limits_service
+            .check_kes(user_id, Decimal::from(request.amount_kes))
+            .await?;
+        let quote = self
+            .quote(BillQuoteParams {
+                amount_kes: request.amount_kes,
+            })
+            .await?;
+
+        let description = payee.description();
+        let transaction_id = self
+            .repository
+            .create_withdrawal(
+                user_id,
+                MobileMoneyNetwork::Mpesa,
+                serde_json::json!({
+                    "description": description,
+                    "counterparty": payee.shortcode(),
+                    "business_payee": payee,
+                }),
+                quote.amount_sats,
+                quote.amount_kes,
+                quote.fee_sats,
+                quote.fee_kes,
+                quote.exchange_rate,
+                quote.fee_schedule_id,
+            )
+            .await?;
+
+        // Results come back under our transaction ID, so a payment whose answer is lost
+        // is still settled by its callback
+        if let Some(reference) = self.mpesa.client_reference(TransferDirection::Disbursement, transaction_id) {
+            self.repository.set_provider_reference(transaction_id, &reference).await?;
+        }
+        match self
+            .mpesa
+            .pay_business(transaction_id, &payee, quote.amount_kes.as_decimal(), &description)
+            .await
+        {
+            Ok(Submission::Accepted(_)) => {}
+            // Only an explicit refusal means the money never moved
+            Ok(Submission::Rejected(e)) => {
+                self.repository.fail(transaction_id, &e.to_string()).await?;
+                return Err(e);
+            }
+            Err(e) => {
+                warn!("M-Pesa did not confirm bill payment {}, leaving it processing: {:?}", transaction_id, e);
+            }
+        }
+
+        info!("Bill payment {} sent for user {}", transaction_id, user_id);
+        Ok(BillPaymentResponse {
+            transaction_id,
+            payee,
+            quote,
+            status: TransactionStatus::Processing,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_business_payee() {
+        let request: BillPaymentRequest = serde_json::from_value(serde_json::json!({
+            "paybill_number": " 888880",
+            "account_reference": "37123456789 ",
+            "amount_kes": 1500
+        }))
+        .unwrap();
+        let payee = request.payee.normalized().unwrap();
+        assert_eq!(payee.shortcode(), "888880");
+        assert_eq!(payee.description(), "Paybill 888880 account 37123456789");
+
+        let request: BillPaymentRequest =
+            serde_json::from_value(serde_json::json!({ "till_number": "5123456", "amount_kes": 250 })).unwrap();
+        assert_eq!(
+            request.payee,
+            BusinessPayee::Till {
+                till_number: "5123456".to_string()
+            }
+        );
+
+        let till = |number: &str| BusinessPayee::Till {
+            till_number: number.to_string(),
+        };
+        assert!(till("12a456").normalized().is_err());
+        assert!(till("1234").normalized().is_err());
+        let paybill = |account: &str| BusinessPayee::Paybill {
+            paybill_number: "247247".to_string(),
+            account_reference: account.to_string(),
+        };
+        assert!(paybill("  ").normalized().is_err());
+        assert!(paybill("0712345678; DROP").normalized().is_err());
+        assert!(paybill("ACC-001/2").normalized().is_ok());
+    }
+}
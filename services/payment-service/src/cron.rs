@@ -0,0 +1,198 @@
+/// Cron expressions for recurring payments
+///
+/// Standard five fields (minute, hour, day of month, month, day of week) with
+/// `*`, lists, ranges and steps, evaluated in Nairobi time (EAT, UTC+3, no
+/// daylight saving). As in cron, when both day fields are restricted a day
+/// matching either one runs. Payments run at most once an hour, so the minute
+/// field must be a single value.
+
+use chrono::{DateTime, Datelike, Duration, NaiveDate, NaiveDateTime, TimeZone, Timelike, Utc};
+use shared_errors::{AppError, Result};
+
+/// Offset of Nairobi time from UTC
+pub const NAIROBI_OFFSET_HOURS: i64 = 3;
+/// How far ahead to look for the next run before giving up
+const MAX_SEARCH_DAYS: i64 = 5 * 366;
+
+/// Parsed cron expression
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CronSchedule {
+    expression: String,
+    minutes: u64,
+    hours: u64,
+    days_of_month: u64,
+    months: u64,
+    days_of_week: u64,
+    any_day_of_month: bool,
+    any_day_of_week: bool,
+}
+
+impl CronSchedule {
+    pub fn parse(expression: &str) -> Result<Self> {
+        let invalid = |detail: &str| AppError::Validation {
+            message: format!("Invalid cron expression '{}': {}", expression, detail),
+        };
+
+        let fields: Vec<&str> = expression.split_whitespace().collect();
+        let [minute, hour, day_of_month, month, day_of_week] = fields[..] else {
+            return Err(invalid("expected 5 fields (minute hour day-of-month month day-of-week)"));
+        };
+
+        let minutes = parse_field(minute, 0, 59).ok_or_else(|| invalid("bad minute"))?;
+        if minutes.count_ones() != 1 {
+            return Err(invalid("payments run at most once an hour, use a single minute (e.g. '0 9 * * 1')"));
+        }
+        let hours = parse_field(hour, 0, 23).ok_or_else(|| invalid("bad hour"))?;
+        let days_of_month = parse_field(day_of_month, 1, 31).ok_or_else(|| invalid("bad day of month"))?;
+        let months = parse_field(month, 1, 12).ok_or_else(|| invalid("bad month"))?;
+        let mut days_of_week = parse_field(day_of_week, 0, 7).ok_or_else(|| invalid("bad day of week"))?;
+        // 7 is another name for Sunday
+        if days_of_week & (1 << 7) != 0 {
+            days_of_week = (days_of_week & !(1 << 7)) | 1;
+        }
+
+        Ok(Self {
+            expression: fields.join(" "),
+            minutes,
+            hours,
+            days_of_month,
+            months,
+            days_of_week,
+            any_day_of_month: day_of_month.starts_with('*'),
+            any_day_of_week: day_of_week.starts_with('*'),
+        })
+    }
+
+    /// Normalized expression (single spaces)
+    pub fn expression(&self) -> &str {
+        &self.expression
+    }
+
+    /// First run strictly after `after`, or `None` if the expression never matches
+    pub fn next_after(&self, after: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let offset = Duration::hours(NAIROBI_OFFSET_HOURS);
+        let local = (after + offset).naive_utc();
+        let mut t = local.date().and_hms_opt(local.hour(), local.minute(), 0)? + Duration::minutes(1);
+        let end = t + Duration::days(MAX_SEARCH_DAYS);
+
+        while t < end {
+            if !has(self.months, t.month()) {
+                t = first_of_next_month(t.date())?;
+            } else if !self.day_matches(t.date()) {
+                t = (t.date() + Duration::days(1)).and_hms_opt(0, 0, 0)?;
+            } else if !has(self.hours, t.hour()) {
+                t = t.date().and_hms_opt(t.hour(), 0, 0)? + Duration::hours(1);
+            } else if !has(self.minutes, t.minute()) {
+                t += Duration::minutes(1);
+            } else {
+                return Some(Utc.from_utc_datetime(&(t - offset)));
+            }
+        }
+
+        None
+    }
+
+    fn day_matches(&self, date: NaiveDate) -> bool {
+        let day_of_month = has(self.days_of_month, date.day());
+        let day_of_week = has(self.days_of_week, date.weekday().num_days_from_sunday());
+
+        match (self.any_day_of_month, self.any_day_of_week) {
+            (true, true) => true,
+            (false, true) => day_of_month,
+            (true, false) => day_of_week,
+            (false, false) => day_of_month || day_of_week,
+        }
+    }
+}
+
+fn has(set: u64, value: u32) -> bool {
+    set & (1 << value) != 0
+}
+
+fn first_of_next_month(date: NaiveDate) -> Option<NaiveDateTime> {
+    let (year, month) = match date.month() {
+        12 => (date.year() + 1, 1),
+        month => (date.year(), month + 1),
+    };
+    NaiveDate::from_ymd_opt(year, month, 1)?.and_hms_opt(0, 0, 0)
+}
+
+/// Bit set of the values a field allows, or `None` if it is malformed
+fn parse_field(field: &str, min: u32, max: u32) -> Option<u64> {
+    let mut set = 0u64;
+
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, step.parse::<u32>().ok().filter(|s| *s > 0)?),
+            None => (part, 1),
+        };
+
+        let (start, end) = match range {
+            "*" => (min, max),
+            _ => match range.split_once('-') {
+                Some((start, end)) => (start.parse().ok()?, end.parse().ok()?),
+                // `5/15` means from 5 to the end in steps of 15
+                None if part.contains('/') => (range.parse().ok()?, max),
+                None => {
+                    let value = range.parse().ok()?;
+                    (value, value)
+                }
+            },
+        };
+        if start < min || end > max || start > end {
+            return None;
+        }
+
+        for value in (start..=end).step_by(step as usize) {
+            set |= 1 << value;
+        }
+    }
+
+    Some(set)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utc(y: i32, m: u32, d: u32, h: u32, min: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(y, m, d, h, min, 0).unwrap()
+    }
+
+    #[test]
+    fn test_monthly_rent_in_nairobi_time() {
+        // 08:00 EAT on the 1st is 05:00 UTC
+        let schedule = CronSchedule::parse("0 8 1 * *").unwrap();
+        assert_eq!(schedule.next_after(utc(2026, 1, 15, 12, 0)), Some(utc(2026, 2, 1, 5, 0)));
+        assert_eq!(schedule.next_after(utc(2026, 2, 1, 5, 0)), Some(utc(2026, 3, 1, 5, 0)));
+        assert_eq!(schedule.next_after(utc(2026, 12, 20, 0, 0)), Some(utc(2027, 1, 1, 5, 0)));
+    }
+
+    #[test]
+    fn test_weekdays_and_steps() {
+        // Fridays at 18:30 EAT
+        let schedule = CronSchedule::parse("30 18 * * 5").unwrap();
+        // 2026-03-02 is a Monday
+        assert_eq!(schedule.next_after(utc(2026, 3, 2, 0, 0)), Some(utc(2026, 3, 6, 15, 30)));
+
+        // Every 6 hours, Sunday written as 7
+        let schedule = CronSchedule::parse("0 */6 * * 7").unwrap();
+        assert_eq!(schedule.next_after(utc(2026, 3, 7, 22, 0)), Some(utc(2026, 3, 8, 3, 0)));
+
+        // Either the 15th or a Monday when both day fields are set
+        let schedule = CronSchedule::parse("0 9 15 * 1").unwrap();
+        assert_eq!(schedule.next_after(utc(2026, 3, 10, 0, 0)), Some(utc(2026, 3, 15, 6, 0)));
+        assert_eq!(schedule.next_after(utc(2026, 3, 15, 6, 0)), Some(utc(2026, 3, 16, 6, 0)));
+    }
+
+    #[test]
+    fn test_invalid_expressions() {
+        for expression in ["", "0 9 * *", "* 9 * * *", "*/15 * * * *", "0 24 * * *", "0 9 0 * *", "0 9 * 13 *", "0 9 5-1 * *"] {
+            assert!(CronSchedule::parse(expression).is_err(), "{} should be rejected", expression);
+        }
+
+        // Valid but never happens
+        let schedule = CronSchedule::parse("0 9 31 2 *").unwrap();
+        assert_eq!(schedule.next_after(utc(2026, 1, 1, 0, 0)), None);
+    }
+}
@@ -0,0 +1,876 @@
+/// Recurring buys (dollar-cost averaging) from M-Pesa into sats
+///
+/// A DCA plan deposits a fixed KES amount on a cron schedule (Nairobi time). Each
+/// run sends an STK Push through the normal deposit flow, so the deposit is
+/// converted at the quote when it completes and counts towards the user's limits.
+/// Deposit outcomes arrive as domain events and are recorded against the run,
+/// giving each plan a purchase history and an average price. A plan pauses itself
+/// after `MAX_CONSECUTIVE_FAILURES` runs in a row are declined or can't start.
+
+use crate::cron::CronSchedule;
+use crate::domain::MpesaDepositRequest;
+use crate::limits::LimitsService;
+use crate::service::PaymentService;
+use chrono::{DateTime, Duration, Utc};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use shared_errors::{AppError, Result};
+use shared_events::{record_event, DomainEvent, EventEnvelope};
+use shared_types::*;
+use sqlx::{PgConnection, PgPool};
+use std::sync::Arc;
+use tracing::{info, instrument, warn};
+use uuid::Uuid;
+use validator::Validate;
+
+/// Failed runs in a row before a plan pauses itself
+const MAX_CONSECUTIVE_FAILURES: i32 = 3;
+/// How long a worker has to start a run
+const RUN_LEASE_MINUTES: i64 = 5;
+/// Runs shown with a plan
+const RECENT_RUNS: i64 = 30;
+const SATS_PER_BTC: i64 = 100_000_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[sqlx(type_name = "dca_plan_status", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum DcaPlanStatus {
+    Active,
+    Paused,
+    Cancelled,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[sqlx(type_name = "dca_run_status", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum DcaRunStatus {
+    Starting,
+    Initiated,
+    Completed,
+    Declined,
+    Failed,
+}
+
+/// Request to start a DCA plan
+#[derive(Debug, Deserialize, Validate)]
+pub struct CreateDcaPlanRequest {
+    /// KES deposited on every run
+    #[validate(range(min = 10, max = 500000))]
+    pub amount_kes: i32,
+    /// When to buy, e.g. "0 9 28 * *" for 09:00 on the 28th of every month
+    pub cron: String,
+}
+
+/// Changes to a plan; fields left out stay as they are
+#[derive(Debug, Deserialize, Validate)]
+pub struct UpdateDcaPlanRequest {
+    #[validate(range(min = 10, max = 500000))]
+    pub amount_kes: Option<i32>,
+    pub cron: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DcaPlan {
+    pub id: Uuid,
+    pub user_id: UserId,
+    pub amount_kes: i32,
+    pub cron: String,
+    pub status: DcaPlanStatus,
+    /// Set when the plan paused itself
+    pub pause_reason: Option<String>,
+    pub next_run_at: Option<DateTime<Utc>>,
+    pub consecutive_failures: i32,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// One scheduled purchase
+#[derive(Debug, Clone, Serialize)]
+pub struct DcaRun {
+    pub id: Uuid,
+    pub scheduled_for: DateTime<Utc>,
+    pub status: DcaRunStatus,
+    pub transaction_id: Option<Uuid>,
+    pub amount_kes: i32,
+    pub amount_sats: Option<i64>,
+    /// Effective price paid (KES per BTC, fees included)
+    pub price_kes_per_btc: Option<Decimal>,
+    pub error: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
+}
+
+/// Totals over a plan's completed purchases
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DcaSummary {
+    pub purchases: i64,
+    pub total_kes: Decimal,
+    pub total_sats: i64,
+    /// Total KES spent over total BTC bought
+    pub average_price_kes_per_btc: Option<Decimal>,
+}
+
+/// Plan with its purchase history
+#[derive(Debug, Serialize)]
+pub struct DcaPlanDetails {
+    #[serde(flatten)]
+    pub plan: DcaPlan,
+    pub summary: DcaSummary,
+    pub recent_runs: Vec<DcaRun>,
+}
+
+/// KES per BTC paid for `sats` (rounded to cents)
+pub fn price_per_btc(kes: Decimal, sats: i64) -> Option<Decimal> {
+    if sats <= 0 {
+        return None;
+    }
+    Some((kes * Decimal::from(SATS_PER_BTC) / Decimal::from(sats)).round_dp(2))
+}
+
+/// Whether a plan should pause itself after a failed run
+pub fn should_pause(status: DcaPlanStatus, consecutive_failures: i32) -> bool {
+    status == DcaPlanStatus::Active && consecutive_failures >= MAX_CONSECUTIVE_FAILURES
+}
+
+fn parse_cron(cron: &str, now: DateTime<Utc>) -> Result<(CronSchedule, DateTime<Utc>)> {
+    let schedule = CronSchedule::parse(cron)?;
+    let next_run = schedule.next_after(now).ok_or_else(|| AppError::Validation {
+        message: "Schedule never runs".to_string(),
+    })?;
+    Ok((schedule, next_run))
+}
+
+/// Database access for DCA plans
+pub struct DcaRepository {
+    pool: PgPool,
+}
+
+impl DcaRepository {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+
+    #[instrument(skip(self))]
+    pub async fn create(&self, user_id: UserId, amount_kes: i32, cron: &str, next_run: DateTime<Utc>) -> Result<Uuid> {
+        let row = sqlx::query!(
+            r#"
+            INSERT INTO dca_plans (user_id, amount_kes, cron, next_run_at)
+            VALUES ($1, $2, $3, $4)
+            RETURNING id
+            "#,
+            user_id.0,
+            amount_kes,
+            cron,
+            next_run,
+        )
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(row.id)
+    }
+
+    #[instrument(skip(self))]
+    pub async fn get(&self, user_id: UserId, id: Uuid) -> Result<Option<DcaPlan>> {
+        let row = sqlx::query!(
+            r#"
+            SELECT id, user_id, amount_kes, cron, status as "status: DcaPlanStatus", pause_reason,
+                   next_run_at, consecutive_failures, created_at, updated_at
+            FROM dca_plans
+            WHERE id = $1 AND user_id = $2
+            "#,
+            id,
+            user_id.0,
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.map(|r| DcaPlan {
+            id: r.id,
+            user_id: UserId(r.user_id),
+            amount_kes: r.amount_kes,
+            cron: r.cron,
+            status: r.status,
+            pause_reason: r.pause_reason,
+            next_run_at: r.next_run_at,
+            consecutive_failures: r.consecutive_failures,
+            created_at: r.created_at,
+            updated_at: r.updated_at,
+        }))
+    }
+
+    #[instrument(skip(self))]
+    pub async fn list(&self, user_id: UserId) -> Result<Vec<DcaPlan>> {
+        let rows = sqlx::query!(
+            r#"
+            SELECT id, user_id, amount_kes, cron, status as "status: DcaPlanStatus", pause_reason,
+                   next_run_at, consecutive_failures, created_at, updated_at
+            FROM dca_plans
+            WHERE user_id = $1
+            ORDER BY status = 'cancelled', created_at DESC
+            "#,
+            user_id.0,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|r| DcaPlan {
+                id: r.id,
+                user_id: UserId(r.user_id),
+                amount_kes: r.amount_kes,
+                cron: r.cron,
+                status: r.status,
+                pause_reason: r.pause_reason,
+                next_run_at: r.next_run_at,
+                consecutive_failures: r.consecutive_failures,
+                created_at: r.created_at,
+                updated_at: r.updated_at,
+            })
+            .collect())
+    }
+
+    /// Change the amount or schedule of a plan that isn't starting a run
+    #[instrument(skip(self))]
+    pub async fn update(
+        &self,
+        user_id: UserId,
+        id: Uuid,
+        amount_kes: i32,
+        cron: &str,
+        next_run: DateTime<Utc>,
+    ) -> Result<bool> {
+        let updated = sqlx::query!(
+            r#"
+            UPDATE dca_plans SET amount_kes = $3, cron = $4, next_run_at = $5
+            WHERE id = $1 AND user_id = $2 AND status != 'cancelled'
+              AND (lease_until IS NULL OR lease_until < NOW())
+            "#,
+            id,
+            user_id.0,
+            amount_kes,
+            cron,
+            next_run,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(updated.rows_affected() == 1)
+    }
+
+    #[instrument(skip(self))]
+    pub async fn pause(&self, user_id: UserId, id: Uuid) -> Result<bool> {
+        let updated = sqlx::query!(
+            r#"
+            UPDATE dca_plans SET status = 'paused'
+            WHERE id = $1 AND user_id = $2 AND status = 'active'
+              AND (lease_until IS NULL OR lease_until < NOW())
+            "#,
+            id,
+            user_id.0,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(updated.rows_affected() == 1)
+    }
+
+    /// Resume a paused plan with a clean failure count
+    #[instrument(skip(self))]
+    pub async fn resume(&self, user_id: UserId, id: Uuid, next_run: DateTime<Utc>) -> Result<bool> {
+        let updated = sqlx::query!(
+            r#"
+            UPDATE dca_plans
+            SET status = 'active', next_run_at = $3, consecutive_failures = 0, pause_reason = NULL
+            WHERE id = $1 AND user_id = $2 AND status = 'paused'
+            "#,
+            id,
+            user_id.0,
+            next_run,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(updated.rows_affected() == 1)
+    }
+
+    #[instrument(skip(self))]
+    pub async fn cancel(&self, user_id: UserId, id: Uuid) -> Result<bool> {
+        let updated = sqlx::query!(
+            r#"
+            UPDATE dca_plans SET status = 'cancelled', next_run_at = NULL
+            WHERE id = $1 AND user_id = $2 AND status != 'cancelled'
+              AND (lease_until IS NULL OR lease_until < NOW())
+            "#,
+            id,
+            user_id.0,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(updated.rows_affected() == 1)
+    }
+
+    /// Lease active plans that are due
+    #[instrument(skip(self))]
+    pub async fn claim_due(&self, limit: i64, lease_until: DateTime<Utc>) -> Result<Vec<DcaPlan>> {
+        let rows = sqlx::query!(
+            r#"
+            UPDATE dca_plans
+            SET lease_until = $2
+            WHERE id IN (
+                SELECT id FROM dca_plans
+                WHERE status = 'active' AND next_run_at <= NOW()
+                  AND (lease_until IS NULL OR lease_until < NOW())
+                ORDER BY next_run_at
+                LIMIT $1
+                FOR UPDATE SKIP LOCKED
+            )
+            RETURNING id, user_id, amount_kes, cron, status as "status: DcaPlanStatus", pause_reason,
+                      next_run_at, consecutive_failures, created_at, updated_at
+            "#,
+            limit,
+            lease_until,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|r| DcaPlan {
+                id: r.id,
+                user_id: UserId(r.user_id),
+                amount_kes: r.amount_kes,
+                cron: r.cron,
+                status: r.status,
+                pause_reason: r.pause_reason,
+                next_run_at: r.next_run_at,
+                consecutive_failures: r.consecutive_failures,
+                created_at: r.created_at,
+                updated_at: r.updated_at,
+            })
+            .collect())
+    }
+
+    /// Record a run before sending the STK Push
+    /// Returns `None` if the occurrence already has a run
+    #[instrument(skip(self))]
+    pub async fn start_run(&self, plan_id: Uuid, scheduled_for: DateTime<Utc>, amount_kes: i32) -> Result<Option<Uuid>> {
+        let row = sqlx::query!(
+            r#"
+            INSERT INTO dca_plan_runs (dca_plan_id, scheduled_for, amount_kes)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (dca_plan_id, scheduled_for) DO NOTHING
+            RETURNING id
+            "#,
+            plan_id,
+            scheduled_for,
+            amount_kes,
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.map(|r| r.id))
+    }
+
+    /// Skip an occurrence whose run was already started; a run left in
+    /// `starting` by a stopped worker may have sent its STK Push, so it isn't repeated
+    #[instrument(skip(self))]
+    pub async fn skip_started(&self, plan_id: Uuid, scheduled_for: DateTime<Utc>, next_run: Option<DateTime<Utc>>) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query!(
+            r#"
+            UPDATE dca_plan_runs
+            SET status = 'failed', error = 'Stopped before the deposit was confirmed', finished_at = NOW()
+            WHERE dca_plan_id = $1 AND scheduled_for = $2 AND status = 'starting'
+            "#,
+            plan_id,
+            scheduled_for,
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        Self::advance(&mut *tx, plan_id, next_run).await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// STK Push sent: wait for the deposit outcome and move on to the next occurrence
+    #[instrument(skip(self))]
+    pub async fn mark_initiated(
+        &self,
+        plan_id: Uuid,
+        run_id: Uuid,
+        transaction_id: Uuid,
+        next_run: Option<DateTime<Utc>>,
+    ) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query!(
+            "UPDATE dca_plan_runs SET status = 'initiated', transaction_id = $2 WHERE id = $1",
+            run_id,
+            transaction_id,
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        Self::advance(&mut *tx, plan_id, next_run).await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// STK Push could not be sent
+    #[instrument(skip(self))]
+    pub async fn mark_start_failed(
+        &self,
+        plan_id: Uuid,
+        run_id: Uuid,
+        error: &str,
+        next_run: Option<DateTime<Utc>>,
+    ) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query!(
+            "UPDATE dca_plan_runs SET status = 'failed', error = $2, finished_at = NOW() WHERE id = $1",
+            run_id,
+            error,
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        Self::advance(&mut *tx, plan_id, next_run).await?;
+        Self::count_failure(&mut *tx, plan_id, error).await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Record a completed deposit against its run; returns the plan if the deposit was a DCA run
+    #[instrument(skip(self))]
+    pub async fn deposit_completed(&self, transaction_id: Uuid, amount_sats: Option<i64>) -> Result<Option<Uuid>> {
+        let mut tx = self.pool.begin().await?;
+
+        let run = sqlx::query!(
+            r#"
+            UPDATE dca_plan_runs SET status = 'completed', amount_sats = $2, finished_at = NOW()
+            WHERE transaction_id = $1 AND status = 'initiated'
+            RETURNING dca_plan_id
+            "#,
+            transaction_id,
+            amount_sats,
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        let Some(run) = run else {
+            return Ok(None);
+        };
+
+        sqlx::query!(
+            "UPDATE dca_plans SET consecutive_failures = 0 WHERE id = $1",
+            run.dca_plan_id,
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+        Ok(Some(run.dca_plan_id))
+    }
+
+    /// Record a declined deposit against its run; returns the plan if the deposit was a DCA run
+    #[instrument(skip(self))]
+    pub async fn deposit_failed(&self, transaction_id: Uuid, reason: &str) -> Result<Option<Uuid>> {
+        let mut tx = self.pool.begin().await?;
+
+        let run = sqlx::query!(
+            r#"
+            UPDATE dca_plan_runs SET status = 'declined', error = $2, finished_at = NOW()
+            WHERE transaction_id = $1 AND status = 'initiated'
+            RETURNING dca_plan_id
+            "#,
+            transaction_id,
+            reason,
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        let Some(run) = run else {
+            return Ok(None);
+        };
+
+        Self::count_failure(&mut *tx, run.dca_plan_id, reason).await?;
+        tx.commit().await?;
+        Ok(Some(run.dca_plan_id))
+    }
+
+    #[instrument(skip(self))]
+    pub async fn runs(&self, plan_id: Uuid, limit: i64) -> Result<Vec<DcaRun>> {
+        let rows = sqlx::query!(
+            r#"
+            SELECT id, scheduled_for, status as "status: DcaRunStatus", transaction_id, amount_kes,
+                   amount_sats, error, created_at, finished_at
+            FROM dca_plan_runs
+            WHERE dca_plan_id = $1
+            ORDER BY scheduled_for DESC
+            LIMIT $2
+            "#,
+            plan_id,
+            limit,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|r| DcaRun {
+                id: r.id,
+                scheduled_for: r.scheduled_for,
+                status: r.status,
+                transaction_id: r.transaction_id,
+                amount_kes: r.amount_kes,
+                amount_sats: r.amount_sats,
+                price_kes_per_btc: r
+                    .amount_sats
+                    .and_then(|sats| price_per_btc(Decimal::from(r.amount_kes), sats)),
+                error: r.error,
+                created_at: r.created_at,
+                finished_at: r.finished_at,
+            })
+            .collect())
+    }
+
+    #[instrument(skip(self))]
+    pub async fn summary(&self, plan_id: Uuid) -> Result<DcaSummary> {
+        let row = sqlx::query!(
+            r#"
+            SELECT COUNT(*) AS "purchases!",
+                   COALESCE(SUM(amount_kes), 0) AS "total_kes!",
+                   COALESCE(SUM(amount_sats), 0)::BIGINT AS "total_sats!"
+            FROM dca_plan_runs
+            WHERE dca_plan_id = $1 AND status = 'completed'
+            "#,
+            plan_id,
+        )
+        .fetch_one(&self.pool)
+        .await?;
+
+        let total_kes = Decimal::from(row.total_kes);
+        Ok(DcaSummary {
+            purchases: row.purchases,
+            total_kes,
+            total_sats: row.total_sats,
+            average_price_kes_per_btc: price_per_btc(total_kes, row.total_sats),
+        })
+    }
+
+    /// Clear the lease and schedule the next occurrence (none if the schedule has ended)
+    async fn advance(conn: &mut PgConnection, plan_id: Uuid, next_run: Option<DateTime<Utc>>) -> Result<()> {
+        sqlx::query!(
+            "UPDATE dca_plans SET next_run_at = $2, lease_until = NULL WHERE id = $1",
+            plan_id,
+            next_run,
+        )
+        .execute(conn)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Count a failed run and pause the plan (notifying the user) once too many fail in a row
+    async fn count_failure(conn: &mut PgConnection, plan_id: Uuid, reason: &str) -> Result<()> {
+        let plan = sqlx::query!(
+            r#"
+            UPDATE dca_plans SET consecutive_failures = consecutive_failures + 1
+            WHERE id = $1
+            RETURNING user_id, amount_kes, status as "status: DcaPlanStatus", consecutive_failures
+            "#,
+            plan_id,
+        )
+        .fetch_one(&mut *conn)
+        .await?;
+
+        if !should_pause(plan.status, plan.consecutive_failures) {
+            return Ok(());
+        }
+
+        sqlx::query!(
+            "UPDATE dca_plans SET status = 'paused', pause_reason = $2 WHERE id = $1",
+            plan_id,
+            reason,
+        )
+        .execute(&mut *conn)
+        .await?;
+
+        record_event(
+            &mut *conn,
+            &DomainEvent::DcaPlanPaused {
+                dca_plan_id: plan_id,
+                user_id: UserId(plan.user_id),
+                amount_kes: KesAmount::new(Decimal::from(plan.amount_kes)),
+                failures: plan.consecutive_failures,
+                reason: reason.to_string(),
+            },
+        )
+        .await?;
+
+        warn!("Paused DCA plan {} after {} failed runs", plan_id, plan.consecutive_failures);
+        Ok(())
+    }
+}
+
+/// Managing and running DCA plans
+pub struct DcaService {
+    repository: Arc<DcaRepository>,
+    payment_service: Arc<PaymentService>,
+    limits_service: Arc<LimitsService>,
+}
+
+impl DcaService {
+    pub fn new(
+        repository: Arc<DcaRepository>,
+        payment_service: Arc<PaymentService>,
+        limits_service: Arc<LimitsService>,
+    ) -> Self {
+        Self {
+            repository,
+            payment_service,
+            limits_service,
+        }
+    }
+
+    #[instrument(skip(self, request))]
+    pub async fn create(&self, user_id: UserId, request: CreateDcaPlanRequest) -> Result<DcaPlan> {
+        request.validate().map_err(|e| AppError::Validation {
+            message: format!("Invalid DCA plan: {}", e),
+        })?;
+
+        let (schedule, next_run) = parse_cron(&request.cron, Utc::now())?;
+        let id = self
+            .repository
+            .create(user_id, request.amount_kes, schedule.expression(), next_run)
+            .await?;
+
+        info!("User {} started DCA plan {} for KES {}", user_id, id, request.amount_kes);
+        self.find(user_id, id).await
+    }
+
+    #[instrument(skip(self))]
+    pub async fn get(&self, user_id: UserId, id: Uuid) -> Result<DcaPlanDetails> {
+        let plan = self.find(user_id, id).await?;
+        let summary = self.repository.summary(id).await?;
+        let recent_runs = self.repository.runs(id, RECENT_RUNS).await?;
+        Ok(DcaPlanDetails {
+            plan,
+            summary,
+            recent_runs,
+        })
+    }
+
+    #[instrument(skip(self))]
+    pub async fn list(&self, user_id: UserId) -> Result<Vec<DcaPlan>> {
+        self.repository.list(user_id).await
+    }
+
+    #[instrument(skip(self, request))]
+    pub async fn update(&self, user_id: UserId, id: Uuid, request: UpdateDcaPlanRequest) -> Result<DcaPlan> {
+        request.validate().map_err(|e| AppError::Validation {
+            message: format!("Invalid DCA plan: {}", e),
+        })?;
+
+        let plan = self.find(user_id, id).await?;
+        let amount_kes = request.amount_kes.unwrap_or(plan.amount_kes);
+        let (schedule, next_run) = parse_cron(request.cron.as_deref().unwrap_or(&plan.cron), Utc::now())?;
+
+        if !self
+            .repository
+            .update(user_id, id, amount_kes, schedule.expression(), next_run)
+            .await?
+        {
+            return Err(Self::not_changeable(&plan));
+        }
+        self.find(user_id, id).await
+    }
+
+    #[instrument(skip(self))]
+    pub async fn pause(&self, user_id: UserId, id: Uuid) -> Result<DcaPlan> {
+        let plan = self.find(user_id, id).await?;
+        if !self.repository.pause(user_id, id).await? {
+            return Err(Self::not_changeable(&plan));
+        }
+        self.find(user_id, id).await
+    }
+
+    #[instrument(skip(self))]
+    pub async fn resume(&self, user_id: UserId, id: Uuid) -> Result<DcaPlan> {
+        let plan = self.find(user_id, id).await?;
+        let (_, next_run) = parse_cron(&plan.cron, Utc::now())?;
+        if !self.repository.resume(user_id, id, next_run).await? {
+            return Err(AppError::Conflict {
+                message: "Only paused plans can be resumed".to_string(),
+            });
+        }
+        self.find(user_id, id).await
+    }
+
+    #[instrument(skip(self))]
+    pub async fn cancel(&self, user_id: UserId, id: Uuid) -> Result<DcaPlan> {
+        let plan = self.find(user_id, id).await?;
+        if !self.repository.cancel(user_id, id).await? {
+            return Err(Self::not_changeable(&plan));
+        }
+        self.find(user_id, id).await
+    }
+
+    /// Start runs that are due; returns how many plans were due
+    #[instrument(skip(self))]
+    pub async fn run_due(&self, limit: i64) -> Result<usize> {
+        let lease_until = Utc::now() + Duration::minutes(RUN_LEASE_MINUTES);
+        let plans = self.repository.claim_due(limit, lease_until).await?;
+
+        for plan in &plans {
+            if let Err(e) = self.run(plan).await {
+                warn!("Failed to run DCA plan {}: {}", plan.id, e);
+            }
+        }
+
+        Ok(plans.len())
+    }
+
+    /// Record deposit outcomes for DCA runs
+    pub async fn handle_event(&self, envelope: &EventEnvelope) -> Result<()> {
+        let plan_id = match &envelope.event {
+            DomainEvent::DepositCompleted {
+                transaction_id,
+                amount_sats,
+                ..
+            } => {
+                self.repository
+                    .deposit_completed(*transaction_id, amount_sats.map(|sats| sats.as_i64()))
+                    .await?
+            }
+            DomainEvent::DepositFailed {
+                transaction_id, reason, ..
+            } => {
+                let reason = reason.as_deref().unwrap_or("The M-Pesa payment was not completed");
+                self.repository.deposit_failed(*transaction_id, reason).await?
+            }
+            _ => None,
+        };
+
+        if let Some(plan_id) = plan_id {
+            info!("Recorded deposit outcome for DCA plan {}", plan_id);
+        }
+        Ok(())
+    }
+
+    /// Send the STK Push for a leased plan's current occurrence
+    async fn run(&self, plan: &DcaPlan) -> Result<()> {
+        let now = Utc::now();
+        let scheduled_for = plan
+            .next_run_at
+            .ok_or_else(|| AppError::Internal(anyhow::anyhow!("DCA plan {} has no run time", plan.id)))?;
+        let next_run = CronSchedule::parse(&plan.cron)?.next_after(scheduled_for.max(now));
+
+        let Some(run_id) = self.repository.start_run(plan.id, scheduled_for, plan.amount_kes).await? else {
+            warn!("DCA plan {} already started its {} run, skipping", plan.id, scheduled_for);
+            return self.repository.skip_started(plan.id, scheduled_for, next_run).await;
+        };
+
+        match self.deposit(plan).await {
+            Ok(transaction_id) => {
+                info!("DCA plan {} sent STK Push for transaction {}", plan.id, transaction_id);
+                self.repository
+                    .mark_initiated(plan.id, run_id, transaction_id, next_run)
+                    .await
+            }
+            Err(e) => {
+                warn!("DCA plan {} could not start its run: {}", plan.id, e);
+                self.repository
+                    .mark_start_failed(plan.id, run_id, &e.user_message(), next_run)
+                    .await
+            }
+        }
+    }
+
+    async fn deposit(&self, plan: &DcaPlan) -> Result<Uuid> {
+        let request = MpesaDepositRequest {
+            amount_kes: plan.amount_kes,
+        };
+        request.validate().map_err(|e| AppError::Validation {
+            message: format!("Invalid deposit: {}", e),
+        })?;
+
+        let response = self
+            .limits_service
+            .within_kes(plan.user_id, Decimal::from(plan.amount_kes), || {
+                self.payment_service.initiate_mpesa_deposit(plan.user_id, request)
+            })
+            .await?;
+
+        response
+            .transaction_id
+            .parse()
+            .map_err(|_| AppError::Internal(anyhow::anyhow!("Invalid deposit transaction ID {}", response.transaction_id)))
+    }
+
+    async fn find(&self, user_id: UserId, id: Uuid) -> Result<DcaPlan> {
+        self.repository
+            .get(user_id, id)
+            .await?
+            .ok_or_else(|| AppError::Payment {
+                message: "DCA plan not found".to_string(),
+            })
+    }
+
+    fn not_changeable(plan: &DcaPlan) -> AppError {
+        let message = match plan.status {
+            DcaPlanStatus::Cancelled => "Cancelled plans can't be changed",
+            _ => "The plan is starting a purchase right now, try again in a few minutes",
+        };
+        AppError::Conflict {
+            message: message.to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_average_price() {
+        // KES 1,000 for 1,500 sats is KES 66,666,666.67 per BTC
+        assert_eq!(
+            price_per_btc(Decimal::from(1000), 1500),
+            Some(Decimal::new(6_666_666_667, 2))
+        );
+        // Two purchases at different prices average by amount bought, not per purchase
+        let total_kes = Decimal::from(1000 + 1000);
+        assert_eq!(price_per_btc(total_kes, 1000 + 2000), Some(Decimal::new(6_666_666_667, 2)));
+        assert_eq!(price_per_btc(Decimal::from(1000), 0), None);
+    }
+
+    #[test]
+    fn test_pauses_after_repeated_failures() {
+        assert!(!should_pause(DcaPlanStatus::Active, MAX_CONSECUTIVE_FAILURES - 1));
+        assert!(should_pause(DcaPlanStatus::Active, MAX_CONSECUTIVE_FAILURES));
+        // A plan the user already paused or cancelled keeps its status
+        assert!(!should_pause(DcaPlanStatus::Paused, MAX_CONSECUTIVE_FAILURES + 1));
+        assert!(!should_pause(DcaPlanStatus::Cancelled, MAX_CONSECUTIVE_FAILURES));
+    }
+
+    #[test]
+    fn test_plan_validation() {
+        let request = CreateDcaPlanRequest {
+            amount_kes: 5,
+            cron: "0 9 28 * *".to_string(),
+        };
+        assert!(request.validate().is_err());
+
+        let now = Utc::now();
+        let (schedule, next_run) = parse_cron("0  9 28 * *", now).unwrap();
+        assert_eq!(schedule.expression(), "0 9 28 * *");
+        assert!(next_run > now);
+        assert!(parse_cron("0 9 30 2 *", now).is_err());
+    }
+}
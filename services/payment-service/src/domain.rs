@@ -3,8 +3,11 @@
 /// This module defines the core payment operations, business rules, and validation
 /// for M-Pesa deposits/withdrawals and Lightning Network transactions.
 
+use crate::fees::FeeSchedule;
 use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
+use shared_errors::{AppError, Result};
+use shared_types::conversion::{Rounding, Side};
 use shared_types::*;
 use validator::Validate;
 
@@ -23,8 +26,10 @@ pub struct MpesaDepositResponse {
     pub checkout_request_id: String,
     pub amount_kes: KesAmount,
     pub estimated_sats: SatAmount,
-    pub exchange_rate: Decimal,
+    pub exchange_rate: BtcKesRate,
     pub fee_kes: KesAmount,
+    /// Version of the fee schedule used to price this deposit
+    pub fee_schedule_version: i32,
     pub message: String,
 }
 
@@ -44,9 +49,11 @@ pub struct MpesaWithdrawalResponse {
     pub transaction_id: String,
     pub amount_sats: SatAmount,
     pub amount_kes: KesAmount,
-    pub exchange_rate: Decimal,
+    pub exchange_rate: BtcKesRate,
     pub fee_kes: KesAmount,
     pub fee_sats: SatAmount,
+    /// Version of the fee schedule used to price this withdrawal
+    pub fee_schedule_version: i32,
     pub recipient_phone: PhoneNumber,
     pub estimated_completion: chrono::DateTime<chrono::Utc>,
 }
@@ -110,19 +117,32 @@ pub struct WalletBalance {
     pub pending_mpesa_kes: KesAmount,
     /// Unconfirmed Lightning payments (waiting for confirmation)
     pub pending_lightning_sats: SatAmount,
-    /// Current exchange rate used for conversions
-    pub exchange_rate: Decimal,
+    /// Sats held in savings vaults (not included in `balance_sats`)
+    pub vaulted_sats: SatAmount,
+    /// Spendable plus vaulted sats
+    pub total_sats: SatAmount,
+    /// Current exchange rate used for conversions (KES per BTC)
+    pub exchange_rate: BtcKesRate,
     /// Last update timestamp
     pub updated_at: chrono::DateTime<chrono::Utc>,
 }
 
+impl WalletBalance {
+    /// Add the user's vault totals to the balance
+    pub fn with_vaults(mut self, vaulted_sats: SatAmount) -> Self {
+        self.vaulted_sats = vaulted_sats;
+        self.total_sats = SatAmount::new(self.balance_sats.as_i64() + vaulted_sats.as_i64());
+        self
+    }
+}
+
 /// Transaction history query parameters
 #[derive(Debug, Deserialize)]
 pub struct TransactionHistoryParams {
     /// Number of transactions to return (default: 20, max: 100)
-    pub limit: Option<i32>,
-    /// Offset for pagination (default: 0)
-    pub offset: Option<i32>,
+    pub limit: Option<i64>,
+    /// `next_cursor` or `prev_cursor` from a previous page (omit for the newest page)
+    pub cursor: Option<String>,
     /// Filter by transaction type
     pub transaction_type: Option<TransactionType>,
     /// Filter by status
@@ -131,27 +151,44 @@ pub struct TransactionHistoryParams {
     pub from_date: Option<chrono::DateTime<chrono::Utc>>,
     /// Filter transactions before this date
     pub to_date: Option<chrono::DateTime<chrono::Utc>>,
+    /// Amount range in KES (inclusive)
+    pub min_amount_kes: Option<Decimal>,
+    pub max_amount_kes: Option<Decimal>,
+    /// Amount range in sats (inclusive)
+    pub min_amount_sats: Option<i64>,
+    pub max_amount_sats: Option<i64>,
+    /// Other party: phone number, Lightning address or username (exact match)
+    pub counterparty: Option<String>,
+    /// Text to look for in the memo (case-insensitive)
+    pub memo: Option<String>,
 }
 
 /// Transaction history response
+/// Transactions are newest first; `next_cursor` pages to older ones and
+/// `prev_cursor` back to newer ones
 #[derive(Debug, Serialize)]
 pub struct TransactionHistoryResponse {
     pub transactions: Vec<TransactionSummary>,
-    pub total_count: i64,
+    pub next_cursor: Option<String>,
+    pub prev_cursor: Option<String>,
     pub has_more: bool,
 }
 
 /// Simplified transaction summary for history lists
 #[derive(Debug, Serialize)]
 pub struct TransactionSummary {
-    pub id: String,
+    pub id: uuid::Uuid,
     pub transaction_type: TransactionType,
     pub status: TransactionStatus,
+    /// Mobile money network, for deposits and withdrawals
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub provider: Option<MobileMoneyNetwork>,
     pub amount_kes: Option<KesAmount>,
     pub amount_sats: Option<SatAmount>,
     pub fee_kes: Option<KesAmount>,
     pub fee_sats: Option<SatAmount>,
     pub description: Option<String>,
+    pub counterparty: Option<String>,
     pub created_at: chrono::DateTime<chrono::Utc>,
     pub completed_at: Option<chrono::DateTime<chrono::Utc>>,
 }
@@ -198,76 +235,105 @@ pub struct CallbackItem {
 
 /// Business rules and validation
 impl MpesaDepositRequest {
-    /// Calculate fees for M-Pesa deposit (1% fee)
-    pub fn calculate_fee(&self) -> KesAmount {
-        let fee = Decimal::from(self.amount_kes) * Decimal::new(1, 2); // 1%
-        KesAmount::new(fee.max(Decimal::new(10, 0))) // Minimum 10 KES fee
+    /// Calculate fees for M-Pesa deposit using the active deposit fee schedule
+    pub fn calculate_fee(&self, schedule: &FeeSchedule) -> KesAmount {
+        schedule.calculate(Decimal::from(self.amount_kes)).total_kes
     }
 
     /// Calculate net amount after fees
-    pub fn net_amount(&self) -> KesAmount {
+    pub fn net_amount(&self, schedule: &FeeSchedule) -> KesAmount {
         let amount = Decimal::from(self.amount_kes);
-        let fee = self.calculate_fee().0;
+        let fee = self.calculate_fee(schedule).as_decimal();
         KesAmount::new(amount - fee)
     }
+
+    /// Sats credited for the net deposit (rounded down to the whole sat)
+    pub fn estimated_sats(&self, schedule: &FeeSchedule, rate: BtcKesRate) -> Result<SatAmount> {
+        Ok(rate.kes_to_sats(&self.net_amount(schedule), Side::Payout, Rounding::HouseFavourable)?)
+    }
 }
 
 impl MpesaWithdrawalRequest {
-    /// Calculate fees for M-Pesa withdrawal
-    pub fn calculate_fees(&self, exchange_rate: Decimal) -> (KesAmount, SatAmount) {
-        // Convert sats to KES
-        let kes_amount = self.sats_to_kes(exchange_rate);
-        
-        // Our fee: 1% of KES amount
-        let our_fee_kes = kes_amount.0 * Decimal::new(1, 2); // 1%
+    /// Calculate fees for M-Pesa withdrawal using the active withdrawal fee schedule
+    /// (our service fee plus Safaricom's tiered tariff)
+    pub fn calculate_fees(&self, schedule: &FeeSchedule, rate: BtcKesRate) -> Result<(KesAmount, SatAmount)> {
+        // KES the user receives for their sats
+        let kes_amount = self.kes_payout(rate)?;
         
-        // M-Pesa fees (tiered based on amount)
-        let mpesa_fee_kes = Self::calculate_mpesa_fee(kes_amount.0);
-        
-        let total_fee_kes = KesAmount::new(our_fee_kes + mpesa_fee_kes);
-        let fee_sats = SatAmount::new(self.kes_to_sats(total_fee_kes.0, exchange_rate));
+        let total_fee_kes = schedule.calculate(kes_amount.as_decimal()).total_kes;
+
+        // Sats needed to cover the fee (rounded up so fees are never under-collected)
+        let fee_sats = rate.kes_to_sats(&total_fee_kes, Side::Charge, Rounding::HouseFavourable)?;
         
-        (total_fee_kes, fee_sats)
+        Ok((total_fee_kes, fee_sats))
     }
 
-    /// Convert satoshis to KES at given exchange rate
-    fn sats_to_kes(&self, btc_kes_rate: Decimal) -> KesAmount {
-        let btc_amount = Decimal::from(self.amount_sats) / Decimal::new(100_000_000, 0); // sats to BTC
-        KesAmount::new(btc_amount * btc_kes_rate)
+    /// KES sent to M-Pesa for the withdrawn sats (rounded down to the cent)
+    pub fn kes_payout(&self, rate: BtcKesRate) -> Result<KesAmount> {
+        Ok(rate.sats_to_kes(SatAmount::new(self.amount_sats), Side::Payout, Rounding::HouseFavourable)?)
     }
+}
 
-    /// Convert KES to satoshis at given exchange rate
-    fn kes_to_sats(&self, kes_amount: Decimal, btc_kes_rate: Decimal) -> i64 {
-        let btc_amount = kes_amount / btc_kes_rate;
-        (btc_amount * Decimal::new(100_000_000, 0)).to_i64().unwrap_or(0)
+impl CreateInvoiceRequest {
+    /// Get expiry duration (default: 1 hour)
+    pub fn expiry_duration(&self) -> chrono::Duration {
+        chrono::Duration::seconds(self.expiry_seconds.unwrap_or(3600) as i64)
     }
+}
+
+/// Legal change of a transaction's status
+///
+/// Transactions move `pending → processing → completed | failed`, and a failed
+/// transaction can be `refunded`. The database enforces the same rules with a
+/// trigger, so this only exists to fail early with a clear error.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StatusTransition {
+    from: TransactionStatus,
+    to: TransactionStatus,
+}
+
+impl StatusTransition {
+    pub fn new(from: TransactionStatus, to: TransactionStatus) -> Result<Self> {
+        use TransactionStatus::*;
 
-    /// Calculate M-Pesa withdrawal fees (Safaricom's tiered structure)
-    fn calculate_mpesa_fee(amount: Decimal) -> Decimal {
-        match amount.to_i64().unwrap_or(0) {
-            1..=49 => Decimal::new(1, 0),           // 1 KES
-            50..=100 => Decimal::new(5, 0),         // 5 KES  
-            101..=500 => Decimal::new(7, 0),        // 7 KES
-            501..=1000 => Decimal::new(13, 0),      // 13 KES
-            1001..=1500 => Decimal::new(20, 0),     // 20 KES
-            1501..=2500 => Decimal::new(25, 0),     // 25 KES
-            2501..=3500 => Decimal::new(30, 0),     // 30 KES
-            3501..=5000 => Decimal::new(35, 0),     // 35 KES
-            5001..=7500 => Decimal::new(45, 0),     // 45 KES
-            7501..=10000 => Decimal::new(55, 0),    // 55 KES
-            10001..=15000 => Decimal::new(60, 0),   // 60 KES
-            15001..=20000 => Decimal::new(65, 0),   // 65 KES
-            20001..=25000 => Decimal::new(70, 0),   // 70 KES
-            25001..=30000 => Decimal::new(75, 0),   // 75 KES
-            _ => Decimal::new(105, 0),              // 105 KES for >30k
+        match (&from, &to) {
+            (Pending, Processing) | (Processing, Completed) | (Processing, Failed) | (Failed, Refunded) => {
+                Ok(Self { from, to })
+            }
+            _ => Err(AppError::Conflict {
+                message: format!(
+                    "Transaction cannot move from {} to {}",
+                    from.as_str(),
+                    to.as_str()
+                ),
+            }),
         }
     }
+
+    pub fn from(&self) -> &TransactionStatus {
+        &self.from
+    }
+
+    pub fn to(&self) -> &TransactionStatus {
+        &self.to
+    }
 }
 
-impl CreateInvoiceRequest {
-    /// Get expiry duration (default: 1 hour)
-    pub fn expiry_duration(&self) -> chrono::Duration {
-        chrono::Duration::seconds(self.expiry_seconds.unwrap_or(3600) as i64)
+/// Who changed a transaction's status (recorded in `transaction_status_history`)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StatusActor {
+    /// Payment flows, provider callbacks and background jobs
+    System,
+    /// Operator acting through an internal endpoint
+    Operator(String),
+}
+
+impl std::fmt::Display for StatusActor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StatusActor::System => write!(f, "system"),
+            StatusActor::Operator(operator) => write!(f, "operator:{}", operator),
+        }
     }
 }
 
@@ -277,16 +343,62 @@ mod tests {
 
     #[test]
     fn test_deposit_fee_calculation() {
+        let schedule = crate::fees::tests::deposit_schedule();
         let request = MpesaDepositRequest { amount_kes: 1000 };
-        assert_eq!(request.calculate_fee().0, Decimal::new(10, 0)); // 1% = 10 KES
-        assert_eq!(request.net_amount().0, Decimal::new(990, 0)); // 990 KES after fees
+        assert_eq!(request.calculate_fee(&schedule).as_decimal(), Decimal::new(10, 0)); // 1% = 10 KES
+        assert_eq!(request.net_amount(&schedule).as_decimal(), Decimal::new(990, 0)); // 990 KES after fees
     }
 
     #[test]
     fn test_mpesa_fee_tiers() {
-        assert_eq!(MpesaWithdrawalRequest::calculate_mpesa_fee(Decimal::new(50, 0)), Decimal::new(5, 0));
-        assert_eq!(MpesaWithdrawalRequest::calculate_mpesa_fee(Decimal::new(1000, 0)), Decimal::new(13, 0));
-        assert_eq!(MpesaWithdrawalRequest::calculate_mpesa_fee(Decimal::new(5000, 0)), Decimal::new(35, 0));
+        let tariff = &crate::fees::tests::withdrawal_schedule().components[1];
+        assert_eq!(tariff.calculate(Decimal::new(50, 0)), Decimal::new(5, 0));
+        assert_eq!(tariff.calculate(Decimal::new(1000, 0)), Decimal::new(13, 0));
+        assert_eq!(tariff.calculate(Decimal::new(5000, 0)), Decimal::new(35, 0));
+    }
+
+    #[test]
+    fn test_withdrawal_conversion_and_fees() {
+        let schedule = crate::fees::tests::withdrawal_schedule();
+        let rate = BtcKesRate::new(Decimal::new(5_000_000, 0)).unwrap();
+        let request = MpesaWithdrawalRequest { amount_sats: 20_000, recipient_phone: None };
+
+        assert_eq!(request.kes_payout(rate).unwrap().as_decimal(), Decimal::new(1000, 0)); // 20k sats = 1,000 KES
+        let (fee_kes, fee_sats) = request.calculate_fees(&schedule, rate).unwrap();
+        assert_eq!(fee_kes.as_decimal(), Decimal::new(23, 0)); // 10 KES service fee + 13 KES tariff
+        assert_eq!(fee_sats.as_i64(), 460);
+    }
+
+    #[test]
+    fn test_deposit_estimated_sats() {
+        let schedule = crate::fees::tests::deposit_schedule();
+        let rate = BtcKesRate::new(Decimal::new(5_300_000, 0)).unwrap();
+        let request = MpesaDepositRequest { amount_kes: 1000 };
+
+        // 990 KES net = 18,679.2 sats, rounded down
+        assert_eq!(request.estimated_sats(&schedule, rate).unwrap().as_i64(), 18_679);
+    }
+
+    #[test]
+    fn test_status_transitions() {
+        use TransactionStatus::*;
+
+        assert!(StatusTransition::new(Pending, Processing).is_ok());
+        assert!(StatusTransition::new(Processing, Completed).is_ok());
+        assert!(StatusTransition::new(Processing, Failed).is_ok());
+        assert!(StatusTransition::new(Failed, Refunded).is_ok());
+
+        for (from, to) in [
+            (Pending, Completed),
+            (Completed, Processing),
+            (Completed, Refunded),
+            (Refunded, Failed),
+            (Failed, Processing),
+        ] {
+            assert!(matches!(StatusTransition::new(from, to), Err(AppError::Conflict { .. })));
+        }
+
+        assert_eq!(StatusActor::Operator("jane".to_string()).to_string(), "operator:jane");
     }
 
     #[test]
@@ -0,0 +1,254 @@
+/// Historical BTC/KES exchange rates
+///
+/// Aggregates the raw samples in the `exchange_rates` table into OHLC candles
+/// for price charts and month-end valuation. Range and resolution are bounded
+/// so a single request can never scan or return an unbounded amount of data.
+
+use chrono::{DateTime, Duration, Utc};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use shared_errors::{AppError, Result};
+use sqlx::PgPool;
+use std::sync::Arc;
+use tracing::instrument;
+
+/// Maximum number of candles a single history request can return
+pub const MAX_CANDLES: i64 = 2000;
+
+/// Candle resolution for historical rate queries
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RateInterval {
+    #[serde(rename = "1m")]
+    OneMinute,
+    #[serde(rename = "1h")]
+    OneHour,
+    #[serde(rename = "1d")]
+    OneDay,
+}
+
+impl RateInterval {
+    /// Width of a single candle
+    pub fn duration(&self) -> Duration {
+        match self {
+            RateInterval::OneMinute => Duration::minutes(1),
+            RateInterval::OneHour => Duration::hours(1),
+            RateInterval::OneDay => Duration::days(1),
+        }
+    }
+
+    /// Longest time range that can be requested at this resolution
+    pub fn max_range(&self) -> Duration {
+        match self {
+            RateInterval::OneMinute => Duration::days(1),  // 1,440 candles
+            RateInterval::OneHour => Duration::days(62),   // 1,488 candles
+            RateInterval::OneDay => Duration::days(1825),  // 5 years
+        }
+    }
+
+    /// Short label used in query strings and messages
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RateInterval::OneMinute => "1m",
+            RateInterval::OneHour => "1h",
+            RateInterval::OneDay => "1d",
+        }
+    }
+
+    /// Postgres interval literal used for bucketing
+    fn as_pg_interval(&self) -> &'static str {
+        match self {
+            RateInterval::OneMinute => "1 minute",
+            RateInterval::OneHour => "1 hour",
+            RateInterval::OneDay => "1 day",
+        }
+    }
+}
+
+/// Query parameters for `/exchange-rates/history`
+#[derive(Debug, Deserialize)]
+pub struct RateHistoryParams {
+    /// Start of the range (inclusive)
+    pub from: DateTime<Utc>,
+    /// End of the range (exclusive, default: now)
+    pub to: Option<DateTime<Utc>>,
+    /// Candle resolution (default: 1h)
+    pub interval: Option<RateInterval>,
+}
+
+/// Single OHLC candle of BTC/KES prices
+#[derive(Debug, Clone, Serialize)]
+pub struct RateCandle {
+    /// Start of the candle's time bucket
+    pub bucket_start: DateTime<Utc>,
+    /// First recorded rate in the bucket (KES per BTC)
+    pub open: Decimal,
+    pub high: Decimal,
+    pub low: Decimal,
+    /// Last recorded rate in the bucket (KES per BTC)
+    pub close: Decimal,
+    /// Number of raw rate samples aggregated into this candle
+    pub sample_count: i64,
+}
+
+/// Response for `/exchange-rates/history`
+#[derive(Debug, Serialize)]
+pub struct RateHistoryResponse {
+    pub interval: RateInterval,
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+    /// Candles in ascending time order; buckets with no samples are omitted
+    pub candles: Vec<RateCandle>,
+}
+
+/// Validated time range for a history query
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateHistoryRange {
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+    pub interval: RateInterval,
+}
+
+impl RateHistoryParams {
+    /// Apply defaults and enforce range and resolution bounds
+    pub fn resolve(&self, now: DateTime<Utc>) -> Result<RateHistoryRange> {
+        let interval = self.interval.unwrap_or(RateInterval::OneHour);
+        let to = self.to.unwrap_or(now).min(now);
+
+        if self.from >= to {
+            return Err(AppError::Validation {
+                message: "'from' must be earlier than 'to' and not in the future".to_string(),
+            });
+        }
+
+        let range = to - self.from;
+        if range > interval.max_range() {
+            return Err(AppError::Validation {
+                message: format!(
+                    "Range too large for {} candles (maximum {} days)",
+                    interval.as_str(),
+                    interval.max_range().num_days()
+                ),
+            });
+        }
+
+        if range.num_seconds() / interval.duration().num_seconds() > MAX_CANDLES {
+            return Err(AppError::Validation {
+                message: format!("Requested range exceeds {} candles", MAX_CANDLES),
+            });
+        }
+
+        Ok(RateHistoryRange { from: self.from, to, interval })
+    }
+}
+
+/// Read-only access to historical exchange rate samples
+pub struct RateHistoryRepository {
+    pool: PgPool,
+}
+
+impl RateHistoryRepository {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+
+    /// Aggregate raw samples into OHLC candles aligned to the interval
+    #[instrument(skip(self))]
+    pub async fn candles(&self, range: &RateHistoryRange) -> Result<Vec<RateCandle>> {
+        let rows = sqlx::query!(
+            r#"
+            SELECT
+                date_bin($3::text::interval, created_at, TIMESTAMPTZ '2000-01-01 00:00:00+00') AS "bucket_start!",
+                (array_agg(btc_kes ORDER BY created_at ASC, id ASC))[1] AS "open!",
+                MAX(btc_kes) AS "high!",
+                MIN(btc_kes) AS "low!",
+                (array_agg(btc_kes ORDER BY created_at DESC, id DESC))[1] AS "close!",
+                COUNT(*) AS "sample_count!"
+            FROM exchange_rates
+            WHERE created_at >= $1 AND created_at < $2
+            GROUP BY 1
+            ORDER BY 1 ASC
+            "#,
+            range.from,
+            range.to,
+            range.interval.as_pg_interval(),
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|r| RateCandle {
+                bucket_start: r.bucket_start,
+                open: r.open,
+                high: r.high,
+                low: r.low,
+                close: r.close,
+                sample_count: r.sample_count,
+            })
+            .collect())
+    }
+}
+
+/// Service exposing historical rates to handlers
+pub struct RateHistoryService {
+    repository: Arc<RateHistoryRepository>,
+}
+
+impl RateHistoryService {
+    pub fn new(repository: Arc<RateHistoryRepository>) -> Self {
+        Self { repository }
+    }
+
+    /// Get OHLC candles for the requested range
+    #[instrument(skip(self))]
+    pub async fn get_history(&self, params: RateHistoryParams) -> Result<RateHistoryResponse> {
+        let range = params.resolve(Utc::now())?;
+        let candles = self.repository.candles(&range).await?;
+
+        Ok(RateHistoryResponse {
+            interval: range.interval,
+            from: range.from,
+            to: range.to,
+            candles,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params(from: DateTime<Utc>, to: Option<DateTime<Utc>>, interval: RateInterval) -> RateHistoryParams {
+        RateHistoryParams { from, to, interval: Some(interval) }
+    }
+
+    #[test]
+    fn test_interval_deserialization() {
+        let interval: RateInterval = serde_json::from_str("\"1m\"").unwrap();
+        assert_eq!(interval, RateInterval::OneMinute);
+        assert!(serde_json::from_str::<RateInterval>("\"5m\"").is_err());
+    }
+
+    #[test]
+    fn test_range_defaults_and_clamping() {
+        let now = Utc::now();
+        let request = RateHistoryParams { from: now - Duration::hours(5), to: None, interval: None };
+        let range = request.resolve(now).unwrap();
+        assert_eq!(range.interval, RateInterval::OneHour);
+        assert_eq!(range.to, now);
+
+        // Future end dates are clamped to now
+        let request = params(now - Duration::hours(5), Some(now + Duration::days(1)), RateInterval::OneHour);
+        assert_eq!(request.resolve(now).unwrap().to, now);
+    }
+
+    #[test]
+    fn test_range_bounds() {
+        let now = Utc::now();
+        assert!(params(now, Some(now - Duration::hours(1)), RateInterval::OneHour).resolve(now).is_err());
+        assert!(params(now - Duration::days(2), None, RateInterval::OneMinute).resolve(now).is_err());
+        assert!(params(now - Duration::days(1), None, RateInterval::OneMinute).resolve(now).is_ok());
+        assert!(params(now - Duration::days(90), None, RateInterval::OneHour).resolve(now).is_err());
+        assert!(params(now - Duration::days(365), None, RateInterval::OneDay).resolve(now).is_ok());
+    }
+}
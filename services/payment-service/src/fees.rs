@@ -0,0 +1,621 @@
+/// Database-driven fee schedule engine
+///
+/// Fees for each payment rail are defined by versioned schedules stored in the
+/// database. A schedule is made of components (percentage, flat or tiered),
+/// each optionally clamped to a min/max. The schedule applied to a transaction
+/// is the latest one whose `effective_from` has passed, and its ID is recorded
+/// on the transaction for audit.
+
+use chrono::{DateTime, Utc};
+use rust_decimal::{Decimal, RoundingStrategy};
+use serde::{Deserialize, Serialize};
+use shared_errors::{AppError, Result};
+use shared_types::*;
+use sqlx::PgPool;
+use std::collections::HashSet;
+use std::sync::Arc;
+use tracing::{info, instrument};
+use uuid::Uuid;
+
+/// How a fee component is computed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[sqlx(type_name = "fee_kind", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum FeeKind {
+    /// A share of the amount (`rate` = 0.01 for 1%)
+    Percentage,
+    /// A fixed KES amount per transaction
+    Flat,
+    /// A fixed KES amount picked by amount band
+    Tiered,
+}
+
+/// Amount band of a tiered component
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FeeTier {
+    /// Upper bound of the band (inclusive); `None` covers everything above
+    pub up_to_kes: Option<Decimal>,
+    pub fee_kes: Decimal,
+}
+
+/// Single line of a fee schedule
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FeeComponent {
+    pub name: String,
+    pub kind: FeeKind,
+    pub rate: Option<Decimal>,
+    pub flat_kes: Option<Decimal>,
+    pub min_kes: Option<Decimal>,
+    pub max_kes: Option<Decimal>,
+    #[serde(default)]
+    pub tiers: Vec<FeeTier>,
+}
+
+/// Versioned fee schedule for one rail
+#[derive(Debug, Clone, Serialize)]
+pub struct FeeSchedule {
+    pub id: Uuid,
+    pub rail: TransactionType,
+    pub version: i32,
+    pub effective_from: DateTime<Utc>,
+    pub description: Option<String>,
+    pub components: Vec<FeeComponent>,
+}
+
+/// Fee charged by one component
+#[derive(Debug, Clone, Serialize)]
+pub struct FeeLine {
+    pub name: String,
+    pub fee_kes: KesAmount,
+}
+
+/// Result of pricing an amount against a schedule
+#[derive(Debug, Clone, Serialize)]
+pub struct FeeBreakdown {
+    /// Schedule to record on the transaction
+    pub schedule_id: Uuid,
+    pub schedule_version: i32,
+    pub lines: Vec<FeeLine>,
+    pub total_kes: KesAmount,
+}
+
+impl FeeComponent {
+    /// Calculate this component's fee for an amount, rounded to cents
+    pub fn calculate(&self, amount_kes: Decimal) -> Decimal {
+        let raw = match self.kind {
+            FeeKind::Percentage => amount_kes * self.rate.unwrap_or(Decimal::ZERO),
+            FeeKind::Flat => self.flat_kes.unwrap_or(Decimal::ZERO),
+            FeeKind::Tiered => self.tier_fee(amount_kes),
+        };
+
+        let mut fee = raw;
+        if let Some(min) = self.min_kes {
+            fee = fee.max(min);
+        }
+        if let Some(max) = self.max_kes {
+            fee = fee.min(max);
+        }
+
+        // Fees are rounded up to the cent so we never under-collect a tariff
+        fee.round_dp_with_strategy(2, RoundingStrategy::AwayFromZero)
+    }
+
+    /// Find the band an amount falls into (bands are checked smallest first)
+    fn tier_fee(&self, amount_kes: Decimal) -> Decimal {
+        let mut tiers: Vec<&FeeTier> = self.tiers.iter().collect();
+        tiers.sort_by(|a, b| match (a.up_to_kes, b.up_to_kes) {
+            (Some(a), Some(b)) => a.cmp(&b),
+            (Some(_), None) => std::cmp::Ordering::Less,
+            (None, Some(_)) => std::cmp::Ordering::Greater,
+            (None, None) => std::cmp::Ordering::Equal,
+        });
+
+        tiers
+            .into_iter()
+            .find(|tier| tier.up_to_kes.map_or(true, |limit| amount_kes <= limit))
+            .map(|tier| tier.fee_kes)
+            .unwrap_or(Decimal::ZERO)
+    }
+
+    /// Check that the fields required by the component kind are present
+    fn validate(&self) -> Result<()> {
+        let invalid = |message: &str| AppError::Validation {
+            message: format!("Fee component '{}': {}", self.name, message),
+        };
+
+        match self.kind {
+            FeeKind::Percentage if self.rate.map_or(true, |r| r < Decimal::ZERO) => {
+                return Err(invalid("percentage components need a non-negative rate"));
+            }
+            FeeKind::Flat if self.flat_kes.map_or(true, |f| f < Decimal::ZERO) => {
+                return Err(invalid("flat components need a non-negative flat_kes"));
+            }
+            FeeKind::Tiered if self.tiers.is_empty() => {
+                return Err(invalid("tiered components need at least one tier"));
+            }
+            _ => {}
+        }
+
+        // Without exactly one open-ended band, amounts above the top band
+        // would match no tier and be charged nothing
+        if self.kind == FeeKind::Tiered
+            && self.tiers.iter().filter(|t| t.up_to_kes.is_none()).count() != 1
+        {
+            return Err(invalid(
+                "tiered components need exactly one open-ended tier (up_to_kes: null)",
+            ));
+        }
+
+        if self.tiers.iter().any(|t| t.fee_kes < Decimal::ZERO) {
+            return Err(invalid("tier fees cannot be negative"));
+        }
+
+        if let (Some(min), Some(max)) = (self.min_kes, self.max_kes) {
+            if min > max {
+                return Err(invalid("min_kes cannot exceed max_kes"));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl FeeSchedule {
+    /// Price an amount (in KES) against every component of the schedule
+    pub fn calculate(&self, amount_kes: Decimal) -> FeeBreakdown {
+        let lines: Vec<FeeLine> = self
+            .components
+            .iter()
+            .map(|component| FeeLine {
+                name: component.name.clone(),
+                fee_kes: KesAmount::new(component.calculate(amount_kes)),
+            })
+            .collect();
+
+        let total = lines.iter().map(|line| line.fee_kes.as_decimal()).sum();
+
+        FeeBreakdown {
+            schedule_id: self.id,
+            schedule_version: self.version,
+            lines,
+            total_kes: KesAmount::new(total),
+        }
+    }
+}
+
+/// Request to publish a new fee schedule version (internal/operations use)
+#[derive(Debug, Deserialize)]
+pub struct CreateFeeScheduleRequest {
+    pub rail: TransactionType,
+    /// When the new tariff starts applying (must not be in the past)
+    pub effective_from: DateTime<Utc>,
+    pub description: Option<String>,
+    pub components: Vec<FeeComponent>,
+}
+
+impl CreateFeeScheduleRequest {
+    /// Validate the schedule before it is stored
+    pub fn validate(&self, now: DateTime<Utc>) -> Result<()> {
+        if self.effective_from < now {
+            return Err(AppError::Validation {
+                message: "Fee schedules cannot take effect in the past".to_string(),
+            });
+        }
+
+        if self.components.is_empty() {
+            return Err(AppError::Validation {
+                message: "Fee schedule needs at least one component".to_string(),
+            });
+        }
+
+        let mut names = HashSet::new();
+        for component in &self.components {
+            if !names.insert(component.name.as_str()) {
+                return Err(AppError::Validation {
+                    message: format!("Duplicate fee component '{}'", component.name),
+                });
+            }
+            component.validate()?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Fee preview query parameters
+#[derive(Debug, Deserialize)]
+pub struct FeePreviewParams {
+    pub transaction_type: TransactionType,
+    /// Amount in KES to price
+    pub amount_kes: Decimal,
+}
+
+/// Fee preview shown to the user before confirming a payment
+#[derive(Debug, Serialize)]
+pub struct FeePreviewResponse {
+    pub transaction_type: TransactionType,
+    pub amount_kes: KesAmount,
+    pub fee_kes: KesAmount,
+    /// Amount remaining after fees are deducted
+    pub net_amount_kes: KesAmount,
+    pub breakdown: Vec<FeeLine>,
+    pub schedule_version: i32,
+    pub effective_from: DateTime<Utc>,
+}
+
+/// Fee schedule repository
+pub struct FeeScheduleRepository {
+    pool: PgPool,
+}
+
+impl FeeScheduleRepository {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+
+    /// Find the schedule in force for a rail at a point in time
+    #[instrument(skip(self))]
+    pub async fn find_active(
+        &self,
+        rail: &TransactionType,
+        at: DateTime<Utc>,
+    ) -> Result<Option<FeeSchedule>> {
+        let row = sqlx::query!(
+            r#"
+            SELECT id, rail as "rail: TransactionType", version, effective_from, description
+            FROM fee_schedules
+            WHERE rail = $1 AND effective_from <= $2
+            ORDER BY effective_from DESC, version DESC
+            LIMIT 1
+            "#,
+            rail.clone() as _,
+            at,
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        let Some(row) = row else {
+            return Ok(None);
+        };
+
+        let components = self.load_components(row.id).await?;
+
+        Ok(Some(FeeSchedule {
+            id: row.id,
+            rail: row.rail,
+            version: row.version,
+            effective_from: row.effective_from,
+            description: row.description,
+            components,
+        }))
+    }
+
+    /// Load a schedule's components together with their tiers
+    async fn load_components(&self, schedule_id: Uuid) -> Result<Vec<FeeComponent>> {
+        let component_rows = sqlx::query!(
+            r#"
+            SELECT id, name, kind as "kind: FeeKind", rate, flat_kes, min_kes, max_kes
+            FROM fee_schedule_components
+            WHERE schedule_id = $1
+            ORDER BY position, name
+            "#,
+            schedule_id
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        let tier_rows = sqlx::query!(
+            r#"
+            SELECT t.component_id, t.up_to_kes, t.fee_kes
+            FROM fee_schedule_tiers t
+            JOIN fee_schedule_components c ON c.id = t.component_id
+            WHERE c.schedule_id = $1
+            "#,
+            schedule_id
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(component_rows
+            .into_iter()
+            .map(|c| FeeComponent {
+                tiers: tier_rows
+                    .iter()
+                    .filter(|t| t.component_id == c.id)
+                    .map(|t| FeeTier { up_to_kes: t.up_to_kes, fee_kes: t.fee_kes })
+                    .collect(),
+                name: c.name,
+                kind: c.kind,
+                rate: c.rate,
+                flat_kes: c.flat_kes,
+                min_kes: c.min_kes,
+                max_kes: c.max_kes,
+            })
+            .collect())
+    }
+
+    /// Store a new schedule as the next version for its rail
+    #[instrument(skip(self, request))]
+    pub async fn create(&self, request: &CreateFeeScheduleRequest) -> Result<FeeSchedule> {
+        let mut tx = self.pool.begin().await?;
+
+        let version = sqlx::query_scalar!(
+            r#"SELECT COALESCE(MAX(version), 0) + 1 AS "version!" FROM fee_schedules WHERE rail = $1"#,
+            request.rail.clone() as _,
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+
+        let schedule_id = Uuid::new_v4();
+        sqlx::query!(
+            r#"
+            INSERT INTO fee_schedules (id, rail, version, effective_from, description)
+            VALUES ($1, $2, $3, $4, $5)
+            "#,
+            schedule_id,
+            request.rail.clone() as _,
+            version,
+            request.effective_from,
+            request.description,
+        )
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| {
+            if e.to_string().contains("unique_fee_schedule_version") {
+                AppError::Validation {
+                    message: "A concurrent fee schedule update is in progress. Please retry.".to_string(),
+                }
+            } else {
+                AppError::Database(e)
+            }
+        })?;
+
+        for (position, component) in request.components.iter().enumerate() {
+            let component_id = Uuid::new_v4();
+            sqlx::query!(
+                r#"
+                INSERT INTO fee_schedule_components
+                    (id, schedule_id, name, kind, rate, flat_kes, min_kes, max_kes, position)
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+                "#,
+                component_id,
+                schedule_id,
+                component.name,
+                component.kind as _,
+                component.rate,
+                component.flat_kes,
+                component.min_kes,
+                component.max_kes,
+                position as i32,
+            )
+            .execute(&mut *tx)
+            .await?;
+
+            for tier in &component.tiers {
+                sqlx::query!(
+                    "INSERT INTO fee_schedule_tiers (component_id, up_to_kes, fee_kes) VALUES ($1, $2, $3)",
+                    component_id,
+                    tier.up_to_kes,
+                    tier.fee_kes,
+                )
+                .execute(&mut *tx)
+                .await?;
+            }
+        }
+
+        tx.commit().await?;
+
+        Ok(FeeSchedule {
+            id: schedule_id,
+            rail: request.rail.clone(),
+            version,
+            effective_from: request.effective_from,
+            description: request.description.clone(),
+            components: request.components.clone(),
+        })
+    }
+}
+
+/// Service resolving and applying fee schedules
+pub struct FeeService {
+    repository: Arc<FeeScheduleRepository>,
+}
+
+impl FeeService {
+    pub fn new(repository: Arc<FeeScheduleRepository>) -> Self {
+        Self { repository }
+    }
+
+    /// Get the schedule currently in force for a rail
+    #[instrument(skip(self))]
+    pub async fn active_schedule(&self, rail: &TransactionType) -> Result<FeeSchedule> {
+        self.repository
+            .find_active(rail, Utc::now())
+            .await?
+            .ok_or_else(|| {
+                AppError::Internal(anyhow::anyhow!("No fee schedule configured for {:?}", rail))
+            })
+    }
+
+    /// Price an amount on a rail with the schedule currently in force
+    #[instrument(skip(self))]
+    pub async fn quote(&self, rail: &TransactionType, amount_kes: Decimal) -> Result<FeeBreakdown> {
+        let schedule = self.active_schedule(rail).await?;
+        Ok(schedule.calculate(amount_kes))
+    }
+
+    /// Show the user what a payment would cost before they confirm it
+    #[instrument(skip(self))]
+    pub async fn preview(&self, params: FeePreviewParams) -> Result<FeePreviewResponse> {
+        if params.amount_kes <= Decimal::ZERO {
+            return Err(AppError::invalid_amount());
+        }
+
+        // Only rails with a schedule charge fees we can preview
+        let schedule = self
+            .repository
+            .find_active(&params.transaction_type, Utc::now())
+            .await?
+            .ok_or_else(|| AppError::Validation {
+                message: format!("No fee preview for {:?} payments", params.transaction_type),
+            })?;
+        let breakdown = schedule.calculate(params.amount_kes);
+
+        Ok(FeePreviewResponse {
+            transaction_type: params.transaction_type,
+            amount_kes: KesAmount::new(params.amount_kes),
+            net_amount_kes: KesAmount::new(params.amount_kes - breakdown.total_kes.as_decimal()),
+            fee_kes: breakdown.total_kes,
+            breakdown: breakdown.lines,
+            schedule_version: schedule.version,
+            effective_from: schedule.effective_from,
+        })
+    }
+
+    /// Publish a new schedule version
+    #[instrument(skip(self, request))]
+    pub async fn create_schedule(&self, request: CreateFeeScheduleRequest) -> Result<FeeSchedule> {
+        request.validate(Utc::now())?;
+
+        let schedule = self.repository.create(&request).await?;
+
+        info!(
+            "Fee schedule v{} for {:?} published, effective {}",
+            schedule.version, schedule.rail, schedule.effective_from
+        );
+
+        Ok(schedule)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    fn percentage(name: &str, rate: Decimal, min_kes: Option<Decimal>) -> FeeComponent {
+        FeeComponent {
+            name: name.to_string(),
+            kind: FeeKind::Percentage,
+            rate: Some(rate),
+            flat_kes: None,
+            min_kes,
+            max_kes: None,
+            tiers: vec![],
+        }
+    }
+
+    fn schedule(rail: TransactionType, components: Vec<FeeComponent>) -> FeeSchedule {
+        FeeSchedule {
+            id: Uuid::new_v4(),
+            rail,
+            version: 1,
+            effective_from: Utc::now(),
+            description: None,
+            components,
+        }
+    }
+
+    /// Deposit schedule matching the seeded version 1
+    pub(crate) fn deposit_schedule() -> FeeSchedule {
+        schedule(
+            TransactionType::DepositMpesa,
+            vec![percentage("service_fee", Decimal::new(1, 2), Some(Decimal::new(10, 0)))],
+        )
+    }
+
+    /// Withdrawal schedule matching the seeded version 1
+    pub(crate) fn withdrawal_schedule() -> FeeSchedule {
+        let bands = [
+            (49, 1), (100, 5), (500, 7), (1000, 13), (1500, 20), (2500, 25), (3500, 30),
+            (5000, 35), (7500, 45), (10000, 55), (15000, 60), (20000, 65), (25000, 70), (30000, 75),
+        ];
+        let mut tiers: Vec<FeeTier> = bands
+            .iter()
+            .map(|(up_to, fee)| FeeTier {
+                up_to_kes: Some(Decimal::from(*up_to)),
+                fee_kes: Decimal::from(*fee),
+            })
+            .collect();
+        tiers.push(FeeTier { up_to_kes: None, fee_kes: Decimal::from(105) });
+
+        schedule(
+            TransactionType::WithdrawalMpesa,
+            vec![
+                percentage("service_fee", Decimal::new(1, 2), None),
+                FeeComponent {
+                    name: "mpesa_tariff".to_string(),
+                    kind: FeeKind::Tiered,
+                    rate: None,
+                    flat_kes: None,
+                    min_kes: None,
+                    max_kes: None,
+                    tiers,
+                },
+            ],
+        )
+    }
+
+    #[test]
+    fn test_percentage_with_minimum() {
+        let schedule = deposit_schedule();
+        assert_eq!(schedule.calculate(Decimal::from(500)).total_kes.as_decimal(), Decimal::new(10, 0)); // Minimum applies
+        assert_eq!(schedule.calculate(Decimal::from(5000)).total_kes.as_decimal(), Decimal::new(50, 0));
+    }
+
+    #[test]
+    fn test_tiered_bands() {
+        let tariff = &withdrawal_schedule().components[1];
+        assert_eq!(tariff.calculate(Decimal::from(49)), Decimal::from(1));
+        assert_eq!(tariff.calculate(Decimal::new(4950, 2)), Decimal::from(5)); // 49.50 falls in the next band
+        assert_eq!(tariff.calculate(Decimal::from(30000)), Decimal::from(75));
+        assert_eq!(tariff.calculate(Decimal::from(30001)), Decimal::from(105));
+    }
+
+    #[test]
+    fn test_breakdown_and_rounding() {
+        let breakdown = withdrawal_schedule().calculate(Decimal::new(123456, 2)); // 1,234.56 KES
+        assert_eq!(breakdown.lines.len(), 2);
+        assert_eq!(breakdown.lines[0].fee_kes.as_decimal(), Decimal::new(1235, 2)); // 12.3456 rounds up to 12.35
+        assert_eq!(breakdown.lines[1].fee_kes.as_decimal(), Decimal::from(20));
+        assert_eq!(breakdown.total_kes.as_decimal(), Decimal::new(3235, 2));
+    }
+
+    #[test]
+    fn test_max_cap() {
+        let mut component = percentage("service_fee", Decimal::new(1, 2), None);
+        component.max_kes = Some(Decimal::from(200));
+        assert_eq!(component.calculate(Decimal::from(100_000)), Decimal::from(200));
+    }
+
+    #[test]
+    fn test_create_request_validation() {
+        let now = Utc::now();
+        let mut request = CreateFeeScheduleRequest {
+            rail: TransactionType::DepositMpesa,
+            effective_from: now + chrono::Duration::days(1),
+            description: None,
+            components: deposit_schedule().components,
+        };
+        assert!(request.validate(now).is_ok());
+
+        request.components.push(request.components[0].clone());
+        assert!(request.validate(now).is_err()); // Duplicate component name
+
+        request.components.pop();
+        request.effective_from = now - chrono::Duration::days(1);
+        assert!(request.validate(now).is_err()); // Back-dated schedule
+    }
+
+    #[test]
+    fn test_tiered_requires_open_ended_tier() {
+        let mut component = withdrawal_schedule().components[1].clone();
+        assert!(component.validate().is_ok());
+
+        component.tiers.retain(|tier| tier.up_to_kes.is_some());
+        assert!(component.validate().is_err()); // No band above the top limit
+
+        component.tiers.push(FeeTier { up_to_kes: None, fee_kes: Decimal::from(105) });
+        component.tiers.push(FeeTier { up_to_kes: None, fee_kes: Decimal::from(110) });
+        assert!(component.validate().is_err()); // Ambiguous open-ended bands
+    }
+}
@@ -0,0 +1,361 @@
+/// Transaction history with keyset pagination
+///
+/// Pages are ordered newest first by `(created_at, id)`, the order of the
+/// `idx_transactions_user_created` index. Cursors are opaque tokens holding the
+/// position of the first or last row of a page, so new transactions arriving
+/// between requests never shift or repeat rows the way offsets did.
+
+use crate::domain::*;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::{DateTime, TimeZone, Utc};
+use shared_errors::{AppError, Result};
+use shared_types::*;
+use sqlx::{PgPool, Postgres, QueryBuilder, Row};
+use std::sync::Arc;
+use tracing::instrument;
+use uuid::Uuid;
+
+/// Default and maximum page size
+const DEFAULT_PAGE_SIZE: i64 = 20;
+const MAX_PAGE_SIZE: i64 = 100;
+
+/// Which way a cursor pages
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CursorDirection {
+    /// Older transactions than the cursor
+    Next,
+    /// Newer transactions than the cursor
+    Prev,
+}
+
+/// Position in the history
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HistoryCursor {
+    pub direction: CursorDirection,
+    pub created_at: DateTime<Utc>,
+    pub id: Uuid,
+}
+
+impl HistoryCursor {
+    /// Opaque token given to clients
+    pub fn encode(&self) -> String {
+        let direction = match self.direction {
+            CursorDirection::Next => 'n',
+            CursorDirection::Prev => 'p',
+        };
+        URL_SAFE_NO_PAD.encode(format!("{}:{}:{}", direction, self.created_at.timestamp_micros(), self.id))
+    }
+
+    pub fn decode(token: &str) -> Result<Self> {
+        let invalid = || AppError::Validation {
+            message: "Invalid cursor".to_string(),
+        };
+
+        let decoded = URL_SAFE_NO_PAD.decode(token).map_err(|_| invalid())?;
+        let decoded = String::from_utf8(decoded).map_err(|_| invalid())?;
+        let mut parts = decoded.splitn(3, ':');
+
+        let direction = match parts.next() {
+            Some("n") => CursorDirection::Next,
+            Some("p") => CursorDirection::Prev,
+            _ => return Err(invalid()),
+        };
+        let micros: i64 = parts.next().and_then(|p| p.parse().ok()).ok_or_else(invalid)?;
+        let created_at = Utc.timestamp_micros(micros).single().ok_or_else(invalid)?;
+        let id = parts.next().and_then(|p| p.parse().ok()).ok_or_else(invalid)?;
+
+        Ok(Self { direction, created_at, id })
+    }
+
+    fn at(direction: CursorDirection, transaction: &TransactionSummary) -> String {
+        Self {
+            direction,
+            created_at: transaction.created_at,
+            id: transaction.id,
+        }
+        .encode()
+    }
+}
+
+/// Build a page from rows fetched with one extra row to detect more results
+/// Rows must be newest first, except for `Prev` pages which are fetched oldest first
+pub fn paginate(
+    mut rows: Vec<TransactionSummary>,
+    limit: i64,
+    cursor: Option<&HistoryCursor>,
+) -> TransactionHistoryResponse {
+    let has_extra = rows.len() as i64 > limit;
+    rows.truncate(limit as usize);
+
+    let direction = cursor.map(|c| c.direction);
+    if direction == Some(CursorDirection::Prev) {
+        rows.reverse();
+    }
+
+    // Older rows exist if this page was cut short, or if we paged back from them
+    let has_older = match direction {
+        Some(CursorDirection::Prev) => true,
+        _ => has_extra,
+    };
+    // Newer rows exist if we paged forward from them, or if a backwards page was cut short
+    let has_newer = match direction {
+        None => false,
+        Some(CursorDirection::Next) => true,
+        Some(CursorDirection::Prev) => has_extra,
+    };
+
+    let next_cursor = rows
+        .last()
+        .filter(|_| has_older)
+        .map(|last| HistoryCursor::at(CursorDirection::Next, last));
+    let prev_cursor = rows
+        .first()
+        .filter(|_| has_newer)
+        .map(|first| HistoryCursor::at(CursorDirection::Prev, first));
+
+    TransactionHistoryResponse {
+        has_more: next_cursor.is_some(),
+        transactions: rows,
+        next_cursor,
+        prev_cursor,
+    }
+}
+
+/// Escape `%`, `_` and `\` so text is matched literally by ILIKE
+pub fn like_pattern(text: &str) -> String {
+    let escaped = text
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_");
+    format!("%{}%", escaped)
+}
+
+/// Database access for transaction history
+pub struct HistoryRepository {
+    pool: PgPool,
+}
+
+impl HistoryRepository {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+
+    /// Fetch up to `limit` rows matching the filters, starting after the cursor
+    #[instrument(skip(self, params))]
+    pub async fn fetch(
+        &self,
+        user_id: UserId,
+        params: &TransactionHistoryParams,
+        cursor: Option<&HistoryCursor>,
+        limit: i64,
+    ) -> Result<Vec<TransactionSummary>> {
+        let mut query = QueryBuilder::<Postgres>::new(
+            r#"
+            SELECT id, type, status, provider, amount_kes, amount_sats, fee_kes, fee_sats,
+                   metadata->>'description' AS description,
+                   metadata->>'counterparty' AS counterparty,
+                   created_at, completed_at
+            FROM transactions
+            WHERE user_id = "#,
+        );
+        query.push_bind(user_id.0);
+
+        if let Some(transaction_type) = &params.transaction_type {
+            query.push(" AND type = ").push_bind(transaction_type.clone());
+        }
+        if let Some(status) = &params.status {
+            query.push(" AND status = ").push_bind(status.clone());
+        }
+        if let Some(from_date) = params.from_date {
+            query.push(" AND created_at >= ").push_bind(from_date);
+        }
+        if let Some(to_date) = params.to_date {
+            query.push(" AND created_at < ").push_bind(to_date);
+        }
+        if let Some(min) = params.min_amount_kes {
+            query.push(" AND amount_kes >= ").push_bind(min);
+        }
+        if let Some(max) = params.max_amount_kes {
+            query.push(" AND amount_kes <= ").push_bind(max);
+        }
+        if let Some(min) = params.min_amount_sats {
+            query.push(" AND amount_sats >= ").push_bind(min);
+        }
+        if let Some(max) = params.max_amount_sats {
+            query.push(" AND amount_sats <= ").push_bind(max);
+        }
+        if let Some(counterparty) = &params.counterparty {
+            // Containment uses the GIN index on metadata
+            query
+                .push(" AND metadata @> ")
+                .push_bind(serde_json::json!({ "counterparty": counterparty }));
+        }
+        if let Some(memo) = &params.memo {
+            query
+                .push(" AND metadata->>'description' ILIKE ")
+                .push_bind(like_pattern(memo));
+        }
+
+        let order = match cursor {
+            Some(cursor) => {
+                let (comparison, order) = match cursor.direction {
+                    CursorDirection::Next => (" < ", " ORDER BY created_at DESC, id DESC"),
+                    CursorDirection::Prev => (" > ", " ORDER BY created_at ASC, id ASC"),
+                };
+                query
+                    .push(" AND (created_at, id)")
+                    .push(comparison)
+                    .push("(")
+                    .push_bind(cursor.created_at)
+                    .push(", ")
+                    .push_bind(cursor.id)
+                    .push(")");
+                order
+            }
+            None => " ORDER BY created_at DESC, id DESC",
+        };
+        query.push(order).push(" LIMIT ").push_bind(limit);
+
+        let rows = query.build().fetch_all(&self.pool).await?;
+
+        rows.into_iter()
+            .map(|row| {
+                Ok(TransactionSummary {
+                    id: row.try_get("id")?,
+                    transaction_type: row.try_get("type")?,
+                    status: row.try_get("status")?,
+                    provider: row.try_get("provider")?,
+                    amount_kes: row.try_get("amount_kes")?,
+                    amount_sats: row.try_get("amount_sats")?,
+                    fee_kes: row.try_get("fee_kes")?,
+                    fee_sats: row.try_get("fee_sats")?,
+                    description: row.try_get("description")?,
+                    counterparty: row.try_get("counterparty")?,
+                    created_at: row.try_get("created_at")?,
+                    completed_at: row.try_get("completed_at")?,
+                })
+            })
+            .collect()
+    }
+}
+
+/// Transaction history queries
+pub struct HistoryService {
+    repository: Arc<HistoryRepository>,
+}
+
+impl HistoryService {
+    pub fn new(repository: Arc<HistoryRepository>) -> Self {
+        Self { repository }
+    }
+
+    /// One page of the user's transactions
+    #[instrument(skip(self))]
+    pub async fn get_transaction_history(
+        &self,
+        user_id: UserId,
+        params: TransactionHistoryParams,
+    ) -> Result<TransactionHistoryResponse> {
+        let limit = params.limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE);
+        let cursor = params.cursor.as_deref().map(HistoryCursor::decode).transpose()?;
+
+        if let (Some(min), Some(max)) = (params.min_amount_kes, params.max_amount_kes) {
+            if min > max {
+                return Err(AppError::Validation {
+                    message: "min_amount_kes cannot be greater than max_amount_kes".to_string(),
+                });
+            }
+        }
+        if let (Some(min), Some(max)) = (params.min_amount_sats, params.max_amount_sats) {
+            if min > max {
+                return Err(AppError::Validation {
+                    message: "min_amount_sats cannot be greater than max_amount_sats".to_string(),
+                });
+            }
+        }
+
+        let rows = self
+            .repository
+            .fetch(user_id, &params, cursor.as_ref(), limit + 1)
+            .await?;
+
+        Ok(paginate(rows, limit, cursor.as_ref()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    fn summary(minutes_ago: i64) -> TransactionSummary {
+        TransactionSummary {
+            id: Uuid::new_v4(),
+            transaction_type: TransactionType::LightningReceive,
+            status: TransactionStatus::Completed,
+            provider: None,
+            amount_kes: None,
+            amount_sats: Some(SatAmount::new(1000)),
+            fee_kes: None,
+            fee_sats: None,
+            description: None,
+            counterparty: None,
+            created_at: Utc.timestamp_micros(1_700_000_000_000_000).unwrap() - Duration::minutes(minutes_ago),
+            completed_at: None,
+        }
+    }
+
+    #[test]
+    fn test_cursor_round_trip() {
+        let cursor = HistoryCursor {
+            direction: CursorDirection::Prev,
+            created_at: Utc.timestamp_micros(1_700_000_000_123_456).unwrap(),
+            id: Uuid::new_v4(),
+        };
+        assert_eq!(HistoryCursor::decode(&cursor.encode()).unwrap(), cursor);
+
+        assert!(HistoryCursor::decode("not-a-cursor").is_err());
+        assert!(HistoryCursor::decode(&URL_SAFE_NO_PAD.encode("x:1:abc")).is_err());
+    }
+
+    #[test]
+    fn test_first_and_middle_pages() {
+        // First page with one extra row: older rows exist, nothing newer
+        let page = paginate(vec![summary(0), summary(1), summary(2)], 2, None);
+        assert_eq!(page.transactions.len(), 2);
+        assert!(page.has_more);
+        assert!(page.prev_cursor.is_none());
+
+        let next = HistoryCursor::decode(page.next_cursor.as_ref().unwrap()).unwrap();
+        assert_eq!(next.direction, CursorDirection::Next);
+        assert_eq!(next.id, page.transactions[1].id);
+
+        // Last page reached going forward
+        let page = paginate(vec![summary(3)], 2, Some(&next));
+        assert!(page.next_cursor.is_none());
+        assert!(!page.has_more);
+        assert!(page.prev_cursor.is_some());
+    }
+
+    #[test]
+    fn test_prev_page_is_newest_first() {
+        let cursor = HistoryCursor {
+            direction: CursorDirection::Prev,
+            created_at: summary(5).created_at,
+            id: Uuid::new_v4(),
+        };
+
+        // Fetched oldest first, exactly one page: no newer rows left
+        let page = paginate(vec![summary(4), summary(3)], 2, Some(&cursor));
+        assert!(page.transactions[0].created_at > page.transactions[1].created_at);
+        assert!(page.prev_cursor.is_none());
+        assert!(page.next_cursor.is_some());
+    }
+
+    #[test]
+    fn test_like_pattern_escapes_wildcards() {
+        assert_eq!(like_pattern("rent"), "%rent%");
+        assert_eq!(like_pattern("50%_off\\"), "%50\\%\\_off\\\\%");
+    }
+}
@@ -0,0 +1,525 @@
+/// Idempotency-Key handling for money-moving endpoints
+///
+/// Clients send an `Idempotency-Key` header with every deposit, withdrawal and
+/// Lightning payment. The first request with a key is executed and its response
+/// stored in Postgres together with a hash of the request body; retries with the
+/// same key get the stored response back, and a key reused with a different body
+/// is rejected with 422. Keys live in the database so all instances share them.
+
+use axum::{
+    async_trait,
+    body::Bytes,
+    extract::{FromRequest, MatchedPath, Request},
+    http::{HeaderValue, StatusCode},
+    response::{IntoResponse, Json, Response},
+};
+use chrono::{DateTime, Duration, Utc};
+use serde::{de::DeserializeOwned, Serialize};
+use sha2::{Digest, Sha256};
+use shared_errors::{AppError, ErrorResponse, Result};
+use shared_types::UserId;
+use sqlx::PgPool;
+use std::future::Future;
+use std::sync::Arc;
+use tracing::{info, instrument, warn};
+
+/// Request header carrying the client-generated key
+pub const IDEMPOTENCY_KEY_HEADER: &str = "Idempotency-Key";
+
+/// Response header set when a stored response is replayed
+pub const IDEMPOTENT_REPLAYED_HEADER: &str = "Idempotent-Replayed";
+
+/// Longest key accepted (fits the database column)
+pub const MAX_IDEMPOTENCY_KEY_LENGTH: usize = 255;
+
+/// How long a key and its response are kept
+pub fn idempotency_key_ttl() -> Duration {
+    Duration::hours(24)
+}
+
+/// Idempotency key of a request, with the route and body hash it was sent with
+#[derive(Debug, Clone)]
+pub struct IdempotencyKey {
+    pub key: String,
+    pub endpoint: String,
+    pub request_hash: String,
+}
+
+/// JSON request body that must be sent with an `Idempotency-Key` header
+///
+/// Use in place of `Json<T>` on money-moving routes.
+#[derive(Debug)]
+pub struct Idempotent<T> {
+    pub key: IdempotencyKey,
+    pub body: T,
+}
+
+#[async_trait]
+impl<S, T> FromRequest<S> for Idempotent<T>
+where
+    S: Send + Sync,
+    T: DeserializeOwned,
+{
+    type Rejection = AppError;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self> {
+        let key = parse_idempotency_key(req.headers().get(IDEMPOTENCY_KEY_HEADER))?;
+        let endpoint = req
+            .extensions()
+            .get::<MatchedPath>()
+            .map(|path| path.as_str().to_string())
+            .unwrap_or_else(|| req.uri().path().to_string());
+
+        let bytes = Bytes::from_request(req, state)
+            .await
+            .map_err(|e| AppError::Validation { message: e.body_text() })?;
+
+        let value: serde_json::Value = serde_json::from_slice(&bytes)
+            .map_err(|e| AppError::Validation { message: format!("Invalid JSON body: {}", e) })?;
+        let request_hash = hash_request(&value);
+
+        let body = serde_json::from_value(value)
+            .map_err(|e| AppError::Validation { message: format!("Invalid request body: {}", e) })?;
+
+        Ok(Idempotent {
+            key: IdempotencyKey { key, endpoint, request_hash },
+            body,
+        })
+    }
+}
+
+/// Validate the raw header value
+fn parse_idempotency_key(header: Option<&HeaderValue>) -> Result<String> {
+    let header = header.ok_or_else(|| AppError::Validation {
+        message: format!("{} header is required for this request", IDEMPOTENCY_KEY_HEADER),
+    })?;
+
+    let key = header
+        .to_str()
+        .ok()
+        .map(str::trim)
+        .filter(|key| !key.is_empty() && key.len() <= MAX_IDEMPOTENCY_KEY_LENGTH)
+        .filter(|key| key.chars().all(|c| c.is_ascii_graphic()))
+        .ok_or_else(|| AppError::Validation {
+            message: format!(
+                "{} must be 1-{} printable ASCII characters",
+                IDEMPOTENCY_KEY_HEADER, MAX_IDEMPOTENCY_KEY_LENGTH
+            ),
+        })?;
+
+    Ok(key.to_string())
+}
+
+/// SHA-256 of the body in canonical form (object keys sorted, no whitespace),
+/// so a retry that reorders fields still matches the original
+fn hash_request(value: &serde_json::Value) -> String {
+    let mut canonical = String::new();
+    write_canonical(value, &mut canonical);
+    hex::encode(Sha256::digest(canonical.as_bytes()))
+}
+
+fn write_canonical(value: &serde_json::Value, out: &mut String) {
+    match value {
+        serde_json::Value::Object(map) => {
+            let mut entries: Vec<_> = map.iter().collect();
+            entries.sort_by(|a, b| a.0.cmp(b.0));
+
+            out.push('{');
+            for (i, (key, value)) in entries.into_iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                out.push_str(&serde_json::Value::String(key.clone()).to_string());
+                out.push(':');
+                write_canonical(value, out);
+            }
+            out.push('}');
+        }
+        serde_json::Value::Array(items) => {
+            out.push('[');
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                write_canonical(item, out);
+            }
+            out.push(']');
+        }
+        scalar => out.push_str(&scalar.to_string()),
+    }
+}
+
+/// Key already present in the database
+#[derive(Debug, Clone)]
+pub struct StoredIdempotencyKey {
+    pub endpoint: String,
+    pub request_hash: String,
+    pub response_status: Option<i16>,
+    pub response_body: Option<serde_json::Value>,
+    pub completed_at: Option<DateTime<Utc>>,
+}
+
+impl StoredIdempotencyKey {
+    /// Response to send for a retry of `request`
+    pub fn replay(self, request: &IdempotencyKey) -> Result<Response> {
+        if self.endpoint != request.endpoint || self.request_hash != request.request_hash {
+            return Err(AppError::IdempotencyKeyReused {
+                message: "This Idempotency-Key was already used for a different request".to_string(),
+            });
+        }
+
+        let (Some(status), Some(body), Some(_)) = (self.response_status, self.response_body, self.completed_at) else {
+            return Err(AppError::Conflict {
+                message: "A request with this Idempotency-Key is still being processed".to_string(),
+            });
+        };
+
+        let status = u16::try_from(status)
+            .ok()
+            .and_then(|s| StatusCode::from_u16(s).ok())
+            .unwrap_or(StatusCode::OK);
+
+        let mut response = (status, Json(body)).into_response();
+        response
+            .headers_mut()
+            .insert(IDEMPOTENT_REPLAYED_HEADER, HeaderValue::from_static("true"));
+        Ok(response)
+    }
+}
+
+/// Database access for idempotency keys
+pub struct IdempotencyRepository {
+    pool: PgPool,
+}
+
+impl IdempotencyRepository {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+
+    /// Claim a key for a new request
+    /// Returns false if the key is already in use (expired keys are taken over)
+    #[instrument(skip(self))]
+    pub async fn claim(
+        &self,
+        user_id: UserId,
+        request: &IdempotencyKey,
+        expires_at: DateTime<Utc>,
+    ) -> Result<bool> {
+        let claimed = sqlx::query!(
+            r#"
+            INSERT INTO idempotency_keys (user_id, idempotency_key, endpoint, request_hash, expires_at)
+            VALUES ($1, $2, $3, $4, $5)
+            ON CONFLICT (user_id, idempotency_key) DO UPDATE SET
+                endpoint = EXCLUDED.endpoint,
+                request_hash = EXCLUDED.request_hash,
+                response_status = NULL,
+                response_body = NULL,
+                created_at = NOW(),
+                completed_at = NULL,
+                expires_at = EXCLUDED.expires_at
+            WHERE idempotency_keys.expires_at < NOW()
+            RETURNING user_id
+            "#,
+            user_id.0,
+            request.key,
+            request.endpoint,
+            request.request_hash,
+            expires_at,
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(claimed.is_some())
+    }
+
+    /// Load a key previously used by this user
+    #[instrument(skip(self))]
+    pub async fn find(&self, user_id: UserId, key: &str) -> Result<Option<StoredIdempotencyKey>> {
+        let row = sqlx::query!(
+            r#"
+            SELECT endpoint, request_hash, response_status, response_body, completed_at
+            FROM idempotency_keys
+            WHERE user_id = $1 AND idempotency_key = $2
+            "#,
+            user_id.0,
+            key,
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.map(|r| StoredIdempotencyKey {
+            endpoint: r.endpoint,
+            request_hash: r.request_hash,
+            response_status: r.response_status,
+            response_body: r.response_body,
+            completed_at: r.completed_at,
+        }))
+    }
+
+    /// Store the response of a completed request
+    #[instrument(skip(self, body))]
+    pub async fn complete(
+        &self,
+        user_id: UserId,
+        key: &str,
+        status: StatusCode,
+        body: &serde_json::Value,
+    ) -> Result<()> {
+        sqlx::query!(
+            r#"
+            UPDATE idempotency_keys
+            SET response_status = $3, response_body = $4, completed_at = NOW()
+            WHERE user_id = $1 AND idempotency_key = $2
+            "#,
+            user_id.0,
+            key,
+            status.as_u16() as i16,
+            body,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Free a key whose request failed so the client can retry with it
+    #[instrument(skip(self))]
+    pub async fn release(&self, user_id: UserId, key: &str) -> Result<()> {
+        sqlx::query!(
+            "DELETE FROM idempotency_keys WHERE user_id = $1 AND idempotency_key = $2 AND completed_at IS NULL",
+            user_id.0,
+            key,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Delete expired keys
+    #[instrument(skip(self))]
+    pub async fn purge_expired(&self) -> Result<u64> {
+        let result = sqlx::query!("DELETE FROM idempotency_keys WHERE expires_at < NOW()")
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected())
+    }
+}
+
+/// Whether a handler error was raised before the request changed anything
+///
+/// Validation, limit and balance errors are raised up front. Provider, database
+/// and internal errors can come after the wallet was debited or a payout sent.
+fn is_rejection(error: &AppError) -> bool {
+    matches!(
+        error,
+        AppError::User { .. }
+            | AppError::Auth { .. }
+            | AppError::Payment { .. }
+            | AppError::Validation { .. }
+            | AppError::RateLimit { .. }
+            | AppError::Conflict { .. }
+            | AppError::IdempotencyKeyReused { .. }
+            | AppError::LimitExceeded { .. }
+    )
+}
+
+/// Body sent for an error, in the same shape as `AppError::into_response`
+fn error_response(error: &AppError) -> ErrorResponse {
+    ErrorResponse {
+        error: error.error_code().to_string(),
+        message: error.user_message(),
+        code: error.error_code().to_string(),
+        details: error.details(),
+    }
+}
+
+/// Runs money-moving requests at most once per idempotency key
+pub struct IdempotencyService {
+    repository: Arc<IdempotencyRepository>,
+}
+
+impl IdempotencyService {
+    pub fn new(repository: Arc<IdempotencyRepository>) -> Self {
+        Self { repository }
+    }
+
+    /// Run `handler` unless this key was already used, in which case the
+    /// stored response is replayed
+    ///
+    /// Requests rejected before anything was written are not stored, so a client
+    /// can correct and retry them with the same key. Any other failure is stored
+    /// and replayed like a success.
+    #[instrument(skip(self, handler))]
+    pub async fn execute<T, F, Fut>(
+        &self,
+        user_id: UserId,
+        request: &IdempotencyKey,
+        handler: F,
+    ) -> Result<Response>
+    where
+        T: Serialize,
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        let expires_at = Utc::now() + idempotency_key_ttl();
+
+        if !self.repository.claim(user_id, request, expires_at).await? {
+            info!("Replaying request for idempotency key {}", request.key);
+
+            // The key can disappear between claim and find if the original request failed
+            return match self.repository.find(user_id, &request.key).await? {
+                Some(stored) => stored.replay(request),
+                None => Err(AppError::Conflict {
+                    message: "A request with this Idempotency-Key just failed, please retry".to_string(),
+                }),
+            };
+        }
+
+        let response = match handler().await {
+            Ok(response) => response,
+            Err(error) if is_rejection(&error) => {
+                if let Err(release_error) = self.repository.release(user_id, &request.key).await {
+                    warn!("Failed to release idempotency key {}: {}", request.key, release_error);
+                }
+                return Err(error);
+            }
+            Err(error) => {
+                // Money may already have moved, so retries get this error back
+                // instead of running the payment a second time
+                let body = serde_json::to_value(error_response(&error))
+                    .map_err(|e| AppError::Internal(e.into()))?;
+                if let Err(store_error) = self
+                    .repository
+                    .complete(user_id, &request.key, error.status_code(), &body)
+                    .await
+                {
+                    warn!("Failed to store error for idempotency key {}: {}", request.key, store_error);
+                }
+                return Err(error);
+            }
+        };
+
+        let body = serde_json::to_value(&response).map_err(|e| AppError::Internal(e.into()))?;
+        self.repository
+            .complete(user_id, &request.key, StatusCode::OK, &body)
+            .await?;
+
+        Ok(Json(body).into_response())
+    }
+
+    /// Delete expired keys (run periodically)
+    pub async fn purge_expired(&self) -> Result<u64> {
+        self.repository.purge_expired().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn request(body: serde_json::Value) -> IdempotencyKey {
+        IdempotencyKey {
+            key: "key-1".to_string(),
+            endpoint: "/withdrawals/mpesa".to_string(),
+            request_hash: hash_request(&body),
+        }
+    }
+
+    fn stored(request: &IdempotencyKey, response: Option<serde_json::Value>) -> StoredIdempotencyKey {
+        StoredIdempotencyKey {
+            endpoint: request.endpoint.clone(),
+            request_hash: request.request_hash.clone(),
+            response_status: response.as_ref().map(|_| 200),
+            completed_at: response.as_ref().map(|_| Utc::now()),
+            response_body: response,
+        }
+    }
+
+    #[test]
+    fn test_key_validation() {
+        assert!(parse_idempotency_key(None).is_err());
+        assert!(parse_idempotency_key(Some(&HeaderValue::from_static(""))).is_err());
+        assert!(parse_idempotency_key(Some(&HeaderValue::from_static("has space"))).is_err());
+
+        let long = HeaderValue::from_str(&"a".repeat(MAX_IDEMPOTENCY_KEY_LENGTH + 1)).unwrap();
+        assert!(parse_idempotency_key(Some(&long)).is_err());
+
+        let key = parse_idempotency_key(Some(&HeaderValue::from_static("7f9c2a1e-3b4d-4c5e-8f6a-1b2c3d4e5f60")));
+        assert_eq!(key.unwrap(), "7f9c2a1e-3b4d-4c5e-8f6a-1b2c3d4e5f60");
+    }
+
+    #[test]
+    fn test_request_hash_ignores_field_order() {
+        let a = hash_request(&json!({"amount_sats": 20000, "recipient_phone": "+254712345678"}));
+        let b = hash_request(&json!({"recipient_phone": "+254712345678", "amount_sats": 20000}));
+        let c = hash_request(&json!({"amount_sats": 20001, "recipient_phone": "+254712345678"}));
+
+        assert_eq!(a, b);
+        assert_ne!(a, c);
+        assert_eq!(a.len(), 64);
+    }
+
+    #[test]
+    fn test_replay_returns_original_response() {
+        let request = request(json!({"amount_kes": 1000}));
+        let response = stored(&request, Some(json!({"transaction_id": "abc"})))
+            .replay(&request)
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[IDEMPOTENT_REPLAYED_HEADER], "true");
+    }
+
+    #[test]
+    fn test_reused_key_with_different_body_is_rejected() {
+        let original = request(json!({"amount_kes": 1000}));
+        let retry = request(json!({"amount_kes": 5000}));
+
+        let error = stored(&original, Some(json!({}))).replay(&retry).unwrap_err();
+        assert_eq!(error.status_code(), StatusCode::UNPROCESSABLE_ENTITY);
+
+        let mut other_route = original.clone();
+        other_route.endpoint = "/deposits/mpesa".to_string();
+        assert!(matches!(
+            stored(&original, Some(json!({}))).replay(&other_route),
+            Err(AppError::IdempotencyKeyReused { .. })
+        ));
+    }
+
+    #[test]
+    fn test_only_up_front_errors_release_the_key() {
+        assert!(is_rejection(&AppError::invalid_amount()));
+        assert!(is_rejection(&AppError::insufficient_balance(2_000, 1_000)));
+        assert!(is_rejection(&AppError::LimitExceeded {
+            message: "Daily limit reached".to_string(),
+            details: json!({}),
+        }));
+
+        assert!(!is_rejection(&AppError::mpesa_timeout()));
+        assert!(!is_rejection(&AppError::lightning_route_not_found()));
+        assert!(!is_rejection(&AppError::Internal(anyhow::anyhow!("boom"))));
+    }
+
+    #[test]
+    fn test_stored_error_is_replayed() {
+        let request = request(json!({"amount_kes": 1000}));
+        let error = AppError::mpesa_timeout();
+        let mut stored = stored(&request, Some(serde_json::to_value(error_response(&error)).unwrap()));
+        stored.response_status = Some(error.status_code().as_u16() as i16);
+
+        let response = stored.replay(&request).unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+        assert_eq!(response.headers()[IDEMPOTENT_REPLAYED_HEADER], "true");
+    }
+
+    #[test]
+    fn test_in_progress_request_is_a_conflict() {
+        let request = request(json!({"amount_kes": 1000}));
+        let error = stored(&request, None).replay(&request).unwrap_err();
+        assert_eq!(error.status_code(), StatusCode::CONFLICT);
+    }
+}
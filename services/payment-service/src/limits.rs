@@ -0,0 +1,443 @@
+/// KYC-tier transaction limits
+///
+/// Every payment counts against the user's rolling 24-hour and 30-day volume,
+/// measured in KES across all transaction types. Sats amounts are valued at the
+/// rate stored on the transaction, or at the current rate when none was stored.
+/// The limits themselves come from `shared_compliance::get_transaction_limits`.
+///
+/// `LimitsService` checks are a fast early rejection. Every debit checks again
+/// under a per-user lock: flows that record the payment themselves call
+/// `lock_and_check_limits` inside their transaction, and flows that go through
+/// `PaymentService` run it inside `LimitsService::within_kes`/`within_sats`.
+/// The lock is what actually keeps concurrent payments within the limits.
+
+use chrono::{DateTime, Duration, Utc};
+use rust_decimal::Decimal;
+use serde::Serialize;
+use shared_errors::{AppError, Result};
+use shared_types::conversion::{BtcKesRate, Rounding, Side};
+use shared_types::*;
+use sqlx::{PgConnection, PgPool, Postgres, Transaction};
+use std::future::Future;
+use std::sync::Arc;
+use tracing::{info, instrument, warn};
+
+/// Length of the rolling windows
+const DAILY_WINDOW_HOURS: i64 = 24;
+const MONTHLY_WINDOW_DAYS: i64 = 30;
+
+/// Usage of one rolling limit window
+#[derive(Debug, Clone, Serialize)]
+pub struct LimitUsage {
+    pub limit_kes: KesAmount,
+    pub used_kes: KesAmount,
+    pub remaining_kes: KesAmount,
+    /// Start of the rolling window; volume before this no longer counts
+    pub window_start: DateTime<Utc>,
+}
+
+impl LimitUsage {
+    fn new(limit_kes: i64, used_kes: Decimal, window_start: DateTime<Utc>) -> Self {
+        let limit = Decimal::from(limit_kes);
+        Self {
+            limit_kes: KesAmount::new(limit),
+            used_kes: KesAmount::new(used_kes),
+            remaining_kes: KesAmount::new((limit - used_kes).max(Decimal::ZERO)),
+            window_start,
+        }
+    }
+}
+
+/// Response for `/limits`
+#[derive(Debug, Clone, Serialize)]
+pub struct LimitsResponse {
+    pub kyc_tier: KycTier,
+    pub daily: LimitUsage,
+    pub monthly: LimitUsage,
+}
+
+impl LimitsResponse {
+    /// Limits and usage for a tier given the KES volume in each window
+    pub fn evaluate(
+        kyc_tier: KycTier,
+        daily_used_kes: Decimal,
+        monthly_used_kes: Decimal,
+        now: DateTime<Utc>,
+    ) -> Self {
+        let (daily_limit, monthly_limit) = shared_compliance::get_transaction_limits(kyc_tier.clone());
+        Self {
+            kyc_tier,
+            daily: LimitUsage::new(daily_limit, daily_used_kes, now - Duration::hours(DAILY_WINDOW_HOURS)),
+            monthly: LimitUsage::new(monthly_limit, monthly_used_kes, now - Duration::days(MONTHLY_WINDOW_DAYS)),
+        }
+    }
+
+    /// Reject an amount that does not fit in both windows
+    pub fn check(&self, amount_kes: Decimal) -> Result<()> {
+        let exceeded = if amount_kes > self.daily.remaining_kes.as_decimal() {
+            "daily"
+        } else if amount_kes > self.monthly.remaining_kes.as_decimal() {
+            "monthly"
+        } else {
+            return Ok(());
+        };
+
+        Err(AppError::LimitExceeded {
+            message: format!(
+                "This payment exceeds your {} limit. Daily remaining: {} KES, monthly remaining: {} KES",
+                exceeded, self.daily.remaining_kes, self.monthly.remaining_kes
+            ),
+            details: serde_json::json!({
+                "exceeded": exceeded,
+                "kyc_tier": self.kyc_tier,
+                "requested_kes": KesAmount::new(amount_kes),
+                "daily_limit_kes": self.daily.limit_kes,
+                "daily_remaining_kes": self.daily.remaining_kes,
+                "monthly_limit_kes": self.monthly.limit_kes,
+                "monthly_remaining_kes": self.monthly.remaining_kes,
+            }),
+        })
+    }
+}
+
+/// Amount of a BOLT11 invoice, read from its human-readable part
+/// Returns `None` for invoices without an amount or that cannot be parsed
+pub fn invoice_amount_sats(bolt11: &str) -> Option<SatAmount> {
+    let invoice = bolt11.trim().to_lowercase();
+    let invoice = invoice.strip_prefix("lightning:").unwrap_or(&invoice);
+
+    // The data part never contains '1', so the last one separates it from the prefix
+    let hrp = &invoice[..invoice.rfind('1')?];
+    let amount = hrp.strip_prefix("ln")?.trim_start_matches(|c: char| c.is_ascii_alphabetic());
+    if amount.is_empty() {
+        return None;
+    }
+
+    let digits_end = amount.find(|c: char| !c.is_ascii_digit()).unwrap_or(amount.len());
+    let value: i64 = amount[..digits_end].parse().ok()?;
+
+    // Multipliers are fractions of a bitcoin; convert to millisatoshis
+    let msats = match &amount[digits_end..] {
+        "" => value.checked_mul(100_000_000_000)?,
+        "m" => value.checked_mul(100_000_000)?,
+        "u" => value.checked_mul(100_000)?,
+        "n" => value.checked_mul(100)?,
+        "p" if value % 10 == 0 => value / 10,
+        _ => return None,
+    };
+
+    // Round partial sats up so limits are never under-counted
+    let sats = MsatAmount::new(msats).to_sats(Side::Charge, Rounding::HouseFavourable);
+    sats.is_positive().then_some(sats)
+}
+
+/// Database access for limit checks
+pub struct LimitsRepository {
+    pool: PgPool,
+}
+
+impl LimitsRepository {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+
+    /// Transaction to hold the per-user limits lock in
+    pub async fn begin(&self) -> Result<Transaction<'static, Postgres>> {
+        Ok(self.pool.begin().await?)
+    }
+
+    /// Current KYC tier from the users table (tokens can carry a stale tier)
+    #[instrument(skip(self))]
+    pub async fn kyc_tier(&self, user_id: UserId) -> Result<Option<KycTier>> {
+        let mut conn = self.pool.acquire().await?;
+        fetch_kyc_tier(&mut conn, user_id).await
+    }
+
+    /// Latest BTC/KES rate
+    #[instrument(skip(self))]
+    pub async fn current_rate(&self) -> Result<Option<Decimal>> {
+        let rate = sqlx::query_scalar!("SELECT btc_kes FROM exchange_rates ORDER BY created_at DESC LIMIT 1")
+            .fetch_optional(&self.pool)
+            .await?;
+
+        Ok(rate)
+    }
+
+    /// KES volume since `daily_since` and since `monthly_since`
+    /// Failed and refunded transactions, refund entries and vault moves do not count; pending
+    /// ones do, so in-flight payments cannot be used to go over the limit
+    #[instrument(skip(self))]
+    pub async fn volume(
+        &self,
+        user_id: UserId,
+        daily_since: DateTime<Utc>,
+        monthly_since: DateTime<Utc>,
+        current_rate: Decimal,
+    ) -> Result<(Decimal, Decimal)> {
+        let mut conn = self.pool.acquire().await?;
+        fetch_volume(&mut conn, user_id, daily_since, monthly_since, current_rate).await
+    }
+}
+
+async fn fetch_kyc_tier(conn: &mut PgConnection, user_id: UserId) -> Result<Option<KycTier>> {
+    let tier = sqlx::query_scalar!(
+        r#"SELECT kyc_tier as "kyc_tier: KycTier" FROM users WHERE id = $1"#,
+        user_id.0
+    )
+    .fetch_optional(conn)
+    .await?;
+
+    Ok(tier)
+}
+
+async fn fetch_volume(
+    conn: &mut PgConnection,
+    user_id: UserId,
+    daily_since: DateTime<Utc>,
+    monthly_since: DateTime<Utc>,
+    current_rate: Decimal,
+) -> Result<(Decimal, Decimal)> {
+    let row = sqlx::query!(
+        r#"
+        WITH volume AS (
+            SELECT created_at,
+                   COALESCE(amount_kes, amount_sats::numeric * COALESCE(exchange_rate, $4) / 100000000) AS kes
+            FROM transactions
+            WHERE user_id = $1
+              AND created_at >= $3
+              AND status IN ('pending', 'processing', 'completed')
+              AND type NOT IN ('refund', 'vault_deposit', 'vault_withdrawal')
+        )
+        SELECT COALESCE(SUM(kes) FILTER (WHERE created_at >= $2), 0) AS "daily!",
+               COALESCE(SUM(kes), 0) AS "monthly!"
+        FROM volume
+        "#,
+        user_id.0,
+        daily_since,
+        monthly_since,
+        current_rate,
+    )
+    .fetch_one(conn)
+    .await?;
+
+    Ok((row.daily, row.monthly))
+}
+
+/// Take the user's limits lock and check a payment against their limits
+///
+/// Call inside the database transaction that records the payment, before its
+/// row is inserted and before any wallet row is locked. The lock makes
+/// concurrent payments by the same user take turns, so each one counts the
+/// volume of those committed before it instead of all passing against the
+/// same remaining allowance. It is an advisory lock rather than the wallet
+/// row so `PaymentService`, which debits on its own connections, can run
+/// while it is held.
+#[instrument(skip(conn))]
+pub async fn lock_and_check_limits(
+    conn: &mut PgConnection,
+    user_id: UserId,
+    amount_kes: Decimal,
+    rate: BtcKesRate,
+) -> Result<()> {
+    sqlx::query!(
+        "SELECT pg_advisory_xact_lock(hashtext($1))",
+        format!("limits:{}", user_id.0)
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    let kyc_tier = fetch_kyc_tier(&mut *conn, user_id)
+        .await?
+        .ok_or_else(AppError::user_not_found)?;
+
+    let now = Utc::now();
+    let (daily, monthly) = fetch_volume(
+        &mut *conn,
+        user_id,
+        now - Duration::hours(DAILY_WINDOW_HOURS),
+        now - Duration::days(MONTHLY_WINDOW_DAYS),
+        rate.kes_per_btc(),
+    )
+    .await?;
+
+    LimitsResponse::evaluate(kyc_tier, daily, monthly, now).check(amount_kes)
+}
+
+/// Limits engine used by every payment flow
+pub struct LimitsService {
+    repository: Arc<LimitsRepository>,
+}
+
+impl LimitsService {
+    pub fn new(repository: Arc<LimitsRepository>) -> Self {
+        Self { repository }
+    }
+
+    /// Limits and current usage for a user
+    #[instrument(skip(self))]
+    pub async fn usage(&self, user_id: UserId) -> Result<LimitsResponse> {
+        let rate = self.current_rate().await?;
+        self.usage_at_rate(user_id, rate).await
+    }
+
+    /// Reject a KES amount that would take the user over a limit
+    #[instrument(skip(self))]
+    pub async fn check_kes(&self, user_id: UserId, amount_kes: Decimal) -> Result<()> {
+        let rate = self.current_rate().await?;
+        self.usage_at_rate(user_id, rate).await?.check(amount_kes)
+    }
+
+    /// Reject a sats amount that would take the user over a limit
+    #[instrument(skip(self))]
+    pub async fn check_sats(&self, user_id: UserId, amount_sats: SatAmount) -> Result<()> {
+        let rate = self.current_rate().await?;
+        let amount_kes = rate.sats_to_kes(amount_sats, Side::Charge, Rounding::HouseFavourable)?;
+        self.usage_at_rate(user_id, rate).await?.check(amount_kes.as_decimal())
+    }
+
+    /// Run a KES payment with the user's limits locked and checked
+    ///
+    /// `pay` must record the payment before it returns; the lock is released
+    /// afterwards, so the next payment by the same user counts it.
+    pub async fn within_kes<T, Fut>(
+        &self,
+        user_id: UserId,
+        amount_kes: Decimal,
+        pay: impl FnOnce() -> Fut,
+    ) -> Result<T>
+    where
+        Fut: Future<Output = Result<T>>,
+    {
+        let rate = self.current_rate().await?;
+        self.within_limits(user_id, amount_kes, rate, pay).await
+    }
+
+    /// Run a sats payment with the user's limits locked and checked
+    pub async fn within_sats<T, Fut>(
+        &self,
+        user_id: UserId,
+        amount_sats: SatAmount,
+        pay: impl FnOnce() -> Fut,
+    ) -> Result<T>
+    where
+        Fut: Future<Output = Result<T>>,
+    {
+        let rate = self.current_rate().await?;
+        let amount_kes = rate.sats_to_kes(amount_sats, Side::Charge, Rounding::HouseFavourable)?;
+        self.within_limits(user_id, amount_kes.as_decimal(), rate, pay).await
+    }
+
+    /// Run a Lightning payment, checked using the amount encoded in the invoice
+    pub async fn within_invoice<T, Fut>(
+        &self,
+        user_id: UserId,
+        bolt11: &str,
+        pay: impl FnOnce() -> Fut,
+    ) -> Result<T>
+    where
+        Fut: Future<Output = Result<T>>,
+    {
+        let amount_sats = invoice_amount_sats(bolt11).ok_or_else(|| AppError::Validation {
+            message: "Lightning invoice must specify an amount".to_string(),
+        })?;
+        self.within_sats(user_id, amount_sats, pay).await
+    }
+
+    async fn within_limits<T, Fut>(
+        &self,
+        user_id: UserId,
+        amount_kes: Decimal,
+        rate: BtcKesRate,
+        pay: impl FnOnce() -> Fut,
+    ) -> Result<T>
+    where
+        Fut: Future<Output = Result<T>>,
+    {
+        // The transaction only holds the lock; dropping it on cancellation
+        // rolls back and releases the lock too
+        let mut tx = self.repository.begin().await?;
+        lock_and_check_limits(&mut *tx, user_id, amount_kes, rate).await?;
+
+        let result = pay().await;
+
+        if let Err(e) = tx.rollback().await {
+            warn!("Failed to release limits lock for user {}: {}", user_id.0, e);
+        }
+        result
+    }
+
+    async fn usage_at_rate(&self, user_id: UserId, rate: BtcKesRate) -> Result<LimitsResponse> {
+        let kyc_tier = self
+            .repository
+            .kyc_tier(user_id)
+            .await?
+            .ok_or_else(AppError::user_not_found)?;
+
+        let now = Utc::now();
+        let (daily, monthly) = self
+            .repository
+            .volume(
+                user_id,
+                now - Duration::hours(DAILY_WINDOW_HOURS),
+                now - Duration::days(MONTHLY_WINDOW_DAYS),
+                rate.kes_per_btc(),
+            )
+            .await?;
+
+        let response = LimitsResponse::evaluate(kyc_tier, daily, monthly, now);
+        info!(
+            "User {} has {} KES of daily and {} KES of monthly limit left",
+            user_id.0, response.daily.remaining_kes, response.monthly.remaining_kes
+        );
+        Ok(response)
+    }
+
+    pub async fn current_rate(&self) -> Result<BtcKesRate> {
+        let rate = self.repository.current_rate().await?.ok_or_else(|| AppError::ExternalService {
+            message: "No exchange rate available".to_string(),
+        })?;
+        Ok(BtcKesRate::new(rate)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_remaining_allowance() {
+        let limits = LimitsResponse::evaluate(KycTier::Tier0, Decimal::new(400000, 2), Decimal::from(48000), Utc::now());
+
+        assert_eq!(limits.daily.limit_kes, KesAmount::new(Decimal::from(10000)));
+        assert_eq!(limits.daily.remaining_kes, KesAmount::new(Decimal::from(6000)));
+        assert_eq!(limits.monthly.remaining_kes, KesAmount::new(Decimal::from(2000)));
+        assert!(limits.check(Decimal::from(2000)).is_ok());
+
+        // Fits the daily window but not the monthly one
+        match limits.check(Decimal::from(2500)) {
+            Err(AppError::LimitExceeded { details, .. }) => {
+                assert_eq!(details["exceeded"], "monthly");
+                assert_eq!(details["monthly_remaining_kes"], serde_json::json!(KesAmount::new(Decimal::from(2000))));
+            }
+            other => panic!("expected limit error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_remaining_never_negative() {
+        let limits = LimitsResponse::evaluate(KycTier::Tier1, Decimal::from(150000), Decimal::from(150000), Utc::now());
+        assert_eq!(limits.daily.remaining_kes, KesAmount::zero());
+        assert!(matches!(limits.check(Decimal::ONE), Err(AppError::LimitExceeded { .. })));
+    }
+
+    #[test]
+    fn test_invoice_amount() {
+        assert_eq!(invoice_amount_sats("lnbc2500u1pvjluezpp5qqq"), Some(SatAmount::new(250_000)));
+        assert_eq!(invoice_amount_sats("LNBC20M1PVJLUEZ"), Some(SatAmount::new(2_000_000)));
+        assert_eq!(invoice_amount_sats("lntb10n1pvjluez"), Some(SatAmount::new(1)));
+        assert_eq!(invoice_amount_sats("lightning:lnbcrt15p1pvjluez"), None);
+        assert_eq!(invoice_amount_sats("lnbcrt110p1pvjluez"), Some(SatAmount::new(1)));
+        assert_eq!(invoice_amount_sats("lnbc1pvjluezpp5qqq"), None);
+        assert_eq!(invoice_amount_sats("not an invoice"), None);
+    }
+}
@@ -0,0 +1,171 @@
+/// Lightning address payments
+///
+/// A Lightning address (`name@domain`) is resolved with LNURL-pay: the domain's
+/// `/.well-known/lnurlp/<name>` endpoint describes the amounts it accepts and a
+/// callback URL, and the callback returns a BOLT11 invoice for the chosen amount.
+
+use crate::limits::invoice_amount_sats;
+use serde::Deserialize;
+use shared_errors::{AppError, Result};
+use shared_types::SatAmount;
+use std::time::Duration;
+use tracing::instrument;
+
+/// Parsed `name@domain` Lightning address
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParsedLightningAddress {
+    pub name: String,
+    pub domain: String,
+}
+
+impl ParsedLightningAddress {
+    pub fn parse(address: &str) -> Result<Self> {
+        let invalid = || AppError::Validation {
+            message: format!("Invalid Lightning address: {}", address),
+        };
+
+        let (name, domain) = address.trim().split_once('@').ok_or_else(invalid)?;
+        let name_ok = !name.is_empty()
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | '+'));
+        let domain_ok = domain.contains('.')
+            && !domain.starts_with('.')
+            && !domain.ends_with('.')
+            && domain.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '.'));
+        if !name_ok || !domain_ok {
+            return Err(invalid());
+        }
+
+        Ok(Self {
+            name: name.to_lowercase(),
+            domain: domain.to_lowercase(),
+        })
+    }
+
+    /// LNURL-pay endpoint of the address
+    pub fn well_known_url(&self) -> String {
+        format!("https://{}/.well-known/lnurlp/{}", self.domain, self.name)
+    }
+}
+
+/// First LNURL-pay response
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PayParams {
+    callback: String,
+    /// Millisatoshis
+    min_sendable: i64,
+    max_sendable: i64,
+    /// Longest comment the recipient accepts (0 or missing: none)
+    comment_allowed: Option<usize>,
+}
+
+/// Callback response
+#[derive(Debug, Deserialize)]
+struct InvoiceResponse {
+    pr: String,
+}
+
+/// LNURL error response (`{"status": "ERROR", "reason": "..."}`)
+#[derive(Debug, Deserialize)]
+struct ErrorResponse {
+    status: String,
+    reason: Option<String>,
+}
+
+/// Client fetching invoices for Lightning addresses
+pub struct LnurlClient {
+    http: reqwest::Client,
+}
+
+impl Default for LnurlClient {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LnurlClient {
+    pub fn new() -> Self {
+        Self {
+            http: reqwest::Client::builder()
+                .timeout(Duration::from_secs(10))
+                .build()
+                .expect("Failed to build HTTP client"),
+        }
+    }
+
+    /// Invoice for paying `amount_sats` to a Lightning address
+    #[instrument(skip(self, comment))]
+    pub async fn fetch_invoice(&self, address: &str, amount_sats: SatAmount, comment: Option<&str>) -> Result<String> {
+        let address = ParsedLightningAddress::parse(address)?;
+        let params: PayParams = self.get_json(&address.well_known_url(), &[]).await?;
+
+        let amount_msats = amount_sats.as_i64() * 1000;
+        if amount_msats < params.min_sendable || amount_msats > params.max_sendable {
+            return Err(AppError::Payment {
+                message: format!(
+                    "{}@{} accepts between {} and {} sats",
+                    address.name,
+                    address.domain,
+                    params.min_sendable / 1000,
+                    params.max_sendable / 1000
+                ),
+            });
+        }
+
+        let mut query = vec![("amount", amount_msats.to_string())];
+        if let (Some(comment), Some(allowed)) = (comment, params.comment_allowed.filter(|n| *n > 0)) {
+            query.push(("comment", comment.chars().take(allowed).collect()));
+        }
+        let invoice: InvoiceResponse = self.get_json(&params.callback, &query).await?;
+
+        // Never pay an invoice for a different amount than was asked for
+        if invoice_amount_sats(&invoice.pr) != Some(amount_sats) {
+            return Err(AppError::ExternalService {
+                message: format!("{} returned an invoice for the wrong amount", address.domain),
+            });
+        }
+
+        Ok(invoice.pr)
+    }
+
+    async fn get_json<T: serde::de::DeserializeOwned>(&self, url: &str, query: &[(&str, String)]) -> Result<T> {
+        let external = |message: String| AppError::ExternalService { message };
+
+        let body: serde_json::Value = self
+            .http
+            .get(url)
+            .query(query)
+            .send()
+            .await
+            .map_err(|e| external(format!("Lightning address request failed: {}", e)))?
+            .json()
+            .await
+            .map_err(|e| external(format!("Invalid Lightning address response: {}", e)))?;
+
+        if let Ok(error) = serde_json::from_value::<ErrorResponse>(body.clone()) {
+            if error.status.eq_ignore_ascii_case("ERROR") {
+                return Err(AppError::Payment {
+                    message: error.reason.unwrap_or_else(|| "Lightning address rejected the payment".to_string()),
+                });
+            }
+        }
+
+        serde_json::from_value(body).map_err(|e| external(format!("Invalid Lightning address response: {}", e)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_lightning_address() {
+        let address = ParsedLightningAddress::parse("Alice@Pesa.co.ke").unwrap();
+        assert_eq!(address.name, "alice");
+        assert_eq!(address.well_known_url(), "https://pesa.co.ke/.well-known/lnurlp/alice");
+
+        for invalid in ["alice", "@pesa.co.ke", "alice@localhost", "alice@pesa.co.ke/x", "a b@pesa.co.ke"] {
+            assert!(ParsedLightningAddress::parse(invalid).is_err(), "{} should be rejected", invalid);
+        }
+    }
+}
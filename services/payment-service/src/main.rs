@@ -3,43 +3,116 @@
 /// This service handles all financial operations:
 /// - M-Pesa deposits (KES → Bitcoin)
 /// - M-Pesa withdrawals (Bitcoin → KES)  
+/// - Deposits and withdrawals on other mobile money networks (Airtel Money)
+/// - Paying Paybill numbers and Buy Goods tills from sats
+/// - Airtime top-ups for any Kenyan number, paid in sats
+/// - Bulk payout batches from CSV or JSON
 /// - Lightning Network payments (send/receive)
 /// - Wallet balance management
 /// - Exchange rate conversions
+/// - KYC-tier daily and monthly limits
+/// - Refunds of failed transactions
+/// - CSV and PDF account statements
+/// - Scheduled and recurring payments
+/// - Payment requests between users
+/// - Recurring M-Pesa buys (DCA plans)
+/// - Savings vaults with time locks
+/// - Merchant accounts with POS invoices and daily settlement
 
 use axum::{
-    extract::{Path, Query, State},
-    http::StatusCode,
-    response::Json,
-    routing::{get, post},
+    extract::{Form, Path, Query, State},
+    http::{header, StatusCode},
+    response::{IntoResponse, Json, Response},
+    routing::{delete, get, post},
     Router,
 };
 use shared_auth::AuthUser;
 use shared_database::DatabaseConfig;
 use shared_errors::{AppError, Result};
+use shared_events::{EventConsumer, OutboxRelay};
 use shared_tracing::init_tracing;
 use shared_types::*;
 use sqlx::PgPool;
+use std::collections::HashMap;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::net::TcpListener;
 use tower_http::cors::CorsLayer;
-use tracing::{info, instrument};
+use tracing::{error, info, instrument};
 
+mod airtime;
+mod bill_payments;
 mod domain;
 mod repository;
 mod service;
 mod integrations;
+mod exchange_rate_history;
+mod fees;
+mod history;
+mod idempotency;
+mod limits;
+mod cron;
+mod dca;
+mod lnurl;
+mod merchants;
+mod mobile_money;
+mod payment_requests;
+mod payouts;
+mod refunds;
+mod scheduled;
+mod statements;
+mod transfers;
+mod transitions;
+mod vaults;
 
+use airtime::*;
+use bill_payments::*;
 use domain::*;
 use repository::*;
 use service::*;
 use integrations::*;
+use exchange_rate_history::*;
+use fees::*;
+use history::*;
+use idempotency::*;
+use limits::*;
+use dca::*;
+use lnurl::*;
+use merchants::*;
+use mobile_money::*;
+use payment_requests::*;
+use payouts::*;
+use refunds::*;
+use scheduled::*;
+use statements::*;
+use vaults::*;
+
+/// Consumer group that refunds failed transactions
+const REFUNDS_CONSUMER_GROUP: &str = "payment-refunds";
+/// Consumer group that records deposit outcomes for DCA plans
+const DCA_CONSUMER_GROUP: &str = "payment-dca";
 
 /// Application state shared across all handlers
 #[derive(Clone)]
 pub struct AppState {
     pub payment_service: Arc<PaymentService>,
     pub wallet_service: Arc<WalletService>,
+    pub rate_history_service: Arc<RateHistoryService>,
+    pub fee_service: Arc<FeeService>,
+    pub history_service: Arc<HistoryService>,
+    pub idempotency_service: Arc<IdempotencyService>,
+    pub limits_service: Arc<LimitsService>,
+    pub refund_service: Arc<RefundService>,
+    pub statement_service: Arc<StatementService>,
+    pub scheduled_payment_service: Arc<ScheduledPaymentService>,
+    pub payment_request_service: Arc<PaymentRequestService>,
+    pub dca_service: Arc<DcaService>,
+    pub vault_service: Arc<VaultService>,
+    pub merchant_service: Arc<MerchantService>,
+    pub mobile_money_service: Arc<MobileMoneyService>,
+    pub bill_payment_service: Arc<BillPaymentService>,
+    pub airtime_service: Arc<AirtimeService>,
+    pub payout_service: Arc<PayoutService>,
     pub db: PgPool,
 }
 
@@ -55,14 +128,43 @@ async fn main() -> Result<()> {
     let wallet_repository = Arc::new(WalletRepository::new(db.clone()));
     let transaction_repository = Arc::new(TransactionRepository::new(db.clone()));
     let exchange_rate_repository = Arc::new(ExchangeRateRepository::new(db.clone()));
+    let rate_history_repository = Arc::new(RateHistoryRepository::new(db.clone()));
+    let fee_schedule_repository = Arc::new(FeeScheduleRepository::new(db.clone()));
+    let history_repository = Arc::new(HistoryRepository::new(db.clone()));
+    let idempotency_repository = Arc::new(IdempotencyRepository::new(db.clone()));
+    let limits_repository = Arc::new(LimitsRepository::new(db.clone()));
+    let refund_repository = Arc::new(RefundRepository::new(db.clone()));
+    let statement_repository = Arc::new(StatementRepository::new(db.clone()));
+    let scheduled_payment_repository = Arc::new(ScheduledPaymentRepository::new(db.clone()));
+    let payment_request_repository = Arc::new(PaymentRequestRepository::new(db.clone()));
+    let dca_repository = Arc::new(DcaRepository::new(db.clone()));
+    let vault_repository = Arc::new(VaultRepository::new(db.clone()));
+    let merchant_repository = Arc::new(MerchantRepository::new(db.clone()));
+    let mobile_money_repository = Arc::new(MobileMoneyRepository::new(db.clone()));
     
     // Create external service clients
     let mpesa_client = Arc::new(MpesaClient::new());
     let lightning_client = Arc::new(LightningClient::new());
     let exchange_rate_client = Arc::new(ExchangeRateClient::new());
+    let lnurl_client = Arc::new(LnurlClient::new());
+
+    // Mobile money networks (Airtel Money once its credentials are configured)
+    let mpesa_provider = Arc::new(MpesaProvider::new(MpesaProviderConfig::from_env()));
+    let mut mobile_money_providers: Vec<Arc<dyn MobileMoneyProvider>> = vec![mpesa_provider.clone()];
+    if let Some(airtel_config) = AirtelMoneyConfig::from_env() {
+        mobile_money_providers.push(Arc::new(AirtelMoneyProvider::new(airtel_config)));
+    }
     
     // Create services
     let wallet_service = Arc::new(WalletService::new(wallet_repository.clone()));
+    let rate_history_service = Arc::new(RateHistoryService::new(rate_history_repository));
+    let fee_service = Arc::new(FeeService::new(fee_schedule_repository));
+    let history_service = Arc::new(HistoryService::new(history_repository));
+    let idempotency_service = Arc::new(IdempotencyService::new(idempotency_repository));
+    let limits_service = Arc::new(LimitsService::new(limits_repository));
+    let refund_service = Arc::new(RefundService::new(refund_repository));
+    let statement_service = Arc::new(StatementService::new(statement_repository));
+    let vault_service = Arc::new(VaultService::new(vault_repository));
     
     let payment_service = Arc::new(PaymentService::new(
         wallet_repository,
@@ -71,14 +173,231 @@ async fn main() -> Result<()> {
         mpesa_client,
         lightning_client,
         exchange_rate_client,
+        fee_service.clone(),
+    ));
+    let scheduled_payment_service = Arc::new(ScheduledPaymentService::new(
+        scheduled_payment_repository,
+        payment_service.clone(),
+        limits_service.clone(),
+        lnurl_client.clone(),
+    ));
+    let payment_request_service = Arc::new(PaymentRequestService::new(
+        payment_request_repository,
+        limits_service.clone(),
+    ));
+    let dca_service = Arc::new(DcaService::new(
+        dca_repository,
+        payment_service.clone(),
+        limits_service.clone(),
+    ));
+    let merchant_service = Arc::new(MerchantService::new(
+        merchant_repository,
+        payment_service.clone(),
+        limits_service.clone(),
     ));
+    let bill_payment_service = Arc::new(BillPaymentService::new(
+        mobile_money_repository.clone(),
+        mpesa_provider,
+        fee_service.clone(),
+        limits_service.clone(),
+    ));
+    let airtime_provider: Arc<dyn AirtimeProvider> = Arc::new(AfricasTalkingAirtime::new(AirtimeConfig::from_env()));
+    let airtime_service = Arc::new(AirtimeService::new(
+        Arc::new(AirtimeRepository::new(db.clone())),
+        airtime_provider,
+        limits_service.clone(),
+    ));
+    let mobile_money_service = Arc::new(MobileMoneyService::new(
+        mobile_money_repository,
+        MobileMoneyProviders::new(mobile_money_providers),
+        fee_service.clone(),
+        limits_service.clone(),
+    ));
+    let payout_service = Arc::new(PayoutService::new(
+        Arc::new(PayoutRepository::new(db.clone())),
+        payment_service.clone(),
+        mobile_money_service.clone(),
+        fee_service.clone(),
+        limits_service.clone(),
+        lnurl_client,
+    ));
+
+    // Publish domain events recorded in the outbox to Redis Streams
+    let outbox_relay = OutboxRelay::from_env(db.clone())?;
+    tokio::spawn(outbox_relay.run());
+
+    // Refund failed withdrawals, Lightning sends and unconverted deposits
+    let redis_url = std::env::var("REDIS_URL")
+        .unwrap_or_else(|_| "redis://:redis_dev_password@localhost:6379".to_string());
+    let redis = redis::Client::open(redis_url).map_err(|e| AppError::ExternalService {
+        message: format!("Invalid Redis URL: {}", e),
+    })?;
+    let consumer_name = std::env::var("HOSTNAME").unwrap_or_else(|_| uuid::Uuid::new_v4().to_string());
+    let consumer = EventConsumer::new(redis.clone(), REFUNDS_CONSUMER_GROUP, &consumer_name);
+    consumer.ensure_group().await?;
+    tokio::spawn(consume_refund_events(consumer, refund_service.clone()));
+
+    // Record deposit outcomes for DCA runs
+    let dca_consumer = EventConsumer::new(redis, DCA_CONSUMER_GROUP, &consumer_name);
+    dca_consumer.ensure_group().await?;
+    tokio::spawn(consume_dca_events(dca_consumer, dca_service.clone()));
 
     let state = AppState {
         payment_service,
         wallet_service,
+        rate_history_service,
+        fee_service,
+        history_service,
+        idempotency_service: idempotency_service.clone(),
+        limits_service,
+        refund_service,
+        statement_service: statement_service.clone(),
+        scheduled_payment_service: scheduled_payment_service.clone(),
+        payment_request_service: payment_request_service.clone(),
+        dca_service: dca_service.clone(),
+        vault_service: vault_service.clone(),
+        merchant_service: merchant_service.clone(),
+        mobile_money_service: mobile_money_service.clone(),
+        bill_payment_service,
+        airtime_service: airtime_service.clone(),
+        payout_service: payout_service.clone(),
         db,
     };
 
+    // Periodically delete expired idempotency keys
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(std::time::Duration::from_secs(3600));
+        loop {
+            interval.tick().await;
+            match idempotency_service.purge_expired().await {
+                Ok(purged) => info!("Purged {} expired idempotency keys", purged),
+                Err(e) => tracing::warn!("Failed to purge idempotency keys: {}", e),
+            }
+        }
+    });
+
+    // Generate queued statements and delete expired statement files
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(std::time::Duration::from_secs(15));
+        loop {
+            interval.tick().await;
+            match statement_service.process_pending(5).await {
+                Ok(0) => {}
+                Ok(generated) => info!("Generated {} queued statements", generated),
+                Err(e) => tracing::warn!("Failed to generate statements: {}", e),
+            }
+            match statement_service.expire().await {
+                Ok(0) => {}
+                Ok(expired) => info!("Expired {} statement files", expired),
+                Err(e) => tracing::warn!("Failed to expire statements: {}", e),
+            }
+        }
+    });
+
+    // Run scheduled payments that are due
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(std::time::Duration::from_secs(30));
+        loop {
+            interval.tick().await;
+            match scheduled_payment_service.run_due(20).await {
+                Ok(0) => {}
+                Ok(ran) => info!("Ran {} scheduled payments", ran),
+                Err(e) => tracing::warn!("Failed to run scheduled payments: {}", e),
+            }
+        }
+    });
+
+    // Send STK Pushes for DCA plans that are due
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(std::time::Duration::from_secs(30));
+        loop {
+            interval.tick().await;
+            match dca_service.run_due(20).await {
+                Ok(0) => {}
+                Ok(ran) => info!("Started {} DCA runs", ran),
+                Err(e) => tracing::warn!("Failed to run DCA plans: {}", e),
+            }
+        }
+    });
+
+    // Complete early vault withdrawals once their cooling-off delay has ended
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(std::time::Duration::from_secs(60));
+        loop {
+            interval.tick().await;
+            match vault_service.complete_due_withdrawals(50).await {
+                Ok(0) => {}
+                Ok(resolved) => info!("Resolved {} early vault withdrawals", resolved),
+                Err(e) => tracing::warn!("Failed to complete vault withdrawals: {}", e),
+            }
+        }
+    });
+
+    // Settle merchants' share of the previous day's incoming sats to M-Pesa
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(std::time::Duration::from_secs(600));
+        loop {
+            interval.tick().await;
+            match merchant_service.run_settlements(20).await {
+                Ok(0) => {}
+                Ok(settled) => info!("Settled {} merchants", settled),
+                Err(e) => tracing::warn!("Failed to run merchant settlements: {}", e),
+            }
+        }
+    });
+
+    // Expire unanswered payment requests
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(std::time::Duration::from_secs(60));
+        loop {
+            interval.tick().await;
+            match payment_request_service.expire().await {
+                Ok(0) => {}
+                Ok(expired) => info!("Expired {} payment requests", expired),
+                Err(e) => tracing::warn!("Failed to expire payment requests: {}", e),
+            }
+        }
+    });
+
+    // Ask mobile money providers about deposits and withdrawals whose callback never came
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(std::time::Duration::from_secs(60));
+        loop {
+            interval.tick().await;
+            match mobile_money_service.check_stale(50).await {
+                Ok(0) => {}
+                Ok(settled) => info!("Settled {} mobile money transactions from status checks", settled),
+                Err(e) => tracing::warn!("Failed to check mobile money transactions: {}", e),
+            }
+        }
+    });
+
+    // Pay queued payout batch rows, within each provider's rate limit
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(std::time::Duration::from_secs(10));
+        loop {
+            interval.tick().await;
+            match payout_service.run_due().await {
+                Ok(0) => {}
+                Ok(paid) => info!("Paid {} payout batch rows", paid),
+                Err(e) => tracing::warn!("Failed to pay payout batches: {}", e),
+            }
+        }
+    });
+
+    // Ask the airtime provider about purchases whose callback never came
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(std::time::Duration::from_secs(60));
+        loop {
+            interval.tick().await;
+            match airtime_service.check_stale(50).await {
+                Ok(0) => {}
+                Ok(settled) => info!("Settled {} airtime purchases from status checks", settled),
+                Err(e) => tracing::warn!("Failed to check airtime purchases: {}", e),
+            }
+        }
+    });
+
     // Build router with all endpoints
     let app = Router::new()
         .route("/health", get(health_check))
@@ -86,6 +405,9 @@ async fn main() -> Result<()> {
         // Wallet endpoints
         .route("/balance", get(get_balance))
         .route("/wallets/:user_id", post(create_wallet))
+
+        // KYC-tier limits and usage
+        .route("/limits", get(get_limits))
         
         // Deposit endpoints (M-Pesa → Bitcoin)
         .route("/deposits/mpesa", post(initiate_mpesa_deposit))
@@ -93,6 +415,21 @@ async fn main() -> Result<()> {
         
         // Withdrawal endpoints (Bitcoin → M-Pesa)
         .route("/withdrawals/mpesa", post(initiate_mpesa_withdrawal))
+
+        // Mobile money on any network (M-Pesa, Airtel Money)
+        .route("/mobile-money/providers", get(list_mobile_money_providers))
+        .route("/deposits/mobile-money", post(initiate_mobile_money_deposit))
+        .route("/withdrawals/mobile-money", post(initiate_mobile_money_withdrawal))
+        .route("/mobile-money/:provider/callback", post(mobile_money_callback))
+
+        // Paybill and Buy Goods payments (Bitcoin → M-Pesa business)
+        .route("/bill-payments/quote", get(quote_bill_payment))
+        .route("/bill-payments", post(pay_bill))
+
+        // Airtime top-ups for any Kenyan number
+        .route("/airtime/quote", get(quote_airtime))
+        .route("/airtime", post(buy_airtime))
+        .route("/airtime/callback", post(airtime_callback))
         
         // Lightning payments
         .route("/lightning/invoice", post(create_lightning_invoice))
@@ -101,9 +438,68 @@ async fn main() -> Result<()> {
         // Transaction history
         .route("/transactions", get(get_transaction_history))
         .route("/transactions/:id", get(get_transaction))
+
+        // Account statements (downloads use signed links instead of a login)
+        .route("/statements", post(create_statement).get(list_statements))
+        .route("/statements/:id", get(get_statement))
+        .route("/statements/:id/download", get(download_statement))
+
+        // Scheduled and recurring payments
+        .route("/scheduled-payments", post(create_scheduled_payment).get(list_scheduled_payments))
+        .route(
+            "/scheduled-payments/:id",
+            get(get_scheduled_payment).patch(update_scheduled_payment).delete(cancel_scheduled_payment),
+        )
+        .route("/scheduled-payments/:id/pause", post(pause_scheduled_payment))
+        .route("/scheduled-payments/:id/resume", post(resume_scheduled_payment))
+
+        // Payment requests between users
+        .route("/payment-requests", post(create_payment_request).get(list_payment_requests))
+        .route("/payment-requests/:id", get(get_payment_request))
+        .route("/payment-requests/:id/approve", post(approve_payment_request))
+        .route("/payment-requests/:id/decline", post(decline_payment_request))
+        .route("/payment-requests/:id/cancel", post(cancel_payment_request))
+
+        // Recurring buys from M-Pesa (DCA plans)
+        .route("/dca-plans", post(create_dca_plan).get(list_dca_plans))
+        .route(
+            "/dca-plans/:id",
+            get(get_dca_plan).patch(update_dca_plan).delete(cancel_dca_plan),
+        )
+        .route("/dca-plans/:id/pause", post(pause_dca_plan))
+        .route("/dca-plans/:id/resume", post(resume_dca_plan))
+
+        // Savings vaults
+        .route("/vaults", post(create_vault).get(list_vaults))
+        .route("/vaults/:id", get(get_vault).patch(update_vault).delete(close_vault))
+        .route("/vaults/:id/deposit", post(deposit_to_vault))
+        .route("/vaults/:id/withdraw", post(withdraw_from_vault))
+        .route("/vaults/:id/withdrawals/:withdrawal_id", delete(cancel_vault_withdrawal))
+
+        // Merchant accounts
+        .route("/merchant", post(create_merchant).get(get_merchant).patch(update_merchant))
+        .route("/merchant/invoices", post(create_pos_invoice).get(list_pos_invoices))
+        .route("/merchant/invoices/:id", get(get_pos_invoice))
+        .route("/merchant/reports/daily", get(get_sales_report))
+        .route("/merchant/settlements", get(list_merchant_settlements))
+
+        // Bulk payout batches
+        .route("/payouts", post(create_payout_batch).get(list_payout_batches))
+        .route("/payouts/:id", get(get_payout_batch))
+        .route("/payouts/:id/cancel", post(cancel_payout_batch))
         
         // Exchange rates
         .route("/exchange-rates/current", get(get_current_exchange_rate))
+        .route("/exchange-rates/history", get(get_exchange_rate_history))
+        
+        // Fees
+        .route("/fees/preview", get(preview_fees))
+        .route("/internal/fee-schedules", post(create_fee_schedule))
+
+        // Refunds (internal, operators only)
+        .route("/internal/refunds", post(create_manual_refund))
+        .route("/internal/refunds/payouts", get(list_pending_refund_payouts))
+        .route("/internal/refunds/:id/payout", post(record_refund_payout))
         
         .layer(CorsLayer::permissive())
         .layer(shared_tracing::trace_id_layer())
@@ -123,6 +519,64 @@ async fn main() -> Result<()> {
     Ok(())
 }
 
+/// Refund transactions whose failure events arrive on the stream
+/// Events are acknowledged once handled; refunding twice is prevented by the
+/// transaction's refunded status, so redelivery is harmless
+async fn consume_refund_events(consumer: EventConsumer, refund_service: Arc<RefundService>) {
+    loop {
+        let events = match consumer.read(50, Duration::from_secs(5)).await {
+            Ok(events) => events,
+            Err(e) => {
+                error!("Failed to read events: {}", e);
+                tokio::time::sleep(Duration::from_secs(5)).await;
+                continue;
+            }
+        };
+
+        let mut handled = Vec::with_capacity(events.len());
+        for event in events {
+            match refund_service.handle_event(&event.envelope).await {
+                Ok(_) => handled.push(event.stream_id),
+                Err(e) => error!("Failed to refund for event {}: {}", event.envelope.id, e),
+            }
+        }
+
+        if !handled.is_empty() {
+            if let Err(e) = consumer.ack(&handled).await {
+                error!("Failed to acknowledge events: {}", e);
+            }
+        }
+    }
+}
+
+/// Record deposit outcomes for DCA runs
+async fn consume_dca_events(consumer: EventConsumer, dca_service: Arc<DcaService>) {
+    loop {
+        let events = match consumer.read(50, Duration::from_secs(5)).await {
+            Ok(events) => events,
+            Err(e) => {
+                error!("Failed to read events: {}", e);
+                tokio::time::sleep(Duration::from_secs(5)).await;
+                continue;
+            }
+        };
+
+        let mut handled = Vec::with_capacity(events.len());
+        for event in events {
+            match dca_service.handle_event(&event.envelope).await {
+                Ok(()) => handled.push(event.stream_id),
+                Err(e) => error!("Failed to record DCA outcome for event {}: {}", event.envelope.id, e),
+            }
+        }
+
+        if !handled.is_empty() {
+            if let Err(e) = consumer.ack(&handled).await {
+                error!("Failed to acknowledge events: {}", e);
+            }
+        }
+    }
+}
+
 /// Health check endpoint
 #[instrument]
 async fn health_check(State(state): State<AppState>) -> Result<Json<serde_json::Value>> {
@@ -143,7 +597,18 @@ async fn get_balance(
     auth_user: AuthUser,
 ) -> Result<Json<WalletBalance>> {
     let balance = state.wallet_service.get_balance(auth_user.user_id).await?;
-    Ok(Json(balance))
+    let vaulted_sats = state.vault_service.total(auth_user.user_id).await?;
+    Ok(Json(balance.with_vaults(vaulted_sats)))
+}
+
+/// Get the user's KYC-tier limits and how much of them is used
+#[instrument(skip(state))]
+async fn get_limits(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+) -> Result<Json<LimitsResponse>> {
+    let limits = state.limits_service.usage(auth_user.user_id).await?;
+    Ok(Json(limits))
 }
 
 /// Create wallet for new user (internal endpoint called by user service)
@@ -161,39 +626,175 @@ async fn create_wallet(
 }
 
 /// Initiate M-Pesa deposit (user adds money via M-Pesa)
+/// Requires an Idempotency-Key header; retries replay the original response
 #[instrument(skip(state))]
 async fn initiate_mpesa_deposit(
     State(state): State<AppState>,
     auth_user: AuthUser,
-    Json(request): Json<MpesaDepositRequest>,
-) -> Result<Json<MpesaDepositResponse>> {
-    let response = state.payment_service
-        .initiate_mpesa_deposit(auth_user.user_id, request)
-        .await?;
-    Ok(Json(response))
+    Idempotent { key, body: request }: Idempotent<MpesaDepositRequest>,
+) -> Result<Response> {
+    state.idempotency_service
+        .execute(auth_user.user_id, &key, || async {
+            state.limits_service
+                .within_kes(auth_user.user_id, request.amount_kes.into(), || {
+                    state.payment_service.initiate_mpesa_deposit(auth_user.user_id, request)
+                })
+                .await
+        })
+        .await
 }
 
 /// M-Pesa callback webhook (called by Safaricom when payment completes)
-#[instrument(skip(state))]
+/// Needs the mobile money callback token; the outcome is confirmed with M-Pesa before it's applied
+#[instrument(skip(state, params, payload))]
 async fn mpesa_deposit_callback(
     State(state): State<AppState>,
-    Json(callback): Json<MpesaCallback>,
+    Query(params): Query<ProviderCallbackParams>,
+    Json(payload): Json<serde_json::Value>,
 ) -> Result<Json<serde_json::Value>> {
-    state.payment_service.process_mpesa_callback(callback).await?;
+    let confirmed = state.mobile_money_service
+        .verify_legacy_deposit_callback(params.token.as_deref(), &payload)
+        .await?;
+    if confirmed {
+        let callback: MpesaCallback = serde_json::from_value(payload).map_err(|e| AppError::Validation {
+            message: format!("Invalid M-Pesa callback: {}", e),
+        })?;
+        state.payment_service.process_mpesa_callback(callback).await?;
+    }
     Ok(Json(serde_json::json!({"status": "processed"})))
 }
 
 /// Initiate M-Pesa withdrawal (user cashes out Bitcoin to M-Pesa)
+/// Requires an Idempotency-Key header; retries replay the original response
 #[instrument(skip(state))]
 async fn initiate_mpesa_withdrawal(
     State(state): State<AppState>,
     auth_user: AuthUser,
-    Json(request): Json<MpesaWithdrawalRequest>,
-) -> Result<Json<MpesaWithdrawalResponse>> {
-    let response = state.payment_service
-        .initiate_mpesa_withdrawal(auth_user.user_id, request)
+    Idempotent { key, body: request }: Idempotent<MpesaWithdrawalRequest>,
+) -> Result<Response> {
+    state.idempotency_service
+        .execute(auth_user.user_id, &key, || async {
+            state.limits_service
+                .within_sats(auth_user.user_id, SatAmount::new(request.amount_sats), || {
+                    state.payment_service.initiate_mpesa_withdrawal(auth_user.user_id, request)
+                })
+                .await
+        })
+        .await
+}
+
+/// Mobile money networks available for deposits and withdrawals
+#[instrument(skip(state))]
+async fn list_mobile_money_providers(
+    State(state): State<AppState>,
+    _auth_user: AuthUser,
+) -> Result<Json<Vec<MobileMoneyOption>>> {
+    Ok(Json(state.mobile_money_service.options()))
+}
+
+/// Deposit from a phone on any mobile money network
+/// Requires an Idempotency-Key header; retries replay the original response
+#[instrument(skip(state))]
+async fn initiate_mobile_money_deposit(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    Idempotent { key, body: request }: Idempotent<MobileMoneyDepositRequest>,
+) -> Result<Response> {
+    state.idempotency_service
+        .execute(auth_user.user_id, &key, || async {
+            state.mobile_money_service.deposit(auth_user.user_id, request).await
+        })
+        .await
+}
+
+/// Withdraw to a phone on any mobile money network
+/// Requires an Idempotency-Key header; retries replay the original response
+#[instrument(skip(state))]
+async fn initiate_mobile_money_withdrawal(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    Idempotent { key, body: request }: Idempotent<MobileMoneyWithdrawalRequest>,
+) -> Result<Response> {
+    state.idempotency_service
+        .execute(auth_user.user_id, &key, || async {
+            state.mobile_money_service.withdraw(auth_user.user_id, request).await
+        })
+        .await
+}
+
+/// Result callback from a mobile money provider
+#[instrument(skip(state, params, payload))]
+async fn mobile_money_callback(
+    State(state): State<AppState>,
+    Path(provider): Path<MobileMoneyNetwork>,
+    Query(params): Query<ProviderCallbackParams>,
+    Json(payload): Json<serde_json::Value>,
+) -> Result<Json<serde_json::Value>> {
+    state.mobile_money_service
+        .handle_callback(provider, params.token.as_deref(), &payload)
         .await?;
-    Ok(Json(response))
+    Ok(Json(serde_json::json!({"status": "processed"})))
+}
+
+/// Sats cost of a bill at the current rate
+#[instrument(skip(state))]
+async fn quote_bill_payment(
+    State(state): State<AppState>,
+    _auth_user: AuthUser,
+    Query(params): Query<BillQuoteParams>,
+) -> Result<Json<BillPaymentQuote>> {
+    Ok(Json(state.bill_payment_service.quote(params).await?))
+}
+
+/// Pay a Paybill number or Buy Goods till from the wallet
+/// Requires an Idempotency-Key header; retries replay the original response
+#[instrument(skip(state))]
+async fn pay_bill(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    Idempotent { key, body: request }: Idempotent<BillPaymentRequest>,
+) -> Result<Response> {
+    state.idempotency_service
+        .execute(auth_user.user_id, &key, || async {
+            state.bill_payment_service.pay(auth_user.user_id, request).await
+        })
+        .await
+}
+
+/// Sats cost of airtime at the current rate
+#[instrument(skip(state))]
+async fn quote_airtime(
+    State(state): State<AppState>,
+    _auth_user: AuthUser,
+    Query(params): Query<AirtimeQuoteParams>,
+) -> Result<Json<AirtimeQuote>> {
+    Ok(Json(state.airtime_service.quote(params).await?))
+}
+
+/// Buy airtime for any Kenyan number from the wallet
+/// Requires an Idempotency-Key header; retries replay the original response
+#[instrument(skip(state))]
+async fn buy_airtime(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    Idempotent { key, body: request }: Idempotent<BuyAirtimeRequest>,
+) -> Result<Response> {
+    state.idempotency_service
+        .execute(auth_user.user_id, &key, || async {
+            state.airtime_service.buy(auth_user.user_id, request).await
+        })
+        .await
+}
+
+/// Delivery status callback from the airtime provider (form encoded)
+#[instrument(skip(state, params, fields))]
+async fn airtime_callback(
+    State(state): State<AppState>,
+    Query(params): Query<ProviderCallbackParams>,
+    Form(fields): Form<HashMap<String, String>>,
+) -> Result<Json<serde_json::Value>> {
+    state.airtime_service.handle_callback(params.token.as_deref(), &fields).await?;
+    Ok(Json(serde_json::json!({"status": "processed"})))
 }
 
 /// Create Lightning invoice for receiving payment
@@ -203,23 +804,32 @@ async fn create_lightning_invoice(
     auth_user: AuthUser,
     Json(request): Json<CreateInvoiceRequest>,
 ) -> Result<Json<CreateInvoiceResponse>> {
-    let response = state.payment_service
-        .create_lightning_invoice(auth_user.user_id, request)
+    let response = state.limits_service
+        .within_sats(auth_user.user_id, SatAmount::new(request.amount_sats), || {
+            state.payment_service.create_lightning_invoice(auth_user.user_id, request)
+        })
         .await?;
     Ok(Json(response))
 }
 
 /// Pay Lightning invoice (user sends money via Lightning)
+/// Requires an Idempotency-Key header; retries replay the original response
 #[instrument(skip(state))]
 async fn pay_lightning_invoice(
     State(state): State<AppState>,
     auth_user: AuthUser,
-    Json(request): Json<PayInvoiceRequest>,
-) -> Result<Json<PayInvoiceResponse>> {
-    let response = state.payment_service
-        .pay_lightning_invoice(auth_user.user_id, request)
-        .await?;
-    Ok(Json(response))
+    Idempotent { key, body: request }: Idempotent<PayInvoiceRequest>,
+) -> Result<Response> {
+    state.idempotency_service
+        .execute(auth_user.user_id, &key, || async {
+            let bolt11 = request.bolt11_invoice.clone();
+            state.limits_service
+                .within_invoice(auth_user.user_id, &bolt11, || {
+                    state.payment_service.pay_lightning_invoice(auth_user.user_id, request)
+                })
+                .await
+        })
+        .await
 }
 
 /// Get user's transaction history
@@ -229,7 +839,7 @@ async fn get_transaction_history(
     auth_user: AuthUser,
     Query(params): Query<TransactionHistoryParams>,
 ) -> Result<Json<TransactionHistoryResponse>> {
-    let response = state.payment_service
+    let response = state.history_service
         .get_transaction_history(auth_user.user_id, params)
         .await?;
     Ok(Json(response))
@@ -251,6 +861,568 @@ async fn get_transaction(
     Ok(Json(transaction))
 }
 
+/// Request a statement
+/// Short periods are ready straight away (201); longer ones are generated in the background (202)
+#[instrument(skip(state))]
+async fn create_statement(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    Json(request): Json<CreateStatementRequest>,
+) -> Result<(StatusCode, Json<StatementResponse>)> {
+    let statement = state.statement_service.request(auth_user.user_id, request).await?;
+    let status = match statement.status {
+        StatementStatus::Ready => StatusCode::CREATED,
+        _ => StatusCode::ACCEPTED,
+    };
+    Ok((status, Json(statement)))
+}
+
+/// List the user's recent statements
+#[instrument(skip(state))]
+async fn list_statements(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+) -> Result<Json<Vec<StatementResponse>>> {
+    let statements = state.statement_service.list(auth_user.user_id).await?;
+    Ok(Json(statements))
+}
+
+/// Get a statement's status, with a fresh download link once it is ready
+#[instrument(skip(state))]
+async fn get_statement(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    Path(statement_id): Path<String>,
+) -> Result<Json<StatementResponse>> {
+    let statement_id = statement_id.parse::<uuid::Uuid>()
+        .map_err(|_| AppError::Validation { message: "Invalid statement ID".to_string() })?;
+
+    let statement = state.statement_service.get(auth_user.user_id, statement_id).await?;
+    Ok(Json(statement))
+}
+
+/// Download a statement file through a signed link
+#[instrument(skip(state, params))]
+async fn download_statement(
+    State(state): State<AppState>,
+    Path(statement_id): Path<String>,
+    Query(params): Query<StatementDownloadParams>,
+) -> Result<Response> {
+    let statement_id = statement_id.parse::<uuid::Uuid>()
+        .map_err(|_| AppError::Validation { message: "Invalid statement ID".to_string() })?;
+
+    let file = state.statement_service.download(statement_id, params).await?;
+    Ok((
+        [
+            (header::CONTENT_TYPE, file.format.content_type().to_string()),
+            (header::CONTENT_DISPOSITION, format!("attachment; filename=\"{}\"", file.file_name)),
+            (header::CACHE_CONTROL, "private, no-store".to_string()),
+        ],
+        file.content,
+    )
+        .into_response())
+}
+
+/// Schedule a one-off or recurring payment
+#[instrument(skip(state, request))]
+async fn create_scheduled_payment(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    Json(request): Json<CreateScheduledPaymentRequest>,
+) -> Result<(StatusCode, Json<ScheduledPayment>)> {
+    let payment = state.scheduled_payment_service.create(auth_user.user_id, request).await?;
+    Ok((StatusCode::CREATED, Json(payment)))
+}
+
+/// List the user's scheduled payments
+#[instrument(skip(state))]
+async fn list_scheduled_payments(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+) -> Result<Json<Vec<ScheduledPayment>>> {
+    let payments = state.scheduled_payment_service.list(auth_user.user_id).await?;
+    Ok(Json(payments))
+}
+
+/// Get a scheduled payment with its recent runs
+#[instrument(skip(state))]
+async fn get_scheduled_payment(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    Path(payment_id): Path<String>,
+) -> Result<Json<ScheduledPaymentDetails>> {
+    let payment_id = parse_scheduled_payment_id(&payment_id)?;
+    let payment = state.scheduled_payment_service.get(auth_user.user_id, payment_id).await?;
+    Ok(Json(payment))
+}
+
+/// Edit a scheduled payment
+#[instrument(skip(state, request))]
+async fn update_scheduled_payment(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    Path(payment_id): Path<String>,
+    Json(request): Json<UpdateScheduledPaymentRequest>,
+) -> Result<Json<ScheduledPayment>> {
+    let payment_id = parse_scheduled_payment_id(&payment_id)?;
+    let payment = state.scheduled_payment_service.update(auth_user.user_id, payment_id, request).await?;
+    Ok(Json(payment))
+}
+
+/// Cancel a scheduled payment
+#[instrument(skip(state))]
+async fn cancel_scheduled_payment(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    Path(payment_id): Path<String>,
+) -> Result<Json<ScheduledPayment>> {
+    let payment_id = parse_scheduled_payment_id(&payment_id)?;
+    let payment = state.scheduled_payment_service.cancel(auth_user.user_id, payment_id).await?;
+    Ok(Json(payment))
+}
+
+/// Pause a scheduled payment
+#[instrument(skip(state))]
+async fn pause_scheduled_payment(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    Path(payment_id): Path<String>,
+) -> Result<Json<ScheduledPayment>> {
+    let payment_id = parse_scheduled_payment_id(&payment_id)?;
+    let payment = state.scheduled_payment_service.pause(auth_user.user_id, payment_id).await?;
+    Ok(Json(payment))
+}
+
+/// Resume a paused scheduled payment
+#[instrument(skip(state))]
+async fn resume_scheduled_payment(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    Path(payment_id): Path<String>,
+) -> Result<Json<ScheduledPayment>> {
+    let payment_id = parse_scheduled_payment_id(&payment_id)?;
+    let payment = state.scheduled_payment_service.resume(auth_user.user_id, payment_id).await?;
+    Ok(Json(payment))
+}
+
+fn parse_scheduled_payment_id(id: &str) -> Result<uuid::Uuid> {
+    id.parse::<uuid::Uuid>()
+        .map_err(|_| AppError::Validation { message: "Invalid scheduled payment ID".to_string() })
+}
+
+/// Ask another user for money
+#[instrument(skip(state, request))]
+async fn create_payment_request(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    Json(request): Json<CreatePaymentRequestRequest>,
+) -> Result<(StatusCode, Json<PaymentRequestResponse>)> {
+    let payment_request = state.payment_request_service.create(auth_user.user_id, request).await?;
+    Ok((StatusCode::CREATED, Json(payment_request)))
+}
+
+/// List payment requests the user sent or received
+#[instrument(skip(state))]
+async fn list_payment_requests(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    Query(params): Query<ListPaymentRequestsParams>,
+) -> Result<Json<Vec<PaymentRequestResponse>>> {
+    let payment_requests = state.payment_request_service.list(auth_user.user_id, params).await?;
+    Ok(Json(payment_requests))
+}
+
+/// Get a payment request
+#[instrument(skip(state))]
+async fn get_payment_request(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    Path(request_id): Path<String>,
+) -> Result<Json<PaymentRequestResponse>> {
+    let request_id = parse_payment_request_id(&request_id)?;
+    let payment_request = state.payment_request_service.get(auth_user.user_id, request_id).await?;
+    Ok(Json(payment_request))
+}
+
+/// Pay a payment request
+#[instrument(skip(state))]
+async fn approve_payment_request(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    Path(request_id): Path<String>,
+) -> Result<Json<PaymentRequestResponse>> {
+    let request_id = parse_payment_request_id(&request_id)?;
+    let payment_request = state.payment_request_service.approve(auth_user.user_id, request_id).await?;
+    Ok(Json(payment_request))
+}
+
+/// Decline a payment request
+#[instrument(skip(state))]
+async fn decline_payment_request(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    Path(request_id): Path<String>,
+) -> Result<Json<PaymentRequestResponse>> {
+    let request_id = parse_payment_request_id(&request_id)?;
+    let payment_request = state.payment_request_service.decline(auth_user.user_id, request_id).await?;
+    Ok(Json(payment_request))
+}
+
+/// Withdraw a payment request the user sent
+#[instrument(skip(state))]
+async fn cancel_payment_request(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    Path(request_id): Path<String>,
+) -> Result<Json<PaymentRequestResponse>> {
+    let request_id = parse_payment_request_id(&request_id)?;
+    let payment_request = state.payment_request_service.cancel(auth_user.user_id, request_id).await?;
+    Ok(Json(payment_request))
+}
+
+fn parse_payment_request_id(id: &str) -> Result<uuid::Uuid> {
+    id.parse::<uuid::Uuid>()
+        .map_err(|_| AppError::Validation { message: "Invalid payment request ID".to_string() })
+}
+
+/// Start a recurring buy
+#[instrument(skip(state, request))]
+async fn create_dca_plan(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    Json(request): Json<CreateDcaPlanRequest>,
+) -> Result<(StatusCode, Json<DcaPlan>)> {
+    let plan = state.dca_service.create(auth_user.user_id, request).await?;
+    Ok((StatusCode::CREATED, Json(plan)))
+}
+
+/// List the user's recurring buys
+#[instrument(skip(state))]
+async fn list_dca_plans(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+) -> Result<Json<Vec<DcaPlan>>> {
+    let plans = state.dca_service.list(auth_user.user_id).await?;
+    Ok(Json(plans))
+}
+
+/// Get a recurring buy with its purchase history and average price
+#[instrument(skip(state))]
+async fn get_dca_plan(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    Path(plan_id): Path<String>,
+) -> Result<Json<DcaPlanDetails>> {
+    let plan_id = parse_dca_plan_id(&plan_id)?;
+    let plan = state.dca_service.get(auth_user.user_id, plan_id).await?;
+    Ok(Json(plan))
+}
+
+/// Change the amount or schedule of a recurring buy
+#[instrument(skip(state, request))]
+async fn update_dca_plan(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    Path(plan_id): Path<String>,
+    Json(request): Json<UpdateDcaPlanRequest>,
+) -> Result<Json<DcaPlan>> {
+    let plan_id = parse_dca_plan_id(&plan_id)?;
+    let plan = state.dca_service.update(auth_user.user_id, plan_id, request).await?;
+    Ok(Json(plan))
+}
+
+/// Cancel a recurring buy
+#[instrument(skip(state))]
+async fn cancel_dca_plan(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    Path(plan_id): Path<String>,
+) -> Result<Json<DcaPlan>> {
+    let plan_id = parse_dca_plan_id(&plan_id)?;
+    let plan = state.dca_service.cancel(auth_user.user_id, plan_id).await?;
+    Ok(Json(plan))
+}
+
+/// Pause a recurring buy
+#[instrument(skip(state))]
+async fn pause_dca_plan(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    Path(plan_id): Path<String>,
+) -> Result<Json<DcaPlan>> {
+    let plan_id = parse_dca_plan_id(&plan_id)?;
+    let plan = state.dca_service.pause(auth_user.user_id, plan_id).await?;
+    Ok(Json(plan))
+}
+
+/// Resume a paused recurring buy
+#[instrument(skip(state))]
+async fn resume_dca_plan(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    Path(plan_id): Path<String>,
+) -> Result<Json<DcaPlan>> {
+    let plan_id = parse_dca_plan_id(&plan_id)?;
+    let plan = state.dca_service.resume(auth_user.user_id, plan_id).await?;
+    Ok(Json(plan))
+}
+
+fn parse_dca_plan_id(id: &str) -> Result<uuid::Uuid> {
+    id.parse::<uuid::Uuid>()
+        .map_err(|_| AppError::Validation { message: "Invalid DCA plan ID".to_string() })
+}
+
+/// Open a savings vault
+#[instrument(skip(state, request))]
+async fn create_vault(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    Json(request): Json<CreateVaultRequest>,
+) -> Result<(StatusCode, Json<Vault>)> {
+    let vault = state.vault_service.create(auth_user.user_id, request).await?;
+    Ok((StatusCode::CREATED, Json(vault)))
+}
+
+/// List the user's vaults
+#[instrument(skip(state))]
+async fn list_vaults(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+) -> Result<Json<Vec<Vault>>> {
+    let vaults = state.vault_service.list(auth_user.user_id).await?;
+    Ok(Json(vaults))
+}
+
+/// Get a vault with its recent entries and pending early withdrawals
+#[instrument(skip(state))]
+async fn get_vault(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    Path(vault_id): Path<String>,
+) -> Result<Json<VaultDetails>> {
+    let vault_id = parse_vault_id(&vault_id)?;
+    let vault = state.vault_service.get(auth_user.user_id, vault_id).await?;
+    Ok(Json(vault))
+}
+
+/// Rename a vault, change its target or extend its lock
+#[instrument(skip(state, request))]
+async fn update_vault(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    Path(vault_id): Path<String>,
+    Json(request): Json<UpdateVaultRequest>,
+) -> Result<Json<Vault>> {
+    let vault_id = parse_vault_id(&vault_id)?;
+    let vault = state.vault_service.update(auth_user.user_id, vault_id, request).await?;
+    Ok(Json(vault))
+}
+
+/// Close an empty vault
+#[instrument(skip(state))]
+async fn close_vault(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    Path(vault_id): Path<String>,
+) -> Result<Json<Vault>> {
+    let vault_id = parse_vault_id(&vault_id)?;
+    let vault = state.vault_service.close(auth_user.user_id, vault_id).await?;
+    Ok(Json(vault))
+}
+
+/// Move sats from the spendable balance into a vault
+#[instrument(skip(state, request))]
+async fn deposit_to_vault(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    Path(vault_id): Path<String>,
+    Json(request): Json<VaultDepositRequest>,
+) -> Result<Json<Vault>> {
+    let vault_id = parse_vault_id(&vault_id)?;
+    let vault = state.vault_service.deposit(auth_user.user_id, vault_id, request).await?;
+    Ok(Json(vault))
+}
+
+/// Move sats out of a vault; locked vaults need the PIN and a cooling-off delay
+#[instrument(skip(state, request))]
+async fn withdraw_from_vault(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    Path(vault_id): Path<String>,
+    Json(request): Json<VaultWithdrawRequest>,
+) -> Result<(StatusCode, Json<VaultWithdrawResponse>)> {
+    let vault_id = parse_vault_id(&vault_id)?;
+    let response = state.vault_service.withdraw(auth_user.user_id, vault_id, request).await?;
+    let status = if response.scheduled.is_some() { StatusCode::ACCEPTED } else { StatusCode::OK };
+    Ok((status, Json(response)))
+}
+
+/// Cancel an early withdrawal during its cooling-off delay
+#[instrument(skip(state))]
+async fn cancel_vault_withdrawal(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    Path((vault_id, withdrawal_id)): Path<(String, String)>,
+) -> Result<Json<VaultDetails>> {
+    let vault_id = parse_vault_id(&vault_id)?;
+    let withdrawal_id = withdrawal_id
+        .parse::<uuid::Uuid>()
+        .map_err(|_| AppError::Validation { message: "Invalid withdrawal ID".to_string() })?;
+    let vault = state
+        .vault_service
+        .cancel_withdrawal(auth_user.user_id, vault_id, withdrawal_id)
+        .await?;
+    Ok(Json(vault))
+}
+
+fn parse_vault_id(id: &str) -> Result<uuid::Uuid> {
+    id.parse::<uuid::Uuid>()
+        .map_err(|_| AppError::Validation { message: "Invalid vault ID".to_string() })
+}
+
+/// Check a batch of payouts and queue it for payment
+/// Requires an Idempotency-Key header; retries replay the original response
+#[instrument(skip(state, request))]
+async fn create_payout_batch(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    Idempotent { key, body: request }: Idempotent<CreatePayoutBatchRequest>,
+) -> Result<Response> {
+    state.idempotency_service
+        .execute(auth_user.user_id, &key, || async {
+            state.payout_service.create(auth_user.user_id, request).await
+        })
+        .await
+}
+
+/// List the user's payout batches
+#[instrument(skip(state))]
+async fn list_payout_batches(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+) -> Result<Json<Vec<PayoutBatch>>> {
+    let batches = state.payout_service.list(auth_user.user_id).await?;
+    Ok(Json(batches))
+}
+
+/// Get a payout batch with the status of every row
+#[instrument(skip(state))]
+async fn get_payout_batch(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    Path(batch_id): Path<String>,
+) -> Result<Json<PayoutBatchDetails>> {
+    let batch_id = parse_payout_batch_id(&batch_id)?;
+    let batch = state.payout_service.get(auth_user.user_id, batch_id).await?;
+    Ok(Json(batch))
+}
+
+/// Cancel a payout batch; rows not yet started are dropped
+#[instrument(skip(state))]
+async fn cancel_payout_batch(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    Path(batch_id): Path<String>,
+) -> Result<Json<PayoutBatch>> {
+    let batch_id = parse_payout_batch_id(&batch_id)?;
+    let batch = state.payout_service.cancel(auth_user.user_id, batch_id).await?;
+    Ok(Json(batch))
+}
+
+fn parse_payout_batch_id(id: &str) -> Result<uuid::Uuid> {
+    id.parse::<uuid::Uuid>()
+        .map_err(|_| AppError::Validation { message: "Invalid payout batch ID".to_string() })
+}
+
+/// Set up the business profile of a merchant account
+#[instrument(skip(state, request))]
+async fn create_merchant(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    Json(request): Json<CreateMerchantRequest>,
+) -> Result<(StatusCode, Json<Merchant>)> {
+    let merchant = state.merchant_service.create(auth_user.user_id, request).await?;
+    Ok((StatusCode::CREATED, Json(merchant)))
+}
+
+/// Get the business profile and settlement preferences
+#[instrument(skip(state))]
+async fn get_merchant(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+) -> Result<Json<Merchant>> {
+    let merchant = state.merchant_service.get(auth_user.user_id).await?;
+    Ok(Json(merchant))
+}
+
+/// Update the business profile or settlement preferences
+#[instrument(skip(state, request))]
+async fn update_merchant(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    Json(request): Json<UpdateMerchantRequest>,
+) -> Result<Json<Merchant>> {
+    let merchant = state.merchant_service.update(auth_user.user_id, request).await?;
+    Ok(Json(merchant))
+}
+
+/// Create a KES-priced POS invoice
+#[instrument(skip(state, request))]
+async fn create_pos_invoice(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    Json(request): Json<CreatePosInvoiceRequest>,
+) -> Result<(StatusCode, Json<PosInvoice>)> {
+    let invoice = state.merchant_service.create_pos_invoice(auth_user.user_id, request).await?;
+    Ok((StatusCode::CREATED, Json(invoice)))
+}
+
+/// List POS invoices, newest first
+#[instrument(skip(state))]
+async fn list_pos_invoices(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    Query(params): Query<ListPosInvoicesParams>,
+) -> Result<Json<Vec<PosInvoice>>> {
+    let invoices = state.merchant_service.list_pos_invoices(auth_user.user_id, params).await?;
+    Ok(Json(invoices))
+}
+
+/// Get a POS invoice and whether it has been paid
+#[instrument(skip(state))]
+async fn get_pos_invoice(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    Path(invoice_id): Path<String>,
+) -> Result<Json<PosInvoice>> {
+    let invoice_id = invoice_id
+        .parse::<uuid::Uuid>()
+        .map_err(|_| AppError::Validation { message: "Invalid invoice ID".to_string() })?;
+    let invoice = state.merchant_service.get_pos_invoice(auth_user.user_id, invoice_id).await?;
+    Ok(Json(invoice))
+}
+
+/// Daily sales for the merchant dashboard
+#[instrument(skip(state))]
+async fn get_sales_report(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    Query(params): Query<SalesReportParams>,
+) -> Result<Json<SalesReport>> {
+    let report = state.merchant_service.sales_report(auth_user.user_id, params).await?;
+    Ok(Json(report))
+}
+
+/// Recent daily settlements to M-Pesa
+#[instrument(skip(state))]
+async fn list_merchant_settlements(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+) -> Result<Json<Vec<MerchantSettlement>>> {
+    let settlements = state.merchant_service.settlements(auth_user.user_id).await?;
+    Ok(Json(settlements))
+}
+
 /// Get current BTC/KES exchange rate
 #[instrument(skip(state))]
 async fn get_current_exchange_rate(
@@ -258,4 +1430,66 @@ async fn get_current_exchange_rate(
 ) -> Result<Json<ExchangeRate>> {
     let rate = state.payment_service.get_current_exchange_rate().await?;
     Ok(Json(rate))
-}
\ No newline at end of file
+}
+
+/// Get historical BTC/KES rates as OHLC candles
+#[instrument(skip(state))]
+async fn get_exchange_rate_history(
+    State(state): State<AppState>,
+    Query(params): Query<RateHistoryParams>,
+) -> Result<Json<RateHistoryResponse>> {
+    let history = state.rate_history_service.get_history(params).await?;
+    Ok(Json(history))
+}
+
+/// Preview the fees for a payment before confirming it
+#[instrument(skip(state))]
+async fn preview_fees(
+    State(state): State<AppState>,
+    _auth_user: AuthUser,
+    Query(params): Query<FeePreviewParams>,
+) -> Result<Json<FeePreviewResponse>> {
+    let preview = state.fee_service.preview(params).await?;
+    Ok(Json(preview))
+}
+
+/// Publish a new fee schedule version (internal endpoint, not exposed via the gateway)
+#[instrument(skip(state, request))]
+async fn create_fee_schedule(
+    State(state): State<AppState>,
+    Json(request): Json<CreateFeeScheduleRequest>,
+) -> Result<Json<FeeSchedule>> {
+    let schedule = state.fee_service.create_schedule(request).await?;
+    Ok(Json(schedule))
+}
+
+/// Refund a transaction on an operator's request (internal endpoint, not exposed via the gateway)
+#[instrument(skip(state, request))]
+async fn create_manual_refund(
+    State(state): State<AppState>,
+    Json(request): Json<ManualRefundRequest>,
+) -> Result<Json<Refund>> {
+    let refund = state.refund_service.refund_manually(request).await?;
+    Ok(Json(refund))
+}
+
+/// KES refunds waiting to be paid out over M-Pesa (internal endpoint)
+#[instrument(skip(state))]
+async fn list_pending_refund_payouts(State(state): State<AppState>) -> Result<Json<Vec<Refund>>> {
+    let refunds = state.refund_service.pending_payouts().await?;
+    Ok(Json(refunds))
+}
+
+/// Record the M-Pesa payout of a KES refund (internal endpoint)
+#[instrument(skip(state, request))]
+async fn record_refund_payout(
+    State(state): State<AppState>,
+    Path(refund_id): Path<String>,
+    Json(request): Json<RecordPayoutRequest>,
+) -> Result<Json<Refund>> {
+    let refund_id = refund_id.parse::<uuid::Uuid>()
+        .map_err(|_| AppError::Validation { message: "Invalid refund ID".to_string() })?;
+
+    let refund = state.refund_service.record_payout(refund_id, request).await?;
+    Ok(Json(refund))
+}
@@ -0,0 +1,1024 @@
+/// Merchant accounts and point-of-sale invoices
+///
+/// Merchant accounts (users registered with account type `merchant`) set up a
+/// business profile with the till name customers see. POS invoices are priced in
+/// KES and locked to sats at the current rate when created; the Lightning invoice
+/// behind each one is an ordinary `lightning_receive` transaction; terminals can
+/// create them with an API key scoped to `create:invoice`. Each Nairobi day the
+/// configured share of the previous day's incoming sats is withdrawn to
+/// M-Pesa, and daily sales reports are built from `daily_transaction_metrics`.
+
+use crate::cron::NAIROBI_OFFSET_HOURS;
+use crate::domain::{CreateInvoiceRequest, CreateInvoiceResponse, MpesaWithdrawalRequest};
+use crate::limits::LimitsService;
+use crate::service::PaymentService;
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use shared_errors::{AppError, Result};
+use shared_types::conversion::{Rounding, Side};
+use shared_types::*;
+use sqlx::PgPool;
+use std::sync::Arc;
+use tracing::{info, instrument, warn};
+use uuid::Uuid;
+use validator::Validate;
+
+/// Largest POS invoice (same as a single M-Pesa deposit)
+const MAX_INVOICE_KES: i64 = 500_000;
+/// How long a POS invoice's locked price holds when the merchant doesn't say
+const DEFAULT_INVOICE_EXPIRY_SECONDS: i32 = 15 * 60;
+/// Smallest settlement (the M-Pesa withdrawal minimum)
+const MIN_SETTLEMENT_SATS: i64 = 1000;
+/// How long a worker may take to settle a merchant before another one retries
+const SETTLEMENT_LEASE_MINUTES: i64 = 10;
+/// Longest sales report (`daily_transaction_metrics` keeps 90 days)
+const MAX_REPORT_DAYS: i64 = 90;
+/// Settlements shown in the history
+const RECENT_SETTLEMENTS: i64 = 30;
+
+/// Payment state of a POS invoice
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PosInvoiceStatus {
+    Pending,
+    Paid,
+    Expired,
+    Failed,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[sqlx(type_name = "merchant_settlement_status", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum MerchantSettlementStatus {
+    Starting,
+    Initiated,
+    Skipped,
+    Failed,
+}
+
+/// Request to set up the business profile of a merchant account
+#[derive(Debug, Deserialize, Validate)]
+pub struct CreateMerchantRequest {
+    #[validate(length(min = 2, max = 100))]
+    pub business_name: String,
+    /// Shown to customers on invoices
+    #[validate(length(min = 2, max = 30))]
+    pub till_name: String,
+    #[validate(length(max = 50))]
+    pub business_category: Option<String>,
+    #[validate(length(equal = 11))]
+    pub kra_pin: Option<String>,
+    #[validate(email)]
+    pub contact_email: Option<String>,
+    /// Share of each day's incoming sats sent to M-Pesa (default 0, off)
+    #[validate(range(min = 0, max = 100))]
+    pub settlement_percent: Option<i16>,
+    /// Defaults to the account's phone number
+    pub settlement_phone: Option<String>,
+}
+
+/// Changes to the business profile and settlement preferences
+#[derive(Debug, Deserialize, Validate)]
+pub struct UpdateMerchantRequest {
+    #[validate(length(min = 2, max = 100))]
+    pub business_name: Option<String>,
+    #[validate(length(min = 2, max = 30))]
+    pub till_name: Option<String>,
+    #[validate(length(max = 50))]
+    pub business_category: Option<String>,
+    #[validate(length(equal = 11))]
+    pub kra_pin: Option<String>,
+    #[validate(email)]
+    pub contact_email: Option<String>,
+    #[validate(range(min = 0, max = 100))]
+    pub settlement_percent: Option<i16>,
+    pub settlement_phone: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Merchant {
+    pub user_id: UserId,
+    pub business_name: String,
+    pub till_name: String,
+    pub business_category: Option<String>,
+    pub kra_pin: Option<String>,
+    pub contact_email: Option<String>,
+    pub settlement_percent: i16,
+    pub settlement_phone: Option<String>,
+    /// Incoming sats before this have been settled
+    pub settled_until: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// Request for a POS invoice
+#[derive(Debug, Deserialize, Validate)]
+pub struct CreatePosInvoiceRequest {
+    /// Price in KES (up to two decimal places)
+    pub amount_kes: Decimal,
+    /// Merchant's order or receipt number
+    #[validate(length(min = 1, max = 64))]
+    pub reference: Option<String>,
+    #[validate(length(max = 200))]
+    pub description: Option<String>,
+    /// How long the locked price holds (default 15 minutes)
+    #[validate(range(min = 60, max = 3600))]
+    pub expiry_seconds: Option<i32>,
+}
+
+/// POS invoice listing parameters
+#[derive(Debug, Deserialize)]
+pub struct ListPosInvoicesParams {
+    /// Number of invoices to return (default: 20, max: 100)
+    pub limit: Option<i64>,
+    /// Only invoices created before this (for paging)
+    pub before: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PosInvoice {
+    pub id: Uuid,
+    pub transaction_id: Uuid,
+    pub till_name: String,
+    pub reference: Option<String>,
+    pub description: Option<String>,
+    pub amount_kes: KesAmount,
+    pub amount_sats: SatAmount,
+    /// KES per BTC the price was locked at
+    pub exchange_rate: Decimal,
+    pub payment_request: String,
+    pub status: PosInvoiceStatus,
+    pub expires_at: DateTime<Utc>,
+    pub paid_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Sales report date range (inclusive); defaults to the last 30 days
+#[derive(Debug, Deserialize)]
+pub struct SalesReportParams {
+    pub from_date: Option<NaiveDate>,
+    pub to_date: Option<NaiveDate>,
+}
+
+/// One day of completed transactions on a merchant account
+#[derive(Debug, Clone, Serialize)]
+pub struct DailySales {
+    pub date: NaiveDate,
+    /// Incoming payments (POS invoices and any other receipts)
+    pub payments_received: i64,
+    pub received_sats: SatAmount,
+    pub pos_invoices_paid: i64,
+    pub pos_sales_kes: KesAmount,
+    /// M-Pesa withdrawals, including settlements
+    pub withdrawn_sats: SatAmount,
+    pub withdrawn_kes: KesAmount,
+    pub fees_sats: SatAmount,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SalesReport {
+    pub from_date: NaiveDate,
+    pub to_date: NaiveDate,
+    pub days: Vec<DailySales>,
+    pub total_received_sats: SatAmount,
+    pub total_pos_sales_kes: KesAmount,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MerchantSettlement {
+    pub id: Uuid,
+    pub period_start: DateTime<Utc>,
+    pub period_end: DateTime<Utc>,
+    pub received_sats: SatAmount,
+    pub settlement_percent: i16,
+    pub amount_sats: SatAmount,
+    pub status: MerchantSettlementStatus,
+    pub transaction_id: Option<Uuid>,
+    pub error: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Merchant claimed for settlement by a worker
+#[derive(Debug, Clone)]
+pub struct DueSettlement {
+    pub merchant_id: UserId,
+    pub settlement_percent: i16,
+    pub settlement_phone: Option<String>,
+    pub period_start: DateTime<Utc>,
+}
+
+pub fn pos_invoice_status(
+    transaction_status: &TransactionStatus,
+    expires_at: DateTime<Utc>,
+    now: DateTime<Utc>,
+) -> PosInvoiceStatus {
+    match transaction_status {
+        TransactionStatus::Completed => PosInvoiceStatus::Paid,
+        TransactionStatus::Failed | TransactionStatus::Refunded => PosInvoiceStatus::Failed,
+        TransactionStatus::Pending | TransactionStatus::Processing if expires_at <= now => PosInvoiceStatus::Expired,
+        TransactionStatus::Pending | TransactionStatus::Processing => PosInvoiceStatus::Pending,
+    }
+}
+
+/// Start of the current day in Nairobi; settlements cover incoming sats up to here
+pub fn settlement_cutoff(now: DateTime<Utc>) -> DateTime<Utc> {
+    let offset = Duration::hours(NAIROBI_OFFSET_HOURS);
+    let midnight = (now + offset).date_naive().and_hms_opt(0, 0, 0).expect("midnight is valid");
+    midnight.and_utc() - offset
+}
+
+/// Sats settled from the day's receipts (rounded down)
+pub fn settlement_share(received_sats: i64, percent: i16) -> i64 {
+    received_sats * i64::from(percent) / 100
+}
+
+fn validate_invoice_amount(amount_kes: Decimal) -> Result<KesAmount> {
+    if amount_kes <= Decimal::ZERO || amount_kes > Decimal::from(MAX_INVOICE_KES) || amount_kes.scale() > 2 {
+        return Err(AppError::Validation {
+            message: format!("amount_kes must be between 0.01 and {} with at most two decimal places", MAX_INVOICE_KES),
+        });
+    }
+    Ok(KesAmount::new(amount_kes))
+}
+
+fn validate_settlement_phone(phone: Option<&str>) -> Result<()> {
+    if let Some(phone) = phone {
+        PhoneNumber::new(phone.to_string()).map_err(|_| AppError::invalid_phone_number())?;
+    }
+    Ok(())
+}
+
+/// Database access for merchants, API keys, POS invoices and settlements
+pub struct MerchantRepository {
+    pool: PgPool,
+}
+
+impl MerchantRepository {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+
+    #[instrument(skip(self))]
+    pub async fn account_type(&self, user_id: UserId) -> Result<Option<AccountType>> {
+        let account_type = sqlx::query_scalar!(
+            r#"SELECT account_type as "account_type: AccountType" FROM users WHERE id = $1"#,
+            user_id.0,
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(account_type)
+    }
+
+    /// Insert the business profile; `false` if the till name is taken
+    #[instrument(skip(self, request))]
+    pub async fn create(&self, user_id: UserId, request: &CreateMerchantRequest) -> Result<bool> {
+        let inserted = sqlx::query!(
+            r#"
+            INSERT INTO merchants (user_id, business_name, till_name, business_category, kra_pin,
+                                   contact_email, settlement_percent, settlement_phone)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+            ON CONFLICT DO NOTHING
+            "#,
+            user_id.0,
+            request.business_name.trim(),
+            request.till_name.trim(),
+            request.business_category,
+            request.kra_pin,
+            request.contact_email,
+            request.settlement_percent.unwrap_or(0),
+            request.settlement_phone,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(inserted.rows_affected() == 1)
+    }
+
+    #[instrument(skip(self))]
+    pub async fn get(&self, user_id: UserId) -> Result<Option<Merchant>> {
+        let row = sqlx::query!(
+            r#"
+            SELECT user_id, business_name, till_name, business_category, kra_pin, contact_email,
+                   settlement_percent, settlement_phone, settled_until, created_at, updated_at
+            FROM merchants
+            WHERE user_id = $1
+            "#,
+            user_id.0,
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.map(|r| Merchant {
+            user_id: UserId(r.user_id),
+            business_name: r.business_name,
+            till_name: r.till_name,
+            business_category: r.business_category,
+            kra_pin: r.kra_pin,
+            contact_email: r.contact_email,
+            settlement_percent: r.settlement_percent,
+            settlement_phone: r.settlement_phone,
+            settled_until: r.settled_until,
+            created_at: r.created_at,
+            updated_at: r.updated_at,
+        }))
+    }
+
+    #[instrument(skip(self))]
+    pub async fn till_name_taken(&self, user_id: UserId, till_name: &str) -> Result<bool> {
+        let taken = sqlx::query_scalar!(
+            r#"
+            SELECT EXISTS (
+                SELECT 1 FROM merchants WHERE user_id != $1 AND LOWER(till_name) = LOWER($2)
+            ) AS "taken!"
+            "#,
+            user_id.0,
+            till_name,
+        )
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(taken)
+    }
+
+    #[instrument(skip(self, merchant))]
+    pub async fn update(&self, merchant: &Merchant) -> Result<()> {
+        sqlx::query!(
+            r#"
+            UPDATE merchants
+            SET business_name = $2, till_name = $3, business_category = $4, kra_pin = $5,
+                contact_email = $6, settlement_percent = $7, settlement_phone = $8
+            WHERE user_id = $1
+            "#,
+            merchant.user_id.0,
+            merchant.business_name,
+            merchant.till_name,
+            merchant.business_category,
+            merchant.kra_pin,
+            merchant.contact_email,
+            merchant.settlement_percent,
+            merchant.settlement_phone,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    #[instrument(skip(self, request, rate, invoice))]
+    pub async fn create_pos_invoice(
+        &self,
+        merchant_id: UserId,
+        request: &CreatePosInvoiceRequest,
+        amount_sats: SatAmount,
+        rate: BtcKesRate,
+        transaction_id: Uuid,
+        invoice: &CreateInvoiceResponse,
+    ) -> Result<Uuid> {
+        let row = sqlx::query!(
+            r#"
+            INSERT INTO pos_invoices (merchant_id, transaction_id, amount_kes, amount_sats, exchange_rate,
+                                      payment_request, reference, description, expires_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+            RETURNING id
+            "#,
+            merchant_id.0,
+            transaction_id,
+            request.amount_kes,
+            amount_sats.as_i64(),
+            rate.kes_per_btc(),
+            invoice.payment_request,
+            request.reference,
+            request.description,
+            invoice.expires_at,
+        )
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(row.id)
+    }
+
+    #[instrument(skip(self))]
+    pub async fn get_pos_invoice(&self, merchant_id: UserId, id: Uuid) -> Result<Option<PosInvoice>> {
+        let now = Utc::now();
+        let row = sqlx::query!(
+            r#"
+            SELECT p.id, p.transaction_id, m.till_name, p.reference, p.description, p.amount_kes,
+                   p.amount_sats, p.exchange_rate, p.payment_request, p.expires_at, p.created_at,
+                   t.status as "status: TransactionStatus", t.completed_at
+            FROM pos_invoices p
+            JOIN merchants m ON m.user_id = p.merchant_id
+            JOIN transactions t ON t.id = p.transaction_id
+            WHERE p.id = $1 AND p.merchant_id = $2
+            "#,
+            id,
+            merchant_id.0,
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.map(|r| PosInvoice {
+            id: r.id,
+            transaction_id: r.transaction_id,
+            till_name: r.till_name,
+            reference: r.reference,
+            description: r.description,
+            amount_kes: KesAmount::new(r.amount_kes),
+            amount_sats: SatAmount::new(r.amount_sats),
+            exchange_rate: r.exchange_rate,
+            payment_request: r.payment_request,
+            status: pos_invoice_status(&r.status, r.expires_at, now),
+            expires_at: r.expires_at,
+            paid_at: r.completed_at,
+            created_at: r.created_at,
+        }))
+    }
+
+    #[instrument(skip(self))]
+    pub async fn list_pos_invoices(
+        &self,
+        merchant_id: UserId,
+        before: Option<DateTime<Utc>>,
+        limit: i64,
+    ) -> Result<Vec<PosInvoice>> {
+        let now = Utc::now();
+        let rows = sqlx::query!(
+            r#"
+            SELECT p.id, p.transaction_id, m.till_name, p.reference, p.description, p.amount_kes,
+                   p.amount_sats, p.exchange_rate, p.payment_request, p.expires_at, p.created_at,
+                   t.status as "status: TransactionStatus", t.completed_at
+            FROM pos_invoices p
+            JOIN merchants m ON m.user_id = p.merchant_id
+            JOIN transactions t ON t.id = p.transaction_id
+            WHERE p.merchant_id = $1 AND ($2::timestamptz IS NULL OR p.created_at < $2)
+            ORDER BY p.created_at DESC
+            LIMIT $3
+            "#,
+            merchant_id.0,
+            before,
+            limit,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|r| PosInvoice {
+                id: r.id,
+                transaction_id: r.transaction_id,
+                till_name: r.till_name,
+                reference: r.reference,
+                description: r.description,
+                amount_kes: KesAmount::new(r.amount_kes),
+                amount_sats: SatAmount::new(r.amount_sats),
+                exchange_rate: r.exchange_rate,
+                payment_request: r.payment_request,
+                status: pos_invoice_status(&r.status, r.expires_at, now),
+                expires_at: r.expires_at,
+                paid_at: r.completed_at,
+                created_at: r.created_at,
+            })
+            .collect())
+    }
+
+    /// Completed transactions per day from `daily_transaction_metrics`, with POS
+    /// sales in KES alongside
+    #[instrument(skip(self))]
+    pub async fn daily_sales(&self, merchant_id: UserId, from_date: NaiveDate, to_date: NaiveDate) -> Result<Vec<DailySales>> {
+        let rows = sqlx::query!(
+            r#"
+            WITH pos AS (
+                SELECT DATE(t.created_at) AS date, COUNT(*) AS invoices, SUM(p.amount_kes) AS kes
+                FROM pos_invoices p
+                JOIN transactions t ON t.id = p.transaction_id
+                WHERE p.merchant_id = $1 AND t.status = 'completed'
+                  AND DATE(t.created_at) BETWEEN $2 AND $3
+                GROUP BY DATE(t.created_at)
+            )
+            SELECT m.date AS "date!",
+                   COALESCE(SUM(m.transaction_count) FILTER (WHERE m.type = 'lightning_receive'), 0)::BIGINT AS "payments_received!",
+                   COALESCE(SUM(m.total_sats) FILTER (WHERE m.type = 'lightning_receive'), 0)::BIGINT AS "received_sats!",
+                   COALESCE(SUM(m.total_sats) FILTER (WHERE m.type = 'withdrawal_mpesa'), 0)::BIGINT AS "withdrawn_sats!",
+                   COALESCE(SUM(m.total_kes) FILTER (WHERE m.type = 'withdrawal_mpesa'), 0) AS "withdrawn_kes!",
+                   COALESCE(SUM(m.total_fees_sats), 0)::BIGINT AS "fees_sats!",
+                   COALESCE(MAX(pos.invoices), 0)::BIGINT AS "pos_invoices_paid!",
+                   COALESCE(MAX(pos.kes), 0) AS "pos_sales_kes!"
+            FROM daily_transaction_metrics m
+            LEFT JOIN pos ON pos.date = m.date
+            WHERE m.user_id = $1 AND m.status = 'completed' AND m.date BETWEEN $2 AND $3
+            GROUP BY m.date
+            ORDER BY m.date DESC
+            "#,
+            merchant_id.0,
+            from_date,
+            to_date,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|r| DailySales {
+                date: r.date,
+                payments_received: r.payments_received,
+                received_sats: SatAmount::new(r.received_sats),
+                pos_invoices_paid: r.pos_invoices_paid,
+                pos_sales_kes: KesAmount::new(r.pos_sales_kes),
+                withdrawn_sats: SatAmount::new(r.withdrawn_sats),
+                withdrawn_kes: KesAmount::new(r.withdrawn_kes),
+                fees_sats: SatAmount::new(r.fees_sats),
+            })
+            .collect())
+    }
+
+    /// Lease merchants with settlement turned on that haven't settled up to `cutoff`
+    #[instrument(skip(self))]
+    pub async fn claim_due_settlements(
+        &self,
+        cutoff: DateTime<Utc>,
+        limit: i64,
+        lease_until: DateTime<Utc>,
+    ) -> Result<Vec<DueSettlement>> {
+        let rows = sqlx::query!(
+            r#"
+            UPDATE merchants SET settlement_lease_until = $3
+            WHERE user_id IN (
+                SELECT user_id FROM merchants
+                WHERE settlement_percent > 0
+                  AND (settled_until IS NULL OR settled_until < $1)
+                  AND (settlement_lease_until IS NULL OR settlement_lease_until < NOW())
+                ORDER BY settled_until NULLS FIRST
+                LIMIT $2
+                FOR UPDATE SKIP LOCKED
+            )
+            RETURNING user_id, settlement_percent, settlement_phone,
+                      COALESCE(settled_until, created_at) AS "period_start!"
+            "#,
+            cutoff,
+            limit,
+            lease_until,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|r| DueSettlement {
+                merchant_id: UserId(r.user_id),
+                settlement_percent: r.settlement_percent,
+                settlement_phone: r.settlement_phone,
+                period_start: r.period_start,
+            })
+            .collect())
+    }
+
+    /// Sats received in completed incoming payments in [from, to)
+    #[instrument(skip(self))]
+    pub async fn received_sats(&self, merchant_id: UserId, from: DateTime<Utc>, to: DateTime<Utc>) -> Result<i64> {
+        let received = sqlx::query_scalar!(
+            r#"
+            SELECT COALESCE(SUM(amount_sats), 0)::BIGINT AS "received!"
+            FROM transactions
+            WHERE user_id = $1 AND type = 'lightning_receive' AND status = 'completed'
+              AND completed_at >= $2 AND completed_at < $3
+            "#,
+            merchant_id.0,
+            from,
+            to,
+        )
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(received)
+    }
+
+    /// Record the settlement for a period; `None` if it was already recorded
+    #[instrument(skip(self, due))]
+    pub async fn start_settlement(
+        &self,
+        due: &DueSettlement,
+        period_end: DateTime<Utc>,
+        received_sats: i64,
+        amount_sats: i64,
+    ) -> Result<Option<Uuid>> {
+        let row = sqlx::query!(
+            r#"
+            INSERT INTO merchant_settlements (merchant_id, period_start, period_end, received_sats,
+                                              settlement_percent, amount_sats)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            ON CONFLICT (merchant_id, period_end) DO NOTHING
+            RETURNING id
+            "#,
+            due.merchant_id.0,
+            due.period_start,
+            period_end,
+            received_sats,
+            due.settlement_percent,
+            amount_sats,
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.map(|r| r.id))
+    }
+
+    /// Record the outcome of a settlement and move the merchant past its period
+    #[instrument(skip(self))]
+    pub async fn finish_settlement(
+        &self,
+        merchant_id: UserId,
+        settlement_id: Option<Uuid>,
+        period_end: DateTime<Utc>,
+        status: MerchantSettlementStatus,
+        transaction_id: Option<Uuid>,
+        error: Option<&str>,
+    ) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+
+        if let Some(settlement_id) = settlement_id {
+            sqlx::query!(
+                "UPDATE merchant_settlements SET status = $2, transaction_id = $3, error = $4 WHERE id = $1",
+                settlement_id,
+                status as _,
+                transaction_id,
+                error,
+            )
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        sqlx::query!(
+            "UPDATE merchants SET settled_until = $2, settlement_lease_until = NULL WHERE user_id = $1",
+            merchant_id.0,
+            period_end,
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    #[instrument(skip(self))]
+    pub async fn settlements(&self, merchant_id: UserId, limit: i64) -> Result<Vec<MerchantSettlement>> {
+        let rows = sqlx::query!(
+            r#"
+            SELECT id, period_start, period_end, received_sats, settlement_percent, amount_sats,
+                   status as "status: MerchantSettlementStatus", transaction_id, error, created_at
+            FROM merchant_settlements
+            WHERE merchant_id = $1
+            ORDER BY period_end DESC
+            LIMIT $2
+            "#,
+            merchant_id.0,
+            limit,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|r| MerchantSettlement {
+                id: r.id,
+                period_start: r.period_start,
+                period_end: r.period_end,
+                received_sats: SatAmount::new(r.received_sats),
+                settlement_percent: r.settlement_percent,
+                amount_sats: SatAmount::new(r.amount_sats),
+                status: r.status,
+                transaction_id: r.transaction_id,
+                error: r.error,
+                created_at: r.created_at,
+            })
+            .collect())
+    }
+}
+
+/// Merchant profiles, API keys, POS invoices, settlement and reports
+pub struct MerchantService {
+    repository: Arc<MerchantRepository>,
+    payment_service: Arc<PaymentService>,
+    limits_service: Arc<LimitsService>,
+}
+
+impl MerchantService {
+    pub fn new(
+        repository: Arc<MerchantRepository>,
+        payment_service: Arc<PaymentService>,
+        limits_service: Arc<LimitsService>,
+    ) -> Self {
+        Self {
+            repository,
+            payment_service,
+            limits_service,
+        }
+    }
+
+    /// Set up the business profile of a merchant account
+    #[instrument(skip(self, request))]
+    pub async fn create(&self, user_id: UserId, request: CreateMerchantRequest) -> Result<Merchant> {
+        request.validate().map_err(|e| AppError::Validation {
+            message: format!("Invalid business profile: {}", e),
+        })?;
+        validate_settlement_phone(request.settlement_phone.as_deref())?;
+
+        if self.repository.account_type(user_id).await? != Some(AccountType::Merchant) {
+            return Err(AppError::User {
+                message: "Only merchant accounts can set up a business profile".to_string(),
+            });
+        }
+        if self.repository.get(user_id).await?.is_some() {
+            return Err(AppError::Conflict {
+                message: "The business profile is already set up".to_string(),
+            });
+        }
+        if !self.repository.create(user_id, &request).await? {
+            return Err(Self::till_name_taken(&request.till_name));
+        }
+
+        info!("Merchant {} set up till {}", user_id, request.till_name.trim());
+        self.find(user_id).await
+    }
+
+    #[instrument(skip(self))]
+    pub async fn get(&self, user_id: UserId) -> Result<Merchant> {
+        self.find(user_id).await
+    }
+
+    #[instrument(skip(self, request))]
+    pub async fn update(&self, user_id: UserId, request: UpdateMerchantRequest) -> Result<Merchant> {
+        request.validate().map_err(|e| AppError::Validation {
+            message: format!("Invalid business profile: {}", e),
+        })?;
+        validate_settlement_phone(request.settlement_phone.as_deref())?;
+
+        let mut merchant = self.find(user_id).await?;
+        if let Some(till_name) = request.till_name {
+            let till_name = till_name.trim().to_string();
+            if self.repository.till_name_taken(user_id, &till_name).await? {
+                return Err(Self::till_name_taken(&till_name));
+            }
+            merchant.till_name = till_name;
+        }
+        if let Some(business_name) = request.business_name {
+            merchant.business_name = business_name.trim().to_string();
+        }
+        merchant.business_category = request.business_category.or(merchant.business_category);
+        merchant.kra_pin = request.kra_pin.or(merchant.kra_pin);
+        merchant.contact_email = request.contact_email.or(merchant.contact_email);
+        merchant.settlement_percent = request.settlement_percent.unwrap_or(merchant.settlement_percent);
+        merchant.settlement_phone = request.settlement_phone.or(merchant.settlement_phone);
+
+        self.repository.update(&merchant).await?;
+        self.find(user_id).await
+    }
+
+    /// Create a KES-priced invoice locked to sats at the current rate
+    #[instrument(skip(self, request))]
+    pub async fn create_pos_invoice(&self, merchant_id: UserId, request: CreatePosInvoiceRequest) -> Result<PosInvoice> {
+        request.validate().map_err(|e| AppError::Validation {
+            message: format!("Invalid invoice: {}", e),
+        })?;
+        let amount_kes = validate_invoice_amount(request.amount_kes)?;
+        let merchant = self.find(merchant_id).await?;
+
+        let rate = self.limits_service.current_rate().await?;
+        let amount_sats = rate.kes_to_sats(&amount_kes, Side::Charge, Rounding::HouseFavourable)?;
+
+        let description = match request.description.as_deref().or(request.reference.as_deref()) {
+            Some(detail) => format!("{}: {}", merchant.till_name, detail),
+            None => merchant.till_name.clone(),
+        };
+        let invoice = self
+            .limits_service
+            .within_kes(merchant_id, amount_kes.as_decimal(), || {
+                self.payment_service.create_lightning_invoice(
+                    merchant_id,
+                    CreateInvoiceRequest {
+                        amount_sats: amount_sats.as_i64(),
+                        description: Some(description),
+                        expiry_seconds: Some(request.expiry_seconds.unwrap_or(DEFAULT_INVOICE_EXPIRY_SECONDS)),
+                    },
+                )
+            })
+            .await?;
+        let transaction_id: Uuid = invoice.transaction_id.parse().map_err(|_| {
+            AppError::Internal(anyhow::anyhow!("Invalid invoice transaction ID {}", invoice.transaction_id))
+        })?;
+
+        let id = self
+            .repository
+            .create_pos_invoice(merchant_id, &request, amount_sats, rate, transaction_id, &invoice)
+            .await?;
+
+        info!("Merchant {} created POS invoice {} for KES {}", merchant_id, id, amount_kes);
+        self.get_pos_invoice(merchant_id, id).await
+    }
+
+    #[instrument(skip(self))]
+    pub async fn get_pos_invoice(&self, merchant_id: UserId, id: Uuid) -> Result<PosInvoice> {
+        self.repository
+            .get_pos_invoice(merchant_id, id)
+            .await?
+            .ok_or_else(|| AppError::Payment {
+                message: "Invoice not found".to_string(),
+            })
+    }
+
+    #[instrument(skip(self))]
+    pub async fn list_pos_invoices(&self, merchant_id: UserId, params: ListPosInvoicesParams) -> Result<Vec<PosInvoice>> {
+        let limit = params.limit.unwrap_or(20).clamp(1, 100);
+        self.repository
+            .list_pos_invoices(merchant_id, params.before, limit)
+            .await
+    }
+
+    /// Daily sales for a date range
+    #[instrument(skip(self))]
+    pub async fn sales_report(&self, merchant_id: UserId, params: SalesReportParams) -> Result<SalesReport> {
+        self.find(merchant_id).await?;
+
+        let today = Utc::now().date_naive();
+        let to_date = params.to_date.unwrap_or(today).min(today);
+        let from_date = params.from_date.unwrap_or(to_date - Duration::days(29));
+        if from_date > to_date {
+            return Err(AppError::Validation {
+                message: "from_date must not be after to_date".to_string(),
+            });
+        }
+        if from_date < today - Duration::days(MAX_REPORT_DAYS - 1) {
+            return Err(AppError::Validation {
+                message: format!("Sales reports cover the last {} days", MAX_REPORT_DAYS),
+            });
+        }
+
+        let days = self.repository.daily_sales(merchant_id, from_date, to_date).await?;
+        let total_received_sats = SatAmount::new(days.iter().map(|d| d.received_sats.as_i64()).sum());
+        let total_pos_sales_kes = KesAmount::new(days.iter().map(|d| d.pos_sales_kes.as_decimal()).sum());
+        Ok(SalesReport {
+            from_date,
+            to_date,
+            days,
+            total_received_sats,
+            total_pos_sales_kes,
+        })
+    }
+
+    #[instrument(skip(self))]
+    pub async fn settlements(&self, merchant_id: UserId) -> Result<Vec<MerchantSettlement>> {
+        self.find(merchant_id).await?;
+        self.repository.settlements(merchant_id, RECENT_SETTLEMENTS).await
+    }
+
+    /// Settle merchants that haven't settled up to the start of today; returns how many were due
+    #[instrument(skip(self))]
+    pub async fn run_settlements(&self, limit: i64) -> Result<usize> {
+        let now = Utc::now();
+        let cutoff = settlement_cutoff(now);
+        let lease_until = now + Duration::minutes(SETTLEMENT_LEASE_MINUTES);
+        let due = self
+            .repository
+            .claim_due_settlements(cutoff, limit, lease_until)
+            .await?;
+
+        for merchant in &due {
+            if let Err(e) = self.settle(merchant, cutoff).await {
+                warn!("Failed to settle merchant {}: {}", merchant.merchant_id, e);
+            }
+        }
+
+        Ok(due.len())
+    }
+
+    /// Withdraw the merchant's share of incoming sats up to `period_end`
+    async fn settle(&self, due: &DueSettlement, period_end: DateTime<Utc>) -> Result<()> {
+        let received_sats = self
+            .repository
+            .received_sats(due.merchant_id, due.period_start, period_end)
+            .await?;
+        let amount_sats = settlement_share(received_sats, due.settlement_percent);
+
+        let Some(settlement_id) = self
+            .repository
+            .start_settlement(due, period_end, received_sats, amount_sats)
+            .await?
+        else {
+            warn!("Merchant {} already settled up to {}, skipping", due.merchant_id, period_end);
+            return self
+                .repository
+                .finish_settlement(due.merchant_id, None, period_end, MerchantSettlementStatus::Skipped, None, None)
+                .await;
+        };
+
+        if amount_sats < MIN_SETTLEMENT_SATS {
+            return self
+                .repository
+                .finish_settlement(
+                    due.merchant_id,
+                    Some(settlement_id),
+                    period_end,
+                    MerchantSettlementStatus::Skipped,
+                    None,
+                    None,
+                )
+                .await;
+        }
+
+        let (status, transaction_id, error) = match self.withdraw(due, amount_sats).await {
+            Ok(transaction_id) => {
+                info!("Settled {} sats for merchant {}", amount_sats, due.merchant_id);
+                (MerchantSettlementStatus::Initiated, Some(transaction_id), None)
+            }
+            Err(e) => {
+                warn!("Settlement for merchant {} could not start: {}", due.merchant_id, e);
+                (MerchantSettlementStatus::Failed, None, Some(e.user_message()))
+            }
+        };
+        self.repository
+            .finish_settlement(
+                due.merchant_id,
+                Some(settlement_id),
+                period_end,
+                status,
+                transaction_id,
+                error.as_deref(),
+            )
+            .await
+    }
+
+    async fn withdraw(&self, due: &DueSettlement, amount_sats: i64) -> Result<Uuid> {
+        let request = MpesaWithdrawalRequest {
+            amount_sats,
+            recipient_phone: due.settlement_phone.clone(),
+        };
+
+        let response = self
+            .limits_service
+            .within_sats(due.merchant_id, SatAmount::new(amount_sats), || {
+                self.payment_service.initiate_mpesa_withdrawal(due.merchant_id, request)
+            })
+            .await?;
+
+        response
+            .transaction_id
+            .parse()
+            .map_err(|_| AppError::Internal(anyhow::anyhow!("Invalid withdrawal transaction ID {}", response.transaction_id)))
+    }
+
+    async fn find(&self, user_id: UserId) -> Result<Merchant> {
+        self.repository
+            .get(user_id)
+            .await?
+            .ok_or_else(|| AppError::User {
+                message: "Set up your business profile first".to_string(),
+            })
+    }
+
+    fn till_name_taken(till_name: &str) -> AppError {
+        AppError::Conflict {
+            message: format!("The till name {} is taken", till_name.trim()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_pos_invoice_status() {
+        let now = Utc::now();
+        let later = now + Duration::minutes(5);
+        let earlier = now - Duration::minutes(5);
+
+        assert_eq!(pos_invoice_status(&TransactionStatus::Pending, later, now), PosInvoiceStatus::Pending);
+        assert_eq!(pos_invoice_status(&TransactionStatus::Pending, earlier, now), PosInvoiceStatus::Expired);
+        // A payment that lands is paid even if it settled after the locked price expired
+        assert_eq!(pos_invoice_status(&TransactionStatus::Completed, earlier, now), PosInvoiceStatus::Paid);
+        assert_eq!(pos_invoice_status(&TransactionStatus::Failed, later, now), PosInvoiceStatus::Failed);
+    }
+
+    #[test]
+    fn test_settlement() {
+        // 22:30 EAT on 14 March is still the 14th in Nairobi
+        let now = Utc.with_ymd_and_hms(2026, 3, 14, 19, 30, 0).unwrap();
+        assert_eq!(settlement_cutoff(now), Utc.with_ymd_and_hms(2026, 3, 13, 21, 0, 0).unwrap());
+        // 01:00 EAT on the 15th is the next Nairobi day
+        let now = Utc.with_ymd_and_hms(2026, 3, 14, 22, 0, 0).unwrap();
+        assert_eq!(settlement_cutoff(now), Utc.with_ymd_and_hms(2026, 3, 14, 21, 0, 0).unwrap());
+
+        assert_eq!(settlement_share(100_001, 30), 30_000);
+        assert_eq!(settlement_share(100_000, 100), 100_000);
+        assert_eq!(settlement_share(100_000, 0), 0);
+    }
+
+    #[test]
+    fn test_invoice_amount() {
+        assert!(validate_invoice_amount(Decimal::new(25050, 2)).is_ok());
+        assert!(validate_invoice_amount(Decimal::ZERO).is_err());
+        assert!(validate_invoice_amount(Decimal::new(1005, 3)).is_err());
+        assert!(validate_invoice_amount(Decimal::from(MAX_INVOICE_KES + 1)).is_err());
+    }
+}
@@ -0,0 +1,1786 @@
+/// Mobile money deposits and withdrawals
+///
+/// Each network implements `MobileMoneyProvider`: collecting money from a phone
+/// (deposits), disbursing to one (withdrawals), asking for the status of a request
+/// and reading the network's callbacks. M-Pesa (Daraja) and Airtel Money Kenya are
+/// implemented; another network (e.g. MTN MoMo) only needs an implementation, a
+/// `MobileMoneyNetwork` variant and an entry in the provider map built in `main`.
+///
+/// Transactions on every network use the `deposit_mpesa` and `withdrawal_mpesa`
+/// types and fee schedules, with `provider` saying which network was used.
+
+use crate::bill_payments::BusinessPayee;
+use crate::domain::StatusActor;
+use crate::domain::StatusTransition;
+use crate::fees::FeeService;
+use crate::limits::{lock_and_check_limits, LimitsService};
+use crate::transitions::{set_status_actor, transition_status};
+use async_trait::async_trait;
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use chrono::{DateTime, Duration, FixedOffset, Utc};
+use reqwest::Client;
+use rust_decimal::prelude::ToPrimitive;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use shared_errors::{AppError, Result};
+use shared_events::{record_event, DomainEvent};
+use shared_security::authorize_callback;
+use shared_types::conversion::{Rounding, Side};
+use shared_types::*;
+use sqlx::{PgConnection, PgPool};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+use tracing::{error, info, instrument, warn};
+use uuid::Uuid;
+use validator::Validate;
+
+/// How long a request may sit in processing before we ask the provider about it
+const STATUS_CHECK_AFTER_MINUTES: i64 = 2;
+/// Deposits the provider still reports as pending after this are failed
+/// (the customer's payment prompt has long expired)
+const DEPOSIT_TIMEOUT_MINUTES: i64 = 60;
+/// Payouts still unconfirmed after this are left for an operator instead of being
+/// checked again (the money may have gone out, so they are never failed automatically)
+const DISBURSEMENT_REVIEW_HOURS: i64 = 24;
+/// Refresh provider access tokens this long before they expire
+const TOKEN_REFRESH_MARGIN_SECONDS: i64 = 60;
+
+/// Whether money is coming in from the phone or going out to it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransferDirection {
+    Collection,
+    Disbursement,
+}
+
+impl TransferDirection {
+    fn of(transaction_type: &TransactionType) -> Option<Self> {
+        match transaction_type {
+            TransactionType::DepositMpesa => Some(TransferDirection::Collection),
+            TransactionType::WithdrawalMpesa => Some(TransferDirection::Disbursement),
+            _ => None,
+        }
+    }
+}
+
+/// Money to collect from or send to a phone
+#[derive(Debug, Clone)]
+pub struct MobileMoneyRequest {
+    /// Our transaction, passed to providers that accept a client reference
+    pub transaction_id: Uuid,
+    pub phone_number: PhoneNumber,
+    /// Whole shillings (mobile money networks don't move cents)
+    pub amount_kes: Decimal,
+    pub description: String,
+}
+
+/// Where a provider says a request stands
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProviderOutcome {
+    Pending,
+    /// Money moved; `receipt` is the network's transaction code when known
+    Succeeded { receipt: Option<String> },
+    Failed { reason: String },
+}
+
+/// Provider's answer to a new request
+#[derive(Debug)]
+pub enum Submission {
+    /// Accepted; carries the provider's reference for the request
+    Accepted(String),
+    /// Refused outright, so no money will move
+    Rejected(AppError),
+}
+
+/// Result reported by a provider callback
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProviderCallback {
+    /// Reference returned when the request was made
+    pub provider_reference: String,
+    pub outcome: ProviderOutcome,
+}
+
+/// A mobile money network
+#[async_trait]
+pub trait MobileMoneyProvider: Send + Sync {
+    fn network(&self) -> MobileMoneyNetwork;
+
+    /// Reference the provider will report a request under, when it is ours rather than theirs
+    fn client_reference(&self, direction: TransferDirection, transaction_id: Uuid) -> Option<String>;
+
+    /// Ask the customer to pay
+    ///
+    /// `Err` means no usable answer came back (timeout, unreadable response) and the
+    /// request may still go through.
+    async fn collect(&self, request: &MobileMoneyRequest) -> Result<Submission>;
+
+    /// Send money to the customer; `Err` means the payout may still go through
+    async fn disburse(&self, request: &MobileMoneyRequest) -> Result<Submission>;
+
+    /// Current state of an earlier request
+    async fn query_status(&self, direction: TransferDirection, provider_reference: &str) -> Result<ProviderOutcome>;
+
+    /// Read a callback posted by the provider
+    fn parse_callback(&self, payload: &Value) -> Result<ProviderCallback>;
+}
+
+/// Providers by network
+#[derive(Clone, Default)]
+pub struct MobileMoneyProviders {
+    providers: HashMap<MobileMoneyNetwork, Arc<dyn MobileMoneyProvider>>,
+}
+
+impl MobileMoneyProviders {
+    pub fn new(providers: Vec<Arc<dyn MobileMoneyProvider>>) -> Self {
+        Self {
+            providers: providers.into_iter().map(|provider| (provider.network(), provider)).collect(),
+        }
+    }
+
+    pub fn get(&self, network: MobileMoneyNetwork) -> Result<Arc<dyn MobileMoneyProvider>> {
+        self.providers.get(&network).cloned().ok_or_else(|| AppError::Validation {
+            message: format!("{} isn't available", network.display_name()),
+        })
+    }
+
+    /// Networks users can pick from
+    pub fn networks(&self) -> Vec<MobileMoneyNetwork> {
+        let mut networks: Vec<_> = self.providers.keys().copied().collect();
+        networks.sort_by_key(|network| network.display_name());
+        networks
+    }
+}
+
+/// Phone number without the leading `+` (e.g. 254712345678)
+fn international_msisdn(phone_number: &PhoneNumber) -> String {
+    phone_number.0.trim_start_matches('+').to_string()
+}
+
+/// Whole shillings as sent to providers
+fn whole_kes(amount_kes: Decimal) -> Result<u64> {
+    amount_kes
+        .trunc()
+        .to_u64()
+        .filter(|amount| *amount > 0)
+        .ok_or_else(|| AppError::Validation {
+            message: format!("Invalid mobile money amount: KES {}", amount_kes),
+        })
+}
+
+/// Bearer token cached until shortly before it expires
+#[derive(Default)]
+struct TokenCache {
+    token: Mutex<Option<(String, DateTime<Utc>)>>,
+}
+
+impl TokenCache {
+    async fn get_or_refresh<F, Fut>(&self, refresh: F) -> Result<String>
+    where
+        F: FnOnce() -> Fut,
+        Fut: std::future::Future<Output = Result<(String, i64)>>,
+    {
+        let mut cached = self.token.lock().await;
+        if let Some((token, expires_at)) = cached.as_ref() {
+            if *expires_at > Utc::now() + Duration::seconds(TOKEN_REFRESH_MARGIN_SECONDS) {
+                return Ok(token.clone());
+            }
+        }
+
+        let (token, expires_in) = refresh().await?;
+        *cached = Some((token.clone(), Utc::now() + Duration::seconds(expires_in)));
+        Ok(token)
+    }
+}
+
+/// Secret providers must put in the callback URL (`?token=`); callbacks are refused without one
+fn callback_token_from_env() -> Option<String> {
+    std::env::var("MOBILE_MONEY_CALLBACK_TOKEN").ok().filter(|token| !token.is_empty())
+}
+
+/// Query string of a provider callback
+#[derive(Debug, Deserialize)]
+pub struct ProviderCallbackParams {
+    pub token: Option<String>,
+}
+
+/// Safaricom Daraja credentials
+#[derive(Debug, Clone)]
+pub struct MpesaProviderConfig {
+    pub base_url: String,
+    pub consumer_key: String,
+    pub consumer_secret: String,
+    /// Paybill or till collecting deposits (STK push)
+    pub shortcode: String,
+    pub passkey: String,
+    /// Shortcode paying out withdrawals (B2C) and bills (B2B)
+    pub b2c_shortcode: String,
+    pub initiator_name: String,
+    /// Initiator password encrypted with Safaricom's certificate
+    pub security_credential: String,
+    /// Public URL of `/mobile-money/mpesa/callback`, carrying the callback token
+    pub callback_url: String,
+}
+
+impl MpesaProviderConfig {
+    pub fn from_env() -> Self {
+        let shortcode = std::env::var("MPESA_SHORTCODE").unwrap_or_else(|_| "174379".to_string());
+        let callback_url = std::env::var("MPESA_MOBILE_MONEY_CALLBACK_URL")
+            .unwrap_or_else(|_| "https://your-domain.com/api/v1/mobile-money/mpesa/callback".to_string());
+        Self {
+            base_url: std::env::var("MPESA_SANDBOX_URL")
+                .unwrap_or_else(|_| "https://sandbox.safaricom.co.ke".to_string()),
+            consumer_key: std::env::var("MPESA_CONSUMER_KEY").unwrap_or_default(),
+            consumer_secret: std::env::var("MPESA_CONSUMER_SECRET").unwrap_or_default(),
+            passkey: std::env::var("MPESA_PASSKEY").unwrap_or_default(),
+            b2c_shortcode: std::env::var("MPESA_B2C_SHORTCODE").unwrap_or_else(|_| shortcode.clone()),
+            shortcode,
+            initiator_name: std::env::var("MPESA_INITIATOR_NAME").unwrap_or_else(|_| "testapi".to_string()),
+            security_credential: std::env::var("MPESA_SECURITY_CREDENTIAL").unwrap_or_default(),
+            callback_url: match callback_token_from_env() {
+                Some(token) => format!("{}?token={}", callback_url, token),
+                None => callback_url,
+            },
+        }
+    }
+}
+
+/// M-Pesa through Safaricom Daraja: STK push, B2C and STK push queries
+pub struct MpesaProvider {
+    config: MpesaProviderConfig,
+    http_client: Client,
+    token: TokenCache,
+}
+
+#[derive(Debug, Deserialize)]
+struct DarajaToken {
+    access_token: String,
+    /// Seconds, sent as a string
+    expires_in: String,
+}
+
+impl MpesaProvider {
+    pub fn new(config: MpesaProviderConfig) -> Self {
+        Self {
+            config,
+            http_client: Client::new(),
+            token: TokenCache::default(),
+        }
+    }
+
+    async fn access_token(&self) -> Result<String> {
+        self.token
+            .get_or_refresh(|| async {
+                let token: DarajaToken = self
+                    .http_client
+                    .get(format!("{}/oauth/v1/generate?grant_type=client_credentials", self.config.base_url))
+                    .basic_auth(&self.config.consumer_key, Some(&self.config.consumer_secret))
+                    .send()
+                    .await
+                    .and_then(|response| response.error_for_status())
+                    .map_err(|e| AppError::Mpesa {
+                        message: format!("M-Pesa authentication failed: {}", e),
+                    })?
+                    .json()
+                    .await
+                    .map_err(|e| AppError::Mpesa {
+                        message: format!("Invalid M-Pesa token response: {}", e),
+                    })?;
+                Ok((token.access_token, token.expires_in.parse().unwrap_or(3599)))
+            })
+            .await
+    }
+
+    /// STK push password and the timestamp it was made with (Nairobi time)
+    fn stk_password(&self, now: DateTime<Utc>) -> (String, String) {
+        let nairobi = FixedOffset::east_opt(3 * 3600).expect("valid offset");
+        let timestamp = now.with_timezone(&nairobi).format("%Y%m%d%H%M%S").to_string();
+        let password = BASE64.encode(format!("{}{}{}", self.config.shortcode, self.config.passkey, timestamp));
+        (password, timestamp)
+    }
+
+    async fn post(&self, path: &str, body: &Value) -> Result<Value> {
+        let token = self.access_token().await?;
+        let response = self
+            .http_client
+            .post(format!("{}{}", self.config.base_url, path))
+            .bearer_auth(token)
+            .json(body)
+            .send()
+            .await
+            .map_err(|e| AppError::Mpesa {
+                message: format!("M-Pesa request failed: {}", e),
+            })?;
+
+        response.json().await.map_err(|e| AppError::Mpesa {
+            message: format!("Invalid M-Pesa response: {}", e),
+        })
+    }
+
+    /// Pay a Paybill or Buy Goods till over B2B; `Err` means the payment may still go through
+    ///
+    /// Results arrive at the same callback URL as B2C withdrawals, in the same shape,
+    /// under our transaction ID.
+    #[instrument(skip(self, remarks))]
+    pub async fn pay_business(
+        &self,
+        transaction_id: Uuid,
+        payee: &BusinessPayee,
+        amount_kes: Decimal,
+        remarks: &str,
+    ) -> Result<Submission> {
+        let (command_id, account_reference) = match payee {
+            BusinessPayee::Paybill { account_reference, .. } => ("BusinessPayBill", account_reference.as_str()),
+            BusinessPayee::Till { .. } => ("BusinessBuyGoods", ""),
+        };
+        let body = serde_json::json!({
+            "OriginatorConversationID": transaction_id.to_string(),
+            "Initiator": self.config.initiator_name,
+            "SecurityCredential": self.config.security_credential,
+            "CommandID": command_id,
+            "SenderIdentifierType": "4",
+            "RecieverIdentifierType": "4",
+            "Amount": whole_kes(amount_kes)?,
+            "PartyA": self.config.b2c_shortcode,
+            "PartyB": payee.shortcode(),
+            "AccountReference": account_reference,
+            "Remarks": remarks,
+            "QueueTimeOutURL": self.config.callback_url,
+            "ResultURL": self.config.callback_url,
+        });
+
+        let response = self.post("/mpesa/b2b/v1/paymentrequest", &body).await?;
+        match response["ResponseCode"].as_str() {
+            Some("0") => Ok(Submission::Accepted(transaction_id.to_string())),
+            _ if daraja_rejected(&response) => Ok(Submission::Rejected(AppError::Mpesa {
+                message: daraja_error(&response, "M-Pesa bill payment was rejected"),
+            })),
+            _ => Err(unrecognised_daraja_response()),
+        }
+    }
+}
+
+#[async_trait]
+impl MobileMoneyProvider for MpesaProvider {
+    fn network(&self) -> MobileMoneyNetwork {
+        MobileMoneyNetwork::Mpesa
+    }
+
+    /// B2C results echo our OriginatorConversationID; STK push is only known by Safaricom's CheckoutRequestID
+    fn client_reference(&self, direction: TransferDirection, transaction_id: Uuid) -> Option<String> {
+        match direction {
+            TransferDirection::Collection => None,
+            TransferDirection::Disbursement => Some(transaction_id.to_string()),
+        }
+    }
+
+    #[instrument(skip(self, request), fields(transaction_id = %request.transaction_id))]
+    async fn collect(&self, request: &MobileMoneyRequest) -> Result<Submission> {
+        let (password, timestamp) = self.stk_password(Utc::now());
+        let msisdn = international_msisdn(&request.phone_number);
+        let body = serde_json::json!({
+            "BusinessShortCode": self.config.shortcode,
+            "Password": password,
+            "Timestamp": timestamp,
+            "TransactionType": "CustomerPayBillOnline",
+            "Amount": whole_kes(request.amount_kes)?,
+            "PartyA": msisdn,
+            "PartyB": self.config.shortcode,
+            "PhoneNumber": msisdn,
+            "CallBackURL": self.config.callback_url,
+            "AccountReference": "PesaBit",
+            "TransactionDesc": request.description,
+        });
+
+        let response = self.post("/mpesa/stkpush/v1/processrequest", &body).await?;
+        match (response["ResponseCode"].as_str(), response["CheckoutRequestID"].as_str()) {
+            (Some("0"), Some(checkout_request_id)) => Ok(Submission::Accepted(checkout_request_id.to_string())),
+            _ if daraja_rejected(&response) => Ok(Submission::Rejected(AppError::Mpesa {
+                message: daraja_error(&response, "M-Pesa payment request was rejected"),
+            })),
+            _ => Err(unrecognised_daraja_response()),
+        }
+    }
+
+    #[instrument(skip(self, request), fields(transaction_id = %request.transaction_id))]
+    async fn disburse(&self, request: &MobileMoneyRequest) -> Result<Submission> {
+        let body = serde_json::json!({
+            "OriginatorConversationID": request.transaction_id.to_string(),
+            "InitiatorName": self.config.initiator_name,
+            "SecurityCredential": self.config.security_credential,
+            "CommandID": "BusinessPayment",
+            "Amount": whole_kes(request.amount_kes)?,
+            "PartyA": self.config.b2c_shortcode,
+            "PartyB": international_msisdn(&request.phone_number),
+            "Remarks": request.description,
+            "QueueTimeOutURL": self.config.callback_url,
+            "ResultURL": self.config.callback_url,
+            "Occasion": "",
+        });
+
+        let response = self.post("/mpesa/b2c/v3/paymentrequest", &body).await?;
+        match response["ResponseCode"].as_str() {
+            Some("0") => Ok(Submission::Accepted(request.transaction_id.to_string())),
+            _ if daraja_rejected(&response) => Ok(Submission::Rejected(AppError::Mpesa {
+                message: daraja_error(&response, "M-Pesa payout was rejected"),
+            })),
+            _ => Err(unrecognised_daraja_response()),
+        }
+    }
+
+    #[instrument(skip(self))]
+    async fn query_status(&self, direction: TransferDirection, provider_reference: &str) -> Result<ProviderOutcome> {
+        // Daraja only reports B2C results to the result URL
+        if direction == TransferDirection::Disbursement {
+            return Ok(ProviderOutcome::Pending);
+        }
+
+        let (password, timestamp) = self.stk_password(Utc::now());
+        let body = serde_json::json!({
+            "BusinessShortCode": self.config.shortcode,
+            "Password": password,
+            "Timestamp": timestamp,
+            "CheckoutRequestID": provider_reference,
+        });
+        let response = self.post("/mpesa/stkpushquery/v1/query", &body).await?;
+
+        // While the customer hasn't answered, Daraja returns an error instead of a result
+        Ok(match response["ResultCode"].as_str() {
+            Some("0") => ProviderOutcome::Succeeded { receipt: None },
+            Some(_) => ProviderOutcome::Failed {
+                reason: daraja_error(&response, "M-Pesa payment was not completed"),
+            },
+            None => ProviderOutcome::Pending,
+        })
+    }
+
+    fn parse_callback(&self, payload: &Value) -> Result<ProviderCallback> {
+        parse_mpesa_callback(payload)
+    }
+}
+
+/// Daraja refuses a request with a non-zero ResponseCode or an errorCode
+fn daraja_rejected(response: &Value) -> bool {
+    matches!(response["ResponseCode"].as_str(), Some(code) if code != "0") || response["errorCode"].is_string()
+}
+
+fn unrecognised_daraja_response() -> AppError {
+    AppError::Mpesa {
+        message: "Unrecognised M-Pesa response".to_string(),
+    }
+}
+
+fn daraja_error(response: &Value, fallback: &str) -> String {
+    ["ResultDesc", "ResponseDescription", "errorMessage"]
+        .iter()
+        .find_map(|field| response[*field].as_str())
+        .unwrap_or(fallback)
+        .to_string()
+}
+
+/// STK push callbacks (deposits) and B2C/B2B results (withdrawals and bill payments)
+fn parse_mpesa_callback(payload: &Value) -> Result<ProviderCallback> {
+    let invalid = || AppError::Validation {
+        message: "Unrecognised M-Pesa callback".to_string(),
+    };
+
+    let stk = &payload["Body"]["stkCallback"];
+    if stk.is_object() {
+        let provider_reference = stk["CheckoutRequestID"].as_str().ok_or_else(invalid)?.to_string();
+        let outcome = match stk["ResultCode"].as_i64().ok_or_else(invalid)? {
+            0 => ProviderOutcome::Succeeded {
+                receipt: stk["CallbackMetadata"]["Item"]
+                    .as_array()
+                    .and_then(|items| items.iter().find(|item| item["Name"] == "MpesaReceiptNumber"))
+                    .and_then(|item| item["Value"].as_str())
+                    .map(str::to_string),
+            },
+            _ => ProviderOutcome::Failed {
+                reason: stk["ResultDesc"].as_str().unwrap_or("M-Pesa payment failed").to_string(),
+            },
+        };
+        return Ok(ProviderCallback { provider_reference, outcome });
+    }
+
+    let result = &payload["Result"];
+    if result.is_object() {
+        // Our transaction ID, sent as the OriginatorConversationID
+        let provider_reference = result["OriginatorConversationID"].as_str().ok_or_else(invalid)?.to_string();
+        let outcome = match result["ResultCode"].as_i64().ok_or_else(invalid)? {
+            0 => ProviderOutcome::Succeeded {
+                receipt: result["TransactionID"].as_str().map(str::to_string),
+            },
+            _ => ProviderOutcome::Failed {
+                reason: result["ResultDesc"].as_str().unwrap_or("M-Pesa payout failed").to_string(),
+            },
+        };
+        return Ok(ProviderCallback { provider_reference, outcome });
+    }
+
+    Err(invalid())
+}
+
+/// Airtel Africa Open API credentials
+#[derive(Debug, Clone)]
+pub struct AirtelMoneyConfig {
+    pub base_url: String,
+    pub client_id: String,
+    pub client_secret: String,
+    /// Disbursement PIN encrypted with Airtel's public key
+    pub encrypted_pin: String,
+}
+
+impl AirtelMoneyConfig {
+    /// Airtel Money is offered once a client ID is configured
+    pub fn from_env() -> Option<Self> {
+        let client_id = std::env::var("AIRTEL_MONEY_CLIENT_ID").ok().filter(|id| !id.is_empty())?;
+        Some(Self {
+            base_url: std::env::var("AIRTEL_MONEY_BASE_URL")
+                .unwrap_or_else(|_| "https://openapiuat.airtel.africa".to_string()),
+            client_id,
+            client_secret: std::env::var("AIRTEL_MONEY_CLIENT_SECRET").unwrap_or_default(),
+            encrypted_pin: std::env::var("AIRTEL_MONEY_ENCRYPTED_PIN").unwrap_or_default(),
+        })
+    }
+}
+
+/// Airtel Money Kenya through the Airtel Africa Open API
+///
+/// Requests are identified by our transaction ID, which Airtel echoes back in
+/// callbacks and status queries.
+pub struct AirtelMoneyProvider {
+    config: AirtelMoneyConfig,
+    http_client: Client,
+    token: TokenCache,
+}
+
+#[derive(Debug, Deserialize)]
+struct AirtelToken {
+    access_token: String,
+    expires_in: i64,
+}
+
+impl AirtelMoneyProvider {
+    pub fn new(config: AirtelMoneyConfig) -> Self {
+        Self {
+            config,
+            http_client: Client::new(),
+            token: TokenCache::default(),
+        }
+    }
+
+    async fn access_token(&self) -> Result<String> {
+        self.token
+            .get_or_refresh(|| async {
+                let token: AirtelToken = self
+                    .http_client
+                    .post(format!("{}/auth/oauth2/token", self.config.base_url))
+                    .json(&serde_json::json!({
+                        "client_id": self.config.client_id,
+                        "client_secret": self.config.client_secret,
+                        "grant_type": "client_credentials",
+                    }))
+                    .send()
+                    .await
+                    .and_then(|response| response.error_for_status())
+                    .map_err(|e| AppError::ExternalService {
+                        message: format!("Airtel Money authentication failed: {}", e),
+                    })?
+                    .json()
+                    .await
+                    .map_err(|e| AppError::ExternalService {
+                        message: format!("Invalid Airtel Money token response: {}", e),
+                    })?;
+                Ok((token.access_token, token.expires_in))
+            })
+            .await
+    }
+
+    async fn send(&self, request: reqwest::RequestBuilder) -> Result<Value> {
+        let token = self.access_token().await?;
+        let response = request
+            .bearer_auth(token)
+            .header("X-Country", "KE")
+            .header("X-Currency", "KES")
+            .send()
+            .await
+            .map_err(|e| AppError::ExternalService {
+                message: format!("Airtel Money request failed: {}", e),
+            })?;
+
+        response.json().await.map_err(|e| AppError::ExternalService {
+            message: format!("Invalid Airtel Money response: {}", e),
+        })
+    }
+
+    /// Our transaction ID as sent to Airtel
+    fn reference(transaction_id: Uuid) -> String {
+        transaction_id.simple().to_string()
+    }
+}
+
+/// Kenyan number without the country code, as Airtel expects (e.g. 733123456)
+fn airtel_msisdn(phone_number: &PhoneNumber) -> String {
+    let msisdn = international_msisdn(phone_number);
+    msisdn.strip_prefix("254").map(str::to_string).unwrap_or(msisdn)
+}
+
+/// Accepted under `reference`, or refused when Airtel says so; anything else is unreadable
+fn airtel_submission(response: &Value, reference: String, fallback: &str) -> Result<Submission> {
+    match response["status"]["success"].as_bool() {
+        Some(true) => Ok(Submission::Accepted(reference)),
+        Some(false) => Ok(Submission::Rejected(AppError::ExternalService {
+            message: response["status"]["message"].as_str().unwrap_or(fallback).to_string(),
+        })),
+        None => Err(AppError::ExternalService {
+            message: "Unrecognised Airtel Money response".to_string(),
+        }),
+    }
+}
+
+/// Airtel transaction status: TS succeeded, TF failed, anything else is in progress
+fn airtel_outcome(status_code: Option<&str>, airtel_money_id: Option<&str>, message: Option<&str>) -> ProviderOutcome {
+    match status_code {
+        Some("TS") => ProviderOutcome::Succeeded {
+            receipt: airtel_money_id.map(str::to_string),
+        },
+        Some("TF") => ProviderOutcome::Failed {
+            reason: message.unwrap_or("Airtel Money transaction failed").to_string(),
+        },
+        _ => ProviderOutcome::Pending,
+    }
+}
+
+#[async_trait]
+impl MobileMoneyProvider for AirtelMoneyProvider {
+    fn network(&self) -> MobileMoneyNetwork {
+        MobileMoneyNetwork::AirtelMoney
+    }
+
+    fn client_reference(&self, _direction: TransferDirection, transaction_id: Uuid) -> Option<String> {
+        Some(Self::reference(transaction_id))
+    }
+
+    #[instrument(skip(self, request), fields(transaction_id = %request.transaction_id))]
+    async fn collect(&self, request: &MobileMoneyRequest) -> Result<Submission> {
+        let reference = Self::reference(request.transaction_id);
+        let body = serde_json::json!({
+            "reference": request.description,
+            "subscriber": {
+                "country": "KE",
+                "currency": "KES",
+                "msisdn": airtel_msisdn(&request.phone_number),
+            },
+            "transaction": {
+                "amount": whole_kes(request.amount_kes)?,
+                "country": "KE",
+                "currency": "KES",
+                "id": reference,
+            },
+        });
+
+        let response = self
+            .send(self.http_client.post(format!("{}/merchant/v1/payments/", self.config.base_url)).json(&body))
+            .await?;
+        airtel_submission(&response, reference, "Airtel Money payment request was rejected")
+    }
+
+    #[instrument(skip(self, request), fields(transaction_id = %request.transaction_id))]
+    async fn disburse(&self, request: &MobileMoneyRequest) -> Result<Submission> {
+        let reference = Self::reference(request.transaction_id);
+        let body = serde_json::json!({
+            "payee": { "msisdn": airtel_msisdn(&request.phone_number) },
+            "reference": request.description,
+            "pin": self.config.encrypted_pin,
+            "transaction": {
+                "amount": whole_kes(request.amount_kes)?,
+                "id": reference,
+            },
+        });
+
+        let response = self
+            .send(self.http_client.post(format!("{}/standard/v1/disbursements/", self.config.base_url)).json(&body))
+            .await?;
+        airtel_submission(&response, reference, "Airtel Money payout was rejected")
+    }
+
+    #[instrument(skip(self))]
+    async fn query_status(&self, direction: TransferDirection, provider_reference: &str) -> Result<ProviderOutcome> {
+        let path = match direction {
+            TransferDirection::Collection => "standard/v1/payments",
+            TransferDirection::Disbursement => "standard/v1/disbursements",
+        };
+        let response = self
+            .send(self.http_client.get(format!("{}/{}/{}", self.config.base_url, path, provider_reference)))
+            .await?;
+
+        let transaction = &response["data"]["transaction"];
+        Ok(airtel_outcome(
+            transaction["status"].as_str(),
+            transaction["airtel_money_id"].as_str(),
+            transaction["message"].as_str(),
+        ))
+    }
+
+    fn parse_callback(&self, payload: &Value) -> Result<ProviderCallback> {
+        let transaction = &payload["transaction"];
+        let provider_reference = transaction["id"].as_str().ok_or_else(|| AppError::Validation {
+            message: "Unrecognised Airtel Money callback".to_string(),
+        })?;
+
+        Ok(ProviderCallback {
+            provider_reference: provider_reference.to_string(),
+            outcome: airtel_outcome(
+                transaction["status_code"].as_str(),
+                transaction["airtel_money_id"].as_str(),
+                transaction["message"].as_str(),
+            ),
+        })
+    }
+}
+
+/// Deposit through any mobile money network
+#[derive(Debug, Deserialize, Validate)]
+pub struct MobileMoneyDepositRequest {
+    #[serde(default)]
+    pub provider: MobileMoneyNetwork,
+    /// Whole shillings
+    #[validate(range(min = 10, max = 500000))]
+    pub amount_kes: i32,
+    /// Phone paying (defaults to the user's registered number)
+    pub phone_number: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MobileMoneyDepositResponse {
+    pub transaction_id: Uuid,
+    pub provider: MobileMoneyNetwork,
+    pub amount_kes: KesAmount,
+    pub estimated_sats: SatAmount,
+    pub exchange_rate: BtcKesRate,
+    pub fee_kes: KesAmount,
+    pub fee_schedule_version: i32,
+    pub message: String,
+}
+
+/// Withdrawal to any mobile money network
+#[derive(Debug, Deserialize, Validate)]
+pub struct MobileMoneyWithdrawalRequest {
+    #[serde(default)]
+    pub provider: MobileMoneyNetwork,
+    #[validate(range(min = 1000))]
+    pub amount_sats: i64,
+    /// Phone receiving the money (defaults to the user's registered number)
+    pub recipient_phone: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MobileMoneyWithdrawalResponse {
+    pub transaction_id: Uuid,
+    pub provider: MobileMoneyNetwork,
+    pub amount_sats: SatAmount,
+    pub amount_kes: KesAmount,
+    pub exchange_rate: BtcKesRate,
+    pub fee_kes: KesAmount,
+    pub fee_sats: SatAmount,
+    pub fee_schedule_version: i32,
+    pub recipient_phone: PhoneNumber,
+}
+
+/// Network a user can deposit from or withdraw to
+#[derive(Debug, Serialize)]
+pub struct MobileMoneyOption {
+    pub provider: MobileMoneyNetwork,
+    pub name: &'static str,
+}
+
+/// Mobile money transaction waiting on its provider
+#[derive(Debug, Clone)]
+pub struct ProviderTransaction {
+    pub id: Uuid,
+    pub user_id: UserId,
+    pub transaction_type: TransactionType,
+    pub provider: MobileMoneyNetwork,
+    pub provider_reference: String,
+    pub amount_kes: Option<Decimal>,
+    pub amount_sats: Option<i64>,
+    pub fee_kes: Option<Decimal>,
+    pub exchange_rate: Option<Decimal>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Sats credited for a completed deposit, at the rate locked when it was made
+pub fn deposit_sats(transaction: &ProviderTransaction) -> Result<SatAmount> {
+    let (Some(amount_kes), Some(exchange_rate)) = (transaction.amount_kes, transaction.exchange_rate) else {
+        return Err(AppError::Internal(anyhow::anyhow!(
+            "Deposit {} has no amount or exchange rate",
+            transaction.id
+        )));
+    };
+    let net_kes = KesAmount::new(amount_kes - transaction.fee_kes.unwrap_or_default());
+    let rate = BtcKesRate::new(exchange_rate)?;
+    Ok(rate.kes_to_sats(&net_kes, Side::Payout, Rounding::HouseFavourable)?)
+}
+
+/// Mobile money transaction repository
+pub struct MobileMoneyRepository {
+    pool: PgPool,
+}
+
+impl MobileMoneyRepository {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+
+    #[instrument(skip(self))]
+    pub async fn phone_number(&self, user_id: UserId) -> Result<String> {
+        let row = sqlx::query!("SELECT phone_number FROM users WHERE id = $1", user_id.0)
+            .fetch_optional(&self.pool)
+            .await?
+            .ok_or_else(|| AppError::User {
+                message: "User not found".to_string(),
+            })?;
+
+        Ok(row.phone_number)
+    }
+
+    /// Record a deposit about to be requested from the provider
+    /// Limits are re-checked under the limits lock so concurrent deposits can't overshoot them
+    #[allow(clippy::too_many_arguments)]
+    #[instrument(skip(self))]
+    pub async fn create_deposit(
+        &self,
+        user_id: UserId,
+        provider: MobileMoneyNetwork,
+        phone_number: &PhoneNumber,
+        amount_kes: Decimal,
+        fee_kes: Decimal,
+        rate: BtcKesRate,
+        fee_schedule_id: Uuid,
+    ) -> Result<Uuid> {
+        let mut tx = self.pool.begin().await?;
+        lock_and_check_limits(&mut *tx, user_id, amount_kes, rate).await?;
+        set_status_actor(&mut *tx, &StatusActor::System, Some("mobile_money_deposit")).await?;
+
+        let id = sqlx::query_scalar!(
+            r#"
+            INSERT INTO transactions (user_id, type, status, provider, amount_kes, fee_kes, exchange_rate,
+                                      fee_schedule_id, metadata)
+            VALUES ($1, 'deposit_mpesa', 'processing', $2, $3, $4, $5, $6, $7)
+            RETURNING id
+            "#,
+            user_id.0,
+            provider as _,
+            amount_kes,
+            fee_kes,
+            rate.kes_per_btc(),
+            fee_schedule_id,
+            serde_json::json!({ "phone_number": phone_number.0 }),
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+        Ok(id)
+    }
+
+    /// Debit the wallet and record a withdrawal about to be sent to the provider
+    /// Limits are re-checked under the limits lock so concurrent payouts can't overshoot them
+    #[allow(clippy::too_many_arguments)]
+    #[instrument(skip(self))]
+    pub async fn create_withdrawal(
+        &self,
+        user_id: UserId,
+        provider: MobileMoneyNetwork,
+        metadata: Value,
+        amount_sats: SatAmount,
+        amount_kes: KesAmount,
+        fee_sats: SatAmount,
+        fee_kes: KesAmount,
+        rate: BtcKesRate,
+        fee_schedule_id: Uuid,
+    ) -> Result<Uuid> {
+        let mut tx = self.pool.begin().await?;
+        lock_and_check_limits(&mut *tx, user_id, amount_kes.as_decimal(), rate).await?;
+        set_status_actor(&mut *tx, &StatusActor::System, Some("mobile_money_withdrawal")).await?;
+
+        let debited = sqlx::query!(
+            "UPDATE wallets SET balance_sats = balance_sats - $2 WHERE user_id = $1 AND balance_sats >= $2",
+            user_id.0,
+            amount_sats.as_i64() + fee_sats.as_i64(),
+        )
+        .execute(&mut *tx)
+        .await?;
+        if debited.rows_affected() != 1 {
+            return Err(AppError::Payment {
+                message: "Insufficient balance".to_string(),
+            });
+        }
+
+        let id = sqlx::query_scalar!(
+            r#"
+            INSERT INTO transactions (user_id, type, status, provider, amount_sats, amount_kes, fee_sats, fee_kes,
+                                      exchange_rate, fee_schedule_id, metadata)
+            VALUES ($1, 'withdrawal_mpesa', 'processing', $2, $3, $4, $5, $6, $7, $8, $9)
+            RETURNING id
+            "#,
+            user_id.0,
+            provider as _,
+            amount_sats.as_i64(),
+            amount_kes.as_decimal(),
+            fee_sats.as_i64(),
+            fee_kes.as_decimal(),
+            rate.kes_per_btc(),
+            fee_schedule_id,
+            metadata,
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+        Ok(id)
+    }
+
+    #[instrument(skip(self))]
+    pub async fn set_provider_reference(&self, id: Uuid, provider_reference: &str) -> Result<()> {
+        sqlx::query!(
+            "UPDATE transactions SET provider_reference = $2 WHERE id = $1",
+            id,
+            provider_reference,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Processing transaction with the provider's reference
+    #[instrument(skip(self))]
+    pub async fn find_processing(
+        &self,
+        provider: MobileMoneyNetwork,
+        provider_reference: &str,
+    ) -> Result<Option<ProviderTransaction>> {
+        let row = sqlx::query!(
+            r#"
+            SELECT id, user_id, type as "transaction_type: TransactionType",
+                   provider as "provider!: MobileMoneyNetwork", provider_reference as "provider_reference!",
+                   amount_kes, amount_sats, fee_kes, exchange_rate, created_at
+            FROM transactions
+            WHERE provider = $1 AND provider_reference = $2 AND status = 'processing'
+            "#,
+            provider as _,
+            provider_reference,
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.map(|r| ProviderTransaction {
+            id: r.id,
+            user_id: UserId(r.user_id),
+            transaction_type: r.transaction_type,
+            provider: r.provider,
+            provider_reference: r.provider_reference,
+            amount_kes: r.amount_kes,
+            amount_sats: r.amount_sats,
+            fee_kes: r.fee_kes,
+            exchange_rate: r.exchange_rate,
+            created_at: r.created_at,
+        }))
+    }
+
+    /// Lock a processing transaction by the provider's reference
+    async fn lock_processing(
+        conn: &mut PgConnection,
+        provider: MobileMoneyNetwork,
+        provider_reference: &str,
+    ) -> Result<Option<ProviderTransaction>> {
+        let row = sqlx::query!(
+            r#"
+            SELECT id, user_id, type as "transaction_type: TransactionType",
+                   provider as "provider!: MobileMoneyNetwork", provider_reference as "provider_reference!",
+                   amount_kes, amount_sats, fee_kes, exchange_rate, created_at
+            FROM transactions
+            WHERE provider = $1 AND provider_reference = $2 AND status = 'processing'
+            FOR UPDATE
+            "#,
+            provider as _,
+            provider_reference,
+        )
+        .fetch_optional(conn)
+        .await?;
+
+        Ok(row.map(|r| ProviderTransaction {
+            id: r.id,
+            user_id: UserId(r.user_id),
+            transaction_type: r.transaction_type,
+            provider: r.provider,
+            provider_reference: r.provider_reference,
+            amount_kes: r.amount_kes,
+            amount_sats: r.amount_sats,
+            fee_kes: r.fee_kes,
+            exchange_rate: r.exchange_rate,
+            created_at: r.created_at,
+        }))
+    }
+
+    /// Apply a provider's final answer; returns the transaction if it was still processing
+    #[instrument(skip(self))]
+    pub async fn settle(
+        &self,
+        provider: MobileMoneyNetwork,
+        provider_reference: &str,
+        outcome: &ProviderOutcome,
+    ) -> Result<Option<ProviderTransaction>> {
+        let mut tx = self.pool.begin().await?;
+        let Some(transaction) = Self::lock_processing(&mut *tx, provider, provider_reference).await? else {
+            return Ok(None);
+        };
+
+        let event = match (outcome, &transaction.transaction_type) {
+            (ProviderOutcome::Pending, _) => return Ok(None),
+            (ProviderOutcome::Succeeded { receipt }, TransactionType::DepositMpesa) => {
+                let amount_sats = deposit_sats(&transaction)?;
+                sqlx::query!(
+                    "UPDATE transactions SET amount_sats = $2, mpesa_code = $3 WHERE id = $1",
+                    transaction.id,
+                    amount_sats.as_i64(),
+                    receipt.as_deref(),
+                )
+                .execute(&mut *tx)
+                .await?;
+
+                let credited = sqlx::query!(
+                    "UPDATE wallets SET balance_sats = balance_sats + $2 WHERE user_id = $1",
+                    transaction.user_id.0,
+                    amount_sats.as_i64(),
+                )
+                .execute(&mut *tx)
+                .await?;
+                if credited.rows_affected() != 1 {
+                    return Err(AppError::Internal(anyhow::anyhow!("No wallet for user {}", transaction.user_id)));
+                }
+
+                DomainEvent::DepositCompleted {
+                    transaction_id: transaction.id,
+                    user_id: transaction.user_id,
+                    amount_kes: transaction.amount_kes.map(KesAmount::new),
+                    amount_sats: Some(amount_sats),
+                    mpesa_code: receipt.clone().map(MpesaCode),
+                }
+            }
+            (ProviderOutcome::Succeeded { receipt }, _) => {
+                sqlx::query!(
+                    "UPDATE transactions SET mpesa_code = $2 WHERE id = $1",
+                    transaction.id,
+                    receipt.as_deref(),
+                )
+                .execute(&mut *tx)
+                .await?;
+
+                DomainEvent::WithdrawalCompleted {
+                    transaction_id: transaction.id,
+                    user_id: transaction.user_id,
+                    amount_sats: transaction.amount_sats.map(SatAmount::new),
+                    amount_kes: transaction.amount_kes.map(KesAmount::new),
+                    mpesa_code: receipt.clone().map(MpesaCode),
+                }
+            }
+            (ProviderOutcome::Failed { reason }, transaction_type) => {
+                Self::record_error(&mut *tx, transaction.id, reason).await?;
+                Self::failed_event(&transaction, transaction_type, reason)
+            }
+        };
+
+        let to = match outcome {
+            ProviderOutcome::Succeeded { .. } => TransactionStatus::Completed,
+            _ => TransactionStatus::Failed,
+        };
+        let transition = StatusTransition::new(TransactionStatus::Processing, to)?;
+        transition_status(&mut *tx, transaction.id, &transition, &StatusActor::System, Some("mobile_money_callback")).await?;
+        record_event(&mut *tx, &event).await?;
+
+        tx.commit().await?;
+        Ok(Some(transaction))
+    }
+
+    /// Fail a transaction the provider never accepted
+    #[instrument(skip(self))]
+    pub async fn fail(&self, id: Uuid, reason: &str) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+
+        let row = sqlx::query!(
+            r#"
+            SELECT user_id, type as "transaction_type: TransactionType",
+                   provider as "provider!: MobileMoneyNetwork", amount_kes, amount_sats, fee_kes, exchange_rate,
+                   created_at
+            FROM transactions
+            WHERE id = $1 AND status = 'processing'
+            FOR UPDATE
+            "#,
+            id,
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+        let Some(row) = row else {
+            return Ok(());
+        };
+        let transaction = ProviderTransaction {
+            id,
+            user_id: UserId(row.user_id),
+            transaction_type: row.transaction_type,
+            provider: row.provider,
+            provider_reference: String::new(),
+            amount_kes: row.amount_kes,
+            amount_sats: row.amount_sats,
+            fee_kes: row.fee_kes,
+            exchange_rate: row.exchange_rate,
+            created_at: row.created_at,
+        };
+
+        Self::record_error(&mut *tx, id, reason).await?;
+        let transition = StatusTransition::new(TransactionStatus::Processing, TransactionStatus::Failed)?;
+        transition_status(&mut *tx, id, &transition, &StatusActor::System, Some("mobile_money_rejected")).await?;
+        record_event(&mut *tx, &Self::failed_event(&transaction, &transaction.transaction_type, reason)).await?;
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Leave a transaction the provider never confirmed to an operator, who refunds it
+    /// through `/internal/refunds` if the money never moved; it is no longer checked
+    #[instrument(skip(self))]
+    pub async fn flag_for_review(&self, id: Uuid, reason: &str) -> Result<()> {
+        sqlx::query!(
+            r#"
+            UPDATE transactions
+            SET metadata = COALESCE(metadata, '{}') || jsonb_build_object('needs_review', true, 'error', $2::text)
+            WHERE id = $1 AND status = 'processing'
+            "#,
+            id,
+            reason,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Claim processing transactions due for a status check
+    ///
+    /// Requests the provider never answered have an empty `provider_reference`.
+    #[instrument(skip(self))]
+    pub async fn claim_stale(&self, limit: i64) -> Result<Vec<ProviderTransaction>> {
+        let rows = sqlx::query!(
+            r#"
+            UPDATE transactions SET provider_checked_at = NOW()
+            WHERE id IN (
+                SELECT id FROM transactions
+                WHERE status = 'processing' AND provider IS NOT NULL
+                  AND NOT COALESCE((metadata->>'needs_review')::boolean, false)
+                  AND created_at <= NOW() - make_interval(mins => $1)
+                  AND (provider_checked_at IS NULL OR provider_checked_at <= NOW() - make_interval(mins => $1))
+                ORDER BY created_at
+                LIMIT $2
+                FOR UPDATE SKIP LOCKED
+            )
+            RETURNING id, user_id, type as "transaction_type: TransactionType",
+                      provider as "provider!: MobileMoneyNetwork",
+                      COALESCE(provider_reference, '') as "provider_reference!",
+                      amount_kes, amount_sats, fee_kes, exchange_rate, created_at
+            "#,
+            STATUS_CHECK_AFTER_MINUTES as i32,
+            limit,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|r| ProviderTransaction {
+                id: r.id,
+                user_id: UserId(r.user_id),
+                transaction_type: r.transaction_type,
+                provider: r.provider,
+                provider_reference: r.provider_reference,
+                amount_kes: r.amount_kes,
+                amount_sats: r.amount_sats,
+                fee_kes: r.fee_kes,
+                exchange_rate: r.exchange_rate,
+                created_at: r.created_at,
+            })
+            .collect())
+    }
+
+    async fn record_error(conn: &mut PgConnection, id: Uuid, reason: &str) -> Result<()> {
+        sqlx::query!(
+            "UPDATE transactions SET metadata = COALESCE(metadata, '{}') || jsonb_build_object('error', $2::text) WHERE id = $1",
+            id,
+            reason,
+        )
+        .execute(conn)
+        .await?;
+
+        Ok(())
+    }
+
+    fn failed_event(transaction: &ProviderTransaction, transaction_type: &TransactionType, reason: &str) -> DomainEvent {
+        match transaction_type {
+            TransactionType::DepositMpesa => DomainEvent::DepositFailed {
+                transaction_id: transaction.id,
+                user_id: transaction.user_id,
+                amount_kes: transaction.amount_kes.map(KesAmount::new),
+                reason: Some(reason.to_string()),
+            },
+            // Refunded to the wallet by the refunds consumer
+            _ => DomainEvent::WithdrawalFailed {
+                transaction_id: transaction.id,
+                user_id: transaction.user_id,
+                amount_sats: transaction.amount_sats.map(SatAmount::new),
+                reason: Some(reason.to_string()),
+            },
+        }
+    }
+}
+
+/// Mobile money deposits and withdrawals on any network
+pub struct MobileMoneyService {
+    repository: Arc<MobileMoneyRepository>,
+    providers: MobileMoneyProviders,
+    fee_service: Arc<FeeService>,
+    limits_service: Arc<LimitsService>,
+    callback_token: Option<String>,
+}
+
+impl MobileMoneyService {
+    pub fn new(
+        repository: Arc<MobileMoneyRepository>,
+        providers: MobileMoneyProviders,
+        fee_service: Arc<FeeService>,
+        limits_service: Arc<LimitsService>,
+    ) -> Self {
+        Self {
+            repository,
+            providers,
+            fee_service,
+            limits_service,
+            callback_token: callback_token_from_env(),
+        }
+    }
+
+    pub fn options(&self) -> Vec<MobileMoneyOption> {
+        self.providers
+            .networks()
+            .into_iter()
+            .map(|provider| MobileMoneyOption {
+                provider,
+                name: provider.display_name(),
+            })
+            .collect()
+    }
+
+    async fn phone_or_default(&self, user_id: UserId, phone_number: Option<String>) -> Result<PhoneNumber> {
+        let phone_number = match phone_number {
+            Some(phone_number) => phone_number,
+            None => self.repository.phone_number(user_id).await?,
+        };
+        PhoneNumber::new(phone_number).map_err(|_| AppError::invalid_phone_number())
+    }
+
+    /// Store the reference a request will be reported under when it's known before sending,
+    /// so a request whose answer is lost can still be settled
+    async fn record_client_reference(
+        &self,
+        provider: &dyn MobileMoneyProvider,
+        direction: TransferDirection,
+        transaction_id: Uuid,
+    ) -> Result<()> {
+        match provider.client_reference(direction, transaction_id) {
+            Some(reference) => self.repository.set_provider_reference(transaction_id, &reference).await,
+            None => Ok(()),
+        }
+    }
+
+    /// Record the provider's answer to a new request
+    ///
+    /// Only an explicit refusal fails the transaction. Without a usable answer the money
+    /// may still move, so the transaction stays processing for the callback or status checks.
+    async fn apply_submission(
+        &self,
+        network: MobileMoneyNetwork,
+        transaction_id: Uuid,
+        submission: Result<Submission>,
+    ) -> Result<()> {
+        match submission {
+            Ok(Submission::Accepted(reference)) => {
+                self.repository.set_provider_reference(transaction_id, &reference).await
+            }
+            Ok(Submission::Rejected(e)) => {
+                self.repository.fail(transaction_id, &e.to_string()).await?;
+                Err(e)
+            }
+            Err(e) => {
+                warn!(
+                    "{} did not confirm transaction {}, leaving it processing: {:?}",
+                    network.display_name(),
+                    transaction_id,
+                    e
+                );
+                Ok(())
+            }
+        }
+    }
+
+    /// Ask the customer's phone to approve a payment; sats are credited on the provider's callback
+    #[instrument(skip(self, request))]
+    pub async fn deposit(&self, user_id: UserId, request: MobileMoneyDepositRequest) -> Result<MobileMoneyDepositResponse> {
+        request.validate().map_err(|e| AppError::Validation {
+            message: format!("Invalid deposit: {}", e),
+        })?;
+        let provider = self.providers.get(request.provider)?;
+        let phone_number = self.phone_or_default(user_id, request.phone_number).await?;
+
+        let amount_kes = Decimal::from(request.amount_kes);
+        self.limits_service.check_kes(user_id, amount_kes).await?;
+        let fees = self.fee_service.quote(&TransactionType::DepositMpesa, amount_kes).await?;
+        let rate = self.limits_service.current_rate().await?;
+        let net_kes = KesAmount::new(amount_kes - fees.total_kes.as_decimal());
+        let estimated_sats = rate.kes_to_sats(&net_kes, Side::Payout, Rounding::HouseFavourable)?;
+
+        let transaction_id = self
+            .repository
+            .create_deposit(
+                user_id,
+                request.provider,
+                &phone_number,
+                amount_kes,
+                fees.total_kes.as_decimal(),
+                rate,
+                fees.schedule_id,
+            )
+            .await?;
+
+        let provider_request = MobileMoneyRequest {
+            transaction_id,
+            phone_number,
+            amount_kes,
+            description: "PesaBit deposit".to_string(),
+        };
+        self.record_client_reference(provider.as_ref(), TransferDirection::Collection, transaction_id)
+            .await?;
+        let submission = provider.collect(&provider_request).await;
+        self.apply_submission(request.provider, transaction_id, submission).await?;
+
+        info!("{} deposit {} requested for user {}", request.provider.display_name(), transaction_id, user_id);
+        Ok(MobileMoneyDepositResponse {
+            transaction_id,
+            provider: request.provider,
+            amount_kes: KesAmount::new(amount_kes),
+            estimated_sats,
+            exchange_rate: rate,
+            fee_kes: fees.total_kes,
+            fee_schedule_version: fees.schedule_version,
+            message: format!("Approve the {} payment on your phone", request.provider.display_name()),
+        })
+    }
+
+    /// Sell sats and send the KES to a phone; failed payouts are refunded to the wallet
+    #[instrument(skip(self, request))]
+    pub async fn withdraw(
+        &self,
+        user_id: UserId,
+        request: MobileMoneyWithdrawalRequest,
+    ) -> Result<MobileMoneyWithdrawalResponse> {
+        request.validate().map_err(|e| AppError::Validation {
+            message: format!("Invalid withdrawal: {}", e),
+        })?;
+        let recipient_phone = self.phone_or_default(user_id, request.recipient_phone).await?;
+
+        let amount_sats = SatAmount::new(request.amount_sats);
+        self.limits_service.check_sats(user_id, amount_sats).await?;
+        let rate = self.limits_service.current_rate().await?;
+        // Networks only pay out whole shillings
+        let payout = rate.sats_to_kes(amount_sats, Side::Payout, Rounding::HouseFavourable)?;
+        let amount_kes = KesAmount::new(payout.as_decimal().trunc());
+
+        self.pay_out(user_id, request.provider, recipient_phone, amount_sats, amount_kes, rate)
+            .await
+    }
+
+    /// Send exactly `amount_kes` to a phone, selling the sats it costs at the current rate
+    #[instrument(skip(self))]
+    pub async fn withdraw_kes(
+        &self,
+        user_id: UserId,
+        network: MobileMoneyNetwork,
+        recipient_phone: &str,
+        amount_kes: KesAmount,
+    ) -> Result<MobileMoneyWithdrawalResponse> {
+        if amount_kes.as_decimal().fract() != Decimal::ZERO {
+            return Err(AppError::Validation {
+                message: "Mobile money payouts are in whole shillings".to_string(),
+            });
+        }
+        let recipient_phone = self.phone_or_default(user_id, Some(recipient_phone.to_string())).await?;
+
+        self.limits_service.check_kes(user_id, amount_kes.as_decimal()).await?;
+        let rate = self.limits_service.current_rate().await?;
+        let amount_sats = rate.kes_to_sats(&amount_kes, Side::Charge, Rounding::HouseFavourable)?;
+
+        self.pay_out(user_id, network, recipient_phone, amount_sats, amount_kes, rate)
+            .await
+    }
+
+    /// Debit the sats and ask the network to pay out the shillings
+    async fn pay_out(
+        &self,
+        user_id: UserId,
+        network: MobileMoneyNetwork,
+        recipient_phone: PhoneNumber,
+        amount_sats: SatAmount,
+        amount_kes: KesAmount,
+        rate: BtcKesRate,
+    ) -> Result<MobileMoneyWithdrawalResponse> {
+        let provider = self.providers.get(network)?;
+        let fees = self
+            .fee_service
+            .quote(&TransactionType::WithdrawalMpesa, amount_kes.as_decimal())
+            .await?;
+        let fee_sats = rate.kes_to_sats(&fees.total_kes, Side::Charge, Rounding::HouseFavourable)?;
+
+        let transaction_id = self
+            .repository
+            .create_withdrawal(
+                user_id,
+                network,
+                serde_json::json!({ "recipient_phone": recipient_phone.0 }),
+                amount_sats,
+                amount_kes,
+                fee_sats,
+                fees.total_kes,
+                rate,
+                fees.schedule_id,
+            )
+            .await?;
+
+        let provider_request = MobileMoneyRequest {
+            transaction_id,
+            phone_number: recipient_phone.clone(),
+            amount_kes: amount_kes.as_decimal(),
+            description: "PesaBit withdrawal".to_string(),
+        };
+        self.record_client_reference(provider.as_ref(), TransferDirection::Disbursement, transaction_id)
+            .await?;
+        let submission = provider.disburse(&provider_request).await;
+        self.apply_submission(network, transaction_id, submission).await?;
+
+        info!("{} withdrawal {} sent for user {}", network.display_name(), transaction_id, user_id);
+        Ok(MobileMoneyWithdrawalResponse {
+            transaction_id,
+            provider: network,
+            amount_sats,
+            amount_kes,
+            exchange_rate: rate,
+            fee_kes: fees.total_kes,
+            fee_sats,
+            fee_schedule_version: fees.schedule_version,
+            recipient_phone,
+        })
+    }
+
+    /// Apply a provider callback (duplicates and callbacks for settled transactions are ignored)
+    ///
+    /// Successful deposits are confirmed with the provider before the wallet is credited.
+    #[instrument(skip(self, token, payload))]
+    pub async fn handle_callback(&self, network: MobileMoneyNetwork, token: Option<&str>, payload: &Value) -> Result<()> {
+        if let Err(e) = authorize_callback(self.callback_token.as_deref(), token) {
+            warn!("Refused {} callback without a valid token", network.display_name());
+            return Err(e);
+        }
+
+        let provider = self.providers.get(network)?;
+        let callback = provider.parse_callback(payload)?;
+        let mut outcome = callback.outcome;
+        if let ProviderOutcome::Succeeded { receipt } = &outcome {
+            let Some(transaction) = self.repository.find_processing(network, &callback.provider_reference).await? else {
+                return Ok(());
+            };
+            if TransferDirection::of(&transaction.transaction_type) == Some(TransferDirection::Collection) {
+                outcome = match provider
+                    .query_status(TransferDirection::Collection, &callback.provider_reference)
+                    .await?
+                {
+                    ProviderOutcome::Succeeded { receipt: confirmed } => ProviderOutcome::Succeeded {
+                        receipt: confirmed.or_else(|| receipt.clone()),
+                    },
+                    // Left for the status checker
+                    ProviderOutcome::Pending => {
+                        warn!("{} has not confirmed deposit {} yet", network.display_name(), transaction.id);
+                        return Ok(());
+                    }
+                    failed => failed,
+                };
+            }
+        }
+
+        if let Some(transaction) = self
+            .repository
+            .settle(network, &callback.provider_reference, &outcome)
+            .await?
+        {
+            info!("{} callback settled transaction {}", network.display_name(), transaction.id);
+        }
+        Ok(())
+    }
+
+    /// Check a callback for a deposit made through the legacy `/deposits/mpesa`
+    /// endpoint before `PaymentService` applies it
+    ///
+    /// Those deposits aren't tracked here, so the callback is only authorized
+    /// and confirmed with M-Pesa. Returns whether M-Pesa reports the same outcome;
+    /// a deposit it hasn't settled yet is left for the next callback.
+    #[instrument(skip(self, token, payload))]
+    pub async fn verify_legacy_deposit_callback(&self, token: Option<&str>, payload: &Value) -> Result<bool> {
+        if let Err(e) = authorize_callback(self.callback_token.as_deref(), token) {
+            warn!("Refused legacy M-Pesa deposit callback without a valid token");
+            return Err(e);
+        }
+
+        let provider = self.providers.get(MobileMoneyNetwork::Mpesa)?;
+        let callback = provider.parse_callback(payload)?;
+        let confirmed = provider
+            .query_status(TransferDirection::Collection, &callback.provider_reference)
+            .await?;
+
+        let agrees = matches!(
+            (&callback.outcome, &confirmed),
+            (ProviderOutcome::Succeeded { .. }, ProviderOutcome::Succeeded { .. })
+                | (ProviderOutcome::Failed { .. }, ProviderOutcome::Failed { .. })
+        );
+        if !agrees {
+            warn!(
+                "M-Pesa reports {:?} for deposit {}, ignoring callback reporting {:?}",
+                confirmed, callback.provider_reference, callback.outcome
+            );
+        }
+        Ok(agrees)
+    }
+
+    /// Ask providers about transactions whose callback hasn't arrived
+    pub async fn check_stale(&self, limit: i64) -> Result<usize> {
+        let stale = self.repository.claim_stale(limit).await?;
+        let mut settled = 0;
+
+        for transaction in stale {
+            let Some(direction) = TransferDirection::of(&transaction.transaction_type) else {
+                continue;
+            };
+            // Without a reference there's nothing to ask; the request just times out
+            let unanswered = transaction.provider_reference.is_empty();
+            let outcome = match self.providers.get(transaction.provider) {
+                Ok(_) if unanswered => Ok(ProviderOutcome::Pending),
+                Ok(provider) => provider.query_status(direction, &transaction.provider_reference).await,
+                Err(e) => Err(e),
+            };
+            let outcome = match outcome {
+                Ok(ProviderOutcome::Pending)
+                    if direction == TransferDirection::Collection
+                        && transaction.created_at <= Utc::now() - Duration::minutes(DEPOSIT_TIMEOUT_MINUTES) =>
+                {
+                    ProviderOutcome::Failed {
+                        reason: "Payment was not approved in time".to_string(),
+                    }
+                }
+                Ok(ProviderOutcome::Pending)
+                    if direction == TransferDirection::Disbursement
+                        && transaction.created_at <= Utc::now() - Duration::hours(DISBURSEMENT_REVIEW_HOURS) =>
+                {
+                    error!(
+                        "{} payout {} unconfirmed after {} hours, needs operator review",
+                        transaction.provider.display_name(),
+                        transaction.id,
+                        DISBURSEMENT_REVIEW_HOURS
+                    );
+                    self.repository
+                        .flag_for_review(transaction.id, "Payout was never confirmed by the provider")
+                        .await?;
+                    continue;
+                }
+                Ok(outcome) => outcome,
+                Err(e) => {
+                    warn!("Status check failed for transaction {}: {:?}", transaction.id, e);
+                    continue;
+                }
+            };
+
+            match outcome {
+                ProviderOutcome::Failed { reason } if unanswered => {
+                    self.repository.fail(transaction.id, &reason).await?;
+                    settled += 1;
+                }
+                _ if unanswered => {}
+                outcome => {
+                    if self
+                        .repository
+                        .settle(transaction.provider, &transaction.provider_reference, &outcome)
+                        .await?
+                        .is_some()
+                    {
+                        settled += 1;
+                    }
+                }
+            }
+        }
+
+        Ok(settled)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mpesa_callbacks() {
+        let stk = serde_json::json!({
+            "Body": {
+                "stkCallback": {
+                    "MerchantRequestID": "29115-34620561-1",
+                    "CheckoutRequestID": "ws_CO_191220191020363925",
+                    "ResultCode": 0,
+                    "ResultDesc": "The service request is processed successfully.",
+                    "CallbackMetadata": {
+                        "Item": [
+                            { "Name": "Amount", "Value": 1000 },
+                            { "Name": "MpesaReceiptNumber", "Value": "NLJ7RT61SV" },
+                            { "Name": "PhoneNumber", "Value": 254708374149u64 }
+                        ]
+                    }
+                }
+            }
+        });
+        assert_eq!(
+            parse_mpesa_callback(&stk).unwrap(),
+            ProviderCallback {
+                provider_reference: "ws_CO_191220191020363925".to_string(),
+                outcome: ProviderOutcome::Succeeded {
+                    receipt: Some("NLJ7RT61SV".to_string())
+                },
+            }
+        );
+
+        let cancelled = serde_json::json!({
+            "Body": { "stkCallback": {
+                "CheckoutRequestID": "ws_CO_1", "ResultCode": 1032, "ResultDesc": "Request cancelled by user"
+            } }
+        });
+        assert_eq!(
+            parse_mpesa_callback(&cancelled).unwrap().outcome,
+            ProviderOutcome::Failed {
+                reason: "Request cancelled by user".to_string()
+            }
+        );
+
+        let b2c = serde_json::json!({
+            "Result": {
+                "ResultType": 0, "ResultCode": 0, "ResultDesc": "The service request is processed successfully.",
+                "OriginatorConversationID": "10571-7910404-1", "ConversationID": "AG_20191219_00004e48cf7e3533f581",
+                "TransactionID": "NLJ41HAY6Q"
+            }
+        });
+        assert_eq!(
+            parse_mpesa_callback(&b2c).unwrap(),
+            ProviderCallback {
+                provider_reference: "10571-7910404-1".to_string(),
+                outcome: ProviderOutcome::Succeeded {
+                    receipt: Some("NLJ41HAY6Q".to_string())
+                },
+            }
+        );
+
+        assert!(parse_mpesa_callback(&serde_json::json!({ "hello": "world" })).is_err());
+    }
+
+    #[test]
+    fn test_airtel_money() {
+        let provider = AirtelMoneyProvider::new(AirtelMoneyConfig {
+            base_url: "https://openapiuat.airtel.africa".to_string(),
+            client_id: "id".to_string(),
+            client_secret: "secret".to_string(),
+            encrypted_pin: "pin".to_string(),
+        });
+        let callback = serde_json::json!({
+            "transaction": {
+                "id": "0b8f4c1e5d1a4c3e9f532f0e6a7b9c10",
+                "message": "Paid KES 1000 to PesaBit",
+                "status_code": "TS",
+                "airtel_money_id": "MP210603.1234.L06941"
+            }
+        });
+        assert_eq!(
+            provider.parse_callback(&callback).unwrap(),
+            ProviderCallback {
+                provider_reference: "0b8f4c1e5d1a4c3e9f532f0e6a7b9c10".to_string(),
+                outcome: ProviderOutcome::Succeeded {
+                    receipt: Some("MP210603.1234.L06941".to_string())
+                },
+            }
+        );
+
+        assert_eq!(airtel_outcome(Some("TIP"), None, None), ProviderOutcome::Pending);
+        assert_eq!(
+            airtel_outcome(Some("TF"), None, Some("Insufficient funds")),
+            ProviderOutcome::Failed {
+                reason: "Insufficient funds".to_string()
+            }
+        );
+
+        let phone = PhoneNumber::new("+254733123456".to_string()).unwrap();
+        assert_eq!(airtel_msisdn(&phone), "733123456");
+        assert_eq!(international_msisdn(&phone), "254733123456");
+    }
+
+    #[test]
+    fn test_deposit_sats() {
+        let deposit = ProviderTransaction {
+            id: Uuid::new_v4(),
+            user_id: UserId::new(),
+            transaction_type: TransactionType::DepositMpesa,
+            provider: MobileMoneyNetwork::AirtelMoney,
+            provider_reference: "ref".to_string(),
+            amount_kes: Some(Decimal::from(1000)),
+            amount_sats: None,
+            fee_kes: Some(Decimal::from(15)),
+            exchange_rate: Some(Decimal::from(10_000_000)),
+            created_at: Utc::now(),
+        };
+        // KES 985 at KES 10M per BTC
+        assert_eq!(deposit_sats(&deposit).unwrap(), SatAmount::new(9850));
+        assert!(deposit_sats(&ProviderTransaction { exchange_rate: None, ..deposit }).is_err());
+
+        assert_eq!(whole_kes(Decimal::new(100_075, 2)).unwrap(), 1000);
+        assert!(whole_kes(Decimal::new(50, 2)).is_err());
+    }
+
+    #[test]
+    fn test_rejections() {
+        let rejected = serde_json::json!({ "ResponseCode": "1", "ResponseDescription": "Insufficient balance" });
+        assert!(daraja_rejected(&rejected));
+        assert!(daraja_rejected(&serde_json::json!({ "errorCode": "400.002.02", "errorMessage": "Bad Request" })));
+        assert!(!daraja_rejected(&serde_json::json!({ "ResponseCode": "0" })));
+        assert!(!daraja_rejected(&serde_json::json!({ "fault": "gateway timeout" })));
+
+        let accepted = serde_json::json!({ "status": { "success": true } });
+        assert!(matches!(
+            airtel_submission(&accepted, "ref".to_string(), "rejected"),
+            Ok(Submission::Accepted(reference)) if reference == "ref"
+        ));
+        let refused = serde_json::json!({ "status": { "success": false, "message": "Invalid MSISDN" } });
+        assert!(matches!(
+            airtel_submission(&refused, "ref".to_string(), "rejected"),
+            Ok(Submission::Rejected(_))
+        ));
+        // Unreadable answers may hide an accepted request
+        assert!(airtel_submission(&serde_json::json!({}), "ref".to_string(), "rejected").is_err());
+    }
+}
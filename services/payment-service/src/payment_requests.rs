@@ -0,0 +1,790 @@
+/// Peer-to-peer payment requests
+///
+/// A user asks another PesaBit user (by phone number or Lightning username) for
+/// an amount in sats or KES. The payer approves, which pays the request with an
+/// internal transfer after the usual limit checks, or declines; the requester
+/// can withdraw the request while it is pending. Requests not answered in time
+/// expire. Both sides see the request and are notified as it changes.
+
+use crate::limits::LimitsService;
+use crate::transfers::{self, get_party, find_party, InternalTransfer, Party, RecipientHandle};
+use chrono::{DateTime, Duration, Utc};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use shared_errors::{AppError, Result};
+use shared_events::{record_event, DomainEvent};
+use shared_types::conversion::{BtcKesRate, Rounding, Side};
+use shared_types::*;
+use sqlx::{PgConnection, PgPool};
+use std::sync::Arc;
+use tracing::{info, instrument};
+use uuid::Uuid;
+use validator::Validate;
+
+/// How long a request stays open when the requester doesn't say
+const DEFAULT_EXPIRY_HOURS: i64 = 72;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[sqlx(type_name = "payment_request_status", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum PaymentRequestStatus {
+    Pending,
+    Paid,
+    Declined,
+    Cancelled,
+    Expired,
+}
+
+impl PaymentRequestStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PaymentRequestStatus::Pending => "pending",
+            PaymentRequestStatus::Paid => "paid",
+            PaymentRequestStatus::Declined => "declined",
+            PaymentRequestStatus::Cancelled => "cancelled",
+            PaymentRequestStatus::Expired => "expired",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PaymentRequestCurrency {
+    Sats,
+    Kes,
+}
+
+/// Amount asked for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RequestedAmount {
+    Sats(SatAmount),
+    /// Converted at the rate when the request is paid
+    Kes(KesAmount),
+}
+
+impl RequestedAmount {
+    pub fn new(amount: Decimal, currency: PaymentRequestCurrency) -> Result<Self> {
+        if amount <= Decimal::ZERO {
+            return Err(AppError::Validation {
+                message: "Amount must be positive".to_string(),
+            });
+        }
+
+        match currency {
+            PaymentRequestCurrency::Sats => {
+                let sats = i64::try_from(amount)
+                    .ok()
+                    .filter(|_| amount.fract().is_zero())
+                    .ok_or_else(|| AppError::Validation {
+                        message: "Sats amounts must be whole numbers".to_string(),
+                    })?;
+                Ok(RequestedAmount::Sats(SatAmount::new(sats)))
+            }
+            PaymentRequestCurrency::Kes => Ok(RequestedAmount::Kes(KesAmount::new(amount))),
+        }
+    }
+
+    fn from_columns(amount_sats: Option<i64>, amount_kes: Option<Decimal>) -> Result<Self> {
+        match (amount_sats, amount_kes) {
+            (Some(sats), None) => Ok(RequestedAmount::Sats(SatAmount::new(sats))),
+            (None, Some(kes)) => Ok(RequestedAmount::Kes(KesAmount::new(kes))),
+            _ => Err(AppError::Internal(anyhow::anyhow!("Payment request must have exactly one amount"))),
+        }
+    }
+
+    fn columns(&self) -> (Option<i64>, Option<Decimal>) {
+        match self {
+            RequestedAmount::Sats(sats) => (Some(sats.as_i64()), None),
+            RequestedAmount::Kes(kes) => (None, Some(kes.as_decimal())),
+        }
+    }
+
+    pub fn amount(&self) -> Decimal {
+        match self {
+            RequestedAmount::Sats(sats) => Decimal::from(sats.as_i64()),
+            RequestedAmount::Kes(kes) => kes.as_decimal(),
+        }
+    }
+
+    pub fn currency(&self) -> PaymentRequestCurrency {
+        match self {
+            RequestedAmount::Sats(_) => PaymentRequestCurrency::Sats,
+            RequestedAmount::Kes(_) => PaymentRequestCurrency::Kes,
+        }
+    }
+
+    /// Amount for notifications, e.g. "KES 500.00" or "5000 sats"
+    pub fn display(&self) -> String {
+        match self {
+            RequestedAmount::Sats(sats) => format!("{} sats", sats.as_i64()),
+            RequestedAmount::Kes(kes) => format!("KES {:.2}", kes.as_decimal()),
+        }
+    }
+}
+
+/// Request for money from another user
+#[derive(Debug, Deserialize, Validate)]
+pub struct CreatePaymentRequestRequest {
+    /// Phone number (E.164) or Lightning username of the payer
+    #[validate(length(min = 1, max = 64))]
+    pub to: String,
+    pub amount: Decimal,
+    pub currency: PaymentRequestCurrency,
+    #[validate(length(max = 140))]
+    pub memo: Option<String>,
+    /// Hours until the request expires (default 72, at most 30 days)
+    #[validate(range(min = 1, max = 720))]
+    pub expires_in_hours: Option<i64>,
+}
+
+/// Which requests to list
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PaymentRequestDirection {
+    /// Requests the user was asked to pay
+    Incoming,
+    /// Requests the user sent
+    Outgoing,
+}
+
+impl PaymentRequestDirection {
+    fn as_str(&self) -> &'static str {
+        match self {
+            PaymentRequestDirection::Incoming => "incoming",
+            PaymentRequestDirection::Outgoing => "outgoing",
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ListPaymentRequestsParams {
+    pub direction: Option<PaymentRequestDirection>,
+    pub status: Option<PaymentRequestStatus>,
+}
+
+/// What a user does with a request
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PaymentRequestAction {
+    Approve,
+    Decline,
+    Cancel,
+}
+
+/// Payment request as stored
+#[derive(Debug, Clone)]
+pub struct PaymentRequestRecord {
+    pub id: Uuid,
+    pub requester: Party,
+    pub payer: Party,
+    pub amount: RequestedAmount,
+    pub memo: Option<String>,
+    pub status: PaymentRequestStatus,
+    pub expires_at: DateTime<Utc>,
+    pub paid_amount_sats: Option<i64>,
+    pub debit_transaction_id: Option<Uuid>,
+    pub credit_transaction_id: Option<Uuid>,
+    pub created_at: DateTime<Utc>,
+    pub resolved_at: Option<DateTime<Utc>>,
+}
+
+impl PaymentRequestRecord {
+    fn involves(&self, user_id: UserId) -> bool {
+        self.requester.user_id == user_id || self.payer.user_id == user_id
+    }
+
+    /// Whether `user_id` may take `action` on the request now
+    pub fn check(&self, user_id: UserId, action: PaymentRequestAction, now: DateTime<Utc>) -> Result<()> {
+        if !self.involves(user_id) {
+            return Err(not_found());
+        }
+
+        let allowed = match action {
+            PaymentRequestAction::Approve | PaymentRequestAction::Decline => self.payer.user_id == user_id,
+            PaymentRequestAction::Cancel => self.requester.user_id == user_id,
+        };
+        if !allowed {
+            let message = match action {
+                PaymentRequestAction::Approve | PaymentRequestAction::Decline => {
+                    "Only the user asked to pay can answer this request"
+                }
+                PaymentRequestAction::Cancel => "Only the user who sent this request can withdraw it",
+            };
+            return Err(AppError::Validation {
+                message: message.to_string(),
+            });
+        }
+
+        if self.status != PaymentRequestStatus::Pending {
+            return Err(AppError::Conflict {
+                message: format!("Payment request is already {}", self.status.as_str()),
+            });
+        }
+        if self.expires_at <= now {
+            return Err(AppError::Conflict {
+                message: "Payment request has expired".to_string(),
+            });
+        }
+
+        Ok(())
+    }
+}
+
+/// Payment request as seen by one of its users
+#[derive(Debug, Serialize)]
+pub struct PaymentRequestResponse {
+    pub id: Uuid,
+    pub direction: PaymentRequestDirection,
+    /// Lightning address of the user asking
+    pub requester: String,
+    /// Lightning address of the user asked to pay
+    pub payer: String,
+    pub amount: Decimal,
+    pub currency: PaymentRequestCurrency,
+    pub memo: Option<String>,
+    pub status: PaymentRequestStatus,
+    pub expires_at: DateTime<Utc>,
+    /// Sats moved once paid
+    pub paid_amount_sats: Option<i64>,
+    /// This user's side of the payment once paid
+    pub transaction_id: Option<Uuid>,
+    pub created_at: DateTime<Utc>,
+    pub resolved_at: Option<DateTime<Utc>>,
+}
+
+impl PaymentRequestResponse {
+    pub fn for_user(record: PaymentRequestRecord, user_id: UserId) -> Self {
+        let incoming = record.payer.user_id == user_id;
+        Self {
+            id: record.id,
+            direction: if incoming {
+                PaymentRequestDirection::Incoming
+            } else {
+                PaymentRequestDirection::Outgoing
+            },
+            requester: record.requester.address,
+            payer: record.payer.address,
+            amount: record.amount.amount(),
+            currency: record.amount.currency(),
+            memo: record.memo,
+            status: record.status,
+            expires_at: record.expires_at,
+            paid_amount_sats: record.paid_amount_sats,
+            transaction_id: if incoming {
+                record.debit_transaction_id
+            } else {
+                record.credit_transaction_id
+            },
+            created_at: record.created_at,
+            resolved_at: record.resolved_at,
+        }
+    }
+}
+
+fn not_found() -> AppError {
+    AppError::Payment {
+        message: "Payment request not found".to_string(),
+    }
+}
+
+/// Database access for payment requests
+pub struct PaymentRequestRepository {
+    pool: PgPool,
+}
+
+impl PaymentRequestRepository {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+
+    /// Create a request and notify the payer
+    #[instrument(skip(self, memo))]
+    pub async fn create(
+        &self,
+        requester_id: UserId,
+        payer: &RecipientHandle,
+        amount: RequestedAmount,
+        memo: Option<&str>,
+        expires_at: DateTime<Utc>,
+    ) -> Result<PaymentRequestRecord> {
+        let mut tx = self.pool.begin().await?;
+
+        let requester = get_party(&mut *tx, requester_id).await?;
+        let payer = find_party(&mut *tx, payer).await?.ok_or_else(|| AppError::User {
+            message: "No PesaBit account found for that phone number or username".to_string(),
+        })?;
+        if payer.user_id == requester.user_id {
+            return Err(AppError::Validation {
+                message: "You can't request money from yourself".to_string(),
+            });
+        }
+
+        let (amount_sats, amount_kes) = amount.columns();
+        let row = sqlx::query!(
+            r#"
+            INSERT INTO payment_requests (requester_id, payer_id, amount_sats, amount_kes, memo, expires_at)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            RETURNING id
+            "#,
+            requester.user_id.0,
+            payer.user_id.0,
+            amount_sats,
+            amount_kes,
+            memo,
+            expires_at,
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+
+        record_event(
+            &mut *tx,
+            &DomainEvent::PaymentRequestReceived {
+                payment_request_id: row.id,
+                user_id: payer.user_id,
+                requester: requester.address.clone(),
+                amount: amount.display(),
+                memo: memo.map(str::to_string),
+            },
+        )
+        .await?;
+
+        let record = Self::fetch(&mut *tx, row.id).await?.ok_or_else(not_found)?;
+        tx.commit().await?;
+        Ok(record)
+    }
+
+    #[instrument(skip(self))]
+    pub async fn get(&self, id: Uuid) -> Result<Option<PaymentRequestRecord>> {
+        let mut conn = self.pool.acquire().await?;
+        Self::fetch(&mut conn, id).await
+    }
+
+    /// Requests sent or received by the user, newest first
+    #[instrument(skip(self))]
+    pub async fn list(
+        &self,
+        user_id: UserId,
+        direction: Option<PaymentRequestDirection>,
+        status: Option<PaymentRequestStatus>,
+    ) -> Result<Vec<PaymentRequestRecord>> {
+        let rows = sqlx::query!(
+            r#"
+            SELECT pr.id, pr.requester_id, requester.lightning_username AS requester_username,
+                   pr.payer_id, payer.lightning_username AS payer_username,
+                   pr.amount_sats, pr.amount_kes, pr.memo, pr.status as "status: PaymentRequestStatus",
+                   pr.expires_at, pr.paid_amount_sats, pr.debit_transaction_id, pr.credit_transaction_id,
+                   pr.created_at, pr.resolved_at
+            FROM payment_requests pr
+            JOIN users requester ON requester.id = pr.requester_id
+            JOIN users payer ON payer.id = pr.payer_id
+            WHERE CASE $2::text
+                      WHEN 'incoming' THEN pr.payer_id = $1
+                      WHEN 'outgoing' THEN pr.requester_id = $1
+                      ELSE pr.payer_id = $1 OR pr.requester_id = $1
+                  END
+              AND ($3::payment_request_status IS NULL OR pr.status = $3)
+            ORDER BY pr.created_at DESC
+            LIMIT 100
+            "#,
+            user_id.0,
+            direction.map(|d| d.as_str()),
+            status as _,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.into_iter()
+            .map(|r| {
+                Ok(PaymentRequestRecord {
+                    id: r.id,
+                    requester: Party {
+                        user_id: UserId(r.requester_id),
+                        address: LightningAddress::new(&r.requester_username, transfers::LIGHTNING_ADDRESS_DOMAIN).0,
+                    },
+                    payer: Party {
+                        user_id: UserId(r.payer_id),
+                        address: LightningAddress::new(&r.payer_username, transfers::LIGHTNING_ADDRESS_DOMAIN).0,
+                    },
+                    amount: RequestedAmount::from_columns(r.amount_sats, r.amount_kes)?,
+                    memo: r.memo,
+                    status: r.status,
+                    expires_at: r.expires_at,
+                    paid_amount_sats: r.paid_amount_sats,
+                    debit_transaction_id: r.debit_transaction_id,
+                    credit_transaction_id: r.credit_transaction_id,
+                    created_at: r.created_at,
+                    resolved_at: r.resolved_at,
+                })
+            })
+            .collect()
+    }
+
+    /// Pay a pending request with an internal transfer and notify both users
+    #[instrument(skip(self))]
+    pub async fn pay(
+        &self,
+        id: Uuid,
+        payer_id: UserId,
+        amount_sats: SatAmount,
+        rate: BtcKesRate,
+    ) -> Result<PaymentRequestRecord> {
+        let mut tx = self.pool.begin().await?;
+
+        let request = Self::lock(&mut *tx, id).await?;
+        request.check(payer_id, PaymentRequestAction::Approve, Utc::now())?;
+
+        let receipt = transfers::transfer(
+            &mut *tx,
+            &InternalTransfer {
+                payer: &request.payer,
+                payee: &request.requester,
+                amount_sats,
+                rate,
+                memo: request.memo.as_deref(),
+                reference: serde_json::json!({ "payment_request_id": request.id }),
+            },
+        )
+        .await?;
+
+        sqlx::query!(
+            r#"
+            UPDATE payment_requests
+            SET status = 'paid', paid_amount_sats = $2, debit_transaction_id = $3,
+                credit_transaction_id = $4, resolved_at = NOW()
+            WHERE id = $1
+            "#,
+            id,
+            amount_sats.as_i64(),
+            receipt.debit_transaction_id,
+            receipt.credit_transaction_id,
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        record_event(
+            &mut *tx,
+            &DomainEvent::PaymentRequestPaid {
+                payment_request_id: id,
+                user_id: request.requester.user_id,
+                payer: request.payer.address.clone(),
+                amount_sats,
+                transaction_id: receipt.credit_transaction_id,
+            },
+        )
+        .await?;
+        record_event(
+            &mut *tx,
+            &DomainEvent::PaymentRequestApproved {
+                payment_request_id: id,
+                user_id: request.payer.user_id,
+                requester: request.requester.address.clone(),
+                amount_sats,
+                transaction_id: receipt.debit_transaction_id,
+            },
+        )
+        .await?;
+
+        let record = Self::fetch(&mut *tx, id).await?.ok_or_else(not_found)?;
+        tx.commit().await?;
+        Ok(record)
+    }
+
+    /// Decline (payer) or withdraw (requester) a pending request and notify the other user
+    #[instrument(skip(self))]
+    pub async fn close(&self, id: Uuid, user_id: UserId, action: PaymentRequestAction) -> Result<PaymentRequestRecord> {
+        let mut tx = self.pool.begin().await?;
+
+        let request = Self::lock(&mut *tx, id).await?;
+        request.check(user_id, action, Utc::now())?;
+
+        let (status, event) = match action {
+            PaymentRequestAction::Decline => (
+                PaymentRequestStatus::Declined,
+                DomainEvent::PaymentRequestDeclined {
+                    payment_request_id: id,
+                    user_id: request.requester.user_id,
+                    payer: request.payer.address.clone(),
+                    amount: request.amount.display(),
+                },
+            ),
+            PaymentRequestAction::Cancel => (
+                PaymentRequestStatus::Cancelled,
+                DomainEvent::PaymentRequestCancelled {
+                    payment_request_id: id,
+                    user_id: request.payer.user_id,
+                    requester: request.requester.address.clone(),
+                    amount: request.amount.display(),
+                },
+            ),
+            PaymentRequestAction::Approve => {
+                return Err(AppError::Internal(anyhow::anyhow!("Approvals go through pay")));
+            }
+        };
+
+        sqlx::query!(
+            "UPDATE payment_requests SET status = $2, resolved_at = NOW() WHERE id = $1",
+            id,
+            status as _,
+        )
+        .execute(&mut *tx)
+        .await?;
+        record_event(&mut *tx, &event).await?;
+
+        let record = Self::fetch(&mut *tx, id).await?.ok_or_else(not_found)?;
+        tx.commit().await?;
+        Ok(record)
+    }
+
+    /// Expire pending requests past their expiry; returns how many
+    #[instrument(skip(self))]
+    pub async fn expire(&self) -> Result<u64> {
+        let result = sqlx::query!(
+            r#"
+            UPDATE payment_requests SET status = 'expired', resolved_at = NOW()
+            WHERE status = 'pending' AND expires_at <= NOW()
+            "#
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    /// Lock a request for the rest of the database transaction
+    async fn lock(conn: &mut PgConnection, id: Uuid) -> Result<PaymentRequestRecord> {
+        sqlx::query!("SELECT id FROM payment_requests WHERE id = $1 FOR UPDATE", id)
+            .fetch_optional(&mut *conn)
+            .await?
+            .ok_or_else(not_found)?;
+
+        Self::fetch(conn, id).await?.ok_or_else(not_found)
+    }
+
+    async fn fetch(conn: &mut PgConnection, id: Uuid) -> Result<Option<PaymentRequestRecord>> {
+        let row = sqlx::query!(
+            r#"
+            SELECT pr.id, pr.requester_id, requester.lightning_username AS requester_username,
+                   pr.payer_id, payer.lightning_username AS payer_username,
+                   pr.amount_sats, pr.amount_kes, pr.memo, pr.status as "status: PaymentRequestStatus",
+                   pr.expires_at, pr.paid_amount_sats, pr.debit_transaction_id, pr.credit_transaction_id,
+                   pr.created_at, pr.resolved_at
+            FROM payment_requests pr
+            JOIN users requester ON requester.id = pr.requester_id
+            JOIN users payer ON payer.id = pr.payer_id
+            WHERE pr.id = $1
+            "#,
+            id,
+        )
+        .fetch_optional(conn)
+        .await?;
+        let Some(r) = row else {
+            return Ok(None);
+        };
+
+        Ok(Some(PaymentRequestRecord {
+            id: r.id,
+            requester: Party {
+                user_id: UserId(r.requester_id),
+                address: LightningAddress::new(&r.requester_username, transfers::LIGHTNING_ADDRESS_DOMAIN).0,
+            },
+            payer: Party {
+                user_id: UserId(r.payer_id),
+                address: LightningAddress::new(&r.payer_username, transfers::LIGHTNING_ADDRESS_DOMAIN).0,
+            },
+            amount: RequestedAmount::from_columns(r.amount_sats, r.amount_kes)?,
+            memo: r.memo,
+            status: r.status,
+            expires_at: r.expires_at,
+            paid_amount_sats: r.paid_amount_sats,
+            debit_transaction_id: r.debit_transaction_id,
+            credit_transaction_id: r.credit_transaction_id,
+            created_at: r.created_at,
+            resolved_at: r.resolved_at,
+        }))
+    }
+}
+
+/// Sending, answering and listing payment requests
+pub struct PaymentRequestService {
+    repository: Arc<PaymentRequestRepository>,
+    limits_service: Arc<LimitsService>,
+}
+
+impl PaymentRequestService {
+    pub fn new(repository: Arc<PaymentRequestRepository>, limits_service: Arc<LimitsService>) -> Self {
+        Self {
+            repository,
+            limits_service,
+        }
+    }
+
+    #[instrument(skip(self, request))]
+    pub async fn create(&self, user_id: UserId, request: CreatePaymentRequestRequest) -> Result<PaymentRequestResponse> {
+        request.validate().map_err(|e| AppError::Validation {
+            message: format!("Invalid payment request: {}", e),
+        })?;
+
+        let payer = RecipientHandle::parse(&request.to)?;
+        let amount = RequestedAmount::new(request.amount, request.currency)?;
+        let expires_at = Utc::now() + Duration::hours(request.expires_in_hours.unwrap_or(DEFAULT_EXPIRY_HOURS));
+
+        let record = self
+            .repository
+            .create(user_id, &payer, amount, request.memo.as_deref(), expires_at)
+            .await?;
+
+        info!("User {} requested {} from {}", user_id, amount.display(), record.payer.user_id);
+        Ok(PaymentRequestResponse::for_user(record, user_id))
+    }
+
+    #[instrument(skip(self))]
+    pub async fn get(&self, user_id: UserId, id: Uuid) -> Result<PaymentRequestResponse> {
+        let record = self.find(user_id, id).await?;
+        Ok(PaymentRequestResponse::for_user(record, user_id))
+    }
+
+    #[instrument(skip(self))]
+    pub async fn list(&self, user_id: UserId, params: ListPaymentRequestsParams) -> Result<Vec<PaymentRequestResponse>> {
+        let records = self.repository.list(user_id, params.direction, params.status).await?;
+        Ok(records
+            .into_iter()
+            .map(|record| PaymentRequestResponse::for_user(record, user_id))
+            .collect())
+    }
+
+    /// Pay a request addressed to the user
+    #[instrument(skip(self))]
+    pub async fn approve(&self, user_id: UserId, id: Uuid) -> Result<PaymentRequestResponse> {
+        let request = self.find(user_id, id).await?;
+        request.check(user_id, PaymentRequestAction::Approve, Utc::now())?;
+
+        let rate = self.limits_service.current_rate().await?;
+        let amount_sats = match request.amount {
+            RequestedAmount::Sats(sats) => sats,
+            RequestedAmount::Kes(kes) => rate.kes_to_sats(&kes, Side::Charge, Rounding::HouseFavourable)?,
+        };
+
+        // Limits are checked inside the transfer, under the payer's limits lock
+        let record = self.repository.pay(id, user_id, amount_sats, rate).await?;
+        info!("User {} paid payment request {} ({} sats)", user_id, id, amount_sats.as_i64());
+        Ok(PaymentRequestResponse::for_user(record, user_id))
+    }
+
+    /// Refuse a request addressed to the user
+    #[instrument(skip(self))]
+    pub async fn decline(&self, user_id: UserId, id: Uuid) -> Result<PaymentRequestResponse> {
+        let record = self.repository.close(id, user_id, PaymentRequestAction::Decline).await?;
+        Ok(PaymentRequestResponse::for_user(record, user_id))
+    }
+
+    /// Withdraw a request the user sent
+    #[instrument(skip(self))]
+    pub async fn cancel(&self, user_id: UserId, id: Uuid) -> Result<PaymentRequestResponse> {
+        let record = self.repository.close(id, user_id, PaymentRequestAction::Cancel).await?;
+        Ok(PaymentRequestResponse::for_user(record, user_id))
+    }
+
+    /// Mark unanswered requests as expired
+    pub async fn expire(&self) -> Result<u64> {
+        self.repository.expire().await
+    }
+
+    async fn find(&self, user_id: UserId, id: Uuid) -> Result<PaymentRequestRecord> {
+        self.repository
+            .get(id)
+            .await?
+            .filter(|record| record.involves(user_id))
+            .ok_or_else(not_found)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn party() -> Party {
+        Party {
+            user_id: UserId::new(),
+            address: "someone@pesa.co.ke".to_string(),
+        }
+    }
+
+    fn pending(now: DateTime<Utc>) -> PaymentRequestRecord {
+        PaymentRequestRecord {
+            id: Uuid::new_v4(),
+            requester: party(),
+            payer: party(),
+            amount: RequestedAmount::Kes(KesAmount::new(Decimal::from(500))),
+            memo: Some("Lunch".to_string()),
+            status: PaymentRequestStatus::Pending,
+            expires_at: now + Duration::hours(1),
+            paid_amount_sats: None,
+            debit_transaction_id: None,
+            credit_transaction_id: None,
+            created_at: now,
+            resolved_at: None,
+        }
+    }
+
+    #[test]
+    fn test_requested_amount() {
+        let kes = RequestedAmount::new(Decimal::new(50050, 2), PaymentRequestCurrency::Kes).unwrap();
+        assert_eq!(kes.display(), "KES 500.50");
+        let sats = RequestedAmount::new(Decimal::from(5000), PaymentRequestCurrency::Sats).unwrap();
+        assert_eq!(sats, RequestedAmount::Sats(SatAmount::new(5000)));
+        assert_eq!(sats.display(), "5000 sats");
+
+        assert!(RequestedAmount::new(Decimal::new(15, 1), PaymentRequestCurrency::Sats).is_err());
+        assert!(RequestedAmount::new(Decimal::ZERO, PaymentRequestCurrency::Kes).is_err());
+    }
+
+    #[test]
+    fn test_who_can_answer() {
+        let now = Utc::now();
+        let request = pending(now);
+        let (requester, payer) = (request.requester.user_id, request.payer.user_id);
+
+        assert!(request.check(payer, PaymentRequestAction::Approve, now).is_ok());
+        assert!(request.check(payer, PaymentRequestAction::Decline, now).is_ok());
+        assert!(request.check(requester, PaymentRequestAction::Cancel, now).is_ok());
+
+        assert!(matches!(
+            request.check(requester, PaymentRequestAction::Approve, now),
+            Err(AppError::Validation { .. })
+        ));
+        assert!(matches!(
+            request.check(payer, PaymentRequestAction::Cancel, now),
+            Err(AppError::Validation { .. })
+        ));
+        // Other users can't tell the request exists
+        assert!(matches!(
+            request.check(UserId::new(), PaymentRequestAction::Approve, now),
+            Err(AppError::Payment { .. })
+        ));
+    }
+
+    #[test]
+    fn test_only_open_requests_can_be_answered() {
+        let now = Utc::now();
+        let request = pending(now);
+        let payer = request.payer.user_id;
+
+        let paid = PaymentRequestRecord {
+            status: PaymentRequestStatus::Paid,
+            ..request.clone()
+        };
+        assert!(matches!(
+            paid.check(payer, PaymentRequestAction::Approve, now),
+            Err(AppError::Conflict { .. })
+        ));
+        assert!(matches!(
+            request.check(payer, PaymentRequestAction::Approve, now + Duration::hours(2)),
+            Err(AppError::Conflict { .. })
+        ));
+
+        let view = PaymentRequestResponse::for_user(request.clone(), payer);
+        assert_eq!(view.direction, PaymentRequestDirection::Incoming);
+        assert_eq!(view.currency, PaymentRequestCurrency::Kes);
+        let view = PaymentRequestResponse::for_user(request.clone(), request.requester.user_id);
+        assert_eq!(view.direction, PaymentRequestDirection::Outgoing);
+    }
+}
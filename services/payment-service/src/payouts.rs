@@ -0,0 +1,1103 @@
+/// Bulk payouts from CSV or JSON batches
+///
+/// Employers and NGOs pay many recipients in one batch: rows of recipient (a
+/// Kenyan phone number or a Lightning address), amount and reference, uploaded as
+/// JSON or CSV. The whole batch is checked before anything is paid: every row must
+/// be valid and the balance and limits must cover the total. A background worker
+/// then pays the rows in order, throttled per provider so no network is sent more
+/// payouts a minute than it accepts. Each row keeps its own status, and cancelling
+/// a batch drops the rows that haven't started. As with scheduled payments, a row
+/// cut short by a crash is failed rather than retried, so nobody is paid twice.
+
+use crate::airtime::{kenyan_phone_number, KenyanCarrier};
+use crate::domain::PayInvoiceRequest;
+use crate::fees::FeeService;
+use crate::limits::LimitsService;
+use crate::lnurl::{LnurlClient, ParsedLightningAddress};
+use crate::mobile_money::{MobileMoneyService, MobileMoneyWithdrawalRequest};
+use crate::service::PaymentService;
+use chrono::{DateTime, Utc};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use shared_errors::{AppError, Result};
+use shared_types::conversion::{Rounding, Side};
+use shared_types::*;
+use sqlx::PgPool;
+use std::sync::Arc;
+use tracing::{info, instrument, warn};
+use uuid::Uuid;
+use validator::Validate;
+
+/// Most rows in one batch
+const MAX_BATCH_ROWS: usize = 1000;
+const MAX_REFERENCE_LENGTH: usize = 100;
+/// Smallest mobile money payout (the withdrawal minimum)
+const MIN_MOBILE_MONEY_SATS: i64 = 1000;
+/// How long a row may take before it counts as interrupted
+const ROW_LEASE_MINUTES: i32 = 5;
+/// Row errors listed when a batch is rejected
+const MAX_REPORTED_ERRORS: usize = 20;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[sqlx(type_name = "payout_batch_status", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum PayoutBatchStatus {
+    Processing,
+    Completed,
+    Cancelled,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[sqlx(type_name = "payout_recipient_type", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum PayoutRecipientType {
+    MobileMoney,
+    LightningAddress,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[sqlx(type_name = "payout_item_status", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum PayoutItemStatus {
+    Pending,
+    Processing,
+    /// Payment made; the transaction has the final outcome
+    Sent,
+    Failed,
+    Cancelled,
+}
+
+/// Provider rows are paid through; each is throttled separately
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PayoutLane {
+    MobileMoney(MobileMoneyNetwork),
+    Lightning,
+}
+
+impl PayoutLane {
+    const ALL: [PayoutLane; 3] = [
+        PayoutLane::MobileMoney(MobileMoneyNetwork::Mpesa),
+        PayoutLane::MobileMoney(MobileMoneyNetwork::AirtelMoney),
+        PayoutLane::Lightning,
+    ];
+
+    /// Payouts started per minute across all batches, within what each provider accepts
+    fn per_minute(&self) -> i64 {
+        match self {
+            PayoutLane::MobileMoney(MobileMoneyNetwork::Mpesa) => 60,
+            PayoutLane::MobileMoney(MobileMoneyNetwork::AirtelMoney) => 30,
+            PayoutLane::Lightning => 120,
+        }
+    }
+
+    /// Name of the provider's advisory lock
+    fn lock_key(&self) -> &'static str {
+        match self {
+            PayoutLane::MobileMoney(MobileMoneyNetwork::Mpesa) => "payout_lane:mpesa",
+            PayoutLane::MobileMoney(MobileMoneyNetwork::AirtelMoney) => "payout_lane:airtel_money",
+            PayoutLane::Lightning => "payout_lane:lightning",
+        }
+    }
+
+    fn fields(&self) -> (PayoutRecipientType, Option<MobileMoneyNetwork>) {
+        match self {
+            PayoutLane::MobileMoney(network) => (PayoutRecipientType::MobileMoney, Some(*network)),
+            PayoutLane::Lightning => (PayoutRecipientType::LightningAddress, None),
+        }
+    }
+}
+
+/// Where a row is paid
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PayoutRecipient {
+    MobileMoney {
+        network: MobileMoneyNetwork,
+        phone_number: PhoneNumber,
+    },
+    LightningAddress(String),
+}
+
+impl PayoutRecipient {
+    /// Lightning address if it has an `@`, otherwise a Kenyan phone number paid on its own network
+    pub fn parse(recipient: &str) -> Result<Self> {
+        if recipient.contains('@') {
+            let address = ParsedLightningAddress::parse(recipient)?;
+            return Ok(PayoutRecipient::LightningAddress(format!("{}@{}", address.name, address.domain)));
+        }
+
+        let phone_number = kenyan_phone_number(recipient)?;
+        let network = match KenyanCarrier::from_phone(&phone_number) {
+            Some(KenyanCarrier::Safaricom) => MobileMoneyNetwork::Mpesa,
+            Some(KenyanCarrier::Airtel) => MobileMoneyNetwork::AirtelMoney,
+            _ => {
+                return Err(AppError::Validation {
+                    message: format!("{} is not on M-Pesa or Airtel Money", phone_number.0),
+                })
+            }
+        };
+        Ok(PayoutRecipient::MobileMoney { network, phone_number })
+    }
+
+    pub fn lane(&self) -> PayoutLane {
+        match self {
+            PayoutRecipient::MobileMoney { network, .. } => PayoutLane::MobileMoney(*network),
+            PayoutRecipient::LightningAddress(_) => PayoutLane::Lightning,
+        }
+    }
+
+    fn recipient(&self) -> &str {
+        match self {
+            PayoutRecipient::MobileMoney { phone_number, .. } => &phone_number.0,
+            PayoutRecipient::LightningAddress(address) => address,
+        }
+    }
+}
+
+/// One row as uploaded
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct PayoutRow {
+    /// Kenyan phone number or Lightning address
+    pub recipient: String,
+    pub amount_kes: Option<Decimal>,
+    pub amount_sats: Option<i64>,
+    pub reference: Option<String>,
+}
+
+/// Validated row, priced in sats
+///
+/// KES rows are paid in KES; their sats only size the batch's balance and limit checks.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PayoutInstruction {
+    pub row_number: i32,
+    pub recipient: PayoutRecipient,
+    pub amount_sats: SatAmount,
+    pub amount_kes: Option<KesAmount>,
+    pub reference: Option<String>,
+}
+
+impl PayoutInstruction {
+    /// Check a row; KES amounts are converted at `rate`
+    pub fn from_row(
+        row_number: i32,
+        row: PayoutRow,
+        rate: &BtcKesRate,
+        networks: &[MobileMoneyNetwork],
+    ) -> Result<Self> {
+        let recipient = PayoutRecipient::parse(&row.recipient)?;
+        if let PayoutRecipient::MobileMoney { network, .. } = &recipient {
+            if !networks.contains(network) {
+                return Err(AppError::Validation {
+                    message: format!("{} payouts are not available", network.display_name()),
+                });
+            }
+        }
+
+        let (amount_sats, amount_kes) = match (row.amount_sats, row.amount_kes) {
+            (Some(sats), None) if sats > 0 => (SatAmount::new(sats), None),
+            (None, Some(kes)) if kes.fract() != Decimal::ZERO => {
+                return Err(AppError::Validation {
+                    message: format!("KES amounts must be whole shillings: {}", kes),
+                })
+            }
+            (None, Some(kes)) if kes > Decimal::ZERO => {
+                let kes = KesAmount::new(kes);
+                (rate.kes_to_sats(&kes, Side::Charge, Rounding::HouseFavourable)?, Some(kes))
+            }
+            (Some(_), Some(_)) | (None, None) => {
+                return Err(AppError::Validation {
+                    message: "Give exactly one of amount_sats and amount_kes".to_string(),
+                })
+            }
+            _ => {
+                return Err(AppError::Validation {
+                    message: "Amount must be positive".to_string(),
+                })
+            }
+        };
+        if recipient.lane() != PayoutLane::Lightning && amount_sats.as_i64() < MIN_MOBILE_MONEY_SATS {
+            return Err(AppError::Validation {
+                message: format!("Mobile money payouts must be at least {} sats", MIN_MOBILE_MONEY_SATS),
+            });
+        }
+
+        let reference = row.reference.map(|r| r.trim().to_string()).filter(|r| !r.is_empty());
+        if reference.as_ref().is_some_and(|r| r.chars().count() > MAX_REFERENCE_LENGTH) {
+            return Err(AppError::Validation {
+                message: format!("Reference is longer than {} characters", MAX_REFERENCE_LENGTH),
+            });
+        }
+
+        Ok(Self {
+            row_number,
+            recipient,
+            amount_sats,
+            amount_kes,
+            reference,
+        })
+    }
+}
+
+/// Split CSV text into records (quoted fields may hold commas, quotes and newlines)
+fn csv_records(text: &str) -> Vec<Vec<String>> {
+    let mut records = Vec::new();
+    let mut record = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = text.trim_start_matches('\u{feff}').chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match (c, quoted) {
+            ('"', true) if chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            ('"', true) => quoted = false,
+            ('"', false) if field.trim().is_empty() => {
+                field.clear();
+                quoted = true;
+            }
+            (',', false) => record.push(std::mem::take(&mut field)),
+            ('\r', false) => {}
+            ('\n', false) => {
+                record.push(std::mem::take(&mut field));
+                records.push(std::mem::take(&mut record));
+            }
+            _ => field.push(c),
+        }
+    }
+    if !field.is_empty() || !record.is_empty() {
+        record.push(field);
+        records.push(record);
+    }
+
+    records.retain(|record| record.iter().any(|field| !field.trim().is_empty()));
+    records
+}
+
+/// Rows of a CSV upload with a header line; each row parses or says why it doesn't
+pub fn parse_csv(text: &str) -> Result<Vec<Result<PayoutRow>>> {
+    let mut records = csv_records(text).into_iter();
+    let header: Vec<String> = records
+        .next()
+        .ok_or_else(|| AppError::Validation {
+            message: "The CSV is empty".to_string(),
+        })?
+        .iter()
+        .map(|name| name.trim().to_lowercase())
+        .collect();
+    let column = |names: &[&str]| header.iter().position(|name| names.contains(&name.as_str()));
+
+    let recipient = column(&["recipient", "phone_number", "phone", "lightning_address"]).ok_or_else(|| {
+        AppError::Validation {
+            message: "The CSV needs a recipient column".to_string(),
+        }
+    })?;
+    let amount_kes = column(&["amount_kes", "amount"]);
+    let amount_sats = column(&["amount_sats"]);
+    if amount_kes.is_none() && amount_sats.is_none() {
+        return Err(AppError::Validation {
+            message: "The CSV needs an amount_kes or amount_sats column".to_string(),
+        });
+    }
+    let reference = column(&["reference"]);
+
+    Ok(records
+        .map(|record| {
+            let value = |index: Option<usize>| {
+                index
+                    .and_then(|i| record.get(i))
+                    .map(|v| v.trim().to_string())
+                    .filter(|v| !v.is_empty())
+            };
+            let invalid_amount = |v: &str| AppError::Validation {
+                message: format!("Invalid amount: {}", v),
+            };
+
+            Ok(PayoutRow {
+                recipient: value(Some(recipient)).unwrap_or_default(),
+                amount_kes: value(amount_kes)
+                    .map(|v| v.replace(',', "").parse().map_err(|_| invalid_amount(&v)))
+                    .transpose()?,
+                amount_sats: value(amount_sats)
+                    .map(|v| v.replace(',', "").parse().map_err(|_| invalid_amount(&v)))
+                    .transpose()?,
+                reference: value(reference),
+            })
+        })
+        .collect())
+}
+
+/// Message for a rejected batch, listing the rows that are wrong
+fn rejection_message(errors: &[String]) -> String {
+    let mut message = format!("{} rows are invalid, nothing was paid. ", errors.len());
+    message.push_str(&errors[..errors.len().min(MAX_REPORTED_ERRORS)].join("; "));
+    if errors.len() > MAX_REPORTED_ERRORS {
+        message.push_str(&format!("; and {} more", errors.len() - MAX_REPORTED_ERRORS));
+    }
+    message
+}
+
+/// Request to pay a batch; give the rows as JSON or as CSV text
+#[derive(Debug, Deserialize, Validate)]
+pub struct CreatePayoutBatchRequest {
+    #[validate(length(max = 100))]
+    pub name: Option<String>,
+    pub rows: Option<Vec<PayoutRow>>,
+    /// Header line naming the columns: recipient, amount_kes or amount_sats, reference
+    pub csv: Option<String>,
+}
+
+impl CreatePayoutBatchRequest {
+    fn rows(&self) -> Result<Vec<Result<PayoutRow>>> {
+        match (&self.rows, &self.csv) {
+            (Some(rows), None) => Ok(rows.iter().cloned().map(Ok).collect()),
+            (None, Some(csv)) => parse_csv(csv),
+            _ => Err(AppError::Validation {
+                message: "Give exactly one of rows and csv".to_string(),
+            }),
+        }
+    }
+}
+
+/// Rows of a batch by status
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct PayoutCounts {
+    pub total: i64,
+    pub pending: i64,
+    pub processing: i64,
+    pub sent: i64,
+    pub failed: i64,
+    pub cancelled: i64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PayoutBatch {
+    pub id: Uuid,
+    pub name: Option<String>,
+    pub status: PayoutBatchStatus,
+    /// Debited if every row is paid, fees included
+    pub total_sats: SatAmount,
+    pub exchange_rate: Decimal,
+    pub rows: PayoutCounts,
+    pub created_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
+}
+
+/// One row and how it went
+#[derive(Debug, Clone, Serialize)]
+pub struct PayoutItem {
+    pub row_number: i32,
+    pub recipient_type: PayoutRecipientType,
+    pub recipient: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub provider: Option<MobileMoneyNetwork>,
+    pub amount_sats: SatAmount,
+    pub amount_kes: Option<KesAmount>,
+    pub reference: Option<String>,
+    pub status: PayoutItemStatus,
+    pub transaction_id: Option<Uuid>,
+    /// Final outcome of the payment once sent
+    pub transaction_status: Option<TransactionStatus>,
+    pub error: Option<String>,
+    pub started_at: Option<DateTime<Utc>>,
+    pub finished_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PayoutBatchDetails {
+    #[serde(flatten)]
+    pub batch: PayoutBatch,
+    pub items: Vec<PayoutItem>,
+}
+
+/// Row claimed by the worker
+#[derive(Debug, Clone)]
+pub struct ClaimedPayout {
+    pub id: Uuid,
+    pub batch_id: Uuid,
+    pub user_id: UserId,
+    pub row_number: i32,
+    pub recipient: String,
+    pub provider: Option<MobileMoneyNetwork>,
+    pub amount_sats: SatAmount,
+    /// Set for rows uploaded in KES, which are paid in KES at the rate when they're paid
+    pub amount_kes: Option<KesAmount>,
+    pub reference: Option<String>,
+}
+
+/// Database access for payout batches
+pub struct PayoutRepository {
+    pool: PgPool,
+}
+
+impl PayoutRepository {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+
+    #[instrument(skip(self, instructions))]
+    pub async fn create(
+        &self,
+        user_id: UserId,
+        name: Option<&str>,
+        total_sats: SatAmount,
+        rate: BtcKesRate,
+        instructions: &[PayoutInstruction],
+    ) -> Result<Uuid> {
+        let mut tx = self.pool.begin().await?;
+
+        let batch_id = sqlx::query_scalar!(
+            r#"
+            INSERT INTO payout_batches (user_id, name, total_sats, exchange_rate)
+            VALUES ($1, $2, $3, $4)
+            RETURNING id
+            "#,
+            user_id.0,
+            name,
+            total_sats.as_i64(),
+            rate.kes_per_btc(),
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+
+        for instruction in instructions {
+            let (recipient_type, provider) = instruction.recipient.lane().fields();
+            sqlx::query!(
+                r#"
+                INSERT INTO payout_items
+                    (batch_id, row_number, recipient_type, recipient, provider, amount_sats, amount_kes, reference)
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+                "#,
+                batch_id,
+                instruction.row_number,
+                recipient_type as _,
+                instruction.recipient.recipient(),
+                provider as _,
+                instruction.amount_sats.as_i64(),
+                instruction.amount_kes.map(|kes| kes.as_decimal()),
+                instruction.reference,
+            )
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        tx.commit().await?;
+        Ok(batch_id)
+    }
+
+    #[instrument(skip(self))]
+    pub async fn get(&self, user_id: UserId, id: Uuid) -> Result<Option<PayoutBatch>> {
+        let row = sqlx::query!(
+            r#"
+            SELECT b.id, b.name, b.status as "status: PayoutBatchStatus", b.total_sats, b.exchange_rate,
+                   b.created_at, b.finished_at,
+                   COUNT(i.id) AS "total!",
+                   COUNT(i.id) FILTER (WHERE i.status = 'pending') AS "pending!",
+                   COUNT(i.id) FILTER (WHERE i.status = 'processing') AS "processing!",
+                   COUNT(i.id) FILTER (WHERE i.status = 'sent') AS "sent!",
+                   COUNT(i.id) FILTER (WHERE i.status = 'failed') AS "failed!",
+                   COUNT(i.id) FILTER (WHERE i.status = 'cancelled') AS "cancelled!"
+            FROM payout_batches b
+            LEFT JOIN payout_items i ON i.batch_id = b.id
+            WHERE b.id = $1 AND b.user_id = $2
+            GROUP BY b.id
+            "#,
+            id,
+            user_id.0,
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.map(|r| PayoutBatch {
+            id: r.id,
+            name: r.name,
+            status: r.status,
+            total_sats: SatAmount::new(r.total_sats),
+            exchange_rate: r.exchange_rate,
+            rows: PayoutCounts {
+                total: r.total,
+                pending: r.pending,
+                processing: r.processing,
+                sent: r.sent,
+                failed: r.failed,
+                cancelled: r.cancelled,
+            },
+            created_at: r.created_at,
+            finished_at: r.finished_at,
+        }))
+    }
+
+    /// Newest first
+    #[instrument(skip(self))]
+    pub async fn list(&self, user_id: UserId) -> Result<Vec<PayoutBatch>> {
+        let rows = sqlx::query!(
+            r#"
+            SELECT b.id, b.name, b.status as "status: PayoutBatchStatus", b.total_sats, b.exchange_rate,
+                   b.created_at, b.finished_at,
+                   COUNT(i.id) AS "total!",
+                   COUNT(i.id) FILTER (WHERE i.status = 'pending') AS "pending!",
+                   COUNT(i.id) FILTER (WHERE i.status = 'processing') AS "processing!",
+                   COUNT(i.id) FILTER (WHERE i.status = 'sent') AS "sent!",
+                   COUNT(i.id) FILTER (WHERE i.status = 'failed') AS "failed!",
+                   COUNT(i.id) FILTER (WHERE i.status = 'cancelled') AS "cancelled!"
+            FROM payout_batches b
+            LEFT JOIN payout_items i ON i.batch_id = b.id
+            WHERE b.user_id = $1
+            GROUP BY b.id
+            ORDER BY b.created_at DESC
+            LIMIT 100
+            "#,
+            user_id.0,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|r| PayoutBatch {
+                id: r.id,
+                name: r.name,
+                status: r.status,
+                total_sats: SatAmount::new(r.total_sats),
+                exchange_rate: r.exchange_rate,
+                rows: PayoutCounts {
+                    total: r.total,
+                    pending: r.pending,
+                    processing: r.processing,
+                    sent: r.sent,
+                    failed: r.failed,
+                    cancelled: r.cancelled,
+                },
+                created_at: r.created_at,
+                finished_at: r.finished_at,
+            })
+            .collect())
+    }
+
+    #[instrument(skip(self))]
+    pub async fn items(&self, batch_id: Uuid) -> Result<Vec<PayoutItem>> {
+        let rows = sqlx::query!(
+            r#"
+            SELECT i.row_number, i.recipient_type as "recipient_type: PayoutRecipientType", i.recipient,
+                   i.provider as "provider: MobileMoneyNetwork", i.amount_sats, i.amount_kes, i.reference,
+                   i.status as "status: PayoutItemStatus", i.transaction_id,
+                   t.status as "transaction_status?: TransactionStatus",
+                   i.error, i.started_at, i.finished_at
+            FROM payout_items i
+            LEFT JOIN transactions t ON t.id = i.transaction_id
+            WHERE i.batch_id = $1
+            ORDER BY i.row_number
+            "#,
+            batch_id,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|r| PayoutItem {
+                row_number: r.row_number,
+                recipient_type: r.recipient_type,
+                recipient: r.recipient,
+                provider: r.provider,
+                amount_sats: SatAmount::new(r.amount_sats),
+                amount_kes: r.amount_kes.map(KesAmount::new),
+                reference: r.reference,
+                status: r.status,
+                transaction_id: r.transaction_id,
+                transaction_status: r.transaction_status,
+                error: r.error,
+                started_at: r.started_at,
+                finished_at: r.finished_at,
+            })
+            .collect())
+    }
+
+    /// Stop a batch that is being paid and drop its rows that haven't started
+    #[instrument(skip(self))]
+    pub async fn cancel(&self, user_id: UserId, id: Uuid) -> Result<bool> {
+        let mut tx = self.pool.begin().await?;
+
+        let cancelled = sqlx::query!(
+            r#"
+            UPDATE payout_batches SET status = 'cancelled', finished_at = NOW()
+            WHERE id = $1 AND user_id = $2 AND status = 'processing'
+            "#,
+            id,
+            user_id.0,
+        )
+        .execute(&mut *tx)
+        .await?;
+        if cancelled.rows_affected() != 1 {
+            return Ok(false);
+        }
+
+        sqlx::query!(
+            r#"
+            UPDATE payout_items SET status = 'cancelled', finished_at = NOW()
+            WHERE batch_id = $1 AND status = 'pending'
+            "#,
+            id,
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+        Ok(true)
+    }
+
+    #[instrument(skip(self))]
+    pub async fn balance_sats(&self, user_id: UserId) -> Result<i64> {
+        let row = sqlx::query!("SELECT balance_sats FROM wallets WHERE user_id = $1", user_id.0)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        Ok(row.map(|r| r.balance_sats).unwrap_or(0))
+    }
+
+    /// Claim the next row paid through a provider, unless it has had its payouts for this minute
+    #[instrument(skip(self))]
+    pub async fn claim_next(&self, lane: PayoutLane) -> Result<Option<ClaimedPayout>> {
+        let (recipient_type, provider) = lane.fields();
+        let mut tx = self.pool.begin().await?;
+
+        // One worker at a time counts and claims a provider's rows
+        sqlx::query!("SELECT pg_advisory_xact_lock(hashtext($1))", lane.lock_key())
+        .execute(&mut *tx)
+        .await?;
+
+        let started = sqlx::query_scalar!(
+            r#"
+            SELECT COUNT(*) AS "started!" FROM payout_items
+            WHERE started_at > NOW() - INTERVAL '1 minute'
+              AND recipient_type = $1 AND provider IS NOT DISTINCT FROM $2
+            "#,
+            recipient_type as _,
+            provider as _,
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+        if started >= lane.per_minute() {
+            return Ok(None);
+        }
+
+        let row = sqlx::query!(
+            r#"
+            UPDATE payout_items i SET status = 'processing', started_at = NOW()
+            FROM payout_batches b
+            WHERE b.id = i.batch_id AND i.id = (
+                SELECT i.id FROM payout_items i
+                JOIN payout_batches b ON b.id = i.batch_id
+                WHERE b.status = 'processing' AND i.status = 'pending'
+                  AND i.recipient_type = $1 AND i.provider IS NOT DISTINCT FROM $2
+                ORDER BY b.created_at, i.row_number
+                LIMIT 1
+                FOR UPDATE OF i SKIP LOCKED
+            )
+            RETURNING i.id, i.batch_id, b.user_id, i.row_number, i.recipient,
+                      i.provider as "provider: MobileMoneyNetwork", i.amount_sats, i.amount_kes, i.reference
+            "#,
+            recipient_type as _,
+            provider as _,
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+        Ok(row.map(|r| ClaimedPayout {
+            id: r.id,
+            batch_id: r.batch_id,
+            user_id: UserId(r.user_id),
+            row_number: r.row_number,
+            recipient: r.recipient,
+            provider: r.provider,
+            amount_sats: SatAmount::new(r.amount_sats),
+            amount_kes: r.amount_kes.map(KesAmount::new),
+            reference: r.reference,
+        }))
+    }
+
+    #[instrument(skip(self))]
+    pub async fn record_sent(&self, id: Uuid, transaction_id: Option<Uuid>) -> Result<()> {
+        sqlx::query!(
+            r#"
+            UPDATE payout_items SET status = 'sent', transaction_id = $2, finished_at = NOW()
+            WHERE id = $1 AND status = 'processing'
+            "#,
+            id,
+            transaction_id,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    #[instrument(skip(self))]
+    pub async fn record_failure(&self, id: Uuid, error: &str) -> Result<()> {
+        sqlx::query!(
+            r#"
+            UPDATE payout_items SET status = 'failed', error = $2, finished_at = NOW()
+            WHERE id = $1 AND status = 'processing'
+            "#,
+            id,
+            error,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Fail rows a stopped worker left unfinished; they may have been paid, so they aren't retried
+    #[instrument(skip(self))]
+    pub async fn fail_interrupted(&self) -> Result<u64> {
+        let result = sqlx::query!(
+            r#"
+            UPDATE payout_items
+            SET status = 'failed', finished_at = NOW(),
+                error = 'Stopped before the payment was confirmed. Check your transactions before paying again.'
+            WHERE status = 'processing' AND started_at < NOW() - make_interval(mins => $1)
+            "#,
+            ROW_LEASE_MINUTES,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    /// Complete batches with no rows left to pay
+    #[instrument(skip(self))]
+    pub async fn complete_finished(&self) -> Result<u64> {
+        let result = sqlx::query!(
+            r#"
+            UPDATE payout_batches b SET status = 'completed', finished_at = NOW()
+            WHERE b.status = 'processing' AND NOT EXISTS (
+                SELECT 1 FROM payout_items i
+                WHERE i.batch_id = b.id AND i.status IN ('pending', 'processing')
+            )
+            "#,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+}
+
+/// Creating, paying and cancelling payout batches
+pub struct PayoutService {
+    repository: Arc<PayoutRepository>,
+    payment_service: Arc<PaymentService>,
+    mobile_money_service: Arc<MobileMoneyService>,
+    fee_service: Arc<FeeService>,
+    limits_service: Arc<LimitsService>,
+    lnurl_client: Arc<LnurlClient>,
+}
+
+impl PayoutService {
+    pub fn new(
+        repository: Arc<PayoutRepository>,
+        payment_service: Arc<PaymentService>,
+        mobile_money_service: Arc<MobileMoneyService>,
+        fee_service: Arc<FeeService>,
+        limits_service: Arc<LimitsService>,
+        lnurl_client: Arc<LnurlClient>,
+    ) -> Self {
+        Self {
+            repository,
+            payment_service,
+            mobile_money_service,
+            fee_service,
+            limits_service,
+            lnurl_client,
+        }
+    }
+
+    /// Check the whole batch, then queue its rows for payment
+    #[instrument(skip(self, request))]
+    pub async fn create(&self, user_id: UserId, request: CreatePayoutBatchRequest) -> Result<PayoutBatchDetails> {
+        request.validate().map_err(|e| AppError::Validation {
+            message: format!("Invalid payout batch: {}", e),
+        })?;
+        let rows = request.rows()?;
+        if rows.is_empty() || rows.len() > MAX_BATCH_ROWS {
+            return Err(AppError::Validation {
+                message: format!("A batch has between 1 and {} rows", MAX_BATCH_ROWS),
+            });
+        }
+
+        let rate = self.limits_service.current_rate().await?;
+        let networks: Vec<MobileMoneyNetwork> = self
+            .mobile_money_service
+            .options()
+            .into_iter()
+            .map(|option| option.provider)
+            .collect();
+
+        let mut instructions = Vec::with_capacity(rows.len());
+        let mut errors = Vec::new();
+        for (index, row) in rows.into_iter().enumerate() {
+            let row_number = index as i32 + 1;
+            match row.and_then(|row| PayoutInstruction::from_row(row_number, row, &rate, &networks)) {
+                Ok(instruction) => instructions.push(instruction),
+                Err(e) => errors.push(format!("Row {}: {}", row_number, e.user_message())),
+            }
+        }
+        if !errors.is_empty() {
+            return Err(AppError::Validation {
+                message: rejection_message(&errors),
+            });
+        }
+
+        // Mobile money rows pay the withdrawal fee, as they would one by one
+        let mut total_sats = 0;
+        for instruction in &instructions {
+            total_sats += instruction.amount_sats.as_i64();
+            if instruction.recipient.lane() != PayoutLane::Lightning {
+                let payout = rate.sats_to_kes(instruction.amount_sats, Side::Payout, Rounding::HouseFavourable)?;
+                let fees = self
+                    .fee_service
+                    .quote(&TransactionType::WithdrawalMpesa, payout.as_decimal().trunc())
+                    .await?;
+                total_sats += rate
+                    .kes_to_sats(&fees.total_kes, Side::Charge, Rounding::HouseFavourable)?
+                    .as_i64();
+            }
+        }
+        let total_sats = SatAmount::new(total_sats);
+
+        let balance = self.repository.balance_sats(user_id).await?;
+        if balance < total_sats.as_i64() {
+            return Err(AppError::Payment {
+                message: format!(
+                    "Insufficient balance: {} sats needed for the batch, {} sats available",
+                    total_sats.as_i64(),
+                    balance
+                ),
+            });
+        }
+        // Early rejection only: each row is checked again under the limits lock when it's paid
+        self.limits_service.check_sats(user_id, total_sats).await?;
+
+        let id = self
+            .repository
+            .create(user_id, request.name.as_deref(), total_sats, rate, &instructions)
+            .await?;
+
+        info!("User {} queued payout batch {} of {} rows", user_id, id, instructions.len());
+        self.get(user_id, id).await
+    }
+
+    #[instrument(skip(self))]
+    pub async fn get(&self, user_id: UserId, id: Uuid) -> Result<PayoutBatchDetails> {
+        let batch = self.find(user_id, id).await?;
+        let items = self.repository.items(id).await?;
+        Ok(PayoutBatchDetails { batch, items })
+    }
+
+    #[instrument(skip(self))]
+    pub async fn list(&self, user_id: UserId) -> Result<Vec<PayoutBatch>> {
+        self.repository.list(user_id).await
+    }
+
+    /// Stop paying a batch; rows already sent stay sent
+    #[instrument(skip(self))]
+    pub async fn cancel(&self, user_id: UserId, id: Uuid) -> Result<PayoutBatch> {
+        self.find(user_id, id).await?;
+        if !self.repository.cancel(user_id, id).await? {
+            return Err(AppError::Conflict {
+                message: "Only batches still being paid can be cancelled".to_string(),
+            });
+        }
+
+        info!("User {} cancelled payout batch {}", user_id, id);
+        self.find(user_id, id).await
+    }
+
+    /// Pay queued rows, taking turns between providers; returns how many were attempted
+    #[instrument(skip(self))]
+    pub async fn run_due(&self) -> Result<usize> {
+        let interrupted = self.repository.fail_interrupted().await?;
+        if interrupted > 0 {
+            warn!("Failed {} payout rows left unfinished by a stopped worker", interrupted);
+        }
+
+        let mut attempted = 0;
+        loop {
+            let mut claimed = false;
+            for lane in PayoutLane::ALL {
+                if let Some(payout) = self.repository.claim_next(lane).await? {
+                    self.pay(&payout).await?;
+                    attempted += 1;
+                    claimed = true;
+                }
+            }
+            if !claimed {
+                break;
+            }
+        }
+
+        self.repository.complete_finished().await?;
+        Ok(attempted)
+    }
+
+    /// Make one row's payment and record how it went
+    async fn pay(&self, payout: &ClaimedPayout) -> Result<()> {
+        let result = match (payout.provider, payout.amount_kes) {
+            (Some(provider), Some(amount_kes)) => self
+                .mobile_money_service
+                .withdraw_kes(payout.user_id, provider, &payout.recipient, amount_kes)
+                .await
+                .map(|response| Some(response.transaction_id)),
+            (Some(provider), None) => self
+                .mobile_money_service
+                .withdraw(
+                    payout.user_id,
+                    MobileMoneyWithdrawalRequest {
+                        provider,
+                        amount_sats: payout.amount_sats.as_i64(),
+                        recipient_phone: Some(payout.recipient.clone()),
+                    },
+                )
+                .await
+                .map(|response| Some(response.transaction_id)),
+            (None, _) => self.pay_lightning_address(payout).await,
+        };
+
+        match result {
+            Ok(transaction_id) => {
+                info!("Payout batch {} row {} sent", payout.batch_id, payout.row_number);
+                self.repository.record_sent(payout.id, transaction_id).await
+            }
+            Err(e) => {
+                warn!("Payout batch {} row {} failed: {}", payout.batch_id, payout.row_number, e);
+                self.repository.record_failure(payout.id, &e.user_message()).await
+            }
+        }
+    }
+
+    async fn pay_lightning_address(&self, payout: &ClaimedPayout) -> Result<Option<Uuid>> {
+        let amount_sats = match &payout.amount_kes {
+            Some(amount_kes) => self
+                .limits_service
+                .current_rate()
+                .await?
+                .kes_to_sats(amount_kes, Side::Charge, Rounding::HouseFavourable)?,
+            None => payout.amount_sats,
+        };
+        self.limits_service.check_sats(payout.user_id, amount_sats).await?;
+        let invoice = self
+            .lnurl_client
+            .fetch_invoice(&payout.recipient, amount_sats, payout.reference.as_deref())
+            .await?;
+        let response = self
+            .limits_service
+            .within_sats(payout.user_id, amount_sats, || {
+                self.payment_service.pay_lightning_invoice(
+                    payout.user_id,
+                    PayInvoiceRequest {
+                        bolt11_invoice: invoice,
+                        max_fee_sats: None,
+                    },
+                )
+            })
+            .await?;
+
+        if response.status == TransactionStatus::Failed {
+            return Err(AppError::Lightning {
+                message: response
+                    .failure_reason
+                    .unwrap_or_else(|| "Lightning payment failed".to_string()),
+            });
+        }
+        Ok(response.transaction_id.parse().ok())
+    }
+
+    async fn find(&self, user_id: UserId, id: Uuid) -> Result<PayoutBatch> {
+        self.repository
+            .get(user_id, id)
+            .await?
+            .ok_or_else(|| AppError::Payment {
+                message: "Payout batch not found".to_string(),
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_csv() {
+        let csv = "\u{feff}Recipient,Amount_KES,Reference\r\n\
+                   0712 345 678,\"1,500\",\"March wages, week 1\"\r\n\
+                   \r\n\
+                   alice@getalby.com,250,\"Said \"\"thanks\"\"\"\n\
+                   +254733123456,abc,\n";
+        let rows = parse_csv(csv).unwrap();
+        assert_eq!(rows.len(), 3);
+        assert_eq!(
+            rows[0].as_ref().unwrap(),
+            &PayoutRow {
+                recipient: "0712 345 678".to_string(),
+                amount_kes: Some(Decimal::from(1500)),
+                amount_sats: None,
+                reference: Some("March wages, week 1".to_string()),
+            }
+        );
+        assert_eq!(rows[1].as_ref().unwrap().reference.as_deref(), Some("Said \"thanks\""));
+        assert!(rows[2].is_err());
+
+        assert!(parse_csv("").is_err());
+        assert!(parse_csv("phone,reference\n0712345678,x").is_err());
+    }
+
+    #[test]
+    fn test_payout_instructions() {
+        let rate = BtcKesRate::new(Decimal::from(10_000_000)).unwrap();
+        let networks = [MobileMoneyNetwork::Mpesa];
+        let row = |recipient: &str, amount_kes: Option<i64>, amount_sats: Option<i64>| PayoutRow {
+            recipient: recipient.to_string(),
+            amount_kes: amount_kes.map(Decimal::from),
+            amount_sats,
+            reference: Some("  ".to_string()),
+        };
+
+        let mpesa = PayoutInstruction::from_row(1, row("0712345678", Some(1000), None), &rate, &networks).unwrap();
+        assert_eq!(mpesa.recipient.lane(), PayoutLane::MobileMoney(MobileMoneyNetwork::Mpesa));
+        assert!(mpesa.amount_sats.as_i64() > 0);
+        assert_eq!(mpesa.reference, None);
+
+        let lightning =
+            PayoutInstruction::from_row(2, row(" Alice@GetAlby.com ", None, Some(500)), &rate, &networks).unwrap();
+        assert_eq!(lightning.recipient, PayoutRecipient::LightningAddress("alice@getalby.com".to_string()));
+        assert_eq!(lightning.amount_sats, SatAmount::new(500));
+
+        let invalid = [
+            // Airtel Money isn't enabled
+            row("0733123456", Some(1000), None),
+            // Telkom has no mobile money payouts
+            row("0771234567", Some(1000), None),
+            // Below the withdrawal minimum
+            row("0712345678", None, Some(999)),
+            row("alice@getalby.com", Some(10), Some(500)),
+            row("alice@getalby.com", None, Some(0)),
+            // Networks only pay whole shillings
+            PayoutRow {
+                amount_kes: Some(Decimal::new(100_050, 2)),
+                ..row("0712345678", None, None)
+            },
+            row("not a recipient", None, Some(500)),
+        ];
+        for row in invalid {
+            assert!(PayoutInstruction::from_row(1, row.clone(), &rate, &networks).is_err(), "{:?}", row);
+        }
+
+        let errors: Vec<String> = (1..=25).map(|n| format!("Row {}: Invalid phone number", n)).collect();
+        let message = rejection_message(&errors);
+        assert!(message.starts_with("25 rows are invalid"));
+        assert!(message.ends_with("and 5 more"));
+    }
+}
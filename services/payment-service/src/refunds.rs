@@ -0,0 +1,655 @@
+/// Refunds of failed transactions
+///
+/// A refund adds a `refund` ledger entry linked to the original transaction,
+/// marks the original as refunded with the reason, writes an audit log entry and
+/// records an event so the user is notified. Failed withdrawals, Lightning
+/// sends and airtime purchases are refunded automatically when their failure events arrive; operators
+/// can also refund manually. Deposits that were paid but could not be converted
+/// are returned in KES over M-Pesa by an operator, who then records the receipt.
+
+use crate::domain::{StatusActor, StatusTransition};
+use crate::transitions::{set_status_actor, transition_status};
+use chrono::{DateTime, Utc};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use shared_errors::{AppError, Result};
+use shared_events::{record_event, DomainEvent, EventEnvelope};
+use shared_types::*;
+use sqlx::{PgConnection, PgPool};
+use std::sync::Arc;
+use tracing::{info, instrument};
+use uuid::Uuid;
+use validator::Validate;
+
+/// Why a transaction was refunded
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[sqlx(type_name = "refund_reason", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum RefundReason {
+    WithdrawalFailed,
+    LightningSendFailed,
+    DepositNotConverted,
+    AirtimeFailed,
+    Manual,
+}
+
+impl RefundReason {
+    /// Reason used when a failed transaction of this type is refunded automatically
+    pub fn automatic(transaction_type: &TransactionType) -> Option<Self> {
+        match transaction_type {
+            TransactionType::WithdrawalMpesa => Some(RefundReason::WithdrawalFailed),
+            TransactionType::LightningSend => Some(RefundReason::LightningSendFailed),
+            TransactionType::DepositMpesa => Some(RefundReason::DepositNotConverted),
+            TransactionType::AirtimePurchase => Some(RefundReason::AirtimeFailed),
+            TransactionType::LightningReceive
+            | TransactionType::Refund
+            | TransactionType::VaultDeposit
+            | TransactionType::VaultWithdrawal => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RefundReason::WithdrawalFailed => "withdrawal_failed",
+            RefundReason::LightningSendFailed => "lightning_send_failed",
+            RefundReason::DepositNotConverted => "deposit_not_converted",
+            RefundReason::AirtimeFailed => "airtime_failed",
+            RefundReason::Manual => "manual",
+        }
+    }
+}
+
+/// What a refund gives back
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RefundAmount {
+    /// Credited to the wallet straight away
+    Sats(SatAmount),
+    /// Returned over M-Pesa by an operator
+    Kes(KesAmount),
+}
+
+/// Fields of a transaction a refund needs
+#[derive(Debug, Clone)]
+pub struct RefundableTransaction {
+    pub id: Uuid,
+    pub user_id: UserId,
+    pub transaction_type: TransactionType,
+    pub status: TransactionStatus,
+    pub amount_kes: Option<Decimal>,
+    pub amount_sats: Option<i64>,
+    pub fee_sats: Option<i64>,
+    pub mpesa_code: Option<String>,
+}
+
+impl RefundableTransaction {
+    /// Amount to give back, or why the transaction cannot be refunded
+    /// Only failed transactions are refunded automatically; operators can also
+    /// refund transactions stuck in processing (they are failed first)
+    pub fn refund_amount(&self, manual: bool) -> Result<RefundAmount> {
+        match self.status {
+            TransactionStatus::Failed => {}
+            TransactionStatus::Processing if manual => {}
+            TransactionStatus::Refunded => {
+                return Err(AppError::Conflict {
+                    message: "Transaction was already refunded".to_string(),
+                })
+            }
+            TransactionStatus::Completed => {
+                return Err(AppError::Payment {
+                    message: "Completed transactions cannot be refunded".to_string(),
+                })
+            }
+            TransactionStatus::Pending => {
+                return Err(AppError::Payment {
+                    message: "Pending transactions cannot be refunded".to_string(),
+                })
+            }
+            TransactionStatus::Processing => {
+                return Err(AppError::Payment {
+                    message: "Only failed transactions are refunded automatically".to_string(),
+                })
+            }
+        }
+
+        match self.transaction_type {
+            // Sats were debited when the payment was initiated; fees are returned too
+            TransactionType::WithdrawalMpesa | TransactionType::LightningSend | TransactionType::AirtimePurchase => {
+                let sats = self
+                    .amount_sats
+                    .unwrap_or(0)
+                    .checked_add(self.fee_sats.unwrap_or(0))
+                    .filter(|sats| *sats > 0)
+                    .ok_or_else(|| AppError::Payment {
+                        message: "Transaction has no sats to refund".to_string(),
+                    })?;
+                Ok(RefundAmount::Sats(SatAmount::new(sats)))
+            }
+            // Without an M-Pesa receipt the money never reached PesaBit
+            TransactionType::DepositMpesa => match (&self.mpesa_code, self.amount_kes) {
+                (Some(_), Some(kes)) if kes > Decimal::ZERO => Ok(RefundAmount::Kes(KesAmount::new(kes))),
+                _ => Err(AppError::Payment {
+                    message: "Deposit was never paid, there is nothing to refund".to_string(),
+                }),
+            },
+            TransactionType::LightningReceive
+            | TransactionType::Refund
+            | TransactionType::VaultDeposit
+            | TransactionType::VaultWithdrawal => Err(AppError::Payment {
+                message: "This type of transaction cannot be refunded".to_string(),
+            }),
+        }
+    }
+}
+
+/// Refund as stored
+#[derive(Debug, Clone, Serialize)]
+pub struct Refund {
+    pub id: Uuid,
+    pub original_transaction_id: Uuid,
+    pub reversal_transaction_id: Uuid,
+    pub user_id: UserId,
+    pub reason: RefundReason,
+    pub note: Option<String>,
+    pub initiated_by: Option<String>,
+    pub amount_sats: Option<SatAmount>,
+    pub amount_kes: Option<KesAmount>,
+    pub payout_mpesa_code: Option<String>,
+    pub paid_out_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Operator request to refund a transaction
+#[derive(Debug, Deserialize, Validate)]
+pub struct ManualRefundRequest {
+    pub transaction_id: Uuid,
+    /// Why the refund was made (kept on the refund and in the audit log)
+    #[validate(length(min = 1, max = 1000))]
+    pub note: String,
+    /// Operator making the refund
+    #[validate(length(min = 1, max = 100))]
+    pub operator: String,
+}
+
+/// Operator confirmation that a KES refund was paid out over M-Pesa
+#[derive(Debug, Deserialize, Validate)]
+pub struct RecordPayoutRequest {
+    #[validate(length(min = 1, max = 50))]
+    pub mpesa_code: String,
+    #[validate(length(min = 1, max = 100))]
+    pub operator: String,
+}
+
+/// Who asked for a refund
+struct RefundOrigin<'a> {
+    reason: Option<RefundReason>,
+    note: Option<&'a str>,
+    initiated_by: Option<&'a str>,
+}
+
+/// Database access for refunds
+pub struct RefundRepository {
+    pool: PgPool,
+}
+
+impl RefundRepository {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+
+    /// Refund a transaction: reversal entry, status change, wallet credit,
+    /// audit log and event all commit together
+    /// `origin.reason` is `None` for automatic refunds (derived from the transaction type)
+    #[instrument(skip(self, origin))]
+    async fn create(&self, transaction_id: Uuid, origin: RefundOrigin<'_>) -> Result<Refund> {
+        let mut tx = self.pool.begin().await?;
+
+        let transaction = Self::lock_transaction(&mut *tx, transaction_id)
+            .await?
+            .ok_or_else(|| AppError::Payment {
+                message: "Transaction not found".to_string(),
+            })?;
+
+        let manual = origin.reason == Some(RefundReason::Manual);
+        let amount = transaction.refund_amount(manual)?;
+        let reason = origin
+            .reason
+            .or_else(|| RefundReason::automatic(&transaction.transaction_type))
+            .ok_or_else(|| AppError::Payment {
+                message: "This type of transaction cannot be refunded".to_string(),
+            })?;
+
+        let (amount_sats, amount_kes) = match amount {
+            RefundAmount::Sats(sats) => (Some(sats.as_i64()), None),
+            RefundAmount::Kes(kes) => (None, Some(kes.as_decimal())),
+        };
+        // KES reversals stay processing until an operator records the M-Pesa payout
+        let (status, completed_at) = match amount {
+            RefundAmount::Sats(_) => (TransactionStatus::Completed, Some(Utc::now())),
+            RefundAmount::Kes(_) => (TransactionStatus::Processing, None),
+        };
+
+        let actor = match origin.initiated_by {
+            Some(operator) => StatusActor::Operator(operator.to_string()),
+            None => StatusActor::System,
+        };
+        let status_reason = origin.note.unwrap_or(reason.as_str());
+        set_status_actor(&mut *tx, &actor, Some(status_reason)).await?;
+
+        let refund_id = Uuid::new_v4();
+        let reversal_id = Uuid::new_v4();
+
+        sqlx::query!(
+            r#"
+            INSERT INTO transactions
+                (id, user_id, type, status, amount_sats, amount_kes, reverses_transaction_id, metadata, completed_at)
+            VALUES ($1, $2, 'refund', $3, $4, $5, $6, $7, $8)
+            "#,
+            reversal_id,
+            transaction.user_id.0,
+            status as _,
+            amount_sats,
+            amount_kes,
+            transaction.id,
+            serde_json::json!({"refund_id": refund_id, "reason": reason}),
+            completed_at,
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        if transaction.status == TransactionStatus::Processing {
+            let fail = StatusTransition::new(TransactionStatus::Processing, TransactionStatus::Failed)?;
+            transition_status(&mut *tx, transaction.id, &fail, &actor, Some(status_reason)).await?;
+        }
+        let refunded = StatusTransition::new(TransactionStatus::Failed, TransactionStatus::Refunded)?;
+        transition_status(&mut *tx, transaction.id, &refunded, &actor, Some(status_reason)).await?;
+
+        sqlx::query!(
+            "UPDATE transactions SET metadata = COALESCE(metadata, '{}'::jsonb) || $2 WHERE id = $1",
+            transaction.id,
+            serde_json::json!({"refund_id": refund_id, "refund_reason": reason, "refund_note": origin.note}),
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        if let Some(sats) = amount_sats {
+            let credited = sqlx::query!(
+                "UPDATE wallets SET balance_sats = balance_sats + $2 WHERE user_id = $1",
+                transaction.user_id.0,
+                sats,
+            )
+            .execute(&mut *tx)
+            .await?;
+
+            if credited.rows_affected() != 1 {
+                return Err(AppError::Internal(anyhow::anyhow!(
+                    "No wallet to refund for user {}",
+                    transaction.user_id.0
+                )));
+            }
+        }
+
+        let row = sqlx::query!(
+            r#"
+            INSERT INTO refunds
+                (id, original_transaction_id, reversal_transaction_id, user_id, reason, note,
+                 initiated_by, amount_sats, amount_kes)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+            RETURNING created_at
+            "#,
+            refund_id,
+            transaction.id,
+            reversal_id,
+            transaction.user_id.0,
+            reason as _,
+            origin.note,
+            origin.initiated_by,
+            amount_sats,
+            amount_kes,
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+
+        Self::audit(
+            &mut *tx,
+            "transaction.refunded",
+            transaction.id,
+            serde_json::json!({
+                "refund_id": refund_id,
+                "reversal_transaction_id": reversal_id,
+                "reason": reason,
+                "note": origin.note,
+                "initiated_by": origin.initiated_by,
+                "previous_status": transaction.status,
+                "amount_sats": amount_sats,
+                "amount_kes": amount_kes,
+            }),
+        )
+        .await?;
+
+        if let RefundAmount::Sats(amount_sats) = amount {
+            record_event(
+                &mut *tx,
+                &DomainEvent::RefundCredited {
+                    transaction_id: transaction.id,
+                    refund_transaction_id: reversal_id,
+                    user_id: transaction.user_id,
+                    amount_sats,
+                    reason: reason.as_str().to_string(),
+                },
+            )
+            .await?;
+        }
+
+        tx.commit().await?;
+
+        Ok(Refund {
+            id: refund_id,
+            original_transaction_id: transaction.id,
+            reversal_transaction_id: reversal_id,
+            user_id: transaction.user_id,
+            reason,
+            note: origin.note.map(str::to_string),
+            initiated_by: origin.initiated_by.map(str::to_string),
+            amount_sats: amount_sats.map(SatAmount::new),
+            amount_kes: amount_kes.map(KesAmount::new),
+            payout_mpesa_code: None,
+            paid_out_at: None,
+            created_at: row.created_at,
+        })
+    }
+
+    /// Record the M-Pesa payout of a KES refund and complete its reversal entry
+    #[instrument(skip(self))]
+    async fn record_payout(&self, refund_id: Uuid, mpesa_code: &str, operator: &str) -> Result<Refund> {
+        let mut tx = self.pool.begin().await?;
+
+        let row = sqlx::query!(
+            r#"
+            UPDATE refunds
+            SET payout_mpesa_code = $2, paid_out_at = NOW()
+            WHERE id = $1 AND amount_kes IS NOT NULL AND paid_out_at IS NULL
+            RETURNING id, original_transaction_id, reversal_transaction_id, user_id,
+                      reason as "reason: RefundReason", note, initiated_by,
+                      amount_kes as "amount_kes!", paid_out_at as "paid_out_at!", created_at
+            "#,
+            refund_id,
+            mpesa_code,
+        )
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or_else(|| AppError::Conflict {
+            message: "Refund not found or already paid out".to_string(),
+        })?;
+
+        let paid_out = StatusTransition::new(TransactionStatus::Processing, TransactionStatus::Completed)?;
+        transition_status(
+            &mut *tx,
+            row.reversal_transaction_id,
+            &paid_out,
+            &StatusActor::Operator(operator.to_string()),
+            Some("Refund paid out over M-Pesa"),
+        )
+        .await?;
+
+        sqlx::query!(
+            "UPDATE transactions SET mpesa_code = $2 WHERE id = $1",
+            row.reversal_transaction_id,
+            mpesa_code,
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        Self::audit(
+            &mut *tx,
+            "refund.paid_out",
+            row.original_transaction_id,
+            serde_json::json!({
+                "refund_id": row.id,
+                "mpesa_code": mpesa_code,
+                "operator": operator,
+                "amount_kes": row.amount_kes,
+            }),
+        )
+        .await?;
+
+        record_event(
+            &mut *tx,
+            &DomainEvent::DepositRefunded {
+                transaction_id: row.original_transaction_id,
+                refund_transaction_id: row.reversal_transaction_id,
+                user_id: UserId(row.user_id),
+                amount_kes: KesAmount::new(row.amount_kes),
+                mpesa_code: MpesaCode(mpesa_code.to_string()),
+            },
+        )
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(Refund {
+            id: row.id,
+            original_transaction_id: row.original_transaction_id,
+            reversal_transaction_id: row.reversal_transaction_id,
+            user_id: UserId(row.user_id),
+            reason: row.reason,
+            note: row.note,
+            initiated_by: row.initiated_by,
+            amount_sats: None,
+            amount_kes: Some(KesAmount::new(row.amount_kes)),
+            payout_mpesa_code: Some(mpesa_code.to_string()),
+            paid_out_at: Some(row.paid_out_at),
+            created_at: row.created_at,
+        })
+    }
+
+    /// KES refunds still waiting to be paid out, oldest first
+    #[instrument(skip(self))]
+    async fn pending_payouts(&self) -> Result<Vec<Refund>> {
+        let rows = sqlx::query!(
+            r#"
+            SELECT id, original_transaction_id, reversal_transaction_id, user_id,
+                   reason as "reason: RefundReason", note, initiated_by, amount_kes, created_at
+            FROM refunds
+            WHERE amount_kes IS NOT NULL AND paid_out_at IS NULL
+            ORDER BY created_at
+            LIMIT 500
+            "#
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|r| Refund {
+                id: r.id,
+                original_transaction_id: r.original_transaction_id,
+                reversal_transaction_id: r.reversal_transaction_id,
+                user_id: UserId(r.user_id),
+                reason: r.reason,
+                note: r.note,
+                initiated_by: r.initiated_by,
+                amount_sats: None,
+                amount_kes: r.amount_kes.map(KesAmount::new),
+                payout_mpesa_code: None,
+                paid_out_at: None,
+                created_at: r.created_at,
+            })
+            .collect())
+    }
+
+    async fn lock_transaction(conn: &mut PgConnection, transaction_id: Uuid) -> Result<Option<RefundableTransaction>> {
+        let row = sqlx::query!(
+            r#"
+            SELECT id, user_id, type as "transaction_type: TransactionType",
+                   status as "status: TransactionStatus", amount_kes, amount_sats, fee_sats, mpesa_code
+            FROM transactions
+            WHERE id = $1
+            FOR UPDATE
+            "#,
+            transaction_id
+        )
+        .fetch_optional(conn)
+        .await?;
+
+        Ok(row.map(|r| RefundableTransaction {
+            id: r.id,
+            user_id: UserId(r.user_id),
+            transaction_type: r.transaction_type,
+            status: r.status,
+            amount_kes: r.amount_kes,
+            amount_sats: r.amount_sats,
+            fee_sats: r.fee_sats,
+            mpesa_code: r.mpesa_code,
+        }))
+    }
+
+    async fn audit(conn: &mut PgConnection, action: &str, transaction_id: Uuid, details: serde_json::Value) -> Result<()> {
+        sqlx::query!(
+            r#"
+            INSERT INTO audit_logs (action, entity_type, entity_id, details)
+            VALUES ($1, 'transaction', $2, $3)
+            "#,
+            action,
+            transaction_id.to_string(),
+            details,
+        )
+        .execute(conn)
+        .await?;
+
+        Ok(())
+    }
+}
+
+/// Refund orchestration
+pub struct RefundService {
+    repository: Arc<RefundRepository>,
+}
+
+impl RefundService {
+    pub fn new(repository: Arc<RefundRepository>) -> Self {
+        Self { repository }
+    }
+
+    /// Refund the transaction behind a failure event, if it is refundable
+    /// Safe for redelivered events: an already refunded transaction is skipped
+    #[instrument(skip(self, envelope), fields(event_id = %envelope.id))]
+    pub async fn handle_event(&self, envelope: &EventEnvelope) -> Result<Option<Refund>> {
+        let transaction_id = match &envelope.event {
+            DomainEvent::WithdrawalFailed { transaction_id, .. }
+            | DomainEvent::PaymentFailed { transaction_id, .. }
+            | DomainEvent::DepositFailed { transaction_id, .. }
+            | DomainEvent::AirtimeFailed { transaction_id, .. } => *transaction_id,
+            _ => return Ok(None),
+        };
+
+        let origin = RefundOrigin {
+            reason: None,
+            note: None,
+            initiated_by: None,
+        };
+
+        match self.repository.create(transaction_id, origin).await {
+            Ok(refund) => {
+                info!("Refunded transaction {} ({})", transaction_id, refund.reason.as_str());
+                Ok(Some(refund))
+            }
+            // Already refunded, or nothing to give back (e.g., an unpaid deposit)
+            Err(AppError::Conflict { .. } | AppError::Payment { .. }) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Refund a transaction on an operator's request
+    #[instrument(skip(self, request), fields(transaction_id = %request.transaction_id, operator = %request.operator))]
+    pub async fn refund_manually(&self, request: ManualRefundRequest) -> Result<Refund> {
+        request.validate().map_err(|e| AppError::Validation {
+            message: format!("Invalid refund request: {}", e),
+        })?;
+
+        let origin = RefundOrigin {
+            reason: Some(RefundReason::Manual),
+            note: Some(&request.note),
+            initiated_by: Some(&request.operator),
+        };
+        let refund = self.repository.create(request.transaction_id, origin).await?;
+
+        info!("Operator {} refunded transaction {}", request.operator, request.transaction_id);
+        Ok(refund)
+    }
+
+    /// KES refunds an operator still has to send over M-Pesa
+    #[instrument(skip(self))]
+    pub async fn pending_payouts(&self) -> Result<Vec<Refund>> {
+        self.repository.pending_payouts().await
+    }
+
+    /// Record that a KES refund was paid out, notifying the user
+    #[instrument(skip(self, request), fields(operator = %request.operator))]
+    pub async fn record_payout(&self, refund_id: Uuid, request: RecordPayoutRequest) -> Result<Refund> {
+        request.validate().map_err(|e| AppError::Validation {
+            message: format!("Invalid payout: {}", e),
+        })?;
+
+        self.repository
+            .record_payout(refund_id, &request.mpesa_code, &request.operator)
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn transaction(transaction_type: TransactionType, status: TransactionStatus) -> RefundableTransaction {
+        RefundableTransaction {
+            id: Uuid::new_v4(),
+            user_id: UserId::new(),
+            transaction_type,
+            status,
+            amount_kes: Some(Decimal::from(1000)),
+            amount_sats: Some(18_679),
+            fee_sats: Some(120),
+            mpesa_code: Some("QL12XYZ789".to_string()),
+        }
+    }
+
+    #[test]
+    fn test_failed_sends_refund_amount_and_fees() {
+        for transaction_type in [
+            TransactionType::WithdrawalMpesa,
+            TransactionType::LightningSend,
+            TransactionType::AirtimePurchase,
+        ] {
+            let failed = transaction(transaction_type, TransactionStatus::Failed);
+            assert_eq!(failed.refund_amount(false).unwrap(), RefundAmount::Sats(SatAmount::new(18_799)));
+        }
+    }
+
+    #[test]
+    fn test_deposit_refunds_need_mpesa_receipt() {
+        let paid = transaction(TransactionType::DepositMpesa, TransactionStatus::Failed);
+        assert_eq!(
+            paid.refund_amount(false).unwrap(),
+            RefundAmount::Kes(KesAmount::new(Decimal::from(1000)))
+        );
+
+        let unpaid = RefundableTransaction { mpesa_code: None, ..paid };
+        assert!(matches!(unpaid.refund_amount(false), Err(AppError::Payment { .. })));
+    }
+
+    #[test]
+    fn test_refund_eligibility_by_status() {
+        let stuck = transaction(TransactionType::WithdrawalMpesa, TransactionStatus::Processing);
+        assert!(stuck.refund_amount(false).is_err());
+        assert!(stuck.refund_amount(true).is_ok());
+
+        let completed = transaction(TransactionType::WithdrawalMpesa, TransactionStatus::Completed);
+        assert!(matches!(completed.refund_amount(true), Err(AppError::Payment { .. })));
+
+        let refunded = transaction(TransactionType::LightningSend, TransactionStatus::Refunded);
+        assert!(matches!(refunded.refund_amount(true), Err(AppError::Conflict { .. })));
+
+        let received = transaction(TransactionType::LightningReceive, TransactionStatus::Failed);
+        assert!(received.refund_amount(true).is_err());
+    }
+}
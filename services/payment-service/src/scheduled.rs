@@ -0,0 +1,1275 @@
+/// Scheduled and recurring payments
+///
+/// Users set up one-off future payments or recurring ones (cron expressions in
+/// Nairobi time) to a Lightning address, another PesaBit user by phone number,
+/// or an M-Pesa number. The background worker leases due payments, checks the
+/// balance and limits, and makes the payment through `PaymentService`. Failed
+/// runs are retried according to the payment's policy; when a run fails for good
+/// the user is notified. Every attempt is recorded before the payment is made,
+/// and a run cut short by a crash, or whose payment may have gone out, is never
+/// retried, so a payment is made at most once per occurrence.
+
+use crate::cron::CronSchedule;
+use crate::domain::{MpesaWithdrawalRequest, PayInvoiceRequest};
+use crate::limits::LimitsService;
+use crate::lnurl::{LnurlClient, ParsedLightningAddress};
+use crate::service::PaymentService;
+use crate::transfers::LIGHTNING_ADDRESS_DOMAIN;
+use chrono::{DateTime, Duration, Utc};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use shared_errors::{AppError, Result};
+use shared_events::{record_event, DomainEvent};
+use shared_types::conversion::{Rounding, Side};
+use shared_types::*;
+use sqlx::PgPool;
+use std::sync::Arc;
+use tracing::{error, info, instrument, warn};
+use uuid::Uuid;
+use validator::Validate;
+
+/// Retry policy used when the request doesn't give one
+const DEFAULT_MAX_ATTEMPTS: i32 = 3;
+const DEFAULT_RETRY_AFTER_MINUTES: i32 = 30;
+/// How long a worker has to finish a run before it counts as interrupted
+const RUN_LEASE_MINUTES: i64 = 5;
+/// Runs shown with a scheduled payment
+const RECENT_RUNS: i64 = 20;
+
+/// Why an attempt failed
+#[derive(Debug)]
+enum RunError {
+    /// Nothing was paid (balance, limits, bad target, payment refused), so the run can be retried
+    NotSent(AppError),
+    /// The payment may have gone out; retrying could pay twice
+    Unconfirmed(AppError),
+}
+
+impl RunError {
+    /// Errors from making the payment only prove nothing was sent when it was refused up front
+    fn from_payment(error: AppError) -> Self {
+        match error {
+            AppError::Payment { .. }
+            | AppError::LimitExceeded { .. }
+            | AppError::Validation { .. }
+            | AppError::User { .. } => RunError::NotSent(error),
+            error => RunError::Unconfirmed(error),
+        }
+    }
+}
+
+/// Where a scheduled payment goes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[sqlx(type_name = "scheduled_payment_target", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum ScheduledPaymentTarget {
+    LightningAddress,
+    /// Another PesaBit user
+    PhoneNumber,
+    MpesaWithdrawal,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[sqlx(type_name = "scheduled_payment_status", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum ScheduledPaymentStatus {
+    Active,
+    Paused,
+    Completed,
+    Cancelled,
+    Failed,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[sqlx(type_name = "scheduled_payment_run_status", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum ScheduledRunStatus {
+    Started,
+    Succeeded,
+    Failed,
+    Interrupted,
+}
+
+/// Amount paid on each run
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScheduledAmount {
+    Sats(SatAmount),
+    /// Converted at the rate when the payment runs
+    Kes(KesAmount),
+}
+
+impl ScheduledAmount {
+    fn from_fields(amount_sats: Option<i64>, amount_kes: Option<Decimal>) -> Result<Self> {
+        match (amount_sats, amount_kes) {
+            (Some(sats), None) if sats > 0 => Ok(ScheduledAmount::Sats(SatAmount::new(sats))),
+            (None, Some(kes)) if kes > Decimal::ZERO => Ok(ScheduledAmount::Kes(KesAmount::new(kes))),
+            (Some(_), Some(_)) | (None, None) => Err(AppError::Validation {
+                message: "Give exactly one of amount_sats and amount_kes".to_string(),
+            }),
+            _ => Err(AppError::Validation {
+                message: "Amount must be positive".to_string(),
+            }),
+        }
+    }
+
+    fn fields(&self) -> (Option<i64>, Option<Decimal>) {
+        match self {
+            ScheduledAmount::Sats(sats) => (Some(sats.as_i64()), None),
+            ScheduledAmount::Kes(kes) => (None, Some(kes.as_decimal())),
+        }
+    }
+}
+
+/// When a scheduled payment runs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Timing {
+    Once(DateTime<Utc>),
+    Recurring(CronSchedule),
+}
+
+impl Timing {
+    fn from_fields(run_at: Option<DateTime<Utc>>, cron: Option<&str>) -> Result<Self> {
+        match (run_at, cron) {
+            (Some(run_at), None) => Ok(Timing::Once(run_at)),
+            (None, Some(cron)) => Ok(Timing::Recurring(CronSchedule::parse(cron)?)),
+            _ => Err(AppError::Validation {
+                message: "Give exactly one of run_at and cron".to_string(),
+            }),
+        }
+    }
+
+    fn fields(&self) -> (Option<DateTime<Utc>>, Option<String>) {
+        match self {
+            Timing::Once(run_at) => (Some(*run_at), None),
+            Timing::Recurring(cron) => (None, Some(cron.expression().to_string())),
+        }
+    }
+
+    /// First run of a new or edited schedule
+    pub fn first_run(&self, now: DateTime<Utc>) -> Result<DateTime<Utc>> {
+        match self {
+            Timing::Once(run_at) if *run_at > now => Ok(*run_at),
+            Timing::Once(_) => Err(AppError::Validation {
+                message: "run_at must be in the future".to_string(),
+            }),
+            Timing::Recurring(cron) => cron.next_after(now).ok_or_else(|| AppError::Validation {
+                message: "Schedule never runs".to_string(),
+            }),
+        }
+    }
+
+    /// Occurrence after the one that just ran (missed occurrences are skipped, not caught up)
+    pub fn next_after(&self, occurrence_at: DateTime<Utc>, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        match self {
+            Timing::Once(_) => None,
+            Timing::Recurring(cron) => cron.next_after(occurrence_at.max(now)),
+        }
+    }
+
+    /// Next run when a paused schedule is resumed (an overdue one-off payment runs straight away)
+    pub fn resume_at(&self, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        match self {
+            Timing::Once(run_at) => Some((*run_at).max(now)),
+            Timing::Recurring(cron) => cron.next_after(now),
+        }
+    }
+}
+
+/// Validated payment instruction
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScheduleSpec {
+    pub target_type: ScheduledPaymentTarget,
+    pub target: String,
+    pub amount: ScheduledAmount,
+    pub memo: Option<String>,
+    pub timing: Timing,
+    pub max_attempts: i32,
+    pub retry_after_minutes: i32,
+}
+
+impl ScheduleSpec {
+    /// Normalized target, or why it is invalid for the target type
+    fn normalize_target(target_type: ScheduledPaymentTarget, target: &str) -> Result<String> {
+        match target_type {
+            ScheduledPaymentTarget::LightningAddress => {
+                let address = ParsedLightningAddress::parse(target)?;
+                Ok(format!("{}@{}", address.name, address.domain))
+            }
+            ScheduledPaymentTarget::PhoneNumber | ScheduledPaymentTarget::MpesaWithdrawal => {
+                let phone = PhoneNumber::new(target.trim().to_string())
+                    .map_err(|message| AppError::Validation { message })?;
+                Ok(phone.0)
+            }
+        }
+    }
+}
+
+/// Request to schedule a payment
+#[derive(Debug, Deserialize, Validate)]
+pub struct CreateScheduledPaymentRequest {
+    pub target_type: ScheduledPaymentTarget,
+    /// Lightning address or phone number (E.164)
+    #[validate(length(min = 1, max = 255))]
+    pub target: String,
+    pub amount_sats: Option<i64>,
+    pub amount_kes: Option<Decimal>,
+    #[validate(length(max = 140))]
+    pub memo: Option<String>,
+    /// One-off payment time
+    pub run_at: Option<DateTime<Utc>>,
+    /// Recurring schedule, e.g. "0 8 1 * *" for 08:00 on the 1st of every month
+    pub cron: Option<String>,
+    /// Attempts per run (default 3)
+    #[validate(range(min = 1, max = 5))]
+    pub max_attempts: Option<i32>,
+    /// Wait between attempts (default 30 minutes)
+    #[validate(range(min = 5, max = 1440))]
+    pub retry_after_minutes: Option<i32>,
+}
+
+impl CreateScheduledPaymentRequest {
+    pub fn spec(&self) -> Result<ScheduleSpec> {
+        Ok(ScheduleSpec {
+            target_type: self.target_type,
+            target: ScheduleSpec::normalize_target(self.target_type, &self.target)?,
+            amount: ScheduledAmount::from_fields(self.amount_sats, self.amount_kes)?,
+            memo: self.memo.clone(),
+            timing: Timing::from_fields(self.run_at, self.cron.as_deref())?,
+            max_attempts: self.max_attempts.unwrap_or(DEFAULT_MAX_ATTEMPTS),
+            retry_after_minutes: self.retry_after_minutes.unwrap_or(DEFAULT_RETRY_AFTER_MINUTES),
+        })
+    }
+}
+
+/// Changes to a scheduled payment; fields left out stay as they are
+#[derive(Debug, Deserialize, Validate)]
+pub struct UpdateScheduledPaymentRequest {
+    pub target_type: Option<ScheduledPaymentTarget>,
+    #[validate(length(min = 1, max = 255))]
+    pub target: Option<String>,
+    /// Giving either amount replaces the current one
+    pub amount_sats: Option<i64>,
+    pub amount_kes: Option<Decimal>,
+    #[validate(length(max = 140))]
+    pub memo: Option<String>,
+    /// Giving either replaces the current schedule
+    pub run_at: Option<DateTime<Utc>>,
+    pub cron: Option<String>,
+    #[validate(range(min = 1, max = 5))]
+    pub max_attempts: Option<i32>,
+    #[validate(range(min = 5, max = 1440))]
+    pub retry_after_minutes: Option<i32>,
+}
+
+impl UpdateScheduledPaymentRequest {
+    /// Instruction after applying the changes
+    pub fn apply(&self, current: &ScheduleSpec) -> Result<ScheduleSpec> {
+        let target_type = self.target_type.unwrap_or(current.target_type);
+        let target = match (&self.target, self.target_type) {
+            (Some(target), _) => ScheduleSpec::normalize_target(target_type, target)?,
+            (None, Some(_)) => ScheduleSpec::normalize_target(target_type, &current.target)?,
+            (None, None) => current.target.clone(),
+        };
+
+        let amount = if self.amount_sats.is_some() || self.amount_kes.is_some() {
+            ScheduledAmount::from_fields(self.amount_sats, self.amount_kes)?
+        } else {
+            current.amount
+        };
+        let timing = if self.run_at.is_some() || self.cron.is_some() {
+            Timing::from_fields(self.run_at, self.cron.as_deref())?
+        } else {
+            current.timing.clone()
+        };
+
+        Ok(ScheduleSpec {
+            target_type,
+            target,
+            amount,
+            memo: self.memo.clone().or_else(|| current.memo.clone()),
+            timing,
+            max_attempts: self.max_attempts.unwrap_or(current.max_attempts),
+            retry_after_minutes: self.retry_after_minutes.unwrap_or(current.retry_after_minutes),
+        })
+    }
+}
+
+/// Scheduled payment as stored
+#[derive(Debug, Clone, Serialize)]
+pub struct ScheduledPayment {
+    pub id: Uuid,
+    pub user_id: UserId,
+    pub target_type: ScheduledPaymentTarget,
+    pub target: String,
+    pub amount_sats: Option<SatAmount>,
+    pub amount_kes: Option<KesAmount>,
+    pub memo: Option<String>,
+    pub run_at: Option<DateTime<Utc>>,
+    pub cron: Option<String>,
+    pub max_attempts: i32,
+    pub retry_after_minutes: i32,
+    pub status: ScheduledPaymentStatus,
+    /// Scheduled time of the run in progress
+    pub occurrence_at: Option<DateTime<Utc>>,
+    pub next_run_at: Option<DateTime<Utc>>,
+    /// Attempts made for the current run
+    pub attempt: i32,
+    pub run_count: i32,
+    pub last_run_at: Option<DateTime<Utc>>,
+    pub last_error: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl ScheduledPayment {
+    pub fn spec(&self) -> Result<ScheduleSpec> {
+        Ok(ScheduleSpec {
+            target_type: self.target_type,
+            target: self.target.clone(),
+            amount: ScheduledAmount::from_fields(
+                self.amount_sats.map(|s| s.as_i64()),
+                self.amount_kes.map(|k| k.as_decimal()),
+            )?,
+            memo: self.memo.clone(),
+            timing: Timing::from_fields(self.run_at, self.cron.as_deref())?,
+            max_attempts: self.max_attempts,
+            retry_after_minutes: self.retry_after_minutes,
+        })
+    }
+}
+
+/// One attempt of a run
+#[derive(Debug, Clone, Serialize)]
+pub struct ScheduledPaymentRun {
+    pub id: Uuid,
+    pub occurrence_at: DateTime<Utc>,
+    pub attempt: i32,
+    pub status: ScheduledRunStatus,
+    pub transaction_id: Option<Uuid>,
+    pub error: Option<String>,
+    pub started_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
+}
+
+/// Scheduled payment with its recent runs
+#[derive(Debug, Serialize)]
+pub struct ScheduledPaymentDetails {
+    #[serde(flatten)]
+    pub payment: ScheduledPayment,
+    pub recent_runs: Vec<ScheduledPaymentRun>,
+}
+
+/// Database access for scheduled payments
+pub struct ScheduledPaymentRepository {
+    pool: PgPool,
+}
+
+impl ScheduledPaymentRepository {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+
+    #[instrument(skip(self, spec))]
+    pub async fn create(&self, user_id: UserId, spec: &ScheduleSpec, first_run: DateTime<Utc>) -> Result<Uuid> {
+        let (amount_sats, amount_kes) = spec.amount.fields();
+        let (run_at, cron) = spec.timing.fields();
+
+        let row = sqlx::query!(
+            r#"
+            INSERT INTO scheduled_payments
+                (user_id, target_type, target, amount_sats, amount_kes, memo, run_at, cron,
+                 max_attempts, retry_after_minutes, occurrence_at, next_run_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $11)
+            RETURNING id
+            "#,
+            user_id.0,
+            spec.target_type as _,
+            spec.target,
+            amount_sats,
+            amount_kes,
+            spec.memo,
+            run_at,
+            cron,
+            spec.max_attempts,
+            spec.retry_after_minutes,
+            first_run,
+        )
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(row.id)
+    }
+
+    #[instrument(skip(self))]
+    pub async fn get(&self, user_id: UserId, id: Uuid) -> Result<Option<ScheduledPayment>> {
+        let row = sqlx::query!(
+            r#"
+            SELECT id, user_id, target_type as "target_type: ScheduledPaymentTarget", target,
+                   amount_sats, amount_kes, memo, run_at, cron, max_attempts, retry_after_minutes,
+                   status as "status: ScheduledPaymentStatus", occurrence_at, next_run_at, attempt,
+                   run_count, last_run_at, last_error, created_at, updated_at
+            FROM scheduled_payments
+            WHERE id = $1 AND user_id = $2
+            "#,
+            id,
+            user_id.0,
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.map(|r| ScheduledPayment {
+            id: r.id,
+            user_id: UserId(r.user_id),
+            target_type: r.target_type,
+            target: r.target,
+            amount_sats: r.amount_sats.map(SatAmount::new),
+            amount_kes: r.amount_kes.map(KesAmount::new),
+            memo: r.memo,
+            run_at: r.run_at,
+            cron: r.cron,
+            max_attempts: r.max_attempts,
+            retry_after_minutes: r.retry_after_minutes,
+            status: r.status,
+            occurrence_at: r.occurrence_at,
+            next_run_at: r.next_run_at,
+            attempt: r.attempt,
+            run_count: r.run_count,
+            last_run_at: r.last_run_at,
+            last_error: r.last_error,
+            created_at: r.created_at,
+            updated_at: r.updated_at,
+        }))
+    }
+
+    /// Active and paused payments first, then finished ones, newest first
+    #[instrument(skip(self))]
+    pub async fn list(&self, user_id: UserId) -> Result<Vec<ScheduledPayment>> {
+        let rows = sqlx::query!(
+            r#"
+            SELECT id, user_id, target_type as "target_type: ScheduledPaymentTarget", target,
+                   amount_sats, amount_kes, memo, run_at, cron, max_attempts, retry_after_minutes,
+                   status as "status: ScheduledPaymentStatus", occurrence_at, next_run_at, attempt,
+                   run_count, last_run_at, last_error, created_at, updated_at
+            FROM scheduled_payments
+            WHERE user_id = $1
+            ORDER BY status IN ('active', 'paused') DESC, created_at DESC
+            LIMIT 100
+            "#,
+            user_id.0,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|r| ScheduledPayment {
+                id: r.id,
+                user_id: UserId(r.user_id),
+                target_type: r.target_type,
+                target: r.target,
+                amount_sats: r.amount_sats.map(SatAmount::new),
+                amount_kes: r.amount_kes.map(KesAmount::new),
+                memo: r.memo,
+                run_at: r.run_at,
+                cron: r.cron,
+                max_attempts: r.max_attempts,
+                retry_after_minutes: r.retry_after_minutes,
+                status: r.status,
+                occurrence_at: r.occurrence_at,
+                next_run_at: r.next_run_at,
+                attempt: r.attempt,
+                run_count: r.run_count,
+                last_run_at: r.last_run_at,
+                last_error: r.last_error,
+                created_at: r.created_at,
+                updated_at: r.updated_at,
+            })
+            .collect())
+    }
+
+    /// Replace the instruction of an active or paused payment that isn't running
+    #[instrument(skip(self, spec))]
+    pub async fn update(&self, user_id: UserId, id: Uuid, spec: &ScheduleSpec, next_run: DateTime<Utc>) -> Result<bool> {
+        let (amount_sats, amount_kes) = spec.amount.fields();
+        let (run_at, cron) = spec.timing.fields();
+
+        let updated = sqlx::query!(
+            r#"
+            UPDATE scheduled_payments
+            SET target_type = $3, target = $4, amount_sats = $5, amount_kes = $6, memo = $7,
+                run_at = $8, cron = $9, max_attempts = $10, retry_after_minutes = $11,
+                occurrence_at = $12, next_run_at = $12, attempt = 0, last_error = NULL
+            WHERE id = $1 AND user_id = $2
+              AND status IN ('active', 'paused')
+              AND (lease_until IS NULL OR lease_until < NOW())
+            "#,
+            id,
+            user_id.0,
+            spec.target_type as _,
+            spec.target,
+            amount_sats,
+            amount_kes,
+            spec.memo,
+            run_at,
+            cron,
+            spec.max_attempts,
+            spec.retry_after_minutes,
+            next_run,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(updated.rows_affected() == 1)
+    }
+
+    #[instrument(skip(self))]
+    pub async fn pause(&self, user_id: UserId, id: Uuid) -> Result<bool> {
+        let updated = sqlx::query!(
+            r#"
+            UPDATE scheduled_payments SET status = 'paused'
+            WHERE id = $1 AND user_id = $2 AND status = 'active'
+              AND (lease_until IS NULL OR lease_until < NOW())
+            "#,
+            id,
+            user_id.0,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(updated.rows_affected() == 1)
+    }
+
+    /// Resume a paused payment; the current run starts over
+    #[instrument(skip(self))]
+    pub async fn resume(&self, user_id: UserId, id: Uuid, next_run: DateTime<Utc>) -> Result<bool> {
+        let updated = sqlx::query!(
+            r#"
+            UPDATE scheduled_payments
+            SET status = 'active', occurrence_at = $3, next_run_at = $3, attempt = 0
+            WHERE id = $1 AND user_id = $2 AND status = 'paused'
+            "#,
+            id,
+            user_id.0,
+            next_run,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(updated.rows_affected() == 1)
+    }
+
+    #[instrument(skip(self))]
+    pub async fn cancel(&self, user_id: UserId, id: Uuid) -> Result<bool> {
+        let updated = sqlx::query!(
+            r#"
+            UPDATE scheduled_payments SET status = 'cancelled', next_run_at = NULL
+            WHERE id = $1 AND user_id = $2 AND status IN ('active', 'paused')
+              AND (lease_until IS NULL OR lease_until < NOW())
+            "#,
+            id,
+            user_id.0,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(updated.rows_affected() == 1)
+    }
+
+    /// Lease active payments that are due
+    #[instrument(skip(self))]
+    pub async fn claim_due(&self, limit: i64, lease_until: DateTime<Utc>) -> Result<Vec<ScheduledPayment>> {
+        let rows = sqlx::query!(
+            r#"
+            UPDATE scheduled_payments
+            SET lease_until = $2
+            WHERE id IN (
+                SELECT id FROM scheduled_payments
+                WHERE status = 'active' AND next_run_at <= NOW()
+                  AND (lease_until IS NULL OR lease_until < NOW())
+                ORDER BY next_run_at
+                LIMIT $1
+                FOR UPDATE SKIP LOCKED
+            )
+            RETURNING id, user_id, target_type as "target_type: ScheduledPaymentTarget", target,
+                      amount_sats, amount_kes, memo, run_at, cron, max_attempts, retry_after_minutes,
+                      status as "status: ScheduledPaymentStatus", occurrence_at, next_run_at, attempt,
+                      run_count, last_run_at, last_error, created_at, updated_at
+            "#,
+            limit,
+            lease_until,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|r| ScheduledPayment {
+                id: r.id,
+                user_id: UserId(r.user_id),
+                target_type: r.target_type,
+                target: r.target,
+                amount_sats: r.amount_sats.map(SatAmount::new),
+                amount_kes: r.amount_kes.map(KesAmount::new),
+                memo: r.memo,
+                run_at: r.run_at,
+                cron: r.cron,
+                max_attempts: r.max_attempts,
+                retry_after_minutes: r.retry_after_minutes,
+                status: r.status,
+                occurrence_at: r.occurrence_at,
+                next_run_at: r.next_run_at,
+                attempt: r.attempt,
+                run_count: r.run_count,
+                last_run_at: r.last_run_at,
+                last_error: r.last_error,
+                created_at: r.created_at,
+                updated_at: r.updated_at,
+            })
+            .collect())
+    }
+
+    /// Record an attempt before making the payment
+    /// Returns `None` if the attempt was already recorded (another worker got there first)
+    #[instrument(skip(self))]
+    pub async fn start_run(&self, payment_id: Uuid, occurrence_at: DateTime<Utc>, attempt: i32) -> Result<Option<Uuid>> {
+        let row = sqlx::query!(
+            r#"
+            INSERT INTO scheduled_payment_runs (scheduled_payment_id, occurrence_at, attempt)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (scheduled_payment_id, occurrence_at, attempt) DO NOTHING
+            RETURNING id
+            "#,
+            payment_id,
+            occurrence_at,
+            attempt,
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.map(|r| r.id))
+    }
+
+    /// Mark attempts left unfinished by a stopped worker; returns how many there were
+    #[instrument(skip(self))]
+    pub async fn interrupt_unfinished_runs(&self, payment_id: Uuid, occurrence_at: DateTime<Utc>) -> Result<u64> {
+        let result = sqlx::query!(
+            r#"
+            UPDATE scheduled_payment_runs
+            SET status = 'interrupted', finished_at = NOW(), error = 'Stopped before the payment was confirmed'
+            WHERE scheduled_payment_id = $1 AND occurrence_at = $2 AND status = 'started'
+            "#,
+            payment_id,
+            occurrence_at,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    /// Mark an attempt whose payment may have gone out; it is never retried
+    #[instrument(skip(self))]
+    pub async fn interrupt_run(&self, run_id: Uuid, error: &str) -> Result<()> {
+        sqlx::query!(
+            "UPDATE scheduled_payment_runs SET status = 'interrupted', error = $2, finished_at = NOW() WHERE id = $1",
+            run_id,
+            error,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Run succeeded: move on to the next occurrence, or complete a one-off payment
+    #[instrument(skip(self))]
+    pub async fn record_success(
+        &self,
+        payment_id: Uuid,
+        run_id: Uuid,
+        transaction_id: Option<Uuid>,
+        next_run: Option<DateTime<Utc>>,
+    ) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query!(
+            r#"
+            UPDATE scheduled_payment_runs
+            SET status = 'succeeded', transaction_id = $2, finished_at = NOW()
+            WHERE id = $1
+            "#,
+            run_id,
+            transaction_id,
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query!(
+            r#"
+            UPDATE scheduled_payments
+            SET status = CASE WHEN $2::timestamptz IS NULL THEN 'completed'::scheduled_payment_status ELSE status END,
+                occurrence_at = $2, next_run_at = $2, attempt = 0, lease_until = NULL,
+                run_count = run_count + 1, last_run_at = NOW(), last_error = NULL
+            WHERE id = $1
+            "#,
+            payment_id,
+            next_run,
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Attempt failed but the policy allows another one
+    #[instrument(skip(self))]
+    pub async fn record_retry(
+        &self,
+        payment_id: Uuid,
+        run_id: Uuid,
+        attempt: i32,
+        error: &str,
+        retry_at: DateTime<Utc>,
+    ) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query!(
+            "UPDATE scheduled_payment_runs SET status = 'failed', error = $2, finished_at = NOW() WHERE id = $1",
+            run_id,
+            error,
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query!(
+            r#"
+            UPDATE scheduled_payments
+            SET attempt = $2, next_run_at = $3, lease_until = NULL, last_error = $4
+            WHERE id = $1
+            "#,
+            payment_id,
+            attempt,
+            retry_at,
+            error,
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Run failed for good: notify the user and move on to the next occurrence,
+    /// or mark a one-off payment failed
+    /// `run_id` is `None` when an interrupted run is given up without a new attempt
+    #[instrument(skip(self, payment))]
+    pub async fn record_failure(
+        &self,
+        payment: &ScheduledPayment,
+        run_id: Option<Uuid>,
+        attempts: i32,
+        error: &str,
+        next_run: Option<DateTime<Utc>>,
+    ) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+
+        if let Some(run_id) = run_id {
+            sqlx::query!(
+                "UPDATE scheduled_payment_runs SET status = 'failed', error = $2, finished_at = NOW() WHERE id = $1",
+                run_id,
+                error,
+            )
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        sqlx::query!(
+            r#"
+            UPDATE scheduled_payments
+            SET status = CASE WHEN $2::timestamptz IS NULL THEN 'failed'::scheduled_payment_status ELSE status END,
+                occurrence_at = $2, next_run_at = $2, attempt = 0, lease_until = NULL,
+                run_count = run_count + 1, last_run_at = NOW(), last_error = $3
+            WHERE id = $1
+            "#,
+            payment.id,
+            next_run,
+            error,
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        record_event(
+            &mut *tx,
+            &DomainEvent::ScheduledPaymentFailed {
+                scheduled_payment_id: payment.id,
+                user_id: payment.user_id,
+                target: payment.target.clone(),
+                attempts,
+                reason: error.to_string(),
+            },
+        )
+        .await?;
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    #[instrument(skip(self))]
+    pub async fn runs(&self, payment_id: Uuid, limit: i64) -> Result<Vec<ScheduledPaymentRun>> {
+        let rows = sqlx::query!(
+            r#"
+            SELECT id, occurrence_at, attempt, status as "status: ScheduledRunStatus",
+                   transaction_id, error, started_at, finished_at
+            FROM scheduled_payment_runs
+            WHERE scheduled_payment_id = $1
+            ORDER BY started_at DESC
+            LIMIT $2
+            "#,
+            payment_id,
+            limit,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|r| ScheduledPaymentRun {
+                id: r.id,
+                occurrence_at: r.occurrence_at,
+                attempt: r.attempt,
+                status: r.status,
+                transaction_id: r.transaction_id,
+                error: r.error,
+                started_at: r.started_at,
+                finished_at: r.finished_at,
+            })
+            .collect())
+    }
+
+    #[instrument(skip(self))]
+    pub async fn balance_sats(&self, user_id: UserId) -> Result<i64> {
+        let row = sqlx::query!("SELECT balance_sats FROM wallets WHERE user_id = $1", user_id.0)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        Ok(row.map(|r| r.balance_sats).unwrap_or(0))
+    }
+
+    /// Lightning username of the PesaBit user with a phone number
+    #[instrument(skip(self))]
+    pub async fn lightning_username(&self, phone_number: &str) -> Result<Option<String>> {
+        let row = sqlx::query!(
+            "SELECT lightning_username FROM users WHERE phone_number = $1 AND is_active",
+            phone_number,
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.map(|r| r.lightning_username))
+    }
+}
+
+/// Scheduling and running scheduled payments
+pub struct ScheduledPaymentService {
+    repository: Arc<ScheduledPaymentRepository>,
+    payment_service: Arc<PaymentService>,
+    limits_service: Arc<LimitsService>,
+    lnurl_client: Arc<LnurlClient>,
+}
+
+impl ScheduledPaymentService {
+    pub fn new(
+        repository: Arc<ScheduledPaymentRepository>,
+        payment_service: Arc<PaymentService>,
+        limits_service: Arc<LimitsService>,
+        lnurl_client: Arc<LnurlClient>,
+    ) -> Self {
+        Self {
+            repository,
+            payment_service,
+            limits_service,
+            lnurl_client,
+        }
+    }
+
+    #[instrument(skip(self, request))]
+    pub async fn create(&self, user_id: UserId, request: CreateScheduledPaymentRequest) -> Result<ScheduledPayment> {
+        request.validate().map_err(|e| AppError::Validation {
+            message: format!("Invalid scheduled payment: {}", e),
+        })?;
+
+        let spec = request.spec()?;
+        let first_run = spec.timing.first_run(Utc::now())?;
+        let id = self.repository.create(user_id, &spec, first_run).await?;
+
+        info!("User {} scheduled payment {} first running at {}", user_id, id, first_run);
+        self.find(user_id, id).await
+    }
+
+    #[instrument(skip(self))]
+    pub async fn get(&self, user_id: UserId, id: Uuid) -> Result<ScheduledPaymentDetails> {
+        let payment = self.find(user_id, id).await?;
+        let recent_runs = self.repository.runs(id, RECENT_RUNS).await?;
+        Ok(ScheduledPaymentDetails { payment, recent_runs })
+    }
+
+    #[instrument(skip(self))]
+    pub async fn list(&self, user_id: UserId) -> Result<Vec<ScheduledPayment>> {
+        self.repository.list(user_id).await
+    }
+
+    /// Edit an active or paused payment; its next run is worked out again
+    #[instrument(skip(self, request))]
+    pub async fn update(&self, user_id: UserId, id: Uuid, request: UpdateScheduledPaymentRequest) -> Result<ScheduledPayment> {
+        request.validate().map_err(|e| AppError::Validation {
+            message: format!("Invalid scheduled payment: {}", e),
+        })?;
+
+        let current = self.find(user_id, id).await?;
+        let spec = request.apply(&current.spec()?)?;
+        let next_run = spec.timing.first_run(Utc::now())?;
+
+        if !self.repository.update(user_id, id, &spec, next_run).await? {
+            return Err(Self::not_changeable(&current));
+        }
+        self.find(user_id, id).await
+    }
+
+    #[instrument(skip(self))]
+    pub async fn pause(&self, user_id: UserId, id: Uuid) -> Result<ScheduledPayment> {
+        let current = self.find(user_id, id).await?;
+        if !self.repository.pause(user_id, id).await? {
+            return Err(Self::not_changeable(&current));
+        }
+        self.find(user_id, id).await
+    }
+
+    #[instrument(skip(self))]
+    pub async fn resume(&self, user_id: UserId, id: Uuid) -> Result<ScheduledPayment> {
+        let current = self.find(user_id, id).await?;
+        let next_run = current.spec()?.timing.resume_at(Utc::now()).ok_or_else(|| AppError::Validation {
+            message: "Schedule never runs".to_string(),
+        })?;
+
+        if !self.repository.resume(user_id, id, next_run).await? {
+            return Err(AppError::Conflict {
+                message: "Only paused payments can be resumed".to_string(),
+            });
+        }
+        self.find(user_id, id).await
+    }
+
+    #[instrument(skip(self))]
+    pub async fn cancel(&self, user_id: UserId, id: Uuid) -> Result<ScheduledPayment> {
+        let current = self.find(user_id, id).await?;
+        if !self.repository.cancel(user_id, id).await? {
+            return Err(Self::not_changeable(&current));
+        }
+        self.find(user_id, id).await
+    }
+
+    /// Run payments that are due; returns how many were attempted
+    #[instrument(skip(self))]
+    pub async fn run_due(&self, limit: i64) -> Result<usize> {
+        let lease_until = Utc::now() + Duration::minutes(RUN_LEASE_MINUTES);
+        let payments = self.repository.claim_due(limit, lease_until).await?;
+
+        for payment in &payments {
+            if let Err(e) = self.run(payment).await {
+                warn!("Failed to run scheduled payment {}: {}", payment.id, e);
+            }
+        }
+
+        Ok(payments.len())
+    }
+
+    /// Make one attempt of a leased payment and record the outcome
+    async fn run(&self, payment: &ScheduledPayment) -> Result<()> {
+        let now = Utc::now();
+        let spec = payment.spec()?;
+        let occurrence_at = payment
+            .occurrence_at
+            .or(payment.next_run_at)
+            .ok_or_else(|| AppError::Internal(anyhow::anyhow!("Scheduled payment {} has no run time", payment.id)))?;
+        let next_run = spec.timing.next_after(occurrence_at, now);
+
+        // The payment may have gone through before the worker stopped: don't risk paying twice
+        if self.repository.interrupt_unfinished_runs(payment.id, occurrence_at).await? > 0 {
+            warn!("Scheduled payment {} was interrupted, skipping this run", payment.id);
+            let error = "The payment was interrupted. Check your transactions before paying again.";
+            return self
+                .repository
+                .record_failure(payment, None, payment.attempt, error, next_run)
+                .await;
+        }
+
+        let attempt = payment.attempt + 1;
+        let Some(run_id) = self.repository.start_run(payment.id, occurrence_at, attempt).await? else {
+            return Ok(());
+        };
+
+        match self.execute(payment, &spec).await {
+            Ok(transaction_id) => {
+                info!("Scheduled payment {} ran (attempt {})", payment.id, attempt);
+                self.repository
+                    .record_success(payment.id, run_id, transaction_id, next_run)
+                    .await
+            }
+            Err(RunError::Unconfirmed(e)) => {
+                error!("Scheduled payment {} attempt {} may have paid, not retrying: {}", payment.id, attempt, e);
+                self.repository.interrupt_run(run_id, &e.to_string()).await?;
+                let error = "The payment may have gone through. Check your transactions before paying again.";
+                self.repository
+                    .record_failure(payment, None, attempt, error, next_run)
+                    .await
+            }
+            Err(RunError::NotSent(e)) => {
+                warn!("Scheduled payment {} attempt {} failed: {}", payment.id, attempt, e);
+                let error = e.user_message();
+                // Invalid instructions won't get better by waiting
+                let retry = attempt < payment.max_attempts && !matches!(e, AppError::Validation { .. });
+
+                if retry {
+                    let retry_at = now + Duration::minutes(payment.retry_after_minutes as i64);
+                    self.repository
+                        .record_retry(payment.id, run_id, attempt, &error, retry_at)
+                        .await
+                } else {
+                    self.repository
+                        .record_failure(payment, Some(run_id), attempt, &error, next_run)
+                        .await
+                }
+            }
+        }
+    }
+
+    /// Check balance and limits, then make the payment
+    async fn execute(&self, payment: &ScheduledPayment, spec: &ScheduleSpec) -> std::result::Result<Option<Uuid>, RunError> {
+        let amount_sats = self.prepare(payment, spec).await.map_err(RunError::NotSent)?;
+
+        // The limits lock is held until the payment is recorded; failing to
+        // take it or a limit rejection means nothing was sent
+        self.limits_service
+            .within_sats(payment.user_id, amount_sats, || async {
+                Ok(self.pay(payment.user_id, spec, amount_sats).await)
+            })
+            .await
+            .map_err(RunError::NotSent)?
+    }
+
+    /// Amount to pay once the balance allows it; nothing is paid yet
+    async fn prepare(&self, payment: &ScheduledPayment, spec: &ScheduleSpec) -> Result<SatAmount> {
+        let user_id = payment.user_id;
+        let amount_sats = match spec.amount {
+            ScheduledAmount::Sats(sats) => sats,
+            ScheduledAmount::Kes(kes) => self
+                .limits_service
+                .current_rate()
+                .await?
+                .kes_to_sats(&kes, Side::Charge, Rounding::HouseFavourable)?,
+        };
+
+        let balance = self.repository.balance_sats(user_id).await?;
+        if balance < amount_sats.as_i64() {
+            return Err(AppError::Payment {
+                message: format!(
+                    "Insufficient balance: {} sats needed, {} sats available",
+                    amount_sats.as_i64(),
+                    balance
+                ),
+            });
+        }
+        Ok(amount_sats)
+    }
+
+    /// Make the payment
+    async fn pay(
+        &self,
+        user_id: UserId,
+        spec: &ScheduleSpec,
+        amount_sats: SatAmount,
+    ) -> std::result::Result<Option<Uuid>, RunError> {
+        let transaction_id = match spec.target_type {
+            ScheduledPaymentTarget::MpesaWithdrawal => {
+                let request = MpesaWithdrawalRequest {
+                    amount_sats: amount_sats.as_i64(),
+                    recipient_phone: Some(spec.target.clone()),
+                };
+                request.validate().map_err(|e| {
+                    RunError::NotSent(AppError::Validation {
+                        message: format!("Invalid withdrawal: {}", e),
+                    })
+                })?;
+                self.payment_service
+                    .initiate_mpesa_withdrawal(user_id, request)
+                    .await
+                    .map_err(RunError::from_payment)?
+                    .transaction_id
+            }
+            ScheduledPaymentTarget::LightningAddress => {
+                self.pay_lightning_address(user_id, &spec.target, amount_sats, spec.memo.as_deref())
+                    .await?
+            }
+            ScheduledPaymentTarget::PhoneNumber => {
+                let username = self
+                    .repository
+                    .lightning_username(&spec.target)
+                    .await
+                    .map_err(RunError::NotSent)?
+                    .ok_or_else(|| {
+                        RunError::NotSent(AppError::Validation {
+                            message: format!("No PesaBit account uses {}", spec.target),
+                        })
+                    })?;
+                let address = LightningAddress::new(&username, LIGHTNING_ADDRESS_DOMAIN);
+                self.pay_lightning_address(user_id, &address.0, amount_sats, spec.memo.as_deref())
+                    .await?
+            }
+        };
+
+        Ok(transaction_id.parse().ok())
+    }
+
+    async fn pay_lightning_address(
+        &self,
+        user_id: UserId,
+        address: &str,
+        amount_sats: SatAmount,
+        memo: Option<&str>,
+    ) -> std::result::Result<String, RunError> {
+        let invoice = self
+            .lnurl_client
+            .fetch_invoice(address, amount_sats, memo)
+            .await
+            .map_err(RunError::NotSent)?;
+        let response = self
+            .payment_service
+            .pay_lightning_invoice(
+                user_id,
+                PayInvoiceRequest {
+                    bolt11_invoice: invoice,
+                    max_fee_sats: None,
+                },
+            )
+            .await
+            .map_err(RunError::from_payment)?;
+
+        // A payment that failed outright was refunded, so it can be tried again
+        if response.status == TransactionStatus::Failed {
+            return Err(RunError::NotSent(AppError::Lightning {
+                message: response
+                    .failure_reason
+                    .unwrap_or_else(|| "Lightning payment failed".to_string()),
+            }));
+        }
+        Ok(response.transaction_id)
+    }
+
+    async fn find(&self, user_id: UserId, id: Uuid) -> Result<ScheduledPayment> {
+        self.repository
+            .get(user_id, id)
+            .await?
+            .ok_or_else(|| AppError::Payment {
+                message: "Scheduled payment not found".to_string(),
+            })
+    }
+
+    fn not_changeable(payment: &ScheduledPayment) -> AppError {
+        let message = match payment.status {
+            ScheduledPaymentStatus::Active | ScheduledPaymentStatus::Paused => {
+                "The payment is running right now, try again in a few minutes".to_string()
+            }
+            _ => "Finished payments can't be changed".to_string(),
+        };
+        AppError::Conflict { message }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn request(json: serde_json::Value) -> CreateScheduledPaymentRequest {
+        serde_json::from_value(json).unwrap()
+    }
+
+    #[test]
+    fn test_create_request_validation() {
+        let spec = request(serde_json::json!({
+            "target_type": "lightning_address",
+            "target": " Landlord@Pesa.co.ke ",
+            "amount_kes": "25000",
+            "cron": "0  8 1 * *",
+            "memo": "Rent",
+        }))
+        .spec()
+        .unwrap();
+        assert_eq!(spec.target, "landlord@pesa.co.ke");
+        assert_eq!(spec.amount, ScheduledAmount::Kes(KesAmount::new(Decimal::from(25000))));
+        assert_eq!(spec.timing.fields().1.as_deref(), Some("0 8 1 * *"));
+        assert_eq!((spec.max_attempts, spec.retry_after_minutes), (DEFAULT_MAX_ATTEMPTS, DEFAULT_RETRY_AFTER_MINUTES));
+
+        let invalid = [
+            // Both amounts
+            serde_json::json!({"target_type": "mpesa_withdrawal", "target": "+254712345678", "amount_sats": 5000, "amount_kes": "100", "cron": "0 8 * * *"}),
+            // No schedule
+            serde_json::json!({"target_type": "mpesa_withdrawal", "target": "+254712345678", "amount_sats": 5000}),
+            // Phone number that isn't E.164
+            serde_json::json!({"target_type": "phone_number", "target": "0712345678", "amount_sats": 5000, "cron": "0 8 * * *"}),
+            // Every minute
+            serde_json::json!({"target_type": "lightning_address", "target": "a@b.co", "amount_sats": 5000, "cron": "* * * * *"}),
+        ];
+        for json in invalid {
+            assert!(request(json.clone()).spec().is_err(), "{} should be rejected", json);
+        }
+    }
+
+    #[test]
+    fn test_update_keeps_unchanged_fields() {
+        let current = request(serde_json::json!({
+            "target_type": "mpesa_withdrawal",
+            "target": "+254712345678",
+            "amount_sats": 50000,
+            "cron": "0 18 * * 5",
+            "memo": "Allowance",
+            "max_attempts": 2,
+        }))
+        .spec()
+        .unwrap();
+
+        let update: UpdateScheduledPaymentRequest =
+            serde_json::from_value(serde_json::json!({"amount_kes": "1500"})).unwrap();
+        let updated = update.apply(&current).unwrap();
+        assert_eq!(updated.amount, ScheduledAmount::Kes(KesAmount::new(Decimal::from(1500))));
+        assert_eq!(updated.timing, current.timing);
+        assert_eq!(updated.memo.as_deref(), Some("Allowance"));
+        assert_eq!(updated.max_attempts, 2);
+
+        // Switching to a Lightning address re-validates the existing phone number target
+        let update: UpdateScheduledPaymentRequest =
+            serde_json::from_value(serde_json::json!({"target_type": "lightning_address"})).unwrap();
+        assert!(update.apply(&current).is_err());
+    }
+
+    #[test]
+    fn test_timing() {
+        let now = Utc.with_ymd_and_hms(2026, 3, 10, 12, 0, 0).unwrap();
+
+        let once = Timing::Once(now + Duration::days(2));
+        assert_eq!(once.first_run(now).unwrap(), now + Duration::days(2));
+        assert!(Timing::Once(now - Duration::minutes(1)).first_run(now).is_err());
+        assert_eq!(once.next_after(now + Duration::days(2), now), None);
+        // Overdue one-off payments run as soon as they are resumed
+        assert_eq!(Timing::Once(now - Duration::days(1)).resume_at(now), Some(now));
+
+        // Missed occurrences are skipped rather than caught up
+        let daily = Timing::Recurring(CronSchedule::parse("0 9 * * *").unwrap());
+        let three_days_ago = Utc.with_ymd_and_hms(2026, 3, 7, 6, 0, 0).unwrap();
+        assert_eq!(
+            daily.next_after(three_days_ago, now),
+            Some(Utc.with_ymd_and_hms(2026, 3, 11, 6, 0, 0).unwrap())
+        );
+    }
+}
@@ -0,0 +1,1198 @@
+/// Account statements
+///
+/// A statement lists every transaction created in a period with its fees and
+/// the exchange rate used, the sats balance after each one, and totals. Balances
+/// are rebuilt backwards from the current wallet balance using the database's
+/// `transaction_balance_effect`. Periods of up to a month are generated while the
+/// user waits; longer ones are queued for the background worker. Files are
+/// downloaded through signed links that expire.
+
+use chrono::{DateTime, Duration, Utc};
+use hmac::{Hmac, Mac};
+use printpdf::{BuiltinFont, IndirectFontRef, Mm, PdfDocument, PdfDocumentReference, PdfLayerReference};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use shared_errors::{AppError, Result};
+use shared_types::*;
+use sqlx::PgPool;
+use std::sync::Arc;
+use tracing::{info, instrument, warn};
+use uuid::Uuid;
+
+/// Longest period generated while the user waits
+const INLINE_MAX_DAYS: i64 = 31;
+/// Longest period a statement can cover
+const MAX_PERIOD_DAYS: i64 = 366;
+/// How long generated files are kept
+const RETENTION_DAYS: i64 = 7;
+/// How long a download link works (never beyond the file's retention)
+const LINK_TTL_HOURS: i64 = 24;
+/// How long a worker may take before another one picks the statement up
+const GENERATION_LEASE_MINUTES: i64 = 10;
+/// Attempts before a statement is marked failed
+const MAX_ATTEMPTS: i32 = 3;
+
+/// File format of a statement
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[sqlx(type_name = "statement_format", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum StatementFormat {
+    Csv,
+    Pdf,
+}
+
+impl StatementFormat {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            StatementFormat::Csv => "text/csv; charset=utf-8",
+            StatementFormat::Pdf => "application/pdf",
+        }
+    }
+
+    fn extension(&self) -> &'static str {
+        match self {
+            StatementFormat::Csv => "csv",
+            StatementFormat::Pdf => "pdf",
+        }
+    }
+}
+
+/// Generation state of a statement
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[sqlx(type_name = "statement_status", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum StatementStatus {
+    Pending,
+    Generating,
+    Ready,
+    Failed,
+    Expired,
+}
+
+/// Request for a new statement
+#[derive(Debug, Deserialize)]
+pub struct CreateStatementRequest {
+    pub format: StatementFormat,
+    /// Start of the period (inclusive)
+    pub from_date: DateTime<Utc>,
+    /// End of the period (exclusive, capped at now)
+    pub to_date: DateTime<Utc>,
+}
+
+impl CreateStatementRequest {
+    /// Validated period
+    pub fn period(&self, now: DateTime<Utc>) -> Result<(DateTime<Utc>, DateTime<Utc>)> {
+        let to = self.to_date.min(now);
+
+        if self.from_date >= to {
+            return Err(AppError::Validation {
+                message: "from_date must be before to_date and in the past".to_string(),
+            });
+        }
+        if to - self.from_date > Duration::days(MAX_PERIOD_DAYS) {
+            return Err(AppError::Validation {
+                message: format!("Statements can cover at most {} days", MAX_PERIOD_DAYS),
+            });
+        }
+
+        Ok((self.from_date, to))
+    }
+}
+
+/// Statement as stored (without the file)
+#[derive(Debug, Clone)]
+pub struct StatementRecord {
+    pub id: Uuid,
+    pub user_id: UserId,
+    pub format: StatementFormat,
+    pub period_start: DateTime<Utc>,
+    pub period_end: DateTime<Utc>,
+    pub status: StatementStatus,
+    pub transaction_count: Option<i32>,
+    pub created_at: DateTime<Utc>,
+    pub ready_at: Option<DateTime<Utc>>,
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+/// Statement as returned to the user
+#[derive(Debug, Clone, Serialize)]
+pub struct StatementResponse {
+    pub id: Uuid,
+    pub format: StatementFormat,
+    pub period_start: DateTime<Utc>,
+    pub period_end: DateTime<Utc>,
+    pub status: StatementStatus,
+    pub transaction_count: Option<i32>,
+    pub created_at: DateTime<Utc>,
+    pub ready_at: Option<DateTime<Utc>>,
+    /// When the file is deleted
+    pub expires_at: Option<DateTime<Utc>>,
+    /// Signed link to the file (only when ready)
+    pub download_url: Option<String>,
+    pub download_url_expires_at: Option<DateTime<Utc>>,
+}
+
+/// Query of a signed download link
+#[derive(Debug, Deserialize)]
+pub struct StatementDownloadParams {
+    pub expires: i64,
+    pub signature: String,
+}
+
+/// Generated file
+#[derive(Debug)]
+pub struct StatementFile {
+    pub format: StatementFormat,
+    pub file_name: String,
+    pub content: Vec<u8>,
+}
+
+/// Holder of the account, shown at the top of the statement
+#[derive(Debug, Clone)]
+pub struct StatementAccount {
+    pub full_name: Option<String>,
+    pub phone_number: String,
+}
+
+/// Transaction as listed on a statement
+#[derive(Debug, Clone)]
+pub struct StatementEntry {
+    pub id: Uuid,
+    pub created_at: DateTime<Utc>,
+    pub transaction_type: TransactionType,
+    pub status: TransactionStatus,
+    /// Mobile money network, for deposits and withdrawals
+    pub provider: Option<MobileMoneyNetwork>,
+    pub description: Option<String>,
+    /// Mobile money receipt (M-Pesa code or Airtel Money ID)
+    pub mpesa_code: Option<String>,
+    pub amount_kes: Option<Decimal>,
+    pub amount_sats: Option<i64>,
+    pub fee_kes: Option<Decimal>,
+    pub fee_sats: Option<i64>,
+    /// KES per BTC used for the transaction
+    pub exchange_rate: Option<Decimal>,
+    /// Change to the sats balance
+    pub balance_effect_sats: i64,
+}
+
+/// Entry with the balance after it
+#[derive(Debug, Clone)]
+pub struct StatementLine {
+    pub entry: StatementEntry,
+    pub balance_sats: i64,
+}
+
+/// Totals over the period
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StatementTotals {
+    pub money_in_sats: i64,
+    pub money_out_sats: i64,
+    /// Fees kept by PesaBit (fees of failed and refunded transactions are returned)
+    pub fees_sats: i64,
+    pub fees_kes: Decimal,
+    pub transaction_count: usize,
+}
+
+/// Contents of a statement, ready to render
+#[derive(Debug, Clone)]
+pub struct Statement {
+    pub account: StatementAccount,
+    pub period_start: DateTime<Utc>,
+    pub period_end: DateTime<Utc>,
+    pub generated_at: DateTime<Utc>,
+    pub opening_balance_sats: i64,
+    pub closing_balance_sats: i64,
+    pub lines: Vec<StatementLine>,
+    pub totals: StatementTotals,
+}
+
+impl Statement {
+    /// Running balances and totals from the opening balance and the period's entries (oldest first)
+    pub fn build(
+        account: StatementAccount,
+        period_start: DateTime<Utc>,
+        period_end: DateTime<Utc>,
+        opening_balance_sats: i64,
+        entries: Vec<StatementEntry>,
+        generated_at: DateTime<Utc>,
+    ) -> Self {
+        let mut balance = opening_balance_sats;
+        let mut totals = StatementTotals {
+            transaction_count: entries.len(),
+            ..Default::default()
+        };
+
+        let lines = entries
+            .into_iter()
+            .map(|entry| {
+                balance += entry.balance_effect_sats;
+                if entry.balance_effect_sats > 0 {
+                    totals.money_in_sats += entry.balance_effect_sats;
+                } else {
+                    totals.money_out_sats -= entry.balance_effect_sats;
+                }
+                if !matches!(entry.status, TransactionStatus::Failed | TransactionStatus::Refunded) {
+                    totals.fees_sats += entry.fee_sats.unwrap_or(0);
+                    totals.fees_kes += entry.fee_kes.unwrap_or(Decimal::ZERO);
+                }
+                StatementLine { entry, balance_sats: balance }
+            })
+            .collect();
+
+        Self {
+            account,
+            period_start,
+            period_end,
+            generated_at,
+            opening_balance_sats,
+            closing_balance_sats: balance,
+            lines,
+            totals,
+        }
+    }
+
+    /// e.g. `pesabit-statement-2026-01-01-to-2026-03-31.pdf` (end date inclusive)
+    pub fn file_name(&self, format: StatementFormat) -> String {
+        format!(
+            "pesabit-statement-{}-to-{}.{}",
+            self.period_start.format("%Y-%m-%d"),
+            (self.period_end - Duration::seconds(1)).format("%Y-%m-%d"),
+            format.extension()
+        )
+    }
+
+    pub fn render(&self, format: StatementFormat) -> Result<Vec<u8>> {
+        match format {
+            StatementFormat::Csv => render_csv(self),
+            StatementFormat::Pdf => render_pdf(self),
+        }
+    }
+}
+
+/// Name of an entry's transaction type on statements
+fn type_label(entry: &StatementEntry) -> &'static str {
+    match (&entry.transaction_type, entry.provider) {
+        (TransactionType::DepositMpesa, Some(MobileMoneyNetwork::AirtelMoney)) => "Airtel Money deposit",
+        (TransactionType::WithdrawalMpesa, Some(MobileMoneyNetwork::AirtelMoney)) => "Airtel Money withdrawal",
+        (TransactionType::DepositMpesa, _) => "M-Pesa deposit",
+        (TransactionType::WithdrawalMpesa, _) => "M-Pesa withdrawal",
+        (TransactionType::LightningSend, _) => "Lightning payment sent",
+        (TransactionType::LightningReceive, _) => "Lightning payment received",
+        (TransactionType::Refund, _) => "Refund",
+        (TransactionType::AirtimePurchase, _) => "Airtime",
+        (TransactionType::VaultDeposit, _) => "Moved to vault",
+        (TransactionType::VaultWithdrawal, _) => "Moved from vault",
+    }
+}
+
+fn optional<T: ToString>(value: Option<T>) -> String {
+    value.map(|v| v.to_string()).unwrap_or_default()
+}
+
+fn kes(value: Option<Decimal>) -> String {
+    value.map(|v| format!("{:.2}", v)).unwrap_or_default()
+}
+
+fn render_csv(statement: &Statement) -> Result<Vec<u8>> {
+    let csv_error = |e: csv::Error| AppError::Internal(anyhow::anyhow!("Failed to write CSV statement: {}", e));
+    let mut writer = csv::WriterBuilder::new().flexible(true).from_writer(Vec::new());
+
+    let summary = [
+        vec!["PesaBit account statement".to_string()],
+        vec!["Account holder".to_string(), optional(statement.account.full_name.as_deref())],
+        vec!["Phone number".to_string(), statement.account.phone_number.clone()],
+        vec!["Period start".to_string(), statement.period_start.to_rfc3339()],
+        vec!["Period end".to_string(), statement.period_end.to_rfc3339()],
+        vec!["Generated at".to_string(), statement.generated_at.to_rfc3339()],
+        vec!["Opening balance (sats)".to_string(), statement.opening_balance_sats.to_string()],
+    ];
+    for record in &summary {
+        writer.write_record(record).map_err(csv_error)?;
+    }
+
+    writer
+        .write_record([
+            "Date",
+            "Transaction ID",
+            "Type",
+            "Status",
+            "Description",
+            "Receipt",
+            "Amount (KES)",
+            "Amount (sats)",
+            "Fee (KES)",
+            "Fee (sats)",
+            "Exchange rate (KES/BTC)",
+            "Change (sats)",
+            "Balance (sats)",
+        ])
+        .map_err(csv_error)?;
+
+    for line in &statement.lines {
+        let entry = &line.entry;
+        writer
+            .write_record([
+                entry.created_at.to_rfc3339(),
+                entry.id.to_string(),
+                type_label(entry).to_string(),
+                entry.status.as_str().to_string(),
+                optional(entry.description.as_deref()),
+                optional(entry.mpesa_code.as_deref()),
+                kes(entry.amount_kes),
+                optional(entry.amount_sats),
+                kes(entry.fee_kes),
+                optional(entry.fee_sats),
+                kes(entry.exchange_rate),
+                entry.balance_effect_sats.to_string(),
+                line.balance_sats.to_string(),
+            ])
+            .map_err(csv_error)?;
+    }
+
+    let totals = &statement.totals;
+    let footer = [
+        ["Money in (sats)".to_string(), totals.money_in_sats.to_string()],
+        ["Money out (sats)".to_string(), totals.money_out_sats.to_string()],
+        ["Fees (sats)".to_string(), totals.fees_sats.to_string()],
+        ["Fees (KES)".to_string(), format!("{:.2}", totals.fees_kes)],
+        ["Transactions".to_string(), totals.transaction_count.to_string()],
+        ["Closing balance (sats)".to_string(), statement.closing_balance_sats.to_string()],
+    ];
+    for record in &footer {
+        writer.write_record(record).map_err(csv_error)?;
+    }
+
+    writer
+        .into_inner()
+        .map_err(|e| AppError::Internal(anyhow::anyhow!("Failed to write CSV statement: {}", e)))
+}
+
+/// A4 landscape so the table fits on one line per transaction
+const PAGE_WIDTH_MM: f32 = 297.0;
+const PAGE_HEIGHT_MM: f32 = 210.0;
+const MARGIN_MM: f32 = 12.0;
+const LINE_HEIGHT_MM: f32 = 4.2;
+const FONT_SIZE: f32 = 7.5;
+
+/// Text lines laid out top to bottom over as many pages as needed
+struct PdfPages {
+    doc: PdfDocumentReference,
+    regular: IndirectFontRef,
+    bold: IndirectFontRef,
+    layer: PdfLayerReference,
+    y: f32,
+    /// Repeated at the top of every new page
+    table_header: Option<String>,
+}
+
+impl PdfPages {
+    fn new(title: &str) -> Result<Self> {
+        let (doc, page, layer) = PdfDocument::new(title, Mm(PAGE_WIDTH_MM), Mm(PAGE_HEIGHT_MM), "Statement");
+        let font_error = |e| AppError::Internal(anyhow::anyhow!("Failed to load PDF font: {}", e));
+        // Courier keeps the table columns aligned
+        let regular = doc.add_builtin_font(BuiltinFont::Courier).map_err(font_error)?;
+        let bold = doc.add_builtin_font(BuiltinFont::CourierBold).map_err(font_error)?;
+        let layer = doc.get_page(page).get_layer(layer);
+
+        Ok(Self {
+            doc,
+            regular,
+            bold,
+            layer,
+            y: PAGE_HEIGHT_MM - MARGIN_MM,
+            table_header: None,
+        })
+    }
+
+    fn line(&mut self, text: &str, bold: bool) {
+        if self.y < MARGIN_MM {
+            let (page, layer) = self.doc.add_page(Mm(PAGE_WIDTH_MM), Mm(PAGE_HEIGHT_MM), "Statement");
+            self.layer = self.doc.get_page(page).get_layer(layer);
+            self.y = PAGE_HEIGHT_MM - MARGIN_MM;
+            if let Some(header) = self.table_header.clone() {
+                self.line(&header, true);
+            }
+        }
+
+        // Built-in fonts only cover Latin-1
+        let text: String = text.chars().map(|c| if c.is_ascii() { c } else { '?' }).collect();
+        let font = if bold { &self.bold } else { &self.regular };
+        self.layer.use_text(text, FONT_SIZE, Mm(MARGIN_MM), Mm(self.y), font);
+        self.y -= LINE_HEIGHT_MM;
+    }
+
+    fn blank(&mut self) {
+        self.y -= LINE_HEIGHT_MM;
+    }
+
+    fn finish(self) -> Result<Vec<u8>> {
+        self.doc
+            .save_to_bytes()
+            .map_err(|e| AppError::Internal(anyhow::anyhow!("Failed to write PDF statement: {}", e)))
+    }
+}
+
+/// Cut or pad text to a column width
+fn column(text: &str, width: usize) -> String {
+    let text: String = text.chars().take(width).collect();
+    format!("{:<width$}", text, width = width)
+}
+
+/// 1234567 → "1,234,567"
+fn grouped(value: i64) -> String {
+    let digits = value.unsigned_abs().to_string();
+    let mut out = String::new();
+    for (i, digit) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i) % 3 == 0 {
+            out.push(',');
+        }
+        out.push(digit);
+    }
+    if value < 0 {
+        out.insert(0, '-');
+    }
+    out
+}
+
+fn table_row(line: &StatementLine) -> String {
+    let entry = &line.entry;
+    format!(
+        "{} {} {} {} {:>12} {:>12} {:>9} {:>8} {:>13} {:>12} {:>13}",
+        column(&entry.created_at.format("%Y-%m-%d %H:%M").to_string(), 16),
+        column(type_label(entry), 26),
+        column(entry.status.as_str(), 10),
+        column(entry.description.as_deref().unwrap_or(""), 24),
+        kes(entry.amount_kes),
+        entry.amount_sats.map(grouped).unwrap_or_default(),
+        kes(entry.fee_kes),
+        entry.fee_sats.map(grouped).unwrap_or_default(),
+        kes(entry.exchange_rate),
+        grouped(entry.balance_effect_sats),
+        grouped(line.balance_sats),
+    )
+}
+
+fn render_pdf(statement: &Statement) -> Result<Vec<u8>> {
+    let mut pages = PdfPages::new("PesaBit account statement")?;
+
+    pages.line("PesaBit account statement", true);
+    pages.blank();
+    pages.line(&format!("Account holder:  {}", statement.account.full_name.as_deref().unwrap_or("-")), false);
+    pages.line(&format!("Phone number:    {}", statement.account.phone_number), false);
+    pages.line(
+        &format!(
+            "Period:          {} to {} (UTC)",
+            statement.period_start.format("%Y-%m-%d %H:%M"),
+            statement.period_end.format("%Y-%m-%d %H:%M")
+        ),
+        false,
+    );
+    pages.line(&format!("Generated at:    {} UTC", statement.generated_at.format("%Y-%m-%d %H:%M")), false);
+    pages.line(&format!("Opening balance: {} sats", grouped(statement.opening_balance_sats)), true);
+    pages.blank();
+
+    let header = format!(
+        "{} {} {} {} {:>12} {:>12} {:>9} {:>8} {:>13} {:>12} {:>13}",
+        column("Date", 16),
+        column("Type", 26),
+        column("Status", 10),
+        column("Description", 24),
+        "Amount KES",
+        "Amount sats",
+        "Fee KES",
+        "Fee sats",
+        "Rate KES/BTC",
+        "Change sats",
+        "Balance sats",
+    );
+    pages.line(&header, true);
+    pages.table_header = Some(header);
+
+    if statement.lines.is_empty() {
+        pages.line("No transactions in this period", false);
+    }
+    for line in &statement.lines {
+        pages.line(&table_row(line), false);
+    }
+    pages.table_header = None;
+    pages.blank();
+
+    let totals = &statement.totals;
+    pages.line(&format!("Transactions:    {}", totals.transaction_count), false);
+    pages.line(&format!("Money in:        {} sats", grouped(totals.money_in_sats)), false);
+    pages.line(&format!("Money out:       {} sats", grouped(totals.money_out_sats)), false);
+    pages.line(&format!("Fees:            {} sats, KES {:.2}", grouped(totals.fees_sats), totals.fees_kes), false);
+    pages.line(&format!("Closing balance: {} sats", grouped(statement.closing_balance_sats)), true);
+
+    pages.finish()
+}
+
+/// Signature of a download link
+pub fn sign_download(secret: &str, statement_id: Uuid, expires: i64) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(statement_id.as_bytes());
+    mac.update(&expires.to_be_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+/// Check a download link's signature and expiry
+pub fn verify_download(
+    secret: &str,
+    statement_id: Uuid,
+    params: &StatementDownloadParams,
+    now: DateTime<Utc>,
+) -> Result<()> {
+    let invalid = || AppError::Auth {
+        message: "Download link is invalid or has expired".to_string(),
+    };
+
+    let signature = hex::decode(&params.signature).map_err(|_| invalid())?;
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(statement_id.as_bytes());
+    mac.update(&params.expires.to_be_bytes());
+    mac.verify_slice(&signature).map_err(|_| invalid())?;
+
+    if params.expires <= now.timestamp() {
+        return Err(invalid());
+    }
+    Ok(())
+}
+
+/// Database access for statements
+pub struct StatementRepository {
+    pool: PgPool,
+}
+
+impl StatementRepository {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+
+    /// Store a new statement
+    /// Statements generated inline start out leased so the worker leaves them alone
+    #[instrument(skip(self))]
+    pub async fn create(
+        &self,
+        user_id: UserId,
+        format: StatementFormat,
+        period: (DateTime<Utc>, DateTime<Utc>),
+        lease_until: Option<DateTime<Utc>>,
+    ) -> Result<StatementRecord> {
+        let status = match lease_until {
+            Some(_) => StatementStatus::Generating,
+            None => StatementStatus::Pending,
+        };
+
+        let row = sqlx::query!(
+            r#"
+            INSERT INTO statements (user_id, format, period_start, period_end, status, attempts, lease_until)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)
+            RETURNING id, created_at
+            "#,
+            user_id.0,
+            format as _,
+            period.0,
+            period.1,
+            status as _,
+            i32::from(lease_until.is_some()),
+            lease_until,
+        )
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(StatementRecord {
+            id: row.id,
+            user_id,
+            format,
+            period_start: period.0,
+            period_end: period.1,
+            status,
+            transaction_count: None,
+            created_at: row.created_at,
+            ready_at: None,
+            expires_at: None,
+        })
+    }
+
+    #[instrument(skip(self))]
+    pub async fn get(&self, user_id: UserId, statement_id: Uuid) -> Result<Option<StatementRecord>> {
+        let row = sqlx::query!(
+            r#"
+            SELECT id, user_id, format as "format: StatementFormat", period_start, period_end,
+                   status as "status: StatementStatus", transaction_count,
+                   created_at, ready_at, expires_at
+            FROM statements
+            WHERE id = $1 AND user_id = $2
+            "#,
+            statement_id,
+            user_id.0,
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.map(|r| StatementRecord {
+            id: r.id,
+            user_id: UserId(r.user_id),
+            format: r.format,
+            period_start: r.period_start,
+            period_end: r.period_end,
+            status: r.status,
+            transaction_count: r.transaction_count,
+            created_at: r.created_at,
+            ready_at: r.ready_at,
+            expires_at: r.expires_at,
+        }))
+    }
+
+    /// Most recent statements first
+    #[instrument(skip(self))]
+    pub async fn list(&self, user_id: UserId, limit: i64) -> Result<Vec<StatementRecord>> {
+        let rows = sqlx::query!(
+            r#"
+            SELECT id, user_id, format as "format: StatementFormat", period_start, period_end,
+                   status as "status: StatementStatus", transaction_count,
+                   created_at, ready_at, expires_at
+            FROM statements
+            WHERE user_id = $1
+            ORDER BY created_at DESC
+            LIMIT $2
+            "#,
+            user_id.0,
+            limit,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|r| StatementRecord {
+                id: r.id,
+                user_id: UserId(r.user_id),
+                format: r.format,
+                period_start: r.period_start,
+                period_end: r.period_end,
+                status: r.status,
+                transaction_count: r.transaction_count,
+                created_at: r.created_at,
+                ready_at: r.ready_at,
+                expires_at: r.expires_at,
+            })
+            .collect())
+    }
+
+    /// Lease queued statements, and ones whose worker stopped before finishing
+    #[instrument(skip(self))]
+    pub async fn claim_pending(&self, limit: i64, lease_until: DateTime<Utc>) -> Result<Vec<StatementRecord>> {
+        let rows = sqlx::query!(
+            r#"
+            UPDATE statements
+            SET status = 'generating', attempts = attempts + 1, lease_until = $2
+            WHERE id IN (
+                SELECT id FROM statements
+                WHERE status = 'pending' OR (status = 'generating' AND lease_until < NOW())
+                ORDER BY created_at
+                LIMIT $1
+                FOR UPDATE SKIP LOCKED
+            )
+            RETURNING id, user_id, format as "format: StatementFormat", period_start, period_end,
+                      status as "status: StatementStatus", transaction_count,
+                      created_at, ready_at, expires_at
+            "#,
+            limit,
+            lease_until,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|r| StatementRecord {
+                id: r.id,
+                user_id: UserId(r.user_id),
+                format: r.format,
+                period_start: r.period_start,
+                period_end: r.period_end,
+                status: r.status,
+                transaction_count: r.transaction_count,
+                created_at: r.created_at,
+                ready_at: r.ready_at,
+                expires_at: r.expires_at,
+            })
+            .collect())
+    }
+
+    #[instrument(skip(self))]
+    pub async fn account(&self, user_id: UserId) -> Result<StatementAccount> {
+        let row = sqlx::query!("SELECT full_name, phone_number FROM users WHERE id = $1", user_id.0)
+            .fetch_optional(&self.pool)
+            .await?
+            .ok_or_else(|| AppError::User {
+                message: "User not found".to_string(),
+            })?;
+
+        Ok(StatementAccount {
+            full_name: row.full_name,
+            phone_number: row.phone_number,
+        })
+    }
+
+    /// Sats balance just before `at`: the current balance minus everything since
+    #[instrument(skip(self))]
+    pub async fn balance_at(&self, user_id: UserId, at: DateTime<Utc>) -> Result<i64> {
+        let row = sqlx::query!(
+            r#"
+            SELECT w.balance_sats - COALESCE((
+                SELECT SUM(transaction_balance_effect(t.type, t.status, t.amount_sats, t.fee_sats))
+                FROM transactions t
+                WHERE t.user_id = w.user_id AND t.created_at >= $2
+            ), 0)::BIGINT AS "balance!"
+            FROM wallets w
+            WHERE w.user_id = $1
+            "#,
+            user_id.0,
+            at,
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.map(|r| r.balance).unwrap_or(0))
+    }
+
+    /// Transactions created in the period, oldest first
+    #[instrument(skip(self))]
+    pub async fn entries(
+        &self,
+        user_id: UserId,
+        period_start: DateTime<Utc>,
+        period_end: DateTime<Utc>,
+    ) -> Result<Vec<StatementEntry>> {
+        let rows = sqlx::query!(
+            r#"
+            SELECT id, created_at, type as "transaction_type: TransactionType",
+                   status as "status: TransactionStatus",
+                   provider as "provider: MobileMoneyNetwork",
+                   metadata->>'description' AS description, mpesa_code,
+                   amount_kes, amount_sats, fee_kes, fee_sats, exchange_rate,
+                   transaction_balance_effect(type, status, amount_sats, fee_sats) AS "balance_effect!"
+            FROM transactions
+            WHERE user_id = $1 AND created_at >= $2 AND created_at < $3
+            ORDER BY created_at ASC, id ASC
+            "#,
+            user_id.0,
+            period_start,
+            period_end,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|r| StatementEntry {
+                id: r.id,
+                created_at: r.created_at,
+                transaction_type: r.transaction_type,
+                status: r.status,
+                provider: r.provider,
+                description: r.description,
+                mpesa_code: r.mpesa_code,
+                amount_kes: r.amount_kes,
+                amount_sats: r.amount_sats,
+                fee_kes: r.fee_kes,
+                fee_sats: r.fee_sats,
+                exchange_rate: r.exchange_rate,
+                balance_effect_sats: r.balance_effect,
+            })
+            .collect())
+    }
+
+    #[instrument(skip(self, content))]
+    pub async fn mark_ready(
+        &self,
+        statement_id: Uuid,
+        file_name: &str,
+        content: &[u8],
+        transaction_count: i32,
+        expires_at: DateTime<Utc>,
+    ) -> Result<()> {
+        sqlx::query!(
+            r#"
+            UPDATE statements
+            SET status = 'ready', content = $2, file_name = $3, transaction_count = $4,
+                ready_at = NOW(), expires_at = $5, lease_until = NULL, last_error = NULL
+            WHERE id = $1
+            "#,
+            statement_id,
+            content,
+            file_name,
+            transaction_count,
+            expires_at,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Record a failed attempt; the statement is queued again until attempts run out
+    #[instrument(skip(self))]
+    pub async fn mark_attempt_failed(&self, statement_id: Uuid, error: &str) -> Result<()> {
+        sqlx::query!(
+            r#"
+            UPDATE statements
+            SET status = CASE WHEN attempts >= $3 THEN 'failed'::statement_status ELSE 'pending'::statement_status END,
+                lease_until = NULL, last_error = $2
+            WHERE id = $1
+            "#,
+            statement_id,
+            error,
+            MAX_ATTEMPTS,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// File of a ready statement
+    #[instrument(skip(self))]
+    pub async fn file(&self, statement_id: Uuid) -> Result<Option<StatementFile>> {
+        let row = sqlx::query!(
+            r#"
+            SELECT format as "format: StatementFormat", file_name as "file_name!", content as "content!"
+            FROM statements
+            WHERE id = $1 AND status = 'ready' AND expires_at > NOW()
+            "#,
+            statement_id,
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.map(|r| StatementFile {
+            format: r.format,
+            file_name: r.file_name,
+            content: r.content,
+        }))
+    }
+
+    /// Delete files past their retention
+    #[instrument(skip(self))]
+    pub async fn expire(&self) -> Result<u64> {
+        let result = sqlx::query!(
+            r#"
+            UPDATE statements
+            SET status = 'expired', content = NULL
+            WHERE status = 'ready' AND expires_at <= NOW()
+            "#
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+}
+
+/// Statement requests, generation and downloads
+pub struct StatementService {
+    repository: Arc<StatementRepository>,
+    link_secret: String,
+}
+
+impl StatementService {
+    pub fn new(repository: Arc<StatementRepository>) -> Self {
+        let link_secret = std::env::var("STATEMENT_LINK_SECRET")
+            .unwrap_or_else(|_| "statement-link-secret".to_string());
+
+        Self { repository, link_secret }
+    }
+
+    /// Create a statement, generating it straight away when the period is short
+    #[instrument(skip(self))]
+    pub async fn request(&self, user_id: UserId, request: CreateStatementRequest) -> Result<StatementResponse> {
+        let now = Utc::now();
+        let period = request.period(now)?;
+
+        if period.1 - period.0 > Duration::days(INLINE_MAX_DAYS) {
+            let statement = self.repository.create(user_id, request.format, period, None).await?;
+            info!(statement_id = %statement.id, "Queued statement for generation");
+            return Ok(self.response(statement, now));
+        }
+
+        let lease_until = now + Duration::minutes(GENERATION_LEASE_MINUTES);
+        let statement = self.repository.create(user_id, request.format, period, Some(lease_until)).await?;
+        // A failed attempt is queued again, so the user can still pick it up later
+        if let Err(e) = self.generate(&statement).await {
+            warn!(statement_id = %statement.id, "Failed to generate statement inline: {}", e);
+        }
+
+        let statement = self
+            .repository
+            .get(user_id, statement.id)
+            .await?
+            .ok_or_else(|| AppError::Internal(anyhow::anyhow!("Statement {} disappeared", statement.id)))?;
+        Ok(self.response(statement, Utc::now()))
+    }
+
+    #[instrument(skip(self))]
+    pub async fn get(&self, user_id: UserId, statement_id: Uuid) -> Result<StatementResponse> {
+        let statement = self
+            .repository
+            .get(user_id, statement_id)
+            .await?
+            .ok_or_else(|| AppError::Payment {
+                message: "Statement not found".to_string(),
+            })?;
+        Ok(self.response(statement, Utc::now()))
+    }
+
+    #[instrument(skip(self))]
+    pub async fn list(&self, user_id: UserId) -> Result<Vec<StatementResponse>> {
+        let now = Utc::now();
+        let statements = self.repository.list(user_id, 50).await?;
+        Ok(statements.into_iter().map(|s| self.response(s, now)).collect())
+    }
+
+    /// File behind a signed download link
+    #[instrument(skip(self, params))]
+    pub async fn download(&self, statement_id: Uuid, params: StatementDownloadParams) -> Result<StatementFile> {
+        verify_download(&self.link_secret, statement_id, &params, Utc::now())?;
+
+        self.repository
+            .file(statement_id)
+            .await?
+            .ok_or_else(|| AppError::Payment {
+                message: "Statement is no longer available".to_string(),
+            })
+    }
+
+    /// Generate queued statements; returns how many were attempted
+    #[instrument(skip(self))]
+    pub async fn process_pending(&self, limit: i64) -> Result<usize> {
+        let lease_until = Utc::now() + Duration::minutes(GENERATION_LEASE_MINUTES);
+        let statements = self.repository.claim_pending(limit, lease_until).await?;
+
+        for statement in &statements {
+            if let Err(e) = self.generate(statement).await {
+                warn!(statement_id = %statement.id, "Failed to generate statement: {}", e);
+            }
+        }
+
+        Ok(statements.len())
+    }
+
+    /// Delete files past their retention
+    pub async fn expire(&self) -> Result<u64> {
+        self.repository.expire().await
+    }
+
+    /// Build, render and store a statement, recording the failure if any step fails
+    async fn generate(&self, statement: &StatementRecord) -> Result<()> {
+        let result = self.render(statement).await;
+
+        match result {
+            Ok((file_name, content, count)) => {
+                let expires_at = Utc::now() + Duration::days(RETENTION_DAYS);
+                self.repository
+                    .mark_ready(statement.id, &file_name, &content, count, expires_at)
+                    .await?;
+                info!(statement_id = %statement.id, transactions = count, "Statement ready");
+                Ok(())
+            }
+            Err(e) => {
+                self.repository.mark_attempt_failed(statement.id, &e.to_string()).await?;
+                Err(e)
+            }
+        }
+    }
+
+    async fn render(&self, statement: &StatementRecord) -> Result<(String, Vec<u8>, i32)> {
+        let account = self.repository.account(statement.user_id).await?;
+        let opening_balance = self.repository.balance_at(statement.user_id, statement.period_start).await?;
+        let entries = self
+            .repository
+            .entries(statement.user_id, statement.period_start, statement.period_end)
+            .await?;
+
+        let built = Statement::build(
+            account,
+            statement.period_start,
+            statement.period_end,
+            opening_balance,
+            entries,
+            Utc::now(),
+        );
+        let content = built.render(statement.format)?;
+
+        Ok((
+            built.file_name(statement.format),
+            content,
+            built.totals.transaction_count as i32,
+        ))
+    }
+
+    /// Response with a fresh download link when the file is available
+    fn response(&self, statement: StatementRecord, now: DateTime<Utc>) -> StatementResponse {
+        let link_expires_at = statement
+            .expires_at
+            .filter(|expires_at| statement.status == StatementStatus::Ready && *expires_at > now)
+            .map(|expires_at| expires_at.min(now + Duration::hours(LINK_TTL_HOURS)));
+
+        let download_url = link_expires_at.map(|link_expires_at| {
+            let expires = link_expires_at.timestamp();
+            format!(
+                "/v1/statements/{}/download?expires={}&signature={}",
+                statement.id,
+                expires,
+                sign_download(&self.link_secret, statement.id, expires)
+            )
+        });
+
+        StatementResponse {
+            id: statement.id,
+            format: statement.format,
+            period_start: statement.period_start,
+            period_end: statement.period_end,
+            status: statement.status,
+            transaction_count: statement.transaction_count,
+            created_at: statement.created_at,
+            ready_at: statement.ready_at,
+            expires_at: statement.expires_at,
+            download_url,
+            download_url_expires_at: link_expires_at,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn entry(
+        transaction_type: TransactionType,
+        status: TransactionStatus,
+        amount_sats: i64,
+        fee_sats: i64,
+        balance_effect_sats: i64,
+    ) -> StatementEntry {
+        StatementEntry {
+            id: Uuid::new_v4(),
+            created_at: Utc.with_ymd_and_hms(2026, 3, 2, 9, 30, 0).unwrap(),
+            transaction_type,
+            status,
+            provider: None,
+            description: Some("Rent – March".to_string()),
+            mpesa_code: None,
+            amount_kes: None,
+            amount_sats: Some(amount_sats),
+            fee_kes: None,
+            fee_sats: Some(fee_sats),
+            exchange_rate: Some(Decimal::new(850_000_000, 2)),
+            balance_effect_sats,
+        }
+    }
+
+    fn statement() -> Statement {
+        Statement::build(
+            StatementAccount {
+                full_name: Some("Jane Wanjiku".to_string()),
+                phone_number: "+254712345678".to_string(),
+            },
+            Utc.with_ymd_and_hms(2026, 3, 1, 0, 0, 0).unwrap(),
+            Utc.with_ymd_and_hms(2026, 4, 1, 0, 0, 0).unwrap(),
+            10_000,
+            vec![
+                entry(TransactionType::LightningReceive, TransactionStatus::Completed, 5_000, 0, 5_000),
+                entry(TransactionType::LightningSend, TransactionStatus::Completed, 2_000, 10, -2_010),
+                entry(TransactionType::WithdrawalMpesa, TransactionStatus::Refunded, 3_000, 30, -3_030),
+                entry(TransactionType::Refund, TransactionStatus::Completed, 3_030, 0, 3_030),
+            ],
+            Utc.with_ymd_and_hms(2026, 4, 2, 8, 0, 0).unwrap(),
+        )
+    }
+
+    #[test]
+    fn test_balances_and_totals() {
+        let statement = statement();
+
+        let balances: Vec<i64> = statement.lines.iter().map(|l| l.balance_sats).collect();
+        assert_eq!(balances, vec![15_000, 12_990, 9_960, 12_990]);
+        assert_eq!(statement.closing_balance_sats, 12_990);
+        assert_eq!(
+            statement.totals,
+            StatementTotals {
+                money_in_sats: 8_030,
+                money_out_sats: 5_040,
+                // The refunded withdrawal's fee was given back
+                fees_sats: 10,
+                fees_kes: Decimal::ZERO,
+                transaction_count: 4,
+            }
+        );
+        assert_eq!(statement.file_name(StatementFormat::Pdf), "pesabit-statement-2026-03-01-to-2026-03-31.pdf");
+    }
+
+    #[test]
+    fn test_csv_and_pdf_rendering() {
+        let statement = statement();
+
+        let csv = String::from_utf8(statement.render(StatementFormat::Csv).unwrap()).unwrap();
+        assert!(csv.starts_with("PesaBit account statement\n"));
+        assert!(csv.contains("Opening balance (sats),10000\n"));
+        assert!(csv.contains(",Lightning payment sent,completed,Rent – March,,,2000,,10,8500000.00,-2010,12990\n"));
+        assert!(csv.ends_with("Closing balance (sats),12990\n"));
+
+        let mut deposit = entry(TransactionType::DepositMpesa, TransactionStatus::Completed, 5000, 0, 5000);
+        assert_eq!(type_label(&deposit), "M-Pesa deposit");
+        deposit.provider = Some(MobileMoneyNetwork::AirtelMoney);
+        assert_eq!(type_label(&deposit), "Airtel Money deposit");
+
+        let pdf = statement.render(StatementFormat::Pdf).unwrap();
+        assert!(pdf.starts_with(b"%PDF"));
+    }
+
+    #[test]
+    fn test_period_validation() {
+        let now = Utc.with_ymd_and_hms(2026, 6, 1, 12, 0, 0).unwrap();
+        let request = |from: DateTime<Utc>, to: DateTime<Utc>| CreateStatementRequest {
+            format: StatementFormat::Csv,
+            from_date: from,
+            to_date: to,
+        };
+
+        // End is capped at now
+        let (_, end) = request(now - Duration::days(10), now + Duration::days(10)).period(now).unwrap();
+        assert_eq!(end, now);
+
+        assert!(request(now, now - Duration::days(1)).period(now).is_err());
+        assert!(request(now + Duration::days(1), now + Duration::days(2)).period(now).is_err());
+        assert!(request(now - Duration::days(MAX_PERIOD_DAYS + 1), now).period(now).is_err());
+    }
+
+    #[test]
+    fn test_download_links() {
+        let id = Uuid::new_v4();
+        let now = Utc::now();
+        let expires = (now + Duration::hours(1)).timestamp();
+        let params = |expires: i64, signature: String| StatementDownloadParams { expires, signature };
+
+        let signature = sign_download("secret", id, expires);
+        assert!(verify_download("secret", id, &params(expires, signature.clone()), now).is_ok());
+
+        // Tampered expiry, other statement, other secret, expired link
+        assert!(verify_download("secret", id, &params(expires + 3600, signature.clone()), now).is_err());
+        assert!(verify_download("secret", Uuid::new_v4(), &params(expires, signature.clone()), now).is_err());
+        assert!(verify_download("other", id, &params(expires, signature.clone()), now).is_err());
+        assert!(verify_download("secret", id, &params(expires, signature), now + Duration::hours(2)).is_err());
+        assert!(verify_download("secret", id, &params(expires, "zz".to_string()), now).is_err());
+    }
+
+    #[test]
+    fn test_grouped() {
+        assert_eq!(grouped(0), "0");
+        assert_eq!(grouped(999), "999");
+        assert_eq!(grouped(1_234_567), "1,234,567");
+        assert_eq!(grouped(-2_010), "-2,010");
+    }
+}
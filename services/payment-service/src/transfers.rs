@@ -0,0 +1,257 @@
+/// Internal transfers between PesaBit wallets
+///
+/// Money sent from one PesaBit user to another never leaves the ledger: the
+/// payer's wallet is debited and the payee's credited inside the caller's
+/// database transaction, after the payer's limits are checked under their
+/// limits lock. Each side gets a completed transaction, booked as a
+/// Lightning send and receive (how a payment between two addresses on our node
+/// settles) with `internal: true` in its metadata and no fee.
+
+use crate::domain::StatusActor;
+use crate::limits::lock_and_check_limits;
+use crate::transitions::set_status_actor;
+use serde::Serialize;
+use shared_errors::{AppError, Result};
+use shared_types::conversion::{BtcKesRate, Rounding, Side};
+use shared_types::*;
+use sqlx::PgConnection;
+use tracing::instrument;
+use uuid::Uuid;
+
+/// Domain of PesaBit users' Lightning addresses
+pub const LIGHTNING_ADDRESS_DOMAIN: &str = "pesa.co.ke";
+
+/// How a PesaBit user is addressed: phone number or Lightning username
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RecipientHandle {
+    Phone(String),
+    Username(String),
+}
+
+impl RecipientHandle {
+    /// `+254712345678`, `alice` or `alice@pesa.co.ke`
+    pub fn parse(handle: &str) -> Result<Self> {
+        let handle = handle.trim();
+        if handle.starts_with('+') {
+            let phone = PhoneNumber::new(handle.to_string()).map_err(|message| AppError::Validation { message })?;
+            return Ok(RecipientHandle::Phone(phone.0));
+        }
+
+        let username = match handle.split_once('@') {
+            Some((name, domain)) if domain.eq_ignore_ascii_case(LIGHTNING_ADDRESS_DOMAIN) => name,
+            Some(_) => {
+                return Err(AppError::Validation {
+                    message: format!("{} is not a PesaBit address", handle),
+                })
+            }
+            None => handle,
+        };
+        let valid = !username.is_empty()
+            && username.len() <= 50
+            && username.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'));
+        if !valid {
+            return Err(AppError::Validation {
+                message: format!("Invalid phone number or username: {}", handle),
+            });
+        }
+
+        Ok(RecipientHandle::Username(username.to_lowercase()))
+    }
+}
+
+/// PesaBit user on one side of a transfer
+#[derive(Debug, Clone, Serialize)]
+pub struct Party {
+    pub user_id: UserId,
+    /// Lightning address, shown to the other side
+    pub address: String,
+}
+
+impl Party {
+    fn from_username(user_id: Uuid, username: &str) -> Self {
+        Self {
+            user_id: UserId(user_id),
+            address: LightningAddress::new(username, LIGHTNING_ADDRESS_DOMAIN).0,
+        }
+    }
+}
+
+/// Active user with the given phone number or username
+#[instrument(skip(conn))]
+pub async fn find_party(conn: &mut PgConnection, handle: &RecipientHandle) -> Result<Option<Party>> {
+    let row = match handle {
+        RecipientHandle::Phone(phone) => {
+            sqlx::query!(
+                "SELECT id, lightning_username FROM users WHERE phone_number = $1 AND is_active",
+                phone,
+            )
+            .fetch_optional(conn)
+            .await?
+            .map(|r| (r.id, r.lightning_username))
+        }
+        RecipientHandle::Username(username) => {
+            sqlx::query!(
+                "SELECT id, lightning_username FROM users WHERE LOWER(lightning_username) = $1 AND is_active",
+                username,
+            )
+            .fetch_optional(conn)
+            .await?
+            .map(|r| (r.id, r.lightning_username))
+        }
+    };
+
+    Ok(row.map(|(id, username)| Party::from_username(id, &username)))
+}
+
+/// User by ID
+#[instrument(skip(conn))]
+pub async fn get_party(conn: &mut PgConnection, user_id: UserId) -> Result<Party> {
+    let row = sqlx::query!("SELECT id, lightning_username FROM users WHERE id = $1", user_id.0)
+        .fetch_optional(conn)
+        .await?
+        .ok_or_else(|| AppError::User {
+            message: "User not found".to_string(),
+        })?;
+
+    Ok(Party::from_username(row.id, &row.lightning_username))
+}
+
+/// Money moving between two PesaBit wallets
+#[derive(Debug)]
+pub struct InternalTransfer<'a> {
+    pub payer: &'a Party,
+    pub payee: &'a Party,
+    pub amount_sats: SatAmount,
+    /// Rate the amount is valued at for the payer's limits
+    pub rate: BtcKesRate,
+    /// Shown as the description on both transactions
+    pub memo: Option<&'a str>,
+    /// Extra metadata for both transactions (e.g., the payment request paid)
+    pub reference: serde_json::Value,
+}
+
+/// Transactions created by a transfer
+#[derive(Debug, Clone, Copy)]
+pub struct TransferReceipt {
+    pub debit_transaction_id: Uuid,
+    pub credit_transaction_id: Uuid,
+}
+
+/// Move sats between wallets inside the caller's database transaction
+/// Fails with a payment error if the payer's balance is too low, or a limit
+/// error if the amount would take them over their limits
+#[instrument(skip(conn, transfer), fields(payer = %transfer.payer.user_id, payee = %transfer.payee.user_id))]
+pub async fn transfer(conn: &mut PgConnection, transfer: &InternalTransfer<'_>) -> Result<TransferReceipt> {
+    if transfer.payer.user_id == transfer.payee.user_id {
+        return Err(AppError::Validation {
+            message: "You can't send money to yourself".to_string(),
+        });
+    }
+    if transfer.amount_sats.as_i64() <= 0 {
+        return Err(AppError::Validation {
+            message: "Amount must be positive".to_string(),
+        });
+    }
+
+    // Limits lock first: every debit path takes it before any wallet lock
+    let amount_kes = transfer
+        .rate
+        .sats_to_kes(transfer.amount_sats, Side::Charge, Rounding::HouseFavourable)?;
+    lock_and_check_limits(&mut *conn, transfer.payer.user_id, amount_kes.as_decimal(), transfer.rate).await?;
+
+    // Lock both wallets in a fixed order so opposite transfers can't deadlock
+    let wallets = sqlx::query_scalar!(
+        "SELECT user_id FROM wallets WHERE user_id = ANY($1) ORDER BY user_id FOR UPDATE",
+        &[transfer.payer.user_id.0, transfer.payee.user_id.0][..],
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+    if wallets.len() != 2 {
+        return Err(AppError::Payment {
+            message: "Wallet not found".to_string(),
+        });
+    }
+
+    let amount = transfer.amount_sats.as_i64();
+    let debited = sqlx::query!(
+        "UPDATE wallets SET balance_sats = balance_sats - $2 WHERE user_id = $1 AND balance_sats >= $2",
+        transfer.payer.user_id.0,
+        amount,
+    )
+    .execute(&mut *conn)
+    .await?;
+    if debited.rows_affected() != 1 {
+        return Err(AppError::Payment {
+            message: "Insufficient balance".to_string(),
+        });
+    }
+
+    sqlx::query!(
+        "UPDATE wallets SET balance_sats = balance_sats + $2 WHERE user_id = $1",
+        transfer.payee.user_id.0,
+        amount,
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    set_status_actor(&mut *conn, &StatusActor::System, Some("internal_transfer")).await?;
+
+    let debit_transaction_id = Uuid::new_v4();
+    let credit_transaction_id = Uuid::new_v4();
+    let sides = [
+        (debit_transaction_id, transfer.payer, transfer.payee, TransactionType::LightningSend),
+        (credit_transaction_id, transfer.payee, transfer.payer, TransactionType::LightningReceive),
+    ];
+
+    for (id, owner, counterparty, transaction_type) in sides {
+        let mut metadata = serde_json::json!({
+            "internal": true,
+            "counterparty": counterparty.address,
+            "description": transfer.memo,
+        });
+        if let (Some(metadata), serde_json::Value::Object(reference)) = (metadata.as_object_mut(), &transfer.reference) {
+            metadata.extend(reference.clone());
+        }
+
+        sqlx::query!(
+            r#"
+            INSERT INTO transactions (id, user_id, type, status, amount_sats, fee_sats, metadata, completed_at)
+            VALUES ($1, $2, $3, 'completed', $4, 0, $5, NOW())
+            "#,
+            id,
+            owner.user_id.0,
+            transaction_type as _,
+            amount,
+            metadata,
+        )
+        .execute(&mut *conn)
+        .await?;
+    }
+
+    Ok(TransferReceipt {
+        debit_transaction_id,
+        credit_transaction_id,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_recipient_handle() {
+        assert_eq!(
+            RecipientHandle::parse(" +254712345678 ").unwrap(),
+            RecipientHandle::Phone("+254712345678".to_string())
+        );
+        assert_eq!(
+            RecipientHandle::parse("Alice@PESA.co.ke").unwrap(),
+            RecipientHandle::Username("alice".to_string())
+        );
+        assert_eq!(RecipientHandle::parse("bob_1").unwrap(), RecipientHandle::Username("bob_1".to_string()));
+
+        for invalid in ["", "+2547", "alice@getalby.com", "a b", "@pesa.co.ke"] {
+            assert!(RecipientHandle::parse(invalid).is_err(), "{} should be rejected", invalid);
+        }
+    }
+}
@@ -0,0 +1,59 @@
+/// Applying transaction status changes
+///
+/// Status changes go through `transition_status`, which tells the database
+/// trigger who is making the change and why, so it ends up in
+/// `transaction_status_history`. Use the connection of an open database
+/// transaction: the actor and reason only last until it commits.
+
+use crate::domain::{StatusActor, StatusTransition};
+use shared_errors::{AppError, Result};
+use sqlx::PgConnection;
+use tracing::instrument;
+use uuid::Uuid;
+
+/// Record who is changing transaction statuses in this database transaction, and why
+/// Also covers transactions inserted afterwards (their initial status is logged too)
+pub async fn set_status_actor(conn: &mut PgConnection, actor: &StatusActor, reason: Option<&str>) -> Result<()> {
+    sqlx::query!(
+        r#"
+        SELECT set_config('pesabit.status_actor', $1, true) AS "actor!",
+               set_config('pesabit.status_reason', $2, true) AS "reason!"
+        "#,
+        actor.to_string(),
+        reason.unwrap_or_default(),
+    )
+    .fetch_one(conn)
+    .await?;
+
+    Ok(())
+}
+
+/// Move a transaction along `transition`
+/// Fails with a conflict if the transaction is no longer in the transition's `from` status
+#[instrument(skip(conn, reason), fields(from = transition.from().as_str(), to = transition.to().as_str()))]
+pub async fn transition_status(
+    conn: &mut PgConnection,
+    transaction_id: Uuid,
+    transition: &StatusTransition,
+    actor: &StatusActor,
+    reason: Option<&str>,
+) -> Result<()> {
+    set_status_actor(&mut *conn, actor, reason).await?;
+
+    let updated = sqlx::query!(
+        "UPDATE transactions SET status = $3 WHERE id = $1 AND status = $2",
+        transaction_id,
+        transition.from().clone() as _,
+        transition.to().clone() as _,
+    )
+    .execute(conn)
+    .await?;
+
+    if updated.rows_affected() == 0 {
+        return Err(AppError::Conflict {
+            message: format!("Transaction is no longer {}", transition.from().as_str()),
+        });
+    }
+
+    Ok(())
+}
@@ -0,0 +1,968 @@
+/// Savings vaults with time locks
+///
+/// A vault is a named sub-balance of the wallet with an optional savings target
+/// and lock date. Moving sats into a vault takes them out of the spendable
+/// balance (`wallets.balance_sats`), so no payment can spend them; every move is
+/// a `vault_entries` ledger row and a `vault_deposit` or `vault_withdrawal`
+/// transaction, so statements account for it. Unlocked vaults can be withdrawn
+/// from at once. Withdrawing from a locked vault needs the user's PIN and only
+/// completes after a cooling-off delay, during which the user is notified and can cancel.
+
+use crate::domain::StatusActor;
+use crate::transitions::set_status_actor;
+use chrono::{DateTime, Duration, Utc};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use shared_auth::PinService;
+use shared_errors::{AppError, Result};
+use shared_events::{record_event, DomainEvent};
+use shared_types::*;
+use sqlx::{PgConnection, PgPool};
+use std::sync::Arc;
+use tracing::{info, instrument, warn};
+use uuid::Uuid;
+use validator::Validate;
+
+/// Delay before an early withdrawal from a locked vault completes
+const COOLING_OFF_HOURS: i64 = 24;
+/// Open vaults per user
+const MAX_VAULTS: i64 = 10;
+/// Ledger entries shown with a vault
+const RECENT_ENTRIES: i64 = 50;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[sqlx(type_name = "vault_status", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum VaultStatus {
+    Active,
+    Closed,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[sqlx(type_name = "vault_entry_type", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum VaultEntryType {
+    Deposit,
+    Withdrawal,
+    EarlyWithdrawal,
+}
+
+impl VaultEntryType {
+    /// Transaction a move is booked as in the wallet's history
+    pub fn transaction_type(&self) -> TransactionType {
+        match self {
+            VaultEntryType::Deposit => TransactionType::VaultDeposit,
+            VaultEntryType::Withdrawal | VaultEntryType::EarlyWithdrawal => TransactionType::VaultWithdrawal,
+        }
+    }
+
+    fn description(&self, vault_name: &str) -> String {
+        match self {
+            VaultEntryType::Deposit => format!("To vault {}", vault_name),
+            VaultEntryType::Withdrawal | VaultEntryType::EarlyWithdrawal => format!("From vault {}", vault_name),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[sqlx(type_name = "vault_withdrawal_status", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum VaultWithdrawalStatus {
+    Pending,
+    Completed,
+    Cancelled,
+    Failed,
+}
+
+/// Request to open a vault
+#[derive(Debug, Deserialize, Validate)]
+pub struct CreateVaultRequest {
+    #[validate(length(min = 1, max = 50))]
+    pub name: String,
+    /// Savings goal in sats
+    #[validate(range(min = 1))]
+    pub target_sats: Option<i64>,
+    /// Withdrawals before this need a PIN and a cooling-off delay
+    pub lock_until: Option<DateTime<Utc>>,
+}
+
+/// Changes to a vault; a lock can be extended but not shortened
+#[derive(Debug, Deserialize, Validate)]
+pub struct UpdateVaultRequest {
+    #[validate(length(min = 1, max = 50))]
+    pub name: Option<String>,
+    #[validate(range(min = 1))]
+    pub target_sats: Option<i64>,
+    pub lock_until: Option<DateTime<Utc>>,
+}
+
+/// Sats moved into a vault
+#[derive(Debug, Deserialize, Validate)]
+pub struct VaultDepositRequest {
+    #[validate(range(min = 1))]
+    pub amount_sats: i64,
+}
+
+/// Sats moved out of a vault
+#[derive(Debug, Deserialize, Validate)]
+pub struct VaultWithdrawRequest {
+    #[validate(range(min = 1))]
+    pub amount_sats: i64,
+    /// Required while the vault is locked
+    #[validate(length(min = 4, max = 6))]
+    pub pin: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Vault {
+    pub id: Uuid,
+    pub user_id: UserId,
+    pub name: String,
+    pub target_sats: Option<i64>,
+    pub lock_until: Option<DateTime<Utc>>,
+    pub balance_sats: SatAmount,
+    /// Share of the target saved so far (0-100)
+    pub progress_percent: Option<Decimal>,
+    pub status: VaultStatus,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct VaultEntry {
+    pub id: Uuid,
+    pub entry_type: VaultEntryType,
+    pub amount_sats: SatAmount,
+    pub vault_balance_sats: SatAmount,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct VaultWithdrawal {
+    pub id: Uuid,
+    pub vault_id: Uuid,
+    pub amount_sats: SatAmount,
+    pub status: VaultWithdrawalStatus,
+    pub available_at: DateTime<Utc>,
+    pub failure_reason: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub resolved_at: Option<DateTime<Utc>>,
+}
+
+/// Vault with its ledger and pending early withdrawals
+#[derive(Debug, Serialize)]
+pub struct VaultDetails {
+    #[serde(flatten)]
+    pub vault: Vault,
+    pub entries: Vec<VaultEntry>,
+    pub pending_withdrawals: Vec<VaultWithdrawal>,
+}
+
+/// Result of a withdrawal request
+#[derive(Debug, Serialize)]
+pub struct VaultWithdrawResponse {
+    pub vault: Vault,
+    /// Set when the vault is locked and the withdrawal waits out the cooling-off delay
+    pub scheduled: Option<VaultWithdrawal>,
+}
+
+pub fn is_locked(lock_until: Option<DateTime<Utc>>, now: DateTime<Utc>) -> bool {
+    lock_until.is_some_and(|until| until > now)
+}
+
+/// A new lock date must be in the future and can't bring forward a lock in force
+pub fn check_lock_change(current: Option<DateTime<Utc>>, new: DateTime<Utc>, now: DateTime<Utc>) -> Result<()> {
+    if new <= now {
+        return Err(AppError::Validation {
+            message: "lock_until must be in the future".to_string(),
+        });
+    }
+    if let Some(current) = current.filter(|current| *current > now && new < *current) {
+        return Err(AppError::Validation {
+            message: format!("The vault is locked until {}; a lock can only be extended", current.format("%Y-%m-%d")),
+        });
+    }
+    Ok(())
+}
+
+pub fn progress_percent(balance_sats: i64, target_sats: Option<i64>) -> Option<Decimal> {
+    let target = target_sats.filter(|target| *target > 0)?;
+    let percent = Decimal::from(balance_sats) * Decimal::from(100) / Decimal::from(target);
+    Some(percent.min(Decimal::from(100)).round_dp(1))
+}
+
+fn insufficient(what: &str) -> AppError {
+    AppError::Payment {
+        message: format!("Insufficient {} balance", what),
+    }
+}
+
+/// Database access for vaults
+pub struct VaultRepository {
+    pool: PgPool,
+}
+
+impl VaultRepository {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+
+    /// Open a vault; `None` if the user already has an open vault with that name
+    #[instrument(skip(self))]
+    pub async fn create(
+        &self,
+        user_id: UserId,
+        name: &str,
+        target_sats: Option<i64>,
+        lock_until: Option<DateTime<Utc>>,
+    ) -> Result<Option<Uuid>> {
+        let row = sqlx::query!(
+            r#"
+            INSERT INTO vaults (user_id, name, target_sats, lock_until)
+            VALUES ($1, $2, $3, $4)
+            ON CONFLICT (user_id, LOWER(name)) WHERE status = 'active' DO NOTHING
+            RETURNING id
+            "#,
+            user_id.0,
+            name,
+            target_sats,
+            lock_until,
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.map(|r| r.id))
+    }
+
+    #[instrument(skip(self))]
+    pub async fn count_active(&self, user_id: UserId) -> Result<i64> {
+        let count = sqlx::query_scalar!(
+            r#"SELECT COUNT(*) AS "count!" FROM vaults WHERE user_id = $1 AND status = 'active'"#,
+            user_id.0,
+        )
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(count)
+    }
+
+    #[instrument(skip(self))]
+    pub async fn get(&self, user_id: UserId, id: Uuid) -> Result<Option<Vault>> {
+        let row = sqlx::query!(
+            r#"
+            SELECT id, user_id, name, target_sats, lock_until, balance_sats,
+                   status as "status: VaultStatus", created_at, updated_at
+            FROM vaults
+            WHERE id = $1 AND user_id = $2
+            "#,
+            id,
+            user_id.0,
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.map(|r| Vault {
+            id: r.id,
+            user_id: UserId(r.user_id),
+            name: r.name,
+            target_sats: r.target_sats,
+            lock_until: r.lock_until,
+            balance_sats: SatAmount::new(r.balance_sats),
+            progress_percent: progress_percent(r.balance_sats, r.target_sats),
+            status: r.status,
+            created_at: r.created_at,
+            updated_at: r.updated_at,
+        }))
+    }
+
+    /// Open vaults first, oldest first
+    #[instrument(skip(self))]
+    pub async fn list(&self, user_id: UserId) -> Result<Vec<Vault>> {
+        let rows = sqlx::query!(
+            r#"
+            SELECT id, user_id, name, target_sats, lock_until, balance_sats,
+                   status as "status: VaultStatus", created_at, updated_at
+            FROM vaults
+            WHERE user_id = $1
+            ORDER BY status = 'closed', created_at
+            "#,
+            user_id.0,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|r| Vault {
+                id: r.id,
+                user_id: UserId(r.user_id),
+                name: r.name,
+                target_sats: r.target_sats,
+                lock_until: r.lock_until,
+                balance_sats: SatAmount::new(r.balance_sats),
+                progress_percent: progress_percent(r.balance_sats, r.target_sats),
+                status: r.status,
+                created_at: r.created_at,
+                updated_at: r.updated_at,
+            })
+            .collect())
+    }
+
+    /// Sats held in the user's vaults
+    #[instrument(skip(self))]
+    pub async fn total(&self, user_id: UserId) -> Result<SatAmount> {
+        let total = sqlx::query_scalar!(
+            r#"SELECT COALESCE(SUM(balance_sats), 0)::BIGINT AS "total!" FROM vaults WHERE user_id = $1 AND status = 'active'"#,
+            user_id.0,
+        )
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(SatAmount::new(total))
+    }
+
+    #[instrument(skip(self))]
+    pub async fn update(
+        &self,
+        user_id: UserId,
+        id: Uuid,
+        name: &str,
+        target_sats: Option<i64>,
+        lock_until: Option<DateTime<Utc>>,
+    ) -> Result<bool> {
+        let mut tx = self.pool.begin().await?;
+
+        let taken = sqlx::query_scalar!(
+            r#"
+            SELECT EXISTS (
+                SELECT 1 FROM vaults
+                WHERE user_id = $1 AND id != $2 AND status = 'active' AND LOWER(name) = LOWER($3)
+            ) AS "taken!"
+            "#,
+            user_id.0,
+            id,
+            name,
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+        if taken {
+            return Err(AppError::Conflict {
+                message: format!("You already have a vault called {}", name),
+            });
+        }
+
+        let updated = sqlx::query!(
+            r#"
+            UPDATE vaults SET name = $3, target_sats = $4, lock_until = $5
+            WHERE id = $1 AND user_id = $2 AND status = 'active'
+            "#,
+            id,
+            user_id.0,
+            name,
+            target_sats,
+            lock_until,
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+        Ok(updated.rows_affected() == 1)
+    }
+
+    /// Move sats from the spendable balance into a vault
+    #[instrument(skip(self))]
+    pub async fn deposit(&self, user_id: UserId, id: Uuid, amount_sats: SatAmount) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+        set_status_actor(&mut *tx, &StatusActor::System, Some("vault_transfer")).await?;
+
+        let debited = sqlx::query!(
+            "UPDATE wallets SET balance_sats = balance_sats - $2 WHERE user_id = $1 AND balance_sats >= $2",
+            user_id.0,
+            amount_sats.as_i64(),
+        )
+        .execute(&mut *tx)
+        .await?;
+        if debited.rows_affected() != 1 {
+            return Err(insufficient("spendable"));
+        }
+
+        let vault = sqlx::query!(
+            r#"
+            UPDATE vaults SET balance_sats = balance_sats + $3
+            WHERE id = $1 AND user_id = $2 AND status = 'active'
+            RETURNING name, balance_sats
+            "#,
+            id,
+            user_id.0,
+            amount_sats.as_i64(),
+        )
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or_else(|| AppError::Conflict {
+            message: "Closed vaults can't receive sats".to_string(),
+        })?;
+
+        Self::record_entry(&mut *tx, id, &vault.name, user_id, VaultEntryType::Deposit, amount_sats, vault.balance_sats)
+            .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Move sats from an unlocked vault back to the spendable balance
+    #[instrument(skip(self))]
+    pub async fn withdraw(&self, user_id: UserId, id: Uuid, amount_sats: SatAmount) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+        set_status_actor(&mut *tx, &StatusActor::System, Some("vault_transfer")).await?;
+
+        let vault = sqlx::query!(
+            r#"
+            UPDATE vaults SET balance_sats = balance_sats - $3
+            WHERE id = $1 AND user_id = $2 AND status = 'active' AND balance_sats >= $3
+              AND (lock_until IS NULL OR lock_until <= NOW())
+            RETURNING name, balance_sats
+            "#,
+            id,
+            user_id.0,
+            amount_sats.as_i64(),
+        )
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or_else(|| insufficient("vault"))?;
+
+        Self::credit_wallet(&mut *tx, user_id, amount_sats).await?;
+        Self::record_entry(&mut *tx, id, &vault.name, user_id, VaultEntryType::Withdrawal, amount_sats, vault.balance_sats)
+            .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Record an early withdrawal from a locked vault and notify the user
+    #[instrument(skip(self, vault))]
+    pub async fn schedule_withdrawal(
+        &self,
+        vault: &Vault,
+        amount_sats: SatAmount,
+        available_at: DateTime<Utc>,
+    ) -> Result<VaultWithdrawal> {
+        let mut tx = self.pool.begin().await?;
+
+        let row = sqlx::query!(
+            r#"
+            INSERT INTO vault_withdrawals (vault_id, user_id, amount_sats, available_at)
+            VALUES ($1, $2, $3, $4)
+            RETURNING id, created_at
+            "#,
+            vault.id,
+            vault.user_id.0,
+            amount_sats.as_i64(),
+            available_at,
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+
+        record_event(
+            &mut *tx,
+            &DomainEvent::VaultWithdrawalScheduled {
+                vault_withdrawal_id: row.id,
+                vault_id: vault.id,
+                user_id: vault.user_id,
+                vault_name: vault.name.clone(),
+                amount_sats,
+                available_at,
+            },
+        )
+        .await?;
+
+        tx.commit().await?;
+        Ok(VaultWithdrawal {
+            id: row.id,
+            vault_id: vault.id,
+            amount_sats,
+            status: VaultWithdrawalStatus::Pending,
+            available_at,
+            failure_reason: None,
+            created_at: row.created_at,
+            resolved_at: None,
+        })
+    }
+
+    #[instrument(skip(self))]
+    pub async fn cancel_withdrawal(&self, user_id: UserId, vault_id: Uuid, withdrawal_id: Uuid) -> Result<bool> {
+        let updated = sqlx::query!(
+            r#"
+            UPDATE vault_withdrawals SET status = 'cancelled', resolved_at = NOW()
+            WHERE id = $1 AND vault_id = $2 AND user_id = $3 AND status = 'pending'
+            "#,
+            withdrawal_id,
+            vault_id,
+            user_id.0,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(updated.rows_affected() == 1)
+    }
+
+    /// Complete early withdrawals whose cooling-off delay has ended; returns how many were resolved
+    #[instrument(skip(self))]
+    pub async fn complete_due_withdrawals(&self, limit: i64) -> Result<usize> {
+        let mut tx = self.pool.begin().await?;
+        set_status_actor(&mut *tx, &StatusActor::System, Some("vault_transfer")).await?;
+
+        let due = sqlx::query!(
+            r#"
+            SELECT id, vault_id, user_id, amount_sats
+            FROM vault_withdrawals
+            WHERE status = 'pending' AND available_at <= NOW()
+            ORDER BY available_at
+            LIMIT $1
+            FOR UPDATE SKIP LOCKED
+            "#,
+            limit,
+        )
+        .fetch_all(&mut *tx)
+        .await?;
+
+        for withdrawal in &due {
+            let user_id = UserId(withdrawal.user_id);
+            let amount_sats = SatAmount::new(withdrawal.amount_sats);
+
+            let vault = sqlx::query!(
+                r#"
+                UPDATE vaults SET balance_sats = balance_sats - $2
+                WHERE id = $1 AND status = 'active' AND balance_sats >= $2
+                RETURNING name, balance_sats
+                "#,
+                withdrawal.vault_id,
+                withdrawal.amount_sats,
+            )
+            .fetch_optional(&mut *tx)
+            .await?;
+
+            let Some(vault) = vault else {
+                warn!("Vault {} no longer holds {} sats for withdrawal {}", withdrawal.vault_id, withdrawal.amount_sats, withdrawal.id);
+                sqlx::query!(
+                    r#"
+                    UPDATE vault_withdrawals
+                    SET status = 'failed', failure_reason = 'The vault no longer holds enough sats', resolved_at = NOW()
+                    WHERE id = $1
+                    "#,
+                    withdrawal.id,
+                )
+                .execute(&mut *tx)
+                .await?;
+                continue;
+            };
+
+            Self::credit_wallet(&mut *tx, user_id, amount_sats).await?;
+            let entry_id = Self::record_entry(
+                &mut *tx,
+                withdrawal.vault_id,
+                &vault.name,
+                user_id,
+                VaultEntryType::EarlyWithdrawal,
+                amount_sats,
+                vault.balance_sats,
+            )
+            .await?;
+
+            sqlx::query!(
+                "UPDATE vault_withdrawals SET status = 'completed', entry_id = $2, resolved_at = NOW() WHERE id = $1",
+                withdrawal.id,
+                entry_id,
+            )
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        tx.commit().await?;
+        Ok(due.len())
+    }
+
+    /// Close an empty vault
+    #[instrument(skip(self))]
+    pub async fn close(&self, user_id: UserId, id: Uuid) -> Result<bool> {
+        let updated = sqlx::query!(
+            r#"
+            UPDATE vaults SET status = 'closed', closed_at = NOW()
+            WHERE id = $1 AND user_id = $2 AND status = 'active' AND balance_sats = 0
+              AND NOT EXISTS (SELECT 1 FROM vault_withdrawals WHERE vault_id = $1 AND status = 'pending')
+            "#,
+            id,
+            user_id.0,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(updated.rows_affected() == 1)
+    }
+
+    #[instrument(skip(self))]
+    pub async fn entries(&self, vault_id: Uuid, limit: i64) -> Result<Vec<VaultEntry>> {
+        let rows = sqlx::query!(
+            r#"
+            SELECT id, entry_type as "entry_type: VaultEntryType", amount_sats, vault_balance_sats, created_at
+            FROM vault_entries
+            WHERE vault_id = $1
+            ORDER BY created_at DESC
+            LIMIT $2
+            "#,
+            vault_id,
+            limit,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|r| VaultEntry {
+                id: r.id,
+                entry_type: r.entry_type,
+                amount_sats: SatAmount::new(r.amount_sats),
+                vault_balance_sats: SatAmount::new(r.vault_balance_sats),
+                created_at: r.created_at,
+            })
+            .collect())
+    }
+
+    #[instrument(skip(self))]
+    pub async fn pending_withdrawals(&self, vault_id: Uuid) -> Result<Vec<VaultWithdrawal>> {
+        let rows = sqlx::query!(
+            r#"
+            SELECT id, vault_id, amount_sats, status as "status: VaultWithdrawalStatus", available_at,
+                   failure_reason, created_at, resolved_at
+            FROM vault_withdrawals
+            WHERE vault_id = $1 AND status = 'pending'
+            ORDER BY available_at
+            "#,
+            vault_id,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|r| VaultWithdrawal {
+                id: r.id,
+                vault_id: r.vault_id,
+                amount_sats: SatAmount::new(r.amount_sats),
+                status: r.status,
+                available_at: r.available_at,
+                failure_reason: r.failure_reason,
+                created_at: r.created_at,
+                resolved_at: r.resolved_at,
+            })
+            .collect())
+    }
+
+    #[instrument(skip(self))]
+    pub async fn pin_hash(&self, user_id: UserId) -> Result<String> {
+        let row = sqlx::query!("SELECT pin_hash FROM users WHERE id = $1", user_id.0)
+            .fetch_optional(&self.pool)
+            .await?
+            .ok_or_else(|| AppError::User {
+                message: "User not found".to_string(),
+            })?;
+
+        Ok(row.pin_hash)
+    }
+
+    async fn credit_wallet(conn: &mut PgConnection, user_id: UserId, amount_sats: SatAmount) -> Result<()> {
+        let credited = sqlx::query!(
+            "UPDATE wallets SET balance_sats = balance_sats + $2 WHERE user_id = $1",
+            user_id.0,
+            amount_sats.as_i64(),
+        )
+        .execute(conn)
+        .await?;
+
+        if credited.rows_affected() != 1 {
+            return Err(AppError::Internal(anyhow::anyhow!("No wallet for user {}", user_id)));
+        }
+        Ok(())
+    }
+
+    /// Record a move in the vault ledger and book it as a wallet transaction,
+    /// so statements rebuilt from transactions see the spendable balance change
+    async fn record_entry(
+        conn: &mut PgConnection,
+        vault_id: Uuid,
+        vault_name: &str,
+        user_id: UserId,
+        entry_type: VaultEntryType,
+        amount_sats: SatAmount,
+        vault_balance_sats: i64,
+    ) -> Result<Uuid> {
+        let transaction_id = sqlx::query_scalar!(
+            r#"
+            INSERT INTO transactions (user_id, type, status, amount_sats, fee_sats, metadata, completed_at)
+            VALUES ($1, $2, 'completed', $3, 0, $4, NOW())
+            RETURNING id
+            "#,
+            user_id.0,
+            entry_type.transaction_type() as _,
+            amount_sats.as_i64(),
+            serde_json::json!({
+                "vault_id": vault_id,
+                "description": entry_type.description(vault_name),
+            }),
+        )
+        .fetch_one(&mut *conn)
+        .await?;
+
+        let row = sqlx::query!(
+            r#"
+            INSERT INTO vault_entries (vault_id, user_id, entry_type, amount_sats, vault_balance_sats, transaction_id)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            RETURNING id
+            "#,
+            vault_id,
+            user_id.0,
+            entry_type as _,
+            amount_sats.as_i64(),
+            vault_balance_sats,
+            transaction_id,
+        )
+        .fetch_one(conn)
+        .await?;
+
+        Ok(row.id)
+    }
+}
+
+/// Managing vaults and moving sats in and out
+pub struct VaultService {
+    repository: Arc<VaultRepository>,
+}
+
+impl VaultService {
+    pub fn new(repository: Arc<VaultRepository>) -> Self {
+        Self { repository }
+    }
+
+    #[instrument(skip(self, request))]
+    pub async fn create(&self, user_id: UserId, request: CreateVaultRequest) -> Result<Vault> {
+        request.validate().map_err(|e| AppError::Validation {
+            message: format!("Invalid vault: {}", e),
+        })?;
+        if let Some(lock_until) = request.lock_until {
+            check_lock_change(None, lock_until, Utc::now())?;
+        }
+        if self.repository.count_active(user_id).await? >= MAX_VAULTS {
+            return Err(AppError::Validation {
+                message: format!("You can have at most {} vaults", MAX_VAULTS),
+            });
+        }
+
+        let name = request.name.trim();
+        let id = self
+            .repository
+            .create(user_id, name, request.target_sats, request.lock_until)
+            .await?
+            .ok_or_else(|| AppError::Conflict {
+                message: format!("You already have a vault called {}", name),
+            })?;
+
+        info!("User {} opened vault {}", user_id, id);
+        self.find(user_id, id).await
+    }
+
+    #[instrument(skip(self))]
+    pub async fn get(&self, user_id: UserId, id: Uuid) -> Result<VaultDetails> {
+        let vault = self.find(user_id, id).await?;
+        let entries = self.repository.entries(id, RECENT_ENTRIES).await?;
+        let pending_withdrawals = self.repository.pending_withdrawals(id).await?;
+        Ok(VaultDetails {
+            vault,
+            entries,
+            pending_withdrawals,
+        })
+    }
+
+    #[instrument(skip(self))]
+    pub async fn list(&self, user_id: UserId) -> Result<Vec<Vault>> {
+        self.repository.list(user_id).await
+    }
+
+    /// Sats held in the user's vaults (shown on the wallet balance)
+    pub async fn total(&self, user_id: UserId) -> Result<SatAmount> {
+        self.repository.total(user_id).await
+    }
+
+    #[instrument(skip(self, request))]
+    pub async fn update(&self, user_id: UserId, id: Uuid, request: UpdateVaultRequest) -> Result<Vault> {
+        request.validate().map_err(|e| AppError::Validation {
+            message: format!("Invalid vault: {}", e),
+        })?;
+
+        let vault = self.find(user_id, id).await?;
+        if let Some(lock_until) = request.lock_until {
+            check_lock_change(vault.lock_until, lock_until, Utc::now())?;
+        }
+
+        let name = request.name.as_deref().map(str::trim).unwrap_or(&vault.name);
+        let updated = self
+            .repository
+            .update(
+                user_id,
+                id,
+                name,
+                request.target_sats.or(vault.target_sats),
+                request.lock_until.or(vault.lock_until),
+            )
+            .await?;
+        if !updated {
+            return Err(Self::closed());
+        }
+        self.find(user_id, id).await
+    }
+
+    #[instrument(skip(self, request))]
+    pub async fn deposit(&self, user_id: UserId, id: Uuid, request: VaultDepositRequest) -> Result<Vault> {
+        request.validate().map_err(|e| AppError::Validation {
+            message: format!("Invalid amount: {}", e),
+        })?;
+
+        self.repository
+            .deposit(user_id, id, SatAmount::new(request.amount_sats))
+            .await?;
+        self.find(user_id, id).await
+    }
+
+    /// Withdraw at once from an unlocked vault, or schedule an early withdrawal
+    /// (PIN required) from a locked one
+    #[instrument(skip(self, request))]
+    pub async fn withdraw(&self, user_id: UserId, id: Uuid, request: VaultWithdrawRequest) -> Result<VaultWithdrawResponse> {
+        request.validate().map_err(|e| AppError::Validation {
+            message: format!("Invalid withdrawal: {}", e),
+        })?;
+
+        let vault = self.find(user_id, id).await?;
+        let amount_sats = SatAmount::new(request.amount_sats);
+        if vault.status != VaultStatus::Active {
+            return Err(Self::closed());
+        }
+        if vault.balance_sats.as_i64() < amount_sats.as_i64() {
+            return Err(insufficient("vault"));
+        }
+
+        let now = Utc::now();
+        if !is_locked(vault.lock_until, now) {
+            self.repository.withdraw(user_id, id, amount_sats).await?;
+            return Ok(VaultWithdrawResponse {
+                vault: self.find(user_id, id).await?,
+                scheduled: None,
+            });
+        }
+
+        let pin = request.pin.as_deref().ok_or_else(|| AppError::Validation {
+            message: "This vault is locked; enter your PIN to withdraw early".to_string(),
+        })?;
+        let pin_hash = self.repository.pin_hash(user_id).await?;
+        if !PinService::verify_pin(pin, &pin_hash)? {
+            return Err(AppError::invalid_pin());
+        }
+
+        let available_at = now + Duration::hours(COOLING_OFF_HOURS);
+        let withdrawal = self
+            .repository
+            .schedule_withdrawal(&vault, amount_sats, available_at)
+            .await?;
+
+        info!("User {} scheduled early withdrawal {} from vault {}", user_id, withdrawal.id, id);
+        Ok(VaultWithdrawResponse {
+            vault,
+            scheduled: Some(withdrawal),
+        })
+    }
+
+    #[instrument(skip(self))]
+    pub async fn cancel_withdrawal(&self, user_id: UserId, vault_id: Uuid, withdrawal_id: Uuid) -> Result<VaultDetails> {
+        if !self
+            .repository
+            .cancel_withdrawal(user_id, vault_id, withdrawal_id)
+            .await?
+        {
+            return Err(AppError::Conflict {
+                message: "Only pending withdrawals can be cancelled".to_string(),
+            });
+        }
+        self.get(user_id, vault_id).await
+    }
+
+    /// Close an empty vault
+    #[instrument(skip(self))]
+    pub async fn close(&self, user_id: UserId, id: Uuid) -> Result<Vault> {
+        let vault = self.find(user_id, id).await?;
+        if !self.repository.close(user_id, id).await? {
+            let message = match vault.status {
+                VaultStatus::Closed => "The vault is already closed",
+                VaultStatus::Active => "Withdraw everything from the vault before closing it",
+            };
+            return Err(AppError::Conflict {
+                message: message.to_string(),
+            });
+        }
+        self.find(user_id, id).await
+    }
+
+    /// Complete early withdrawals whose cooling-off delay has ended
+    pub async fn complete_due_withdrawals(&self, limit: i64) -> Result<usize> {
+        self.repository.complete_due_withdrawals(limit).await
+    }
+
+    async fn find(&self, user_id: UserId, id: Uuid) -> Result<Vault> {
+        self.repository
+            .get(user_id, id)
+            .await?
+            .ok_or_else(|| AppError::Payment {
+                message: "Vault not found".to_string(),
+            })
+    }
+
+    fn closed() -> AppError {
+        AppError::Conflict {
+            message: "The vault is closed".to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_locks_can_only_be_extended() {
+        let now = Utc::now();
+        let in_a_month = now + Duration::days(30);
+
+        assert!(check_lock_change(None, in_a_month, now).is_ok());
+        assert!(check_lock_change(None, now - Duration::days(1), now).is_err());
+        assert!(check_lock_change(Some(in_a_month), in_a_month + Duration::days(30), now).is_ok());
+        assert!(check_lock_change(Some(in_a_month), now + Duration::days(1), now).is_err());
+        // An expired lock can be set to any future date
+        assert!(check_lock_change(Some(now - Duration::days(1)), now + Duration::days(1), now).is_ok());
+
+        assert!(is_locked(Some(in_a_month), now));
+        assert!(!is_locked(Some(now - Duration::seconds(1)), now));
+        assert!(!is_locked(None, now));
+    }
+
+    #[test]
+    fn test_progress() {
+        assert_eq!(progress_percent(25_000, Some(100_000)), Some(Decimal::new(250, 1)));
+        assert_eq!(progress_percent(1, Some(3)), Some(Decimal::new(333, 1)));
+        // Saving past the target shows as complete
+        assert_eq!(progress_percent(150_000, Some(100_000)), Some(Decimal::from(100)));
+        assert_eq!(progress_percent(150_000, None), None);
+    }
+
+    #[test]
+    fn test_entries_are_booked_as_wallet_transactions() {
+        assert_eq!(VaultEntryType::Deposit.transaction_type(), TransactionType::VaultDeposit);
+        assert_eq!(VaultEntryType::Withdrawal.transaction_type(), TransactionType::VaultWithdrawal);
+        assert_eq!(VaultEntryType::EarlyWithdrawal.transaction_type(), TransactionType::VaultWithdrawal);
+        assert_eq!(VaultEntryType::Deposit.description("School fees"), "To vault School fees");
+        assert_eq!(VaultEntryType::EarlyWithdrawal.description("School fees"), "From vault School fees");
+    }
+}
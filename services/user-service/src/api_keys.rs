@@ -0,0 +1,302 @@
+/// API key management
+///
+/// Users create long-lived keys for scripts and integrations instead of copying
+/// short-lived access tokens. Each key lists its scopes and may be limited to an
+/// IP allowlist, its own rate limit and an expiry date. The key itself is only
+/// shown once; the gateway authenticates requests carrying it.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use shared_auth::api_keys::{api_key_lookup, generate_api_key, hash_api_key, ApiKeyScope, IpNetwork};
+use shared_auth::PinService;
+use shared_errors::{AppError, Result};
+use shared_types::*;
+use sqlx::PgPool;
+use tracing::{info, instrument};
+use uuid::Uuid;
+use validator::Validate;
+
+/// Active API keys per user
+const MAX_API_KEYS: i64 = 10;
+/// Addresses or ranges in one key's allowlist
+const MAX_ALLOWLIST_ENTRIES: usize = 20;
+/// Requests per minute when the user doesn't say
+const DEFAULT_RATE_LIMIT_PER_MINUTE: i32 = 60;
+
+/// Request for a new API key
+#[derive(Debug, Deserialize, Validate)]
+pub struct CreateApiKeyRequest {
+    #[validate(length(min = 1, max = 50))]
+    pub name: String,
+    /// What the key may do (at least one)
+    pub scopes: Vec<ApiKeyScope>,
+    /// IP addresses or CIDR ranges the key may be used from (empty = any)
+    #[serde(default)]
+    pub ip_allowlist: Vec<String>,
+    #[validate(range(min = 1, max = 1000))]
+    pub rate_limit_per_minute: Option<i32>,
+    pub expires_at: Option<DateTime<Utc>>,
+    /// Creating a key needs the account PIN
+    pub pin: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ApiKey {
+    pub id: Uuid,
+    pub name: String,
+    /// First characters of the key, to tell keys apart
+    pub key_prefix: String,
+    pub scopes: Vec<ApiKeyScope>,
+    pub ip_allowlist: Vec<String>,
+    pub rate_limit_per_minute: i32,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub revoked_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// A new API key; the key itself is only returned here
+#[derive(Debug, Serialize)]
+pub struct CreatedApiKey {
+    #[serde(flatten)]
+    pub api_key: ApiKey,
+    pub key: String,
+}
+
+/// Allowlist entries in canonical form (`41.90.0.0/16`, single addresses as /32 or /128)
+pub fn normalize_ip_allowlist(allowlist: &[String]) -> Result<Vec<String>> {
+    if allowlist.len() > MAX_ALLOWLIST_ENTRIES {
+        return Err(AppError::Validation {
+            message: format!("An API key can have at most {} allowed IP ranges", MAX_ALLOWLIST_ENTRIES),
+        });
+    }
+
+    let mut networks = Vec::with_capacity(allowlist.len());
+    for entry in allowlist {
+        let network = IpNetwork::parse(entry).ok_or_else(|| AppError::Validation {
+            message: format!("Invalid IP address or range: {}", entry),
+        })?;
+        let network = network.to_string();
+        if !networks.contains(&network) {
+            networks.push(network);
+        }
+    }
+    Ok(networks)
+}
+
+fn parse_scopes(scopes: Vec<String>) -> Vec<ApiKeyScope> {
+    scopes.iter().filter_map(|scope| ApiKeyScope::parse(scope)).collect()
+}
+
+/// API key repository
+pub struct ApiKeyRepository {
+    pool: PgPool,
+}
+
+impl ApiKeyRepository {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+
+    #[instrument(skip(self, key_hash, api_key))]
+    pub async fn create(&self, user_id: UserId, key_hash: &str, api_key: &ApiKey) -> Result<Uuid> {
+        let scopes: Vec<String> = api_key.scopes.iter().map(|scope| scope.as_str().to_string()).collect();
+        let id = sqlx::query_scalar!(
+            r#"
+            INSERT INTO api_keys (user_id, name, key_prefix, key_hash, scopes, ip_allowlist, rate_limit_per_minute, expires_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+            RETURNING id
+            "#,
+            user_id.0,
+            api_key.name,
+            api_key.key_prefix,
+            key_hash,
+            &scopes,
+            &api_key.ip_allowlist,
+            api_key.rate_limit_per_minute,
+            api_key.expires_at,
+        )
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(id)
+    }
+
+    /// Keys that haven't been revoked or expired
+    #[instrument(skip(self))]
+    pub async fn count_active(&self, user_id: UserId) -> Result<i64> {
+        let count = sqlx::query_scalar!(
+            r#"
+            SELECT COUNT(*) AS "count!" FROM api_keys
+            WHERE user_id = $1 AND revoked_at IS NULL AND (expires_at IS NULL OR expires_at > NOW())
+            "#,
+            user_id.0,
+        )
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(count)
+    }
+
+    #[instrument(skip(self))]
+    pub async fn list(&self, user_id: UserId) -> Result<Vec<ApiKey>> {
+        let rows = sqlx::query!(
+            r#"
+            SELECT id, name, key_prefix, scopes, ip_allowlist, rate_limit_per_minute,
+                   expires_at, last_used_at, revoked_at, created_at
+            FROM api_keys
+            WHERE user_id = $1
+            ORDER BY revoked_at IS NOT NULL, created_at DESC
+            "#,
+            user_id.0,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|r| ApiKey {
+                id: r.id,
+                name: r.name,
+                key_prefix: r.key_prefix,
+                scopes: parse_scopes(r.scopes),
+                ip_allowlist: r.ip_allowlist,
+                rate_limit_per_minute: r.rate_limit_per_minute,
+                expires_at: r.expires_at,
+                last_used_at: r.last_used_at,
+                revoked_at: r.revoked_at,
+                created_at: r.created_at,
+            })
+            .collect())
+    }
+
+    #[instrument(skip(self))]
+    pub async fn revoke(&self, user_id: UserId, id: Uuid) -> Result<bool> {
+        let updated = sqlx::query!(
+            "UPDATE api_keys SET revoked_at = NOW() WHERE id = $1 AND user_id = $2 AND revoked_at IS NULL",
+            id,
+            user_id.0,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(updated.rows_affected() == 1)
+    }
+
+    #[instrument(skip(self))]
+    pub async fn pin_hash(&self, user_id: UserId) -> Result<String> {
+        let row = sqlx::query!("SELECT pin_hash FROM users WHERE id = $1", user_id.0)
+            .fetch_optional(&self.pool)
+            .await?
+            .ok_or_else(|| AppError::User {
+                message: "User not found".to_string(),
+            })?;
+
+        Ok(row.pin_hash)
+    }
+}
+
+/// API key service
+pub struct ApiKeyService {
+    repository: ApiKeyRepository,
+}
+
+impl ApiKeyService {
+    pub fn new(repository: ApiKeyRepository) -> Self {
+        Self { repository }
+    }
+
+    /// Create a key after checking the PIN
+    #[instrument(skip(self, request))]
+    pub async fn create(&self, user_id: UserId, request: CreateApiKeyRequest) -> Result<CreatedApiKey> {
+        request.validate().map_err(|e| AppError::Validation {
+            message: format!("Invalid API key: {}", e),
+        })?;
+        if request.scopes.is_empty() {
+            return Err(AppError::Validation {
+                message: "An API key needs at least one scope".to_string(),
+            });
+        }
+        if request.expires_at.is_some_and(|expires_at| expires_at <= Utc::now()) {
+            return Err(AppError::Validation {
+                message: "Expiry must be in the future".to_string(),
+            });
+        }
+        let ip_allowlist = normalize_ip_allowlist(&request.ip_allowlist)?;
+
+        let pin_hash = self.repository.pin_hash(user_id).await?;
+        if !PinService::verify_pin(&request.pin, &pin_hash)? {
+            return Err(AppError::invalid_pin());
+        }
+        if self.repository.count_active(user_id).await? >= MAX_API_KEYS {
+            return Err(AppError::Validation {
+                message: format!("You can have at most {} active API keys", MAX_API_KEYS),
+            });
+        }
+
+        let scopes = ApiKeyScope::ALL
+            .into_iter()
+            .filter(|scope| request.scopes.contains(scope))
+            .collect();
+
+        let key = generate_api_key();
+        let mut api_key = ApiKey {
+            id: Uuid::nil(),
+            name: request.name.trim().to_string(),
+            key_prefix: api_key_lookup(&key).expect("generated keys have a lookup prefix").to_string(),
+            scopes,
+            ip_allowlist,
+            rate_limit_per_minute: request.rate_limit_per_minute.unwrap_or(DEFAULT_RATE_LIMIT_PER_MINUTE),
+            expires_at: request.expires_at,
+            last_used_at: None,
+            revoked_at: None,
+            created_at: Utc::now(),
+        };
+        api_key.id = self.repository.create(user_id, &hash_api_key(&key), &api_key).await?;
+
+        info!("User {} created API key {}", user_id, api_key.id);
+        Ok(CreatedApiKey { api_key, key })
+    }
+
+    #[instrument(skip(self))]
+    pub async fn list(&self, user_id: UserId) -> Result<Vec<ApiKey>> {
+        self.repository.list(user_id).await
+    }
+
+    #[instrument(skip(self))]
+    pub async fn revoke(&self, user_id: UserId, id: Uuid) -> Result<()> {
+        if !self.repository.revoke(user_id, id).await? {
+            return Err(AppError::User {
+                message: "API key not found".to_string(),
+            });
+        }
+        info!("User {} revoked API key {}", user_id, id);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_ip_allowlist() {
+        let allowlist = vec![
+            "41.90.12.7".to_string(),
+            " 41.90.0.0/16".to_string(),
+            "41.90.12.7/32".to_string(),
+        ];
+        assert_eq!(
+            normalize_ip_allowlist(&allowlist).unwrap(),
+            vec!["41.90.12.7/32".to_string(), "41.90.0.0/16".to_string()]
+        );
+        assert!(normalize_ip_allowlist(&["41.90.0.0/40".to_string()]).is_err());
+        assert!(normalize_ip_allowlist(&vec!["10.0.0.1".to_string(); 21]).is_err());
+        assert!(normalize_ip_allowlist(&[]).unwrap().is_empty());
+
+        assert_eq!(
+            parse_scopes(vec!["create:invoice".to_string(), "retired:scope".to_string()]),
+            vec![ApiKeyScope::CreateInvoice]
+        );
+    }
+}
@@ -41,6 +41,12 @@ pub struct VerifyOtpRequest {
     /// Preferred Lightning username (will become username@pesa.co.ke)
     #[validate(length(min = 3, max = 30), regex = "USERNAME_REGEX")]
     pub lightning_username: String,
+    /// Personal (default) or merchant; can't be changed later
+    pub account_type: Option<AccountType>,
+    /// Name of the device, shown in the list of signed-in devices
+    #[validate(length(max = 100))]
+    pub device_name: Option<String>,
+    pub platform: Option<DevicePlatform>,
 }
 
 /// Response after successful OTP verification
@@ -61,6 +67,10 @@ pub struct LoginRequest {
     /// User's PIN
     #[validate(length(min = 4, max = 6))]
     pub pin: String,
+    /// Name of the device, shown in the list of signed-in devices
+    #[validate(length(max = 100))]
+    pub device_name: Option<String>,
+    pub platform: Option<DevicePlatform>,
 }
 
 /// Response after successful login
@@ -93,6 +103,7 @@ pub struct UserProfile {
     pub lightning_username: String,
     pub lightning_address: LightningAddress,
     pub full_name: Option<String>,
+    pub account_type: AccountType,
     pub kyc_status: KycStatus,
     pub kyc_tier: KycTier,
     pub created_at: chrono::DateTime<chrono::Utc>,
@@ -122,6 +133,7 @@ pub struct User {
     pub pin_hash: String,
     pub lightning_username: String,
     pub full_name: Option<String>,
+    pub account_type: AccountType,
     pub kyc_status: KycStatus,
     pub kyc_tier: KycTier,
     pub created_at: chrono::DateTime<chrono::Utc>,
@@ -140,7 +152,7 @@ pub struct OtpCode {
     pub created_at: chrono::DateTime<chrono::Utc>,
 }
 
-/// User session for authentication
+/// User session for authentication (one per signed-in device)
 #[derive(Debug, Clone)]
 pub struct UserSession {
     pub id: Uuid,
@@ -148,9 +160,75 @@ pub struct UserSession {
     pub refresh_token_hash: String,
     pub expires_at: chrono::DateTime<chrono::Utc>,
     pub device_fingerprint: serde_json::Value,
+    pub device_name: Option<String>,
+    pub platform: DevicePlatform,
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+    pub last_seen_at: chrono::DateTime<chrono::Utc>,
     pub created_at: chrono::DateTime<chrono::Utc>,
 }
 
+/// Kind of device a session was started on
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[sqlx(type_name = "device_platform", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum DevicePlatform {
+    Android,
+    Ios,
+    Web,
+    #[default]
+    Other,
+}
+
+/// Where a request came from, as reported by the load balancer
+#[derive(Debug, Clone, Default)]
+pub struct ClientInfo {
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+impl ClientInfo {
+    pub fn from_headers(headers: &axum::http::HeaderMap) -> Self {
+        let ip_address = headers
+            .get("x-forwarded-for")
+            .or_else(|| headers.get("x-real-ip"))
+            .and_then(|h| h.to_str().ok())
+            .and_then(|value| value.split(',').next())
+            .and_then(|ip| ip.trim().parse::<std::net::IpAddr>().ok())
+            .map(|ip| ip.to_string());
+        let user_agent = headers
+            .get("user-agent")
+            .and_then(|h| h.to_str().ok())
+            .map(|agent| agent.chars().take(MAX_USER_AGENT_LENGTH).collect());
+
+        Self { ip_address, user_agent }
+    }
+}
+
+/// Longest user agent kept with a session
+const MAX_USER_AGENT_LENGTH: usize = 255;
+
+/// Signed-in device, as listed to its owner
+#[derive(Debug, Serialize)]
+pub struct SessionResponse {
+    pub id: Uuid,
+    pub device_name: Option<String>,
+    pub platform: DevicePlatform,
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+    /// Whether this is the session making the request
+    pub current: bool,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    pub last_seen_at: chrono::DateTime<chrono::Utc>,
+    pub expires_at: chrono::DateTime<chrono::Utc>,
+}
+
+/// Result of signing out every other device
+#[derive(Debug, Serialize)]
+pub struct RevokeSessionsResponse {
+    pub revoked: u64,
+}
+
 // Validation regex patterns
 lazy_static::lazy_static! {
     /// Phone number validation (E.164 format)
@@ -168,6 +246,7 @@ impl User {
         pin_hash: String,
         lightning_username: String,
         full_name: Option<String>,
+        account_type: AccountType,
     ) -> Self {
         Self {
             id: UserId::new(),
@@ -175,6 +254,7 @@ impl User {
             pin_hash,
             lightning_username,
             full_name,
+            account_type,
             kyc_status: KycStatus::None,
             kyc_tier: KycTier::Tier0,
             created_at: chrono::Utc::now(),
@@ -195,6 +275,7 @@ impl User {
             lightning_username: self.lightning_username.clone(),
             lightning_address: self.lightning_address(),
             full_name: self.full_name.clone(),
+            account_type: self.account_type,
             kyc_status: self.kyc_status.clone(),
             kyc_tier: self.kyc_tier.clone(),
             created_at: self.created_at,
@@ -208,6 +289,23 @@ impl User {
     }
 }
 
+impl UserSession {
+    /// Listing entry; `current_session` is the session of the request
+    pub fn to_response(&self, current_session: Option<Uuid>) -> SessionResponse {
+        SessionResponse {
+            id: self.id,
+            device_name: self.device_name.clone(),
+            platform: self.platform,
+            ip_address: self.ip_address.clone(),
+            user_agent: self.user_agent.clone(),
+            current: current_session == Some(self.id),
+            created_at: self.created_at,
+            last_seen_at: self.last_seen_at,
+            expires_at: self.expires_at,
+        }
+    }
+}
+
 /// Reserved usernames that users cannot register
 const RESERVED_USERNAMES: &[&str] = &[
     "admin", "support", "help", "api", "www", "mail", "ftp", 
@@ -245,8 +343,26 @@ mod tests {
             "pin_hash".to_string(),
             "john".to_string(),
             Some("John Doe".to_string()),
+            AccountType::Personal,
         );
         
         assert_eq!(user.lightning_address().0, "john@pesa.co.ke");
     }
+
+    #[test]
+    fn test_client_info_from_headers() {
+        let mut headers = axum::http::HeaderMap::new();
+        headers.insert("x-forwarded-for", "41.90.64.12, 10.0.0.1".parse().unwrap());
+        headers.insert("user-agent", "PesaBit/2.3 (Android 14)".parse().unwrap());
+
+        let client = ClientInfo::from_headers(&headers);
+        assert_eq!(client.ip_address.as_deref(), Some("41.90.64.12"));
+        assert_eq!(client.user_agent.as_deref(), Some("PesaBit/2.3 (Android 14)"));
+
+        headers.insert("x-forwarded-for", "not-an-ip".parse().unwrap());
+        headers.insert("user-agent", "a".repeat(400).parse().unwrap());
+        let client = ClientInfo::from_headers(&headers);
+        assert_eq!(client.ip_address, None);
+        assert_eq!(client.user_agent.map(|agent| agent.len()), Some(MAX_USER_AGENT_LENGTH));
+    }
 }
\ No newline at end of file
@@ -6,17 +6,22 @@
 /// - PIN-based authentication
 /// - Profile management
 /// - Lightning address creation
+/// - SMS delivery reports
+/// - API keys for programmatic access
+/// - Signed-in devices (sessions) and remote sign-out
 
 use axum::{
-    extract::{Path, State},
-    http::StatusCode,
+    extract::{Path, Query, State},
+    http::{HeaderMap, StatusCode},
     response::Json,
-    routing::{get, patch, post},
-    Router,
+    routing::{delete, get, patch, post},
+    Form, Router,
 };
 use shared_auth::{AuthUser, JwtService, OtpService, PinService};
+use shared_config::AppConfig;
 use shared_database::DatabaseConfig;
 use shared_errors::{AppError, Result};
+use shared_events::OutboxRelay;
 use shared_tracing::init_tracing;
 use shared_types::*;
 use sqlx::PgPool;
@@ -25,18 +30,24 @@ use tokio::net::TcpListener;
 use tower_http::cors::CorsLayer;
 use tracing::{info, instrument};
 
+mod api_keys;
 mod domain;
 mod repository;
 mod service;
+mod sms;
 
+use api_keys::*;
 use domain::*;
 use repository::*;
 use service::*;
+use sms::*;
 
 /// Application state shared across all handlers
 #[derive(Clone)]
 pub struct AppState {
     pub user_service: Arc<UserService>,
+    pub sms_client: Arc<SmsClient>,
+    pub api_key_service: Arc<ApiKeyService>,
     pub db: PgPool,
 }
 
@@ -45,6 +56,9 @@ async fn main() -> Result<()> {
     // Initialize logging first
     init_tracing("user-service");
 
+    // Load configuration
+    let config = AppConfig::from_env()?;
+
     // Connect to database
     let db = shared_database::init().await?;
     
@@ -52,17 +66,33 @@ async fn main() -> Result<()> {
     let user_repository = Arc::new(UserRepository::new(db.clone()));
     let otp_repository = Arc::new(OtpRepository::new(db.clone()));
     let session_repository = Arc::new(SessionRepository::new(db.clone()));
-    let sms_client = Arc::new(SmsClient::new());
+    let sms_message_repository = Arc::new(SmsMessageRepository::new(db.clone()));
+
+    // Send real SMS in production, or in development once credentials are configured
+    let sms_provider: Arc<dyn SmsProvider> = if config.is_production() || config.sms.api_key != "your_sms_api_key" {
+        Arc::new(AfricasTalkingProvider::new(config.sms.clone()))
+    } else {
+        Arc::new(LoggingSmsProvider)
+    };
+    let sms_client = Arc::new(SmsClient::new(sms_provider, sms_message_repository));
     
     let user_service = Arc::new(UserService::new(
         user_repository,
         otp_repository, 
         session_repository,
-        sms_client,
+        sms_client.clone(),
     ));
 
+    let api_key_service = Arc::new(ApiKeyService::new(ApiKeyRepository::new(db.clone())));
+
+    // Publish domain events recorded in the outbox to Redis Streams
+    let outbox_relay = OutboxRelay::from_env(db.clone())?;
+    tokio::spawn(outbox_relay.run());
+
     let state = AppState {
         user_service,
+        sms_client,
+        api_key_service,
         db,
     };
 
@@ -75,7 +105,12 @@ async fn main() -> Result<()> {
         .route("/auth/refresh", post(refresh_token))
         .route("/users/me", get(get_profile))
         .route("/users/me", patch(update_profile))
+        .route("/users/me/api-keys", post(create_api_key).get(list_api_keys))
+        .route("/users/me/api-keys/:id", delete(revoke_api_key))
+        .route("/users/me/sessions", get(list_sessions).delete(revoke_other_sessions))
+        .route("/users/me/sessions/:id", delete(revoke_session))
         .route("/users/:user_id/lightning-address", get(get_lightning_address))
+        .route("/sms/delivery-reports", post(sms_delivery_report))
         .layer(CorsLayer::permissive()) // Allow cross-origin requests
         .layer(shared_tracing::trace_id_layer()) // Add trace IDs to requests
         .with_state(state);
@@ -122,9 +157,10 @@ async fn register(
 #[instrument(skip(state))]
 async fn verify_otp(
     State(state): State<AppState>,
+    headers: HeaderMap,
     Json(request): Json<VerifyOtpRequest>,
 ) -> Result<Json<VerifyOtpResponse>> {
-    let response = state.user_service.verify_otp(request).await?;
+    let response = state.user_service.verify_otp(request, ClientInfo::from_headers(&headers)).await?;
     Ok(Json(response))
 }
 
@@ -132,9 +168,10 @@ async fn verify_otp(
 #[instrument(skip(state))]
 async fn login(
     State(state): State<AppState>,
+    headers: HeaderMap,
     Json(request): Json<LoginRequest>,
 ) -> Result<Json<LoginResponse>> {
-    let response = state.user_service.login(request).await?;
+    let response = state.user_service.login(request, ClientInfo::from_headers(&headers)).await?;
     Ok(Json(response))
 }
 
@@ -142,9 +179,10 @@ async fn login(
 #[instrument(skip(state))]
 async fn refresh_token(
     State(state): State<AppState>,
+    headers: HeaderMap,
     Json(request): Json<RefreshTokenRequest>,
 ) -> Result<Json<RefreshTokenResponse>> {
-    let response = state.user_service.refresh_token(request).await?;
+    let response = state.user_service.refresh_token(request, ClientInfo::from_headers(&headers)).await?;
     Ok(Json(response))
 }
 
@@ -169,6 +207,73 @@ async fn update_profile(
     Ok(Json(profile))
 }
 
+/// Create an API key (the key is only shown in this response)
+#[instrument(skip(state, request))]
+async fn create_api_key(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    Json(request): Json<CreateApiKeyRequest>,
+) -> Result<(StatusCode, Json<CreatedApiKey>)> {
+    let api_key = state.api_key_service.create(auth_user.user_id, request).await?;
+    Ok((StatusCode::CREATED, Json(api_key)))
+}
+
+/// List the user's API keys
+#[instrument(skip(state))]
+async fn list_api_keys(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+) -> Result<Json<Vec<ApiKey>>> {
+    let api_keys = state.api_key_service.list(auth_user.user_id).await?;
+    Ok(Json(api_keys))
+}
+
+/// Revoke an API key
+#[instrument(skip(state))]
+async fn revoke_api_key(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    Path(key_id): Path<String>,
+) -> Result<StatusCode> {
+    let key_id = key_id.parse::<uuid::Uuid>()
+        .map_err(|_| AppError::Validation { message: "Invalid API key ID".to_string() })?;
+    state.api_key_service.revoke(auth_user.user_id, key_id).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// List the devices the user is signed in on
+#[instrument(skip(state))]
+async fn list_sessions(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+) -> Result<Json<Vec<SessionResponse>>> {
+    let sessions = state.user_service.list_sessions(auth_user.user_id, auth_user.session_id).await?;
+    Ok(Json(sessions))
+}
+
+/// Sign one device out
+#[instrument(skip(state))]
+async fn revoke_session(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    Path(session_id): Path<String>,
+) -> Result<StatusCode> {
+    let session_id = session_id.parse::<uuid::Uuid>()
+        .map_err(|_| AppError::Validation { message: "Invalid session ID".to_string() })?;
+    state.user_service.revoke_session(auth_user.user_id, session_id).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Sign out every device except this one
+#[instrument(skip(state))]
+async fn revoke_other_sessions(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+) -> Result<Json<RevokeSessionsResponse>> {
+    let response = state.user_service.revoke_other_sessions(auth_user.user_id, auth_user.session_id).await?;
+    Ok(Json(response))
+}
+
 /// Get user's Lightning address
 #[instrument(skip(state))]
 async fn get_lightning_address(
@@ -180,4 +285,16 @@ async fn get_lightning_address(
     
     let response = state.user_service.get_lightning_address(UserId(user_id)).await?;
     Ok(Json(response))
-}
\ No newline at end of file
+}
+
+/// SMS delivery report webhook (called by Africa's Talking as messages are delivered)
+/// Refused unless the URL carries `SMS_DELIVERY_REPORT_TOKEN`
+#[instrument(skip(state, params))]
+async fn sms_delivery_report(
+    State(state): State<AppState>,
+    Query(params): Query<DeliveryReportParams>,
+    Form(report): Form<SmsDeliveryReport>,
+) -> Result<StatusCode> {
+    state.sms_client.handle_delivery_report(params.token.as_deref(), report).await?;
+    Ok(StatusCode::OK)
+}
@@ -1,10 +1,13 @@
 /// Repository layer for user data access
 /// 
-/// This module handles all database operations for users, OTP codes, and sessions.
+/// This module handles all database operations for users, OTP codes, sessions and
+/// the SMS message log.
 /// It abstracts the database implementation from the business logic.
 
 use crate::domain::*;
+use crate::sms::{SmsRecipientStatus, SmsStatus};
 use shared_errors::{AppError, Result};
+use shared_events::{record_event, DomainEvent};
 use shared_types::*;
 use sqlx::{PgPool, Row};
 use tracing::{instrument, warn};
@@ -20,13 +23,15 @@ impl UserRepository {
         Self { pool }
     }
 
-    /// Create a new user in the database
+    /// Create a new user in the database and record a `UserRegistered` event
     #[instrument(skip(self, user))]
     pub async fn create(&self, user: &User) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+
         sqlx::query!(
             r#"
-            INSERT INTO users (id, phone_number, pin_hash, lightning_username, full_name, kyc_status, kyc_tier)
-            VALUES ($1, $2, $3, $4, $5, $6, $7)
+            INSERT INTO users (id, phone_number, pin_hash, lightning_username, full_name, kyc_status, kyc_tier, account_type)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
             "#,
             user.id.0,
             user.phone_number.0,
@@ -35,8 +40,9 @@ impl UserRepository {
             user.full_name,
             user.kyc_status as _,
             user.kyc_tier as _,
+            user.account_type as _,
         )
-        .execute(&self.pool)
+        .execute(&mut *tx)
         .await
         .map_err(|e| {
             if e.to_string().contains("users_phone_number_key") {
@@ -52,6 +58,17 @@ impl UserRepository {
             }
         })?;
 
+        record_event(
+            &mut *tx,
+            &DomainEvent::UserRegistered {
+                user_id: user.id,
+                lightning_username: user.lightning_username.clone(),
+            },
+        )
+        .await?;
+
+        tx.commit().await?;
+
         Ok(())
     }
 
@@ -71,6 +88,7 @@ impl UserRepository {
             pin_hash: r.pin_hash,
             lightning_username: r.lightning_username,
             full_name: r.full_name,
+            account_type: r.account_type,
             kyc_status: r.kyc_status,
             kyc_tier: r.kyc_tier,
             created_at: r.created_at,
@@ -94,6 +112,7 @@ impl UserRepository {
             pin_hash: r.pin_hash,
             lightning_username: r.lightning_username,
             full_name: r.full_name,
+            account_type: r.account_type,
             kyc_status: r.kyc_status,
             kyc_tier: r.kyc_tier,
             created_at: r.created_at,
@@ -117,6 +136,7 @@ impl UserRepository {
             pin_hash: r.pin_hash,
             lightning_username: r.lightning_username,
             full_name: r.full_name,
+            account_type: r.account_type,
             kyc_status: r.kyc_status,
             kyc_tier: r.kyc_tier,
             created_at: r.created_at,
@@ -268,26 +288,25 @@ impl SessionRepository {
         Self { pool }
     }
 
-    /// Create or update user session (single device login)
-    #[instrument(skip(self, session))]
-    pub async fn create_or_update(&self, session: &UserSession) -> Result<()> {
+    /// Start a new session (each device gets its own)
+    #[instrument(skip(self, session), fields(session_id = %session.id))]
+    pub async fn create(&self, session: &UserSession) -> Result<()> {
         sqlx::query!(
             r#"
-            INSERT INTO sessions (id, user_id, refresh_token_hash, expires_at, device_fingerprint)
-            VALUES ($1, $2, $3, $4, $5)
-            ON CONFLICT (user_id) 
-            DO UPDATE SET 
-                id = $1,
-                refresh_token_hash = $3,
-                expires_at = $4,
-                device_fingerprint = $5,
-                created_at = NOW()
+            INSERT INTO sessions (id, user_id, refresh_token_hash, expires_at, device_fingerprint,
+                                  device_name, platform, ip_address, user_agent, last_seen_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
             "#,
             session.id,
             session.user_id.0,
             session.refresh_token_hash,
             session.expires_at,
             session.device_fingerprint,
+            session.device_name,
+            session.platform as _,
+            session.ip_address,
+            session.user_agent,
+            session.last_seen_at,
         )
         .execute(&self.pool)
         .await?;
@@ -295,11 +314,17 @@ impl SessionRepository {
         Ok(())
     }
 
-    /// Find session by user ID
+    /// Find one of the user's unexpired sessions
     #[instrument(skip(self))]
-    pub async fn find_by_user_id(&self, user_id: UserId) -> Result<Option<UserSession>> {
+    pub async fn find_active(&self, user_id: UserId, id: Uuid) -> Result<Option<UserSession>> {
         let row = sqlx::query!(
-            "SELECT * FROM sessions WHERE user_id = $1 AND expires_at > NOW()",
+            r#"
+            SELECT id, user_id, refresh_token_hash, expires_at, device_fingerprint, device_name,
+                   platform as "platform: DevicePlatform", ip_address, user_agent, last_seen_at, created_at
+            FROM sessions
+            WHERE id = $1 AND user_id = $2 AND expires_at > NOW()
+            "#,
+            id,
             user_id.0
         )
         .fetch_optional(&self.pool)
@@ -310,29 +335,111 @@ impl SessionRepository {
             user_id: UserId(r.user_id),
             refresh_token_hash: r.refresh_token_hash,
             expires_at: r.expires_at,
-            device_fingerprint: r.device_fingerprint,
+            device_fingerprint: r.device_fingerprint.unwrap_or_default(),
+            device_name: r.device_name,
+            platform: r.platform,
+            ip_address: r.ip_address,
+            user_agent: r.user_agent,
+            last_seen_at: r.last_seen_at,
             created_at: r.created_at,
         }))
     }
 
-    /// Find session by refresh token hash
+    /// The user's unexpired sessions, most recently used first
     #[instrument(skip(self))]
-    pub async fn find_by_token_hash(&self, token_hash: &str) -> Result<Option<UserSession>> {
-        let row = sqlx::query!(
-            "SELECT * FROM sessions WHERE refresh_token_hash = $1 AND expires_at > NOW()",
-            token_hash
+    pub async fn list(&self, user_id: UserId) -> Result<Vec<UserSession>> {
+        let rows = sqlx::query!(
+            r#"
+            SELECT id, user_id, refresh_token_hash, expires_at, device_fingerprint, device_name,
+                   platform as "platform: DevicePlatform", ip_address, user_agent, last_seen_at, created_at
+            FROM sessions
+            WHERE user_id = $1 AND expires_at > NOW()
+            ORDER BY last_seen_at DESC
+            "#,
+            user_id.0
         )
-        .fetch_optional(&self.pool)
+        .fetch_all(&self.pool)
         .await?;
 
-        Ok(row.map(|r| UserSession {
-            id: r.id,
-            user_id: UserId(r.user_id),
-            refresh_token_hash: r.refresh_token_hash,
-            expires_at: r.expires_at,
-            device_fingerprint: r.device_fingerprint,
-            created_at: r.created_at,
-        }))
+        Ok(rows
+            .into_iter()
+            .map(|r| UserSession {
+                id: r.id,
+                user_id: UserId(r.user_id),
+                refresh_token_hash: r.refresh_token_hash,
+                expires_at: r.expires_at,
+                device_fingerprint: r.device_fingerprint.unwrap_or_default(),
+                device_name: r.device_name,
+                platform: r.platform,
+                ip_address: r.ip_address,
+                user_agent: r.user_agent,
+                last_seen_at: r.last_seen_at,
+                created_at: r.created_at,
+            })
+            .collect())
+    }
+
+    /// Record that a session was just used (keeps the old address if none is known)
+    #[instrument(skip(self))]
+    pub async fn touch(&self, id: Uuid, ip_address: Option<&str>) -> Result<()> {
+        sqlx::query!(
+            "UPDATE sessions SET last_seen_at = NOW(), ip_address = COALESCE($2, ip_address) WHERE id = $1",
+            id,
+            ip_address
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Sign one device out; false if the user has no such session
+    #[instrument(skip(self))]
+    pub async fn revoke(&self, user_id: UserId, id: Uuid) -> Result<bool> {
+        let result = sqlx::query!(
+            "DELETE FROM sessions WHERE id = $1 AND user_id = $2",
+            id,
+            user_id.0
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Sign out every device except `keep` (all of them if there's no current session)
+    #[instrument(skip(self))]
+    pub async fn revoke_others(&self, user_id: UserId, keep: Option<Uuid>) -> Result<u64> {
+        let result = sqlx::query!(
+            "DELETE FROM sessions WHERE user_id = $1 AND id IS DISTINCT FROM $2",
+            user_id.0,
+            keep
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    /// Drop the least recently used sessions beyond `max_sessions`
+    #[instrument(skip(self))]
+    pub async fn prune(&self, user_id: UserId, max_sessions: i64) -> Result<u64> {
+        let result = sqlx::query!(
+            r#"
+            DELETE FROM sessions
+            WHERE user_id = $1 AND id NOT IN (
+                SELECT id FROM sessions WHERE user_id = $1
+                ORDER BY last_seen_at DESC
+                LIMIT $2
+            )
+            "#,
+            user_id.0,
+            max_sessions
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected())
     }
 
     /// Delete user session (logout)
@@ -363,4 +470,70 @@ impl SessionRepository {
 
         Ok(result.rows_affected())
     }
-}
\ No newline at end of file
+}
+/// Log of sent SMS messages and their delivery state
+pub struct SmsMessageRepository {
+    pool: PgPool,
+}
+
+impl SmsMessageRepository {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+
+    /// Record the provider's answer for one recipient
+    #[instrument(skip(self, status), fields(message_id = ?status.message_id))]
+    pub async fn record(&self, provider: &str, purpose: &str, status: &SmsRecipientStatus) -> Result<()> {
+        sqlx::query!(
+            r#"
+            INSERT INTO sms_messages
+                (provider, provider_message_id, phone_number, purpose, status, status_code,
+                 cost_amount, cost_currency, failure_reason)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+            "#,
+            provider,
+            status.message_id,
+            status.phone_number,
+            purpose,
+            status.status as _,
+            status.status_code,
+            status.cost.as_ref().map(|cost| cost.amount),
+            status.cost.as_ref().map(|cost| cost.currency.as_str()),
+            (!status.status.is_accepted()).then_some(status.description.as_str()),
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Apply a delivery report, returning false if the message is unknown or already final
+    #[instrument(skip(self))]
+    pub async fn apply_delivery_report(
+        &self,
+        provider_message_id: &str,
+        status: SmsStatus,
+        network_code: Option<&str>,
+        failure_reason: Option<&str>,
+    ) -> Result<bool> {
+        let result = sqlx::query!(
+            r#"
+            UPDATE sms_messages
+            SET status = $2,
+                network_code = COALESCE($3, network_code),
+                failure_reason = COALESCE($4, failure_reason),
+                delivered_at = CASE WHEN $2 = 'delivered'::sms_status THEN NOW() ELSE delivered_at END,
+                updated_at = NOW()
+            WHERE provider_message_id = $1 AND status IN ('queued', 'sent')
+            "#,
+            provider_message_id,
+            status as _,
+            network_code,
+            failure_reason,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+}
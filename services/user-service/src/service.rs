@@ -5,13 +5,20 @@
 
 use crate::domain::*;
 use crate::repository::*;
+use crate::sms::{SmsDeliveryReport, SmsProvider, SmsStatus};
 use shared_auth::{JwtService, OtpService, PinService, TokenResponse};
 use shared_errors::{AppError, Result};
+use shared_security::authorize_callback;
 use shared_types::*;
 use std::sync::Arc;
 use tracing::{info, instrument, warn};
 use uuid::Uuid;
 
+/// Devices a user can be signed in on at once (the least recently used is signed out)
+const MAX_SESSIONS_PER_USER: i64 = 10;
+/// Longest device name kept with a session
+const MAX_DEVICE_NAME_LENGTH: usize = 100;
+
 /// Main user service coordinating all user operations
 pub struct UserService {
     user_repository: Arc<UserRepository>,
@@ -67,8 +74,8 @@ impl UserService {
     }
 
     /// Verify OTP and complete user registration
-    #[instrument(skip(self, request), fields(username = %request.lightning_username))]
-    pub async fn verify_otp(&self, request: VerifyOtpRequest) -> Result<VerifyOtpResponse> {
+    #[instrument(skip(self, request, client), fields(username = %request.lightning_username))]
+    pub async fn verify_otp(&self, request: VerifyOtpRequest, client: ClientInfo) -> Result<VerifyOtpResponse> {
         // Parse verification token to get phone number
         let phone_number = self.parse_verification_token(&request.verification_token)?;
 
@@ -97,6 +104,7 @@ impl UserService {
             pin_hash,
             request.lightning_username,
             request.full_name,
+            request.account_type.unwrap_or_default(),
         );
 
         // Save user to database
@@ -105,15 +113,10 @@ impl UserService {
         // Create initial wallet for the user
         self.create_initial_wallet(user.id).await?;
 
-        // Generate authentication tokens
-        let tokens = self.jwt_service.generate_tokens(
-            user.id,
-            &user.phone_number,
-            user.kyc_tier.clone(),
-        )?;
-
-        // Create session
-        self.create_user_session(&user, &tokens).await?;
+        // Start a session for this device and issue its tokens
+        let tokens = self
+            .start_session(&user, request.device_name, request.platform, client)
+            .await?;
 
         info!("User registration completed for {}", user.lightning_username);
 
@@ -126,8 +129,8 @@ impl UserService {
     }
 
     /// Login with phone number and PIN
-    #[instrument(skip(self, request), fields(phone = %request.phone_number))]
-    pub async fn login(&self, request: LoginRequest) -> Result<LoginResponse> {
+    #[instrument(skip(self, request, client), fields(phone = %request.phone_number))]
+    pub async fn login(&self, request: LoginRequest, client: ClientInfo) -> Result<LoginResponse> {
         // Validate phone number
         let phone_number = PhoneNumber::new(request.phone_number)
             .map_err(|_| AppError::invalid_phone_number())?;
@@ -144,15 +147,10 @@ impl UserService {
             return Err(AppError::invalid_pin());
         }
 
-        // Generate authentication tokens
-        let tokens = self.jwt_service.generate_tokens(
-            user.id,
-            &user.phone_number,
-            user.kyc_tier.clone(),
-        )?;
-
-        // Update session
-        self.create_user_session(&user, &tokens).await?;
+        // Start a session for this device and issue its tokens
+        let tokens = self
+            .start_session(&user, request.device_name, request.platform, client)
+            .await?;
 
         info!("User logged in: {}", user.lightning_username);
 
@@ -165,8 +163,32 @@ impl UserService {
     }
 
     /// Refresh access token using refresh token
-    #[instrument(skip(self, request))]
-    pub async fn refresh_token(&self, request: RefreshTokenRequest) -> Result<RefreshTokenResponse> {
+    #[instrument(skip(self, request, client))]
+    pub async fn refresh_token(&self, request: RefreshTokenRequest, client: ClientInfo) -> Result<RefreshTokenResponse> {
+        let claims = self.jwt_service.verify_token(&request.refresh_token)?;
+        let user_id = Uuid::parse_str(&claims.sub)
+            .map_err(|_| AppError::Auth {
+                message: "Invalid user ID in token".to_string(),
+            })?;
+
+        // The session must still exist: signed-out devices can't refresh
+        let session = match claims.session_id()? {
+            Some(session_id) => self.session_repository.find_active(UserId(user_id), session_id).await?,
+            None => None,
+        };
+        let session = session.ok_or_else(|| AppError::Auth {
+            message: "Session has ended, please log in again".to_string(),
+        })?;
+
+        if !PinService::verify_pin(&request.refresh_token, &session.refresh_token_hash)? {
+            warn!("Refresh token doesn't match session {}", session.id);
+            return Err(AppError::Auth {
+                message: "Invalid refresh token".to_string(),
+            });
+        }
+
+        self.session_repository.touch(session.id, client.ip_address.as_deref()).await?;
+
         // Generate new access token
         let access_token = self.jwt_service.refresh_access_token(&request.refresh_token)?;
 
@@ -176,6 +198,35 @@ impl UserService {
         })
     }
 
+    /// Devices the user is signed in on
+    #[instrument(skip(self))]
+    pub async fn list_sessions(&self, user_id: UserId, current_session: Option<Uuid>) -> Result<Vec<SessionResponse>> {
+        let sessions = self.session_repository.list(user_id).await?;
+        Ok(sessions.iter().map(|session| session.to_response(current_session)).collect())
+    }
+
+    /// Sign one device out; its tokens stop working straight away
+    #[instrument(skip(self))]
+    pub async fn revoke_session(&self, user_id: UserId, session_id: Uuid) -> Result<()> {
+        if !self.session_repository.revoke(user_id, session_id).await? {
+            return Err(AppError::User {
+                message: "Session not found".to_string(),
+            });
+        }
+
+        info!("User {} signed out session {}", user_id, session_id);
+        Ok(())
+    }
+
+    /// Sign out every device except the one making the request
+    #[instrument(skip(self))]
+    pub async fn revoke_other_sessions(&self, user_id: UserId, current_session: Option<Uuid>) -> Result<RevokeSessionsResponse> {
+        let revoked = self.session_repository.revoke_others(user_id, current_session).await?;
+
+        info!("User {} signed out {} other sessions", user_id, revoked);
+        Ok(RevokeSessionsResponse { revoked })
+    }
+
     /// Get user profile by ID
     #[instrument(skip(self))]
     pub async fn get_profile(&self, user_id: UserId) -> Result<UserProfile> {
@@ -307,23 +358,49 @@ impl UserService {
             })
     }
 
-    /// Create user session for authentication
-    #[instrument(skip(self, user, tokens))]
-    async fn create_user_session(&self, user: &User, tokens: &TokenResponse) -> Result<()> {
+    /// Start a session for a newly signed-in device and issue its tokens
+    #[instrument(skip(self, user, device_name, client))]
+    async fn start_session(
+        &self,
+        user: &User,
+        device_name: Option<String>,
+        platform: Option<DevicePlatform>,
+        client: ClientInfo,
+    ) -> Result<TokenResponse> {
+        let session_id = Uuid::new_v4();
+        let tokens = self.jwt_service.generate_tokens(
+            user.id,
+            &user.phone_number,
+            user.kyc_tier.clone(),
+            session_id,
+        )?;
         let refresh_token_hash = PinService::hash_pin(&tokens.refresh_token)?;
 
+        let now = chrono::Utc::now();
         let session = UserSession {
-            id: Uuid::new_v4(),
+            id: session_id,
             user_id: user.id,
             refresh_token_hash,
-            expires_at: chrono::Utc::now() + chrono::Duration::days(7),
+            expires_at: now + chrono::Duration::days(7),
             device_fingerprint: serde_json::json!({}), // TODO: Add device fingerprinting
-            created_at: chrono::Utc::now(),
+            device_name: device_name
+                .map(|name| name.trim().chars().take(MAX_DEVICE_NAME_LENGTH).collect::<String>())
+                .filter(|name| !name.is_empty()),
+            platform: platform.unwrap_or_default(),
+            ip_address: client.ip_address,
+            user_agent: client.user_agent,
+            last_seen_at: now,
+            created_at: now,
         };
 
-        self.session_repository.create_or_update(&session).await?;
+        self.session_repository.create(&session).await?;
 
-        Ok(())
+        let pruned = self.session_repository.prune(user.id, MAX_SESSIONS_PER_USER).await?;
+        if pruned > 0 {
+            info!("Signed user {} out of {} least recently used sessions", user.id, pruned);
+        }
+
+        Ok(tokens)
     }
 
     /// Create initial wallet for new user (calls payment service)
@@ -341,30 +418,73 @@ impl UserService {
 }
 
 /// SMS client for sending OTP codes
+/// Sends through the configured provider and keeps a log of every message
 pub struct SmsClient {
-    // In production, this would contain Twilio/Africa's Talking credentials
+    provider: Arc<dyn SmsProvider>,
+    repository: Arc<SmsMessageRepository>,
+    /// Secret the provider puts in the delivery report URL (`?token=`)
+    delivery_report_token: Option<String>,
 }
 
 impl SmsClient {
-    pub fn new() -> Self {
-        Self {}
+    pub fn new(provider: Arc<dyn SmsProvider>, repository: Arc<SmsMessageRepository>) -> Self {
+        Self {
+            provider,
+            repository,
+            delivery_report_token: std::env::var("SMS_DELIVERY_REPORT_TOKEN").ok().filter(|token| !token.is_empty()),
+        }
     }
 
     /// Send OTP code via SMS
-    #[instrument(skip(self))]
+    #[instrument(skip(self, code))]
     pub async fn send_otp(&self, phone_number: &PhoneNumber, code: &str) -> Result<()> {
-        // In development, just log the code
-        if std::env::var("ENVIRONMENT").unwrap_or_default() != "production" {
-            info!("📱 SMS OTP for {}: {}", phone_number.0, code);
-            return Ok(());
+        let message = format!("Your PesaBit verification code is: {}. Valid for 5 minutes.", code);
+
+        let statuses = self.provider.send(std::slice::from_ref(phone_number), &message).await?;
+        for status in &statuses {
+            self.repository.record(self.provider.name(), "otp", status).await?;
+        }
+
+        match statuses.first() {
+            Some(status) if status.status.is_accepted() => Ok(()),
+            Some(status) => {
+                warn!("SMS to {} rejected: {}", phone_number.0, status.description);
+                Err(AppError::ExternalService {
+                    message: format!("SMS rejected: {}", status.description),
+                })
+            }
+            None => Err(AppError::ExternalService {
+                message: "SMS provider returned no recipients".to_string(),
+            }),
+        }
+    }
+
+    /// Update a message's status from a provider delivery report
+    #[instrument(skip(self, token, report), fields(message_id = %report.id, status = %report.status))]
+    pub async fn handle_delivery_report(&self, token: Option<&str>, report: SmsDeliveryReport) -> Result<()> {
+        if let Err(e) = authorize_callback(self.delivery_report_token.as_deref(), token) {
+            warn!("Refused SMS delivery report without a valid token");
+            return Err(e);
+        }
+
+        let status = SmsStatus::from_report(&report.status).ok_or_else(|| AppError::Validation {
+            message: format!("Unknown delivery status: {}", report.status),
+        })?;
+
+        let updated = self
+            .repository
+            .apply_delivery_report(
+                &report.id,
+                status,
+                report.network_code.as_deref(),
+                report.failure_reason.as_deref(),
+            )
+            .await?;
+
+        if !updated {
+            info!("Ignoring delivery report for unknown or finalised SMS {}", report.id);
         }
 
-        // In production, integrate with SMS provider (Twilio, Africa's Talking, etc.)
-        let message = format!("Your PesaBit verification code is: {}. Valid for 5 minutes.", code);
-        
-        // TODO: Implement actual SMS sending
-        info!("Sending SMS to {}: {}", phone_number.0, message);
-        
         Ok(())
     }
 }
@@ -0,0 +1,285 @@
+/// SMS providers
+///
+/// `SmsProvider` hides the SMS gateway behind a small interface. Africa's Talking
+/// is used when credentials are configured; in development without credentials
+/// messages are only logged.
+
+use async_trait::async_trait;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use shared_africastalking::AfricasTalkingSms;
+use shared_config::SmsConfig;
+use shared_errors::Result;
+use shared_types::PhoneNumber;
+use std::str::FromStr;
+use tracing::info;
+
+/// Delivery state of an SMS
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[sqlx(type_name = "sms_status", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum SmsStatus {
+    Queued,
+    Sent,
+    Delivered,
+    Failed,
+    Rejected,
+}
+
+impl SmsStatus {
+    /// Status for an Africa's Talking send status code
+    /// 100 = processed, 101 = sent, 102 = queued, anything else was rejected
+    pub fn from_send_code(code: i32) -> Self {
+        match code {
+            100 | 101 => SmsStatus::Sent,
+            102 => SmsStatus::Queued,
+            _ => SmsStatus::Rejected,
+        }
+    }
+
+    /// Status for an Africa's Talking delivery report, `None` if unknown
+    pub fn from_report(status: &str) -> Option<Self> {
+        match status {
+            "Success" => Some(SmsStatus::Delivered),
+            "Sent" | "Submitted" | "Buffered" => Some(SmsStatus::Sent),
+            "Rejected" => Some(SmsStatus::Rejected),
+            "Failed" | "AbsentSubscriber" | "Expired" => Some(SmsStatus::Failed),
+            _ => None,
+        }
+    }
+
+    /// Whether the provider took the message for delivery
+    pub fn is_accepted(self) -> bool {
+        matches!(self, SmsStatus::Queued | SmsStatus::Sent | SmsStatus::Delivered)
+    }
+}
+
+/// Price the provider charged for a message
+#[derive(Debug, Clone, PartialEq)]
+pub struct SmsCost {
+    pub currency: String,
+    pub amount: Decimal,
+}
+
+impl SmsCost {
+    /// Parse a provider cost string like "KES 0.8000"; "0" (not charged) gives `None`
+    pub fn parse(cost: &str) -> Option<Self> {
+        let (currency, amount) = cost.trim().split_once(' ')?;
+        Some(Self {
+            currency: currency.to_string(),
+            amount: Decimal::from_str(amount.trim()).ok()?,
+        })
+    }
+}
+
+/// Outcome of a send for one recipient
+#[derive(Debug, Clone, PartialEq)]
+pub struct SmsRecipientStatus {
+    pub phone_number: String,
+    pub status: SmsStatus,
+    pub status_code: Option<i32>,
+    /// Provider's description of the status (e.g., "Success", "InvalidPhoneNumber")
+    pub description: String,
+    pub message_id: Option<String>,
+    pub cost: Option<SmsCost>,
+}
+
+/// Something that can send text messages
+#[async_trait]
+pub trait SmsProvider: Send + Sync {
+    /// Provider name stored with each message
+    fn name(&self) -> &'static str;
+
+    /// Send one message to each recipient, returning a status per recipient
+    async fn send(&self, recipients: &[PhoneNumber], message: &str) -> Result<Vec<SmsRecipientStatus>>;
+}
+
+/// Africa's Talking bulk SMS API
+pub struct AfricasTalkingProvider {
+    sms: AfricasTalkingSms,
+}
+
+impl AfricasTalkingProvider {
+    pub fn new(config: SmsConfig) -> Self {
+        Self {
+            sms: AfricasTalkingSms::new(config),
+        }
+    }
+}
+
+#[async_trait]
+impl SmsProvider for AfricasTalkingProvider {
+    fn name(&self) -> &'static str {
+        "africastalking"
+    }
+
+    async fn send(&self, recipients: &[PhoneNumber], message: &str) -> Result<Vec<SmsRecipientStatus>> {
+        let to: Vec<&str> = recipients.iter().map(|phone_number| phone_number.0.as_str()).collect();
+
+        Ok(self
+            .sms
+            .send(&to, message)
+            .await?
+            .into_iter()
+            .map(|recipient| SmsRecipientStatus {
+                status: SmsStatus::from_send_code(recipient.status_code),
+                status_code: Some(recipient.status_code),
+                phone_number: recipient.number,
+                description: recipient.status,
+                message_id: recipient.message_id,
+                cost: SmsCost::parse(&recipient.cost),
+            })
+            .collect())
+    }
+}
+
+/// Development provider that only logs messages
+pub struct LoggingSmsProvider;
+
+#[async_trait]
+impl SmsProvider for LoggingSmsProvider {
+    fn name(&self) -> &'static str {
+        "log"
+    }
+
+    async fn send(&self, recipients: &[PhoneNumber], message: &str) -> Result<Vec<SmsRecipientStatus>> {
+        Ok(recipients
+            .iter()
+            .map(|phone_number| {
+                info!("📱 SMS to {}: {}", phone_number.0, message);
+                SmsRecipientStatus {
+                    phone_number: phone_number.0.clone(),
+                    status: SmsStatus::Sent,
+                    status_code: None,
+                    description: "Logged".to_string(),
+                    message_id: None,
+                    cost: None,
+                }
+            })
+            .collect())
+    }
+}
+
+/// Query string of a delivery report
+#[derive(Debug, Deserialize)]
+pub struct DeliveryReportParams {
+    pub token: Option<String>,
+}
+
+/// Delivery report posted by Africa's Talking (form-encoded)
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SmsDeliveryReport {
+    /// Provider message ID from the send response
+    pub id: String,
+    pub status: String,
+    pub phone_number: Option<String>,
+    pub network_code: Option<String>,
+    /// Set for Rejected and Failed (e.g., "InsufficientCredit", "AbsentSubscriber")
+    pub failure_reason: Option<String>,
+    pub retry_count: Option<i32>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use shared_errors::AppError;
+    use wiremock::matchers::{body_string_contains, header, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn config(server: &MockServer) -> SmsConfig {
+        SmsConfig {
+            provider_url: format!("{}/version1/messaging", server.uri()),
+            api_key: "test_api_key".to_string(),
+            username: "pesabit".to_string(),
+            sender_id: Some("PESABIT".to_string()),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_africastalking_send_parses_recipients() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/version1/messaging"))
+            .and(header("apiKey", "test_api_key"))
+            .and(body_string_contains("username=pesabit"))
+            .and(body_string_contains("from=PESABIT"))
+            .and(body_string_contains("to=%2B254712345678%2C%2B254700000000"))
+            .respond_with(ResponseTemplate::new(201).set_body_json(serde_json::json!({
+                "SMSMessageData": {
+                    "Message": "Sent to 1/2 Total Cost: KES 0.8000",
+                    "Recipients": [
+                        {
+                            "statusCode": 101,
+                            "number": "+254712345678",
+                            "status": "Success",
+                            "cost": "KES 0.8000",
+                            "messageId": "ATXid_1"
+                        },
+                        {
+                            "statusCode": 403,
+                            "number": "+254700000000",
+                            "status": "InvalidPhoneNumber",
+                            "cost": "0",
+                            "messageId": "None"
+                        }
+                    ]
+                }
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let provider = AfricasTalkingProvider::new(config(&server));
+        let statuses = provider
+            .send(
+                &[
+                    PhoneNumber("+254712345678".to_string()),
+                    PhoneNumber("+254700000000".to_string()),
+                ],
+                "Your PesaBit verification code is: 123456",
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(statuses.len(), 2);
+        assert_eq!(statuses[0].status, SmsStatus::Sent);
+        assert_eq!(statuses[0].message_id.as_deref(), Some("ATXid_1"));
+        assert_eq!(
+            statuses[0].cost,
+            Some(SmsCost {
+                currency: "KES".to_string(),
+                amount: Decimal::new(8000, 4),
+            })
+        );
+        assert_eq!(statuses[1].status, SmsStatus::Rejected);
+        assert_eq!(statuses[1].description, "InvalidPhoneNumber");
+        assert_eq!(statuses[1].message_id, None);
+        assert_eq!(statuses[1].cost, None);
+    }
+
+    #[tokio::test]
+    async fn test_africastalking_error_response() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(401).set_body_string("The supplied authentication is invalid"))
+            .mount(&server)
+            .await;
+
+        let provider = AfricasTalkingProvider::new(config(&server));
+        let result = provider
+            .send(&[PhoneNumber("+254712345678".to_string())], "Hello")
+            .await;
+
+        assert!(matches!(result, Err(AppError::ExternalService { .. })));
+    }
+
+    #[test]
+    fn test_delivery_report_statuses() {
+        assert_eq!(SmsStatus::from_report("Success"), Some(SmsStatus::Delivered));
+        assert_eq!(SmsStatus::from_report("Buffered"), Some(SmsStatus::Sent));
+        assert_eq!(SmsStatus::from_report("AbsentSubscriber"), Some(SmsStatus::Failed));
+        assert_eq!(SmsStatus::from_report("Rejected"), Some(SmsStatus::Rejected));
+        assert_eq!(SmsStatus::from_report("Unknown"), None);
+    }
+}
@@ -0,0 +1,240 @@
+/// Africa's Talking API client
+///
+/// OTP codes (user-service), notifications (notification-service) and airtime
+/// (payment-service) all go through Africa's Talking. `AfricasTalkingClient`
+/// attaches the account's credentials to each request, and `AfricasTalkingSms`
+/// is the bulk SMS call both SMS senders share.
+
+use reqwest::{Client, Method, RequestBuilder};
+use serde::Deserialize;
+use shared_config::SmsConfig;
+use shared_errors::{AppError, Result};
+use tracing::instrument;
+
+/// HTTP client authenticated as one Africa's Talking account
+#[derive(Debug, Clone)]
+pub struct AfricasTalkingClient {
+    username: String,
+    api_key: String,
+    http_client: Client,
+}
+
+impl AfricasTalkingClient {
+    pub fn new(username: impl Into<String>, api_key: impl Into<String>) -> Self {
+        Self {
+            username: username.into(),
+            api_key: api_key.into(),
+            http_client: Client::new(),
+        }
+    }
+
+    /// Account name most endpoints expect as `username`
+    pub fn username(&self) -> &str {
+        &self.username
+    }
+
+    /// Request carrying the account's API key and asking for JSON back
+    pub fn request(&self, method: Method, url: &str) -> RequestBuilder {
+        self.http_client
+            .request(method, url)
+            .header("apiKey", &self.api_key)
+            .header("Accept", "application/json")
+    }
+}
+
+/// Outcome of a send for one recipient
+#[derive(Debug, Clone, PartialEq)]
+pub struct SmsRecipient {
+    pub number: String,
+    /// 100 = processed, 101 = sent, 102 = queued, anything else was rejected
+    pub status_code: i32,
+    /// Provider's description of the status (e.g., "Success", "InvalidPhoneNumber")
+    pub status: String,
+    /// Price charged, like "KES 0.8000" ("0" when not charged)
+    pub cost: String,
+    pub message_id: Option<String>,
+}
+
+impl SmsRecipient {
+    /// Whether the provider took the message for delivery
+    pub fn is_accepted(&self) -> bool {
+        (100..=102).contains(&self.status_code)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct AtResponse {
+    #[serde(rename = "SMSMessageData")]
+    data: AtMessageData,
+}
+
+#[derive(Debug, Deserialize)]
+struct AtMessageData {
+    #[serde(rename = "Message")]
+    message: String,
+    #[serde(rename = "Recipients", default)]
+    recipients: Vec<AtRecipient>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AtRecipient {
+    status_code: i32,
+    number: String,
+    status: String,
+    #[serde(default)]
+    cost: String,
+    message_id: Option<String>,
+}
+
+/// Africa's Talking bulk SMS API
+pub struct AfricasTalkingSms {
+    client: AfricasTalkingClient,
+    api_url: String,
+    sender_id: Option<String>,
+}
+
+impl AfricasTalkingSms {
+    pub fn new(config: SmsConfig) -> Self {
+        Self {
+            client: AfricasTalkingClient::new(config.username, config.api_key),
+            api_url: config.provider_url,
+            sender_id: config.sender_id,
+        }
+    }
+
+    /// Send one message to each recipient, returning a status per recipient
+    #[instrument(skip(self, message), fields(recipients = recipients.len()))]
+    pub async fn send(&self, recipients: &[&str], message: &str) -> Result<Vec<SmsRecipient>> {
+        let to = recipients.join(",");
+        let mut form = vec![
+            ("username", self.client.username()),
+            ("to", to.as_str()),
+            ("message", message),
+        ];
+        if let Some(sender_id) = &self.sender_id {
+            form.push(("from", sender_id.as_str()));
+        }
+
+        let response = self
+            .client
+            .request(Method::POST, &self.api_url)
+            .form(&form)
+            .send()
+            .await
+            .map_err(|e| AppError::ExternalService {
+                message: format!("SMS request failed: {}", e),
+            })?;
+
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            return Err(AppError::ExternalService {
+                message: format!("SMS provider returned {}: {}", status, body),
+            });
+        }
+
+        let body: AtResponse = response.json().await.map_err(|e| AppError::ExternalService {
+            message: format!("Invalid SMS provider response: {}", e),
+        })?;
+
+        if body.data.recipients.is_empty() {
+            return Err(AppError::ExternalService {
+                message: format!("SMS not sent: {}", body.data.message),
+            });
+        }
+
+        Ok(body
+            .data
+            .recipients
+            .into_iter()
+            .map(|recipient| SmsRecipient {
+                number: recipient.number,
+                status_code: recipient.status_code,
+                status: recipient.status,
+                cost: recipient.cost,
+                // Rejected recipients get the literal message ID "None"
+                message_id: recipient.message_id.filter(|id| id != "None" && !id.is_empty()),
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wiremock::matchers::{body_string_contains, header, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn config(server: &MockServer) -> SmsConfig {
+        SmsConfig {
+            provider_url: format!("{}/version1/messaging", server.uri()),
+            api_key: "test_api_key".to_string(),
+            username: "pesabit".to_string(),
+            sender_id: Some("PESABIT".to_string()),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_send_parses_recipients() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/version1/messaging"))
+            .and(header("apiKey", "test_api_key"))
+            .and(body_string_contains("username=pesabit"))
+            .and(body_string_contains("from=PESABIT"))
+            .and(body_string_contains("to=%2B254712345678%2C%2B254700000000"))
+            .respond_with(ResponseTemplate::new(201).set_body_json(serde_json::json!({
+                "SMSMessageData": {
+                    "Message": "Sent to 1/2 Total Cost: KES 0.8000",
+                    "Recipients": [
+                        {
+                            "statusCode": 101,
+                            "number": "+254712345678",
+                            "status": "Success",
+                            "cost": "KES 0.8000",
+                            "messageId": "ATXid_1"
+                        },
+                        {
+                            "statusCode": 403,
+                            "number": "+254700000000",
+                            "status": "InvalidPhoneNumber",
+                            "cost": "0",
+                            "messageId": "None"
+                        }
+                    ]
+                }
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let sms = AfricasTalkingSms::new(config(&server));
+        let recipients = sms
+            .send(&["+254712345678", "+254700000000"], "Your PesaBit verification code is: 123456")
+            .await
+            .unwrap();
+
+        assert_eq!(recipients.len(), 2);
+        assert!(recipients[0].is_accepted());
+        assert_eq!(recipients[0].message_id.as_deref(), Some("ATXid_1"));
+        assert_eq!(recipients[0].cost, "KES 0.8000");
+        assert!(!recipients[1].is_accepted());
+        assert_eq!(recipients[1].status, "InvalidPhoneNumber");
+        assert_eq!(recipients[1].message_id, None);
+    }
+
+    #[tokio::test]
+    async fn test_send_error_response() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(401).set_body_string("The supplied authentication is invalid"))
+            .mount(&server)
+            .await;
+
+        let sms = AfricasTalkingSms::new(config(&server));
+        let result = sms.send(&["+254712345678"], "Hello").await;
+
+        assert!(matches!(result, Err(AppError::ExternalService { .. })));
+    }
+}
@@ -0,0 +1,188 @@
+/// API keys for programmatic access
+///
+/// Keys look like `pbk_` followed by 48 hex characters. Only a SHA-256 hash is
+/// stored, next to the first characters used to look the key up. Each key
+/// carries scopes limiting what it can call, an optional IP allowlist and its
+/// own rate limit; the gateway checks all three before forwarding a request.
+
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::net::IpAddr;
+
+/// Header carrying an API key
+pub const API_KEY_HEADER: &str = "x-api-key";
+/// Start of every API key
+const API_KEY_PREFIX: &str = "pbk_";
+/// Characters of the key kept in clear to look it up and show in listings
+const API_KEY_LOOKUP_LEN: usize = 12;
+
+/// What an API key may do
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum ApiKeyScope {
+    /// Balance, limits and fee previews
+    #[serde(rename = "read:balance")]
+    ReadBalance,
+    /// Transaction history and statements
+    #[serde(rename = "read:transactions")]
+    ReadTransactions,
+    /// Lightning and POS invoices
+    #[serde(rename = "create:invoice")]
+    CreateInvoice,
+    /// Paying Lightning invoices
+    #[serde(rename = "send:payment")]
+    SendPayment,
+    /// Cashing out to M-Pesa or another mobile money network
+    #[serde(rename = "withdraw:mpesa")]
+    WithdrawMpesa,
+    /// Merchant sales reports and settlements
+    #[serde(rename = "read:reports")]
+    ReadReports,
+    /// Bulk payout batches
+    #[serde(rename = "send:payouts")]
+    SendPayouts,
+}
+
+impl ApiKeyScope {
+    pub const ALL: [ApiKeyScope; 7] = [
+        ApiKeyScope::ReadBalance,
+        ApiKeyScope::ReadTransactions,
+        ApiKeyScope::CreateInvoice,
+        ApiKeyScope::SendPayment,
+        ApiKeyScope::WithdrawMpesa,
+        ApiKeyScope::ReadReports,
+        ApiKeyScope::SendPayouts,
+    ];
+
+    /// Name used in the API and the database
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ApiKeyScope::ReadBalance => "read:balance",
+            ApiKeyScope::ReadTransactions => "read:transactions",
+            ApiKeyScope::CreateInvoice => "create:invoice",
+            ApiKeyScope::SendPayment => "send:payment",
+            ApiKeyScope::WithdrawMpesa => "withdraw:mpesa",
+            ApiKeyScope::ReadReports => "read:reports",
+            ApiKeyScope::SendPayouts => "send:payouts",
+        }
+    }
+
+    pub fn parse(scope: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|s| s.as_str() == scope)
+    }
+}
+
+/// Random API key
+pub fn generate_api_key() -> String {
+    let mut bytes = [0u8; 24];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    format!("{}{}", API_KEY_PREFIX, hex::encode(bytes))
+}
+
+/// Stored form of an API key
+pub fn hash_api_key(key: &str) -> String {
+    hex::encode(Sha256::digest(key.as_bytes()))
+}
+
+/// Part of the key used to look it up, if it looks like one of our keys
+pub fn api_key_lookup(key: &str) -> Option<&str> {
+    if !key.starts_with(API_KEY_PREFIX) || key.len() <= API_KEY_LOOKUP_LEN {
+        return None;
+    }
+    key.get(..API_KEY_LOOKUP_LEN)
+}
+
+/// IP address or CIDR range in an allowlist (e.g. `41.90.12.7` or `41.90.0.0/16`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IpNetwork {
+    address: IpAddr,
+    prefix_len: u8,
+}
+
+impl IpNetwork {
+    pub fn parse(network: &str) -> Option<Self> {
+        let (address, prefix_len) = match network.trim().split_once('/') {
+            Some((address, prefix_len)) => (address.parse::<IpAddr>().ok()?, Some(prefix_len.parse::<u8>().ok()?)),
+            None => (network.trim().parse::<IpAddr>().ok()?, None),
+        };
+        let max_len = if address.is_ipv4() { 32 } else { 128 };
+        let prefix_len = prefix_len.unwrap_or(max_len);
+        (prefix_len <= max_len).then_some(Self { address, prefix_len })
+    }
+
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.address, ip) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - u32::from(self.prefix_len)).unwrap_or(0);
+                u32::from(network) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - u32::from(self.prefix_len)).unwrap_or(0);
+                u128::from(network) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl std::fmt::Display for IpNetwork {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", self.address, self.prefix_len)
+    }
+}
+
+/// Whether `ip` may use a key with this allowlist (an empty list allows any address)
+pub fn ip_allowed(allowlist: &[String], ip: Option<IpAddr>) -> bool {
+    if allowlist.is_empty() {
+        return true;
+    }
+    let Some(ip) = ip else {
+        return false;
+    };
+    allowlist
+        .iter()
+        .filter_map(|network| IpNetwork::parse(network))
+        .any(|network| network.contains(ip))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_api_keys() {
+        let key = generate_api_key();
+        assert!(key.starts_with("pbk_"));
+        assert_eq!(key.len(), 52);
+        assert_eq!(api_key_lookup(&key), Some(&key[..12]));
+        assert_ne!(generate_api_key(), key);
+
+        assert_eq!(hash_api_key(&key).len(), 64);
+        assert_eq!(hash_api_key(&key), hash_api_key(&key));
+        assert_eq!(api_key_lookup("eyJhbGciOiJIUzI1NiJ9"), None);
+        assert_eq!(api_key_lookup("pbk_short"), None);
+
+        assert_eq!(ApiKeyScope::parse("send:payment"), Some(ApiKeyScope::SendPayment));
+        assert_eq!(ApiKeyScope::parse("send:everything"), None);
+        assert_eq!(serde_json::to_string(&ApiKeyScope::ReadBalance).unwrap(), "\"read:balance\"");
+    }
+
+    #[test]
+    fn test_ip_allowlist() {
+        let ip = |s: &str| s.parse::<IpAddr>().ok();
+        let allowlist = vec!["41.90.0.0/16".to_string(), "2c0f:fe38::/32".to_string(), "196.201.214.200".to_string()];
+
+        assert!(ip_allowed(&allowlist, ip("41.90.12.7")));
+        assert!(!ip_allowed(&allowlist, ip("41.91.12.7")));
+        assert!(ip_allowed(&allowlist, ip("196.201.214.200")));
+        assert!(!ip_allowed(&allowlist, ip("196.201.214.201")));
+        assert!(ip_allowed(&allowlist, ip("2c0f:fe38:2000::1")));
+        assert!(!ip_allowed(&allowlist, None));
+        assert!(ip_allowed(&[], None));
+
+        assert_eq!(IpNetwork::parse("0.0.0.0/0").map(|n| n.contains("8.8.8.8".parse().unwrap())), Some(true));
+        assert_eq!(IpNetwork::parse("10.0.0.0/33"), None);
+        assert_eq!(IpNetwork::parse("not-an-ip"), None);
+        assert_eq!(IpNetwork::parse("10.1.2.3/8").unwrap().to_string(), "10.1.2.3/8");
+    }
+}
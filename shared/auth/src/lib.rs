@@ -18,6 +18,8 @@ use shared_errors::{AppError, Result};
 use shared_types::{KycTier, PhoneNumber, UserId};
 use uuid::Uuid;
 
+pub mod api_keys;
+
 /// JWT token claims structure
 /// Contains user information needed by all services
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -32,6 +34,9 @@ pub struct Claims {
     pub iat: i64,
     /// Expires at (Unix timestamp)  
     pub exp: i64,
+    /// Login session the token belongs to (absent on tokens the gateway issues for API keys)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sid: Option<String>,
 }
 
 /// Authentication token pair (access + refresh tokens)
@@ -62,12 +67,13 @@ impl JwtService {
         }
     }
 
-    /// Generate access and refresh token pair for authenticated user
+    /// Generate access and refresh token pair for a login session
     pub fn generate_tokens(
         &self,
         user_id: UserId,
         phone: &PhoneNumber,
         kyc_tier: KycTier,
+        session_id: Uuid,
     ) -> Result<TokenResponse> {
         let now = Utc::now();
 
@@ -78,6 +84,7 @@ impl JwtService {
             kyc_tier: kyc_tier.clone(),
             iat: now.timestamp(),
             exp: (now + self.access_token_expiry).timestamp(),
+            sid: Some(session_id.to_string()),
         };
 
         let access_token = encode(&Header::default(), &access_claims, &self.encoding_key)
@@ -90,6 +97,7 @@ impl JwtService {
             kyc_tier,
             iat: now.timestamp(),
             exp: (now + self.refresh_token_expiry).timestamp(),
+            sid: Some(session_id.to_string()),
         };
 
         let refresh_token = encode(&Header::default(), &refresh_claims, &self.encoding_key)
@@ -103,6 +111,29 @@ impl JwtService {
         })
     }
 
+    /// Access token with a custom lifetime and no refresh token
+    /// (the gateway issues these to forward API-key requests to services)
+    pub fn generate_access_token(
+        &self,
+        user_id: UserId,
+        phone: &PhoneNumber,
+        kyc_tier: KycTier,
+        expiry: Duration,
+    ) -> Result<String> {
+        let now = Utc::now();
+        let claims = Claims {
+            sub: user_id.to_string(),
+            phone: phone.0.clone(),
+            kyc_tier,
+            iat: now.timestamp(),
+            exp: (now + expiry).timestamp(),
+            sid: None,
+        };
+
+        encode(&Header::default(), &claims, &self.encoding_key)
+            .map_err(|e| AppError::Internal(anyhow::anyhow!("Token generation failed: {}", e)))
+    }
+
     /// Verify and decode JWT token
     pub fn verify_token(&self, token: &str) -> Result<Claims> {
         let validation = Validation::default();
@@ -173,6 +204,22 @@ pub struct AuthUser {
     pub user_id: UserId,
     pub phone: PhoneNumber,
     pub kyc_tier: KycTier,
+    /// Login session of the token (None for API-key requests)
+    pub session_id: Option<Uuid>,
+}
+
+impl Claims {
+    /// Session ID carried by the token, if any
+    pub fn session_id(&self) -> Result<Option<Uuid>> {
+        self.sid
+            .as_deref()
+            .map(|sid| {
+                Uuid::parse_str(sid).map_err(|_| AppError::Auth {
+                    message: "Invalid session ID in token".to_string(),
+                })
+            })
+            .transpose()
+    }
 }
 
 /// Axum extractor to get authenticated user from Authorization header
@@ -212,10 +259,13 @@ where
                 message: "Invalid phone number in token".to_string(),
             })?;
 
+        let session_id = claims.session_id()?;
+
         Ok(AuthUser {
             user_id: UserId(user_id),
             phone,
             kyc_tier: claims.kyc_tier,
+            session_id,
         })
     }
 }
@@ -265,11 +315,25 @@ mod tests {
         let user_id = UserId::new();
         let phone = PhoneNumber::new("+254712345678".to_string()).unwrap();
         
-        let tokens = jwt_service.generate_tokens(user_id, &phone, KycTier::Tier1).unwrap();
+        let session_id = Uuid::new_v4();
+        
+        let tokens = jwt_service.generate_tokens(user_id, &phone, KycTier::Tier1, session_id).unwrap();
         let claims = jwt_service.verify_token(&tokens.access_token).unwrap();
         
         assert_eq!(claims.sub, user_id.to_string());
         assert_eq!(claims.phone, phone.0);
+        assert_eq!(claims.session_id().unwrap(), Some(session_id));
+
+        // Refreshed access tokens stay in the same session
+        let refreshed = jwt_service.refresh_access_token(&tokens.refresh_token).unwrap();
+        let claims = jwt_service.verify_token(&refreshed).unwrap();
+        assert_eq!(claims.session_id().unwrap(), Some(session_id));
+
+        let api_key_token = jwt_service
+            .generate_access_token(user_id, &phone, KycTier::Tier1, Duration::seconds(60))
+            .unwrap();
+        let claims = jwt_service.verify_token(&api_key_token).unwrap();
+        assert_eq!(claims.session_id().unwrap(), None);
     }
 
     #[test]
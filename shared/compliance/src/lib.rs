@@ -3,7 +3,7 @@
 /// This module implements Know Your Customer (KYC) and Anti-Money Laundering (AML)
 /// compliance features required for fintech applications in Kenya.
 
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, Datelike, Utc};
 use serde::{Deserialize, Serialize};
 use shared_errors::{AppError, Result};
 use shared_types::{KycStatus, KycTier, UserId};
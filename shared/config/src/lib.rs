@@ -54,6 +54,7 @@ pub struct JwtConfig {
 pub struct ServicesConfig {
     pub user_service_url: String,
     pub payment_service_url: String,
+    pub notification_service_url: String,
     pub api_gateway_url: String,
 }
 
@@ -96,6 +97,8 @@ pub struct SmsConfig {
     pub provider_url: String,
     pub api_key: String,
     pub username: String,
+    /// Registered alphanumeric sender ID (e.g., "PESABIT"); provider default if unset
+    pub sender_id: Option<String>,
 }
 
 /// Security configuration
@@ -104,6 +107,9 @@ pub struct SecurityConfig {
     pub cors_allowed_origins: Vec<String>,
     pub cors_allowed_methods: Vec<String>,
     pub cors_allowed_headers: Vec<String>,
+    /// Proxies in front of the gateway that append to X-Forwarded-For
+    /// (0 = clients connect directly and the socket address is used)
+    pub trusted_proxy_hops: usize,
 }
 
 /// SSL/TLS configuration
@@ -130,6 +136,23 @@ pub struct ApplicationConfig {
     pub frontend_url: String,
 }
 
+impl SmsConfig {
+    /// Load the SMS settings alone, for services that need nothing else
+    pub fn from_env() -> Self {
+        SmsConfig {
+            provider_url: env::var("SMS_PROVIDER_URL")
+                .unwrap_or_else(|_| "https://api.africastalking.com/version1/messaging".to_string()),
+            api_key: env::var("SMS_API_KEY")
+                .unwrap_or_else(|_| "your_sms_api_key".to_string()),
+            username: env::var("SMS_USERNAME")
+                .unwrap_or_else(|_| "your_sms_username".to_string()),
+            sender_id: env::var("SMS_SENDER_ID")
+                .ok()
+                .filter(|id| !id.is_empty()),
+        }
+    }
+}
+
 impl AppConfig {
     /// Load configuration from environment variables
     pub fn from_env() -> Result<Self> {
@@ -173,6 +196,8 @@ impl AppConfig {
                     .unwrap_or_else(|_| "http://localhost:8001".to_string()),
                 payment_service_url: env::var("PAYMENT_SERVICE_URL")
                     .unwrap_or_else(|_| "http://localhost:8002".to_string()),
+                notification_service_url: env::var("NOTIFICATION_SERVICE_URL")
+                    .unwrap_or_else(|_| "http://localhost:8003".to_string()),
                 api_gateway_url: env::var("API_GATEWAY_URL")
                     .unwrap_or_else(|_| "http://localhost:3000".to_string()),
             },
@@ -214,14 +239,7 @@ impl AppConfig {
                 api_key: env::var("EXCHANGE_RATE_API_KEY")
                     .unwrap_or_else(|_| "your_api_key_here".to_string()),
             },
-            sms: SmsConfig {
-                provider_url: env::var("SMS_PROVIDER_URL")
-                    .unwrap_or_else(|_| "https://api.africastalking.com/version1/messaging".to_string()),
-                api_key: env::var("SMS_API_KEY")
-                    .unwrap_or_else(|_| "your_sms_api_key".to_string()),
-                username: env::var("SMS_USERNAME")
-                    .unwrap_or_else(|_| "your_sms_username".to_string()),
-            },
+            sms: SmsConfig::from_env(),
             security: SecurityConfig {
                 cors_allowed_origins: env::var("CORS_ALLOWED_ORIGINS")
                     .unwrap_or_else(|_| "http://localhost:5173,https://pesa.co.ke".to_string())
@@ -234,10 +252,14 @@ impl AppConfig {
                     .map(|s| s.trim().to_string())
                     .collect(),
                 cors_allowed_headers: env::var("CORS_ALLOWED_HEADERS")
-                    .unwrap_or_else(|_| "Content-Type,Authorization,X-Requested-With".to_string())
+                    .unwrap_or_else(|_| "Content-Type,Authorization,X-Requested-With,Idempotency-Key".to_string())
                     .split(',')
                     .map(|s| s.trim().to_string())
                     .collect(),
+                trusted_proxy_hops: env::var("TRUSTED_PROXY_HOPS")
+                    .unwrap_or_else(|_| "1".to_string())
+                    .parse()
+                    .unwrap_or(1),
             },
             ssl: SslConfig {
                 enabled: env::var("SSL_ENABLED")
@@ -62,6 +62,18 @@ pub enum AppError {
     #[error("Rate limit exceeded: {message}")]
     RateLimit { message: String },
 
+    /// Request conflicts with one still in progress (e.g., a retry racing the original)
+    #[error("Conflict: {message}")]
+    Conflict { message: String },
+
+    /// Idempotency key reused for a different request
+    #[error("Idempotency key reused: {message}")]
+    IdempotencyKeyReused { message: String },
+
+    /// Payment would go over the user's KYC-tier limits; `details` tells the client what is left
+    #[error("Limit exceeded: {message}")]
+    LimitExceeded { message: String, details: serde_json::Value },
+
     /// Internal server errors (unexpected failures)
     #[error("Internal server error")]
     Internal(#[from] anyhow::Error),
@@ -80,6 +92,9 @@ impl AppError {
             AppError::ExternalService { .. } => StatusCode::BAD_GATEWAY,
             AppError::Validation { .. } => StatusCode::BAD_REQUEST,
             AppError::RateLimit { .. } => StatusCode::TOO_MANY_REQUESTS,
+            AppError::Conflict { .. } => StatusCode::CONFLICT,
+            AppError::IdempotencyKeyReused { .. } => StatusCode::UNPROCESSABLE_ENTITY,
+            AppError::LimitExceeded { .. } => StatusCode::FORBIDDEN,
             AppError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
@@ -96,6 +111,9 @@ impl AppError {
             AppError::ExternalService { .. } => "EXTERNAL_SERVICE_ERROR",
             AppError::Validation { .. } => "VALIDATION_ERROR",
             AppError::RateLimit { .. } => "RATE_LIMIT_ERROR",
+            AppError::Conflict { .. } => "CONFLICT",
+            AppError::IdempotencyKeyReused { .. } => "IDEMPOTENCY_KEY_REUSED",
+            AppError::LimitExceeded { .. } => "LIMIT_EXCEEDED",
             AppError::Internal(_) => "INTERNAL_ERROR",
         }
     }
@@ -112,9 +130,20 @@ impl AppError {
             AppError::ExternalService { .. } => "External service unavailable. Please try again.".to_string(),
             AppError::Validation { message } => message.clone(),
             AppError::RateLimit { message } => message.clone(),
+            AppError::Conflict { message } => message.clone(),
+            AppError::IdempotencyKeyReused { message } => message.clone(),
+            AppError::LimitExceeded { message, .. } => message.clone(),
             AppError::Internal(_) => "Internal server error. Please contact support.".to_string(),
         }
     }
+
+    /// Structured data for the client, if the error carries any
+    pub fn details(&self) -> Option<serde_json::Value> {
+        match self {
+            AppError::LimitExceeded { details, .. } => Some(details.clone()),
+            _ => None,
+        }
+    }
 }
 
 /// Convert AppError to HTTP response
@@ -126,7 +155,7 @@ impl IntoResponse for AppError {
             error: self.error_code().to_string(),
             message: self.user_message(),
             code: self.error_code().to_string(),
-            details: None,
+            details: self.details(),
         };
 
         // Log the error for debugging (but don't expose internal details to users)
@@ -136,6 +165,15 @@ impl IntoResponse for AppError {
     }
 }
 
+/// Currency conversion failures are caused by bad amounts or rates
+impl From<shared_types::conversion::ConversionError> for AppError {
+    fn from(error: shared_types::conversion::ConversionError) -> Self {
+        AppError::Validation {
+            message: error.to_string(),
+        }
+    }
+}
+
 /// Convenient result type for all operations
 pub type Result<T> = std::result::Result<T, AppError>;
 
@@ -219,6 +257,18 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_limit_exceeded_details() {
+        let error = AppError::LimitExceeded {
+            message: "Daily limit reached".to_string(),
+            details: serde_json::json!({"daily_remaining_kes": "0.00"}),
+        };
+        assert_eq!(error.status_code(), StatusCode::FORBIDDEN);
+        assert_eq!(error.error_code(), "LIMIT_EXCEEDED");
+        assert_eq!(error.details().unwrap()["daily_remaining_kes"], "0.00");
+        assert!(AppError::invalid_pin().details().is_none());
+    }
+
     #[test]
     fn test_user_messages() {
         let error = AppError::insufficient_balance(1000, 500);
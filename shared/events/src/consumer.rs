@@ -0,0 +1,167 @@
+/// Reading domain events through Redis Stream consumer groups
+///
+/// Every consuming system uses its own group, and each instance its own consumer
+/// name within the group. Events stay pending until acknowledged, and on startup
+/// a consumer first re-reads its own pending events, so nothing is lost when an
+/// instance crashes mid-processing.
+
+use crate::{redis_error, EventEnvelope, EVENTS_STREAM};
+use redis::{
+    streams::{StreamId, StreamReadOptions, StreamReadReply},
+    AsyncCommands, Value,
+};
+use shared_errors::Result;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use tracing::{error, instrument};
+
+/// Event read from the stream, to be acknowledged once handled
+#[derive(Debug, Clone)]
+pub struct ReceivedEvent {
+    /// Redis entry ID, pass it to `EventConsumer::ack`
+    pub stream_id: String,
+    pub envelope: EventEnvelope,
+}
+
+/// Consumer-group reader for the events stream
+pub struct EventConsumer {
+    redis: redis::Client,
+    stream: String,
+    group: String,
+    consumer: String,
+    /// True until this consumer's pending entries have all been re-read
+    recovering: AtomicBool,
+}
+
+impl EventConsumer {
+    pub fn new(redis: redis::Client, group: &str, consumer: &str) -> Self {
+        Self {
+            redis,
+            stream: EVENTS_STREAM.to_string(),
+            group: group.to_string(),
+            consumer: consumer.to_string(),
+            recovering: AtomicBool::new(true),
+        }
+    }
+
+    /// Create the consumer group (and stream) if they don't exist yet
+    /// New groups start from the beginning of the stream
+    pub async fn ensure_group(&self) -> Result<()> {
+        let mut conn = self.redis.get_multiplexed_async_connection().await.map_err(redis_error)?;
+
+        let created: redis::RedisResult<()> = conn.xgroup_create_mkstream(&self.stream, &self.group, "0").await;
+        match created {
+            Ok(()) => Ok(()),
+            Err(e) if e.code() == Some("BUSYGROUP") => Ok(()),
+            Err(e) => Err(redis_error(e)),
+        }
+    }
+
+    /// Read up to `count` events, waiting up to `block` for new ones
+    #[instrument(skip(self))]
+    pub async fn read(&self, count: usize, block: Duration) -> Result<Vec<ReceivedEvent>> {
+        let mut conn = self.redis.get_multiplexed_async_connection().await.map_err(redis_error)?;
+
+        let recovering = self.recovering.load(Ordering::Relaxed);
+        let mut options = StreamReadOptions::default()
+            .group(&self.group, &self.consumer)
+            .count(count);
+
+        // "0" returns entries delivered to us but never acknowledged, ">" returns new entries
+        let start = if recovering {
+            "0"
+        } else {
+            options = options.block(block.as_millis() as usize);
+            ">"
+        };
+
+        let reply: StreamReadReply = conn
+            .xread_options(&[&self.stream], &[start], &options)
+            .await
+            .map_err(redis_error)?;
+
+        let entries: Vec<StreamId> = reply.keys.into_iter().flat_map(|key| key.ids).collect();
+
+        if recovering && entries.is_empty() {
+            self.recovering.store(false, Ordering::Relaxed);
+        }
+
+        let mut events = Vec::with_capacity(entries.len());
+        let mut malformed = Vec::new();
+
+        for entry in entries {
+            match parse_envelope(&entry) {
+                Some(envelope) => events.push(ReceivedEvent { stream_id: entry.id, envelope }),
+                None => {
+                    error!("Skipping malformed event {} on {}", entry.id, self.stream);
+                    malformed.push(entry.id);
+                }
+            }
+        }
+
+        // Malformed entries would be redelivered forever, so acknowledge them now
+        if !malformed.is_empty() {
+            self.ack(&malformed).await?;
+        }
+
+        Ok(events)
+    }
+
+    /// Acknowledge handled events so they aren't redelivered
+    pub async fn ack(&self, stream_ids: &[String]) -> Result<()> {
+        if stream_ids.is_empty() {
+            return Ok(());
+        }
+
+        let mut conn = self.redis.get_multiplexed_async_connection().await.map_err(redis_error)?;
+        let _: i64 = conn
+            .xack(&self.stream, &self.group, stream_ids)
+            .await
+            .map_err(redis_error)?;
+
+        Ok(())
+    }
+}
+
+/// Decode the `envelope` field written by the relay
+fn parse_envelope(entry: &StreamId) -> Option<EventEnvelope> {
+    match entry.map.get("envelope")? {
+        Value::Data(bytes) => serde_json::from_slice(bytes).ok(),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DomainEvent;
+    use shared_types::UserId;
+    use std::collections::HashMap;
+    use uuid::Uuid;
+
+    fn entry(envelope: Option<Value>) -> StreamId {
+        let mut map = HashMap::new();
+        if let Some(value) = envelope {
+            map.insert("envelope".to_string(), value);
+        }
+        StreamId { id: "1700000000000-0".to_string(), map }
+    }
+
+    #[test]
+    fn test_parse_envelope() {
+        let envelope = EventEnvelope {
+            id: Uuid::new_v4(),
+            sequence: 1,
+            occurred_at: chrono::Utc::now(),
+            event: DomainEvent::UserRegistered {
+                user_id: UserId::new(),
+                lightning_username: "amina".to_string(),
+            },
+        };
+        let json = serde_json::to_vec(&envelope).unwrap();
+
+        assert_eq!(parse_envelope(&entry(Some(Value::Data(json)))), Some(envelope));
+        assert_eq!(parse_envelope(&entry(Some(Value::Data(b"not json".to_vec())))), None);
+        assert_eq!(parse_envelope(&entry(None)), None);
+    }
+}
@@ -0,0 +1,466 @@
+/// Domain events for PesaBit
+///
+/// Services record events in the `outbox_events` table inside the same database
+/// transaction as the state change they describe, so an event exists if and only
+/// if the change was committed. `OutboxRelay` publishes recorded events to a Redis
+/// Stream and `EventConsumer` reads them through consumer groups.
+///
+/// Delivery is at-least-once: consumers must deduplicate on `EventEnvelope::id`.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use shared_types::*;
+use uuid::Uuid;
+
+pub mod consumer;
+pub mod outbox;
+pub mod relay;
+
+pub use consumer::{EventConsumer, ReceivedEvent};
+pub use outbox::{record_event, record_transaction_event};
+pub use relay::OutboxRelay;
+
+/// Redis Stream all domain events are published to
+pub const EVENTS_STREAM: &str = "pesabit:events";
+
+/// Something that happened in the business that other systems may react to
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", content = "data")]
+pub enum DomainEvent {
+    /// M-Pesa deposit confirmed and sats credited
+    DepositCompleted {
+        transaction_id: Uuid,
+        user_id: UserId,
+        amount_kes: Option<KesAmount>,
+        amount_sats: Option<SatAmount>,
+        mpesa_code: Option<MpesaCode>,
+    },
+    /// M-Pesa deposit was cancelled or timed out
+    DepositFailed {
+        transaction_id: Uuid,
+        user_id: UserId,
+        amount_kes: Option<KesAmount>,
+        reason: Option<String>,
+    },
+    /// Sats sold and KES sent to the user's M-Pesa
+    WithdrawalCompleted {
+        transaction_id: Uuid,
+        user_id: UserId,
+        amount_sats: Option<SatAmount>,
+        amount_kes: Option<KesAmount>,
+        mpesa_code: Option<MpesaCode>,
+    },
+    /// M-Pesa payout failed
+    WithdrawalFailed {
+        transaction_id: Uuid,
+        user_id: UserId,
+        amount_sats: Option<SatAmount>,
+        reason: Option<String>,
+    },
+    /// Incoming Lightning invoice was paid
+    InvoicePaid {
+        transaction_id: Uuid,
+        user_id: UserId,
+        amount_sats: Option<SatAmount>,
+    },
+    /// Outgoing Lightning payment settled
+    PaymentSent {
+        transaction_id: Uuid,
+        user_id: UserId,
+        amount_sats: Option<SatAmount>,
+        fee_sats: Option<SatAmount>,
+    },
+    /// Outgoing Lightning payment could not be routed
+    PaymentFailed {
+        transaction_id: Uuid,
+        user_id: UserId,
+        amount_sats: Option<SatAmount>,
+        reason: Option<String>,
+    },
+    /// Failed withdrawal or Lightning send refunded to the wallet
+    RefundCredited {
+        /// Transaction that was refunded
+        transaction_id: Uuid,
+        refund_transaction_id: Uuid,
+        user_id: UserId,
+        amount_sats: SatAmount,
+        reason: String,
+    },
+    /// KES from a deposit that could not be converted was returned over M-Pesa
+    DepositRefunded {
+        /// Deposit that was refunded
+        transaction_id: Uuid,
+        refund_transaction_id: Uuid,
+        user_id: UserId,
+        amount_kes: KesAmount,
+        mpesa_code: MpesaCode,
+    },
+    /// A scheduled payment run failed and will not be retried
+    ScheduledPaymentFailed {
+        scheduled_payment_id: Uuid,
+        user_id: UserId,
+        /// Lightning address or phone number the payment was for
+        target: String,
+        attempts: i32,
+        reason: String,
+    },
+    /// Another user asked this user for money
+    PaymentRequestReceived {
+        payment_request_id: Uuid,
+        user_id: UserId,
+        /// Lightning address of the user asking
+        requester: String,
+        /// Requested amount with its currency (e.g., "KES 500.00", "5000 sats")
+        amount: String,
+        memo: Option<String>,
+    },
+    /// A payment request this user sent was paid
+    PaymentRequestPaid {
+        payment_request_id: Uuid,
+        user_id: UserId,
+        /// Lightning address of the user who paid
+        payer: String,
+        amount_sats: SatAmount,
+        /// Credit to this user's wallet
+        transaction_id: Uuid,
+    },
+    /// This user paid a payment request
+    PaymentRequestApproved {
+        payment_request_id: Uuid,
+        user_id: UserId,
+        requester: String,
+        amount_sats: SatAmount,
+        /// Debit from this user's wallet
+        transaction_id: Uuid,
+    },
+    /// A payment request this user sent was declined
+    PaymentRequestDeclined {
+        payment_request_id: Uuid,
+        user_id: UserId,
+        payer: String,
+        amount: String,
+    },
+    /// A payment request to this user was withdrawn by the user who sent it
+    PaymentRequestCancelled {
+        payment_request_id: Uuid,
+        user_id: UserId,
+        requester: String,
+        amount: String,
+    },
+    /// A DCA plan was paused after repeated declined deposits
+    DcaPlanPaused {
+        dca_plan_id: Uuid,
+        user_id: UserId,
+        amount_kes: KesAmount,
+        /// Runs in a row that failed
+        failures: i32,
+        reason: String,
+    },
+    /// An early withdrawal from a locked vault was requested
+    VaultWithdrawalScheduled {
+        vault_withdrawal_id: Uuid,
+        vault_id: Uuid,
+        user_id: UserId,
+        vault_name: String,
+        amount_sats: SatAmount,
+        /// End of the cooling-off delay
+        available_at: DateTime<Utc>,
+    },
+    /// Airtime was delivered to a phone
+    AirtimePurchased {
+        transaction_id: Uuid,
+        user_id: UserId,
+        phone_number: String,
+        amount_kes: Option<KesAmount>,
+        amount_sats: Option<SatAmount>,
+    },
+    /// Airtime could not be delivered (the sats are refunded)
+    AirtimeFailed {
+        transaction_id: Uuid,
+        user_id: UserId,
+        phone_number: String,
+        amount_sats: Option<SatAmount>,
+        reason: Option<String>,
+    },
+    /// New user completed registration
+    UserRegistered {
+        user_id: UserId,
+        lightning_username: String,
+    },
+}
+
+impl DomainEvent {
+    /// Stable event name (matches the serialized `type` tag)
+    pub fn event_type(&self) -> &'static str {
+        match self {
+            DomainEvent::DepositCompleted { .. } => "DepositCompleted",
+            DomainEvent::DepositFailed { .. } => "DepositFailed",
+            DomainEvent::WithdrawalCompleted { .. } => "WithdrawalCompleted",
+            DomainEvent::WithdrawalFailed { .. } => "WithdrawalFailed",
+            DomainEvent::InvoicePaid { .. } => "InvoicePaid",
+            DomainEvent::PaymentSent { .. } => "PaymentSent",
+            DomainEvent::PaymentFailed { .. } => "PaymentFailed",
+            DomainEvent::RefundCredited { .. } => "RefundCredited",
+            DomainEvent::DepositRefunded { .. } => "DepositRefunded",
+            DomainEvent::ScheduledPaymentFailed { .. } => "ScheduledPaymentFailed",
+            DomainEvent::PaymentRequestReceived { .. } => "PaymentRequestReceived",
+            DomainEvent::PaymentRequestPaid { .. } => "PaymentRequestPaid",
+            DomainEvent::PaymentRequestApproved { .. } => "PaymentRequestApproved",
+            DomainEvent::PaymentRequestDeclined { .. } => "PaymentRequestDeclined",
+            DomainEvent::PaymentRequestCancelled { .. } => "PaymentRequestCancelled",
+            DomainEvent::DcaPlanPaused { .. } => "DcaPlanPaused",
+            DomainEvent::VaultWithdrawalScheduled { .. } => "VaultWithdrawalScheduled",
+            DomainEvent::AirtimePurchased { .. } => "AirtimePurchased",
+            DomainEvent::AirtimeFailed { .. } => "AirtimeFailed",
+            DomainEvent::UserRegistered { .. } => "UserRegistered",
+        }
+    }
+
+    /// Kind and ID of the entity the event is about
+    pub fn aggregate(&self) -> (&'static str, Uuid) {
+        match self {
+            DomainEvent::DepositCompleted { transaction_id, .. }
+            | DomainEvent::DepositFailed { transaction_id, .. }
+            | DomainEvent::WithdrawalCompleted { transaction_id, .. }
+            | DomainEvent::WithdrawalFailed { transaction_id, .. }
+            | DomainEvent::InvoicePaid { transaction_id, .. }
+            | DomainEvent::PaymentSent { transaction_id, .. }
+            | DomainEvent::PaymentFailed { transaction_id, .. }
+            | DomainEvent::RefundCredited { transaction_id, .. }
+            | DomainEvent::DepositRefunded { transaction_id, .. }
+            | DomainEvent::AirtimePurchased { transaction_id, .. }
+            | DomainEvent::AirtimeFailed { transaction_id, .. } => ("transaction", *transaction_id),
+            DomainEvent::ScheduledPaymentFailed { scheduled_payment_id, .. } => {
+                ("scheduled_payment", *scheduled_payment_id)
+            }
+            DomainEvent::PaymentRequestReceived { payment_request_id, .. }
+            | DomainEvent::PaymentRequestPaid { payment_request_id, .. }
+            | DomainEvent::PaymentRequestApproved { payment_request_id, .. }
+            | DomainEvent::PaymentRequestDeclined { payment_request_id, .. }
+            | DomainEvent::PaymentRequestCancelled { payment_request_id, .. } => {
+                ("payment_request", *payment_request_id)
+            }
+            DomainEvent::DcaPlanPaused { dca_plan_id, .. } => ("dca_plan", *dca_plan_id),
+            DomainEvent::VaultWithdrawalScheduled { vault_id, .. } => ("vault", *vault_id),
+            DomainEvent::UserRegistered { user_id, .. } => ("user", user_id.0),
+        }
+    }
+
+    /// User the event concerns
+    pub fn user_id(&self) -> UserId {
+        match self {
+            DomainEvent::DepositCompleted { user_id, .. }
+            | DomainEvent::DepositFailed { user_id, .. }
+            | DomainEvent::WithdrawalCompleted { user_id, .. }
+            | DomainEvent::WithdrawalFailed { user_id, .. }
+            | DomainEvent::InvoicePaid { user_id, .. }
+            | DomainEvent::PaymentSent { user_id, .. }
+            | DomainEvent::PaymentFailed { user_id, .. }
+            | DomainEvent::RefundCredited { user_id, .. }
+            | DomainEvent::DepositRefunded { user_id, .. }
+            | DomainEvent::ScheduledPaymentFailed { user_id, .. }
+            | DomainEvent::PaymentRequestReceived { user_id, .. }
+            | DomainEvent::PaymentRequestPaid { user_id, .. }
+            | DomainEvent::PaymentRequestApproved { user_id, .. }
+            | DomainEvent::PaymentRequestDeclined { user_id, .. }
+            | DomainEvent::PaymentRequestCancelled { user_id, .. }
+            | DomainEvent::DcaPlanPaused { user_id, .. }
+            | DomainEvent::VaultWithdrawalScheduled { user_id, .. }
+            | DomainEvent::AirtimePurchased { user_id, .. }
+            | DomainEvent::AirtimeFailed { user_id, .. }
+            | DomainEvent::UserRegistered { user_id, .. } => *user_id,
+        }
+    }
+
+    /// Event fields as a JSON object (the serialized `data` content)
+    pub fn data(&self) -> serde_json::Value {
+        serde_json::to_value(self)
+            .ok()
+            .and_then(|mut event| event.get_mut("data").map(serde_json::Value::take))
+            .unwrap_or_else(|| serde_json::json!({}))
+    }
+
+    /// Event for a transaction that just reached its current status
+    /// Returns `None` for statuses other systems don't react to (pending, processing)
+    pub fn for_transaction(transaction: &Transaction) -> Option<Self> {
+        let transaction_id = transaction.id;
+        let user_id = transaction.user_id;
+        let reason = transaction
+            .metadata
+            .get("error")
+            .and_then(|error| error.as_str())
+            .map(str::to_string);
+
+        let event = match (&transaction.transaction_type, &transaction.status) {
+            (TransactionType::DepositMpesa, TransactionStatus::Completed) => DomainEvent::DepositCompleted {
+                transaction_id,
+                user_id,
+                amount_kes: transaction.amount_kes,
+                amount_sats: transaction.amount_sats,
+                mpesa_code: transaction.mpesa_code.clone(),
+            },
+            (TransactionType::DepositMpesa, TransactionStatus::Failed) => DomainEvent::DepositFailed {
+                transaction_id,
+                user_id,
+                amount_kes: transaction.amount_kes,
+                reason,
+            },
+            (TransactionType::WithdrawalMpesa, TransactionStatus::Completed) => DomainEvent::WithdrawalCompleted {
+                transaction_id,
+                user_id,
+                amount_sats: transaction.amount_sats,
+                amount_kes: transaction.amount_kes,
+                mpesa_code: transaction.mpesa_code.clone(),
+            },
+            (TransactionType::WithdrawalMpesa, TransactionStatus::Failed) => DomainEvent::WithdrawalFailed {
+                transaction_id,
+                user_id,
+                amount_sats: transaction.amount_sats,
+                reason,
+            },
+            (TransactionType::LightningReceive, TransactionStatus::Completed) => DomainEvent::InvoicePaid {
+                transaction_id,
+                user_id,
+                amount_sats: transaction.amount_sats,
+            },
+            (TransactionType::LightningSend, TransactionStatus::Completed) => DomainEvent::PaymentSent {
+                transaction_id,
+                user_id,
+                amount_sats: transaction.amount_sats,
+                fee_sats: transaction.fee_sats,
+            },
+            (TransactionType::LightningSend, TransactionStatus::Failed) => DomainEvent::PaymentFailed {
+                transaction_id,
+                user_id,
+                amount_sats: transaction.amount_sats,
+                reason,
+            },
+            (TransactionType::AirtimePurchase, TransactionStatus::Completed) => DomainEvent::AirtimePurchased {
+                transaction_id,
+                user_id,
+                phone_number: airtime_phone_number(transaction),
+                amount_kes: transaction.amount_kes,
+                amount_sats: transaction.amount_sats,
+            },
+            (TransactionType::AirtimePurchase, TransactionStatus::Failed) => DomainEvent::AirtimeFailed {
+                transaction_id,
+                user_id,
+                phone_number: airtime_phone_number(transaction),
+                amount_sats: transaction.amount_sats,
+                reason,
+            },
+            _ => return None,
+        };
+
+        Some(event)
+    }
+}
+
+/// Phone an airtime purchase was for (kept in the transaction's metadata)
+fn airtime_phone_number(transaction: &Transaction) -> String {
+    transaction
+        .metadata
+        .get("phone_number")
+        .and_then(|phone_number| phone_number.as_str())
+        .unwrap_or_default()
+        .to_string()
+}
+
+/// Event as stored in the outbox and published to the stream
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EventEnvelope {
+    /// Unique event ID, use it to deduplicate redeliveries
+    pub id: Uuid,
+    /// Position in the outbox (increases with commit order of inserts)
+    pub sequence: i64,
+    pub occurred_at: DateTime<Utc>,
+    #[serde(flatten)]
+    pub event: DomainEvent,
+}
+
+/// Convert Redis failures into service errors
+fn redis_error(error: redis::RedisError) -> shared_errors::AppError {
+    shared_errors::AppError::ExternalService {
+        message: format!("Redis error: {}", error),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal::Decimal;
+
+    fn transaction(transaction_type: TransactionType, status: TransactionStatus) -> Transaction {
+        Transaction {
+            id: Uuid::new_v4(),
+            user_id: UserId::new(),
+            transaction_type,
+            status,
+            amount_kes: Some(KesAmount::new(Decimal::from(1000))),
+            amount_sats: Some(SatAmount::new(18_679)),
+            exchange_rate: None,
+            fee_kes: None,
+            fee_sats: None,
+            fee_schedule_id: None,
+            mpesa_code: Some(MpesaCode("QL12XYZ789".to_string())),
+            lightning_invoice: None,
+            lightning_preimage: None,
+            metadata: serde_json::json!({"error": "Request cancelled by user"}),
+            created_at: Utc::now(),
+            completed_at: None,
+        }
+    }
+
+    #[test]
+    fn test_events_for_transaction_status() {
+        let deposit = transaction(TransactionType::DepositMpesa, TransactionStatus::Completed);
+        let event = DomainEvent::for_transaction(&deposit).unwrap();
+        assert_eq!(event.event_type(), "DepositCompleted");
+        assert_eq!(event.aggregate(), ("transaction", deposit.id));
+        assert_eq!(event.user_id(), deposit.user_id);
+
+        let withdrawal = transaction(TransactionType::WithdrawalMpesa, TransactionStatus::Failed);
+        match DomainEvent::for_transaction(&withdrawal) {
+            Some(DomainEvent::WithdrawalFailed { reason, .. }) => {
+                assert_eq!(reason.as_deref(), Some("Request cancelled by user"))
+            }
+            other => panic!("unexpected event {:?}", other),
+        }
+
+        let invoice = transaction(TransactionType::LightningReceive, TransactionStatus::Completed);
+        assert_eq!(DomainEvent::for_transaction(&invoice).unwrap().event_type(), "InvoicePaid");
+
+        let mut airtime = transaction(TransactionType::AirtimePurchase, TransactionStatus::Failed);
+        airtime.metadata["phone_number"] = serde_json::json!("+254733123456");
+        match DomainEvent::for_transaction(&airtime) {
+            Some(DomainEvent::AirtimeFailed { phone_number, .. }) => assert_eq!(phone_number, "+254733123456"),
+            other => panic!("unexpected event {:?}", other),
+        }
+
+        let pending = transaction(TransactionType::DepositMpesa, TransactionStatus::Pending);
+        assert!(DomainEvent::for_transaction(&pending).is_none());
+    }
+
+    #[test]
+    fn test_envelope_serialization() {
+        let user_id = UserId::new();
+        let envelope = EventEnvelope {
+            id: Uuid::new_v4(),
+            sequence: 42,
+            occurred_at: Utc::now(),
+            event: DomainEvent::UserRegistered {
+                user_id,
+                lightning_username: "john".to_string(),
+            },
+        };
+
+        let json = serde_json::to_value(&envelope).unwrap();
+        assert_eq!(json["type"], "UserRegistered");
+        assert_eq!(json["data"]["lightning_username"], "john");
+        assert_eq!(json["sequence"], 42);
+
+        let parsed: EventEnvelope = serde_json::from_value(json).unwrap();
+        assert_eq!(parsed, envelope);
+        assert_eq!(envelope.event.data(), serde_json::json!({"user_id": user_id, "lightning_username": "john"}));
+    }
+}
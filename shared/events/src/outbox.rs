@@ -0,0 +1,47 @@
+/// Writing events to the transactional outbox
+///
+/// Always call these with the connection of the database transaction that makes
+/// the state change, e.g. `record_event(&mut *tx, &event)`, so the event is
+/// committed or rolled back together with it.
+
+use crate::DomainEvent;
+use shared_errors::{AppError, Result};
+use shared_types::Transaction;
+use sqlx::PgConnection;
+use tracing::instrument;
+use uuid::Uuid;
+
+/// Store an event in the outbox, returning its ID
+#[instrument(skip(conn, event), fields(event_type = event.event_type()))]
+pub async fn record_event(conn: &mut PgConnection, event: &DomainEvent) -> Result<Uuid> {
+    let id = Uuid::new_v4();
+    let (aggregate_type, aggregate_id) = event.aggregate();
+    let payload = serde_json::to_value(event).map_err(|e| AppError::Internal(e.into()))?;
+
+    sqlx::query(
+        r#"
+        INSERT INTO outbox_events (id, event_type, aggregate_type, aggregate_id, user_id, payload)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        "#,
+    )
+    .bind(id)
+    .bind(event.event_type())
+    .bind(aggregate_type)
+    .bind(aggregate_id)
+    .bind(event.user_id().0)
+    .bind(payload)
+    .execute(conn)
+    .await?;
+
+    Ok(id)
+}
+
+/// Store the event for a transaction's new status, if that status has one
+///
+/// Call after updating the transaction's status in the same database transaction.
+pub async fn record_transaction_event(conn: &mut PgConnection, transaction: &Transaction) -> Result<Option<Uuid>> {
+    match DomainEvent::for_transaction(transaction) {
+        Some(event) => record_event(conn, &event).await.map(Some),
+        None => Ok(None),
+    }
+}
@@ -0,0 +1,211 @@
+/// Outbox relay: publishes recorded events to Redis Streams
+///
+/// Each batch locks unpublished rows with `FOR UPDATE SKIP LOCKED`, so several
+/// relays (one per service instance) can run side by side without publishing the
+/// same row concurrently. A row is marked published only after Redis accepted it;
+/// if the process dies in between, the event is published again on the next run.
+/// Rows whose payload cannot be decoded are skipped and, once they are old enough,
+/// dead-lettered so they do not stall the relay.
+
+use crate::{redis_error, DomainEvent, EventEnvelope, EVENTS_STREAM};
+use chrono::{DateTime, Utc};
+use redis::{streams::StreamMaxlen, AsyncCommands};
+use shared_errors::{AppError, Result};
+use sqlx::{PgPool, Row};
+use std::time::{Duration, Instant};
+use tracing::{error, info, instrument, warn};
+use uuid::Uuid;
+
+/// Approximate number of entries kept in the stream
+const STREAM_MAX_LEN: usize = 1_000_000;
+
+/// How long published events are kept in the outbox table
+const PUBLISHED_RETENTION_DAYS: i32 = 7;
+
+/// How long a payload that cannot be decoded is retried before it is dead-lettered,
+/// counted from when the event occurred (a newer relay rolling out alongside may
+/// still know the event type)
+const DECODE_RETRY_HOURS: i64 = 24;
+
+/// Publishes outbox events to the events stream
+pub struct OutboxRelay {
+    pool: PgPool,
+    redis: redis::Client,
+    stream: String,
+    batch_size: i64,
+    poll_interval: Duration,
+}
+
+impl OutboxRelay {
+    pub fn new(pool: PgPool, redis: redis::Client) -> Self {
+        Self {
+            pool,
+            redis,
+            stream: EVENTS_STREAM.to_string(),
+            batch_size: 100,
+            poll_interval: Duration::from_millis(500),
+        }
+    }
+
+    /// Create a relay using `REDIS_URL`
+    pub fn from_env(pool: PgPool) -> Result<Self> {
+        let redis_url = std::env::var("REDIS_URL")
+            .unwrap_or_else(|_| "redis://:redis_dev_password@localhost:6379".to_string());
+        let redis = redis::Client::open(redis_url).map_err(redis_error)?;
+
+        Ok(Self::new(pool, redis))
+    }
+
+    /// Publish events until the process exits
+    pub async fn run(self) {
+        info!("Outbox relay publishing to stream {}", self.stream);
+        let mut last_purge = Instant::now();
+
+        loop {
+            match self.publish_batch().await {
+                // A full batch means more events are probably waiting
+                Ok(published) if published as i64 == self.batch_size => continue,
+                Ok(_) => {}
+                Err(e) => error!("Outbox relay failed: {}", e),
+            }
+
+            if last_purge.elapsed() > Duration::from_secs(3600) {
+                if let Err(e) = self.purge_published().await {
+                    warn!("Failed to purge published outbox events: {}", e);
+                }
+                last_purge = Instant::now();
+            }
+
+            tokio::time::sleep(self.poll_interval).await;
+        }
+    }
+
+    /// Publish the oldest unpublished events, returning how many were published
+    #[instrument(skip(self))]
+    pub async fn publish_batch(&self) -> Result<usize> {
+        let mut tx = self.pool.begin().await?;
+
+        let rows = sqlx::query(
+            r#"
+            SELECT id, sequence, payload, occurred_at, publish_attempts
+            FROM outbox_events
+            WHERE published_at IS NULL AND dead_lettered_at IS NULL
+            ORDER BY sequence ASC
+            LIMIT $1
+            FOR UPDATE SKIP LOCKED
+            "#,
+        )
+        .bind(self.batch_size)
+        .fetch_all(&mut *tx)
+        .await?;
+
+        if rows.is_empty() {
+            return Ok(0);
+        }
+
+        let mut conn = self
+            .redis
+            .get_multiplexed_async_connection()
+            .await
+            .map_err(redis_error)?;
+
+        let mut published: Vec<Uuid> = Vec::with_capacity(rows.len());
+
+        for row in rows {
+            let id: Uuid = row.try_get("id")?;
+            let payload: serde_json::Value = row.try_get("payload")?;
+            let event: DomainEvent = match serde_json::from_value(payload) {
+                Ok(event) => event,
+                Err(e) => {
+                    // Skip it so one unreadable row cannot block every event behind it
+                    let attempts: i32 = row.try_get::<i32, _>("publish_attempts")? + 1;
+                    let occurred_at: DateTime<Utc> = row.try_get("occurred_at")?;
+                    let dead_letter = Utc::now() - occurred_at >= chrono::Duration::hours(DECODE_RETRY_HOURS);
+                    if dead_letter {
+                        error!("Dead-lettering outbox event {} after {} attempts: {}", id, attempts, e);
+                    } else if attempts == 1 {
+                        // Retried every poll, so only the first skip is logged
+                        warn!("Skipping undecodable outbox event {}: {}", id, e);
+                    }
+
+                    sqlx::query(
+                        r#"
+                        UPDATE outbox_events
+                        SET publish_attempts = publish_attempts + 1,
+                            last_error = $2,
+                            dead_lettered_at = CASE WHEN $3 THEN NOW() END
+                        WHERE id = $1
+                        "#,
+                    )
+                    .bind(id)
+                    .bind(format!("Undecodable payload: {}", e))
+                    .bind(dead_letter)
+                    .execute(&mut *tx)
+                    .await?;
+                    continue;
+                }
+            };
+
+            let envelope = EventEnvelope {
+                id,
+                sequence: row.try_get("sequence")?,
+                occurred_at: row.try_get::<DateTime<Utc>, _>("occurred_at")?,
+                event,
+            };
+
+            let body = serde_json::to_string(&envelope).map_err(|e| AppError::Internal(e.into()))?;
+            let fields = [
+                ("event_id", id.to_string()),
+                ("event_type", envelope.event.event_type().to_string()),
+                ("envelope", body),
+            ];
+
+            let added: redis::RedisResult<String> = conn
+                .xadd_maxlen(&self.stream, StreamMaxlen::Approx(STREAM_MAX_LEN), "*", &fields)
+                .await;
+
+            if let Err(e) = added {
+                // Stop at the first failure so events keep their order
+                warn!("Failed to publish outbox event {}: {}", id, e);
+                sqlx::query(
+                    "UPDATE outbox_events SET publish_attempts = publish_attempts + 1, last_error = $2 WHERE id = $1",
+                )
+                .bind(id)
+                .bind(e.to_string())
+                .execute(&mut *tx)
+                .await?;
+                break;
+            }
+
+            published.push(id);
+        }
+
+        sqlx::query(
+            r#"
+            UPDATE outbox_events
+            SET published_at = NOW(), publish_attempts = publish_attempts + 1, last_error = NULL
+            WHERE id = ANY($1)
+            "#,
+        )
+        .bind(&published)
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(published.len())
+    }
+
+    /// Delete events that were published long enough ago
+    #[instrument(skip(self))]
+    pub async fn purge_published(&self) -> Result<u64> {
+        let result = sqlx::query(
+            "DELETE FROM outbox_events WHERE published_at < NOW() - make_interval(days => $1)",
+        )
+        .bind(PUBLISHED_RETENTION_DAYS)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+}
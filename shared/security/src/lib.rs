@@ -308,6 +308,23 @@ impl SecurityRateLimiter {
     }
 }
 
+/// Compare callback tokens in constant time
+fn callback_token_matches(expected: &str, given: &str) -> bool {
+    expected.len() == given.len()
+        && expected.bytes().zip(given.bytes()).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+/// Check the secret token a provider callback carries in its URL
+/// No token configured means every callback is refused
+pub fn authorize_callback(expected: Option<&str>, given: Option<&str>) -> Result<()> {
+    match (expected, given) {
+        (Some(expected), Some(given)) if callback_token_matches(expected, given) => Ok(()),
+        _ => Err(AppError::Auth {
+            message: "Invalid callback token".to_string(),
+        }),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -359,4 +376,17 @@ mod tests {
         // Should block requests beyond limit
         assert!(!limiter.check_ip_limit(ip, limit, window));
     }
+
+    #[test]
+    fn test_callback_token_matches() {
+        assert!(callback_token_matches("s3cret-token", "s3cret-token"));
+        assert!(!callback_token_matches("s3cret-token", "s3cret-tokem"));
+        assert!(!callback_token_matches("s3cret-token", "s3cret"));
+        assert!(!callback_token_matches("s3cret-token", ""));
+
+        assert!(authorize_callback(Some("s3cret-token"), Some("s3cret-token")).is_ok());
+        assert!(authorize_callback(Some("s3cret-token"), None).is_err());
+        // Without a configured token every callback is refused
+        assert!(authorize_callback(None, Some("")).is_err());
+    }
 }
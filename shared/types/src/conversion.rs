@@ -0,0 +1,249 @@
+/// Currency conversion between satoshis and Kenyan Shillings
+///
+/// This is the only place sats ↔ KES conversions should happen. Every rate is
+/// expressed as KES per whole bitcoin (`BtcKesRate`), every conversion states
+/// who the rounding should favour, and all arithmetic is checked so an overflow
+/// or a bad rate is reported as an error instead of silently becoming zero.
+
+use crate::{KesAmount, SatAmount};
+use rust_decimal::{Decimal, RoundingStrategy};
+use serde::{Deserialize, Serialize};
+
+/// Number of satoshis in one bitcoin
+pub const SATS_PER_BTC: i64 = 100_000_000;
+
+/// Decimal places KES amounts are rounded to (cents)
+pub const KES_DECIMAL_PLACES: u32 = 2;
+
+/// Errors that can occur while converting between currencies
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum ConversionError {
+    /// Rate was zero or negative
+    #[error("Exchange rate must be positive")]
+    InvalidRate,
+
+    /// Amount to convert was negative
+    #[error("Cannot convert a negative amount")]
+    NegativeAmount,
+
+    /// Result does not fit in the target type
+    #[error("Amount too large to convert")]
+    Overflow,
+}
+
+/// Bitcoin price in KES per whole bitcoin (e.g., 5,300,000 KES per BTC)
+///
+/// This is the single rate unit used across PesaBit: in the `exchange_rates`
+/// table, on transactions and in API responses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, sqlx::Type)]
+#[serde(try_from = "Decimal", into = "Decimal")]
+#[sqlx(transparent)]
+pub struct BtcKesRate(Decimal);
+
+impl BtcKesRate {
+    /// Create a rate from a KES-per-BTC price, rejecting zero and negative values
+    pub fn new(kes_per_btc: Decimal) -> Result<Self, ConversionError> {
+        if kes_per_btc <= Decimal::ZERO {
+            return Err(ConversionError::InvalidRate);
+        }
+        Ok(Self(kes_per_btc))
+    }
+
+    /// Price of one bitcoin in KES
+    pub fn kes_per_btc(&self) -> Decimal {
+        self.0
+    }
+
+    /// Convert satoshis to KES, rounded to cents in the direction `rounding` asks for
+    pub fn sats_to_kes(
+        &self,
+        sats: SatAmount,
+        side: Side,
+        rounding: Rounding,
+    ) -> Result<KesAmount, ConversionError> {
+        if sats.as_i64() < 0 {
+            return Err(ConversionError::NegativeAmount);
+        }
+
+        let kes = Decimal::from(sats.as_i64())
+            .checked_mul(self.0)
+            .and_then(|v| v.checked_div(Decimal::from(SATS_PER_BTC)))
+            .ok_or(ConversionError::Overflow)?;
+
+        Ok(KesAmount::new(
+            kes.round_dp_with_strategy(KES_DECIMAL_PLACES, rounding.strategy(side)),
+        ))
+    }
+
+    /// Convert KES to whole satoshis, rounded in the direction `rounding` asks for
+    pub fn kes_to_sats(
+        &self,
+        kes: &KesAmount,
+        side: Side,
+        rounding: Rounding,
+    ) -> Result<SatAmount, ConversionError> {
+        if kes.as_decimal() < Decimal::ZERO {
+            return Err(ConversionError::NegativeAmount);
+        }
+
+        // Multiply before dividing so the division is the only inexact step
+        let sats = kes
+            .as_decimal()
+            .checked_mul(Decimal::from(SATS_PER_BTC))
+            .and_then(|v| v.checked_div(self.0))
+            .ok_or(ConversionError::Overflow)?
+            .round_dp_with_strategy(0, rounding.strategy(side));
+
+        i64::try_from(sats)
+            .map(SatAmount::new)
+            .map_err(|_| ConversionError::Overflow)
+    }
+}
+
+impl TryFrom<Decimal> for BtcKesRate {
+    type Error = ConversionError;
+
+    fn try_from(value: Decimal) -> Result<Self, Self::Error> {
+        Self::new(value)
+    }
+}
+
+impl From<BtcKesRate> for Decimal {
+    fn from(rate: BtcKesRate) -> Self {
+        rate.0
+    }
+}
+
+impl std::fmt::Display for BtcKesRate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} KES/BTC", self.0)
+    }
+}
+
+/// Which way the converted amount flows relative to the user
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Side {
+    /// The result is paid or credited to the user (e.g., sats received for a deposit)
+    Payout,
+    /// The result is charged or debited from the user (e.g., sats needed to cover a fee)
+    Charge,
+}
+
+/// Who a conversion's rounding should favour
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rounding {
+    /// Round so PesaBit never pays out more or collects less than the exact value
+    HouseFavourable,
+    /// Round in the user's favour (promotions, refunds, goodwill adjustments)
+    UserFavourable,
+}
+
+impl Rounding {
+    /// Decimal rounding direction for amounts on the given side
+    pub(crate) fn strategy(&self, side: Side) -> RoundingStrategy {
+        match (self, side) {
+            (Rounding::HouseFavourable, Side::Payout) | (Rounding::UserFavourable, Side::Charge) => {
+                RoundingStrategy::ToZero
+            }
+            (Rounding::HouseFavourable, Side::Charge) | (Rounding::UserFavourable, Side::Payout) => {
+                RoundingStrategy::AwayFromZero
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use quickcheck_macros::quickcheck;
+
+    fn rate(kes_per_btc: i64) -> BtcKesRate {
+        BtcKesRate::new(Decimal::from(kes_per_btc)).unwrap()
+    }
+
+    /// Map an arbitrary u64 onto a realistic rate (1,000.00 to 100,000,000.00 KES/BTC)
+    fn arbitrary_rate(seed: u64) -> BtcKesRate {
+        let cents = 100_000 + seed % 9_999_900_000;
+        BtcKesRate::new(Decimal::new(cents as i64, 2)).unwrap()
+    }
+
+    #[test]
+    fn test_rate_must_be_positive() {
+        assert_eq!(BtcKesRate::new(Decimal::ZERO), Err(ConversionError::InvalidRate));
+        assert_eq!(BtcKesRate::new(Decimal::from(-1)), Err(ConversionError::InvalidRate));
+        assert!(serde_json::from_str::<BtcKesRate>("\"0\"").is_err());
+    }
+
+    #[test]
+    fn test_sats_to_kes() {
+        let rate = rate(5_300_000);
+        let kes = rate.sats_to_kes(SatAmount::new(100_000), Side::Payout, Rounding::HouseFavourable).unwrap();
+        assert_eq!(kes.as_decimal(), Decimal::new(5300, 0)); // 0.001 BTC = 5,300 KES
+
+        // 1 sat = 0.053 KES: house pays out 0.05, charges 0.06
+        let one = SatAmount::new(1);
+        assert_eq!(rate.sats_to_kes(one, Side::Payout, Rounding::HouseFavourable).unwrap().as_decimal(), Decimal::new(5, 2));
+        assert_eq!(rate.sats_to_kes(one, Side::Charge, Rounding::HouseFavourable).unwrap().as_decimal(), Decimal::new(6, 2));
+        assert_eq!(rate.sats_to_kes(one, Side::Payout, Rounding::UserFavourable).unwrap().as_decimal(), Decimal::new(6, 2));
+    }
+
+    #[test]
+    fn test_kes_to_sats() {
+        let rate = rate(5_300_000);
+        let kes = KesAmount::new(Decimal::from(1000)); // 18,867.92... sats
+
+        assert_eq!(rate.kes_to_sats(&kes, Side::Payout, Rounding::HouseFavourable).unwrap().as_i64(), 18_867);
+        assert_eq!(rate.kes_to_sats(&kes, Side::Charge, Rounding::HouseFavourable).unwrap().as_i64(), 18_868);
+    }
+
+    #[test]
+    fn test_invalid_amounts_are_errors_not_zero() {
+        let rate = rate(5_300_000);
+        assert_eq!(
+            rate.sats_to_kes(SatAmount::new(-1), Side::Payout, Rounding::HouseFavourable),
+            Err(ConversionError::NegativeAmount)
+        );
+
+        let huge = KesAmount::new(Decimal::MAX);
+        assert_eq!(
+            rate.kes_to_sats(&huge, Side::Payout, Rounding::HouseFavourable),
+            Err(ConversionError::Overflow)
+        );
+    }
+
+    /// Selling sats for KES and buying back with that KES never yields more sats
+    #[quickcheck]
+    fn prop_sats_round_trip_never_creates_money(sats: u64, rate_seed: u64) -> bool {
+        let rate = arbitrary_rate(rate_seed);
+        let sats = SatAmount::new((sats % (21_000_000 * SATS_PER_BTC as u64)) as i64);
+
+        let kes = rate.sats_to_kes(sats, Side::Payout, Rounding::HouseFavourable).unwrap();
+        let back = rate.kes_to_sats(&kes, Side::Payout, Rounding::HouseFavourable).unwrap();
+
+        back <= sats
+    }
+
+    /// Buying sats with KES and selling them back never yields more KES
+    #[quickcheck]
+    fn prop_kes_round_trip_never_creates_money(cents: u64, rate_seed: u64) -> bool {
+        let rate = arbitrary_rate(rate_seed);
+        let kes = KesAmount::new(Decimal::new((cents % 100_000_000_000) as i64, 2));
+
+        let sats = rate.kes_to_sats(&kes, Side::Payout, Rounding::HouseFavourable).unwrap();
+        let back = rate.sats_to_kes(sats, Side::Payout, Rounding::HouseFavourable).unwrap();
+
+        back <= kes
+    }
+
+    /// Sats charged to cover a KES amount are always worth at least that amount
+    #[quickcheck]
+    fn prop_charges_never_under_collect(cents: u64, rate_seed: u64) -> bool {
+        let rate = arbitrary_rate(rate_seed);
+        let kes = KesAmount::new(Decimal::new((cents % 100_000_000_000) as i64, 2));
+
+        let charged = rate.kes_to_sats(&kes, Side::Charge, Rounding::HouseFavourable).unwrap();
+        let worth = rate.sats_to_kes(charged, Side::Payout, Rounding::HouseFavourable).unwrap();
+
+        worth >= kes
+    }
+}
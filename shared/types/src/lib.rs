@@ -4,10 +4,15 @@
 /// By centralizing these types, we ensure consistency across all services and avoid duplication.
 
 use chrono::{DateTime, Utc};
-use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
+pub mod conversion;
+pub mod money;
+
+pub use conversion::BtcKesRate;
+pub use money::{KesAmount, MsatAmount, SatAmount};
+
 /// Unique identifier for a user in the system
 /// This is used consistently across all services to identify users
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, sqlx::Type)]
@@ -32,50 +37,6 @@ impl std::fmt::Display for UserId {
     }
 }
 
-/// Represents an amount of money in Kenyan Shillings
-/// Uses Decimal for precise financial calculations (no floating point errors)
-#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
-#[sqlx(transparent)]
-pub struct KesAmount(pub Decimal);
-
-impl KesAmount {
-    pub fn new(amount: Decimal) -> Self {
-        Self(amount)
-    }
-    
-    pub fn from_major(major: i64) -> Self {
-        Self(Decimal::new(major, 2)) // 2 decimal places for cents
-    }
-    
-    pub fn zero() -> Self {
-        Self(Decimal::ZERO)
-    }
-    
-    pub fn is_positive(&self) -> bool {
-        self.0 > Decimal::ZERO
-    }
-}
-
-/// Represents an amount in Bitcoin satoshis (smallest Bitcoin unit)
-/// 1 Bitcoin = 100,000,000 satoshis
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
-#[sqlx(transparent)]
-pub struct SatAmount(pub i64);
-
-impl SatAmount {
-    pub fn new(sats: i64) -> Self {
-        Self(sats)
-    }
-    
-    pub fn zero() -> Self {
-        Self(0)
-    }
-    
-    pub fn is_positive(&self) -> bool {
-        self.0 > 0
-    }
-}
-
 /// Phone number in international E.164 format
 /// Example: +254712345678 (Kenyan mobile number)
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize, sqlx::Type)]
@@ -125,14 +86,22 @@ pub struct PaymentPreimage(pub String);
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
 #[sqlx(type_name = "transaction_type", rename_all = "snake_case")]
 pub enum TransactionType {
-    /// User deposits KES via M-Pesa, gets Bitcoin satoshis
+    /// User deposits KES via mobile money (M-Pesa or another network), gets Bitcoin satoshis
     DepositMpesa,
-    /// User withdraws Bitcoin to M-Pesa as KES
+    /// User withdraws Bitcoin to mobile money as KES
     WithdrawalMpesa, 
     /// User sends Lightning payment to someone else
     LightningSend,
     /// User receives Lightning payment from someone else
     LightningReceive,
+    /// Reversal of a failed transaction (linked to it by `reverses_transaction_id`)
+    Refund,
+    /// User buys airtime for a phone, paid in sats
+    AirtimePurchase,
+    /// User moves sats from the spendable balance into a savings vault
+    VaultDeposit,
+    /// Sats come back from a savings vault to the spendable balance
+    VaultWithdrawal,
 }
 
 /// Current status of a transaction
@@ -151,6 +120,19 @@ pub enum TransactionStatus {
     Refunded,
 }
 
+impl TransactionStatus {
+    /// Name used in the database and in messages
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TransactionStatus::Pending => "pending",
+            TransactionStatus::Processing => "processing",
+            TransactionStatus::Completed => "completed",
+            TransactionStatus::Failed => "failed",
+            TransactionStatus::Refunded => "refunded",
+        }
+    }
+}
+
 /// User's KYC (Know Your Customer) verification status
 /// Higher tiers allow larger transaction limits
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
@@ -178,6 +160,40 @@ pub enum KycTier {
     Tier2,
 }
 
+/// Kind of account, chosen at registration
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[sqlx(type_name = "account_type", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum AccountType {
+    /// Individual user
+    #[default]
+    Personal,
+    /// Business taking payments (has a business profile and POS invoices)
+    Merchant,
+}
+
+/// Mobile money network a deposit or withdrawal goes through
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize, sqlx::Type)]
+#[sqlx(type_name = "mobile_money_network", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum MobileMoneyNetwork {
+    /// Safaricom M-Pesa
+    #[default]
+    Mpesa,
+    /// Airtel Money Kenya
+    AirtelMoney,
+}
+
+impl MobileMoneyNetwork {
+    /// Name shown to users
+    pub fn display_name(&self) -> &'static str {
+        match self {
+            MobileMoneyNetwork::Mpesa => "M-Pesa",
+            MobileMoneyNetwork::AirtelMoney => "Airtel Money",
+        }
+    }
+}
+
 /// Complete transaction record
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Transaction {
@@ -187,9 +203,10 @@ pub struct Transaction {
     pub status: TransactionStatus,
     pub amount_kes: Option<KesAmount>,
     pub amount_sats: Option<SatAmount>,
-    pub exchange_rate: Option<Decimal>, // KES per 100k sats at time of transaction
+    pub exchange_rate: Option<BtcKesRate>, // KES per BTC at time of transaction
     pub fee_kes: Option<KesAmount>,
     pub fee_sats: Option<SatAmount>,
+    pub fee_schedule_id: Option<Uuid>, // Fee schedule version used to price this transaction
     pub mpesa_code: Option<MpesaCode>,
     pub lightning_invoice: Option<LightningInvoice>,
     pub lightning_preimage: Option<PaymentPreimage>,
@@ -217,7 +234,7 @@ pub struct Wallet {
 pub struct ExchangeRate {
     pub id: i32,
     /// Bitcoin price in KES (e.g., 5,300,000 KES per BTC)
-    pub btc_kes: Decimal,
+    pub btc_kes: BtcKesRate,
     /// Data source (blockchain.info, binance, etc.)
     pub source: String,
     pub created_at: DateTime<Utc>,
@@ -240,11 +257,4 @@ mod tests {
         assert!(PhoneNumber::new("254712345678".to_string()).is_err()); // Missing +
         assert!(PhoneNumber::new("+123".to_string()).is_err()); // Too short
     }
-
-    #[test]
-    fn test_kes_amount() {
-        let amount = KesAmount::from_major(1000); // 10.00 KES
-        assert!(amount.is_positive());
-        assert_eq!(amount.0.to_string(), "10.00");
-    }
 }
\ No newline at end of file
@@ -0,0 +1,286 @@
+/// Money amounts with checked arithmetic
+///
+/// `MsatAmount`, `SatAmount` and `KesAmount` keep their inner values private so
+/// every operation goes through checked arithmetic. Amounts serialize as JSON
+/// strings so JavaScript clients never lose precision on large values, and they
+/// map directly onto the existing BIGINT and DECIMAL(15,2) columns.
+
+use crate::conversion::{Rounding, Side, KES_DECIMAL_PLACES};
+use rust_decimal::{Decimal, RoundingStrategy};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+/// Number of millisatoshis in one satoshi
+pub const MSATS_PER_SAT: i64 = 1_000;
+
+/// Amount in Lightning millisatoshis (1 sat = 1,000 msat)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, sqlx::Type)]
+#[sqlx(transparent)]
+pub struct MsatAmount(i64);
+
+impl MsatAmount {
+    pub fn new(msats: i64) -> Self {
+        Self(msats)
+    }
+
+    pub fn zero() -> Self {
+        Self(0)
+    }
+
+    pub fn as_i64(&self) -> i64 {
+        self.0
+    }
+
+    pub fn is_positive(&self) -> bool {
+        self.0 > 0
+    }
+
+    /// Exact conversion from whole satoshis
+    pub fn from_sats(sats: SatAmount) -> Option<Self> {
+        sats.0.checked_mul(MSATS_PER_SAT).map(Self)
+    }
+
+    /// Convert to whole satoshis, rounding any sub-sat remainder as `rounding` asks
+    pub fn to_sats(&self, side: Side, rounding: Rounding) -> SatAmount {
+        let whole = self.0 / MSATS_PER_SAT;
+        let remainder = self.0 % MSATS_PER_SAT;
+
+        let sats = match rounding.strategy(side) {
+            RoundingStrategy::AwayFromZero if remainder != 0 => whole + self.0.signum(),
+            _ => whole,
+        };
+        SatAmount(sats)
+    }
+
+    pub fn checked_add(self, other: Self) -> Option<Self> {
+        self.0.checked_add(other.0).map(Self)
+    }
+
+    pub fn checked_sub(self, other: Self) -> Option<Self> {
+        self.0.checked_sub(other.0).map(Self)
+    }
+
+    pub fn checked_mul(self, factor: i64) -> Option<Self> {
+        self.0.checked_mul(factor).map(Self)
+    }
+}
+
+/// Represents an amount in Bitcoin satoshis (smallest on-chain Bitcoin unit)
+/// 1 Bitcoin = 100,000,000 satoshis
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, sqlx::Type)]
+#[sqlx(transparent)]
+pub struct SatAmount(i64);
+
+impl SatAmount {
+    pub fn new(sats: i64) -> Self {
+        Self(sats)
+    }
+
+    pub fn zero() -> Self {
+        Self(0)
+    }
+
+    pub fn as_i64(&self) -> i64 {
+        self.0
+    }
+
+    pub fn is_positive(&self) -> bool {
+        self.0 > 0
+    }
+
+    pub fn checked_add(self, other: Self) -> Option<Self> {
+        self.0.checked_add(other.0).map(Self)
+    }
+
+    pub fn checked_sub(self, other: Self) -> Option<Self> {
+        self.0.checked_sub(other.0).map(Self)
+    }
+
+    pub fn checked_mul(self, factor: i64) -> Option<Self> {
+        self.0.checked_mul(factor).map(Self)
+    }
+}
+
+/// Represents an amount of money in Kenyan Shillings
+/// Uses Decimal for precise financial calculations (no floating point errors)
+/// and is always normalised to 2 decimal places (cents)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, sqlx::Type)]
+#[sqlx(transparent)]
+pub struct KesAmount(Decimal);
+
+impl KesAmount {
+    /// Create an amount, rounding half-cents away from zero
+    /// Use `conversion` for amounts where the rounding direction matters
+    pub fn new(amount: Decimal) -> Self {
+        let mut cents = amount.round_dp_with_strategy(KES_DECIMAL_PLACES, RoundingStrategy::MidpointAwayFromZero);
+        cents.rescale(KES_DECIMAL_PLACES);
+        Self(cents)
+    }
+
+    pub fn from_major(major: i64) -> Self {
+        Self::new(Decimal::new(major, 2)) // 2 decimal places for cents
+    }
+
+    pub fn zero() -> Self {
+        Self::new(Decimal::ZERO)
+    }
+
+    pub fn as_decimal(&self) -> Decimal {
+        self.0
+    }
+
+    pub fn is_positive(&self) -> bool {
+        self.0 > Decimal::ZERO
+    }
+
+    pub fn checked_add(self, other: Self) -> Option<Self> {
+        self.0.checked_add(other.0).map(Self::new)
+    }
+
+    pub fn checked_sub(self, other: Self) -> Option<Self> {
+        self.0.checked_sub(other.0).map(Self::new)
+    }
+
+    /// Multiply by a whole quantity (exact, no rounding involved)
+    pub fn checked_mul(self, factor: i64) -> Option<Self> {
+        self.0.checked_mul(Decimal::from(factor)).map(Self::new)
+    }
+}
+
+impl std::fmt::Display for MsatAmount {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} msat", self.0)
+    }
+}
+
+impl std::fmt::Display for SatAmount {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} sats", self.0)
+    }
+}
+
+impl std::fmt::Display for KesAmount {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "KES {}", self.0)
+    }
+}
+
+// Amounts are written as JSON strings ("1500", "10.00") but accepted as either
+// strings or numbers so existing clients keep working.
+
+impl Serialize for MsatAmount {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(&self.0)
+    }
+}
+
+impl<'de> Deserialize<'de> for MsatAmount {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(I64Visitor).map(Self)
+    }
+}
+
+impl Serialize for SatAmount {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(&self.0)
+    }
+}
+
+impl<'de> Deserialize<'de> for SatAmount {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(I64Visitor).map(Self)
+    }
+}
+
+impl Serialize for KesAmount {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(&self.0)
+    }
+}
+
+impl<'de> Deserialize<'de> for KesAmount {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        <Decimal as Deserialize>::deserialize(deserializer).map(Self::new)
+    }
+}
+
+/// Accepts an integer given as a JSON number or a string
+struct I64Visitor;
+
+impl<'de> de::Visitor<'de> for I64Visitor {
+    type Value = i64;
+
+    fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        formatter.write_str("an integer amount as a number or string")
+    }
+
+    fn visit_i64<E: de::Error>(self, value: i64) -> Result<i64, E> {
+        Ok(value)
+    }
+
+    fn visit_u64<E: de::Error>(self, value: u64) -> Result<i64, E> {
+        i64::try_from(value).map_err(|_| E::custom("amount out of range"))
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<i64, E> {
+        value.parse().map_err(|_| E::custom("invalid integer amount"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_kes_amount() {
+        let amount = KesAmount::from_major(1000); // 10.00 KES
+        assert!(amount.is_positive());
+        assert_eq!(amount.as_decimal().to_string(), "10.00");
+    }
+
+    #[test]
+    fn test_kes_normalised_to_cents() {
+        assert_eq!(KesAmount::new(Decimal::from(10)).as_decimal().to_string(), "10.00");
+        assert_eq!(KesAmount::new(Decimal::new(10005, 3)).as_decimal().to_string(), "10.01");
+        assert_eq!(KesAmount::new(Decimal::new(10004, 3)).as_decimal().to_string(), "10.00");
+    }
+
+    #[test]
+    fn test_checked_arithmetic() {
+        let a = SatAmount::new(i64::MAX - 1);
+        assert_eq!(a.checked_add(SatAmount::new(1)), Some(SatAmount::new(i64::MAX)));
+        assert_eq!(a.checked_add(SatAmount::new(2)), None);
+        assert_eq!(SatAmount::new(i64::MIN).checked_sub(SatAmount::new(1)), None);
+        assert_eq!(SatAmount::new(1_000).checked_mul(3), Some(SatAmount::new(3_000)));
+
+        let fee = KesAmount::new(Decimal::new(1050, 2));
+        assert_eq!(fee.checked_mul(3).unwrap().as_decimal(), Decimal::new(3150, 2));
+        assert_eq!(
+            KesAmount::from_major(1000).checked_sub(fee).unwrap().as_decimal(),
+            Decimal::new(-50, 2)
+        );
+    }
+
+    #[test]
+    fn test_msat_conversions() {
+        let msats = MsatAmount::new(1_500);
+        assert_eq!(msats.to_sats(Side::Payout, Rounding::HouseFavourable), SatAmount::new(1));
+        assert_eq!(msats.to_sats(Side::Charge, Rounding::HouseFavourable), SatAmount::new(2));
+        assert_eq!(MsatAmount::new(2_000).to_sats(Side::Charge, Rounding::HouseFavourable), SatAmount::new(2));
+
+        assert_eq!(MsatAmount::from_sats(SatAmount::new(21)), Some(MsatAmount::new(21_000)));
+        assert_eq!(MsatAmount::from_sats(SatAmount::new(i64::MAX)), None);
+    }
+
+    #[test]
+    fn test_amounts_serialize_as_strings() {
+        assert_eq!(serde_json::to_string(&SatAmount::new(2_100_000_000_000_000)).unwrap(), "\"2100000000000000\"");
+        assert_eq!(serde_json::to_string(&MsatAmount::new(1_500)).unwrap(), "\"1500\"");
+        assert_eq!(serde_json::to_string(&KesAmount::new(Decimal::from(5))).unwrap(), "\"5.00\"");
+
+        // Numbers are still accepted on input
+        assert_eq!(serde_json::from_str::<SatAmount>("1000").unwrap(), SatAmount::new(1_000));
+        assert_eq!(serde_json::from_str::<SatAmount>("\"1000\"").unwrap(), SatAmount::new(1_000));
+        assert_eq!(serde_json::from_str::<KesAmount>("\"99.9\"").unwrap().as_decimal().to_string(), "99.90");
+        assert!(serde_json::from_str::<SatAmount>("\"12.5\"").is_err());
+    }
+}